@@ -0,0 +1,101 @@
+//! Times the operations of the two caches looked up at every step: the possible tokens cache, whose steps are
+//! all hits after the first run, and the stack to bytes cache of `<except!([nonterminal])>`, filled during each scan.
+//!
+//! The hits and misses of the stack to bytes cache are printed as well, to weigh it against its cost.
+//!
+//! Run with `cargo bench -p bnf_sampler --bench cache`, and add `--no-default-features` to compare with the
+//! caches without the `hashbrown` feature.
+use bnf_sampler::grammar::Grammar;
+use bnf_sampler::sampler::{CacheMode, PossibleTokensResult, Sampler, SamplerConfig};
+use bnf_sampler::utils;
+use bnf_sampler::vocabulary::Vocabulary;
+use std::time::{Duration, Instant};
+
+/// Strings whose characters are written with `<except!([excepted])>`.
+const GRAMMAR: &str = r#"<start>::=<string>|<string>', '<start>
+<string>::='"'<chars>'"'
+<chars>::=<char>|<char><chars>
+<char>::=<except!([excepted])>|'\\"'
+<excepted>::='"'|'\\'|'\n'|'\t'"#;
+
+const TOKENS: &[&str] = &[
+    "\"", "Alice", " and", " the", " end", " of", " the", " story", "\",", " \"", "Bob", " likes",
+    " files", "\"",
+];
+
+fn run(sampler: &mut Sampler, vocabulary: &Vocabulary) -> Duration {
+    sampler.reset();
+    let start = Instant::now();
+    let mut token_id = None;
+    for token in TOKENS.iter() {
+        match sampler.all_possible_next_tokens(token_id).unwrap() {
+            PossibleTokensResult::Continue(_) => {}
+            result => panic!("{result:?} before {token:?}"),
+        }
+        token_id = vocabulary.token_to_id.get(token.as_bytes()).copied();
+    }
+    start.elapsed()
+}
+
+fn per_step(sampler: &mut Sampler, vocabulary: &Vocabulary, iterations: u32) -> Duration {
+    let total: Duration = (0..iterations).map(|_| run(sampler, vocabulary)).sum();
+    total / (iterations * TOKENS.len() as u32)
+}
+
+/// The stack to bytes cache hits and misses of a run, see `StepTiming`.
+fn cache_counts(sampler: &mut Sampler, vocabulary: &Vocabulary) -> (usize, usize) {
+    sampler.reset();
+    let (mut hits, mut misses) = (0, 0);
+    let mut token_id = None;
+    for token in TOKENS.iter() {
+        sampler.all_possible_next_tokens(token_id).unwrap();
+        let timing = sampler.last_step_timing().unwrap();
+        hits += timing.stack_to_bytes_cache_hits;
+        misses += timing.stack_to_bytes_cache_misses;
+        token_id = vocabulary.token_to_id.get(token.as_bytes()).copied();
+    }
+    (hits, misses)
+}
+
+fn main() {
+    let vocabulary =
+        utils::read_rwkv_world_vocab(concat!(env!("CARGO_MANIFEST_DIR"), "/../assets/vocab.txt"))
+            .unwrap();
+    let grammar = Grammar::new(GRAMMAR, vocabulary.clone(), 0).unwrap();
+    let sampler = |config: SamplerConfig| {
+        Sampler::with_config(
+            grammar.clone(),
+            "start".to_string(),
+            vocabulary.clone(),
+            config,
+        )
+        .unwrap()
+    };
+    let features = if cfg!(feature = "hashbrown") {
+        "hashbrown"
+    } else {
+        "no hashbrown"
+    };
+    let mut full = sampler(SamplerConfig::new().cache_mode(CacheMode::Full));
+    run(&mut full, &vocabulary);
+    println!(
+        "{features}: {:?} per possible tokens cache hit",
+        per_step(&mut full, &vocabulary, 1000)
+    );
+    for enabled in [false, true] {
+        let config = SamplerConfig::new()
+            .cache_mode(CacheMode::TrieNodeOnly)
+            .stack_to_bytes_cache(enabled);
+        let mut scan = sampler(config.clone());
+        println!(
+            "{features}: {:?} per step with the stack to bytes cache {}",
+            per_step(&mut scan, &vocabulary, 10),
+            if enabled { "on" } else { "off" }
+        );
+        if enabled {
+            let (hits, misses) =
+                cache_counts(&mut sampler(config.collect_timing(true)), &vocabulary);
+            println!("{features}: {hits} stack to bytes cache hits and {misses} misses per run");
+        }
+    }
+}
@@ -0,0 +1,199 @@
+//! Times the scan of `all_possible_next_tokens` with and without the byte signature filter,
+//! where the possible tokens are recomputed at every step so every step takes the cache miss path.
+//! Then times the steps with the possible tokens cache and reports the memory of the cached masks,
+//! and times the steps of a bounded date grammar with and without skipping the tokens longer than a stack.
+//! Then times the JSON object grammar with and without folding its punctuation across nonterminals.
+//! Finally times the first step of a JSON `value` rule, whose literals are matched by one trie when the rule
+//! mixes them with `<number>`, against the same rule with a nonterminal per literal.
+//!
+//! Run with `cargo bench -p bnf_sampler --bench scan`, and add `--features roaring` to compare the mask types.
+use bnf_sampler::grammar::{Grammar, GrammarBuildOptions};
+use bnf_sampler::sampler::{CacheMode, PossibleTokensResult, Sampler, SamplerConfig};
+use bnf_sampler::utils;
+use bnf_sampler::vocabulary::Vocabulary;
+use std::time::{Duration, Instant};
+
+/// A JSON object whose keys and strings are written with `<except!(...)>`.
+const GRAMMAR: &str = r#"<start>::='{'<members>'}'
+<members>::=<member>|<member>', '<members>
+<member>::='"'<chars>'": '<value>
+<value>::='"'<chars>'"'|<integer>|'true'|'false'|'null'
+<chars>::=<char>|<char><chars>
+<char>::=<except!([escaped])>|'\\"'|'\\\\'
+<escaped>::='"'|'\\'|'\n'
+<integer>::=<digit>|<digit><integer>
+<digit>::='0'|'1'|'2'|'3'|'4'|'5'|'6'|'7'|'8'|'9'"#;
+
+/// A date and time, where every stack can only match a few more bytes.
+const DATE_GRAMMAR: &str = r#"<start>::=<digit>{4}'-'<digit>{2}'-'<digit>{2}'T'<digit>{2}':'<digit>{2}
+<digit>::='0'|'1'|'2'|'3'|'4'|'5'|'6'|'7'|'8'|'9'"#;
+
+/// A JSON value whose literals are split from `<number>` into an implicit `<terminals!(value)>`.
+const VALUE_GRAMMAR: &str = r#"<start>::='['<value>']'
+<value>::='null'|'true'|'false'|<number>
+<number>::='-'<digits>|<digits>
+<digits>::=<digit>|<digit><digits>
+<digit>::='0'|'1'|'2'|'3'|'4'|'5'|'6'|'7'|'8'|'9'"#;
+
+/// [`VALUE_GRAMMAR`] with a nonterminal per literal, so each literal is a stack of its own.
+const VALUE_PER_LITERAL_GRAMMAR: &str = r#"<start>::='['<value>']'
+<value>::=<null>|<true>|<false>|<number>
+<null>::='null'
+<true>::='true'
+<false>::='false'
+<number>::='-'<digits>|<digits>
+<digits>::=<digit>|<digit><digits>
+<digit>::='0'|'1'|'2'|'3'|'4'|'5'|'6'|'7'|'8'|'9'"#;
+
+/// The date accepted by [`DATE_GRAMMAR`], generated one byte per token.
+const DATE: &str = "2024-01-15T12:30";
+
+const TOKENS: &[&str] = &[
+    "{", "\"", "name", "\":", " \"", "Alice", "\",", " \"", "age", "\":", " ", "42", ",", " \"",
+    "admin", "\":", " true", "}",
+];
+
+/// Returns the time taken and the numbers of tokens checked and filtered.
+fn run(sampler: &mut Sampler, vocabulary: &Vocabulary) -> (Duration, usize, usize) {
+    sampler.reset();
+    let start = Instant::now();
+    let (mut checked, mut filtered) = (0, 0);
+    let mut token_id = None;
+    for token in TOKENS.iter() {
+        match sampler.all_possible_next_tokens(token_id).unwrap() {
+            PossibleTokensResult::Continue(_) => {}
+            result => panic!("{result:?} before {token:?}"),
+        }
+        let timing = sampler.last_step_timing().unwrap();
+        checked += timing.tokens_checked;
+        filtered += timing.tokens_filtered;
+        token_id = vocabulary.token_to_id.get(token.as_bytes()).copied();
+    }
+    (start.elapsed(), checked, filtered)
+}
+
+fn main() {
+    let vocabulary =
+        utils::read_rwkv_world_vocab(concat!(env!("CARGO_MANIFEST_DIR"), "/../assets/vocab.txt"))
+            .unwrap();
+    let grammar = Grammar::new(GRAMMAR, vocabulary.clone(), 0).unwrap();
+    for enabled in [false, true] {
+        let config = SamplerConfig::new()
+            .cache_mode(CacheMode::None)
+            .collect_timing(true)
+            .signature_filter(enabled);
+        let mut sampler = Sampler::with_config(
+            grammar.clone(),
+            "start".to_string(),
+            vocabulary.clone(),
+            config,
+        )
+        .unwrap();
+        let (_, checked, filtered) = run(&mut sampler, &vocabulary);
+        let iterations = 10;
+        let total: Duration = (0..iterations)
+            .map(|_| run(&mut sampler, &vocabulary).0)
+            .sum();
+        println!(
+            "signature filter {}: {:?} per step, {checked} tokens checked, {filtered} tokens filtered",
+            if enabled { "on" } else { "off" },
+            total / (iterations * TOKENS.len()) as u32
+        );
+    }
+    let config = SamplerConfig::new()
+        .cache_mode(CacheMode::Full)
+        .collect_timing(true);
+    let mut sampler =
+        Sampler::with_config(grammar, "start".to_string(), vocabulary.clone(), config).unwrap();
+    let (miss, _, _) = run(&mut sampler, &vocabulary);
+    let iterations = 100;
+    let hit: Duration = (0..iterations)
+        .map(|_| run(&mut sampler, &vocabulary).0)
+        .sum();
+    println!(
+        "{} masks: {:?} per step when missing the cache, {:?} per step when hitting it, {} bytes of cached masks",
+        if cfg!(feature = "roaring") { "roaring" } else { "bit set" },
+        miss / TOKENS.len() as u32,
+        hit / (iterations * TOKENS.len()) as u32,
+        sampler.cached_masks_bytes()
+    );
+    let grammar = Grammar::new(DATE_GRAMMAR, vocabulary.clone(), 0).unwrap();
+    for enabled in [false, true] {
+        let config = SamplerConfig::new()
+            .cache_mode(CacheMode::None)
+            .collect_timing(true)
+            .length_bound(enabled);
+        let mut sampler = Sampler::with_config(
+            grammar.clone(),
+            "start".to_string(),
+            vocabulary.clone(),
+            config,
+        )
+        .unwrap();
+        let iterations = 100;
+        let (mut visited, mut too_long) = (0, 0);
+        let start = Instant::now();
+        for _ in 0..iterations {
+            sampler.reset();
+            let mut token_id = None;
+            for byte in DATE.bytes() {
+                sampler.all_possible_next_tokens(token_id).unwrap();
+                let timing = sampler.last_step_timing().unwrap();
+                visited += timing.tokens_visited;
+                too_long += timing.tokens_too_long;
+                token_id = Some(vocabulary.token_to_id[&[byte][..]]);
+            }
+        }
+        println!(
+            "length bound {}: {:?} per step of the date, {} tokens visited, {} tokens too long",
+            if enabled { "on" } else { "off" },
+            start.elapsed() / (iterations * DATE.len()) as u32,
+            visited / iterations,
+            too_long / iterations
+        );
+    }
+    for fold in [false, true] {
+        let grammar = Grammar::with_options(
+            GRAMMAR,
+            vocabulary.clone(),
+            GrammarBuildOptions::new().fold_terminals(fold),
+        )
+        .unwrap();
+        let folded_bytes = grammar.folded_bytes();
+        let config = SamplerConfig::new()
+            .cache_mode(CacheMode::None)
+            .collect_timing(true);
+        let mut sampler =
+            Sampler::with_config(grammar, "start".to_string(), vocabulary.clone(), config).unwrap();
+        let iterations = 10;
+        let total: Duration = (0..iterations)
+            .map(|_| run(&mut sampler, &vocabulary).0)
+            .sum();
+        println!(
+            "terminal folding {}: {:?} per step, {folded_bytes} bytes folded",
+            if fold { "on" } else { "off" },
+            total / (iterations * TOKENS.len()) as u32
+        );
+    }
+    for (name, grammar) in [
+        ("split literals", VALUE_GRAMMAR),
+        ("a nonterminal per literal", VALUE_PER_LITERAL_GRAMMAR),
+    ] {
+        let grammar = Grammar::new(grammar, vocabulary.clone(), 0).unwrap();
+        let config = SamplerConfig::new().cache_mode(CacheMode::None);
+        let mut sampler =
+            Sampler::with_config(grammar, "start".to_string(), vocabulary.clone(), config).unwrap();
+        let bracket = vocabulary.token_to_id["[".as_bytes()];
+        let iterations = 100;
+        let start = Instant::now();
+        for _ in 0..iterations {
+            sampler.reset();
+            sampler.all_possible_next_tokens(None).unwrap();
+            sampler.all_possible_next_tokens(Some(bracket)).unwrap();
+        }
+        println!(
+            "JSON value with {name}: {:?} per step",
+            start.elapsed() / (2 * iterations)
+        );
+    }
+}
@@ -0,0 +1,57 @@
+//! Free text up to a stop sequence, followed by a templated tool call.
+//!
+//! Tokens forced by the grammar, like `Action: ` after the thought, are emitted with
+//! `Sampler::forced_continuation` without calling the model.
+mod common;
+
+use anyhow::{anyhow, Error};
+use bnf_sampler::sampler::PossibleTokensResult;
+
+pub const GRAMMAR: &str = r#"<start>::='Thought: '<thought>'Action: '<name>'('<query>')'
+<thought>::=<except!('Action')>|<except!('Action')><thought>
+<name>::='search'|'weather'
+<query>::=<any!>|<any!><query>
+"#;
+
+pub const TARGET: &str =
+    "Thought: What is the weather in Paris today?\nAction: weather(Paris today)";
+
+/// Returns the output and the number of times the model is called.
+pub fn run() -> Result<(String, usize), Error> {
+    let (mut sampler, vocabulary) = common::new_sampler(GRAMMAR)?;
+    let mut output = vec![];
+    let mut model_calls = 0;
+    let mut input_token_id = None;
+    loop {
+        match sampler.all_possible_next_tokens(input_token_id)? {
+            PossibleTokensResult::Continue(_) => {}
+            PossibleTokensResult::End => break,
+            PossibleTokensResult::InputTokenRejected => {
+                return Err(anyhow!("The sampler rejected an allowed token."))
+            }
+        }
+        for token_id in sampler.forced_continuation(usize::MAX)? {
+            output.extend_from_slice(&vocabulary.id_to_token[&token_id]);
+        }
+        let token_id = match sampler.all_possible_next_tokens(None)? {
+            PossibleTokensResult::Continue(token_ids) => {
+                common::pick_token(&vocabulary, token_ids, &TARGET.as_bytes()[output.len()..])?
+            }
+            PossibleTokensResult::End => break,
+            PossibleTokensResult::InputTokenRejected => {
+                return Err(anyhow!("The sampler rejected an allowed token."))
+            }
+        };
+        model_calls += 1;
+        output.extend_from_slice(&vocabulary.id_to_token[&token_id]);
+        input_token_id = Some(token_id);
+    }
+    Ok((String::from_utf8(output)?, model_calls))
+}
+
+fn main() -> Result<(), Error> {
+    let (output, model_calls) = run()?;
+    println!("{output}");
+    println!("The model is called {model_calls} times.");
+    Ok(())
+}
@@ -0,0 +1,64 @@
+//! The setup shared by the examples: a sampler over the fixture vocabulary, and a "model" that wants
+//! to write a target and picks the longest allowed token that continues it.
+#![allow(dead_code)]
+use anyhow::{anyhow, Error};
+use bnf_sampler::fixtures;
+use bnf_sampler::grammar::Grammar;
+use bnf_sampler::mask::TokenMask;
+use bnf_sampler::sampler::{PossibleTokensResult, Sampler, SamplerConfig};
+use bnf_sampler::vocabulary::Vocabulary;
+use std::sync::Arc;
+
+/// A sampler of `grammar` from `<start>` over the fixture vocabulary, and the vocabulary.
+pub fn new_sampler(grammar: &str) -> Result<(Sampler, Arc<Vocabulary>), Error> {
+    let vocabulary = fixtures::vocabulary();
+    let grammar = Grammar::new(grammar, vocabulary.clone(), 0)?;
+    let sampler = Sampler::with_config(
+        grammar,
+        "start".to_string(),
+        vocabulary.clone(),
+        SamplerConfig::new(),
+    )?;
+    Ok((sampler, vocabulary))
+}
+
+/// The allowed token that continues `remaining` with the most bytes.
+pub fn pick_token(
+    vocabulary: &Vocabulary,
+    token_ids: &TokenMask,
+    remaining: &[u8],
+) -> Result<u32, Error> {
+    token_ids
+        .iter()
+        .map(|id| (id as u32, vocabulary.id_to_token[&(id as u32)].as_slice()))
+        .filter(|(_, token)| remaining.starts_with(token))
+        .max_by_key(|(_, token)| token.len())
+        .map(|(id, _)| id)
+        .ok_or(anyhow!("No allowed token continues the target."))
+}
+
+/// Pick tokens until the sampler ends, calling `inspect` with the allowed tokens of each step.
+pub fn write_target(
+    sampler: &mut Sampler,
+    vocabulary: &Vocabulary,
+    target: &str,
+    mut inspect: impl FnMut(&TokenMask),
+) -> Result<String, Error> {
+    let mut output = vec![];
+    let mut input_token_id = None;
+    loop {
+        let token_id = match sampler.all_possible_next_tokens(input_token_id)? {
+            PossibleTokensResult::Continue(token_ids) => {
+                inspect(token_ids);
+                pick_token(vocabulary, token_ids, &target.as_bytes()[output.len()..])?
+            }
+            PossibleTokensResult::End => break,
+            PossibleTokensResult::InputTokenRejected => {
+                return Err(anyhow!("The sampler rejected an allowed token."))
+            }
+        };
+        output.extend_from_slice(&vocabulary.id_to_token[&token_id]);
+        input_token_id = Some(token_id);
+    }
+    Ok(String::from_utf8(output)?)
+}
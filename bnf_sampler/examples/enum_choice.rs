@@ -0,0 +1,32 @@
+//! Force the output to be one of a few strings, where the first mask only allows the tokens
+//! that start one of them.
+mod common;
+
+use anyhow::Error;
+
+pub const GRAMMAR: &str = r#"<start>::='red'|'green'|'blue'|'yellow'
+"#;
+
+pub const TARGET: &str = "green";
+
+/// Returns the output and the tokens allowed for the first token.
+pub fn run() -> Result<(String, Vec<String>), Error> {
+    let (mut sampler, vocabulary) = common::new_sampler(GRAMMAR)?;
+    let mut choices = vec![];
+    let output = common::write_target(&mut sampler, &vocabulary, TARGET, |token_ids| {
+        if choices.is_empty() {
+            choices = vocabulary
+                .get_token_strings_from_token_ids(token_ids)
+                .map(|x| x.to_string())
+                .collect();
+        }
+    })?;
+    Ok((output, choices))
+}
+
+fn main() -> Result<(), Error> {
+    let (output, choices) = run()?;
+    println!("Allowed first tokens: {:?}", choices);
+    println!("{}", output);
+    Ok(())
+}
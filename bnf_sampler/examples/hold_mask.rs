@@ -0,0 +1,35 @@
+//! Hold the possible tokens while the chosen token is accepted.
+//!
+//! `Sampler::next_mask` copies the possible tokens out of the sampler, so the mask of a step stays usable
+//! after `accept_a_token`, here to record how constrained every step was.
+mod common;
+
+use anyhow::Error;
+use bnf_sampler::sampler::OwnedPossibleTokensResult;
+
+pub const GRAMMAR: &str = r#"<start>::='The answer is '<answer>'.'
+<answer>::='yes'|'no'|'maybe'
+"#;
+
+/// Returns the output and the number of possible tokens at each step.
+pub fn run() -> Result<(String, Vec<usize>), Error> {
+    let (mut sampler, vocabulary) = common::new_sampler(GRAMMAR)?;
+    let mut output = vec![];
+    let mut mask_sizes = vec![];
+    while let OwnedPossibleTokensResult::Continue(mask) = sampler.next_mask(None)? {
+        // Stands in for sampling from the logits of a model.
+        let token_id = mask.iter().last().unwrap() as u32;
+        sampler.accept_a_token(Some(token_id))?;
+        // The mask is still held after the sampler moved on.
+        mask_sizes.push(mask.len());
+        output.extend_from_slice(&vocabulary.id_to_token[&token_id]);
+    }
+    Ok((String::from_utf8(output)?, mask_sizes))
+}
+
+fn main() -> Result<(), Error> {
+    let (output, mask_sizes) = run()?;
+    println!("{}", output);
+    println!("Possible tokens at each step: {:?}", mask_sizes);
+    Ok(())
+}
@@ -0,0 +1,27 @@
+//! Constrain the output to a small subset of JSON, with nested objects and strings written with `<except!('"')>`.
+mod common;
+
+use anyhow::Error;
+
+pub const GRAMMAR: &str = r#"<start>::=<object>
+<object>::='{'<members>'}'|'{}'
+<members>::=<pair>|<pair>', '<members>
+<pair>::=<string>': '<value>
+<value>::=<string>|<number>|'true'|'false'|'null'|<object>
+<string>::='"'<chars>'"'
+<chars>::=<except!('"')>|<except!('"')><chars>
+<number>::=<digit>|<digit><number>
+<digit>::='0'|'1'|'2'|'3'|'4'|'5'|'6'|'7'|'8'|'9'
+"#;
+
+pub const TARGET: &str = r#"{"name": "Alice", "age": 30, "city": "Paris", "admin": false}"#;
+
+pub fn run() -> Result<String, Error> {
+    let (mut sampler, vocabulary) = common::new_sampler(GRAMMAR)?;
+    common::write_target(&mut sampler, &vocabulary, TARGET, |_| {})
+}
+
+fn main() -> Result<(), Error> {
+    println!("{}", run()?);
+    Ok(())
+}
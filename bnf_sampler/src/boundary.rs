@@ -0,0 +1,205 @@
+use crate::grammar::{format_expression, Grammar, SimplifiedExpressions, U8Term};
+use crate::mask::TokenMask;
+use crate::vocabulary::Vocabulary;
+use std::fmt;
+
+/// How to change the grammar so the tokens of a [`BoundaryConflict`] are accepted.
+#[derive(Debug, PartialEq, Clone, Copy, Eq, Hash, PartialOrd, Ord)]
+pub enum BoundaryFix {
+    /// The tokens start inside the terminal, so merging the end of the terminal into the free text lets them be matched.
+    MergeTerminal,
+    /// The tokens end inside the terminal, so splitting the start of the terminal into the free text lets them be matched.
+    SplitTerminal,
+}
+
+/// A terminal next to a `<any!>`, `<except!(...)>` or `<any_except_bytes!(...)>` nonterminal,
+/// where the model is likely to get stuck because some tokens crossing the boundary are rejected.
+///
+/// The token set nonterminal only matches whole tokens of its set. A token crossing the boundary is rejected
+/// when its bytes on the token set side are not a single token of the set, even though they can be written
+/// with several tokens of the set. A greedy tokenizer still prefers such a token.
+#[derive(Debug, PartialEq, Clone, Eq)]
+pub struct BoundaryConflict {
+    /// The nonterminal whose alternative contains the boundary.
+    pub nonterminal: String,
+    /// The alternative in BNF syntax.
+    pub expression: String,
+    /// The terminal at the boundary.
+    pub terminal: Vec<u8>,
+    /// The token set nonterminal at the boundary.
+    pub token_set: String,
+    /// The rejected token ids crossing the boundary, sorted.
+    pub token_ids: Vec<u32>,
+    pub fix: BoundaryFix,
+}
+
+impl fmt::Display for BoundaryConflict {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let terminal = String::from_utf8_lossy(&self.terminal);
+        write!(
+            f,
+            "{} tokens crossing '{}' and <{}> in <{}>::={} are rejected. ",
+            self.token_ids.len(),
+            terminal.escape_debug(),
+            self.token_set,
+            self.nonterminal,
+            self.expression
+        )?;
+        match self.fix {
+            BoundaryFix::MergeTerminal => write!(
+                f,
+                "Consider merging the end of '{}' into <{}>.",
+                terminal.escape_debug(),
+                self.token_set
+            ),
+            BoundaryFix::SplitTerminal => write!(
+                f,
+                "Consider splitting the start of '{}' into <{}>.",
+                terminal.escape_debug(),
+                self.token_set
+            ),
+        }
+    }
+}
+
+/// Whether `bytes` can be written as a sequence of tokens in `token_ids`, a token set of the grammar.
+fn is_segmentable(bytes: &[u8], token_ids: &TokenMask, vocabulary: &Vocabulary) -> bool {
+    let mut reachable = vec![false; bytes.len() + 1];
+    reachable[0] = true;
+    for start in 0..bytes.len() {
+        if !reachable[start] {
+            continue;
+        }
+        for end in start + 1..=bytes.len() {
+            if vocabulary
+                .token_to_id
+                .get(&bytes[start..end])
+                .is_some_and(|id| token_ids.contains(vocabulary.internal_id(*id) as usize))
+            {
+                reachable[end] = true;
+            }
+        }
+    }
+    reachable[bytes.len()]
+}
+
+impl Grammar {
+    fn token_set<'a>(&'a self, term: &'a U8Term) -> Option<(&'a String, &'a TokenMask)> {
+        match term {
+            U8Term::Nonterminal(nonterminal) => self
+                .nonterminal_to_terminal_id
+                .get(nonterminal)
+                .and_then(|id| self.nonterminal_to_token_ids.get(id))
+                .map(|token_ids| (nonterminal, token_ids)),
+            U8Term::Terminal(_) => None,
+        }
+    }
+
+    /// Find the boundaries between a terminal and a token set nonterminal that some tokens cannot cross,
+    /// see [`BoundaryConflict`].
+    ///
+    /// Only a terminal directly next to the token set nonterminal in an alternative is checked.
+    /// `vocabulary` should be the vocabulary the grammar is created with. The conflicts are sorted.
+    pub fn boundary_conflicts(&self, vocabulary: &Vocabulary) -> Vec<BoundaryConflict> {
+        let mut conflicts = vec![];
+        let token_set = |term| self.token_set(term);
+        for (nonterminal, id) in self.nonterminal_to_terminal_id.iter() {
+            let Some(SimplifiedExpressions::Expressions(expressions)) =
+                self.nonterminal_id_to_expression.get(id)
+            else {
+                continue;
+            };
+            for expression in expressions.iter() {
+                for (i, pair) in expression.windows(2).enumerate() {
+                    let mut push = |terminal: &[u8], set: &String, token_ids: Vec<u32>, fix| {
+                        if !token_ids.is_empty() {
+                            conflicts.push(BoundaryConflict {
+                                nonterminal: nonterminal.clone(),
+                                expression: format_expression(expression, &self.terminals),
+                                terminal: terminal.to_vec(),
+                                token_set: set.clone(),
+                                token_ids,
+                                fix,
+                            });
+                        }
+                    };
+                    match (&pair[0], token_set(&pair[0]), &pair[1], token_set(&pair[1])) {
+                        (U8Term::Terminal(terminal), None, _, Some((set, set_ids))) => {
+                            let terminal = self.terminals.get(*terminal);
+                            let next = match expression.get(i + 2) {
+                                Some(U8Term::Terminal(id)) => self.terminals.get(*id),
+                                _ => &[],
+                            };
+                            let mut token_ids: Vec<u32> = vocabulary
+                                .id_to_token
+                                .iter()
+                                .filter(|(_, token)| {
+                                    (1..=terminal.len()).any(|len| {
+                                        let Some(rest) =
+                                            token.strip_prefix(&terminal[terminal.len() - len..])
+                                        else {
+                                            return false;
+                                        };
+                                        // The token may also end in the terminal after the token set nonterminal.
+                                        let accepted = (1..=rest.len()).any(|end| {
+                                            next.starts_with(&rest[end..])
+                                                && vocabulary
+                                                    .token_to_id
+                                                    .get(&rest[..end])
+                                                    .is_some_and(|id| {
+                                                        set_ids
+                                                            .contains(vocabulary.internal_id(*id)
+                                                                as usize)
+                                                    })
+                                        });
+                                        !rest.is_empty()
+                                            && !accepted
+                                            && is_segmentable(rest, set_ids, vocabulary)
+                                    })
+                                })
+                                .map(|(id, _)| *id)
+                                .collect();
+                            token_ids.sort_unstable();
+                            push(terminal, set, token_ids, BoundaryFix::MergeTerminal);
+                        }
+                        (_, Some((set, set_ids)), U8Term::Terminal(terminal), None) => {
+                            let terminal = self.terminals.get(*terminal);
+                            let mut token_ids: Vec<u32> = vocabulary
+                                .id_to_token
+                                .iter()
+                                // A token of the set is matched entirely by the token set nonterminal.
+                                .filter(|(id, _)| {
+                                    !set_ids.contains(vocabulary.internal_id(**id) as usize)
+                                })
+                                .filter(|(_, token)| {
+                                    (1..=terminal.len().min(token.len() - 1)).any(|len| {
+                                        let (head, tail) = token.split_at(token.len() - len);
+                                        terminal.starts_with(tail)
+                                            && vocabulary.token_to_id.get(head).is_none_or(|id| {
+                                                !set_ids
+                                                    .contains(vocabulary.internal_id(*id) as usize)
+                                            })
+                                            && is_segmentable(head, set_ids, vocabulary)
+                                    })
+                                })
+                                .map(|(id, _)| *id)
+                                .collect();
+                            token_ids.sort_unstable();
+                            push(terminal, set, token_ids, BoundaryFix::SplitTerminal);
+                        }
+                        _ => {}
+                    }
+                }
+            }
+        }
+        conflicts.sort_by(|a, b| {
+            (&a.nonterminal, &a.expression, &a.terminal, &a.token_set).cmp(&(
+                &b.nonterminal,
+                &b.expression,
+                &b.terminal,
+                &b.token_set,
+            ))
+        });
+        conflicts
+    }
+}
@@ -0,0 +1,136 @@
+//! Trace bundles, which turn a generation into a reproduction for a bug report.
+//!
+//! A bundle is a directory holding the BNF schema in `grammar.bnf`, the vocabulary in `vocab.txt`
+//! in RWKV-world model series vocabulary format, and the start nonterminal, the [`SamplerConfig`]
+//! and the token ids in `bundle.json`. It is recorded with [`Sampler::record_trace_bundle`]
+//! and replayed with [`TraceBundle::replay`] or `console_playground --replay <bundle>`.
+use crate::grammar::Grammar;
+use crate::sampler::{AcceptTokenResult, Sampler, SamplerConfig};
+use crate::utils::{read_rwkv_world_vocab_with_max_token_bytes, write_rwkv_world_vocab};
+use crate::vocabulary::Vocabulary;
+use anyhow::{anyhow, Context, Error};
+use serde_json::{json, Value};
+use std::fs::{self, File};
+use std::io::BufWriter;
+use std::path::Path;
+use std::sync::Arc;
+
+/// Everything needed to replay a generation, see the module documentation.
+///
+/// The grammar is rebuilt with [`Grammar::new`], so special forms registered with
+/// [`crate::grammar::GrammarBuildOptions::register_form`] are not available when it is replayed,
+/// and the aliases added with [`Vocabulary::alias_token`] are not recorded.
+#[derive(Debug, Clone)]
+pub struct TraceBundle {
+    /// The BNF schema.
+    pub grammar: String,
+    pub start_nonterminal: String,
+    pub vocabulary: Arc<Vocabulary>,
+    pub config: SamplerConfig,
+    /// The accepted token ids, followed by the first rejected one if any.
+    pub token_ids: Vec<u32>,
+}
+
+/// The result of [`TraceBundle::replay`].
+#[derive(Debug)]
+pub struct Replay {
+    /// The sampler after the accepted tokens. It is left before the rejected token when a token is rejected.
+    pub sampler: Sampler,
+    /// The number of tokens accepted before the replay stopped.
+    pub accepted: usize,
+    /// The result of the last token replayed, or [`AcceptTokenResult::Continue`] when there is no token.
+    pub result: AcceptTokenResult,
+}
+
+impl TraceBundle {
+    /// Write the bundle to the directory `path`, creating it if needed.
+    pub fn save(&self, path: impl AsRef<Path>) -> Result<(), Error> {
+        let path = path.as_ref();
+        fs::create_dir_all(path)
+            .with_context(|| format!("cannot create the bundle directory {:?}", path))?;
+        fs::write(path.join("grammar.bnf"), &self.grammar)?;
+        write_rwkv_world_vocab(
+            &self.vocabulary,
+            BufWriter::new(File::create(path.join("vocab.txt"))?),
+        )?;
+        let metadata = json!({
+            "start_nonterminal": self.start_nonterminal,
+            "max_token_bytes": self.vocabulary.max_token_bytes,
+            "config": self.config.to_json(),
+            "token_ids": self.token_ids,
+        });
+        fs::write(
+            path.join("bundle.json"),
+            serde_json::to_string_pretty(&metadata)?,
+        )?;
+        Ok(())
+    }
+
+    /// Read a bundle written by [`TraceBundle::save`] from the directory `path`.
+    pub fn load(path: impl AsRef<Path>) -> Result<Self, Error> {
+        let path = path.as_ref();
+        let metadata: Value = serde_json::from_str(
+            &fs::read_to_string(path.join("bundle.json"))
+                .with_context(|| format!("cannot read the bundle {:?}", path))?,
+        )
+        .with_context(|| format!("invalid bundle.json in {:?}", path))?;
+        let field = |name: &str| {
+            metadata
+                .get(name)
+                .ok_or_else(|| anyhow!("bundle.json in {:?} has no {name}.", path))
+        };
+        let start_nonterminal = field("start_nonterminal")?
+            .as_str()
+            .ok_or_else(|| anyhow!("The start_nonterminal of the bundle is not a string."))?
+            .to_string();
+        let max_token_bytes = field("max_token_bytes")?
+            .as_u64()
+            .ok_or_else(|| anyhow!("The max_token_bytes of the bundle is not a number."))?;
+        let token_ids = field("token_ids")?
+            .as_array()
+            .and_then(|x| {
+                x.iter()
+                    .map(|x| x.as_u64().and_then(|x| u32::try_from(x).ok()))
+                    .collect::<Option<Vec<u32>>>()
+            })
+            .ok_or_else(|| anyhow!("The token_ids of the bundle are not an array of token ids."))?;
+        Ok(TraceBundle {
+            grammar: fs::read_to_string(path.join("grammar.bnf"))?,
+            start_nonterminal,
+            vocabulary: read_rwkv_world_vocab_with_max_token_bytes(
+                path.join("vocab.txt"),
+                max_token_bytes as usize,
+            )?,
+            config: SamplerConfig::from_json(field("config")?)?,
+            token_ids,
+        })
+    }
+
+    /// Build the sampler of the bundle and accept its tokens until one is rejected or the sampler ends.
+    pub fn replay(&self) -> Result<Replay, Error> {
+        let grammar = Grammar::new(&self.grammar, self.vocabulary.clone(), 0)?;
+        let mut sampler = Sampler::with_config(
+            grammar,
+            self.start_nonterminal.clone(),
+            self.vocabulary.clone(),
+            self.config.clone(),
+        )?;
+        let mut accepted = 0;
+        let mut result = AcceptTokenResult::Continue;
+        for token_id in self.token_ids.iter() {
+            result = sampler.accept_a_token(Some(*token_id))?;
+            if result == AcceptTokenResult::Failed {
+                break;
+            }
+            accepted += 1;
+            if result == AcceptTokenResult::End {
+                break;
+            }
+        }
+        Ok(Replay {
+            sampler,
+            accepted,
+            result,
+        })
+    }
+}
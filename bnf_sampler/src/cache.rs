@@ -0,0 +1,100 @@
+//! The map behind the possible tokens cache and the stack to bytes cache of the sampler.
+//!
+//! Both caches are looked up at every step and filled on a miss, so a key is hashed once with [`hash_key`]
+//! and the hash is reused for the insert. Entries are compared with a predicate, which lets a lookup
+//! compare a borrowed form of the key and build the owned key only when it is inserted.
+//!
+//! With the default `hashbrown` feature the entries live in a [`hashbrown::HashTable`] that stores their hashes,
+//! otherwise in a [`FxHashMap`] from hashes to the entries sharing them.
+#[cfg(not(feature = "hashbrown"))]
+use rustc_hash::FxHashMap;
+use rustc_hash::FxHasher;
+use std::hash::{BuildHasher, BuildHasherDefault, Hash};
+
+/// The hash of `key` in a [`HashCache`]. Hashing a borrowed form and its owned key gives the same hash
+/// when they hash the same values, like `&[T]` and `Vec<T>`.
+pub(crate) fn hash_key<Q: Hash + ?Sized>(key: &Q) -> u64 {
+    BuildHasherDefault::<FxHasher>::default().hash_one(key)
+}
+
+/// A map whose entries are found by a hash from [`hash_key`] and a predicate on the key, see the module documentation.
+#[derive(Clone, Debug)]
+pub(crate) struct HashCache<K, V> {
+    #[cfg(feature = "hashbrown")]
+    table: hashbrown::HashTable<(u64, K, V)>,
+    #[cfg(not(feature = "hashbrown"))]
+    table: FxHashMap<u64, Vec<(K, V)>>,
+}
+
+/// Where [`HashCache::find`] found an entry, which reads it again without another lookup.
+#[derive(Clone, Copy, Debug)]
+pub(crate) struct Slot {
+    #[cfg(feature = "hashbrown")]
+    bucket: usize,
+    #[cfg(not(feature = "hashbrown"))]
+    hash: u64,
+    #[cfg(not(feature = "hashbrown"))]
+    position: usize,
+}
+
+impl<K, V> Default for HashCache<K, V> {
+    fn default() -> Self {
+        Self {
+            table: Default::default(),
+        }
+    }
+}
+
+impl<K, V> HashCache<K, V> {
+    /// The slot of the key with `hash` for which `eq` returns true.
+    pub fn find(&self, hash: u64, mut eq: impl FnMut(&K) -> bool) -> Option<Slot> {
+        #[cfg(feature = "hashbrown")]
+        return self
+            .table
+            .find_bucket_index(hash, |(x, key, _)| *x == hash && eq(key))
+            .map(|bucket| Slot { bucket });
+        #[cfg(not(feature = "hashbrown"))]
+        return self
+            .table
+            .get(&hash)?
+            .iter()
+            .position(|(key, _)| eq(key))
+            .map(|position| Slot { hash, position });
+    }
+
+    /// The value in a slot found since the last insert.
+    pub fn slot(&self, slot: Slot) -> &V {
+        #[cfg(feature = "hashbrown")]
+        return &self.table.get_bucket(slot.bucket).unwrap().2;
+        #[cfg(not(feature = "hashbrown"))]
+        return &self.table[&slot.hash][slot.position].1;
+    }
+
+    /// The value of the key with `hash` for which `eq` returns true.
+    pub fn get(&self, hash: u64, eq: impl FnMut(&K) -> bool) -> Option<&V> {
+        self.find(hash, eq).map(|slot| self.slot(slot))
+    }
+
+    /// Inserts a key that [`HashCache::get`] did not find, where `hash` is the hash of the key.
+    pub fn insert_unique(&mut self, hash: u64, key: K, value: V) {
+        #[cfg(feature = "hashbrown")]
+        self.table
+            .insert_unique(hash, (hash, key, value), |(x, _, _)| *x);
+        #[cfg(not(feature = "hashbrown"))]
+        self.table.entry(hash).or_default().push((key, value));
+    }
+
+    pub fn keys(&self) -> impl Iterator<Item = &K> {
+        #[cfg(feature = "hashbrown")]
+        return self.table.iter().map(|(_, key, _)| key);
+        #[cfg(not(feature = "hashbrown"))]
+        return self.table.values().flatten().map(|(key, _)| key);
+    }
+
+    pub fn values(&self) -> impl Iterator<Item = &V> {
+        #[cfg(feature = "hashbrown")]
+        return self.table.iter().map(|(_, _, value)| value);
+        #[cfg(not(feature = "hashbrown"))]
+        return self.table.values().flatten().map(|(_, value)| value);
+    }
+}
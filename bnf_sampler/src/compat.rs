@@ -0,0 +1,135 @@
+//! Adapters mirroring the API of the legacy `sampler` crate, so code written against it can migrate incrementally.
+//!
+//! The legacy API panics on errors and returns `Option`s instead of the result enums.
+//! Every item is deprecated in favor of the API it wraps.
+#![allow(deprecated)]
+use crate::grammar::Grammar;
+use crate::sampler::{AcceptTokenResult, PossibleTokensResult, SamplerConfig};
+use crate::utils::{self, U8ArrayWrapper};
+use crate::vocabulary::{Vocabulary, DEFAULT_MAX_TOKEN_BYTES};
+use bit_set::BitSet;
+use qp_trie::Trie;
+use rustc_hash::FxHashMap;
+use std::sync::Arc;
+
+/// Read the RWKV world vocabulary as the tokens tree and the map from token ids to token strings.
+///
+/// # Panics
+///
+/// Panics if the file cannot be read or is not a RWKV world vocabulary.
+#[deprecated(
+    note = "use `utils::read_rwkv_world_vocab`, which returns a `Vocabulary` holding both maps"
+)]
+pub fn read_world_vocab(file_name: &str) -> (Trie<U8ArrayWrapper, u32>, FxHashMap<u32, String>) {
+    let vocabulary = utils::read_rwkv_world_vocab(file_name)
+        .unwrap_or_else(|e| panic!("{file_name} should be a RWKV world vocabulary: {e}"));
+    (
+        vocabulary.token_to_id.clone(),
+        vocabulary.id_to_token_string.clone(),
+    )
+}
+
+/// A sampler created from a BNF schema and a tokens tree, like the legacy `sampler::Sampler`.
+#[deprecated(
+    note = "use `sampler::Sampler::with_config` with an `Arc<Grammar>` and an `Arc<Vocabulary>`"
+)]
+#[derive(Clone, Debug)]
+pub struct Sampler {
+    inner: crate::sampler::Sampler,
+    /// The possible tokens copied into a `BitSet`, or an empty set once the sampler terminates.
+    token_ids: BitSet<u32>,
+}
+
+impl Sampler {
+    /// Create a sampler. The vocabulary is rebuilt from `tokens_tree`, with the token strings decoded lossily.
+    ///
+    /// # Arguments
+    ///
+    /// * `grammar` - the BNF schema in text format
+    /// * `start` - the starting point of the BNF schema
+    /// * `tokens_tree` - the map from tokens to token ids, as returned by [`read_world_vocab`]
+    /// * `stack_arena_capacity` - the arena capacity. 0 means the capacity is estimated.
+    ///
+    /// # Panics
+    ///
+    /// Panics if the schema is invalid or `start` is not defined in it.
+    pub fn new(
+        grammar: &str,
+        start: &str,
+        tokens_tree: &Trie<U8ArrayWrapper, u32>,
+        stack_arena_capacity: usize,
+    ) -> Self {
+        let mut id_to_token = FxHashMap::default();
+        let mut id_to_token_string = FxHashMap::default();
+        for (token, id) in tokens_tree.iter() {
+            id_to_token.insert(*id, token.0.to_vec());
+            id_to_token_string.insert(*id, String::from_utf8_lossy(&token.0).into_owned());
+        }
+        let max_token_bytes = id_to_token
+            .values()
+            .map(Vec::len)
+            .max()
+            .unwrap_or(0)
+            .max(DEFAULT_MAX_TOKEN_BYTES);
+        let vocabulary = Arc::new(
+            Vocabulary::new(id_to_token, id_to_token_string, max_token_bytes)
+                .expect("The tokens are checked against their own maximum length."),
+        );
+        let grammar = Grammar::new(grammar, vocabulary.clone(), 0)
+            .unwrap_or_else(|e| panic!("The BNF schema should be valid: {e}"));
+        let inner = crate::sampler::Sampler::with_config(
+            grammar,
+            start.to_string(),
+            vocabulary,
+            SamplerConfig::new().stack_arena_capacity(stack_arena_capacity),
+        )
+        .unwrap_or_else(|e| panic!("{e}"));
+        Sampler {
+            inner,
+            token_ids: BitSet::new(),
+        }
+    }
+
+    /// Accept the token and get the possible next tokens.
+    ///
+    /// Returns `None` if the token is rejected, and an empty set once the sampler terminates.
+    ///
+    /// # Panics
+    ///
+    /// Panics on the errors [`crate::sampler::Sampler::all_possible_next_tokens`] returns.
+    pub fn all_possible_next_tokens(
+        &mut self,
+        input_token_id: Option<u32>,
+    ) -> Option<&BitSet<u32>> {
+        self.token_ids.clear();
+        match self
+            .inner
+            .all_possible_next_tokens(input_token_id)
+            .unwrap_or_else(|e| panic!("{e}"))
+        {
+            PossibleTokensResult::Continue(token_ids) => {
+                self.token_ids.extend(token_ids.iter());
+                Some(&self.token_ids)
+            }
+            PossibleTokensResult::End => Some(&self.token_ids),
+            PossibleTokensResult::InputTokenRejected => None,
+        }
+    }
+
+    /// Accept the token. Returns whether it is accepted.
+    ///
+    /// # Panics
+    ///
+    /// Panics on the errors [`crate::sampler::Sampler::accept_a_token`] returns.
+    pub fn accept_a_token(&mut self, token_id: Option<u32>) -> bool {
+        self.inner
+            .accept_a_token(token_id)
+            .unwrap_or_else(|e| panic!("{e}"))
+            != AcceptTokenResult::Failed
+    }
+
+    /// The wrapped sampler, for calling the new API during the migration.
+    pub fn inner(&mut self) -> &mut crate::sampler::Sampler {
+        &mut self.inner
+    }
+}
@@ -0,0 +1,219 @@
+//! Suggest what can follow a byte prefix typed by a human, e.g. for the autocomplete of a command line.
+//!
+//! The prefix is matched byte by byte instead of token by token, so no vocabulary is involved.
+use crate::grammar::{Grammar, SimplifiedExpressions, U8Term};
+use crate::sampler::StackItem;
+use crate::utils::NonterminalID;
+use anyhow::{anyhow, Error};
+use rustc_hash::{FxHashMap, FxHashSet};
+
+/// A stack item with the nonterminal whose alternative put it on the stack.
+type Item = (StackItem, NonterminalID);
+
+/// A suggestion of [`Grammar::completions`].
+#[derive(Debug, PartialEq, Clone, Eq, PartialOrd, Ord, Hash)]
+pub struct Completion {
+    /// The bytes that can follow the prefix, up to the end of a terminal or the next byte with several choices.
+    pub bytes: Vec<u8>,
+    /// The nonterminal whose alternative or terminals contain the bytes.
+    pub nonterminal: String,
+}
+
+impl Grammar {
+    /// At most `limit` suggestions of what can follow `prefix` in a sentence of `start`, sorted and deduplicated.
+    ///
+    /// A suggestion continues a terminal of the grammar up to its end, or up to the next byte where the terminals
+    /// of a nonterminal branch, like `SE` for `'SELECT'|'SET'`. An ambiguous position gives one suggestion per choice,
+    /// and a prefix no sentence starts with gives none. The end of a sentence is not a suggestion.
+    pub fn completions(
+        &self,
+        start: &str,
+        prefix: &[u8],
+        limit: usize,
+    ) -> Result<Vec<Completion>, Error> {
+        let start_id = *self
+            .nonterminal_to_terminal_id
+            .get(start)
+            .ok_or_else(|| anyhow!("<{start}> is not defined in the BNF schema."))?;
+        let mut stacks = FxHashSet::default();
+        self.match_prefix(
+            vec![(StackItem::Nonterminal(start_id), start_id)],
+            prefix,
+            &mut stacks,
+        )?;
+        let mut tops = FxHashSet::default();
+        for stack in stacks {
+            self.next_terminals(stack, &mut tops)?;
+        }
+        let id_to_nonterminal: FxHashMap<NonterminalID, &str> = self
+            .nonterminal_to_terminal_id
+            .iter()
+            .map(|(k, v)| (*v, k.as_str()))
+            .collect();
+        let mut completions = FxHashSet::default();
+        for (item, nonterminal) in tops {
+            let nonterminal = id_to_nonterminal[&nonterminal].to_string();
+            match item {
+                StackItem::Terminal(id, start) => {
+                    completions.insert(Completion {
+                        bytes: self.terminals.get(id)[start..].to_vec(),
+                        nonterminal,
+                    });
+                }
+                StackItem::Terminals(node_id) => {
+                    let trie = &self.terminals_trie;
+                    for (byte, child) in trie.get(node_id).children.iter() {
+                        let mut bytes = vec![*byte];
+                        let mut node = trie.get(*child);
+                        if node.negative_bytes_index.is_some() {
+                            continue;
+                        }
+                        while node.value.is_none() && node.children.len() == 1 {
+                            let (byte, child) = node.children.iter().next().unwrap();
+                            let child = trie.get(*child);
+                            if child.negative_bytes_index.is_some() {
+                                break;
+                            }
+                            bytes.push(*byte);
+                            node = child;
+                        }
+                        completions.insert(Completion {
+                            bytes,
+                            nonterminal: nonterminal.clone(),
+                        });
+                    }
+                }
+                StackItem::Nonterminal(_) => unreachable!("Nonterminals are expanded."),
+            }
+        }
+        let mut completions = completions.into_iter().collect::<Vec<_>>();
+        completions.sort_unstable();
+        completions.truncate(limit);
+        Ok(completions)
+    }
+
+    /// The stacks after matching `bytes`, where the last item is the top.
+    ///
+    /// It follows the matching of [`crate::sampler::Sampler`], except that a prefix can stop inside a token
+    /// of a token set nonterminal, since a human types bytes instead of tokens.
+    fn match_prefix(
+        &self,
+        mut stack: Vec<Item>,
+        bytes: &[u8],
+        stacks: &mut FxHashSet<Vec<Item>>,
+    ) -> Result<(), Error> {
+        if bytes.is_empty() {
+            stacks.insert(stack);
+            return Ok(());
+        }
+        let Some((item, nonterminal)) = stack.pop() else {
+            return Ok(());
+        };
+        match item {
+            StackItem::Nonterminal(id) => {
+                for stack in self.expand(&stack, id)? {
+                    self.match_prefix(stack, bytes, stacks)?;
+                }
+            }
+            StackItem::Terminal(id, start) => {
+                let terminal = &self.terminals.get(id)[start..];
+                if terminal.len() > bytes.len() {
+                    if terminal.starts_with(bytes) {
+                        stack.push((StackItem::Terminal(id, start + bytes.len()), nonterminal));
+                        stacks.insert(stack);
+                    }
+                } else if bytes.starts_with(terminal) {
+                    self.match_prefix(stack, &bytes[terminal.len()..], stacks)?;
+                }
+            }
+            StackItem::Terminals(node_id) => {
+                let trie = &self.terminals_trie;
+                let mut nodes = vec![];
+                let mut node = trie.get(node_id);
+                for byte in bytes {
+                    let Some(child) = node.children.get(byte) else {
+                        break;
+                    };
+                    node = trie.get(*child);
+                    nodes.push(*child);
+                    if let Some(index) = node.negative_bytes_index {
+                        // The bytes of the excepted literal, and the terminals ending in it, are not matched.
+                        nodes.truncate(nodes.len().saturating_sub(index as usize));
+                        break;
+                    }
+                }
+                for (i, child) in nodes.iter().enumerate() {
+                    let node = trie.get(*child);
+                    if i + 1 == bytes.len() {
+                        if !node.children.is_empty() {
+                            let mut stack = stack.clone();
+                            stack.push((StackItem::Terminals(*child), nonterminal));
+                            stacks.insert(stack);
+                        }
+                        if node.value.is_some() {
+                            stacks.insert(stack.clone());
+                        }
+                    } else if node.value.is_some() {
+                        self.match_prefix(stack.clone(), &bytes[i + 1..], stacks)?;
+                    }
+                }
+            }
+        }
+        Ok(())
+    }
+
+    /// The terminal items that can be matched next from `stack`, with the nonterminals they come from.
+    fn next_terminals(
+        &self,
+        mut stack: Vec<Item>,
+        tops: &mut FxHashSet<Item>,
+    ) -> Result<(), Error> {
+        match stack.pop() {
+            Some((StackItem::Nonterminal(id), _)) => {
+                for stack in self.expand(&stack, id)? {
+                    self.next_terminals(stack, tops)?;
+                }
+            }
+            Some(top) => {
+                tops.insert(top);
+            }
+            None => {}
+        }
+        Ok(())
+    }
+
+    /// The stacks after replacing the nonterminal `id` on top of `stack` with each of its alternatives.
+    fn expand(&self, stack: &[Item], id: NonterminalID) -> Result<Vec<Vec<Item>>, Error> {
+        let expressions = self
+            .nonterminal_id_to_expression
+            .get(&id)
+            .ok_or_else(|| anyhow!("A nonterminal is used before it is defined."))?;
+        match expressions {
+            SimplifiedExpressions::Expressions(expressions) => expressions
+                .iter()
+                .map(|expression| {
+                    let mut stack = stack.to_vec();
+                    for term in expression.iter().rev() {
+                        stack.push(match term {
+                            U8Term::Terminal(value) => (StackItem::Terminal(*value, 0), id),
+                            U8Term::Nonterminal(value) => (
+                                StackItem::Nonterminal(
+                                    *self.nonterminal_to_terminal_id.get(value).ok_or_else(
+                                        || anyhow!("Nonterminal string <{value}> is not defined."),
+                                    )?,
+                                ),
+                                id,
+                            ),
+                        });
+                    }
+                    Ok(stack)
+                })
+                .collect(),
+            SimplifiedExpressions::Terminals(root) => {
+                let mut stack = stack.to_vec();
+                stack.push((StackItem::Terminals(*root), id));
+                Ok(vec![stack])
+            }
+        }
+    }
+}
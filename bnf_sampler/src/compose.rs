@@ -0,0 +1,225 @@
+//! Combinators that sample with several grammars, either one after another with [`ChainedSampler`]
+//! or in parallel with [`UnionSampler`].
+//!
+//! They wrap ordinary [`Sampler`]s, so every sampler keeps its own possible tokens cache.
+use crate::grammar::Grammar;
+use crate::mask::TokenMask;
+use crate::sampler::{AcceptTokenResult, PossibleTokensResult, Sampler, SamplerConfig};
+use crate::vocabulary::Vocabulary;
+use anyhow::{ensure, Error};
+use std::collections::VecDeque;
+use std::sync::Arc;
+
+/// Everything needed to create a sampler, so a [`ChainedSampler`] creates it only when its phase begins.
+#[derive(Debug, Clone)]
+pub struct SamplerSpec {
+    pub grammar: Arc<Grammar>,
+    pub start_nonterminal: String,
+    pub vocabulary: Arc<Vocabulary>,
+    pub config: SamplerConfig,
+}
+
+impl SamplerSpec {
+    /// A spec with the default [`SamplerConfig`].
+    pub fn new(
+        grammar: Arc<Grammar>,
+        start_nonterminal: &str,
+        vocabulary: Arc<Vocabulary>,
+    ) -> Self {
+        SamplerSpec {
+            grammar,
+            start_nonterminal: start_nonterminal.to_string(),
+            vocabulary,
+            config: SamplerConfig::default(),
+        }
+    }
+
+    pub fn config(mut self, config: SamplerConfig) -> Self {
+        self.config = config;
+        self
+    }
+
+    /// Create the sampler, see [`Sampler::with_config`].
+    pub fn build(&self) -> Result<Sampler, Error> {
+        Sampler::with_config(
+            self.grammar.clone(),
+            self.start_nonterminal.clone(),
+            self.vocabulary.clone(),
+            self.config.clone(),
+        )
+    }
+}
+
+impl Sampler {
+    /// Continue with a sampler created from `next` once this sampler reaches [`AcceptTokenResult::End`].
+    pub fn then(self, next: SamplerSpec) -> ChainedSampler {
+        ChainedSampler {
+            current: self,
+            remaining: VecDeque::from([next]),
+            phase: 0,
+        }
+    }
+}
+
+/// Samplers used one after another, see [`Sampler::then`].
+///
+/// When the sampler of a phase reaches the end, the sampler of the next phase is created and the next token
+/// is matched against it, so the token ending a phase cannot also begin the next one.
+/// Only the last phase reaching the end ends the chain.
+#[derive(Debug, Clone)]
+pub struct ChainedSampler {
+    current: Sampler,
+    remaining: VecDeque<SamplerSpec>,
+    phase: usize,
+}
+
+impl ChainedSampler {
+    /// Add another phase after the last one.
+    pub fn then(mut self, next: SamplerSpec) -> Self {
+        self.remaining.push_back(next);
+        self
+    }
+
+    /// The index of the current phase, 0 for the sampler [`Sampler::then`] is called on.
+    pub fn phase(&self) -> usize {
+        self.phase
+    }
+
+    /// The sampler of the current phase.
+    pub fn current(&self) -> &Sampler {
+        &self.current
+    }
+
+    /// Accept a token with the sampler of the current phase, like [`Sampler::accept_a_token`].
+    ///
+    /// Returns [`AcceptTokenResult::Continue`] instead of `End` when the token ends a phase other than the last.
+    /// Returns an error when the sampler of the next phase cannot be created.
+    pub fn accept_a_token(&mut self, token_id: Option<u32>) -> Result<AcceptTokenResult, Error> {
+        let result = self.current.accept_a_token(token_id)?;
+        if result != AcceptTokenResult::End {
+            return Ok(result);
+        }
+        match self.remaining.pop_front() {
+            Some(next) => {
+                self.current = next.build()?;
+                self.phase += 1;
+                // The next phase may end before any token, e.g. when it matches the empty string.
+                self.accept_a_token(None)
+            }
+            None => Ok(result),
+        }
+    }
+
+    /// Get the possible tokens of the current phase, like [`Sampler::all_possible_next_tokens`].
+    pub fn all_possible_next_tokens(
+        &mut self,
+        input_token_id: Option<u32>,
+    ) -> Result<PossibleTokensResult<'_>, Error> {
+        match self.accept_a_token(input_token_id)? {
+            AcceptTokenResult::Continue => self.current.all_possible_next_tokens(None),
+            AcceptTokenResult::End => Ok(PossibleTokensResult::End),
+            AcceptTokenResult::Failed => Ok(PossibleTokensResult::InputTokenRejected),
+        }
+    }
+}
+
+/// Samplers tracked in parallel, whose possible tokens are the union of the possible tokens of the samplers
+/// that accepted every token so far.
+///
+/// A token rejected by some of the samplers drops them, and a token rejected by all of them is rejected
+/// without dropping any. The union ends as soon as one of the samplers ends, like a sampler ends as soon as
+/// one of its stacks ends.
+#[derive(Debug, Clone)]
+pub struct UnionSampler {
+    samplers: Vec<Sampler>,
+    alive: Vec<bool>,
+    ended: Option<usize>,
+    token_ids: TokenMask,
+}
+
+impl UnionSampler {
+    /// Returns an error if `samplers` is empty or the samplers do not share the same vocabulary.
+    pub fn new(samplers: Vec<Sampler>) -> Result<Self, Error> {
+        ensure!(
+            !samplers.is_empty(),
+            "A union sampler needs at least one sampler."
+        );
+        ensure!(
+            samplers
+                .iter()
+                .all(|x| Arc::ptr_eq(&x.vocabulary, &samplers[0].vocabulary)),
+            "The samplers of a union sampler should share the same vocabulary."
+        );
+        Ok(UnionSampler {
+            alive: vec![true; samplers.len()],
+            samplers,
+            ended: None,
+            token_ids: TokenMask::new(),
+        })
+    }
+
+    /// The indices of the samplers that accepted every token so far.
+    pub fn candidates(&self) -> Vec<usize> {
+        (0..self.samplers.len())
+            .filter(|i| self.alive[*i])
+            .collect()
+    }
+
+    /// The index of the sampler that ended the union.
+    pub fn ended(&self) -> Option<usize> {
+        self.ended
+    }
+
+    pub fn samplers(&self) -> &[Sampler] {
+        &self.samplers
+    }
+
+    /// Accept a token with every candidate sampler, like [`Sampler::accept_a_token`].
+    pub fn accept_a_token(&mut self, token_id: Option<u32>) -> Result<AcceptTokenResult, Error> {
+        let mut results = vec![];
+        for i in self.candidates() {
+            results.push((i, self.samplers[i].accept_a_token(token_id)?));
+        }
+        if results.iter().all(|(_, x)| *x == AcceptTokenResult::Failed) {
+            return Ok(AcceptTokenResult::Failed);
+        }
+        for (i, result) in results.iter() {
+            self.alive[*i] = *result != AcceptTokenResult::Failed;
+        }
+        self.ended = results
+            .iter()
+            .find(|(_, x)| *x == AcceptTokenResult::End)
+            .map(|(i, _)| *i);
+        Ok(match self.ended {
+            Some(_) => AcceptTokenResult::End,
+            None => AcceptTokenResult::Continue,
+        })
+    }
+
+    /// Get the union of the possible tokens of the candidate samplers, like [`Sampler::all_possible_next_tokens`].
+    ///
+    /// Once a single candidate is left, its possible tokens are returned as they are.
+    pub fn all_possible_next_tokens(
+        &mut self,
+        input_token_id: Option<u32>,
+    ) -> Result<PossibleTokensResult<'_>, Error> {
+        match self.accept_a_token(input_token_id)? {
+            AcceptTokenResult::Continue => {}
+            AcceptTokenResult::End => return Ok(PossibleTokensResult::End),
+            AcceptTokenResult::Failed => return Ok(PossibleTokensResult::InputTokenRejected),
+        }
+        let candidates = self.candidates();
+        if let [i] = candidates[..] {
+            return self.samplers[i].all_possible_next_tokens(None);
+        }
+        self.token_ids.clear();
+        for i in candidates {
+            if let PossibleTokensResult::Continue(token_ids) =
+                self.samplers[i].all_possible_next_tokens(None)?
+            {
+                self.token_ids.union_with(token_ids);
+            }
+        }
+        Ok(PossibleTokensResult::Continue(&self.token_ids))
+    }
+}
@@ -0,0 +1,141 @@
+//! Differential checks between two samplers that should allow the same tokens, e.g. the same grammar with two
+//! [`crate::sampler::SamplerConfig`]s, or a grammar and its manual expansion.
+//!
+//! The masks are compared by their token ids, so masks of different capacities or representations are equal
+//! when they contain the same ids.
+use crate::mask::TokenMask;
+use crate::sampler::{OwnedPossibleTokensResult, Sampler};
+use std::fmt;
+use std::sync::Arc;
+
+/// What a sampler returned at a step of [`compare_samplers`].
+#[derive(Debug, Clone)]
+pub enum StepOutcome {
+    /// The possible tokens.
+    Continue(Arc<TokenMask>),
+    End,
+    InputTokenRejected,
+    /// The error returned by the sampler.
+    Error(String),
+}
+
+impl StepOutcome {
+    fn new(sampler: &mut Sampler, input_token_id: Option<u32>) -> Self {
+        match sampler.next_mask(input_token_id) {
+            Ok(OwnedPossibleTokensResult::Continue(token_ids)) => StepOutcome::Continue(token_ids),
+            Ok(OwnedPossibleTokensResult::End) => StepOutcome::End,
+            Ok(OwnedPossibleTokensResult::InputTokenRejected) => StepOutcome::InputTokenRejected,
+            Err(e) => StepOutcome::Error(e.to_string()),
+        }
+    }
+}
+
+impl PartialEq for StepOutcome {
+    fn eq(&self, other: &Self) -> bool {
+        match (self, other) {
+            (StepOutcome::Continue(a), StepOutcome::Continue(b)) => a.iter().eq(b.iter()),
+            (StepOutcome::End, StepOutcome::End) => true,
+            (StepOutcome::InputTokenRejected, StepOutcome::InputTokenRejected) => true,
+            (StepOutcome::Error(a), StepOutcome::Error(b)) => a == b,
+            _ => false,
+        }
+    }
+}
+
+impl Eq for StepOutcome {}
+
+impl fmt::Display for StepOutcome {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            StepOutcome::Continue(token_ids) => write!(f, "{} possible tokens", token_ids.len()),
+            StepOutcome::End => write!(f, "the end"),
+            StepOutcome::InputTokenRejected => write!(f, "the token rejected"),
+            StepOutcome::Error(e) => write!(f, "the error {e:?}"),
+        }
+    }
+}
+
+/// A step of [`compare_samplers`] where the samplers disagree.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Divergence {
+    /// 0 for the first possible tokens, and `i + 1` after `script[i]`.
+    pub step: usize,
+    /// The token of the script given at the step, or `None` for the first possible tokens.
+    pub token_id: Option<u32>,
+    pub a: StepOutcome,
+    pub b: StepOutcome,
+}
+
+impl Divergence {
+    /// The token ids only `a` allows, empty unless both samplers continue.
+    pub fn only_in_a(&self) -> Vec<u32> {
+        match (&self.a, &self.b) {
+            (StepOutcome::Continue(a), StepOutcome::Continue(b)) => difference(a, b),
+            _ => vec![],
+        }
+    }
+
+    /// The token ids only `b` allows, empty unless both samplers continue.
+    pub fn only_in_b(&self) -> Vec<u32> {
+        match (&self.a, &self.b) {
+            (StepOutcome::Continue(a), StepOutcome::Continue(b)) => difference(b, a),
+            _ => vec![],
+        }
+    }
+}
+
+fn difference(a: &TokenMask, b: &TokenMask) -> Vec<u32> {
+    a.iter()
+        .filter(|x| !b.contains(*x))
+        .map(|x| x as u32)
+        .collect()
+}
+
+impl fmt::Display for Divergence {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self.token_id {
+            Some(token_id) => write!(f, "Step {} after token {token_id}: ", self.step)?,
+            None => write!(f, "Step 0: ")?,
+        }
+        write!(f, "a returns {} and b returns {}", self.a, self.b)?;
+        let (only_in_a, only_in_b) = (self.only_in_a(), self.only_in_b());
+        if !only_in_a.is_empty() {
+            write!(f, ", only a allows {only_in_a:?}")?;
+        }
+        if !only_in_b.is_empty() {
+            write!(f, ", only b allows {only_in_b:?}")?;
+        }
+        Ok(())
+    }
+}
+
+/// Walk both samplers from their current state through the first possible tokens, then through each token of
+/// `script`, and report every step where they return different possible tokens, results or errors.
+///
+/// The walk stops once either sampler does not continue, since the samplers can no longer accept the same tokens.
+pub fn compare_samplers(a: &mut Sampler, b: &mut Sampler, script: &[u32]) -> Vec<Divergence> {
+    let mut divergences = vec![];
+    for (step, token_id) in [None]
+        .into_iter()
+        .chain(script.iter().map(|x| Some(*x)))
+        .enumerate()
+    {
+        let outcomes = (StepOutcome::new(a, token_id), StepOutcome::new(b, token_id));
+        let continues = matches!(
+            outcomes,
+            (StepOutcome::Continue(_), StepOutcome::Continue(_))
+        );
+        if outcomes.0 != outcomes.1 {
+            divergences.push(Divergence {
+                step,
+                token_id,
+                a: outcomes.0,
+                b: outcomes.1,
+            });
+        }
+        if !continues {
+            break;
+        }
+    }
+    divergences
+}
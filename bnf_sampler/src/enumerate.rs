@@ -0,0 +1,215 @@
+//! Enumerate every sentence of a finite grammar, e.g. to check an enum or a bounded template
+//! against the strings a product spec allows.
+use crate::grammar::{Grammar, SimplifiedExpressions, U8Term};
+use crate::trie::TrieNodeID;
+use crate::utils::NonterminalID;
+use anyhow::{anyhow, Error};
+use rustc_hash::{FxHashMap, FxHashSet};
+use std::fmt;
+
+/// The bytes of a sentence so far and the terms left with their depths, where the next term is the last one.
+type Partial<'a> = (Vec<u8>, Vec<(&'a U8Term, usize)>);
+
+/// Limits of [`Grammar::enumerate_sentences`].
+#[derive(Debug, PartialEq, Clone, Copy, Eq)]
+pub struct EnumLimits {
+    /// The maximum number of distinct sentences.
+    pub max_sentences: usize,
+    /// The maximum length of a sentence in bytes.
+    pub max_sentence_bytes: usize,
+    /// The maximum nesting of nonterminals expanded to reach a term.
+    pub max_depth: usize,
+}
+
+impl Default for EnumLimits {
+    fn default() -> Self {
+        Self {
+            max_sentences: 10000,
+            max_sentence_bytes: 1024,
+            max_depth: 64,
+        }
+    }
+}
+
+/// A limit of [`EnumLimits`].
+#[derive(Debug, PartialEq, Clone, Copy, Eq, Hash)]
+pub enum EnumLimit {
+    Sentences,
+    SentenceBytes,
+    Depth,
+}
+
+/// The error of [`Grammar::enumerate_sentences`] when a limit is reached,
+/// returned inside [`anyhow::Error`] so it can be recovered with [`anyhow::Error::downcast_ref`].
+#[derive(Debug, PartialEq, Clone, Copy, Eq)]
+pub struct EnumLimitReached {
+    pub limit: EnumLimit,
+    /// Whether the grammar has infinitely many sentences, so no limits are enough to enumerate them.
+    pub infinite: bool,
+}
+
+impl fmt::Display for EnumLimitReached {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let limit = match self.limit {
+            EnumLimit::Sentences => "the maximum number of sentences",
+            EnumLimit::SentenceBytes => "the maximum length of a sentence",
+            EnumLimit::Depth => "the maximum depth",
+        };
+        write!(f, "The enumeration reaches {limit}")?;
+        match self.infinite {
+            true => write!(f, ", and the grammar has infinitely many sentences."),
+            false => write!(f, ", but the grammar is finite."),
+        }
+    }
+}
+
+impl std::error::Error for EnumLimitReached {}
+
+impl Grammar {
+    /// Every sentence `start` matches, sorted and deduplicated.
+    ///
+    /// The expansions are searched depth first within `limits`, and an [`EnumLimitReached`] error
+    /// tells whether larger limits could help. A token set nonterminal like `<any!>` or `<except!('"')>`
+    /// contributes each whole token of its set, which can be a lot of sentences for a large vocabulary.
+    pub fn enumerate_sentences(
+        &self,
+        start: &str,
+        limits: EnumLimits,
+    ) -> Result<Vec<Vec<u8>>, Error> {
+        let start_id = *self
+            .nonterminal_to_terminal_id
+            .get(start)
+            .ok_or_else(|| anyhow!("<{start}> is not defined in the BNF schema."))?;
+        let limit_reached = |limit| {
+            Error::new(EnumLimitReached {
+                limit,
+                infinite: self.is_infinite(start_id),
+            })
+        };
+        let mut sentences: FxHashSet<Vec<u8>> = FxHashSet::default();
+        let mut work: Vec<Partial> = vec![];
+        let start_term = U8Term::Nonterminal(start.to_string());
+        work.push((vec![], vec![(&start_term, 0)]));
+        while let Some((bytes, mut terms)) = work.pop() {
+            if bytes.len() > limits.max_sentence_bytes {
+                return Err(limit_reached(EnumLimit::SentenceBytes));
+            }
+            let Some((term, depth)) = terms.pop() else {
+                sentences.insert(bytes);
+                if sentences.len() > limits.max_sentences {
+                    return Err(limit_reached(EnumLimit::Sentences));
+                }
+                continue;
+            };
+            let nonterminal = match term {
+                U8Term::Terminal(id) => {
+                    let mut bytes = bytes;
+                    bytes.extend_from_slice(self.terminals.get(*id));
+                    work.push((bytes, terms));
+                    continue;
+                }
+                U8Term::Nonterminal(nonterminal) => nonterminal,
+            };
+            if depth >= limits.max_depth {
+                return Err(limit_reached(EnumLimit::Depth));
+            }
+            let id = self
+                .nonterminal_to_terminal_id
+                .get(nonterminal)
+                .ok_or_else(|| anyhow!("<{nonterminal}> is not defined in the BNF schema."))?;
+            match &self.nonterminal_id_to_expression[id] {
+                SimplifiedExpressions::Expressions(expressions) => {
+                    for expression in expressions.iter() {
+                        let mut terms = terms.clone();
+                        terms.extend(expression.iter().rev().map(|x| (x, depth + 1)));
+                        work.push((bytes.clone(), terms));
+                    }
+                }
+                SimplifiedExpressions::Terminals(root) => {
+                    for terminal in self.trie_terminals(*root) {
+                        let mut bytes = bytes.clone();
+                        bytes.extend_from_slice(terminal);
+                        work.push((bytes, terms.clone()));
+                    }
+                }
+            }
+        }
+        let mut sentences = sentences.into_iter().collect::<Vec<_>>();
+        sentences.sort_unstable();
+        Ok(sentences)
+    }
+
+    /// The terminals of the trie under `root`, without the ones containing a literal excepted by `<except!(...)>`.
+    fn trie_terminals(&self, root: TrieNodeID) -> Vec<&[u8]> {
+        let mut terminals = vec![];
+        let mut nodes = vec![root];
+        while let Some(node_id) = nodes.pop() {
+            let node = self.terminals_trie.get(node_id);
+            if node.negative_bytes_index.is_some() {
+                continue;
+            }
+            if let Some(value) = node.value {
+                terminals.push(self.terminals.get(value));
+            }
+            nodes.extend(node.children.values());
+        }
+        terminals
+    }
+
+    /// Whether a nonterminal reachable from `start` can derive itself among other bytes,
+    /// considering only the alternatives matching some sentence.
+    fn is_infinite(&self, start: NonterminalID) -> bool {
+        let expressions = |id: &NonterminalID| match &self.nonterminal_id_to_expression[id] {
+            SimplifiedExpressions::Expressions(expressions) => Some(expressions),
+            SimplifiedExpressions::Terminals(_) => None,
+        };
+        let nonterminal_id = |term: &U8Term| match term {
+            U8Term::Nonterminal(x) => self.nonterminal_to_terminal_id.get(x).copied(),
+            U8Term::Terminal(_) => None,
+        };
+        let productive = self.productive_nonterminals();
+        let edges: FxHashMap<NonterminalID, Vec<NonterminalID>> = self
+            .nonterminal_id_to_expression
+            .keys()
+            .map(|id| {
+                let next = expressions(id)
+                    .into_iter()
+                    .flatten()
+                    .filter(|terms| {
+                        terms.iter().all(|term| {
+                            nonterminal_id(term).is_none_or(|x| productive.contains(&x))
+                        })
+                    })
+                    .flatten()
+                    .filter_map(nonterminal_id)
+                    .collect();
+                (*id, next)
+            })
+            .collect();
+        // A depth first search, where a nonterminal found on the path closes a cycle.
+        fn visit(
+            id: NonterminalID,
+            edges: &FxHashMap<NonterminalID, Vec<NonterminalID>>,
+            path: &mut FxHashSet<NonterminalID>,
+            done: &mut FxHashSet<NonterminalID>,
+        ) -> bool {
+            if path.contains(&id) {
+                return true;
+            }
+            if !done.insert(id) {
+                return false;
+            }
+            path.insert(id);
+            let found = edges[&id].iter().any(|x| visit(*x, edges, path, done));
+            path.remove(&id);
+            found
+        }
+        productive.contains(&start)
+            && visit(
+                start,
+                &edges,
+                &mut FxHashSet::default(),
+                &mut FxHashSet::default(),
+            )
+    }
+}
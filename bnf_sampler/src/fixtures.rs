@@ -0,0 +1,31 @@
+//! A small synthetic vocabulary and example grammars bundled with the crate, for tests and examples
+//! that should not depend on the assets of a real model.
+//!
+//! The vocabulary has 500 tokens: every byte, common words, runs of whitespace and a few multi-byte UTF-8
+//! sequences. Its first 394 tokens are `assets/tiny_vocab.txt`.
+use crate::utils;
+use crate::vocabulary::{Vocabulary, DEFAULT_MAX_TOKEN_BYTES};
+use std::sync::Arc;
+
+/// The fixture vocabulary in RWKV world vocabulary format.
+pub const VOCABULARY: &str = include_str!("../assets/fixture_vocab.txt");
+
+/// A JSON object with string, number, boolean and null values, starting at `<start>`.
+pub const JSON_OBJECT_GRAMMAR: &str = include_str!("../assets/grammars/json_object.bnf");
+
+/// An arithmetic expression with parentheses followed by `=`, starting at `<start>`.
+pub const ARITHMETIC_GRAMMAR: &str = include_str!("../assets/grammars/arithmetic.bnf");
+
+/// The fixture vocabulary, see the module documentation.
+pub fn vocabulary() -> Arc<Vocabulary> {
+    utils::read_rwkv_world_vocab_from_reader(VOCABULARY.as_bytes(), DEFAULT_MAX_TOKEN_BYTES)
+        .expect("The fixture vocabulary is valid.")
+}
+
+/// The fixture grammars and their names.
+pub fn grammars() -> [(&'static str, &'static str); 2] {
+    [
+        ("json_object", JSON_OBJECT_GRAMMAR),
+        ("arithmetic", ARITHMETIC_GRAMMAR),
+    ]
+}
@@ -1,36 +1,1941 @@
+use crate::mask::TokenMask;
 use crate::sampler::PossibleTokensResult;
-use crate::sampler::Sampler;
+use crate::sampler::{estimate_stack_arena_capacity, Sampler};
+use crate::special;
+use crate::special::{GrammarBuildCtx, ParsedForm, SpecialForm};
+use crate::stack::ArenaExhausted;
 use crate::trie::TerminalsTrie;
 use crate::trie::TrieNodeID;
 use crate::utils;
 use crate::utils::NonterminalID;
-use crate::utils::U8ArrayWrapper;
-use crate::vocabulary::Vocabulary;
+use crate::utils::TerminalID;
+use crate::vocabulary::{TokenClass, Vocabulary};
 use anyhow::{anyhow, ensure, Error};
-use bit_set::BitSet;
 use bnf::Production;
 use bnf::Term;
 use itertools::Itertools;
-use memchr::memmem;
-use regex::Regex;
 use rustc_hash::FxHashMap;
 use rustc_hash::FxHashSet;
+use std::cmp::Reverse;
+use std::collections::{BTreeMap, BTreeSet};
+use std::fmt;
 use std::sync::Arc;
+use std::time::{Duration, Instant};
 #[derive(Debug, Clone, Hash, PartialEq, Eq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
 pub(crate) enum U8Term {
-    Terminal(Vec<u8>),
+    Terminal(TerminalID),
     Nonterminal(String),
 }
 
+/// The errors of [`Grammar::new`] a caller may want to handle,
+/// returned inside [`anyhow::Error`] so they can be recovered with [`anyhow::Error::downcast_ref`].
+#[derive(Debug, PartialEq, Clone, Copy, Eq)]
+pub enum GrammarError {
+    /// The BNF schema is empty, or only contains whitespace and comment lines starting with `#`, `//` or `;`.
+    EmptyGrammar,
+    /// Building the grammar exceeds a limit of [`GrammarBuildOptions::limits`].
+    BuildLimitExceeded(BuildLimit),
+}
+
+/// A syntax error of the BNF schema, returned inside [`anyhow::Error`] by [`Grammar::new`]
+/// so it can be recovered with [`anyhow::Error::downcast_ref`].
+#[derive(Debug, PartialEq, Clone, Eq)]
+pub struct ParseError {
+    /// The line of the error, starting from 1.
+    pub line: usize,
+    /// The column of the error in characters, starting from 1.
+    pub column: usize,
+    /// The line of the error, trimmed.
+    pub snippet: String,
+    pub message: String,
+}
+
+impl fmt::Display for ParseError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(
+            f,
+            "The BNF schema cannot be parsed at line {}, column {}: {} The line is `{}`.",
+            self.line, self.column, self.message, self.snippet
+        )
+    }
+}
+
+impl std::error::Error for ParseError {}
+
+/// How serious a [`Diagnostic`] is.
+#[derive(Debug, PartialEq, Clone, Copy, Eq, Hash, PartialOrd, Ord)]
+pub enum Severity {
+    /// The grammar works, but part of it can never be used.
+    Warning,
+    /// The grammar cannot be built, or no sentence can be sampled from the start nonterminal.
+    Error,
+}
+
+/// The kind of a [`Diagnostic`].
+#[derive(Debug, PartialEq, Clone, Copy, Eq, Hash, PartialOrd, Ord)]
+pub enum DiagnosticKind {
+    /// A nonterminal is used but not defined, or the start nonterminal is not defined.
+    UndefinedNonterminal,
+    /// A nonterminal can start with itself, which is not supported.
+    LeftRecursion,
+    /// A special nonterminal has invalid arguments, like `<except!()>` with nothing to exclude.
+    InvalidSpecialForm,
+    /// Any other error of building the grammar, like a syntax error or an exceeded limit.
+    InvalidSchema,
+    /// A nonterminal whose alternatives all use a nonterminal like itself, so it never matches a complete sentence.
+    /// It is an error for the start nonterminal.
+    NonProductive,
+    /// A nonterminal that no derivation from the start nonterminal uses.
+    Unreachable,
+    /// A `<any!>` or `<except!(...)>` nonterminal that no token in the vocabulary can match.
+    EmptyTokenSet,
+    /// A terminal that no tokenization with the vocabulary can produce, so its alternative is never matched.
+    UnproducibleTerminal,
+    /// A terminal with an unknown escape sequence like `\q`, whose backslash is dropped.
+    /// A raw terminal like `r"C:\temp"` matches backslashes as written.
+    UnknownEscape,
+    /// A rule defining a helper nonterminal like `<int!>`, which replaces the built-in one.
+    OverriddenHelper,
+}
+
+/// A problem of a BNF schema found by [`Grammar::check`] or [`Grammar::validate`].
+///
+/// The errors of building a grammar are returned inside [`anyhow::Error`] by [`Grammar::new`] as well,
+/// so they can be recovered with [`anyhow::Error::downcast_ref`].
+#[derive(Debug, PartialEq, Clone, Eq)]
+pub struct Diagnostic {
+    pub severity: Severity,
+    pub kind: DiagnosticKind,
+    /// The nonterminal the diagnostic concerns, or `None` when it concerns the whole schema or several nonterminals.
+    pub nonterminal: Option<String>,
+    /// The formatted alternative or terminal of the nonterminal the diagnostic concerns, if any.
+    pub production: Option<String>,
+    pub message: String,
+}
+
+impl Diagnostic {
+    fn new(severity: Severity, kind: DiagnosticKind, nonterminal: &str, message: String) -> Self {
+        Diagnostic {
+            severity,
+            kind,
+            nonterminal: Some(nonterminal.to_string()),
+            production: None,
+            message,
+        }
+    }
+}
+
+impl fmt::Display for Diagnostic {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(&self.message)
+    }
+}
+
+impl std::error::Error for Diagnostic {}
+
+/// Find the first syntax error of the BNF schema, to locate an error of the BNF parser,
+/// whose messages do not tell where the error is.
+///
+/// Lines starting with `#`, `//` or `;` are skipped like in [`is_blank`].
+fn locate_syntax_error(input: &str) -> Option<ParseError> {
+    let lines: Vec<Vec<char>> = input.lines().map(|x| x.chars().collect()).collect();
+    let error = |line: usize, column: usize, message: String| ParseError {
+        line: line + 1,
+        column: column + 1,
+        snippet: input.lines().nth(line).unwrap_or("").trim().to_string(),
+        message,
+    };
+    // The column after `::=` if a production starts at `column`.
+    let production_start = |line: &[char], column: usize| -> Option<usize> {
+        let end = column + line[column..].iter().position(|x| *x == '>')?;
+        let rest = line[end + 1..].iter().collect::<String>();
+        let trimmed = rest.trim_start();
+        trimmed
+            .starts_with("::=")
+            .then(|| end + 1 + rest.chars().count() - trimmed.chars().count() + 3)
+    };
+    let mut in_production = false;
+    for (i, line) in lines.iter().enumerate() {
+        let mut j = 0;
+        let text = line.iter().collect::<String>();
+        let trimmed = text.trim_start();
+        if trimmed.starts_with('#') || trimmed.starts_with("//") || trimmed.starts_with(';') {
+            continue;
+        }
+        while j < line.len() {
+            let c = line[j];
+            if c.is_whitespace() {
+                j += 1;
+                continue;
+            }
+            if !in_production {
+                if c != '<' {
+                    return Some(error(
+                        i,
+                        j,
+                        format!(
+                            "a production should start with <nonterminal>::=, but {c:?} is found."
+                        ),
+                    ));
+                }
+                let Some(after) = production_start(line, j) else {
+                    return Some(match line[j..].contains(&'>') {
+                        true => error(i, j, "::= is expected after the nonterminal.".to_string()),
+                        false => error(i, j, "the nonterminal is not closed with >.".to_string()),
+                    });
+                };
+                in_production = true;
+                j = after;
+                continue;
+            }
+            let closing = match c {
+                '\'' | '"' => c,
+                '<' => '>',
+                '[' => ']',
+                '{' => '}',
+                '|' | '(' | ')' | '*' | '+' | '?' => {
+                    j += 1;
+                    continue;
+                }
+                ':' | '=' => {
+                    return Some(error(
+                        i,
+                        j,
+                        "::= is expected after the nonterminal.".to_string(),
+                    ))
+                }
+                _ => return Some(error(i, j, format!("{c:?} is not expected here."))),
+            };
+            if c == '<' {
+                if let Some(after) = production_start(line, j) {
+                    j = after;
+                    continue;
+                }
+            }
+            let mut k = j + 1;
+            while k < line.len() && line[k] != closing {
+                k += if line[k] == '\\' && closing != '>' {
+                    2
+                } else {
+                    1
+                };
+            }
+            if k >= line.len() {
+                let term = match c {
+                    '<' => "nonterminal",
+                    '[' => "character class",
+                    '{' => "repetition bounds",
+                    _ => "terminal",
+                };
+                return Some(error(
+                    i,
+                    j,
+                    format!("the {term} starting with {c} is not closed with {closing} on the same line."),
+                ));
+            }
+            j = k + 1;
+        }
+    }
+    None
+}
+
+/// A limit of [`BuildLimits`].
+#[derive(Debug, PartialEq, Clone, Copy, Eq, Hash)]
+pub enum BuildLimit {
+    Productions,
+    TerminalBytesTotal,
+    TrieNodes,
+    BuildTime,
+}
+
+/// Limits on the work of building a grammar from a BNF schema that is not trusted, see [`GrammarBuildOptions::limits`].
+///
+/// The limits are checked while the grammar is built, so building stops soon after one is exceeded.
+/// The default has no limits.
+#[derive(Debug, PartialEq, Clone, Copy, Eq)]
+pub struct BuildLimits {
+    /// The maximum number of alternatives, including the ones of special nonterminals and repetitions.
+    pub max_productions: usize,
+    /// The maximum sum of the lengths of the terminals in bytes, counting each occurrence.
+    pub max_terminal_bytes_total: usize,
+    /// The maximum number of nodes of the terminals trie, where `<any!>` and `<except!(...)>` add every token.
+    pub max_trie_nodes: usize,
+    pub max_build_time: Duration,
+}
+
+impl Default for BuildLimits {
+    fn default() -> Self {
+        Self {
+            max_productions: usize::MAX,
+            max_terminal_bytes_total: usize::MAX,
+            max_trie_nodes: usize::MAX,
+            max_build_time: Duration::MAX,
+        }
+    }
+}
+
+impl BuildLimits {
+    fn check(&self, limit: BuildLimit, value: usize) -> Result<(), GrammarError> {
+        let max = match limit {
+            BuildLimit::Productions => self.max_productions,
+            BuildLimit::TerminalBytesTotal => self.max_terminal_bytes_total,
+            BuildLimit::TrieNodes => self.max_trie_nodes,
+            BuildLimit::BuildTime => unreachable!(),
+        };
+        match value > max {
+            true => Err(GrammarError::BuildLimitExceeded(limit)),
+            false => Ok(()),
+        }
+    }
+
+    fn check_time(&self, start: Instant) -> Result<(), GrammarError> {
+        match start.elapsed() > self.max_build_time {
+            true => Err(GrammarError::BuildLimitExceeded(BuildLimit::BuildTime)),
+            false => Ok(()),
+        }
+    }
+}
+
+impl fmt::Display for GrammarError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            GrammarError::EmptyGrammar => write!(f, "The BNF schema defines no nonterminal."),
+            GrammarError::BuildLimitExceeded(limit) => {
+                let limit = match limit {
+                    BuildLimit::Productions => "the maximum number of alternatives",
+                    BuildLimit::TerminalBytesTotal => "the maximum total length of the terminals",
+                    BuildLimit::TrieNodes => "the maximum number of trie nodes",
+                    BuildLimit::BuildTime => "the maximum build time",
+                };
+                write!(f, "Building the grammar exceeds {limit}.")
+            }
+        }
+    }
+}
+
+impl std::error::Error for GrammarError {}
+
+/// The number of alternatives of the BNF schema, counting each `::=` and each `|` outside terminals,
+/// so [`BuildLimits::max_productions`] is checked before parsing, which is slow for many alternatives.
+fn count_alternatives(input: &str) -> usize {
+    let mut count = input.matches("::=").count();
+    let mut quote: Option<char> = None;
+    let mut escaped = false;
+    for c in input.chars() {
+        match quote {
+            Some(_) if escaped => escaped = false,
+            Some(_) if c == '\\' => escaped = true,
+            Some(x) if c == x => quote = None,
+            Some(_) => {}
+            None if c == '\'' || c == '"' => quote = Some(c),
+            None if c == '|' => count += 1,
+            None => {}
+        }
+    }
+    count
+}
+
+/// Blank out the comment lines starting with `#`, `//` or `;`, and remove the `; comment` after a production,
+/// keeping the lines so errors point at the same lines. A `#`, `//` or `;` inside a terminal, a nonterminal
+/// or a character class is not a comment.
+pub(crate) fn strip_comments(input: &str) -> String {
+    let mut output = String::with_capacity(input.len());
+    for line in input.split_inclusive('\n') {
+        let (content, newline) = match line.strip_suffix('\n') {
+            Some(content) => (content, "\n"),
+            None => (line, ""),
+        };
+        let trimmed = content.trim_start();
+        if trimmed.starts_with('#') || trimmed.starts_with("//") || trimmed.starts_with(';') {
+            output.push_str(newline);
+            continue;
+        }
+        // The character closing the terminal, the nonterminal or the class being read.
+        let mut closing: Option<char> = None;
+        let mut escaped = false;
+        let mut end = content.len();
+        for (i, c) in content.char_indices() {
+            match closing {
+                Some(_) if escaped => escaped = false,
+                Some(x) if c == '\\' && x != '>' => escaped = true,
+                Some(x) if c == x => closing = None,
+                Some(_) => {}
+                None => match c {
+                    '\'' | '"' => closing = Some(c),
+                    '<' => closing = Some('>'),
+                    '[' => closing = Some(']'),
+                    ';' => {
+                        end = i;
+                        break;
+                    }
+                    _ => {}
+                },
+            }
+        }
+        output.push_str(content[..end].trim_end_matches([' ', '\t']));
+        output.push_str(newline);
+    }
+    output
+}
+
+/// Whether `input` has no line other than blank and comment lines.
+fn is_blank(input: &str) -> bool {
+    input.lines().map(str::trim).all(|line| {
+        line.is_empty() || line.starts_with('#') || line.starts_with("//") || line.starts_with(';')
+    })
+}
+
+#[derive(Clone, Debug, Default)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+/// Stores each distinct terminal of the grammar once so expressions and stacks can refer to them by id.
+pub(crate) struct TerminalsInterner {
+    /// Rebuilt from `terminals` by [`TerminalsInterner::reindex`] when the grammar is loaded.
+    #[cfg_attr(feature = "serde", serde(skip))]
+    terminal_to_id: FxHashMap<Box<[u8]>, TerminalID>,
+    terminals: Vec<Box<[u8]>>,
+}
+
+impl TerminalsInterner {
+    pub fn intern(&mut self, terminal: &[u8]) -> TerminalID {
+        if let Some(id) = self.terminal_to_id.get(terminal) {
+            return *id;
+        }
+        let id = TerminalID(self.terminals.len());
+        let terminal: Box<[u8]> = terminal.into();
+        self.terminals.push(terminal.clone());
+        self.terminal_to_id.insert(terminal, id);
+        id
+    }
+
+    #[inline]
+    pub fn get(&self, id: TerminalID) -> &[u8] {
+        &self.terminals[id.0]
+    }
+
+    pub fn len(&self) -> usize {
+        self.terminals.len()
+    }
+
+    /// The approximate heap memory of the interned terminals in bytes, counting each terminal once
+    /// however many alternatives, trie nodes and stacks refer to it, plus the index of the terminals.
+    pub fn memory_bytes(&self) -> usize {
+        let terminals: usize = self.terminals.iter().map(|x| x.len()).sum();
+        // Each terminal is boxed twice, in the arena and as a key of the index.
+        2 * terminals
+            + self.terminals.capacity() * std::mem::size_of::<Box<[u8]>>()
+            + self.terminal_to_id.capacity() * std::mem::size_of::<(Box<[u8]>, TerminalID)>()
+    }
+
+    /// Rebuild the ids of the terminals after they are deserialized.
+    #[cfg(feature = "serde")]
+    pub fn reindex(&mut self) {
+        self.terminal_to_id = self
+            .terminals
+            .iter()
+            .enumerate()
+            .map(|(i, terminal)| (terminal.clone(), TerminalID(i)))
+            .collect();
+    }
+}
+
+/// Format an expression in BNF syntax for error messages.
+pub(crate) fn format_expression(expression: &[U8Term], terminals: &TerminalsInterner) -> String {
+    if expression.is_empty() {
+        return "''".to_string();
+    }
+    expression
+        .iter()
+        .map(|term| match term {
+            U8Term::Terminal(id) => {
+                format!(
+                    "'{}'",
+                    String::from_utf8_lossy(terminals.get(*id)).escape_debug()
+                )
+            }
+            U8Term::Nonterminal(nonterminal) => format!("<{nonterminal}>"),
+        })
+        .join("")
+}
+
+/// The default maximum length of a terminal in bytes.
+/// It is generous since long fixed templates are legitimate terminals.
+pub const DEFAULT_MAX_TERMINAL_BYTES: usize = 64 * 1024;
+
+/// The default maximum number of literals an `<except!([nonterminal])>` can exclude.
+/// Every literal marks the trie of the special nonterminal, so a larger set slows the construction down.
+pub const DEFAULT_MAX_EXCEPT_TERMINALS: usize = 512;
+
+/// Unescape a terminal of `nonterminal` and check its length.
+fn checked_terminal(
+    nonterminal: &str,
+    value: &str,
+    max_terminal_bytes: usize,
+) -> Result<Vec<u8>, Error> {
+    let bytes = utils::fix_utf8_escape(value)?;
+    ensure!(
+        bytes.len() <= max_terminal_bytes,
+        "<{nonterminal}> contains a terminal of {} bytes, which exceeds the maximum of {max_terminal_bytes} bytes.",
+        bytes.len()
+    );
+    Ok(bytes)
+}
+
+/// Push `term`, merging it into the last term when both are terminals like in the BNF schema,
+/// unless the merged terminal is longer than `max_terminal_bytes`.
+fn push_merged(
+    terms: &mut Vec<U8Term>,
+    term: U8Term,
+    terminals: &mut TerminalsInterner,
+    max_terminal_bytes: usize,
+) {
+    if let (Some(U8Term::Terminal(last)), U8Term::Terminal(id)) = (terms.last(), &term) {
+        let merged = [terminals.get(*last), terminals.get(*id)].concat();
+        if merged.len() <= max_terminal_bytes {
+            *terms.last_mut().unwrap() = U8Term::Terminal(terminals.intern(&merged));
+            return;
+        }
+    }
+    terms.push(term);
+}
+
+/// The nonterminal holding the alternatives of a folded nonterminal, see [`fold_terminals`].
+const FOLDED_NONTERMINAL_NAME: &str = "folded!";
+
+/// Move the bytes every alternative of a nonterminal starts or ends with out of it, next to the terminals
+/// around its uses, see [`GrammarBuildOptions::fold_terminals`]. Returns the number of bytes moved.
+///
+/// `<key>::='"'<chars>'"'|'"k"'` becomes `<folded!(key)>::=<chars>|'k'`, and each use `'{'<key>':'` becomes
+/// `'{"'<folded!(key)>'":'`. `<key>` stays defined as `'"'<folded!(key)>'"'`, so it can still be a start nonterminal.
+/// Special nonterminals, the nonterminals excluded by `<except!([nonterminal])>` and the nonterminals no rule uses
+/// are not folded, and each nonterminal is folded at most once.
+fn fold_terminals(
+    simplified_grammar: &mut FxHashMap<String, FxHashSet<Vec<U8Term>>>,
+    terminals: &mut TerminalsInterner,
+    excepts: &[(String, String)],
+    max_terminal_bytes: usize,
+) -> usize {
+    let mut candidates = simplified_grammar
+        .keys()
+        .filter(|name| !name.contains('!') && excepts.iter().all(|(_, x)| x != *name))
+        .cloned()
+        .collect_vec();
+    candidates.sort_unstable();
+    let mut folded_bytes = 0;
+    // Folding a nonterminal can make the alternatives using it start or end with a terminal, so it is repeated.
+    let mut changed = true;
+    while std::mem::take(&mut changed) {
+        candidates.retain(|name| {
+            match fold_nonterminal(simplified_grammar, terminals, name, max_terminal_bytes) {
+                Some(bytes) => {
+                    folded_bytes += bytes;
+                    changed = true;
+                    false
+                }
+                None => true,
+            }
+        });
+    }
+    folded_bytes
+}
+
+/// The bytes shared by the first terminals of every alternative, or by the last ones when `last` is true,
+/// or `None` if there are none or an alternative does not start or end with a terminal.
+fn shared_bytes(
+    expressions: &[Vec<U8Term>],
+    terminals: &TerminalsInterner,
+    last: bool,
+) -> Option<Vec<u8>> {
+    let mut shared: Option<&[u8]> = None;
+    for terms in expressions {
+        let term = match last {
+            false => terms.first(),
+            true => terms.last(),
+        };
+        let Some(U8Term::Terminal(id)) = term else {
+            return None;
+        };
+        let bytes = terminals.get(*id);
+        shared = Some(match shared {
+            None => bytes,
+            Some(x) if last => {
+                let len = x
+                    .iter()
+                    .rev()
+                    .zip(bytes.iter().rev())
+                    .take_while(|(a, b)| a == b)
+                    .count();
+                &x[x.len() - len..]
+            }
+            Some(x) => &x[..x.iter().zip(bytes).take_while(|(a, b)| a == b).count()],
+        });
+    }
+    shared.filter(|x| !x.is_empty()).map(|x| x.to_vec())
+}
+
+/// Fold `name`, see [`fold_terminals`]. Returns the number of bytes moved, or `None` if it cannot be folded.
+fn fold_nonterminal(
+    simplified_grammar: &mut FxHashMap<String, FxHashSet<Vec<U8Term>>>,
+    terminals: &mut TerminalsInterner,
+    name: &str,
+    max_terminal_bytes: usize,
+) -> Option<usize> {
+    let uses = |x: &U8Term| matches!(x, U8Term::Nonterminal(x) if x == name);
+    if !simplified_grammar.values().flatten().flatten().any(uses) {
+        return None;
+    }
+    let mut expressions = simplified_grammar[name].iter().cloned().collect_vec();
+    let prefix = shared_bytes(&expressions, terminals, false);
+    if let Some(prefix) = &prefix {
+        for terms in expressions.iter_mut() {
+            let U8Term::Terminal(id) = terms[0] else {
+                unreachable!()
+            };
+            match terminals.get(id)[prefix.len()..].to_vec() {
+                rest if rest.is_empty() => _ = terms.remove(0),
+                rest => terms[0] = U8Term::Terminal(terminals.intern(&rest)),
+            }
+        }
+    }
+    let suffix = shared_bytes(&expressions, terminals, true);
+    if let Some(suffix) = &suffix {
+        for terms in expressions.iter_mut() {
+            let Some(U8Term::Terminal(id)) = terms.pop() else {
+                unreachable!()
+            };
+            let bytes = terminals.get(id);
+            let rest = bytes[..bytes.len() - suffix.len()].to_vec();
+            if !rest.is_empty() {
+                terms.push(U8Term::Terminal(terminals.intern(&rest)));
+            }
+        }
+    }
+    if prefix.is_none() && suffix.is_none() {
+        return None;
+    }
+    let folded_bytes = prefix.iter().chain(suffix.iter()).map(|x| x.len()).sum();
+    let folded = format!("{FOLDED_NONTERMINAL_NAME}({name})");
+    let replacement: Vec<U8Term> = prefix
+        .map(|x| U8Term::Terminal(terminals.intern(&x)))
+        .into_iter()
+        .chain([U8Term::Nonterminal(folded.clone())])
+        .chain(suffix.map(|x| U8Term::Terminal(terminals.intern(&x))))
+        .collect();
+    simplified_grammar.insert(folded, expressions.into_iter().collect());
+    for expressions in simplified_grammar.values_mut() {
+        if !expressions.iter().flatten().any(uses) {
+            continue;
+        }
+        *expressions = std::mem::take(expressions)
+            .into_iter()
+            .map(|expression| {
+                let mut terms: Vec<U8Term> = Vec::with_capacity(expression.len() + 2);
+                for term in expression {
+                    let expanded = match uses(&term) {
+                        true => replacement.clone(),
+                        false => vec![term],
+                    };
+                    for term in expanded {
+                        push_merged(&mut terms, term, terminals, max_terminal_bytes);
+                    }
+                }
+                terms
+            })
+            .collect();
+    }
+    simplified_grammar.insert(name.to_string(), FxHashSet::from_iter([replacement]));
+    Some(folded_bytes)
+}
+
+/// Replace the nonterminals whose only alternative is a single terminal or a single nonterminal with that term
+/// wherever they are used, and remove them, see [`GrammarBuildOptions::inline_trivial_nonterminals`].
+///
+/// Special nonterminals, the nonterminals excluded by `<except!([nonterminal])>` and the nonterminals no rule uses
+/// are kept, and a nonterminal is not replaced with a special nonterminal.
+fn inline_trivial(
+    simplified_grammar: &mut FxHashMap<String, FxHashSet<Vec<U8Term>>>,
+    terminals: &mut TerminalsInterner,
+    excepts: &[(String, String)],
+    max_terminal_bytes: usize,
+) {
+    let is_used = |grammar: &FxHashMap<String, FxHashSet<Vec<U8Term>>>, name: &str| {
+        grammar
+            .values()
+            .flatten()
+            .flatten()
+            .any(|term| matches!(term, U8Term::Nonterminal(x) if x == name))
+    };
+    loop {
+        // The smallest name is inlined first, so the terminals are merged the same way on every build.
+        let trivial = simplified_grammar
+            .iter()
+            .filter(|(name, expressions)| {
+                !name.contains('!')
+                    && expressions.len() == 1
+                    && excepts.iter().all(|(_, excepted)| excepted != *name)
+            })
+            .filter_map(
+                |(name, expressions)| match expressions.iter().next().unwrap().as_slice() {
+                    [U8Term::Nonterminal(x)] if x.contains('!') || x == name => None,
+                    [term] => Some((name.clone(), term.clone())),
+                    _ => None,
+                },
+            )
+            .filter(|(name, _)| is_used(simplified_grammar, name))
+            .min_by(|a, b| a.0.cmp(&b.0));
+        let Some((name, replacement)) = trivial else {
+            return;
+        };
+        simplified_grammar.remove(&name);
+        for expressions in simplified_grammar.values_mut() {
+            *expressions = std::mem::take(expressions)
+                .into_iter()
+                .map(|expression| {
+                    let mut terms: Vec<U8Term> = Vec::with_capacity(expression.len());
+                    for term in expression {
+                        let term = match term {
+                            U8Term::Nonterminal(x) if x == name => replacement.clone(),
+                            term => term,
+                        };
+                        push_merged(&mut terms, term, terminals, max_terminal_bytes);
+                    }
+                    terms
+                })
+                .collect();
+        }
+    }
+}
+
+/// The nonterminal matching a run of whitespace, see [`GrammarBuildOptions::collapse_whitespace_runs`].
+const WHITESPACE_RUN_NONTERMINAL: &str = "whitespace_run!";
+
+/// Split the terminals at runs of whitespace, and match the runs with [`WHITESPACE_RUN_NONTERMINAL`].
+fn collapse_whitespace(
+    simplified_grammar: &mut FxHashMap<String, FxHashSet<Vec<U8Term>>>,
+    terminals: &mut TerminalsInterner,
+) {
+    let is_whitespace = |x: &u8| matches!(x, b' ' | b'\t' | b'\n' | b'\r');
+    let run = || U8Term::Nonterminal(WHITESPACE_RUN_NONTERMINAL.to_string());
+    let mut collapsed = false;
+    for (nonterminal, expressions) in simplified_grammar.iter_mut() {
+        if nonterminal.contains('!') {
+            continue;
+        }
+        *expressions = std::mem::take(expressions)
+            .into_iter()
+            .map(|expression| {
+                let mut terms = vec![];
+                for term in expression {
+                    let U8Term::Terminal(id) = term else {
+                        terms.push(term);
+                        continue;
+                    };
+                    let terminal = terminals.get(id).to_vec();
+                    if !terminal.iter().any(is_whitespace) {
+                        terms.push(term);
+                        continue;
+                    }
+                    collapsed = true;
+                    let mut start = 0;
+                    while start < terminal.len() {
+                        let whitespace = is_whitespace(&terminal[start]);
+                        let len = terminal[start..]
+                            .iter()
+                            .take_while(|x| is_whitespace(x) == whitespace)
+                            .count();
+                        match whitespace {
+                            // Adjacent runs are merged, like `' '' '` which is one run.
+                            true if terms.last() == Some(&run()) => {}
+                            true => terms.push(run()),
+                            false => terms.push(U8Term::Terminal(
+                                terminals.intern(&terminal[start..start + len]),
+                            )),
+                        }
+                        start += len;
+                    }
+                }
+                terms
+            })
+            .collect();
+    }
+    if collapsed {
+        let byte = format!("{WHITESPACE_RUN_NONTERMINAL}/byte");
+        let byte_term = U8Term::Nonterminal(byte.clone());
+        simplified_grammar.insert(
+            WHITESPACE_RUN_NONTERMINAL.to_string(),
+            FxHashSet::from_iter([vec![byte_term.clone()], vec![byte_term, run()]]),
+        );
+        simplified_grammar.insert(
+            byte,
+            b" \t\n\r"
+                .iter()
+                .map(|x| vec![U8Term::Terminal(terminals.intern(&[*x]))])
+                .collect(),
+        );
+    }
+}
+
+/// The bytes matched by the character class `class`, written without its brackets, like `^a-z\]`.
+///
+/// Escape sequences are the same as in terminals, and `\]`, `\-`, `\^` and `\\` are the literal characters.
+/// A `-` at the start or the end of the class is literal too.
+fn class_bytes(class: &str) -> Result<[bool; 256], String> {
+    let (negated, class) = match class.strip_prefix('^') {
+        Some(x) => (true, x),
+        None => (false, class),
+    };
+    // Each byte, and whether it is a `-` that forms a range.
+    let mut items: Vec<(u8, bool)> = vec![];
+    let mut chars = class.chars().peekable();
+    while let Some(c) = chars.next() {
+        let item = match c {
+            '\\' => {
+                let escaped: String = match chars.next() {
+                    Some('x') => format!("\\x{}", chars.by_ref().take(2).collect::<String>()),
+                    Some('u') => format!("\\u{}", chars.by_ref().take(4).collect::<String>()),
+                    Some(x) => format!("\\{x}"),
+                    None => return Err("it ends with an incomplete escape sequence".to_string()),
+                };
+                match utils::fix_utf8_escape(&escaped).map_err(|e| e.to_string())?[..] {
+                    [byte] => (byte, false),
+                    _ => return Err(format!("{escaped} is not a single byte")),
+                }
+            }
+            '-' => (b'-', !items.is_empty() && chars.peek().is_some()),
+            c if c.is_ascii() => (c as u8, false),
+            c => {
+                return Err(format!(
+                    "{c} is not a single byte, write bytes above 0x7F like \\xC3 instead"
+                ))
+            }
+        };
+        items.push(item);
+    }
+    let mut bytes = [false; 256];
+    let mut i = 0;
+    while i < items.len() {
+        let (low, high) = match items.get(i + 1) {
+            Some((_, true)) => {
+                let (low, high) = (items[i].0, items[i + 2].0);
+                if low > high {
+                    return Err(format!(
+                        "the range {:?}-{:?} is reversed",
+                        low as char, high as char
+                    ));
+                }
+                i += 3;
+                (low, high)
+            }
+            _ => {
+                i += 1;
+                (items[i - 1].0, items[i - 1].0)
+            }
+        };
+        (low..=high).for_each(|x| bytes[x as usize] = true);
+    }
+    if negated {
+        bytes.iter_mut().for_each(|x| *x = !*x);
+    }
+    if !bytes.contains(&true) {
+        return Err("it matches no byte".to_string());
+    }
+    Ok(bytes)
+}
+
+/// The start nonterminal of a grammar without a `@start` directive.
+pub const DEFAULT_START_NONTERMINAL: &str = "start";
+
+/// Blank out the `@start <name>` line, keeping the lines so errors point at the same lines,
+/// and return the declared start nonterminal. The angle brackets around the name are optional.
+fn take_start_directive(input: &str) -> Result<(String, Option<String>), Error> {
+    let mut output = String::with_capacity(input.len());
+    let mut start = None;
+    for line in input.split_inclusive('\n') {
+        let Some(name) = line.trim_start().strip_prefix("@start") else {
+            output.push_str(line);
+            continue;
+        };
+        let name = name.trim();
+        let name = name
+            .strip_prefix('<')
+            .and_then(|x| x.strip_suffix('>'))
+            .unwrap_or(name);
+        ensure!(
+            !name.is_empty() && !name.contains(['<', '>']) && !name.contains(char::is_whitespace),
+            "{} should be followed by the name of a nonterminal, like @start <json>.",
+            line.trim()
+        );
+        ensure!(
+            start.is_none(),
+            "@start is declared more than once, as <{}> and <{name}>.",
+            start.unwrap_or_default()
+        );
+        start = Some(name.to_string());
+        output.push_str(if line.ends_with('\n') { "\n" } else { "" });
+    }
+    Ok((output, start))
+}
+
+/// Replace every raw terminal, like `r"C:\temp"` or `r#"say "hi""#`, with a terminal matching the same characters,
+/// where the backslashes and the quotes are written as `\x5C`, `\x22` and `\x27`. A raw terminal is delimited
+/// like a raw string of Rust: the closing quote must be followed by as many `#` as the opening quote is preceded by.
+fn lower_raw_terminals(input: &str) -> String {
+    let mut output = String::with_capacity(input.len());
+    // The character closing the nonterminal, the terminal or the class being read.
+    let mut closing: Option<char> = None;
+    let mut escaped = false;
+    let mut i = 0;
+    while let Some(c) = input[i..].chars().next() {
+        i += c.len_utf8();
+        if let Some(x) = closing {
+            output.push(c);
+            match c {
+                _ if escaped => escaped = false,
+                '\\' if x != '>' => escaped = true,
+                _ if c == x => closing = None,
+                _ => {}
+            }
+            continue;
+        }
+        let rest = &input[i..];
+        let hashes = rest.len() - rest.trim_start_matches('#').len();
+        let quote = rest[hashes..]
+            .chars()
+            .next()
+            .filter(|x| *x == '\'' || *x == '"');
+        let is_prefix = !input[..i - 1]
+            .chars()
+            .next_back()
+            .is_some_and(|x| x.is_ascii_alphanumeric() || x == '_');
+        let (Some(quote), 'r', true) = (quote, c, is_prefix) else {
+            match c {
+                '<' => closing = Some('>'),
+                '\'' | '"' | '[' => closing = Some(if c == '[' { ']' } else { c }),
+                _ => {}
+            }
+            output.push(c);
+            continue;
+        };
+        let start = i + hashes + 1;
+        let terminator = format!("{quote}{}", "#".repeat(hashes));
+        let Some(len) = input[start..].find(&terminator) else {
+            // The unclosed terminal is reported by the parser.
+            output.push_str(&input[i - 1..]);
+            break;
+        };
+        output.push(quote);
+        for c in input[start..start + len].chars() {
+            match c {
+                '\\' | '\'' | '"' => output.push_str(&format!("\\x{:02X}", c as u8)),
+                c => output.push(c),
+            }
+        }
+        output.push(quote);
+        i = start + len + terminator.len();
+    }
+    output
+}
+
+/// The terminals with an escape sequence other than `\t`, `\n`, `\r`, `\x`, `\u`, `\\`, `\'` and `\"`,
+/// and the nonterminals defining them. Such a backslash is dropped, which is usually a mistake like `'C:\temp'`
+/// matching a tab, so [`Grammar::validate`] warns about it.
+fn unknown_escapes(input: &str) -> Vec<(String, String)> {
+    let mut found = vec![];
+    let mut lhs = "";
+    // The character closing the nonterminal, the terminal or the class being read, and where it started.
+    let mut closing: Option<(char, usize)> = None;
+    let mut escaped = false;
+    let mut unknown = false;
+    for (i, c) in input.char_indices() {
+        let Some((x, start)) = closing else {
+            match c {
+                '<' => closing = Some(('>', i)),
+                '\'' | '"' => closing = Some((c, i)),
+                '[' => closing = Some((']', i)),
+                _ => {}
+            }
+            continue;
+        };
+        if escaped {
+            escaped = false;
+            unknown |= x != '>'
+                && x != ']'
+                && !matches!(c, 't' | 'n' | 'r' | 'x' | 'u' | '\\' | '\'' | '"');
+            continue;
+        }
+        if c == '\\' && x != '>' {
+            escaped = true;
+            continue;
+        }
+        if c != x {
+            continue;
+        }
+        closing = None;
+        match x {
+            '>' if input[i + 1..].trim_start().starts_with("::=") => lhs = &input[start + 1..i],
+            '\'' | '"' if std::mem::take(&mut unknown) => {
+                found.push((lhs.to_string(), input[start..=i].to_string()))
+            }
+            _ => {}
+        }
+    }
+    found.sort_unstable();
+    found.dedup();
+    found
+}
+
+/// Replace every case-insensitive terminal, like `i"select"`, with a character class per ASCII letter,
+/// like `[sS][eE][lL][eE][cC][tT]`, so each letter shares one root of the terminals trie with the other casing
+/// instead of enumerating every casing. The other characters and the escape sequences are kept as terminals.
+/// The classes are grouped in parentheses when a repetition follows.
+fn lower_case_insensitive(input: &str) -> String {
+    let mut output = String::with_capacity(input.len());
+    // The character closing the nonterminal, the terminal or the class being read.
+    let mut closing: Option<char> = None;
+    let mut escaped = false;
+    let mut chars = input.char_indices().peekable();
+    while let Some((i, c)) = chars.next() {
+        if let Some(x) = closing {
+            output.push(c);
+            match c {
+                _ if escaped => escaped = false,
+                '\\' if x != '>' => escaped = true,
+                _ if c == x => closing = None,
+                _ => {}
+            }
+            continue;
+        }
+        let quote = chars
+            .peek()
+            .map(|(_, x)| *x)
+            .filter(|x| *x == '\'' || *x == '"');
+        let (Some(quote), 'i') = (quote, c) else {
+            match c {
+                '<' => closing = Some('>'),
+                '\'' | '"' | '[' => closing = Some(if c == '[' { ']' } else { c }),
+                _ => {}
+            }
+            output.push(c);
+            continue;
+        };
+        if input[..i]
+            .chars()
+            .next_back()
+            .is_some_and(|x| x.is_ascii_alphanumeric() || x == '_')
+        {
+            output.push(c);
+            continue;
+        }
+        chars.next();
+        let mut pieces = vec![];
+        let mut literal = String::new();
+        let mut closed = false;
+        while let Some((_, c)) = chars.next() {
+            match c {
+                '\\' => {
+                    literal.push(c);
+                    let length = match chars.peek().map(|(_, x)| *x) {
+                        Some('x') => 3,
+                        Some('u') => 5,
+                        _ => 1,
+                    };
+                    literal.extend(chars.by_ref().take(length).map(|(_, x)| x));
+                }
+                _ if c == quote => {
+                    closed = true;
+                    break;
+                }
+                _ if c.is_ascii_alphabetic() => {
+                    if !literal.is_empty() {
+                        pieces.push(format!("{quote}{}{quote}", std::mem::take(&mut literal)));
+                    }
+                    pieces.push(format!(
+                        "[{}{}]",
+                        c.to_ascii_lowercase(),
+                        c.to_ascii_uppercase()
+                    ));
+                }
+                _ => literal.push(c),
+            }
+        }
+        if !closed {
+            // The unclosed terminal is reported by the parser.
+            output.push_str(&input[i..]);
+            break;
+        }
+        if !literal.is_empty() || pieces.is_empty() {
+            pieces.push(format!("{quote}{literal}{quote}"));
+        }
+        let repeated = chars
+            .peek()
+            .is_some_and(|(_, x)| matches!(x, '*' | '+' | '?' | '{'));
+        match pieces.len() > 1 && repeated {
+            true => output.push_str(&format!("({})", pieces.concat())),
+            false => output.push_str(&pieces.concat()),
+        }
+    }
+    output
+}
+
+/// Replace every character class, like `[a-zA-Z_]` or `[^"]`, with a hidden nonterminal like `<[a-zA-Z_]!>`
+/// whose alternatives are the single bytes of the class, so they share one root of the terminals trie.
+fn lower_classes(input: &str) -> Result<String, Error> {
+    let mut output = String::with_capacity(input.len());
+    let mut productions = vec![];
+    let mut defined = FxHashSet::default();
+    let mut lhs = "";
+    // The character closing the nonterminal, the terminal or the class being read, and where it started.
+    let mut closing: Option<(char, usize)> = None;
+    let mut escaped = false;
+    for (i, c) in input.char_indices() {
+        let Some((x, start)) = closing else {
+            match c {
+                '<' => closing = Some(('>', i)),
+                '\'' | '"' => closing = Some((c, i)),
+                '[' => {
+                    closing = Some((']', i));
+                    continue;
+                }
+                _ => {}
+            }
+            output.push(c);
+            continue;
+        };
+        if x != ']' {
+            output.push(c);
+        }
+        if c != x || (x == ']' && escaped) {
+            escaped = x == ']' && c == '\\' && !escaped;
+            continue;
+        }
+        closing = None;
+        if x == '>' && input[i + 1..].trim_start().starts_with("::=") {
+            lhs = &input[start + 1..i];
+        }
+        if x != ']' {
+            continue;
+        }
+        let class = &input[start + 1..i];
+        let bytes = class_bytes(class)
+            .map_err(|e| anyhow!("<{lhs}> is invalid because [{class}] is invalid: {e}."))?;
+        let name = format!("[{}]!", class.replace('>', "\\x3E"));
+        output.push_str(&format!("<{name}>"));
+        if defined.insert(name.clone()) {
+            let alternatives = (0..=u8::MAX)
+                .filter(|x| bytes[*x as usize])
+                .map(|x| format!("'\\x{x:02X}'"))
+                .join("|");
+            productions.push(format!("\n<{name}>::={alternatives}"));
+        }
+    }
+    if let Some((']', start)) = closing {
+        return Err(anyhow!(
+            "<{lhs}> is invalid because {} is not closed.",
+            input[start..].lines().next().unwrap_or_default()
+        ));
+    }
+    output.extend(productions);
+    Ok(output)
+}
+
+/// Replace every group in parentheses, like `('a'|'b')`, with a hidden nonterminal like `<lhs/group1!>`
+/// defined by the alternatives inside the group, since the BNF parser does not accept them.
+///
+/// Nested groups are replaced from the innermost one, and a name already used in the schema is never reused.
+fn lower_groups(input: &str) -> Result<String, Error> {
+    // Everything between `<` and `>`, which includes every nonterminal and maybe parts of terminals.
+    let used: FxHashSet<&str> = input
+        .split('<')
+        .skip(1)
+        .filter_map(|x| x.split_once('>').map(|(name, _)| name))
+        .collect();
+    let mut output = String::with_capacity(input.len());
+    let mut groups: Vec<String> = vec![];
+    let mut productions = vec![];
+    let mut lhs = String::new();
+    // The number of groups of each nonterminal so far, which may be defined more than once.
+    let mut counts: FxHashMap<String, usize> = FxHashMap::default();
+    // The character closing the nonterminal or the terminal being read, and where it started.
+    let mut closing: Option<(char, usize)> = None;
+    for (i, c) in input.char_indices() {
+        let buffer = groups.last_mut().unwrap_or(&mut output);
+        if let Some((x, start)) = closing {
+            buffer.push(c);
+            if c == x {
+                closing = None;
+                if x == '>' && input[i + 1..].trim_start().starts_with("::=") {
+                    lhs = input[start + 1..i].to_string();
+                }
+            }
+            continue;
+        }
+        match c {
+            '<' => closing = Some(('>', i)),
+            '\'' | '"' => closing = Some((c, i)),
+            '(' => {
+                groups.push(String::new());
+                continue;
+            }
+            ')' => {
+                let group = groups
+                    .pop()
+                    .ok_or_else(|| anyhow!("<{lhs}> is invalid because a ) is not opened."))?;
+                ensure!(
+                    !is_blank(&group),
+                    "<{lhs}> is invalid because the group () is empty."
+                );
+                let count = counts.entry(lhs.clone()).or_default();
+                let name = loop {
+                    *count += 1;
+                    let name = format!("{lhs}/group{count}!");
+                    if !used.contains(name.as_str()) {
+                        break name;
+                    }
+                };
+                groups
+                    .last_mut()
+                    .unwrap_or(&mut output)
+                    .push_str(&format!("<{name}>"));
+                productions.push((name, group));
+                continue;
+            }
+            _ => {}
+        }
+        buffer.push(c);
+    }
+    ensure!(
+        groups.is_empty(),
+        "<{lhs}> is invalid because a ( is not closed."
+    );
+    for (name, group) in productions {
+        output.push_str(&format!("\n<{name}>::={group}"));
+    }
+    Ok(output)
+}
+
+/// Replace the `*`, `+`, `?` and `{m,n}` right after a nonterminal or a terminal with a marker nonterminal
+/// like `<*!>` or `<{m,n}!>`, since the BNF parser does not accept them.
+/// [`expand_repetitions`] expands the markers after parsing.
+fn mark_repetitions(input: &str) -> String {
+    let mut output = String::with_capacity(input.len());
+    // The character closing the nonterminal, the terminal or the bounds being read.
+    let mut closing: Option<char> = None;
+    let mut after_term = false;
+    for c in input.chars() {
+        if let Some(x) = closing {
+            output.push(c);
+            if c == x {
+                closing = None;
+                after_term = x != '}';
+                if x == '}' {
+                    output.push_str("!>");
+                }
+            }
+            continue;
+        }
+        match c {
+            '<' => closing = Some('>'),
+            '\'' | '"' => closing = Some(c),
+            '*' | '+' | '?' if after_term => {
+                output.push_str(&format!("<{c}!>"));
+                after_term = false;
+                continue;
+            }
+            '{' if after_term => {
+                output.push('<');
+                closing = Some('}');
+            }
+            _ => {}
+        }
+        after_term = false;
+        output.push(c);
+    }
+    output
+}
+
+/// The minimum and the maximum number of copies of a repetition marker, `None` for no maximum.
+fn repetition_bounds(marker: &str) -> Option<(usize, Option<usize>)> {
+    match marker {
+        "*!" => return Some((0, None)),
+        "+!" => return Some((1, None)),
+        "?!" => return Some((0, Some(1))),
+        _ => {}
+    }
+    let bounds = marker.strip_prefix('{')?.strip_suffix("}!")?;
+    let parse = |x: &str| x.trim().parse::<usize>().ok();
+    match bounds.split_once(',') {
+        None => parse(bounds).map(|x| (x, Some(x))),
+        Some((min, max)) if max.trim().is_empty() => Some((parse(min)?, None)),
+        Some((min, max)) => Some((parse(min)?, Some(parse(max)?))),
+    }
+}
+
+/// The most copies a repetition like `{m,n}` may require or allow, since each optional copy adds a nonterminal.
+const MAX_REPETITIONS: usize = 1024;
+
+/// Find the first repetition bounds above [`MAX_REPETITIONS`], so they are rejected before they are expanded.
+fn locate_large_repetition(input: &str) -> Option<ParseError> {
+    for (i, line) in input.lines().enumerate() {
+        let chars: Vec<char> = line.chars().collect();
+        // The character closing the nonterminal, the terminal or the character class being read.
+        let mut closing: Option<char> = None;
+        let mut j = 0;
+        while j < chars.len() {
+            let c = chars[j];
+            j += 1;
+            match closing {
+                Some(x) if c == '\\' && x != '>' => j += 1,
+                Some(x) if c == x => closing = None,
+                Some(_) => {}
+                None => match c {
+                    '<' => closing = Some('>'),
+                    '[' => closing = Some(']'),
+                    '\'' | '"' => closing = Some(c),
+                    '{' => {
+                        let Some(end) = chars[j..].iter().position(|x| *x == '}') else {
+                            continue;
+                        };
+                        let bounds: String = chars[j..j + end].iter().collect();
+                        let too_large = bounds
+                            .split(',')
+                            .filter_map(|x| x.trim().parse::<usize>().ok())
+                            .any(|x| x > MAX_REPETITIONS);
+                        if too_large {
+                            return Some(ParseError {
+                                line: i + 1,
+                                column: j,
+                                snippet: line.trim().to_string(),
+                                message: format!(
+                                    "the repetition {{{bounds}}} exceeds the maximum of {MAX_REPETITIONS} copies."
+                                ),
+                            });
+                        }
+                        j += end + 1;
+                    }
+                    _ => {}
+                },
+            }
+        }
+    }
+    None
+}
+
+/// The hidden nonterminals created by [`expand_repetitions`].
+#[derive(Default)]
+struct Repetitions {
+    productions: Vec<bnf::Production>,
+    defined: FxHashSet<String>,
+}
+
+impl Repetitions {
+    fn name(term: &Term, suffix: &str) -> String {
+        match term {
+            Term::Nonterminal(x) => format!("{x}{suffix}"),
+            Term::Terminal(x) => format!("'{x}'{suffix}"),
+        }
+    }
+
+    /// `<X+!>::=X|X<X+!>`.
+    fn one_or_more(&mut self, term: &Term) -> Term {
+        let nonterminal = Term::Nonterminal(Self::name(term, "+!"));
+        self.define(
+            &nonterminal,
+            vec![vec![term.clone()], vec![term.clone(), nonterminal.clone()]],
+        );
+        nonterminal
+    }
+
+    /// One to `max` copies, `<X{1,max}!>::=X|X<X{1,max-1}!>`.
+    fn one_to(&mut self, term: &Term, max: usize) -> Term {
+        let mut tail = term.clone();
+        for n in 2..=max {
+            let nonterminal = Term::Nonterminal(Self::name(term, &format!("{{1,{n}}}!")));
+            self.define(
+                &nonterminal,
+                vec![vec![term.clone()], vec![term.clone(), tail]],
+            );
+            tail = nonterminal;
+        }
+        tail
+    }
+
+    fn define(&mut self, nonterminal: &Term, alternatives: Vec<Vec<Term>>) {
+        if self.defined.insert(nonterminal.to_string()) {
+            self.productions.push(bnf::Production::from_parts(
+                nonterminal.clone(),
+                alternatives
+                    .into_iter()
+                    .map(bnf::Expression::from_parts)
+                    .collect(),
+            ));
+        }
+    }
+}
+
+/// Expand the repetitions marked by [`mark_repetitions`].
+///
+/// A term repeated `m` to `n` times becomes `m` copies followed by [`Repetitions::one_to`] `n - m` copies,
+/// or by [`Repetitions::one_or_more`] copies without a maximum. The alternative is split into one with the
+/// optional copies and one without them, which is an empty alternative when nothing else is left.
+///
+/// The alternatives of the expansion are counted against [`BuildLimits::max_productions`] before expanding.
+fn expand_repetitions(grammar: bnf::Grammar, limits: &BuildLimits) -> Result<bnf::Grammar, Error> {
+    let marker_bounds = |term: &Term| match term {
+        Term::Nonterminal(x) if x.ends_with('!') => repetition_bounds(x),
+        _ => None,
+    };
+    let is_marker = |term: &Term| matches!(term, Term::Nonterminal(x) if x.ends_with('!') && (x.starts_with('{') || ["*!", "+!", "?!"].contains(&x.as_str())));
+    // An optional repetition doubles the alternatives of its expression, `<X+!>` adds two alternatives,
+    // and `<X{1,n}!>` adds two alternatives per copy beyond the ones of a shorter repetition of X.
+    let mut expanded = 0usize;
+    let mut copies: FxHashMap<String, usize> = FxHashMap::default();
+    for expression in grammar.productions_iter().flat_map(|x| x.rhs_iter()) {
+        let mut alternatives = 1usize;
+        for (term, marker) in expression.terms_iter().tuple_windows() {
+            let Some((min, max)) = marker_bounds(marker).filter(|_| is_marker(marker)) else {
+                continue;
+            };
+            let (name, n) = match max {
+                None => (Repetitions::name(term, "+!"), 2),
+                Some(max) => (Repetitions::name(term, "{}!"), max - min.min(max)),
+            };
+            if max.map_or(min == 0, |max| max > min) {
+                alternatives = alternatives.saturating_mul(2);
+            }
+            let counted = copies.entry(name).or_insert(1);
+            if n > *counted {
+                expanded = expanded.saturating_add(2 * (n - *counted));
+                *counted = n;
+            }
+        }
+        expanded = expanded.saturating_add(alternatives);
+    }
+    limits.check(BuildLimit::Productions, expanded)?;
+    let mut productions = vec![];
+    let mut repetitions = Repetitions::default();
+    for production in grammar.productions_iter() {
+        let lhs = match &production.lhs {
+            Term::Nonterminal(x) | Term::Terminal(x) => x,
+        };
+        let mut expressions: Vec<bnf::Expression> = vec![];
+        for expression in production.rhs_iter() {
+            let terms = expression.terms_iter().collect_vec();
+            if !terms.iter().any(|x| is_marker(x)) {
+                expressions.push(expression.clone());
+                continue;
+            }
+            let mut alternatives: Vec<Vec<Term>> = vec![vec![]];
+            let mut i = 0;
+            while i < terms.len() {
+                let term = terms[i];
+                ensure!(
+                    !is_marker(term),
+                    "<{lhs}> is invalid because {term} does not follow a nonterminal or a terminal."
+                );
+                let marker = terms.get(i + 1).copied().filter(|x| is_marker(x));
+                i += 1 + marker.is_some() as usize;
+                let Some(marker) = marker else {
+                    alternatives.iter_mut().for_each(|x| x.push(term.clone()));
+                    continue;
+                };
+                let (min, max) = marker_bounds(marker)
+                    .filter(|(min, max)| max.is_none_or(|max| *min <= max))
+                    .ok_or_else(|| {
+                        anyhow!(
+                            "<{lhs}> is invalid because {} is not a valid repetition like {{2}}, {{2,5}} or {{2,}}.",
+                            marker.to_string().trim_start_matches('<').trim_end_matches("!>")
+                        )
+                    })?;
+                let (required, optional) = match max {
+                    None if min == 0 => (vec![], Some(repetitions.one_or_more(term))),
+                    None => {
+                        let mut required = vec![term.clone(); min - 1];
+                        required.push(repetitions.one_or_more(term));
+                        (required, None)
+                    }
+                    Some(max) => (
+                        vec![term.clone(); min],
+                        (max > min).then(|| repetitions.one_to(term, max - min)),
+                    ),
+                };
+                alternatives
+                    .iter_mut()
+                    .for_each(|x| x.extend(required.iter().cloned()));
+                if let Some(optional) = optional {
+                    alternatives = alternatives
+                        .into_iter()
+                        .flat_map(|x| {
+                            let mut y = x.clone();
+                            y.push(optional.clone());
+                            [y, x]
+                        })
+                        .collect();
+                }
+            }
+            for mut alternative in alternatives.into_iter().unique() {
+                // Leaving out every optional copy leaves an empty alternative, written like `''`.
+                if alternative.is_empty() {
+                    alternative.push(Term::Terminal(String::new()));
+                }
+                expressions.push(bnf::Expression::from_parts(alternative));
+            }
+        }
+        productions.push(bnf::Production::from_parts(
+            production.lhs.clone(),
+            expressions,
+        ));
+    }
+    productions.extend(repetitions.productions);
+    Ok(bnf::Grammar::from_parts(productions))
+}
+
+/// The bytes and the pairs of adjacent bytes that some tokenization with `vocabulary` can produce.
+///
+/// Two bytes can be adjacent either inside one token, or across the boundary of a token ending with
+/// the first byte and a token starting with the second.
+struct ByteCoverage {
+    bytes: [bool; 256],
+    pairs: FxHashSet<[u8; 2]>,
+    first_bytes: [bool; 256],
+    last_bytes: [bool; 256],
+}
+
+impl ByteCoverage {
+    fn new(vocabulary: &Vocabulary) -> Self {
+        let mut coverage = ByteCoverage {
+            bytes: [false; 256],
+            pairs: FxHashSet::default(),
+            first_bytes: [false; 256],
+            last_bytes: [false; 256],
+        };
+        for token in vocabulary.id_to_token.values() {
+            let (Some(first), Some(last)) = (token.first(), token.last()) else {
+                continue;
+            };
+            coverage.first_bytes[*first as usize] = true;
+            coverage.last_bytes[*last as usize] = true;
+            for byte in token {
+                coverage.bytes[*byte as usize] = true;
+            }
+            for pair in token.windows(2) {
+                coverage.pairs.insert([pair[0], pair[1]]);
+            }
+        }
+        coverage
+    }
+
+    fn adjacent(&self, first: u8, second: u8) -> bool {
+        self.pairs.contains(&[first, second])
+            || (self.last_bytes[first as usize] && self.first_bytes[second as usize])
+    }
+
+    /// Why no tokenization can produce `terminal`, or `None` if some may.
+    fn unreachable_reason(&self, terminal: &[u8]) -> Option<String> {
+        let escape = |x: &[u8]| x.escape_ascii().to_string();
+        if let Some(byte) = terminal.iter().find(|x| !self.bytes[**x as usize]) {
+            return Some(format!("no token contains '{}'", escape(&[*byte])));
+        }
+        terminal
+            .windows(2)
+            .find(|pair| !self.adjacent(pair[0], pair[1]))
+            .map(|pair| {
+                format!(
+                    "no token contains '{}', and no token ending with '{}' can be followed by a token starting with '{}'",
+                    escape(pair),
+                    escape(&pair[..1]),
+                    escape(&pair[1..])
+                )
+            })
+    }
+}
+
+/// A cycle of nonterminals that can start with the next one, like `["a", "b", "a"]` for
+/// `<a>::=<b>'x'` and `<b>::=<a>'y'`, or `None` if the BNF schema has no left recursion.
+///
+/// A nonterminal can start with every nonterminal of an alternative up to the first one that cannot match the empty string.
+fn left_recursion_cycle(
+    simplified_grammar: &FxHashMap<String, FxHashSet<Vec<U8Term>>>,
+) -> Option<Vec<String>> {
+    let mut nullable: FxHashSet<&str> = FxHashSet::default();
+    loop {
+        let before = nullable.len();
+        for (nonterminal, expressions) in simplified_grammar.iter() {
+            if expressions.iter().any(|x| {
+                x.iter().all(
+                    |term| matches!(term, U8Term::Nonterminal(x) if nullable.contains(x.as_str())),
+                )
+            }) {
+                nullable.insert(nonterminal);
+            }
+        }
+        if nullable.len() == before {
+            break;
+        }
+    }
+    let edges: FxHashMap<&str, Vec<&str>> = simplified_grammar
+        .iter()
+        .map(|(nonterminal, expressions)| {
+            let mut starts = vec![];
+            for expression in expressions.iter() {
+                for term in expression.iter() {
+                    let U8Term::Nonterminal(x) = term else {
+                        break;
+                    };
+                    starts.push(x.as_str());
+                    if !nullable.contains(x.as_str()) {
+                        break;
+                    }
+                }
+            }
+            starts.sort_unstable();
+            starts.dedup();
+            (nonterminal.as_str(), starts)
+        })
+        .collect();
+    // A depth first search keeping the path, where a nonterminal found on the path closes a cycle.
+    fn visit<'a>(
+        nonterminal: &'a str,
+        edges: &FxHashMap<&'a str, Vec<&'a str>>,
+        path: &mut Vec<&'a str>,
+        done: &mut FxHashSet<&'a str>,
+    ) -> Option<Vec<String>> {
+        if let Some(i) = path.iter().position(|x| *x == nonterminal) {
+            let mut cycle = path[i..].iter().map(|x| x.to_string()).collect_vec();
+            cycle.push(nonterminal.to_string());
+            return Some(cycle);
+        }
+        if !done.insert(nonterminal) {
+            return None;
+        }
+        path.push(nonterminal);
+        for next in edges.get(nonterminal).into_iter().flatten() {
+            if let Some(cycle) = visit(next, edges, path, done) {
+                return Some(cycle);
+            }
+        }
+        path.pop();
+        None
+    }
+    let mut done = FxHashSet::default();
+    edges
+        .keys()
+        .sorted_unstable()
+        .find_map(|x| visit(x, &edges, &mut vec![], &mut done))
+}
+
+/// Remove the alternatives of the BNF schema that no tokenization with `vocabulary` can produce,
+/// see [`GrammarBuildOptions::prune_unreachable`].
+///
+/// Returns the pruned nonterminals, alternatives and reasons, and the number of trie nodes the pruned
+/// single terminal alternatives would have taken.
+fn prune_unreachable(
+    simplified_grammar: &mut FxHashMap<String, FxHashSet<Vec<U8Term>>>,
+    terminals: &TerminalsInterner,
+    vocabulary: &Vocabulary,
+) -> (Vec<(String, String, String)>, usize) {
+    let coverage = ByteCoverage::new(vocabulary);
+    let mut pruned = vec![];
+    let mut pruned_trie_nodes = 0;
+    // A nonterminal whose alternatives are all pruned matches nothing, so the alternatives using it are pruned in turn.
+    let mut emptied: FxHashSet<String> = FxHashSet::default();
+    loop {
+        let mut changed = false;
+        for (nonterminal, expressions) in simplified_grammar.iter_mut() {
+            if nonterminal.contains('!') || emptied.contains(nonterminal) {
+                continue;
+            }
+            let (removed, kept): (Vec<_>, Vec<_>) =
+                std::mem::take(expressions).into_iter().partition_map(|x| {
+                    let reason = x.iter().find_map(|term| match term {
+                        U8Term::Terminal(id) => coverage.unreachable_reason(terminals.get(*id)),
+                        U8Term::Nonterminal(x) if emptied.contains(x) => {
+                            Some(format!("<{x}> has no reachable alternative"))
+                        }
+                        U8Term::Nonterminal(_) => None,
+                    });
+                    match reason {
+                        Some(reason) => itertools::Either::Left((x, reason)),
+                        None => itertools::Either::Right(x),
+                    }
+                });
+            let prefixes = |expression: &[U8Term]| match expression {
+                [U8Term::Terminal(id)] => {
+                    let terminal = terminals.get(*id);
+                    (1..=terminal.len()).map(|i| &terminal[..i]).collect_vec()
+                }
+                _ => vec![],
+            };
+            let kept_prefixes: FxHashSet<&[u8]> = kept.iter().flat_map(|x| prefixes(x)).collect();
+            let removed_prefixes: FxHashSet<&[u8]> = removed
+                .iter()
+                .flat_map(|(x, _)| prefixes(x))
+                .filter(|x| !kept_prefixes.contains(x))
+                .collect();
+            pruned_trie_nodes += removed_prefixes.len();
+            if !removed.is_empty() {
+                changed = true;
+                if kept.is_empty() {
+                    emptied.insert(nonterminal.clone());
+                }
+            }
+            for (expression, reason) in removed {
+                pruned.push((
+                    nonterminal.clone(),
+                    format_expression(&expression, terminals),
+                    reason,
+                ));
+            }
+            *expressions = kept.into_iter().collect();
+        }
+        if !changed {
+            break;
+        }
+    }
+    (pruned, pruned_trie_nodes)
+}
+
+/// The maximum number of times the temporary stack arena of `<except!([nonterminal])>` is doubled.
+const MAX_EXCEPT_ARENA_DOUBLINGS: u32 = 8;
+
+/// The first tokens of `extracted`, or `None` if it ends or rejects the initial state.
+///
+/// The temporary sampler is retried with a doubled stack arena while it runs out of capacity.
+fn except_token_ids(
+    grammar: &Arc<Grammar>,
+    extracted: &str,
+    vocabulary: &Arc<Vocabulary>,
+    stack_arena_capacity: usize,
+) -> Result<Option<TokenMask>, Error> {
+    let initial_capacity = match stack_arena_capacity {
+        0 => estimate_stack_arena_capacity(grammar, vocabulary),
+        x => x,
+    };
+    let mut capacity = initial_capacity;
+    for _ in 0..=MAX_EXCEPT_ARENA_DOUBLINGS {
+        let mut temp_machine = Sampler::new(
+            grammar.clone(),
+            extracted.to_string(),
+            vocabulary.clone(),
+            capacity,
+            false,
+        )?;
+        match temp_machine.all_possible_next_tokens(None) {
+            Ok(PossibleTokensResult::Continue(tokens)) => return Ok(Some(tokens.clone())),
+            Ok(_) => return Ok(None),
+            Err(e) if e.downcast_ref::<ArenaExhausted>().is_some() => capacity *= 2,
+            Err(e) => return Err(e),
+        }
+    }
+    Err(anyhow!(
+        "except!([{extracted}]) is invalid because expanding [{extracted}] needs a temporary stack arena larger than {}, \
+        even after its capacity {initial_capacity} was doubled {MAX_EXCEPT_ARENA_DOUBLINGS} times. \
+        Increase the stack_arena_capacity of the grammar, or pass 0 to estimate it.",
+        capacity / 2
+    ))
+}
+
 #[derive(Clone, Debug)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
 /// The struct represents the BNF schema.
 pub struct Grammar {
     pub(crate) nonterminal_id_to_expression: FxHashMap<NonterminalID, SimplifiedExpressions>,
     pub(crate) nonterminal_to_terminal_id: FxHashMap<String, NonterminalID>,
     pub(crate) terminals_trie: TerminalsTrie,
-    pub(crate) nonterminal_to_token_ids: FxHashMap<NonterminalID, BitSet<u32>>,
+    pub(crate) terminals: TerminalsInterner,
+    /// The internal ids of the tokens of each token set nonterminal, see [`Vocabulary::is_remapped`].
+    pub(crate) nonterminal_to_token_ids: FxHashMap<NonterminalID, TokenMask>,
+    /// The nonterminals and the formatted alternatives that are defined more than once, for [`Grammar::lint`].
+    pub(crate) duplicate_alternatives: Vec<(String, String)>,
+    /// The `<except!([nonterminal])>` nonterminals excluding more than half of the maximum number of literals,
+    /// with the number of literals and the maximum, for [`Grammar::lint`].
+    pub(crate) large_excepts: Vec<(String, usize, usize)>,
+    /// The `<except!([nonterminal])>` nonterminals and the nonterminals they exclude,
+    /// for [`Grammar::unreachable_nonterminals`].
+    pub(crate) excepted_nonterminals: Vec<(String, String)>,
+    /// The nonterminals, the formatted alternatives and the reasons of the alternatives removed by
+    /// [`GrammarBuildOptions::prune_unreachable`], for [`Grammar::lint`].
+    pub(crate) pruned_alternatives: Vec<(String, String, String)>,
+    pub(crate) pruned_trie_nodes: usize,
+    /// The bytes moved out of nonterminals by [`GrammarBuildOptions::fold_terminals`].
+    #[cfg_attr(feature = "serde", serde(default))]
+    pub(crate) folded_bytes: usize,
+    /// The nonterminals, the formatted terminals and the reasons of the terminals no tokenization can produce,
+    /// for [`Grammar::validate`]. It is empty when the alternatives are pruned instead.
+    #[cfg_attr(feature = "serde", serde(default))]
+    pub(crate) unproducible_terminals: Vec<(String, String, String)>,
+    /// The nonterminals and the terminals with an unknown escape sequence, for [`Grammar::validate`].
+    #[cfg_attr(feature = "serde", serde(default))]
+    pub(crate) unknown_escapes: Vec<(String, String)>,
+    /// The helper nonterminals like `<int!>` defined in the BNF schema, for [`Grammar::validate`].
+    #[cfg_attr(feature = "serde", serde(default))]
+    pub(crate) overridden_helpers: Vec<String>,
+    /// The start nonterminal declared with `@start`, see [`Grammar::start_nonterminal`].
+    #[cfg_attr(feature = "serde", serde(default))]
+    pub(crate) declared_start: Option<String>,
+    pub(crate) max_terminal_bytes: usize,
+    /// The most bytes that can be matched below each trie node, indexed by the node id,
+    /// for [`crate::sampler::SamplerConfig::length_bound`].
+    #[cfg_attr(feature = "serde", serde(skip))]
+    pub(crate) trie_max_depths: Vec<usize>,
+    /// The most bytes each nonterminal can match, or `usize::MAX` when it is recursive.
+    #[cfg_attr(feature = "serde", serde(skip))]
+    pub(crate) nonterminal_max_bytes: FxHashMap<NonterminalID, usize>,
+    /// The BNF schema the grammar is built from, for [`crate::sampler::Sampler::trace_bundle`].
+    pub(crate) source: String,
+}
+/// Options for [`Grammar::with_options`].
+pub struct GrammarBuildOptions {
+    stack_arena_capacity: usize,
+    max_terminal_bytes: usize,
+    max_except_terminals: usize,
+    forms: Vec<Box<dyn SpecialForm>>,
+    collapse_whitespace_runs: bool,
+    prune_unreachable: bool,
+    inline_trivial_nonterminals: bool,
+    fold_terminals: bool,
+    token_classes: Vec<(String, TokenClass)>,
+    limits: BuildLimits,
+}
+
+impl Default for GrammarBuildOptions {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl std::fmt::Debug for GrammarBuildOptions {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.debug_struct("GrammarBuildOptions")
+            .field("stack_arena_capacity", &self.stack_arena_capacity)
+            .field("max_terminal_bytes", &self.max_terminal_bytes)
+            .field("max_except_terminals", &self.max_except_terminals)
+            .field("collapse_whitespace_runs", &self.collapse_whitespace_runs)
+            .field("prune_unreachable", &self.prune_unreachable)
+            .field(
+                "inline_trivial_nonterminals",
+                &self.inline_trivial_nonterminals,
+            )
+            .field("fold_terminals", &self.fold_terminals)
+            .field("token_classes", &self.token_classes)
+            .field("limits", &self.limits)
+            .field(
+                "forms",
+                &self.forms.iter().map(|x| x.name()).collect::<Vec<_>>(),
+            )
+            .finish()
+    }
+}
+
+impl GrammarBuildOptions {
+    /// The options used by [`Grammar::new`].
+    pub fn new() -> Self {
+        GrammarBuildOptions {
+            stack_arena_capacity: 0,
+            max_terminal_bytes: DEFAULT_MAX_TERMINAL_BYTES,
+            max_except_terminals: DEFAULT_MAX_EXCEPT_TERMINALS,
+            forms: special::builtin_forms(),
+            collapse_whitespace_runs: false,
+            prune_unreachable: false,
+            inline_trivial_nonterminals: false,
+            fold_terminals: false,
+            token_classes: vec![],
+            limits: BuildLimits::default(),
+        }
+    }
+
+    /// The temporary stack arena capacity used to build `<except!([nonterminal])>`.
+    /// 0 means the capacity is estimated by [`crate::sampler::estimate_stack_arena_capacity`].
+    /// A capacity that is too small is doubled up to 8 times before building the grammar fails.
+    pub fn stack_arena_capacity(mut self, stack_arena_capacity: usize) -> Self {
+        self.stack_arena_capacity = stack_arena_capacity;
+        self
+    }
+
+    /// Reject terminals longer than `max_terminal_bytes`.
+    pub fn max_terminal_bytes(mut self, max_terminal_bytes: usize) -> Self {
+        self.max_terminal_bytes = max_terminal_bytes;
+        self
+    }
+
+    /// Reject `<except!([nonterminal])>` when the nonterminal produces more than `max_except_terminals` literals.
+    /// [`Grammar::lint`] reports the ones producing more than half of it.
+    pub fn max_except_terminals(mut self, max_except_terminals: usize) -> Self {
+        self.max_except_terminals = max_except_terminals;
+        self
+    }
+
+    /// Replace every run of spaces, tabs and newlines in the terminals of the BNF schema with a nonterminal
+    /// matching any run of them, so the whitespace tokens of a tokenizer can match whatever the width.
+    ///
+    /// e.g. `'key:\n  '<value>` becomes `'key:'<whitespace_run!><value>`, which also matches `key:` followed by
+    /// a 16 space indent token. The rules of special nonterminals are left as they are.
+    pub fn collapse_whitespace_runs(mut self, enabled: bool) -> Self {
+        self.collapse_whitespace_runs = enabled;
+        self
+    }
+
+    /// Remove the alternatives containing a terminal that no tokenization with the vocabulary can produce,
+    /// because no token contains one of its bytes, or two adjacent bytes can neither come from one token
+    /// nor from a token ending with the first followed by a token starting with the second.
+    /// The alternatives using a nonterminal left without alternatives are removed as well.
+    ///
+    /// Only what is unreachable under any tokenization is removed, so the sampled tokens do not change.
+    /// [`Grammar::lint`] reports every removed alternative and [`Grammar::pruned_trie_nodes`] counts the
+    /// trie nodes saved. The rules of special nonterminals are left as they are.
+    pub fn prune_unreachable(mut self, enabled: bool) -> Self {
+        self.prune_unreachable = enabled;
+        self
+    }
+
+    /// Inline the nonterminals whose only alternative is a single terminal or a single nonterminal,
+    /// like the chains `<a>::=<b>` and `<b>::=<c>` of grammars generated from JSON schemas,
+    /// which otherwise cost a stack expansion per level for every candidate token.
+    ///
+    /// The grammar accepts the same sentences. The inlined nonterminals are removed from the grammar,
+    /// so they cannot be the start nonterminal of a sampler, while the nonterminals no rule uses are kept.
+    /// Special nonterminals and the nonterminals excluded by `<except!([nonterminal])>` are never inlined.
+    pub fn inline_trivial_nonterminals(mut self, enabled: bool) -> Self {
+        self.inline_trivial_nonterminals = enabled;
+        self
+    }
+
+    /// Move the bytes that every alternative of a nonterminal starts or ends with out of it, merging them into
+    /// the terminals around its uses, like the quotes of `<key>::='"'<chars>'"'` into `'{'<key>':'`.
+    /// This leaves fewer terminals on the stacks, so the bytes of a token are matched with fewer stack items.
+    ///
+    /// The grammar accepts the same sentences, and every nonterminal of the BNF schema can still be a start nonterminal.
+    /// [`Grammar::folded_bytes`] counts the bytes moved. Special nonterminals and the nonterminals excluded by
+    /// `<except!([nonterminal])>` are never folded.
+    pub fn fold_terminals(mut self, enabled: bool) -> Self {
+        self.fold_terminals = enabled;
+        self
+    }
+
+    /// Define `<name>` as the tokens of `class`, like `<name>::=<token_class!(class)>` in the BNF schema,
+    /// e.g. `add_token_class("word", TokenClass::AlphabeticOnly)`.
+    /// Defining `<name>` in the BNF schema as well makes building the grammar fail.
+    pub fn add_token_class(mut self, name: impl Into<String>, class: TokenClass) -> Self {
+        self.token_classes.push((name.into(), class));
+        self
+    }
+
+    /// Stop building the grammar with [`GrammarError::BuildLimitExceeded`] once it exceeds one of `limits`,
+    /// so a BNF schema from a user cannot take unbounded time or memory.
+    pub fn limits(mut self, limits: BuildLimits) -> Self {
+        self.limits = limits;
+        self
+    }
+
+    /// Recognize `<name!(args)>` nonterminals of a custom special form.
+    /// Registering a form with the name of another form makes building the grammar fail.
+    pub fn register_form(mut self, form: Box<dyn SpecialForm>) -> Self {
+        self.forms.push(form);
+        self
+    }
 }
+
 #[derive(Clone, Debug)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
 pub(crate) enum SimplifiedExpressions {
     Expressions(FxHashSet<Vec<U8Term>>),
     Terminals(TrieNodeID),
@@ -42,69 +1947,402 @@ impl Grammar {
     ///
     /// * `input` - the BNF schema in text format
     /// * `vocabulary` - vocabulary is used to generate terminals for <any!> and <except!(excepted_literals)>
-    /// * `stack_arena_capacity` - stack_arena_capacity is the temporary stack arena created when generating <except!(excepted_literals)>
+    /// * `stack_arena_capacity` - stack_arena_capacity is the temporary stack arena created when generating <except!(excepted_literals)>.
+    ///   0 means the capacity is estimated by [`crate::sampler::estimate_stack_arena_capacity`].
+    ///   A capacity that is too small is doubled up to 8 times before an error is returned.
     pub fn new(
         input: &str,
         vocabulary: Arc<Vocabulary>,
         stack_arena_capacity: usize,
     ) -> Result<Arc<Self>, Error> {
-        let except_present = utils::EXCEPTS_REGEX.is_match(input);
-        let any_present = input.contains(&format!("<{}>", utils::ANY_NONTERMINAL_NAME));
-        let mut grammar: bnf::Grammar = input.parse()?;
-        if any_present {
-            let mut any_prod = Production::new();
-            any_prod.lhs = Term::Nonterminal(utils::ANY_NONTERMINAL_NAME.to_string());
-            grammar.add_production(any_prod);
+        Self::with_max_terminal_bytes(
+            input,
+            vocabulary,
+            stack_arena_capacity,
+            DEFAULT_MAX_TERMINAL_BYTES,
+        )
+    }
+
+    /// Create a new grammar, rejecting terminals longer than `max_terminal_bytes`.
+    ///
+    /// See [`Grammar::new`] for the other arguments.
+    pub fn with_max_terminal_bytes(
+        input: &str,
+        vocabulary: Arc<Vocabulary>,
+        stack_arena_capacity: usize,
+        max_terminal_bytes: usize,
+    ) -> Result<Arc<Self>, Error> {
+        Self::with_options(
+            input,
+            vocabulary,
+            GrammarBuildOptions::new()
+                .stack_arena_capacity(stack_arena_capacity)
+                .max_terminal_bytes(max_terminal_bytes),
+        )
+    }
+
+    /// Create a new grammar with the given options, see [`GrammarBuildOptions`].
+    pub fn with_options(
+        input: &str,
+        vocabulary: Arc<Vocabulary>,
+        options: GrammarBuildOptions,
+    ) -> Result<Arc<Self>, Error> {
+        let GrammarBuildOptions {
+            stack_arena_capacity,
+            max_terminal_bytes,
+            max_except_terminals,
+            forms,
+            collapse_whitespace_runs,
+            prune_unreachable: prune,
+            inline_trivial_nonterminals,
+            fold_terminals: fold,
+            token_classes,
+            limits,
+        } = options;
+        let start = Instant::now();
+        if is_blank(input) {
+            return Err(GrammarError::EmptyGrammar.into());
         }
-        let mut nonterminal_to_token_ids: FxHashMap<NonterminalID, BitSet<u32>> =
-            FxHashMap::default();
-        let mut excepts: FxHashSet<String> = FxHashSet::default();
-        if except_present {
-            for i in utils::EXCEPT_LITERAL_REGEX.find_iter(input) {
-                let temp = i.as_str().to_string();
-                let mut any_prod = Production::new();
-                excepts.insert(temp.clone());
-                any_prod.lhs = Term::Nonterminal(temp);
-                grammar.add_production(any_prod);
+        let source = input;
+        let (input, declared_start) =
+            take_start_directive(&strip_comments(&lower_raw_terminals(input)))?;
+        let input = &input;
+        let unknown_escapes = unknown_escapes(input);
+        ensure!(
+            !input
+                .lines()
+                .any(|x| x.trim_start().starts_with("@include")),
+            "@include is only resolved by Grammar::from_file and Grammar::from_files, \
+            which know the directory of the including file."
+        );
+        limits.check(BuildLimit::Productions, count_alternatives(input))?;
+        if let Some(error) = locate_large_repetition(input) {
+            return Err(error.into());
+        }
+        for (i, form) in forms.iter().enumerate() {
+            ensure!(
+                forms[..i].iter().all(|x| x.name() != form.name()),
+                "The special form {}! is registered more than once.",
+                form.name()
+            );
+        }
+        // The special nonterminals matching tokens, the <except!([nonterminal])> ones and the ones expanding to rules.
+        let mut token_sets: Vec<(String, &dyn SpecialForm, String)> = vec![];
+        let mut excepts: Vec<(String, String)> = vec![];
+        let mut expansions: Vec<(String, String, String)> = vec![];
+        let mut specials: FxHashSet<String> = FxHashSet::default();
+        // The rules of an expansion may use special nonterminals too.
+        let class_rules: String = token_classes
+            .iter()
+            .map(|(name, class)| format!("<{name}>::=<token_class!({class})>\n"))
+            .collect();
+        let mut sources = vec![input.to_string(), class_rules.clone()];
+        // A helper nonterminal defined in the BNF schema is not expanded, so the definition wins.
+        let defined: FxHashSet<&str> = input
+            .lines()
+            .filter_map(crate::include::defined_nonterminal)
+            .collect();
+        let mut overridden_helpers = vec![];
+        while let Some(source) = sources.pop() {
+            for captures in utils::SPECIAL_FORM_REGEX.captures_iter(&source) {
+                let whole = captures.get(0).unwrap().as_str();
+                let nonterminal = &whole[1..whole.len() - 1];
+                let name = captures.get(1).unwrap().as_str();
+                if special::HELPER_FORMS.contains(&name) && defined.contains(nonterminal) {
+                    if !overridden_helpers.iter().any(|x| x == nonterminal) {
+                        overridden_helpers.push(nonterminal.to_string());
+                    }
+                    continue;
+                }
+                if !specials.insert(nonterminal.to_string()) {
+                    continue;
+                }
+                let args = captures.get(2).map_or("", |x| x.as_str());
+                let form = forms.iter().find(|x| x.name() == name).ok_or_else(|| {
+                    anyhow!("<{nonterminal}> uses the unknown special form {name}!.")
+                })?;
+                match form.parse(args).map_err(|e| {
+                    Diagnostic::new(
+                        Severity::Error,
+                        DiagnosticKind::InvalidSpecialForm,
+                        nonterminal,
+                        format!("<{nonterminal}> is invalid because {e}"),
+                    )
+                })? {
+                    ParsedForm::Tokens => {
+                        token_sets.push((nonterminal.to_string(), form.as_ref(), args.to_string()))
+                    }
+                    ParsedForm::ExceptNonterminal(extracted) => {
+                        excepts.push((nonterminal.to_string(), extracted))
+                    }
+                    ParsedForm::Rules { start, rules } => {
+                        sources.push(rules.clone());
+                        expansions.push((nonterminal.to_string(), start, rules));
+                    }
+                }
+            }
+        }
+        let lowered = lower_groups(&lower_classes(&lower_case_insensitive(input))?)?;
+        let mut grammar = expand_repetitions(
+            mark_repetitions(&lowered).parse().map_err(
+                |e: bnf::Error| match locate_syntax_error(input) {
+                    Some(error) => Error::new(error),
+                    None => anyhow!("The BNF schema cannot be parsed: {e}"),
+                },
+            )?,
+            &limits,
+        )?;
+        for production in grammar.productions_iter() {
+            if let Term::Nonterminal(lhs) = &production.lhs {
+                ensure!(
+                    !specials.contains(lhs),
+                    "<{lhs}> is a special nonterminal and cannot be defined in the BNF schema."
+                );
+                ensure!(
+                    token_classes.iter().all(|(name, _)| name != lhs),
+                    "<{lhs}> is a token class and cannot be defined in the BNF schema."
+                );
             }
-            for i in utils::EXCEPT_NONTERMINAL_REGEX.find_iter(input) {
-                let temp = i.as_str().to_string();
-                excepts.insert(temp);
+        }
+        let class_productions: bnf::Grammar = match class_rules.is_empty() {
+            true => bnf::Grammar::new(),
+            false => class_rules
+                .parse()
+                .map_err(|e| anyhow!("The token class names cannot be parsed: {e}"))?,
+        };
+        for production in class_productions.productions_iter() {
+            grammar.add_production(production.clone());
+        }
+        for (nonterminal, start, rules) in expansions {
+            let mut rules: bnf::Grammar = rules.parse().map_err(|e| {
+                anyhow!("<{nonterminal}> is invalid because its rules cannot be parsed: {e}")
+            })?;
+            let local: FxHashSet<String> = rules
+                .productions_iter()
+                .filter_map(|x| match &x.lhs {
+                    Term::Nonterminal(lhs) => Some(lhs.clone()),
+                    Term::Terminal(_) => None,
+                })
+                .collect();
+            ensure!(
+                local.contains(&start),
+                "<{nonterminal}> is invalid because its rules do not define <{start}>."
+            );
+            // <start> becomes the special nonterminal, and the other local nonterminals are prefixed with it.
+            let rename = |term: &mut Term| {
+                if let Term::Nonterminal(name) = term {
+                    if *name == start {
+                        *name = nonterminal.clone();
+                    } else if local.contains(name) {
+                        *name = format!("{nonterminal}/{name}");
+                    }
+                }
+            };
+            for production in rules.productions_iter_mut() {
+                rename(&mut production.lhs);
+                for expression in production.rhs_iter_mut() {
+                    expression.terms_iter_mut().for_each(rename);
+                }
+                grammar.add_production(production.clone());
             }
         }
+        for (nonterminal, _, _) in token_sets.iter() {
+            let mut any_prod = Production::new();
+            any_prod.lhs = Term::Nonterminal(nonterminal.clone());
+            grammar.add_production(any_prod);
+        }
+        let mut nonterminal_to_token_ids: FxHashMap<NonterminalID, TokenMask> =
+            FxHashMap::default();
         let mut simplified_grammar: FxHashMap<String, FxHashSet<Vec<U8Term>>> =
             FxHashMap::default();
+        let mut terminals = TerminalsInterner::default();
+        let mut duplicate_alternatives = vec![];
+        let (mut productions, mut terminal_bytes) = (0, 0);
         for i in grammar.productions_iter() {
+            limits.check_time(start)?;
             let key = match &i.lhs {
                 Term::Terminal(x) => x,
                 Term::Nonterminal(x) => x,
             };
-            simplified_grammar
-                .entry(key.clone())
-                .or_default()
-                .extend(i.rhs_iter().map(|x| {
-                    let mut temp_vec: Vec<U8Term> = vec![];
-                    let mut temp_string: Option<String> = None;
-                    for i in x.terms_iter() {
-                        match i {
-                            Term::Terminal(x) => match temp_string {
+            let expressions = simplified_grammar.entry(key.clone()).or_default();
+            for x in i.rhs_iter() {
+                productions += 1;
+                limits.check(BuildLimit::Productions, productions)?;
+                let mut temp_vec: Vec<U8Term> = vec![];
+                let mut temp_string: Option<String> = None;
+                for i in x.terms_iter() {
+                    match i {
+                        Term::Terminal(x) => {
+                            terminal_bytes += x.len();
+                            limits.check(BuildLimit::TerminalBytesTotal, terminal_bytes)?;
+                            match temp_string {
                                 Some(value) => temp_string = Some(value + x),
                                 None => temp_string = Some(x.clone()),
-                            },
-                            Term::Nonterminal(nonterminal) => {
-                                if let Some(value) = temp_string {
-                                    temp_vec.push(U8Term::Terminal(utils::fix_utf8_escape(&value)));
-                                    temp_string = None;
-                                }
-                                temp_vec.push(U8Term::Nonterminal(nonterminal.clone()));
                             }
                         }
+                        Term::Nonterminal(nonterminal) => {
+                            // An empty terminal like `''` matches nothing, so it is left out.
+                            if let Some(value) = temp_string.take().filter(|x| !x.is_empty()) {
+                                temp_vec.push(U8Term::Terminal(
+                                    terminals.intern(&checked_terminal(
+                                        key,
+                                        &value,
+                                        max_terminal_bytes,
+                                    )?),
+                                ));
+                            }
+                            temp_vec.push(U8Term::Nonterminal(nonterminal.clone()));
+                        }
                     }
-                    if let Some(value) = temp_string {
-                        temp_vec.push(U8Term::Terminal(utils::fix_utf8_escape(&value)));
+                }
+                // An alternative of empty terminals becomes an epsilon alternative, which matches no bytes.
+                if let Some(value) = temp_string.filter(|x| !x.is_empty()) {
+                    temp_vec.push(U8Term::Terminal(terminals.intern(&checked_terminal(
+                        key,
+                        &value,
+                        max_terminal_bytes,
+                    )?)));
+                }
+                if expressions.contains(&temp_vec) {
+                    duplicate_alternatives
+                        .push((key.clone(), format_expression(&temp_vec, &terminals)));
+                }
+                expressions.insert(temp_vec);
+            }
+        }
+        // The <except!([nonterminal])> nonterminals are only added after the grammar is built.
+        let is_defined = |x: &String| {
+            simplified_grammar.contains_key(x) || excepts.iter().any(|(name, _)| name == x)
+        };
+        let mut undefined: BTreeMap<&str, BTreeSet<&str>> = BTreeMap::new();
+        for (lhs, expressions) in simplified_grammar.iter() {
+            for term in expressions.iter().flatten() {
+                if let U8Term::Nonterminal(x) = term {
+                    if !is_defined(x) {
+                        undefined.entry(x).or_default().insert(lhs);
                     }
-                    temp_vec
-                }));
+                }
+            }
+        }
+        if !undefined.is_empty() {
+            let defined = simplified_grammar
+                .keys()
+                .filter(|x| !x.contains('!'))
+                .map(|x| x.as_str());
+            let message = format!(
+                "The BNF schema uses undefined nonterminals: {}.",
+                undefined
+                    .iter()
+                    .map(|(name, users)| {
+                        let mut message = format!("<{name}> in <{}>", users.iter().join(">, <"));
+                        if let Some(closest) = utils::closest_name(name, defined.clone()) {
+                            message += &format!(" (did you mean <{closest}>?)");
+                        }
+                        message
+                    })
+                    .join("; ")
+            );
+            return Err(Diagnostic {
+                severity: Severity::Error,
+                kind: DiagnosticKind::UndefinedNonterminal,
+                nonterminal: match undefined.len() {
+                    1 => undefined.keys().next().map(|x| x.to_string()),
+                    _ => None,
+                },
+                production: None,
+                message,
+            }
+            .into());
+        }
+        if let Some(cycle) = left_recursion_cycle(&simplified_grammar) {
+            return Err(Diagnostic {
+                production: Some(cycle.iter().map(|x| format!("<{x}>")).join(" -> ")),
+                ..Diagnostic::new(
+                    Severity::Error,
+                    DiagnosticKind::LeftRecursion,
+                    &cycle[0],
+                    format!(
+                        "Left recursion is not supported, but <{}> can start with itself through {}.",
+                        cycle[0],
+                        cycle.iter().map(|x| format!("<{x}>")).join(" -> ")
+                    ),
+                )
+            }
+            .into());
+        }
+        if collapse_whitespace_runs {
+            collapse_whitespace(&mut simplified_grammar, &mut terminals);
+        }
+        if inline_trivial_nonterminals {
+            inline_trivial(
+                &mut simplified_grammar,
+                &mut terminals,
+                &excepts,
+                max_terminal_bytes,
+            );
+        }
+        let folded_bytes = match fold {
+            true => fold_terminals(
+                &mut simplified_grammar,
+                &mut terminals,
+                &excepts,
+                max_terminal_bytes,
+            ),
+            false => 0,
+        };
+        let (pruned_alternatives, pruned_trie_nodes) = match prune {
+            true => prune_unreachable(&mut simplified_grammar, &terminals, &vocabulary),
+            false => (vec![], 0),
+        };
+        // The alternatives that would have been pruned are reported by Grammar::validate instead.
+        let mut unproducible_terminals = vec![];
+        if !prune {
+            let coverage = ByteCoverage::new(&vocabulary);
+            for (nonterminal, expressions) in simplified_grammar.iter() {
+                if nonterminal.contains('!') {
+                    continue;
+                }
+                for id in expressions.iter().flatten().filter_map(|term| match term {
+                    U8Term::Terminal(id) => Some(*id),
+                    U8Term::Nonterminal(_) => None,
+                }) {
+                    if let Some(reason) = coverage.unreachable_reason(terminals.get(id)) {
+                        unproducible_terminals.push((
+                            nonterminal.clone(),
+                            format_expression(&[U8Term::Terminal(id)], &terminals),
+                            reason,
+                        ));
+                    }
+                }
+            }
+            unproducible_terminals.sort_unstable();
+            unproducible_terminals.dedup();
+        }
+        // The single terminal alternatives of a rule that also has other alternatives are moved into
+        // an implicit nonterminal, so they can be matched by the terminals trie.
+        let is_single_terminal =
+            |terms: &Vec<U8Term>| matches!(terms.as_slice(), [U8Term::Terminal(_)]);
+        let mixed_nonterminals = simplified_grammar
+            .iter()
+            .filter(|(_, expressions)| {
+                let count = expressions.iter().filter(|x| is_single_terminal(x)).count();
+                count >= 2 && count < expressions.len()
+            })
+            .map(|(k, _)| k.clone())
+            .collect_vec();
+        for nonterminal in mixed_nonterminals {
+            let name = format!("{}({nonterminal})", utils::TERMINALS_NONTERMINAL_NAME);
+            if simplified_grammar.contains_key(&name) {
+                continue;
+            }
+            let expressions = simplified_grammar.get_mut(&nonterminal).unwrap();
+            let (single_terminals, mut others): (FxHashSet<_>, FxHashSet<_>) =
+                std::mem::take(expressions)
+                    .into_iter()
+                    .partition(is_single_terminal);
+            others.insert(vec![U8Term::Nonterminal(name.clone())]);
+            *expressions = others;
+            simplified_grammar.insert(name, single_terminals);
         }
         let nonterminal_to_terminal_id: FxHashMap<String, NonterminalID> = simplified_grammar
             .iter()
@@ -112,168 +2350,80 @@ impl Grammar {
             .map(|(i, (key, _))| (key.clone(), NonterminalID(i)))
             .collect();
         let mut terminals_arena = TerminalsTrie::new();
-        let add_tokens = |simplified_grammar: &mut FxHashMap<String, FxHashSet<Vec<U8Term>>>,
-                          terminals_arena: &mut TerminalsTrie,
-                          nonterminal_to_terminal_id: &FxHashMap<String, NonterminalID>,
-                          nonterminal_to_token_ids: &mut FxHashMap<NonterminalID, BitSet>,
-                          nonterminal: &str,
-                          excepted_literal: Option<&Vec<&[u8]>>| {
-            simplified_grammar.remove(nonterminal);
-            let predicate = |haystack: &&U8ArrayWrapper| {
-                excepted_literal.is_none()
-                    || excepted_literal.is_some_and(|x| {
-                        x.iter().all(|x| {
-                            &haystack.0[..] != *x && memmem::find(&haystack.0, x).is_none()
-                        })
-                    })
-            };
-            match excepted_literal {
-                Some(_) => {
-                    simplified_grammar.insert(
-                        nonterminal.to_string(),
-                        vocabulary
-                            .token_to_id
-                            .keys()
-                            .filter(predicate)
-                            .map(|k| vec![U8Term::Terminal(k.0.to_vec())])
-                            .collect(),
-                    );
-                    for (key, _) in vocabulary.token_to_id.iter() {
-                        terminals_arena.add(&key.0, nonterminal_to_terminal_id[nonterminal], false)
-                    }
-                    let mut bit_set = BitSet::new();
-                    bit_set.extend(vocabulary.token_to_id.iter().filter_map(|(k, token_id)| {
-                        if predicate(&k) {
-                            Some(*(token_id) as usize)
-                        } else {
-                            None
-                        }
-                    }));
-
-                    nonterminal_to_token_ids
-                        .insert(nonterminal_to_terminal_id[nonterminal], bit_set);
-                }
-                None => {
-                    simplified_grammar.insert(
-                        nonterminal.to_string(),
-                        vocabulary
-                            .token_to_id
-                            .keys()
-                            .map(|k| vec![U8Term::Terminal(k.0.to_vec())])
-                            .collect(),
-                    );
-                    let mut bit_set = BitSet::new();
-                    for (key, token_id) in vocabulary.token_to_id.iter() {
-                        bit_set.insert((*token_id) as usize);
-                        terminals_arena.add(&key.0, nonterminal_to_terminal_id[nonterminal], false)
-                    }
-                    nonterminal_to_token_ids
-                        .insert(nonterminal_to_terminal_id[nonterminal], bit_set);
-                }
-            }
-        };
-        if any_present {
-            add_tokens(
-                &mut simplified_grammar,
+        for (nonterminal, form, args) in token_sets.iter() {
+            let nonterminal_id = nonterminal_to_terminal_id[nonterminal];
+            let mut ctx = GrammarBuildCtx::new(
+                nonterminal,
+                args,
+                &vocabulary,
+                nonterminal_id,
                 &mut terminals_arena,
-                &nonterminal_to_terminal_id,
-                &mut nonterminal_to_token_ids,
-                utils::ANY_NONTERMINAL_NAME,
-                None,
+                &mut terminals,
             );
-        }
-        fn process_valid_excepts<F: FnOnce(&str) -> Result<(), Error>>(
-            regex: &Regex,
-            nonterminal: &str,
-            process: F,
-        ) -> Result<(), Error> {
-            let extracted = utils::extract_excepted(regex, nonterminal);
-            if let Some(extracted) = extracted {
-                if extracted.is_empty() {
-                    return Err(anyhow::anyhow!("{nonterminal} is invalid except!() nonterminal because the brackets contain nothing."));
-                }
-                process(extracted)?;
-            }
-            Ok(())
-        }
-        if except_present {
-            for nonterminal in excepts.iter() {
-                process_valid_excepts(&utils::EXCEPT_LITERAL_REGEX, nonterminal, |extracted| {
-                    let bytes = utils::fix_utf8_escape(extracted);
-                    println!("{:?}", bytes);
-                    add_tokens(
-                        &mut simplified_grammar,
-                        &mut terminals_arena,
-                        &nonterminal_to_terminal_id,
-                        &mut nonterminal_to_token_ids,
-                        nonterminal,
-                        Some(&vec![&bytes]),
-                    );
-                    terminals_arena.except_literal(&bytes, nonterminal_to_terminal_id[nonterminal]);
-                    Ok(())
-                })?;
-            }
+            form.build(&mut ctx)?;
+            nonterminal_to_token_ids.insert(nonterminal_id, ctx.into_token_ids()?);
+            limits.check(BuildLimit::TrieNodes, terminals_arena.node_count())?;
+            limits.check_time(start)?;
         }
         fn convert_u8terms_to_simplified_expressions(
             k: &str,
             v: FxHashSet<Vec<U8Term>>,
             terminals_arena: &mut TerminalsTrie,
+            terminals: &TerminalsInterner,
             nonterminal_to_terminal_id: &FxHashMap<String, NonterminalID>,
-        ) -> (String, SimplifiedExpressions) {
+        ) -> Result<(String, SimplifiedExpressions), Error> {
             for i in v.into_iter() {
-                let value = match i.last().unwrap() {
-                    U8Term::Terminal(value) => value,
-                    _ => panic!("There should only be terminals."),
+                let value = match i.as_slice() {
+                    [U8Term::Terminal(value)] => *value,
+                    _ => {
+                        return Err(anyhow!(
+                            "<{k}> should only contain single terminals, but its alternative {} does not.",
+                            format_expression(&i, terminals)
+                        ))
+                    }
                 };
-                terminals_arena.add(value, nonterminal_to_terminal_id[k], true);
+                terminals_arena.add(value, terminals, nonterminal_to_terminal_id[k], true);
             }
             let v = SimplifiedExpressions::Terminals(
                 terminals_arena.roots[&nonterminal_to_terminal_id[k]],
             );
-            (k.to_string(), v)
+            Ok((k.to_string(), v))
         }
         let mut new_simplified_grammar: FxHashMap<String, SimplifiedExpressions> =
             simplified_grammar
                 .iter()
                 .map(|(k, v)| {
-                    if v.iter().all(|terms| {
-                        terms.len() == 1
-                            && match terms.last().unwrap() {
-                                U8Term::Terminal(_) => true,
-                                U8Term::Nonterminal(_) => false,
-                            }
-                    }) {
+                    // A nonterminal left without alternatives by pruning matches nothing.
+                    if !v.is_empty()
+                        && v.iter().all(|terms| {
+                            terms.len() == 1
+                                && match terms.last().unwrap() {
+                                    U8Term::Terminal(_) => true,
+                                    U8Term::Nonterminal(_) => false,
+                                }
+                        })
+                    {
                         convert_u8terms_to_simplified_expressions(
                             k,
                             v.clone(),
                             &mut terminals_arena,
+                            &terminals,
                             &nonterminal_to_terminal_id,
                         )
                     } else {
-                        (k.clone(), SimplifiedExpressions::Expressions(v.clone()))
+                        Ok((k.clone(), SimplifiedExpressions::Expressions(v.clone())))
                     }
                 })
-                .collect();
-        if any_present {
+                .collect::<Result<_, Error>>()?;
+        limits.check(BuildLimit::TrieNodes, terminals_arena.node_count())?;
+        for (nonterminal, _, _) in token_sets.iter() {
             new_simplified_grammar.insert(
-                utils::ANY_NONTERMINAL_NAME.to_string(),
+                nonterminal.to_string(),
                 SimplifiedExpressions::Terminals(
-                    terminals_arena.roots[&nonterminal_to_terminal_id[utils::ANY_NONTERMINAL_NAME]],
+                    terminals_arena.roots[&nonterminal_to_terminal_id[nonterminal]],
                 ),
             );
         }
-        if except_present {
-            for nonterminal in excepts.iter() {
-                if utils::EXCEPT_LITERAL_REGEX.is_match(nonterminal) {
-                    new_simplified_grammar.insert(
-                        nonterminal.to_string(),
-                        SimplifiedExpressions::Terminals(
-                            terminals_arena.roots[&nonterminal_to_terminal_id[nonterminal]],
-                        ),
-                    );
-                }
-            }
-        }
         let nonterminal_id_to_expression: FxHashMap<NonterminalID, SimplifiedExpressions> =
             new_simplified_grammar
                 .iter()
@@ -283,74 +2433,83 @@ impl Grammar {
             nonterminal_to_terminal_id,
             nonterminal_id_to_expression,
             terminals_trie: terminals_arena,
+            terminals,
             nonterminal_to_token_ids,
+            duplicate_alternatives,
+            large_excepts: vec![],
+            excepted_nonterminals: excepts.clone(),
+            pruned_alternatives,
+            unproducible_terminals,
+            unknown_escapes,
+            overridden_helpers,
+            declared_start,
+            pruned_trie_nodes,
+            folded_bytes,
+            max_terminal_bytes,
+            trie_max_depths: vec![],
+            nonterminal_max_bytes: FxHashMap::default(),
+            // The token classes are kept as rules, so the schema can be rebuilt with [`Grammar::new`].
+            source: match class_rules.is_empty() {
+                true => source.to_string(),
+                false => format!("{source}\n{class_rules}"),
+            },
         });
 
         let mut_grammar = unsafe { &mut *(Arc::as_ptr(&grammar) as *mut Grammar) };
-        if except_present {
-            for nonterminal in excepts.iter() {
-                process_valid_excepts(
-                    &utils::EXCEPT_NONTERMINAL_REGEX,
-                    nonterminal,
-                    |extracted| {
-                        ensure!(
-                        mut_grammar
-                            .nonterminal_to_terminal_id
-                            .contains_key(extracted),
-                        "except!([{extracted}]) is invalid because [{extracted}] is not a valid nonterminal."
-                    );
-                        // println!("{nonterminal}");
-                        mut_grammar.nonterminal_to_terminal_id.insert(
+        for (nonterminal, extracted) in excepts.iter() {
+            limits.check_time(start)?;
+            ensure!(
+                mut_grammar.nonterminal_to_terminal_id.contains_key(extracted),
+                "except!([{extracted}]) is invalid because [{extracted}] is not a valid nonterminal."
+            );
+            let nonterminal_id = NonterminalID(grammar.nonterminal_id_to_expression.len());
+            mut_grammar
+                .nonterminal_to_terminal_id
+                .insert(nonterminal.to_string(), nonterminal_id);
+            match except_token_ids(&grammar, extracted, &vocabulary, stack_arena_capacity)? {
+                Some(tokens) => {
+                    let iter = vocabulary.get_token_from_token_ids(&tokens).collect_vec();
+                    if iter.len() > max_except_terminals {
+                        let sample = iter
+                            .iter()
+                            .take(5)
+                            .map(|x| format!("{:?}", utils::escape_invalid_utf8(x)))
+                            .join(", ");
+                        return Err(anyhow!(
+                            "except!([{extracted}]) is invalid because [{extracted}] produces {} literals, more than the maximum of {max_except_terminals}, e.g. {sample}.",
+                            iter.len()
+                        ));
+                    }
+                    if iter.len() > max_except_terminals / 2 {
+                        mut_grammar.large_excepts.push((
                             nonterminal.to_string(),
-                            NonterminalID(grammar.nonterminal_id_to_expression.len()),
-                        );
-                        let mut temp_machine = Sampler::new(
-                            grammar.clone(),
-                            extracted.to_string(),
-                            vocabulary.clone(),
-                            stack_arena_capacity,
-                            false,
-                        )?;
-                        let mut simplified_grammar: FxHashMap<String, FxHashSet<Vec<U8Term>>> =
-                            FxHashMap::default();
-                        match temp_machine.all_possible_next_tokens(None)? {
-                        PossibleTokensResult::Continue(tokens) => {
-                            let iter = vocabulary
-                                .get_token_from_token_ids(tokens)
-                                .collect_vec();
-                            add_tokens(
-                                &mut simplified_grammar,
-                                &mut mut_grammar.terminals_trie,
-                                &mut_grammar.nonterminal_to_terminal_id,
-                                &mut mut_grammar.nonterminal_to_token_ids,
-                                nonterminal,
-                                Some(&iter),
-                            );
-                            for token in iter {
-                                mut_grammar.terminals_trie.except_literal(
-                                    token,
-                                    mut_grammar.nonterminal_to_terminal_id[nonterminal],
-                                );
-                            }
-                            let (new_k, new_v) = {
-                                let (new_k, new_v) = convert_u8terms_to_simplified_expressions(
-                                    nonterminal,
-                                    simplified_grammar[nonterminal].clone(),
-                                    &mut mut_grammar.terminals_trie,
-                                    &grammar.nonterminal_to_terminal_id,
-                                );
-                                (grammar.nonterminal_to_terminal_id[&new_k], new_v)
-                            };
-                            mut_grammar
-                                .nonterminal_id_to_expression
-                                .insert(new_k, new_v);
-                            simplified_grammar.clear();
-                        },
-                        _ => return Err(anyhow!("except!([{extracted}]) is invalid because [{extracted}] does not produce valid terminals.")),
+                            iter.len(),
+                            max_except_terminals,
+                        ));
                     }
-                        Ok(())
-                    },
-                )?;
+                    let token_ids = special::add_tokens_except_literals(
+                        &mut mut_grammar.terminals_trie,
+                        &mut mut_grammar.terminals,
+                        nonterminal_id,
+                        &vocabulary,
+                        &iter,
+                        false,
+                    );
+                    limits.check(
+                        BuildLimit::TrieNodes,
+                        mut_grammar.terminals_trie.node_count(),
+                    )?;
+                    mut_grammar
+                        .nonterminal_to_token_ids
+                        .insert(nonterminal_id, token_ids);
+                    mut_grammar.nonterminal_id_to_expression.insert(
+                        nonterminal_id,
+                        SimplifiedExpressions::Terminals(
+                            mut_grammar.terminals_trie.roots[&nonterminal_id],
+                        ),
+                    );
+                }
+                None => return Err(anyhow!("except!([{extracted}]) is invalid because [{extracted}] does not produce valid terminals.")),
             }
         }
         for (_, v) in grammar.nonterminal_id_to_expression.iter() {
@@ -368,6 +2527,333 @@ impl Grammar {
                 }
             }
         }
+        if let Some(start) = grammar.declared_start.as_deref() {
+            if let Some(error) = grammar
+                .validate(start)
+                .into_iter()
+                .find(|x| x.severity == Severity::Error)
+            {
+                return Err(error.into());
+            }
+        }
+        mut_grammar.compute_bounds();
         Ok(grammar)
     }
+
+    /// The start nonterminal declared by a `@start <name>` line of the BNF schema,
+    /// or [`DEFAULT_START_NONTERMINAL`] when there is none.
+    ///
+    /// A declared start nonterminal is checked to be defined and productive when the grammar is built.
+    /// [`Sampler::with_default_start`] samples from it, like [`Sampler::new`] given an empty start nonterminal.
+    pub fn start_nonterminal(&self) -> &str {
+        self.declared_start
+            .as_deref()
+            .unwrap_or(DEFAULT_START_NONTERMINAL)
+    }
+
+    /// Compute the bounds of [`crate::sampler::SamplerConfig::length_bound`] once the grammar is built or loaded.
+    pub(crate) fn compute_bounds(&mut self) {
+        self.trie_max_depths = self.terminals_trie.max_depths();
+        self.nonterminal_max_bytes = self.compute_nonterminal_max_bytes();
+    }
+
+    /// The most bytes each nonterminal can match, where a nonterminal that can derive itself is unbounded.
+    fn compute_nonterminal_max_bytes(&self) -> FxHashMap<NonterminalID, usize> {
+        fn visit(
+            grammar: &Grammar,
+            id: NonterminalID,
+            max_bytes: &mut FxHashMap<NonterminalID, usize>,
+        ) -> usize {
+            if let Some(bytes) = max_bytes.get(&id) {
+                return *bytes;
+            }
+            // A nonterminal reached again before its bound is known is recursive.
+            max_bytes.insert(id, usize::MAX);
+            let bytes = match grammar.nonterminal_id_to_expression.get(&id) {
+                None => usize::MAX,
+                Some(SimplifiedExpressions::Terminals(node_id)) => {
+                    grammar.trie_max_depths[node_id.id]
+                }
+                Some(SimplifiedExpressions::Expressions(expressions)) => expressions
+                    .iter()
+                    .map(|terms| {
+                        terms.iter().fold(0usize, |sum, term| {
+                            sum.saturating_add(match term {
+                                U8Term::Terminal(id) => grammar.terminals.get(*id).len(),
+                                U8Term::Nonterminal(nonterminal) => {
+                                    match grammar.nonterminal_to_terminal_id.get(nonterminal) {
+                                        Some(id) => visit(grammar, *id, max_bytes),
+                                        None => usize::MAX,
+                                    }
+                                }
+                            })
+                        })
+                    })
+                    .max()
+                    .unwrap_or(0),
+            };
+            max_bytes.insert(id, bytes);
+            bytes
+        }
+        let mut max_bytes = FxHashMap::default();
+        for id in self.nonterminal_id_to_expression.keys() {
+            visit(self, *id, &mut max_bytes);
+        }
+        max_bytes
+    }
+
+    /// The nonterminals defined in the BNF schema, sorted. The special nonterminals are not included.
+    pub fn nonterminals(&self) -> Vec<&str> {
+        self.nonterminal_to_terminal_id
+            .keys()
+            .filter(|x| !x.contains('!'))
+            .map(|x| x.as_str())
+            .sorted_unstable()
+            .collect()
+    }
+
+    /// The maximum length of a terminal in bytes this grammar was checked against.
+    pub fn max_terminal_bytes(&self) -> usize {
+        self.max_terminal_bytes
+    }
+
+    /// The number of trie nodes the alternatives removed by [`GrammarBuildOptions::prune_unreachable`] would have taken.
+    pub fn pruned_trie_nodes(&self) -> usize {
+        self.pruned_trie_nodes
+    }
+
+    /// The number of bytes [`GrammarBuildOptions::fold_terminals`] moved out of nonterminals.
+    pub fn folded_bytes(&self) -> usize {
+        self.folded_bytes
+    }
+
+    /// The approximate memory of the terminals of the grammar in bytes, including the tokens of `<any!>` and
+    /// `<except!(...)>`. A terminal is stored once and referred to by its id, however many alternatives,
+    /// trie nodes and stacks use it.
+    pub fn terminals_bytes(&self) -> usize {
+        self.terminals.memory_bytes()
+    }
+
+    /// Build the grammar and run every static check of [`Grammar::validate`] from `start`,
+    /// where an empty `start` means [`Grammar::start_nonterminal`].
+    ///
+    /// When the grammar cannot be built, the only diagnostic is the error [`Grammar::with_options`] returns,
+    /// like an undefined nonterminal, a left recursion or an invalid special nonterminal.
+    pub fn check(
+        input: &str,
+        vocabulary: Arc<Vocabulary>,
+        options: GrammarBuildOptions,
+        start: &str,
+    ) -> Vec<Diagnostic> {
+        match Self::with_options(input, vocabulary, options) {
+            Ok(grammar) => grammar.validate(match start.is_empty() {
+                true => grammar.start_nonterminal(),
+                false => start,
+            }),
+            Err(e) => vec![match e.downcast::<Diagnostic>() {
+                Ok(diagnostic) => diagnostic,
+                Err(e) => Diagnostic {
+                    severity: Severity::Error,
+                    kind: DiagnosticKind::InvalidSchema,
+                    nonterminal: None,
+                    production: None,
+                    message: format!("{e:#}"),
+                },
+            }],
+        }
+    }
+
+    /// The problems of the grammar when sampling from `start`, errors first, then sorted by nonterminal and kind.
+    ///
+    /// The errors of building the grammar are not repeated here, see [`Grammar::check`].
+    /// The error of an undefined or non-productive `start` is the one [`Sampler::new`] returns.
+    /// Unproducible terminals are only reported when [`GrammarBuildOptions::prune_unreachable`] is disabled,
+    /// since their alternatives are pruned otherwise, see [`Grammar::lint`].
+    pub fn validate(&self, start: &str) -> Vec<Diagnostic> {
+        if !self.nonterminal_to_terminal_id.contains_key(start) {
+            return vec![Diagnostic::new(
+                Severity::Error,
+                DiagnosticKind::UndefinedNonterminal,
+                start,
+                format!("The start nonterminal <{start}> is not defined in the BNF schema."),
+            )];
+        }
+        let mut diagnostics = vec![];
+        let productive = self.productive_nonterminals();
+        for (nonterminal, id) in self.nonterminal_to_terminal_id.iter() {
+            if !productive.contains(id) {
+                let severity = match nonterminal == start {
+                    true => Severity::Error,
+                    false => Severity::Warning,
+                };
+                diagnostics.push(Diagnostic::new(
+                    severity,
+                    DiagnosticKind::NonProductive,
+                    nonterminal,
+                    format!("<{nonterminal}> never matches a complete sentence, because every alternative uses a nonterminal like itself."),
+                ));
+            }
+        }
+        for nonterminal in self.unreachable_nonterminals(start) {
+            diagnostics.push(Diagnostic::new(
+                Severity::Warning,
+                DiagnosticKind::Unreachable,
+                &nonterminal,
+                format!("<{nonterminal}> is not used by any derivation from <{start}>."),
+            ));
+        }
+        for (nonterminal, id) in self.nonterminal_to_terminal_id.iter() {
+            if self
+                .nonterminal_to_token_ids
+                .get(id)
+                .is_some_and(|x| x.is_empty())
+            {
+                diagnostics.push(Diagnostic::new(
+                    Severity::Warning,
+                    DiagnosticKind::EmptyTokenSet,
+                    nonterminal,
+                    format!("No token in the vocabulary matches <{nonterminal}>."),
+                ));
+            }
+        }
+        for (nonterminal, terminal, reason) in self.unproducible_terminals.iter() {
+            diagnostics.push(Diagnostic {
+                production: Some(terminal.clone()),
+                ..Diagnostic::new(
+                    Severity::Warning,
+                    DiagnosticKind::UnproducibleTerminal,
+                    nonterminal,
+                    format!("{terminal} in <{nonterminal}> is never matched, because {reason}."),
+                )
+            });
+        }
+        for (nonterminal, terminal) in self.unknown_escapes.iter() {
+            diagnostics.push(Diagnostic {
+                production: Some(terminal.clone()),
+                ..Diagnostic::new(
+                    Severity::Warning,
+                    DiagnosticKind::UnknownEscape,
+                    nonterminal,
+                    format!("{terminal} in <{nonterminal}> contains an unknown escape sequence, whose backslash is dropped. Write \\\\ for a backslash, or use a raw terminal like r\"C:\\temp\"."),
+                )
+            });
+        }
+        for nonterminal in self.overridden_helpers.iter() {
+            diagnostics.push(Diagnostic::new(
+                Severity::Warning,
+                DiagnosticKind::OverriddenHelper,
+                nonterminal,
+                format!("<{nonterminal}> is defined in the BNF schema, so the definition replaces the built-in helper nonterminal."),
+            ));
+        }
+        diagnostics.sort_by(|a, b| {
+            let key = |x: &Diagnostic| (Reverse(x.severity), x.nonterminal.clone(), x.kind);
+            (key(a), &a.message).cmp(&(key(b), &b.message))
+        });
+        diagnostics
+    }
+
+    /// The nonterminals with an alternative matching a complete sentence, found by the usual fixpoint
+    /// where an alternative is productive when all its nonterminals are. Token sets and terminals are productive.
+    pub(crate) fn productive_nonterminals(&self) -> FxHashSet<NonterminalID> {
+        let mut productive: FxHashSet<NonterminalID> = FxHashSet::default();
+        loop {
+            let before = productive.len();
+            for (id, expressions) in self.nonterminal_id_to_expression.iter() {
+                let is_productive = match expressions {
+                    SimplifiedExpressions::Terminals(_) => true,
+                    SimplifiedExpressions::Expressions(expressions) => {
+                        expressions.iter().any(|terms| {
+                            terms.iter().all(|term| match term {
+                                U8Term::Nonterminal(x) => self
+                                    .nonterminal_to_terminal_id
+                                    .get(x)
+                                    .is_some_and(|x| productive.contains(x)),
+                                U8Term::Terminal(_) => true,
+                            })
+                        })
+                    }
+                };
+                if is_productive {
+                    productive.insert(*id);
+                }
+            }
+            if productive.len() == before {
+                return productive;
+            }
+        }
+    }
+
+    /// The nonterminals reachable from `start` that match no complete sentence, sorted,
+    /// see [`Grammar::productive_nonterminals`].
+    pub(crate) fn non_productive_nonterminals(&self, start: &str) -> Vec<String> {
+        let productive = self.productive_nonterminals();
+        let mut reachable: FxHashSet<&str> = FxHashSet::default();
+        let mut queue = vec![start];
+        while let Some(nonterminal) = queue.pop() {
+            let Some(id) = self.nonterminal_to_terminal_id.get(nonterminal) else {
+                continue;
+            };
+            if productive.contains(id) || !reachable.insert(nonterminal) {
+                continue;
+            }
+            if let Some(SimplifiedExpressions::Expressions(expressions)) =
+                self.nonterminal_id_to_expression.get(id)
+            {
+                queue.extend(expressions.iter().flatten().filter_map(|term| match term {
+                    U8Term::Nonterminal(x) => Some(x.as_str()),
+                    U8Term::Terminal(_) => None,
+                }));
+            }
+        }
+        reachable
+            .into_iter()
+            .sorted_unstable()
+            .map(|x| x.to_string())
+            .collect()
+    }
+
+    /// The deepest stack any derivation of the grammar can create, or `None` if the nesting is unbounded.
+    ///
+    /// Expanding a nonterminal into an expression leaves the terms after each nonterminal on the stack,
+    /// so the depth is the longest path in the graph of nonterminals weighted by those terms.
+    pub(crate) fn max_stack_depth(&self) -> Option<usize> {
+        let mut depths: FxHashMap<NonterminalID, usize> = self
+            .nonterminal_id_to_expression
+            .keys()
+            .map(|id| (*id, 1))
+            .collect();
+        // A longest path visits each nonterminal at most once, so it stabilizes within len() rounds.
+        for _ in 0..=self.nonterminal_id_to_expression.len() {
+            let mut changed = false;
+            for (id, expressions) in self.nonterminal_id_to_expression.iter() {
+                let SimplifiedExpressions::Expressions(expressions) = expressions else {
+                    continue;
+                };
+                let mut depth = depths[id];
+                for expression in expressions.iter() {
+                    for (i, term) in expression.iter().enumerate() {
+                        if let U8Term::Nonterminal(nonterminal) = term {
+                            let Some(nonterminal_id) =
+                                self.nonterminal_to_terminal_id.get(nonterminal)
+                            else {
+                                continue;
+                            };
+                            let remaining = expression.len() - 1 - i;
+                            depth = depth
+                                .max(remaining + depths.get(nonterminal_id).copied().unwrap_or(1));
+                        }
+                    }
+                }
+                if depth > depths[id] {
+                    depths.insert(*id, depth);
+                    changed = true;
+                }
+            }
+            if !changed {
+                return depths.values().copied().max();
+            }
+        }
+        None
+    }
 }
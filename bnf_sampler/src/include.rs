@@ -0,0 +1,149 @@
+//! Build a grammar from BNF schema files that include each other with `@include "path.bnf"` lines,
+//! e.g. to share the rules of dates, numbers and JSON strings between schemas.
+//!
+//! The files are merged into one schema. A path is relative to the directory of the including file,
+//! a file included more than once is merged once, and a file including itself is an error.
+//! The files share one namespace, so a nonterminal defined by two files is an error instead of being merged.
+use crate::grammar::{strip_comments, Grammar, GrammarBuildOptions, ParseError};
+use crate::vocabulary::Vocabulary;
+use anyhow::{anyhow, bail, ensure, Context, Error};
+use std::collections::BTreeMap;
+use std::fs;
+use std::path::{Path, PathBuf};
+use std::sync::Arc;
+
+/// The maximum nesting of `@include` lines, counting the file given to [`Grammar::from_file`] as 1.
+pub const MAX_INCLUDE_DEPTH: usize = 16;
+
+/// The schema merged from the files, with the file and the line each line comes from.
+#[derive(Default)]
+struct MergedSchema {
+    text: String,
+    origins: Vec<(PathBuf, usize)>,
+    /// The files already merged, by their canonical paths.
+    merged: Vec<PathBuf>,
+    /// The file defining each nonterminal.
+    definitions: BTreeMap<String, PathBuf>,
+}
+
+impl MergedSchema {
+    /// Merge the files included by `path` and then `path` itself, where `chain` is the files including it.
+    fn merge(&mut self, path: &Path, chain: &mut Vec<PathBuf>) -> Result<(), Error> {
+        let canonical = path
+            .canonicalize()
+            .with_context(|| format!("cannot open the BNF schema {:?}", path))?;
+        if let Some(i) = chain.iter().position(|x| *x == canonical) {
+            let cycle = chain[i..]
+                .iter()
+                .chain([&canonical])
+                .map(|x| format!("{x:?}"))
+                .collect::<Vec<_>>();
+            bail!("{:?} includes itself through {}.", path, cycle.join(" -> "));
+        }
+        if self.merged.contains(&canonical) {
+            return Ok(());
+        }
+        ensure!(
+            chain.len() < MAX_INCLUDE_DEPTH,
+            "{:?} is included more than {MAX_INCLUDE_DEPTH} files deep.",
+            path
+        );
+        let text = fs::read_to_string(path)
+            .with_context(|| format!("cannot read the BNF schema {:?}", path))?;
+        let directory = path.parent().unwrap_or(Path::new(""));
+        let mut lines = vec![];
+        chain.push(canonical.clone());
+        for (i, line) in strip_comments(&text).lines().enumerate() {
+            let Some(included) = line.trim_start().strip_prefix("@include") else {
+                lines.push(line.to_string());
+                continue;
+            };
+            let included =
+                parse_include(included).with_context(|| format!("line {} of {:?}", i + 1, path))?;
+            self.merge(&directory.join(included), chain)?;
+            lines.push(String::new());
+        }
+        chain.pop();
+        for line in lines.iter() {
+            let Some(name) = defined_nonterminal(line) else {
+                continue;
+            };
+            match self.definitions.get(name) {
+                Some(other) if *other != canonical => bail!(
+                    "<{name}> is defined in both {:?} and {:?}. The included files share one namespace, \
+                    so rename one of them.",
+                    other,
+                    path
+                ),
+                Some(_) => {}
+                None => {
+                    self.definitions.insert(name.to_string(), canonical.clone());
+                }
+            }
+        }
+        for (i, line) in lines.into_iter().enumerate() {
+            self.text.push_str(&line);
+            self.text.push('\n');
+            self.origins.push((path.to_path_buf(), i + 1));
+        }
+        self.merged.push(canonical);
+        Ok(())
+    }
+}
+
+/// The path quoted after `@include`.
+fn parse_include(rest: &str) -> Result<&str, Error> {
+    let invalid =
+        || anyhow!("@include should be followed by a quoted path, like @include \"dates.bnf\".");
+    let rest = rest.trim().strip_prefix('"').ok_or_else(invalid)?;
+    let end = rest.find('"').ok_or_else(invalid)?;
+    ensure!(rest[end + 1..].trim().is_empty(), invalid());
+    Ok(&rest[..end])
+}
+
+/// The nonterminal defined by a line starting with `<nonterminal>::=`.
+pub(crate) fn defined_nonterminal(line: &str) -> Option<&str> {
+    let rest = line.trim_start().strip_prefix('<')?;
+    let end = rest.find('>')?;
+    rest[end + 1..]
+        .trim_start()
+        .starts_with("::=")
+        .then(|| &rest[..end])
+}
+
+impl Grammar {
+    /// Create a new grammar from a BNF schema file and the files it includes, see the [module documentation](self).
+    pub fn from_file(
+        path: impl AsRef<Path>,
+        vocabulary: Arc<Vocabulary>,
+        options: GrammarBuildOptions,
+    ) -> Result<Arc<Self>, Error> {
+        Self::from_files(&[path], vocabulary, options)
+    }
+
+    /// Create a new grammar from several BNF schema files, merged as if a file included each of them in order.
+    ///
+    /// A syntax error is a [`ParseError`] with the line in its file, and the file is in the context of the error.
+    pub fn from_files(
+        paths: &[impl AsRef<Path>],
+        vocabulary: Arc<Vocabulary>,
+        options: GrammarBuildOptions,
+    ) -> Result<Arc<Self>, Error> {
+        let mut schema = MergedSchema::default();
+        for path in paths {
+            schema.merge(path.as_ref(), &mut vec![])?;
+        }
+        Self::with_options(&schema.text, vocabulary, options).map_err(|e| {
+            match e.downcast::<ParseError>() {
+                Ok(mut error) => match schema.origins.get(error.line - 1) {
+                    Some((path, line)) => {
+                        error.line = *line;
+                        Error::new(error).context(format!("in the BNF schema {:?}", path))
+                    }
+                    None => Error::new(error),
+                },
+                Err(e) => e,
+            }
+        })
+    }
+}
@@ -0,0 +1,61 @@
+//! Read what the nonterminals of a grammar expand to, e.g. for grammar visualizers and docs generators.
+//!
+//! [`Grammar::nonterminals`] lists the nonterminals of the BNF schema. The nonterminals the grammar adds
+//! while it is built, like the special nonterminals such as `except!([escaped])`, appear in the alternatives
+//! and can be read the same way.
+use crate::grammar::{Grammar, SimplifiedExpressions, U8Term};
+use itertools::Itertools;
+
+/// A term of an alternative returned by [`Grammar::productions`].
+#[derive(Debug, PartialEq, Clone, Eq, PartialOrd, Ord, Hash)]
+pub enum PublicTerm {
+    /// The bytes of a terminal, where consecutive terminals are already merged into one.
+    Terminal(Vec<u8>),
+    /// The name of a nonterminal, without the angle brackets.
+    Nonterminal(String),
+}
+
+impl Grammar {
+    /// The sorted alternatives of `nonterminal`, or `None` if it is not defined.
+    ///
+    /// An empty alternative matches no bytes. A nonterminal matching a set of terminals, like `<any!>`
+    /// or `<except!('"')>`, has no alternatives, and its terminals are listed by [`Grammar::terminals_of`] instead.
+    pub fn productions(&self, nonterminal: &str) -> Option<Vec<Vec<PublicTerm>>> {
+        let id = self.nonterminal_to_terminal_id.get(nonterminal)?;
+        match self.nonterminal_id_to_expression.get(id)? {
+            SimplifiedExpressions::Expressions(expressions) => Some(
+                expressions
+                    .iter()
+                    .map(|terms| {
+                        terms
+                            .iter()
+                            .map(|term| match term {
+                                U8Term::Terminal(id) => {
+                                    PublicTerm::Terminal(self.terminals.get(*id).to_vec())
+                                }
+                                U8Term::Nonterminal(name) => PublicTerm::Nonterminal(name.clone()),
+                            })
+                            .collect_vec()
+                    })
+                    .sorted_unstable()
+                    .collect(),
+            ),
+            SimplifiedExpressions::Terminals(_) => Some(vec![]),
+        }
+    }
+
+    /// The terminals of a nonterminal matching a set of terminals, like `<any!>` or `<except!('"')>`,
+    /// in no particular order. Returns `None` if the nonterminal is not defined or has alternatives instead,
+    /// see [`Grammar::productions`].
+    ///
+    /// The set of `<any!>` and `<except!(...)>` holds every matching token of the vocabulary, so it can be large.
+    pub fn terminals_of(&self, nonterminal: &str) -> Option<impl Iterator<Item = &[u8]>> {
+        let id = self.nonterminal_to_terminal_id.get(nonterminal)?;
+        match self.nonterminal_id_to_expression.get(id)? {
+            SimplifiedExpressions::Terminals(root) => {
+                Some(self.terminals_trie.iter(*root, &self.terminals))
+            }
+            SimplifiedExpressions::Expressions(_) => None,
+        }
+    }
+}
@@ -0,0 +1,371 @@
+use anyhow::{anyhow, bail, ensure, Error};
+use serde_json::{Map, Value};
+use std::collections::BTreeMap;
+use std::fmt::Write;
+
+/// The rules shared by every converted schema. Whitespace is only allowed as a single space after `:` and `,`,
+/// so the model cannot spend tokens on formatting.
+const COMMON_RULES: &str = r#"<json_colon>::=':'|': '
+<json_comma>::=','|', '
+<json_string>::='"'<json_chars>'"'|'""'
+<json_chars>::=<json_char>|<json_char><json_chars>
+<json_char>::=<except!([json_escaped])>|'\\'<json_escape>
+<json_escaped>::='"'|'\\'|'\n'|'\r'|'\t'
+<json_escape>::='"'|'\\'|'/'|'b'|'f'|'n'|'r'|'t'|'u'<json_hex><json_hex><json_hex><json_hex>
+<json_hex>::='0'|'1'|'2'|'3'|'4'|'5'|'6'|'7'|'8'|'9'|'a'|'b'|'c'|'d'|'e'|'f'|'A'|'B'|'C'|'D'|'E'|'F'
+<json_integer>::='-'<json_natural>|<json_natural>
+<json_natural>::='0'|<json_digit19>|<json_digit19><json_digits>
+<json_digit19>::='1'|'2'|'3'|'4'|'5'|'6'|'7'|'8'|'9'
+<json_digits>::=<json_digit>|<json_digit><json_digits>
+<json_digit>::='0'|'1'|'2'|'3'|'4'|'5'|'6'|'7'|'8'|'9'
+<json_number>::=<json_integer>|<json_integer><json_fraction>|<json_integer><json_exponent>|<json_integer><json_fraction><json_exponent>
+<json_fraction>::='.'<json_digits>
+<json_exponent>::=<json_e><json_digits>|<json_e><json_sign><json_digits>
+<json_e>::='e'|'E'
+<json_sign>::='+'|'-'
+<json_boolean>::='true'|'false'
+<json_null>::='null'
+<json_value>::=<json_object>|<json_array>|<json_string>|<json_number>|<json_boolean>|<json_null>
+<json_object>::='{'<json_members>'}'|'{}'
+<json_members>::=<json_member>|<json_member><json_comma><json_members>
+<json_member>::=<json_string><json_colon><json_value>
+<json_array>::='['<json_elements>']'|'[]'
+<json_elements>::=<json_value>|<json_value><json_comma><json_elements>
+"#;
+
+/// Quote `bytes` as a terminal, escaping the bytes that cannot appear in a quoted terminal.
+pub(crate) fn bnf_terminal(bytes: &[u8]) -> String {
+    let mut terminal = String::from("'");
+    for byte in bytes {
+        match byte {
+            b'\'' | b'"' | b'\\' | 0..=0x1F | 0x7F.. => write!(terminal, "\\x{byte:02X}").unwrap(),
+            _ => terminal.push(*byte as char),
+        }
+    }
+    terminal.push('\'');
+    terminal
+}
+
+struct Converter<'a> {
+    rules: Vec<String>,
+    count: usize,
+    /// The whole document, where `$ref` pointers are resolved.
+    root: &'a Value,
+    /// The nonterminal of each `$ref` converted so far, so recursive references end.
+    refs: BTreeMap<String, String>,
+}
+
+/// The repetition operator of a group repeated from `min` to `max` times, where `None` is unbounded.
+fn repetition(min: u64, max: Option<u64>) -> String {
+    match (min, max) {
+        (0, None) => "*".to_string(),
+        (1, None) => "+".to_string(),
+        (0, Some(1)) => "?".to_string(),
+        (min, None) => format!("{{{min},}}"),
+        (min, Some(max)) if min == max => format!("{{{min}}}"),
+        (min, Some(max)) => format!("{{{min},{max}}}"),
+    }
+}
+
+impl<'a> Converter<'a> {
+    fn new_nonterminal(&mut self) -> String {
+        self.count += 1;
+        format!("json_schema_{}", self.count - 1)
+    }
+
+    fn add_rule(&mut self, nonterminal: &str, alternatives: &[String]) {
+        self.rules
+            .push(format!("<{nonterminal}>::={}", alternatives.join("|")));
+    }
+
+    /// Convert `schema` and return the nonterminal, or the terminal, matching it.
+    fn convert(&mut self, schema: &'a Value) -> Result<String, Error> {
+        let schema = match schema {
+            Value::Bool(true) => return Ok("<json_value>".to_string()),
+            Value::Bool(false) => bail!("The schema `false` matches nothing."),
+            Value::Object(schema) => schema,
+            _ => bail!("{schema} is not a valid schema."),
+        };
+        if let Some(reference) = schema.get("$ref") {
+            return self.convert_ref(reference);
+        }
+        if let Some(value) = schema.get("const") {
+            return Ok(bnf_terminal(value.to_string().as_bytes()));
+        }
+        if let Some(values) = schema.get("enum") {
+            let values = values
+                .as_array()
+                .filter(|x| !x.is_empty())
+                .ok_or_else(|| anyhow!("enum should be a non-empty array."))?;
+            let nonterminal = self.new_nonterminal();
+            let alternatives: Vec<String> = values
+                .iter()
+                .map(|x| bnf_terminal(x.to_string().as_bytes()))
+                .collect();
+            self.add_rule(&nonterminal, &alternatives);
+            return Ok(format!("<{nonterminal}>"));
+        }
+        for key in ["anyOf", "oneOf"] {
+            if let Some(schemas) = schema.get(key) {
+                let schemas = schemas
+                    .as_array()
+                    .filter(|x| !x.is_empty())
+                    .ok_or_else(|| anyhow!("{key} should be a non-empty array."))?;
+                let nonterminal = self.new_nonterminal();
+                let alternatives = schemas
+                    .iter()
+                    .map(|x| self.convert(x))
+                    .collect::<Result<Vec<_>, _>>()?;
+                self.add_rule(&nonterminal, &alternatives);
+                return Ok(format!("<{nonterminal}>"));
+            }
+        }
+        match schema.get("type") {
+            None => Ok("<json_value>".to_string()),
+            Some(Value::String(kind)) => self.convert_type(kind, schema),
+            Some(Value::Array(kinds)) => {
+                let nonterminal = self.new_nonterminal();
+                let alternatives = kinds
+                    .iter()
+                    .map(|kind| match kind {
+                        Value::String(kind) => self.convert_type(kind, schema),
+                        _ => bail!("{kind} is not a valid type."),
+                    })
+                    .collect::<Result<Vec<_>, _>>()?;
+                ensure!(!alternatives.is_empty(), "type should not be empty.");
+                self.add_rule(&nonterminal, &alternatives);
+                Ok(format!("<{nonterminal}>"))
+            }
+            Some(kind) => bail!("{kind} is not a valid type."),
+        }
+    }
+
+    /// A `$ref` is a JSON pointer into the same document, like `#/$defs/address`, converted once into a nonterminal.
+    fn convert_ref(&mut self, reference: &Value) -> Result<String, Error> {
+        let reference = reference
+            .as_str()
+            .ok_or_else(|| anyhow!("$ref should be a string."))?;
+        if let Some(nonterminal) = self.refs.get(reference) {
+            return Ok(format!("<{nonterminal}>"));
+        }
+        let pointer = reference.strip_prefix('#').ok_or_else(|| {
+            anyhow!("Only a $ref within the same document, like #/$defs/name, is supported, but {reference} is found.")
+        })?;
+        let target = self
+            .root
+            .pointer(pointer)
+            .ok_or_else(|| anyhow!("$ref {reference} does not point into the schema."))?;
+        let nonterminal = self.new_nonterminal();
+        self.refs.insert(reference.to_string(), nonterminal.clone());
+        let expression = self.convert(target)?;
+        self.add_rule(&nonterminal, &[expression]);
+        Ok(format!("<{nonterminal}>"))
+    }
+
+    fn convert_type(
+        &mut self,
+        kind: &str,
+        schema: &'a Map<String, Value>,
+    ) -> Result<String, Error> {
+        match kind {
+            "string" => match schema.get("format").and_then(|x| x.as_str()) {
+                Some("date-time") => Ok("'\"'<datetime!>'\"'".to_string()),
+                Some("date") => Ok("'\"'<date!>'\"'".to_string()),
+                Some("time") => Ok("'\"'<time!>'\"'".to_string()),
+                _ => Ok("<json_string>".to_string()),
+            },
+            "number" => Self::convert_number(schema),
+            "integer" => Self::convert_integer(schema),
+            "boolean" => Ok("<json_boolean>".to_string()),
+            "null" => Ok("<json_null>".to_string()),
+            "array" => self.convert_array(schema),
+            "object" => self.convert_object(schema),
+            _ => bail!("{kind} is not a valid type."),
+        }
+    }
+
+    /// An integer with `minimum`, `maximum`, `exclusiveMinimum` or `exclusiveMaximum` uses `<number!(min, max)>`.
+    fn convert_integer(schema: &Map<String, Value>) -> Result<String, Error> {
+        let bound = |key: &str| -> Result<Option<f64>, Error> {
+            schema
+                .get(key)
+                .map(|x| {
+                    x.as_f64()
+                        .ok_or_else(|| anyhow!("{key} should be a number."))
+                })
+                .transpose()
+        };
+        let mut min = i64::MIN as f64;
+        let mut max = i64::MAX as f64;
+        if let Some(x) = bound("minimum")? {
+            min = min.max(x.ceil());
+        }
+        if let Some(x) = bound("exclusiveMinimum")? {
+            min = min.max(x.floor() + 1.0);
+        }
+        if let Some(x) = bound("maximum")? {
+            max = max.min(x.floor());
+        }
+        if let Some(x) = bound("exclusiveMaximum")? {
+            max = max.min(x.ceil() - 1.0);
+        }
+        if min == i64::MIN as f64 && max == i64::MAX as f64 {
+            return Ok("<json_integer>".to_string());
+        }
+        ensure!(
+            min <= max,
+            "The bounds of an integer exclude every integer."
+        );
+        Ok(format!("<number!({}, {})>", min as i64, max as i64))
+    }
+
+    /// A number whose `multipleOf` is a negative power of ten like 0.01 uses `<decimal!(...)>`,
+    /// allowing as many integer digits as a double holds.
+    fn convert_number(schema: &Map<String, Value>) -> Result<String, Error> {
+        let Some(multiple) = schema.get("multipleOf") else {
+            return Ok("<json_number>".to_string());
+        };
+        let multiple = multiple
+            .as_f64()
+            .filter(|x| *x > 0.0)
+            .ok_or_else(|| anyhow!("multipleOf should be a positive number."))?;
+        let frac_digits = -multiple.log10().round();
+        if frac_digits >= 1.0 && (10f64.powf(-frac_digits) - multiple).abs() <= multiple * 1e-9 {
+            Ok(format!("<decimal!(15, {frac_digits}, optional)>"))
+        } else {
+            Ok("<json_number>".to_string())
+        }
+    }
+
+    fn convert_array(&mut self, schema: &'a Map<String, Value>) -> Result<String, Error> {
+        let item = match schema.get("items") {
+            Some(items) => self.convert(items)?,
+            None => "<json_value>".to_string(),
+        };
+        let count = |key: &str| -> Result<Option<u64>, Error> {
+            schema
+                .get(key)
+                .map(|x| {
+                    x.as_u64()
+                        .ok_or_else(|| anyhow!("{key} should be a non-negative integer."))
+                })
+                .transpose()
+        };
+        let min = count("minItems")?.unwrap_or(0);
+        let max = count("maxItems")?;
+        ensure!(
+            max.is_none_or(|max| min <= max),
+            "minItems should not be larger than maxItems."
+        );
+        let nonterminal = self.new_nonterminal();
+        let mut alternatives = vec![];
+        if min == 0 {
+            alternatives.push("'[]'".to_string());
+        }
+        if max != Some(0) {
+            // The first item, then the others after commas.
+            let rest = match (min.saturating_sub(1), max.map(|x| x - 1)) {
+                (0, Some(0)) => String::new(),
+                (min, max) => format!("(<json_comma>{item}){}", repetition(min, max)),
+            };
+            alternatives.push(format!("'['{item}{rest}']'"));
+        }
+        self.add_rule(&nonterminal, &alternatives);
+        Ok(format!("<{nonterminal}>"))
+    }
+
+    /// The properties are emitted in the order of the schema, and an optional property may be omitted.
+    fn convert_object(&mut self, schema: &'a Map<String, Value>) -> Result<String, Error> {
+        let Some(properties) = schema.get("properties") else {
+            return Ok("<json_object>".to_string());
+        };
+        let properties = properties
+            .as_object()
+            .ok_or_else(|| anyhow!("properties should be an object."))?;
+        let required: Vec<&str> = match schema.get("required") {
+            Some(required) => required
+                .as_array()
+                .and_then(|x| x.iter().map(|x| x.as_str()).collect())
+                .ok_or_else(|| anyhow!("required should be an array of strings."))?,
+            None => vec![],
+        };
+        for name in required.iter() {
+            ensure!(
+                properties.contains_key(*name),
+                "The required property {name} is not in properties."
+            );
+        }
+        let mut members = vec![];
+        for (name, property) in properties.iter() {
+            let key = bnf_terminal(format!("{}", Value::String(name.clone())).as_bytes());
+            let value = self.convert(property)?;
+            members.push((
+                format!("{key}<json_colon>{value}"),
+                required.contains(&name.as_str()),
+            ));
+        }
+        let n = members.len();
+        // nullable[i] is whether the properties from i on can all be omitted.
+        let mut nullable = vec![true; n + 1];
+        for i in (0..n).rev() {
+            nullable[i] = nullable[i + 1] && !members[i].1;
+        }
+        // first[i] emits the first present property from i on, and rest[i] emits the properties from i on after a comma.
+        // Both emit at least one property.
+        let first: Vec<String> = (0..n).map(|_| self.new_nonterminal()).collect();
+        let rest: Vec<String> = (0..n).map(|_| self.new_nonterminal()).collect();
+        for i in 0..n {
+            let (member, is_required) = &members[i];
+            let mut first_alternatives = vec![];
+            let mut rest_alternatives = vec![];
+            if nullable[i + 1] {
+                first_alternatives.push(member.clone());
+                rest_alternatives.push(format!("<json_comma>{member}"));
+            }
+            if i + 1 < n {
+                first_alternatives.push(format!("{member}<{}>", rest[i + 1]));
+                rest_alternatives.push(format!("<json_comma>{member}<{}>", rest[i + 1]));
+                if !is_required {
+                    first_alternatives.push(format!("<{}>", first[i + 1]));
+                    rest_alternatives.push(format!("<{}>", rest[i + 1]));
+                }
+            }
+            self.add_rule(&first[i], &first_alternatives);
+            self.add_rule(&rest[i], &rest_alternatives);
+        }
+        let nonterminal = self.new_nonterminal();
+        let mut alternatives = vec![];
+        if n > 0 {
+            alternatives.push(format!("'{{'<{}>'}}'", first[0]));
+        }
+        if nullable[0] {
+            alternatives.push("'{}'".to_string());
+        }
+        self.add_rule(&nonterminal, &alternatives);
+        Ok(format!("<{nonterminal}>"))
+    }
+}
+
+/// Convert a JSON Schema into a BNF schema whose `<start>` matches the compact JSON documents valid against it.
+///
+/// `type`, `properties`, `required`, `items`, `enum`, `const`, `anyOf`, `oneOf` and `$ref` within the same document
+/// are supported, as well as `minItems` and `maxItems` of arrays, `minimum`, `maximum`, `exclusiveMinimum` and
+/// `exclusiveMaximum` of integers, `multipleOf` of numbers when it is a negative power of ten, and the `date-time`, `date` and `time` formats of strings.
+/// Other keywords are ignored, so the documents may violate them.
+/// The properties of an object are emitted in the order of the schema, and a single space may follow `:` and `,`.
+pub fn to_bnf(schema: &Value) -> Result<String, Error> {
+    let mut converter = Converter {
+        rules: vec![],
+        count: 0,
+        root: schema,
+        refs: BTreeMap::new(),
+    };
+    let start = converter.convert(schema)?;
+    let mut bnf = format!("<start>::={start}\n");
+    for rule in converter.rules.iter() {
+        bnf.push_str(rule);
+        bnf.push('\n');
+    }
+    bnf.push_str(COMMON_RULES);
+    Ok(bnf)
+}
@@ -1,7 +1,34 @@
+pub mod boundary;
+pub mod bundle;
+pub(crate) mod cache;
+pub mod compat;
+pub mod complete;
+pub mod compose;
+pub mod differential;
+pub mod enumerate;
+#[cfg(any(test, feature = "fixtures"))]
+pub mod fixtures;
 pub mod grammar;
+pub mod include;
+pub mod introspect;
+pub mod json_schema;
+pub mod lint;
+pub mod mask;
+pub mod memory;
+pub mod metrics;
+pub mod prelude;
+pub mod presets;
+pub mod quick;
 pub mod sampler;
+pub mod schema;
+#[cfg(feature = "serde")]
+pub mod serialize;
+pub(crate) mod signature;
+pub mod special;
 pub(crate) mod stack;
+pub mod trace;
 pub(crate) mod trie;
+pub mod tutorial;
 pub mod utils;
 pub mod vocabulary;
 use mimalloc::MiMalloc;
@@ -0,0 +1,204 @@
+use crate::grammar::{format_expression, Grammar, SimplifiedExpressions, U8Term};
+use crate::utils;
+use crate::utils::NonterminalID;
+use rustc_hash::{FxHashMap, FxHashSet};
+use std::fmt;
+
+/// The kind of a [`LintFinding`].
+#[derive(Debug, PartialEq, Clone, Copy, Eq, Hash, PartialOrd, Ord)]
+pub enum LintKind {
+    /// The same alternative, after adjacent terminals are concatenated, is defined more than once.
+    DuplicateAlternative,
+    /// The leading terminal of one alternative is a prefix of the leading terminal of another,
+    /// so both alternatives create a stack until the shorter terminal is matched.
+    SharedPrefix,
+    /// A nonterminal with a single alternative that could be inlined into the rules using it.
+    SingleAlternative,
+    /// A `<any!>` or `<except!(...)>` nonterminal that no token in the vocabulary can match.
+    EmptyTokenSet,
+    /// A `<except!([nonterminal])>` whose nonterminal produces more than half of
+    /// [`crate::grammar::GrammarBuildOptions::max_except_terminals`] literals.
+    LargeExceptSet,
+    /// An alternative removed by [`crate::grammar::GrammarBuildOptions::prune_unreachable`] because
+    /// no tokenization with the vocabulary can produce it.
+    UnreachableAlternative,
+    /// A nonterminal whose alternatives all use a nonterminal like itself, so it never matches a complete sentence,
+    /// like `<loop>::='x'<loop>` without a base case.
+    NonProductive,
+}
+
+/// A potential problem of a grammar found by [`Grammar::lint`].
+#[derive(Debug, PartialEq, Clone, Eq)]
+pub struct LintFinding {
+    pub kind: LintKind,
+    /// The nonterminal the finding belongs to.
+    pub nonterminal: String,
+    pub message: String,
+}
+
+impl fmt::Display for LintFinding {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(
+            f,
+            "{:?} in <{}>: {}",
+            self.kind, self.nonterminal, self.message
+        )
+    }
+}
+
+impl Grammar {
+    /// Find alternatives and nonterminals that are redundant or cost extra stacks during sampling.
+    ///
+    /// The findings are sorted by nonterminal and kind.
+    pub fn lint(&self) -> Vec<LintFinding> {
+        let mut findings = vec![];
+        for (nonterminal, expression) in self.duplicate_alternatives.iter() {
+            findings.push(LintFinding {
+                kind: LintKind::DuplicateAlternative,
+                nonterminal: nonterminal.clone(),
+                message: format!("{expression} is defined more than once."),
+            });
+        }
+        let id_to_nonterminal: FxHashMap<NonterminalID, &str> = self
+            .nonterminal_to_terminal_id
+            .iter()
+            .map(|(k, v)| (*v, k.as_str()))
+            .collect();
+        let mut referenced: FxHashSet<&str> = FxHashSet::default();
+        for (id, expressions) in self.nonterminal_id_to_expression.iter() {
+            let SimplifiedExpressions::Expressions(expressions) = expressions else {
+                continue;
+            };
+            for term in expressions.iter().flatten() {
+                if let U8Term::Nonterminal(nonterminal) = term {
+                    if nonterminal != id_to_nonterminal[id] {
+                        referenced.insert(nonterminal);
+                    }
+                }
+            }
+        }
+        for (id, expressions) in self.nonterminal_id_to_expression.iter() {
+            let SimplifiedExpressions::Expressions(expressions) = expressions else {
+                continue;
+            };
+            let nonterminal = id_to_nonterminal[id];
+            let expressions: Vec<&Vec<U8Term>> = expressions.iter().collect();
+            for (i, a) in expressions.iter().enumerate() {
+                for b in expressions[i + 1..].iter() {
+                    let (Some(U8Term::Terminal(x)), Some(U8Term::Terminal(y))) =
+                        (a.first(), b.first())
+                    else {
+                        continue;
+                    };
+                    let (x, y) = (self.terminals.get(*x), self.terminals.get(*y));
+                    let (shorter, longer) = if x.len() <= y.len() { (a, b) } else { (b, a) };
+                    if x.starts_with(y) || y.starts_with(x) {
+                        findings.push(LintFinding {
+                            kind: LintKind::SharedPrefix,
+                            nonterminal: nonterminal.to_string(),
+                            message: format!(
+                                "{} and {} start with the same bytes, so both are matched in separate stacks.",
+                                format_expression(shorter, &self.terminals),
+                                format_expression(longer, &self.terminals)
+                            ),
+                        });
+                    }
+                }
+            }
+            let recursive = expressions
+                .iter()
+                .flat_map(|x| x.iter())
+                .any(|x| matches!(x, U8Term::Nonterminal(x) if x == nonterminal));
+            if expressions.len() == 1
+                && !recursive
+                && referenced.contains(nonterminal)
+                && !nonterminal.starts_with(utils::TERMINALS_NONTERMINAL_NAME)
+            {
+                findings.push(LintFinding {
+                    kind: LintKind::SingleAlternative,
+                    nonterminal: nonterminal.to_string(),
+                    message: format!(
+                        "the only alternative {} could be inlined.",
+                        format_expression(expressions[0], &self.terminals)
+                    ),
+                });
+            }
+        }
+        for (id, token_ids) in self.nonterminal_to_token_ids.iter() {
+            if token_ids.is_empty() {
+                findings.push(LintFinding {
+                    kind: LintKind::EmptyTokenSet,
+                    nonterminal: id_to_nonterminal[id].to_string(),
+                    message: "no token in the vocabulary matches this nonterminal.".to_string(),
+                });
+            }
+        }
+        let productive = self.productive_nonterminals();
+        for (id, nonterminal) in id_to_nonterminal.iter() {
+            if !productive.contains(id) {
+                findings.push(LintFinding {
+                    kind: LintKind::NonProductive,
+                    nonterminal: nonterminal.to_string(),
+                    message: "every alternative uses a nonterminal that never matches a complete sentence, so no derivation ends.".to_string(),
+                });
+            }
+        }
+        for (nonterminal, count, max) in self.large_excepts.iter() {
+            findings.push(LintFinding {
+                kind: LintKind::LargeExceptSet,
+                nonterminal: nonterminal.clone(),
+                message: format!(
+                    "the excepted nonterminal produces {count} literals, close to the maximum of {max}."
+                ),
+            });
+        }
+        for (nonterminal, expression, reason) in self.pruned_alternatives.iter() {
+            findings.push(LintFinding {
+                kind: LintKind::UnreachableAlternative,
+                nonterminal: nonterminal.clone(),
+                message: format!("{expression} was pruned because {reason}."),
+            });
+        }
+        findings.sort_by(|a, b| {
+            (&a.nonterminal, a.kind, &a.message).cmp(&(&b.nonterminal, b.kind, &b.message))
+        });
+        findings
+    }
+
+    /// The nonterminals defined in the BNF schema that no derivation from `start` uses, sorted.
+    ///
+    /// Their terminals still take memory in the terminals trie. The nonterminal excluded by
+    /// `<except!([nonterminal])>` is reachable when the except nonterminal is. If `start` is not defined,
+    /// every nonterminal is unreachable.
+    pub fn unreachable_nonterminals(&self, start: &str) -> Vec<String> {
+        let mut reachable: FxHashSet<&str> = FxHashSet::default();
+        let mut queue: Vec<&str> = vec![start];
+        while let Some(nonterminal) = queue.pop() {
+            let Some(id) = self.nonterminal_to_terminal_id.get(nonterminal) else {
+                continue;
+            };
+            if !reachable.insert(nonterminal) {
+                continue;
+            }
+            if let Some(SimplifiedExpressions::Expressions(expressions)) =
+                self.nonterminal_id_to_expression.get(id)
+            {
+                queue.extend(expressions.iter().flatten().filter_map(|term| match term {
+                    U8Term::Nonterminal(x) => Some(x.as_str()),
+                    U8Term::Terminal(_) => None,
+                }));
+            }
+            queue.extend(
+                self.excepted_nonterminals
+                    .iter()
+                    .filter(|(except, _)| except == nonterminal)
+                    .map(|(_, excepted)| excepted.as_str()),
+            );
+        }
+        self.nonterminals()
+            .into_iter()
+            .filter(|x| !reachable.contains(x))
+            .map(|x| x.to_string())
+            .collect()
+    }
+}
@@ -0,0 +1,178 @@
+//! The set of token ids used for the possible tokens, their cache and the token sets of the grammar.
+//!
+//! It is a [`BitSet`] by default. With the `roaring` feature it is a [`roaring::RoaringBitmap`] instead,
+//! which takes far less memory for sparse sets of a large vocabulary, at some cost in latency.
+#[cfg(not(feature = "roaring"))]
+use bit_set::BitSet;
+
+#[cfg(not(feature = "roaring"))]
+type Inner = BitSet<u32>;
+#[cfg(feature = "roaring")]
+type Inner = roaring::RoaringBitmap;
+
+/// A set of token ids, see the module documentation.
+///
+/// A mask returned by [`Sampler::next_mask`](crate::sampler::Sampler::next_mask) also carries a [`MaskStamp`]
+/// of the step it was computed for, which is ignored by the equality of masks.
+#[derive(Debug, Clone, Default)]
+pub struct TokenMask(Inner, Option<MaskStamp>);
+
+/// Which state of a sampler a mask was computed for, see [`Sampler::assert_mask_fresh`](crate::sampler::Sampler::assert_mask_fresh).
+#[derive(Debug, PartialEq, Clone, Copy, Eq, Hash)]
+pub struct MaskStamp {
+    /// The number of times the sampler had moved, by accepting tokens or bytes or by being reset.
+    pub step: u64,
+    /// The [`Sampler::state_hash`](crate::sampler::Sampler::state_hash) of the sampler.
+    pub state_hash: u64,
+}
+
+impl PartialEq for TokenMask {
+    fn eq(&self, other: &Self) -> bool {
+        self.0 == other.0
+    }
+}
+
+// Equality of sets is an equivalence relation, though `RoaringBitmap` does not implement `Eq`.
+impl Eq for TokenMask {}
+
+impl TokenMask {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Create an empty set sized for the ids below `capacity`. The capacity is ignored by the roaring bitmap.
+    #[cfg_attr(feature = "roaring", allow(unused_variables))]
+    pub fn with_capacity(capacity: usize) -> Self {
+        #[cfg(not(feature = "roaring"))]
+        return TokenMask(BitSet::with_capacity(capacity), None);
+        #[cfg(feature = "roaring")]
+        return TokenMask::default();
+    }
+
+    /// Add `id` to the set. Returns whether it was not in the set.
+    #[inline]
+    pub fn insert(&mut self, id: usize) -> bool {
+        #[cfg(not(feature = "roaring"))]
+        return self.0.insert(id);
+        #[cfg(feature = "roaring")]
+        return self.0.insert(id as u32);
+    }
+
+    /// Remove `id` from the set. Returns whether it was in the set.
+    #[inline]
+    pub fn remove(&mut self, id: usize) -> bool {
+        #[cfg(not(feature = "roaring"))]
+        return self.0.remove(id);
+        #[cfg(feature = "roaring")]
+        return self.0.remove(id as u32);
+    }
+
+    #[inline]
+    pub fn contains(&self, id: usize) -> bool {
+        #[cfg(not(feature = "roaring"))]
+        return self.0.contains(id);
+        #[cfg(feature = "roaring")]
+        return self.0.contains(id as u32);
+    }
+
+    pub fn len(&self) -> usize {
+        #[cfg(not(feature = "roaring"))]
+        return self.0.len();
+        #[cfg(feature = "roaring")]
+        return self.0.len() as usize;
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.0.is_empty()
+    }
+
+    pub fn clear(&mut self) {
+        self.0.clear()
+    }
+
+    /// Iterate the ids in ascending order.
+    pub fn iter(&self) -> impl Iterator<Item = usize> + '_ {
+        #[cfg(not(feature = "roaring"))]
+        return self.0.iter();
+        #[cfg(feature = "roaring")]
+        return self.0.iter().map(|x| x as usize);
+    }
+
+    pub fn union_with(&mut self, other: &TokenMask) {
+        #[cfg(not(feature = "roaring"))]
+        self.0.union_with(&other.0);
+        #[cfg(feature = "roaring")]
+        {
+            self.0 |= &other.0;
+        }
+    }
+
+    /// The number of ids the set has room for without growing, or one more than the largest id for the roaring bitmap.
+    pub fn capacity(&self) -> usize {
+        #[cfg(not(feature = "roaring"))]
+        return self.0.capacity();
+        #[cfg(feature = "roaring")]
+        return self.0.max().map_or(0, |x| x as usize + 1);
+    }
+
+    /// The approximate heap memory of the set in bytes.
+    pub fn memory_bytes(&self) -> usize {
+        #[cfg(not(feature = "roaring"))]
+        return std::mem::size_of_val(self.0.get_ref().storage());
+        #[cfg(feature = "roaring")]
+        return self.0.serialized_size();
+    }
+
+    /// The stamp of a mask returned by [`Sampler::next_mask`](crate::sampler::Sampler::next_mask), or `None`.
+    pub fn stamp(&self) -> Option<MaskStamp> {
+        self.1
+    }
+
+    /// The step of the sampler the mask was computed for, see [`MaskStamp::step`].
+    pub fn step(&self) -> Option<u64> {
+        self.1.map(|x| x.step)
+    }
+
+    pub(crate) fn set_stamp(&mut self, stamp: MaskStamp) {
+        self.1 = Some(stamp);
+    }
+
+    /// Copy the set into a [`bit_set::BitSet`], for code written against the `BitSet` based API.
+    pub fn to_bit_set(&self) -> bit_set::BitSet<u32> {
+        self.iter().collect()
+    }
+}
+
+impl Extend<usize> for TokenMask {
+    fn extend<T: IntoIterator<Item = usize>>(&mut self, iter: T) {
+        for id in iter {
+            self.insert(id);
+        }
+    }
+}
+
+impl FromIterator<usize> for TokenMask {
+    fn from_iter<T: IntoIterator<Item = usize>>(iter: T) -> Self {
+        let mut mask = TokenMask::new();
+        mask.extend(iter);
+        mask
+    }
+}
+
+/// The ids in ascending order, the same for both mask types.
+#[cfg(feature = "serde")]
+impl serde::Serialize for TokenMask {
+    fn serialize<S: serde::Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+        serializer.collect_seq(self.iter())
+    }
+}
+
+#[cfg(feature = "serde")]
+impl<'de> serde::Deserialize<'de> for TokenMask {
+    fn deserialize<D: serde::Deserializer<'de>>(deserializer: D) -> Result<Self, D::Error> {
+        Ok(Vec::<u32>::deserialize(deserializer)?
+            .into_iter()
+            .map(|id| id as usize)
+            .collect())
+    }
+}
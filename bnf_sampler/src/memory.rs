@@ -0,0 +1,145 @@
+//! Attribute the memory of samplers to their owners, e.g. to enforce a quota per tenant of a server,
+//! without a custom global allocator.
+//!
+//! A [`MemoryAccountant`] set with [`SamplerConfig::memory_accountant`](crate::sampler::SamplerConfig::memory_accountant)
+//! is told about the bytes of the stack arena when it is allocated, the stacks whenever their vectors are reallocated,
+//! and the possible tokens cache whenever an entry is inserted or evicted. A sampler reports everything it still holds
+//! as freed when it is dropped, so the bytes of the samplers sharing an accountant add up to what they currently hold.
+//!
+//! The bytes are the sizes of the buffers of the sampler, not what the allocator actually reserves for them.
+use std::fmt;
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::sync::Arc;
+
+/// Told about the bytes a sampler allocates and frees, see the [module documentation](self).
+///
+/// The calls happen on the thread using the sampler, in the middle of its steps, so they should be cheap.
+pub trait MemoryAccountant: Send + Sync {
+    fn on_alloc(&self, bytes: usize);
+    fn on_free(&self, bytes: usize);
+}
+
+/// A [`MemoryAccountant`] counting the bytes currently allocated, and the most bytes allocated at once.
+#[derive(Debug, Default)]
+pub struct CountingAccountant {
+    current: AtomicUsize,
+    peak: AtomicUsize,
+}
+
+impl CountingAccountant {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// The bytes allocated and not freed yet.
+    pub fn current(&self) -> usize {
+        self.current.load(Ordering::Relaxed)
+    }
+
+    /// The most bytes allocated at once.
+    pub fn peak(&self) -> usize {
+        self.peak.load(Ordering::Relaxed)
+    }
+}
+
+impl MemoryAccountant for CountingAccountant {
+    fn on_alloc(&self, bytes: usize) {
+        let current = self.current.fetch_add(bytes, Ordering::Relaxed) + bytes;
+        self.peak.fetch_max(current, Ordering::Relaxed);
+    }
+
+    fn on_free(&self, bytes: usize) {
+        self.current.fetch_sub(bytes, Ordering::Relaxed);
+    }
+}
+
+/// The bytes a sampler currently holds, returned by [`Sampler::stats`](crate::sampler::Sampler::stats).
+///
+/// They are counted whether or not a [`MemoryAccountant`] is set.
+#[derive(Debug, PartialEq, Clone, Copy, Eq, Default)]
+pub struct MemoryStats {
+    /// The stack arena, which is allocated once when the sampler is created.
+    pub arena_bytes: usize,
+    /// The current stacks.
+    pub stacks_bytes: usize,
+    /// The keys and the masks of the possible tokens cache.
+    pub cache_bytes: usize,
+    /// The terminals of the grammar, see [`Grammar::terminals_bytes`](crate::grammar::Grammar::terminals_bytes).
+    /// They are shared by the samplers of the grammar, so they are neither part of [`MemoryStats::total`]
+    /// nor reported to the accountant.
+    pub grammar_terminals_bytes: usize,
+}
+
+impl MemoryStats {
+    /// The bytes held by the sampler alone.
+    pub fn total(&self) -> usize {
+        self.arena_bytes + self.stacks_bytes + self.cache_bytes
+    }
+}
+
+/// The accountant of a [`SamplerConfig`](crate::sampler::SamplerConfig), where configs are equal when they share it.
+#[derive(Clone)]
+pub(crate) struct SharedAccountant(pub Arc<dyn MemoryAccountant>);
+
+impl PartialEq for SharedAccountant {
+    fn eq(&self, other: &Self) -> bool {
+        Arc::ptr_eq(&self.0, &other.0)
+    }
+}
+
+impl Eq for SharedAccountant {}
+
+impl fmt::Debug for SharedAccountant {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str("SharedAccountant")
+    }
+}
+
+/// The bytes held by a part of a sampler, reported to the accountant whenever they change.
+///
+/// A clone holds the same number of new bytes, and the bytes are reported as freed when it is dropped.
+#[derive(Debug, Default)]
+pub(crate) struct Tracked {
+    accountant: Option<SharedAccountant>,
+    bytes: usize,
+}
+
+impl Tracked {
+    pub fn new(accountant: Option<SharedAccountant>, bytes: usize) -> Self {
+        if let Some(accountant) = &accountant {
+            accountant.0.on_alloc(bytes);
+        }
+        Tracked { accountant, bytes }
+    }
+
+    pub fn bytes(&self) -> usize {
+        self.bytes
+    }
+
+    pub fn set(&mut self, bytes: usize) {
+        if let Some(accountant) = &self.accountant {
+            match bytes.cmp(&self.bytes) {
+                std::cmp::Ordering::Greater => accountant.0.on_alloc(bytes - self.bytes),
+                std::cmp::Ordering::Less => accountant.0.on_free(self.bytes - bytes),
+                std::cmp::Ordering::Equal => {}
+            }
+        }
+        self.bytes = bytes;
+    }
+
+    pub fn add(&mut self, bytes: usize) {
+        self.set(self.bytes + bytes);
+    }
+}
+
+impl Clone for Tracked {
+    fn clone(&self) -> Self {
+        Self::new(self.accountant.clone(), self.bytes)
+    }
+}
+
+impl Drop for Tracked {
+    fn drop(&mut self) {
+        self.set(0);
+    }
+}
@@ -0,0 +1,213 @@
+use anyhow::{anyhow, Error};
+use std::collections::BTreeMap;
+use std::fmt;
+use std::time::Duration;
+
+/// The metrics of a single call to [`crate::sampler::Sampler::all_possible_next_tokens`].
+#[derive(Debug, PartialEq, Clone, Copy, Eq)]
+pub struct StepMetrics {
+    /// The number of possible tokens. It is 0 when the sampler terminates at this step.
+    pub mask_size: usize,
+    /// Whether exactly one token is possible.
+    pub forced: bool,
+    /// Whether the sampler can terminate at this step.
+    pub end_eligible: bool,
+}
+
+/// How constrained a generation is, collected when [`crate::sampler::SamplerConfig::metrics`] is enabled.
+#[derive(Debug, PartialEq, Clone, Default)]
+pub struct GenerationMetrics {
+    /// The number of tokens in the vocabulary.
+    pub vocabulary_size: usize,
+    /// The metrics of every step since the sampler was created or reset.
+    pub steps: Vec<StepMetrics>,
+}
+
+impl GenerationMetrics {
+    pub(crate) fn new(vocabulary_size: usize) -> Self {
+        GenerationMetrics {
+            vocabulary_size,
+            steps: Vec::new(),
+        }
+    }
+
+    pub(crate) fn record(&mut self, mask_size: usize, end_eligible: bool) {
+        self.steps.push(StepMetrics {
+            mask_size,
+            forced: mask_size == 1,
+            end_eligible,
+        });
+    }
+
+    /// The number of steps where exactly one token is possible.
+    pub fn forced_steps(&self) -> usize {
+        self.steps.iter().filter(|x| x.forced).count()
+    }
+
+    /// The number of steps where the sampler can terminate.
+    pub fn end_eligible_steps(&self) -> usize {
+        self.steps.iter().filter(|x| x.end_eligible).count()
+    }
+
+    /// The mean number of possible tokens over the steps that do not terminate.
+    pub fn mean_mask_size(&self) -> f64 {
+        let masks = self.steps.iter().filter(|x| !x.end_eligible);
+        let count = masks.clone().count();
+        if count == 0 {
+            return 0.0;
+        }
+        masks.map(|x| x.mask_size as f64).sum::<f64>() / count as f64
+    }
+
+    /// The total entropy removed by the masks in bits,
+    /// assuming the model is uniform over the vocabulary at every step.
+    pub fn entropy_reduction_bits(&self) -> f64 {
+        let vocabulary_bits = (self.vocabulary_size as f64).log2();
+        self.steps
+            .iter()
+            .filter(|x| x.mask_size > 0)
+            .map(|x| vocabulary_bits - (x.mask_size as f64).log2())
+            .sum()
+    }
+
+    /// The number of non-terminating steps in each mask size bucket,
+    /// keyed by the smallest power of two that is no less than the mask size.
+    pub fn mask_size_histogram(&self) -> BTreeMap<usize, usize> {
+        let mut histogram = BTreeMap::new();
+        for step in self.steps.iter().filter(|x| !x.end_eligible) {
+            *histogram
+                .entry(step.mask_size.next_power_of_two())
+                .or_insert(0) += 1;
+        }
+        histogram
+    }
+}
+
+impl fmt::Display for GenerationMetrics {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        writeln!(f, "Steps: {}", self.steps.len())?;
+        writeln!(f, "Forced steps: {}", self.forced_steps())?;
+        writeln!(f, "End eligible steps: {}", self.end_eligible_steps())?;
+        writeln!(
+            f,
+            "Mean mask size: {:.2} / {}",
+            self.mean_mask_size(),
+            self.vocabulary_size
+        )?;
+        writeln!(
+            f,
+            "Entropy reduction: {:.2} bits",
+            self.entropy_reduction_bits()
+        )?;
+        write!(f, "Mask size histogram:")?;
+        for (bucket, count) in self.mask_size_histogram() {
+            write!(f, "\n  <= {bucket}: {count}")?;
+        }
+        Ok(())
+    }
+}
+
+/// Where the time of the last call to [`crate::sampler::Sampler::all_possible_next_tokens`] went,
+/// collected when [`crate::sampler::SamplerConfig::collect_timing`] is enabled.
+///
+/// The phases do not overlap, so their sum is slightly less than [`StepTiming::total`].
+#[derive(Debug, PartialEq, Clone, Copy, Eq, Default)]
+pub struct StepTiming {
+    pub total: Duration,
+    /// Accepting the input token.
+    pub accept: Duration,
+    /// Looking up and filling the possible tokens cache, which only happens in [`crate::sampler::CacheMode::Full`].
+    pub cache_lookup: Duration,
+    /// Adding the precomputed tokens of `<any!>`, `<except!(...)>` and `<any_except_bytes!(...)>`.
+    pub fast_path_union: Duration,
+    /// Matching the remaining tokens against the stacks.
+    pub scan: Duration,
+    /// The number of distinct pairs of a top item and a token in the scan. The stacks sharing a top item
+    /// are matched against each of its tokens in turn, until one of them accepts the token.
+    pub tokens_visited: usize,
+    /// The number of times a token is matched against a stack in the scan.
+    pub tokens_checked: usize,
+    /// The number of tokens found possible in the scan.
+    pub tokens_accepted: usize,
+    /// The number of tokens rejected by [`crate::sampler::SamplerConfig::signature_filter`] without being matched.
+    pub tokens_filtered: usize,
+    /// The number of tokens rejected by [`crate::sampler::SamplerConfig::length_bound`] without being matched.
+    pub tokens_too_long: usize,
+    /// The number of stacks allocated from the arena in the scan.
+    pub arena_allocations: usize,
+    /// The lookups of [`crate::sampler::SamplerConfig::stack_to_bytes_cache`] answered by the cache,
+    /// while accepting the input token and in the scan.
+    pub stack_to_bytes_cache_hits: usize,
+    /// The lookups of the stack to bytes cache that had to match the stack.
+    pub stack_to_bytes_cache_misses: usize,
+}
+
+impl StepTiming {
+    /// The sum of the phases.
+    pub fn phases(&self) -> Duration {
+        self.accept + self.cache_lookup + self.fast_path_union + self.scan
+    }
+}
+
+impl fmt::Display for StepTiming {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        writeln!(f, "Total: {:?}", self.total)?;
+        writeln!(f, "  Accept: {:?}", self.accept)?;
+        writeln!(f, "  Cache lookup: {:?}", self.cache_lookup)?;
+        writeln!(f, "  Fast path union: {:?}", self.fast_path_union)?;
+        writeln!(f, "  Scan: {:?}", self.scan)?;
+        writeln!(f, "    Tokens visited: {}", self.tokens_visited)?;
+        writeln!(f, "    Tokens checked: {}", self.tokens_checked)?;
+        writeln!(f, "    Tokens accepted: {}", self.tokens_accepted)?;
+        writeln!(f, "    Tokens filtered: {}", self.tokens_filtered)?;
+        writeln!(f, "    Tokens too long: {}", self.tokens_too_long)?;
+        writeln!(f, "    Arena allocations: {}", self.arena_allocations)?;
+        write!(
+            f,
+            "  Stack to bytes cache: {} hits, {} misses",
+            self.stack_to_bytes_cache_hits, self.stack_to_bytes_cache_misses
+        )
+    }
+}
+
+/// How much of a model's probability distribution the possible tokens admit, see [`crate::sampler::Sampler::admitted`].
+#[derive(Debug, PartialEq, Clone, Copy, Default)]
+pub struct AdmittedMass {
+    /// The sum of the probabilities of the possible tokens. It is 0 when the sampler terminates.
+    pub mass: f32,
+    /// The entropy in bits of the distribution renormalized over the possible tokens,
+    /// or 0 when their mass is 0.
+    pub entropy_bits: f32,
+}
+
+impl AdmittedMass {
+    /// Sum `probs`, indexed by token id, over `token_ids`.
+    pub(crate) fn new(
+        token_ids: impl Iterator<Item = usize>,
+        probs: &[f32],
+    ) -> Result<Self, Error> {
+        let mut allowed = vec![];
+        for id in token_ids {
+            let p = *probs.get(id).ok_or_else(|| {
+                anyhow!(
+                    "Token id {id} is possible, but only {} probabilities are given.",
+                    probs.len()
+                )
+            })?;
+            allowed.push(p as f64);
+        }
+        let mass: f64 = allowed.iter().sum();
+        let entropy_bits = match mass > 0.0 {
+            true => -allowed
+                .iter()
+                .filter(|p| **p > 0.0)
+                .map(|p| p / mass * (p / mass).log2())
+                .sum::<f64>(),
+            false => 0.0,
+        };
+        Ok(AdmittedMass {
+            mass: mass as f32,
+            entropy_bits: entropy_bits as f32,
+        })
+    }
+}
@@ -0,0 +1,16 @@
+//! The supported public surface for most users, imported with `use bnf_sampler::prelude::*;`.
+//!
+//! The other items stay available through their modules, and `tests/public_api.txt` records every public item,
+//! so changes to the surface are intentional.
+pub use crate::grammar::{Grammar, GrammarBuildOptions, GrammarError};
+pub use crate::mask::TokenMask;
+pub use crate::sampler::{
+    AcceptTokenResult, AmbiguityError, CacheMode, OwnedPossibleTokensResult, PossibleTokensResult,
+    Sampler, SamplerConfig, VisitOutcome,
+};
+pub use crate::utils::{
+    read_rwkv_world_vocab, read_rwkv_world_vocab_from_reader,
+    read_rwkv_world_vocab_with_max_token_bytes,
+};
+pub use crate::vocabulary::{U8ArrayWrapper, Vocabulary, VocabularyBuilder};
+pub use anyhow::Error;
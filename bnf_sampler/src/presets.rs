@@ -0,0 +1,21 @@
+use crate::grammar::Grammar;
+use crate::json_schema;
+use crate::sampler::{Sampler, SamplerConfig};
+use crate::vocabulary::Vocabulary;
+use anyhow::Error;
+use serde_json::Value;
+use std::sync::Arc;
+
+/// Create a sampler whose output is a compact JSON document valid against the JSON Schema `schema`.
+///
+/// The sampler reaches [`crate::sampler::PossibleTokensResult::End`] once the document is complete.
+/// See [`json_schema::to_bnf`] for the supported keywords.
+pub fn constrained_json(
+    schema: &Value,
+    vocabulary: Arc<Vocabulary>,
+    config: SamplerConfig,
+) -> Result<Sampler, Error> {
+    let bnf = json_schema::to_bnf(schema)?;
+    let grammar = Grammar::new(&bnf, vocabulary.clone(), 0)?;
+    Sampler::with_config(grammar, "start".to_string(), vocabulary, config)
+}
@@ -0,0 +1,67 @@
+//! One-shot helpers that build a grammar and a sampler with default settings and throw them away afterwards.
+//!
+//! They are convenient for scripting and testing, but copy the vocabulary and rebuild the grammar on every call.
+use crate::grammar::Grammar;
+use crate::mask::TokenMask;
+use crate::sampler::{AcceptTokenResult, CacheMode, PossibleTokensResult, Sampler, SamplerConfig};
+use crate::vocabulary::Vocabulary;
+use anyhow::{anyhow, Error};
+use std::sync::Arc;
+
+fn new_sampler(schema: &str, start: &str, vocabulary: &Vocabulary) -> Result<Sampler, Error> {
+    let vocabulary = Arc::new(vocabulary.clone());
+    let grammar = Grammar::new(schema, vocabulary.clone(), 0)?;
+    Sampler::with_config(
+        grammar,
+        start.to_string(),
+        vocabulary,
+        SamplerConfig::new().cache_mode(CacheMode::None),
+    )
+}
+
+/// Get all the token ids that can be the first token of a sentence.
+///
+/// # Arguments
+///
+/// * `schema` - the BNF schema in text format
+/// * `start` - the starting point of the BNF schema
+/// * `vocabulary` - the vocabulary
+pub fn allowed_first_tokens(
+    schema: &str,
+    start: &str,
+    vocabulary: &Vocabulary,
+) -> Result<TokenMask, Error> {
+    let mut sampler = new_sampler(schema, start, vocabulary)?;
+    match sampler.all_possible_next_tokens(None)? {
+        PossibleTokensResult::Continue(token_ids) => Ok(token_ids.clone()),
+        PossibleTokensResult::End => Ok(TokenMask::new()),
+        PossibleTokensResult::InputTokenRejected => {
+            Err(anyhow!("The sampler rejects the initial state."))
+        }
+    }
+}
+
+/// Check whether the token ids form a complete sentence of the BNF schema.
+///
+/// # Arguments
+///
+/// * `schema` - the BNF schema in text format
+/// * `start` - the starting point of the BNF schema
+/// * `vocabulary` - the vocabulary
+/// * `token_ids` - the token ids to check
+pub fn accepts(
+    schema: &str,
+    start: &str,
+    vocabulary: &Vocabulary,
+    token_ids: &[u32],
+) -> Result<bool, Error> {
+    let mut sampler = new_sampler(schema, start, vocabulary)?;
+    for (i, token_id) in token_ids.iter().enumerate() {
+        match sampler.accept_a_token(Some(*token_id))? {
+            AcceptTokenResult::Continue => {}
+            AcceptTokenResult::End => return Ok(i == token_ids.len() - 1),
+            AcceptTokenResult::Failed => return Ok(false),
+        }
+    }
+    Ok(false)
+}
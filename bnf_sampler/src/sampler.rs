@@ -1,22 +1,47 @@
+use crate::bundle::TraceBundle;
+use crate::cache::hash_key;
+use crate::cache::HashCache;
+use crate::grammar::format_expression;
 use crate::grammar::Grammar;
 use crate::grammar::SimplifiedExpressions;
 use crate::grammar::U8Term;
+use crate::mask::MaskStamp;
+use crate::mask::TokenMask;
+use crate::memory::MemoryAccountant;
+use crate::memory::MemoryStats;
+use crate::memory::SharedAccountant;
+use crate::memory::Tracked;
+use crate::metrics::AdmittedMass;
+use crate::metrics::GenerationMetrics;
+use crate::metrics::StepTiming;
+use crate::signature::SignatureFilter;
 use crate::stack::BufferArena;
 use crate::stack::FixedBuffer;
-use crate::trie::TerminalsTrie;
+use crate::trace::SplitBranch;
+use crate::trace::SplitTracer;
+use crate::trace::TraceReport;
 use crate::trie::TerminalsTrieIter;
 use crate::trie::TrieNodeID;
+use crate::utils;
 use crate::utils::NonterminalID;
-use crate::utils::U8ArrayWrapper;
+use crate::utils::TerminalID;
+use crate::vocabulary::U8ArrayWrapper;
 use crate::vocabulary::Vocabulary;
 use anyhow::anyhow;
+use anyhow::bail;
+use anyhow::ensure;
 use anyhow::Error;
 use anyhow::Ok;
-use bit_set::BitSet;
+use itertools::Itertools;
 use qp_trie::Trie;
 use rustc_hash::FxHashMap;
 use rustc_hash::FxHashSet;
-use std::collections::hash_map::Entry;
+use rustc_hash::FxHasher;
+use serde_json::json;
+use serde_json::Value;
+use std::hash::Hash;
+use std::hash::Hasher;
+use std::path::Path;
 use std::ptr::NonNull;
 use std::sync::Arc;
 use std::time::Instant;
@@ -24,29 +49,450 @@ use std::vec;
 
 const INVALID_INDEX: i32 = -1;
 
-unsafe impl Send for Sampler {}
-
-unsafe impl Sync for Sampler {}
-
 #[derive(PartialEq, Clone, Debug, Copy, Eq, Hash)]
-enum StackItem {
+pub(crate) enum StackItem {
     Nonterminal(NonterminalID),
-    Terminal(*const [u8]),
+    /// An interned terminal and the index of its first unmatched byte.
+    Terminal(TerminalID, usize),
     Terminals(TrieNodeID),
 }
+/// A stack ending with the nonterminal to expand, and the remaining bytes.
+type StackToBytesKey = (Box<[Option<StackItem>]>, Box<[u8]>);
+/// Whether a stack matches the remaining bytes, see [`SamplerConfig::stack_to_bytes_cache`].
+///
+/// It lives for a whole scan or a whole pass over the stacks, while the arena is cleared after every token,
+/// so the keys own the stack ending with the nonterminal to expand and the remaining bytes.
+/// The hash is computed from the borrowed key, and the owned key is only built on a miss.
+#[derive(Default)]
+struct StackToBytesCache {
+    entries: HashCache<StackToBytesKey, bool>,
+    hits: usize,
+    misses: usize,
+}
 #[derive(Clone, Debug)]
 pub struct Sampler {
     stacks: Vec<Vec<StackItem>>,
     grammar: Arc<Grammar>,
     tokens_buffer: Vec<(U8ArrayWrapper, u32)>,
-    vocabulary: Arc<Vocabulary>,
+    pub(crate) vocabulary: Arc<Vocabulary>,
     stack_arena: BufferArena<StackItem>,
-    stacks_to_token_ids: FxHashMap<Vec<Vec<StackItem>>, BitSet<u32>>,
+    stacks_to_token_ids: HashCache<Vec<Vec<StackItem>>, TokenMask>,
     start_nonterminal: String,
-    token_ids: BitSet<u32>,
+    /// The possible tokens in the internal ids of the vocabulary, see [`Vocabulary::is_remapped`].
+    token_ids: TokenMask,
+    /// The possible tokens translated into token ids when the vocabulary is remapped.
+    external_token_ids: TokenMask,
+    config: SamplerConfig,
+    max_token_len: usize,
+    min_terminal_lens: FxHashMap<TrieNodeID, usize>,
+    metrics: GenerationMetrics,
+    stack_delta: StackDelta,
+    /// The accepted token ids, or `None` once bytes that are not a whole token are accepted.
+    token_history: Option<Vec<u32>>,
+    /// The token history before the first rejected token and that token, see [`Sampler::trace_bundle`].
+    rejected: Option<(Vec<u32>, u32)>,
+    /// The stack to bytes cache hits and misses of the last accepted bytes, for [`StepTiming`].
+    accept_cache_counts: (usize, usize),
+    timing: Option<StepTiming>,
+    /// Built on the first scan, so samplers whose possible tokens are always cached never walk the terminals trie.
+    signature_filter: Option<SignatureFilter>,
+    /// Counts the accepted tokens or bytes and the resets, for the [`MaskStamp`] of the owned masks.
+    step: u64,
+    /// The bytes of `stacks` and `stacks_to_token_ids`, see [`Sampler::stats`].
+    stacks_memory: Tracked,
+    cache_memory: Tracked,
+    /// The bytes accepted since the sampler was created or reset, for the [`SamplerContext`].
+    accepted_bytes: Vec<u8>,
+}
+/// Controls which memoization the sampler performs when computing possible tokens.
+///
+/// The token sets of `<any!>` and `<except!(excepted_literals)>` are precomputed when the grammar is built
+/// and are always used regardless of the mode.
+#[derive(Debug, PartialEq, Clone, Copy, Eq, Default)]
+pub enum CacheMode {
+    /// Memoize the possible tokens of every set of stacks visited, and use the per-step stack to bytes cache.
+    /// Revisiting a state costs one hash lookup, but the memory grows with the number of distinct states visited.
+    #[default]
+    Full,
+    /// Skip the memoization of possible tokens per set of stacks, so the possible tokens are recomputed on every step.
+    /// The memory used by the sampler stays bounded, at the cost of recomputing masks for revisited states.
+    TrieNodeOnly,
+    /// Like `TrieNodeOnly`, and also skip the per-step stack to bytes cache.
+    /// This uses the least memory and is the slowest for grammars with long alternatives.
+    None,
+}
+/// What the sampler does when the stacks matching a token compete, see [`SamplerConfig::ambiguity_policy`].
+///
+/// The stacks matching a token compete unless one of them is a subsequence of every other one.
+/// The others then only add the optional repetitions left to match, like the second alternative of
+/// `<digits>::=<digit>|<digit><digits>` after a digit.
+/// The stacks whose top terminal is partially matched are left out, since the next bytes decide between them,
+/// like between `'{}'` and `'{'<members>'}'` after `{`.
+#[derive(Debug, PartialEq, Clone, Copy, Eq, Default)]
+pub enum AmbiguityPolicy {
+    /// Track every competing stack.
+    #[default]
+    Track,
+    /// Reject the token with an [`AmbiguityError`].
+    Error,
+    /// Keep the first stack in the order of the formatted stacks, along with the next stacks that do not compete with
+    /// the kept ones.
+    PreferFirst,
+}
+
+/// The error returned when a token is matched by competing stacks under [`AmbiguityPolicy::Error`].
+///
+/// It can be retrieved with [`anyhow::Error::downcast_ref`].
+#[derive(Debug, PartialEq, Clone, Eq)]
+pub struct AmbiguityError {
+    /// The bytes of the token.
+    pub token: Vec<u8>,
+    /// The top items of the competing stacks, formatted like the stacks in [`Sampler`]'s `Display`.
+    pub top_items: Vec<String>,
+}
+
+impl std::fmt::Display for AmbiguityError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(
+            f,
+            "The token {:?} is matched by {} competing stacks, whose top items are {}.",
+            utils::escape_invalid_utf8(&self.token),
+            self.top_items.len(),
+            self.top_items.join(", ")
+        )
+    }
+}
+
+impl std::error::Error for AmbiguityError {}
+
+/// The error of [`Sampler::assert_mask_fresh`] when a mask was computed for another state of the sampler.
+///
+/// It can be retrieved with [`anyhow::Error::downcast_ref`].
+#[derive(Debug, PartialEq, Clone, Eq)]
+pub struct StaleMaskError {
+    /// The stamp of the mask, or `None` if it is not returned by [`Sampler::next_mask`].
+    pub mask: Option<MaskStamp>,
+    /// The current step of the sampler.
+    pub current_step: u64,
+}
+
+impl std::fmt::Display for StaleMaskError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self.mask {
+            Some(mask) if mask.step != self.current_step => write!(
+                f,
+                "The mask is computed for step {}, but the sampler is at step {}.",
+                mask.step, self.current_step
+            ),
+            Some(_) => write!(
+                f,
+                "The mask is computed for another state of the sampler at step {}.",
+                self.current_step
+            ),
+            None => write!(
+                f,
+                "The mask has no stamp, so it is not known which step it is computed for. Use the masks returned by Sampler::next_mask."
+            ),
+        }
+    }
+}
+
+impl std::error::Error for StaleMaskError {}
+
+/// Where an invalid stack item of an [`InvariantViolation`] is.
+#[derive(Debug, PartialEq, Clone, Copy, Eq)]
+pub enum StackLocation {
+    /// The stack with this index among the current stacks of the sampler.
+    Stacks(usize),
+    /// A stack of a key of the possible tokens cache.
+    Cache,
+}
+
+/// A broken invariant of a sampler found by [`Sampler::validate_invariants`].
+#[derive(Debug, PartialEq, Clone, Eq)]
+pub enum InvariantViolation {
+    /// A stack item refers to a nonterminal the grammar does not define.
+    UnknownNonterminal { location: StackLocation, id: usize },
+    /// A stack item refers to a node outside the terminals trie.
+    UnknownTrieNode { location: StackLocation, id: usize },
+    /// A stack item refers to a terminal the grammar has not interned.
+    UnknownTerminal { location: StackLocation, id: usize },
+    /// A partially matched terminal starts at or after its end.
+    TerminalOffsetOutOfRange {
+        location: StackLocation,
+        start: usize,
+        len: usize,
+    },
+    /// The temporary stack arena still holds items between calls.
+    ArenaInUse(usize),
+    /// The token history holds a token id that is not in the vocabulary.
+    UnknownHistoryToken(u32),
+}
+
+impl std::fmt::Display for InvariantViolation {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        let location = |location: &StackLocation| match location {
+            StackLocation::Stacks(i) => format!("stack {i}"),
+            StackLocation::Cache => "a cached stack".to_string(),
+        };
+        match self {
+            InvariantViolation::UnknownNonterminal { location: l, id } => {
+                write!(
+                    f,
+                    "{} refers to the undefined nonterminal {id}.",
+                    location(l)
+                )
+            }
+            InvariantViolation::UnknownTrieNode { location: l, id } => {
+                write!(
+                    f,
+                    "{} refers to the trie node {id} outside the terminals trie.",
+                    location(l)
+                )
+            }
+            InvariantViolation::UnknownTerminal { location: l, id } => {
+                write!(
+                    f,
+                    "{} refers to the terminal {id} that is not interned.",
+                    location(l)
+                )
+            }
+            InvariantViolation::TerminalOffsetOutOfRange {
+                location: l,
+                start,
+                len,
+            } => write!(
+                f,
+                "{} matches a terminal of {len} bytes from byte {start}.",
+                location(l)
+            ),
+            InvariantViolation::ArenaInUse(items) => {
+                write!(
+                    f,
+                    "The stack arena still holds {items} items between calls."
+                )
+            }
+            InvariantViolation::UnknownHistoryToken(id) => {
+                write!(
+                    f,
+                    "The token history holds the token id {id} not in the vocabulary."
+                )
+            }
+        }
+    }
+}
+
+/// A corruption of the internals of a sampler for [`Sampler::corrupt`], to test [`Sampler::validate_invariants`].
+#[cfg(feature = "fixtures")]
+#[doc(hidden)]
+#[derive(Debug, PartialEq, Clone, Copy, Eq)]
+pub enum Corruption {
+    UnknownNonterminal,
+    UnknownTrieNode,
+    UnknownTerminal,
+    TerminalOffsetOutOfRange,
+    CachedUnknownNonterminal,
+    ArenaInUse,
+    UnknownHistoryToken,
+}
+
+/// The configuration of a sampler.
+#[derive(Debug, PartialEq, Clone, Eq)]
+pub struct SamplerConfig {
+    stack_arena_capacity: Option<usize>,
     stack_to_bytes_cache_enabled: bool,
+    cache_mode: CacheMode,
+    cache_key_depth: Option<usize>,
+    metrics_enabled: bool,
+    timing_enabled: bool,
+    signature_filter_enabled: bool,
+    length_bound_enabled: bool,
+    ambiguity_policy: AmbiguityPolicy,
+    aliases_enabled: bool,
+    memory_accountant: Option<SharedAccountant>,
+    post_mask_hook: Option<SharedHook>,
 }
-#[derive(Debug, PartialEq, Clone, Copy)]
+
+impl Default for SamplerConfig {
+    fn default() -> Self {
+        SamplerConfig {
+            stack_arena_capacity: None,
+            stack_to_bytes_cache_enabled: true,
+            cache_mode: CacheMode::Full,
+            cache_key_depth: None,
+            metrics_enabled: false,
+            timing_enabled: false,
+            signature_filter_enabled: true,
+            length_bound_enabled: true,
+            ambiguity_policy: AmbiguityPolicy::Track,
+            aliases_enabled: false,
+            memory_accountant: None,
+            post_mask_hook: None,
+        }
+    }
+}
+
+impl SamplerConfig {
+    pub fn new() -> Self {
+        Self::default()
+    }
+    /// Set the arena capacity. This value depends on how long and complex the BNF schema is, and the maximum token length in bytes.
+    ///
+    /// When it is not set or set to 0, [`estimate_stack_arena_capacity`] is used.
+    pub fn stack_arena_capacity(mut self, stack_arena_capacity: usize) -> Self {
+        self.stack_arena_capacity = Some(stack_arena_capacity);
+        self
+    }
+    /// Enable or disable the stack to bytes cache, which speeds up certain types of except!(excepted_literals) when the BNF schema is not very long.
+    pub fn stack_to_bytes_cache(mut self, enabled: bool) -> Self {
+        self.stack_to_bytes_cache_enabled = enabled;
+        self
+    }
+    /// Set the cache mode. See [`CacheMode`] for the memory/latency tradeoff of each mode.
+    pub fn cache_mode(mut self, cache_mode: CacheMode) -> Self {
+        self.cache_mode = cache_mode;
+        self
+    }
+
+    /// Key the possible tokens cache on at most the top `depth` items of each stack.
+    ///
+    /// A stack is only truncated when no token in the vocabulary is long enough to consume the items above the cut,
+    /// so the cached possible tokens are always the same as those computed from the full stacks.
+    /// `None` keys the cache on the full stacks.
+    pub fn cache_key_depth(mut self, depth: Option<usize>) -> Self {
+        self.cache_key_depth = depth;
+        self
+    }
+
+    /// Enable or disable the collection of [`GenerationMetrics`], which counts the possible tokens at every step.
+    pub fn metrics(mut self, enabled: bool) -> Self {
+        self.metrics_enabled = enabled;
+        self
+    }
+
+    /// Enable or disable the collection of a [`StepTiming`] for every call to [`Sampler::all_possible_next_tokens`].
+    /// When disabled, no time is measured.
+    pub fn collect_timing(mut self, enabled: bool) -> Self {
+        self.timing_enabled = enabled;
+        self
+    }
+
+    /// Enable or disable the byte signature filter, which rejects a token without matching it
+    /// when the token contains a byte the stack cannot match. It never changes the possible tokens.
+    pub fn signature_filter(mut self, enabled: bool) -> Self {
+        self.signature_filter_enabled = enabled;
+        self
+    }
+
+    /// Enable or disable the length bound, which rejects a token without matching it when the token is longer
+    /// than the most bytes the stack can still match, e.g. a long identifier token where only a date can follow.
+    /// It never changes the possible tokens, and a stack with a recursive nonterminal rejects nothing.
+    pub fn length_bound(mut self, enabled: bool) -> Self {
+        self.length_bound_enabled = enabled;
+        self
+    }
+
+    /// Set what the sampler does when the stacks matching a token compete. See [`AmbiguityPolicy`].
+    pub fn ambiguity_policy(mut self, ambiguity_policy: AmbiguityPolicy) -> Self {
+        self.ambiguity_policy = ambiguity_policy;
+        self
+    }
+
+    /// Accept an aliased token id as the bytes of the id it is an alias of, and include the aliases of the possible tokens
+    /// in the possible tokens. See [`Vocabulary::alias_token`].
+    pub fn treat_aliased_ids_as_bytes(mut self, enabled: bool) -> Self {
+        self.aliases_enabled = enabled;
+        self
+    }
+
+    /// Report the bytes the sampler allocates and frees to `accountant`, see [`crate::memory`].
+    /// Samplers cloned from the sampler report to the same accountant.
+    ///
+    /// The accountant is not part of [`SamplerConfig::to_json`], and configs are only equal when they share it.
+    pub fn memory_accountant(mut self, accountant: Arc<dyn MemoryAccountant>) -> Self {
+        self.memory_accountant = Some(SharedAccountant(accountant));
+        self
+    }
+
+    /// Restrict the possible tokens further with `hook`, e.g. with a semantic validator of the bytes generated so far.
+    ///
+    /// The hook is called with a copy of the possible tokens of the grammar whenever [`Sampler::all_possible_next_tokens`],
+    /// [`Sampler::next_mask`] or [`Sampler::visit_allowed_tokens`] returns them, after they are computed
+    /// or retrieved from the cache. What the hook removes is never cached, so it is called again at every step
+    /// and may depend on state outside the sampler. Accepting a token the hook removed is not an error,
+    /// and the metrics count the possible tokens of the grammar.
+    ///
+    /// The hook is not part of [`SamplerConfig::to_json`], and configs are only equal when they share it.
+    pub fn post_mask_hook(mut self, hook: Box<PostMaskHook>) -> Self {
+        self.post_mask_hook = Some(SharedHook(Arc::from(hook)));
+        self
+    }
+
+    /// The configuration as a JSON object, read back by [`SamplerConfig::from_json`].
+    pub fn to_json(&self) -> Value {
+        json!({
+            "stack_arena_capacity": self.stack_arena_capacity,
+            "stack_to_bytes_cache": self.stack_to_bytes_cache_enabled,
+            "cache_mode": format!("{:?}", self.cache_mode),
+            "cache_key_depth": self.cache_key_depth,
+            "metrics": self.metrics_enabled,
+            "collect_timing": self.timing_enabled,
+            "signature_filter": self.signature_filter_enabled,
+            "length_bound": self.length_bound_enabled,
+            "ambiguity_policy": format!("{:?}", self.ambiguity_policy),
+            "treat_aliased_ids_as_bytes": self.aliases_enabled,
+        })
+    }
+
+    /// Read the configuration written by [`SamplerConfig::to_json`]. Missing keys keep their default values.
+    pub fn from_json(value: &Value) -> Result<Self, Error> {
+        let mut config = Self::default();
+        let object = value
+            .as_object()
+            .ok_or_else(|| anyhow!("The sampler config {value} is not an object."))?;
+        for (key, value) in object {
+            let invalid = || anyhow!("The sampler config {key} has the invalid value {value}.");
+            let flag = || value.as_bool().ok_or_else(invalid);
+            let size = || match value {
+                Value::Null => Ok(None),
+                x => x.as_u64().map(|x| Some(x as usize)).ok_or_else(invalid),
+            };
+            match key.as_str() {
+                "stack_arena_capacity" => config.stack_arena_capacity = size()?,
+                "stack_to_bytes_cache" => config.stack_to_bytes_cache_enabled = flag()?,
+                "cache_mode" => {
+                    config.cache_mode = match value.as_str() {
+                        Some("Full") => CacheMode::Full,
+                        Some("TrieNodeOnly") => CacheMode::TrieNodeOnly,
+                        Some("None") => CacheMode::None,
+                        _ => return Err(invalid()),
+                    }
+                }
+                "cache_key_depth" => config.cache_key_depth = size()?,
+                "metrics" => config.metrics_enabled = flag()?,
+                "collect_timing" => config.timing_enabled = flag()?,
+                "signature_filter" => config.signature_filter_enabled = flag()?,
+                "length_bound" => config.length_bound_enabled = flag()?,
+                "ambiguity_policy" => {
+                    config.ambiguity_policy = match value.as_str() {
+                        Some("Track") => AmbiguityPolicy::Track,
+                        Some("Error") => AmbiguityPolicy::Error,
+                        Some("PreferFirst") => AmbiguityPolicy::PreferFirst,
+                        _ => return Err(invalid()),
+                    }
+                }
+                "treat_aliased_ids_as_bytes" => config.aliases_enabled = flag()?,
+                _ => bail!("The sampler config has the unknown key {key}."),
+            }
+        }
+        Ok(config)
+    }
+
+    fn stack_to_bytes_cache_enabled(&self) -> bool {
+        self.stack_to_bytes_cache_enabled && self.cache_mode != CacheMode::None
+    }
+}
+#[derive(Debug, PartialEq, Clone, Copy, Eq)]
 pub enum AcceptTokenResult {
     Continue,
     End,
@@ -55,12 +501,127 @@ pub enum AcceptTokenResult {
 #[derive(Debug, PartialEq, Clone, Eq)]
 pub enum PossibleTokensResult<'a> {
     /// contains all possible token ids
-    Continue(&'a BitSet<u32>),
+    Continue(&'a TokenMask),
     /// the sampler successfully terminates
     End,
     InputTokenRejected,
 }
 
+impl PossibleTokensResult<'_> {
+    /// Copy the possible tokens out of the sampler, see [`Sampler::next_mask`].
+    pub fn into_owned(self) -> OwnedPossibleTokensResult {
+        match self {
+            PossibleTokensResult::Continue(token_ids) => {
+                OwnedPossibleTokensResult::Continue(Arc::new(token_ids.clone()))
+            }
+            PossibleTokensResult::End => OwnedPossibleTokensResult::End,
+            PossibleTokensResult::InputTokenRejected => {
+                OwnedPossibleTokensResult::InputTokenRejected
+            }
+        }
+    }
+}
+
+/// The outcome of [`Sampler::next_mask`], which does not borrow the sampler.
+#[derive(Debug, PartialEq, Clone, Eq)]
+pub enum OwnedPossibleTokensResult {
+    /// contains all possible token ids
+    Continue(Arc<TokenMask>),
+    /// the sampler successfully terminates
+    End,
+    InputTokenRejected,
+}
+
+/// The outcome of [`Sampler::visit_allowed_tokens`].
+#[derive(Debug, PartialEq, Clone, Copy, Eq)]
+pub enum VisitOutcome {
+    /// The possible tokens are visited. `cached` is whether they come from the possible tokens cache.
+    Continue {
+        cached: bool,
+    },
+    /// The sampler successfully terminates, and no token is visited.
+    End,
+    InputTokenRejected,
+}
+
+/// How the number of stacks changed while the sampler accepted the last token.
+#[derive(Debug, PartialEq, Clone, Copy, Eq, Default)]
+pub struct StackDelta {
+    /// The number of stacks before the token.
+    pub before: usize,
+    /// The number of stacks created by matching the token's bytes.
+    pub created: usize,
+    /// The number of stacks before the token that cannot match the token's bytes.
+    pub pruned: usize,
+    /// The number of stacks after the token, once their tops are expanded to terminals.
+    pub after: usize,
+}
+
+/// The state of the sampler passed to the [`SamplerConfig::post_mask_hook`].
+#[derive(Debug, PartialEq, Clone, Eq)]
+pub struct SamplerContext<'a> {
+    /// The bytes accepted since the sampler was created or reset.
+    pub accepted_bytes: &'a [u8],
+    /// The step of the mask, like [`MaskStamp::step`].
+    pub step: u64,
+    pub region_kind: RegionKind,
+}
+
+/// A function restricting the possible tokens further, see [`SamplerConfig::post_mask_hook`].
+pub type PostMaskHook = dyn Fn(&SamplerContext, &mut TokenMask) + Send + Sync;
+
+/// The hook of a [`SamplerConfig`], where configs are equal when they share it.
+#[derive(Clone)]
+struct SharedHook(Arc<PostMaskHook>);
+
+impl PartialEq for SharedHook {
+    fn eq(&self, other: &Self) -> bool {
+        Arc::ptr_eq(&self.0, &other.0)
+    }
+}
+
+impl Eq for SharedHook {}
+
+impl std::fmt::Debug for SharedHook {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.write_str("SharedHook")
+    }
+}
+
+/// Whether the sampler is generating free text or following the structure of the grammar.
+#[derive(Debug, PartialEq, Clone, Eq, Hash)]
+pub enum RegionKind {
+    /// No stack is waiting for a token of a `<any!>`, `<except!(...)>` or `<any_except_bytes!(...)>` nonterminal.
+    Structured,
+    /// Every stack is waiting for a token of the same `<any!>`, `<except!(...)>` or `<any_except_bytes!(...)>` nonterminal.
+    TokenSet { nonterminal: String },
+    /// Some stacks are waiting for such tokens and the others are not, or they wait for different nonterminals.
+    Mixed,
+}
+
+/// The outcome of [`Sampler::fast_forward`].
+#[derive(Debug, PartialEq, Clone, Copy, Eq)]
+pub struct FastForwardResult {
+    /// The result of accepting the last token, or of the current state when no token is accepted.
+    pub result: AcceptTokenResult,
+    /// Whether the recorded history does not match, so the sampler is reset and the whole history is replayed.
+    /// It is a warning that the caller passes the wrong history or `previous_len`, which makes every step O(n).
+    pub replayed: bool,
+}
+
+/// The outcome of [`Sampler::accept_closest`] and [`Sampler::accept_nearest_token`].
+#[derive(Debug, PartialEq, Clone, Copy)]
+pub struct ClosestAcceptResult {
+    /// The result of accepting the legal part of the token, or the substituted token.
+    pub result: AcceptTokenResult,
+    /// The number of leading bytes of the token that are kept.
+    pub accepted_bytes: usize,
+    /// The number of trailing bytes of the token that are dropped.
+    pub dropped_bytes: usize,
+    /// The allowed token accepted in place of the rejected token, if any.
+    pub substituted_token_id: Option<u32>,
+}
+
 #[derive(Debug)]
 enum BytesMatchResults {
     Failed,
@@ -93,21 +654,23 @@ impl<'a> BufferOrTreeIter<'a> {
     pub fn new(
         tokens_buffer: &'a [(U8ArrayWrapper, u32)],
         tokens_tree: &'a Trie<U8ArrayWrapper, u32>,
-        trie: &'a TerminalsTrie,
+        grammar: &'a Grammar,
         current_top: StackItem,
     ) -> Self {
+        let trie = &grammar.terminals_trie;
         let tokens_buffer_iter = match current_top {
-            StackItem::Terminal(terminal) => TokensIterType::SinglePrefix(
-                tokens_tree.iter_prefix(&unsafe { &*terminal }[..1]),
+            StackItem::Terminal(terminal, start) => TokensIterType::SinglePrefix(
+                tokens_tree.iter_prefix(&grammar.terminals.get(terminal)[start..start + 1]),
             ),
             StackItem::Terminals(node_id) => {
                 let node = trie.get(node_id);
                 if node.children.len() > (u8::MAX / 2).into() {
                     TokensIterType::Flat(tokens_buffer.iter())
                 } else {
-                    TokensIterType::MultiplePrefixs((trie.iter(node_id), None))
+                    TokensIterType::MultiplePrefixs((trie.iter(node_id, &grammar.terminals), None))
                 }
             }
+            // The top of a stack is always expanded to terminals before tokens are iterated.
             StackItem::Nonterminal(_) => panic!("No nonterminals should be here."),
         };
         BufferOrTreeIter {
@@ -129,26 +692,18 @@ impl<'a> Iterator for BufferOrTreeIter<'a> {
             TokensIterType::SinglePrefix(trie_iter) => {
                 result = trie_iter.next();
             }
-            TokensIterType::MultiplePrefixs((keys, trie_iter)) => match trie_iter {
-                None => {
-                    result = keys.next().and_then(|key| {
-                        let mut iter = self
-                            .tokens_tree
-                            .iter_prefix(&key[..1]);
-                        let temp = iter.next();
-                        *trie_iter = Some(iter);
-                        temp
-                    });
+            TokensIterType::MultiplePrefixs((keys, trie_iter)) => loop {
+                if let Some(item) = trie_iter.as_mut().and_then(|x| x.next()) {
+                    result = Some(item);
+                    break;
                 }
-                Some(trie_iter) => {
-                    result = trie_iter.next().or_else(|| {
-                        keys.next().and_then(|key| {
-                            *trie_iter = self
-                                .tokens_tree
-                                .iter_prefix(&key[..1]);
-                            trie_iter.next()
-                        })
-                    });
+                // No token may start with the first byte of a terminal, so the next terminals are tried.
+                match keys.next() {
+                    Some(key) => *trie_iter = Some(self.tokens_tree.iter_prefix(&key[..1])),
+                    None => {
+                        result = None;
+                        break;
+                    }
                 }
             },
         };
@@ -156,24 +711,150 @@ impl<'a> Iterator for BufferOrTreeIter<'a> {
     }
 }
 
+const MIN_ESTIMATED_ARENA_CAPACITY: usize = 1024;
+const MAX_ESTIMATED_ARENA_CAPACITY: usize = 1024 * 1024 * 16;
+
+/// The bytes of the buffers of `stacks`, see [`Sampler::stats`].
+fn stacks_bytes(stacks: &Vec<Vec<StackItem>>) -> usize {
+    stacks.capacity() * std::mem::size_of::<Vec<StackItem>>()
+        + stacks
+            .iter()
+            .map(|x| x.capacity() * std::mem::size_of::<StackItem>())
+            .sum::<usize>()
+}
+
+/// Estimate a stack arena capacity that is large enough for the grammar and the vocabulary.
+///
+/// The estimate is `(L + 1) * A * (D + P)`, clamped between 1024 and 16M, where
+///
+/// * `L` is the longest token of the vocabulary in bytes,
+/// * `A` is the largest number of alternatives of a nonterminal,
+/// * `P` is the longest alternative in terms,
+/// * `D` is the deepest stack the grammar can derive. When the nesting of the grammar is unbounded,
+///   e.g. nested JSON arrays, `D` is assumed to be `P` times the number of nonterminals.
+pub fn estimate_stack_arena_capacity(grammar: &Grammar, vocabulary: &Vocabulary) -> usize {
+    let max_token_len = vocabulary.max_token_len();
+    let mut max_expression_len = 1;
+    let mut max_alternatives = 1;
+    for expressions in grammar.nonterminal_id_to_expression.values() {
+        if let SimplifiedExpressions::Expressions(expressions) = expressions {
+            max_alternatives = max_alternatives.max(expressions.len());
+            for expression in expressions.iter() {
+                max_expression_len = max_expression_len.max(expression.len());
+            }
+        }
+    }
+    let max_stack_depth = grammar.max_stack_depth().unwrap_or_else(|| {
+        max_expression_len.saturating_mul(grammar.nonterminal_id_to_expression.len())
+    });
+    (max_token_len + 1)
+        .saturating_mul(max_alternatives)
+        .saturating_mul(max_stack_depth.saturating_add(max_expression_len))
+        .clamp(MIN_ESTIMATED_ARENA_CAPACITY, MAX_ESTIMATED_ARENA_CAPACITY)
+}
+
+/// Format stack items with the names of the nonterminals and the unmatched bytes of the terminals.
+fn item_formatter(grammar: &Grammar) -> impl Fn(&StackItem) -> String + '_ {
+    let id_to_nonterminal: FxHashMap<NonterminalID, &str> = grammar
+        .nonterminal_to_terminal_id
+        .iter()
+        .map(|(k, v)| (*v, k.as_str()))
+        .collect();
+    let root_to_nonterminal: FxHashMap<TrieNodeID, NonterminalID> = grammar
+        .terminals_trie
+        .roots
+        .iter()
+        .map(|(k, v)| (*v, *k))
+        .collect();
+    move |item: &StackItem| match item {
+        StackItem::Nonterminal(id) => format!("<{}>", id_to_nonterminal[id]),
+        StackItem::Terminal(id, start) => format!(
+            "'{}'",
+            String::from_utf8_lossy(&grammar.terminals.get(*id)[*start..]).escape_debug()
+        ),
+        StackItem::Terminals(node_id) => match root_to_nonterminal.get(node_id) {
+            Some(id) => format!("<{}>", id_to_nonterminal[id]),
+            None => format!("{:?}", item),
+        },
+    }
+}
+
+/// The most bytes `stack` can still match, or at least `max_token_len` when a token of any length may be matched.
+fn max_stack_bytes(grammar: &Grammar, stack: &[StackItem], max_token_len: usize) -> usize {
+    let mut sum: usize = 0;
+    for item in stack.iter().rev() {
+        if sum >= max_token_len {
+            break;
+        }
+        sum = sum.saturating_add(match item {
+            StackItem::Terminal(id, start) => grammar.terminals.get(*id).len() - start,
+            // The bounds are computed last while building the grammar, so the samplers used to build
+            // `<except!([nonterminal])>` reject nothing.
+            StackItem::Terminals(node_id) => grammar
+                .trie_max_depths
+                .get(node_id.id)
+                .copied()
+                .unwrap_or(usize::MAX),
+            StackItem::Nonterminal(id) => grammar
+                .nonterminal_max_bytes
+                .get(id)
+                .copied()
+                .unwrap_or(usize::MAX),
+        });
+    }
+    sum
+}
+
+/// Whether the top terminal of the stack is partially matched, so the next bytes decide whether it competes.
+fn is_undecided(grammar: &Grammar, stack: &[StackItem]) -> bool {
+    match stack.last() {
+        Some(StackItem::Terminal(_, start)) => *start > 0,
+        Some(StackItem::Terminals(node_id)) => grammar.terminals_trie.get(*node_id).index > 0,
+        _ => false,
+    }
+}
+
+/// Whether the stacks compete, see [`AmbiguityPolicy`].
+fn compete(grammar: &Grammar, stacks: &[&Vec<StackItem>]) -> bool {
+    let stacks = stacks
+        .iter()
+        .filter(|stack| !is_undecided(grammar, stack))
+        .collect_vec();
+    let is_subsequence = |short: &[StackItem], long: &[StackItem]| {
+        let mut long = long.iter();
+        short.iter().all(|x| long.any(|y| x == y))
+    };
+    stacks.len() > 1
+        && !stacks
+            .iter()
+            .any(|x| stacks.iter().all(|y| is_subsequence(x, y)))
+}
+
 impl std::fmt::Display for Sampler {
     fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
         // The `f` value implements the `Write` trait, which is what the
         // write! macro is expecting. Note that this formatting ignores the
         // various flags provided to format strings.
-        write!(f, "stacks: {:?}", self.stacks)
+        let format_item = item_formatter(&self.grammar);
+        let stacks = self
+            .stacks
+            .iter()
+            .map(|stack| format!("[{}]", stack.iter().map(&format_item).join(", ")))
+            .join(", ");
+        write!(f, "stacks: [{}]", stacks)
     }
 }
 
 impl Sampler {
-    /// Create a new grammar.
+    /// Create a new sampler.
     ///
     /// # Arguments
     ///
     /// * `grammar` - the grammar for this sampler
-    /// * `start_nonterminal` - the starting point of the BNF schema
+    /// * `start_nonterminal` - the starting point of the BNF schema. An empty string means [`Grammar::start_nonterminal`].
     /// * `vocabulary` - the vocabulary for this sampler
     /// * `stack_arena_capacity` - the arena capacity. This value depends on how long and complex the BNF schema is, and the maximum token length in bytes.
+    ///   0 means the capacity is estimated by [`estimate_stack_arena_capacity`].
     /// * `stack_to_bytes_cache_enabled` - a cache that speeds up certain types of except!(excepted_literals) when the BNF schema is not very long.
     pub fn new(
         grammar: Arc<Grammar>,
@@ -182,210 +863,1268 @@ impl Sampler {
         stack_arena_capacity: usize,
         stack_to_bytes_cache_enabled: bool,
     ) -> Result<Self, Error> {
+        Self::with_config(
+            grammar,
+            start_nonterminal,
+            vocabulary,
+            SamplerConfig::new()
+                .stack_arena_capacity(stack_arena_capacity)
+                .stack_to_bytes_cache(stack_to_bytes_cache_enabled),
+        )
+    }
+    /// Create a new sampler from a [`SamplerConfig`].
+    ///
+    /// # Arguments
+    ///
+    /// * `grammar` - the grammar for this sampler
+    /// * `start_nonterminal` - the starting point of the BNF schema. An empty string means [`Grammar::start_nonterminal`].
+    /// * `vocabulary` - the vocabulary for this sampler
+    /// * `config` - the configuration of this sampler
+    pub fn with_config(
+        grammar: Arc<Grammar>,
+        start_nonterminal: String,
+        vocabulary: Arc<Vocabulary>,
+        config: SamplerConfig,
+    ) -> Result<Self, Error> {
+        let start_nonterminal = match start_nonterminal.is_empty() {
+            true => grammar.start_nonterminal().to_string(),
+            false => start_nonterminal,
+        };
         let stacks = vec![vec![StackItem::Nonterminal(
             *grammar
                 .nonterminal_to_terminal_id
                 .get(&start_nonterminal)
-                .ok_or(anyhow!(
-                    "Start_nonterminal {start_nonterminal} is not defined in the BNF schema."
-                ))?,
+                .ok_or_else(|| {
+                    let nonterminals = grammar.nonterminals();
+                    anyhow!(
+                        "Start_nonterminal {start_nonterminal} is not defined in the BNF schema. {}{}",
+                        match nonterminals.is_empty() {
+                            true => "No nonterminal is defined.".to_string(),
+                            false => format!(
+                                "The defined nonterminals are <{}>.",
+                                nonterminals.join(">, <")
+                            ),
+                        },
+                        match utils::closest_name(&start_nonterminal, nonterminals.iter().copied()) {
+                            Some(closest) => format!(" Did you mean <{closest}>?"),
+                            None => String::new(),
+                        }
+                    )
+                })?,
         )]];
-        let token_ids: BitSet<u32> = BitSet::with_capacity(u16::MAX.into());
-        let stacks_to_token_ids = FxHashMap::default();
+        let non_productive = grammar.non_productive_nonterminals(&start_nonterminal);
+        ensure!(
+            non_productive.is_empty(),
+            "Start_nonterminal {start_nonterminal} can never match a complete sentence, because every alternative of <{}> uses one of them. Add an alternative that ends, like the base case of a recursion.",
+            non_productive.join(">, <")
+        );
+        let (stack_arena_capacity, capacity_estimated) = match config.stack_arena_capacity {
+            Some(capacity) if capacity > 0 => (capacity, false),
+            _ => (estimate_stack_arena_capacity(&grammar, &vocabulary), true),
+        };
+        let token_ids: TokenMask = TokenMask::with_capacity(vocabulary.internal_len());
+        let stacks_to_token_ids = HashCache::default();
+        let max_token_len = vocabulary.max_token_len();
+        let metrics = GenerationMetrics::new(vocabulary.id_to_token.len());
         let tokens_buffer =
             Vec::from_iter(vocabulary.token_to_id.iter().map(|(k, v)| (k.clone(), *v)));
+        let accountant = config.memory_accountant.clone();
         Ok(Sampler {
+            stacks_memory: Tracked::new(accountant.clone(), stacks_bytes(&stacks)),
+            cache_memory: Tracked::new(accountant.clone(), 0),
+            accepted_bytes: vec![],
             stacks,
             grammar,
             vocabulary,
             tokens_buffer,
             stacks_to_token_ids,
             token_ids,
-            stack_arena: BufferArena::with_capacity(stack_arena_capacity),
-            stack_to_bytes_cache_enabled,
+            external_token_ids: TokenMask::new(),
+            stack_arena: BufferArena::with_capacity(
+                stack_arena_capacity,
+                capacity_estimated,
+                accountant,
+            ),
             start_nonterminal,
+            config,
+            max_token_len,
+            min_terminal_lens: FxHashMap::default(),
+            metrics,
+            stack_delta: StackDelta::default(),
+            token_history: Some(vec![]),
+            rejected: None,
+            accept_cache_counts: (0, 0),
+            timing: None,
+            signature_filter: None,
+            step: 0,
         })
     }
 
+    /// Create a new sampler starting from the start nonterminal of the grammar, see [`Grammar::start_nonterminal`].
+    pub fn with_default_start(
+        grammar: Arc<Grammar>,
+        vocabulary: Arc<Vocabulary>,
+        config: SamplerConfig,
+    ) -> Result<Self, Error> {
+        Self::with_config(grammar, String::new(), vocabulary, config)
+    }
+
     pub fn reset(&mut self) {
         self.stacks = vec![vec![StackItem::Nonterminal(
             self.grammar.nonterminal_to_terminal_id[&self.start_nonterminal],
         )]];
+        self.metrics.steps.clear();
+        self.stack_delta = StackDelta::default();
+        self.token_history = Some(vec![]);
+        self.rejected = None;
+        self.timing = None;
+        self.step += 1;
+        self.stacks_memory.set(stacks_bytes(&self.stacks));
+        self.accepted_bytes.clear();
+    }
+
+    /// How the number of stacks changed in the last call to [`Sampler::accept_a_token`],
+    /// or any method that accepts tokens. It is only valid until the next such call.
+    pub fn last_step_stack_delta(&self) -> StackDelta {
+        self.stack_delta
+    }
+
+    /// Whether the next token is free text, judged from the tops of the current stacks.
+    ///
+    /// The stacks are only expanded to terminals by [`Sampler::all_possible_next_tokens`] or [`Sampler::accept_a_token`],
+    /// so a newly created or reset sampler is always [`RegionKind::Structured`].
+    pub fn region_kind(&self) -> RegionKind {
+        let root_to_nonterminal: FxHashMap<TrieNodeID, NonterminalID> = self
+            .grammar
+            .terminals_trie
+            .roots
+            .iter()
+            .filter(|(k, _)| self.grammar.nonterminal_to_token_ids.contains_key(k))
+            .map(|(k, v)| (*v, *k))
+            .collect();
+        let mut token_sets = self.stacks.iter().map(|stack| match stack.last() {
+            Some(StackItem::Terminals(node_id)) => root_to_nonterminal.get(node_id).copied(),
+            _ => None,
+        });
+        let Some(first) = token_sets.next() else {
+            return RegionKind::Structured;
+        };
+        if !token_sets.all(|x| x == first) {
+            return RegionKind::Mixed;
+        }
+        match first {
+            Some(id) => RegionKind::TokenSet {
+                nonterminal: self
+                    .grammar
+                    .nonterminal_to_terminal_id
+                    .iter()
+                    .find(|(_, v)| **v == id)
+                    .map(|(k, _)| k.clone())
+                    .unwrap(),
+            },
+            None => RegionKind::Structured,
+        }
+    }
+
+    /// The number of bits the sampler's token sets are sized for.
+    ///
+    /// It is the number of tokens when the vocabulary is remapped, see [`Vocabulary::is_remapped`],
+    /// and the largest token id otherwise.
+    pub fn mask_capacity(&self) -> usize {
+        self.token_ids.capacity()
+    }
+
+    /// The approximate heap memory of the possible tokens cached in [`CacheMode::Full`], in bytes.
+    /// The memory of the stacks keying the cache is not included.
+    pub fn cached_masks_bytes(&self) -> usize {
+        self.stacks_to_token_ids
+            .values()
+            .map(|x| x.memory_bytes())
+            .sum()
+    }
+
+    /// The bytes the sampler currently holds, which are also reported to [`SamplerConfig::memory_accountant`].
+    pub fn stats(&self) -> MemoryStats {
+        MemoryStats {
+            arena_bytes: self.stack_arena.memory_bytes(),
+            stacks_bytes: self.stacks_memory.bytes(),
+            cache_bytes: self.cache_memory.bytes(),
+            grammar_terminals_bytes: self.grammar.terminals_bytes(),
+        }
+    }
+
+    /// Evict every possible tokens cached in [`CacheMode::Full`], e.g. when the sampler exceeds a memory quota.
+    /// The possible tokens are recomputed the next time their stacks are visited.
+    pub fn clear_cache(&mut self) {
+        self.stacks_to_token_ids = HashCache::default();
+        self.cache_memory.set(0);
+    }
+
+    /// A hash of the current stacks, for grouping samplers that are in the same grammar state,
+    /// e.g. to batch requests whose masks are the same.
+    ///
+    /// The stacks are hashed as a set, so reaching a state through different tokens of the same bytes gives the same hash.
+    /// Samplers of the same grammar and vocabulary with the same hash have the same possible tokens, barring hash collisions.
+    /// Samplers with different hashes may still have the same possible tokens.
+    ///
+    /// The hash is stable within a process, but not across versions of this crate.
+    pub fn state_hash(&self) -> u64 {
+        let mut stack_hashes = self
+            .stacks
+            .iter()
+            .map(|stack| {
+                let mut hasher = FxHasher::default();
+                stack.hash(&mut hasher);
+                hasher.finish()
+            })
+            .collect_vec();
+        stack_hashes.sort_unstable();
+        stack_hashes.dedup();
+        let mut hasher = FxHasher::default();
+        stack_hashes.hash(&mut hasher);
+        hasher.finish()
+    }
+
+    /// The metrics collected since the sampler was created or reset.
+    /// No steps are recorded unless [`SamplerConfig::metrics`] is enabled.
+    pub fn metrics(&self) -> &GenerationMetrics {
+        &self.metrics
+    }
+
+    /// Where the time of the last call to [`Sampler::all_possible_next_tokens`] went,
+    /// or `None` unless [`SamplerConfig::collect_timing`] is enabled.
+    pub fn last_step_timing(&self) -> Option<&StepTiming> {
+        self.timing.as_ref()
+    }
+
+    fn record_step(&mut self, mask_size: usize, end_eligible: bool) {
+        if self.config.metrics_enabled {
+            self.metrics.record(mask_size, end_eligible);
+        }
+    }
+
+    /// Store the time elapsed since `start` with `f`, when the timing is collected.
+    #[inline]
+    fn record_time(
+        timing: &mut Option<StepTiming>,
+        start: Option<Instant>,
+        f: impl FnOnce(&mut StepTiming, std::time::Duration),
+    ) {
+        if let (Some(timing), Some(start)) = (timing.as_mut(), start) {
+            f(timing, start.elapsed());
+        }
     }
 
     pub fn all_possible_next_tokens(
         &mut self,
         input_token_id: Option<u32>,
-    ) -> Result<PossibleTokensResult, Error> {
-        // let now = Instant::now();
+    ) -> Result<PossibleTokensResult<'_>, Error> {
+        if self.config.post_mask_hook.is_none() {
+            return Ok(self.possible_tokens(input_token_id, None)?.0);
+        }
+        self.hooked_possible_tokens(input_token_id)
+    }
+
+    /// The possible tokens restricted by the [`SamplerConfig::post_mask_hook`], in `self.external_token_ids`.
+    fn hooked_possible_tokens(
+        &mut self,
+        input_token_id: Option<u32>,
+    ) -> Result<PossibleTokensResult<'_>, Error> {
+        let mut mask = match self.possible_tokens(input_token_id, None)?.0 {
+            PossibleTokensResult::Continue(mask) => mask.clone(),
+            PossibleTokensResult::End => return Ok(PossibleTokensResult::End),
+            PossibleTokensResult::InputTokenRejected => {
+                return Ok(PossibleTokensResult::InputTokenRejected)
+            }
+        };
+        let context = SamplerContext {
+            accepted_bytes: &self.accepted_bytes,
+            step: self.step,
+            region_kind: self.region_kind(),
+        };
+        (self.config.post_mask_hook.as_ref().unwrap().0)(&context, &mut mask);
+        self.external_token_ids = mask;
+        Ok(PossibleTokensResult::Continue(&self.external_token_ids))
+    }
+
+    /// Like [`Sampler::all_possible_next_tokens`], but the possible tokens are copied out of the sampler,
+    /// so they can be held while the chosen token is accepted, see `examples/hold_mask.rs`.
+    pub fn next_mask(
+        &mut self,
+        input_token_id: Option<u32>,
+    ) -> Result<OwnedPossibleTokensResult, Error> {
+        let result = self.all_possible_next_tokens(input_token_id)?.into_owned();
+        Ok(match result {
+            OwnedPossibleTokensResult::Continue(mut mask) => {
+                Arc::make_mut(&mut mask).set_stamp(self.mask_stamp());
+                OwnedPossibleTokensResult::Continue(mask)
+            }
+            result => result,
+        })
+    }
+
+    fn mask_stamp(&self) -> MaskStamp {
+        MaskStamp {
+            step: self.step,
+            state_hash: self.state_hash(),
+        }
+    }
+
+    /// Check that `mask` was returned by [`Sampler::next_mask`] for the current state of the sampler,
+    /// e.g. to catch a mask applied to the logits of the next step after a race.
+    ///
+    /// Returns a [`StaleMaskError`] if a token or bytes were accepted or the sampler was reset since the mask was computed,
+    /// or if the mask has no stamp.
+    pub fn assert_mask_fresh(&self, mask: &TokenMask) -> Result<(), Error> {
+        match mask.stamp() {
+            Some(stamp) if stamp == self.mask_stamp() => Ok(()),
+            stamp => Err(StaleMaskError {
+                mask: stamp,
+                current_step: self.step,
+            }
+            .into()),
+        }
+    }
+
+    /// Like [`Sampler::accept_a_token`], but first check with [`Sampler::assert_mask_fresh`] that `mask`,
+    /// the mask the token was chosen from, is computed for the current state of the sampler.
+    ///
+    /// Nothing is accepted when the mask is stale.
+    pub fn accept_a_token_strict(
+        &mut self,
+        token_id: Option<u32>,
+        mask: &TokenMask,
+    ) -> Result<AcceptTokenResult, Error> {
+        self.assert_mask_fresh(mask)?;
+        self.accept_a_token(token_id)
+    }
+
+    /// Like [`Sampler::all_possible_next_tokens`], but call `visitor` with each possible token id as it is found
+    /// instead of returning them as a whole, e.g. to stream them into a GPU buffer.
+    ///
+    /// When the possible tokens are cached, the visitor is called with each id of the cached set.
+    /// Otherwise it is called during the computation, in no particular order. Each id is visited once.
+    pub fn visit_allowed_tokens(
+        &mut self,
+        input_token_id: Option<u32>,
+        visitor: &mut dyn FnMut(u32),
+    ) -> Result<VisitOutcome, Error> {
+        if self.config.post_mask_hook.is_some() {
+            // The hook needs the whole mask, so the ids are visited once it is done.
+            return Ok(match self.all_possible_next_tokens(input_token_id)? {
+                PossibleTokensResult::Continue(mask) => {
+                    mask.iter().for_each(|x| visitor(x as u32));
+                    VisitOutcome::Continue { cached: false }
+                }
+                PossibleTokensResult::End => VisitOutcome::End,
+                PossibleTokensResult::InputTokenRejected => VisitOutcome::InputTokenRejected,
+            });
+        }
+        Ok(match self.possible_tokens(input_token_id, Some(visitor))? {
+            (PossibleTokensResult::Continue(_), cached) => VisitOutcome::Continue { cached },
+            (PossibleTokensResult::End, _) => VisitOutcome::End,
+            (PossibleTokensResult::InputTokenRejected, _) => VisitOutcome::InputTokenRejected,
+        })
+    }
+
+    /// The possible tokens, and whether they come from the possible tokens cache.
+    fn possible_tokens(
+        &mut self,
+        input_token_id: Option<u32>,
+        visitor: Option<&mut dyn FnMut(u32)>,
+    ) -> Result<(PossibleTokensResult<'_>, bool), Error> {
+        let vocabulary = self.vocabulary.clone();
+        let aliased = self.config.aliases_enabled && vocabulary.has_aliases();
+        let mut visit_with_aliases;
+        let mut visitor: Option<&mut dyn FnMut(u32)> = match visitor {
+            Some(visitor) if aliased => {
+                visit_with_aliases = |id| {
+                    visitor(id);
+                    vocabulary.aliases(id).iter().for_each(|x| visitor(*x));
+                };
+                Some(&mut visit_with_aliases as &mut dyn FnMut(u32))
+            }
+            Some(visitor) => Some(visitor),
+            None => None,
+        };
+        let start = self.config.timing_enabled.then(Instant::now);
+        self.timing = start.map(|_| StepTiming::default());
         self.token_ids.clear();
-        match self.accept_a_token(input_token_id)? {
-            AcceptTokenResult::End => Ok(PossibleTokensResult::End),
-            AcceptTokenResult::Failed => Ok(PossibleTokensResult::InputTokenRejected),
+        let result = self.accept_a_token(input_token_id)?;
+        Self::record_time(&mut self.timing, start, |x, t| x.accept = t);
+        if let Some(timing) = self.timing.as_mut() {
+            (
+                timing.stack_to_bytes_cache_hits,
+                timing.stack_to_bytes_cache_misses,
+            ) = self.accept_cache_counts;
+        }
+        match result {
+            AcceptTokenResult::End => {
+                self.record_step(0, true);
+                Self::record_time(&mut self.timing, start, |x, t| x.total = t);
+                Ok((PossibleTokensResult::End, false))
+            }
+            AcceptTokenResult::Failed => {
+                Self::record_time(&mut self.timing, start, |x, t| x.total = t);
+                Ok((PossibleTokensResult::InputTokenRejected, false))
+            }
             AcceptTokenResult::Continue => {
-                let mut cached_node_id = FxHashSet::default();
-                for stack in self.stacks.iter() {
-                    if let StackItem::Terminals(node_id) =
-                        stack.last().expect("The stack should not be empty.")
-                    {
-                        if cached_node_id.contains(node_id) {
-                            continue;
-                        }
-                        if let Some((k, _)) = self
-                            .grammar
-                            .terminals_trie
-                            .roots
+                if self.config.cache_mode != CacheMode::Full {
+                    self.update_token_ids(&mut visitor)?;
+                    self.record_step(self.token_ids.len(), false);
+                    Self::record_time(&mut self.timing, start, |x, t| x.total = t);
+                    return Ok((
+                        PossibleTokensResult::Continue(Self::external_token_ids(
+                            &self.vocabulary,
+                            &self.token_ids,
+                            &mut self.external_token_ids,
+                            aliased,
+                        )),
+                        false,
+                    ));
+                }
+                let lookup_start = start.map(|_| Instant::now());
+                let key = self
+                    .config
+                    .cache_key_depth
+                    .map(|depth| self.truncated_stacks(depth));
+                let key_ref = key.as_ref().unwrap_or(&self.stacks);
+                let hash = hash_key(key_ref);
+                // The slot is read again instead of keeping the borrow, which the insert after a miss would conflict with.
+                if let Some(slot) = self.stacks_to_token_ids.find(hash, |x| x == key_ref) {
+                    let token_ids = self.stacks_to_token_ids.slot(slot);
+                    if self.config.metrics_enabled {
+                        self.metrics.record(token_ids.len(), false);
+                    }
+                    Self::record_time(&mut self.timing, lookup_start, |x, t| x.cache_lookup = t);
+                    if let Some(visitor) = visitor {
+                        token_ids
                             .iter()
-                            .find(|(_, v)| **v == *node_id)
-                        {
-                            if let Some(x) = self.grammar.nonterminal_to_token_ids.get(k) {
-                                self.token_ids.extend(x.iter());
-                                // println!("{} tokens are skipped.", self.token_ids.len());
-                                cached_node_id.insert(*node_id);
-                            }
-                        }
+                            .for_each(|x| visitor(self.vocabulary.external_id(x as u32)));
                     }
+                    let token_ids = Self::external_token_ids(
+                        &self.vocabulary,
+                        token_ids,
+                        &mut self.external_token_ids,
+                        aliased,
+                    );
+                    Self::record_time(&mut self.timing, start, |x, t| x.total = t);
+                    return Ok((PossibleTokensResult::Continue(token_ids), true));
                 }
-                let entry = self.stacks_to_token_ids.entry(self.stacks.clone());
-                match entry {
-                    Entry::Occupied(value) => Ok(PossibleTokensResult::Continue(value.into_mut())),
-                    Entry::Vacant(entry) => {
-                        let mut stack_to_bytes_cache: FxHashMap<
-                            (FixedBuffer<StackItem>, Box<[u8]>),
-                            bool,
-                        > = FxHashMap::default();
-                        for stack in self.stacks.iter() {
-                            let _now = Instant::now();
-                            let iter = BufferOrTreeIter::new(
-                                &self.tokens_buffer,
-                                &self.vocabulary.token_to_id,
-                                &self.grammar.terminals_trie,
-                                *stack.last().unwrap(),
-                            );
+                Self::record_time(&mut self.timing, lookup_start, |x, t| x.cache_lookup = t);
+                self.update_token_ids(&mut visitor)?;
+                let insert_start = start.map(|_| Instant::now());
+                let key = key.unwrap_or_else(|| self.stacks.clone());
+                self.cache_memory
+                    .add(stacks_bytes(&key) + self.token_ids.memory_bytes());
+                self.stacks_to_token_ids
+                    .insert_unique(hash, key, self.token_ids.clone());
+                Self::record_time(&mut self.timing, insert_start, |x, t| x.cache_lookup += t);
+                self.record_step(self.token_ids.len(), false);
+                Self::record_time(&mut self.timing, start, |x, t| x.total = t);
+                Ok((
+                    PossibleTokensResult::Continue(Self::external_token_ids(
+                        &self.vocabulary,
+                        &self.token_ids,
+                        &mut self.external_token_ids,
+                        aliased,
+                    )),
+                    false,
+                ))
+            }
+        }
+    }
 
-                            for (token, token_id) in iter {
-                                if self.token_ids.contains(*token_id as usize) {
-                                    continue;
-                                }
-                                let arena = unsafe {
-                                    NonNull::new_unchecked(
-                                        &mut self.stack_arena as *mut BufferArena<StackItem>,
-                                    )
-                                };
-                                let mut temp_stack =
-                                    self.stack_arena.allocate_a_stack(stack.len())?;
-                                temp_stack.copy_from_slice(stack.as_slice());
-                                let mut cache;
-                                if self.stack_to_bytes_cache_enabled {
-                                    cache = Some(&mut stack_to_bytes_cache);
-                                } else {
-                                    cache = None;
-                                }
-                                let result = Self::find_stacks_matching_bytes::<
-                                    fn(&[Option<StackItem>], Option<StackItem>),
-                                >(
-                                    arena,
-                                    &mut temp_stack,
-                                    &self.grammar,
-                                    Some(&token.0[..]),
-                                    0,
-                                    false,
-                                    &mut cache,
-                                    &mut None,
-                                )?;
-                                if result {
-                                    self.token_ids.insert(*token_id as usize);
+    /// `internal_ids` as token ids, translated into `buffer` when the vocabulary is remapped
+    /// or the aliases of the token ids are added, see [`SamplerConfig::treat_aliased_ids_as_bytes`].
+    fn external_token_ids<'a>(
+        vocabulary: &Vocabulary,
+        internal_ids: &'a TokenMask,
+        buffer: &'a mut TokenMask,
+        aliased: bool,
+    ) -> &'a TokenMask {
+        if !vocabulary.is_remapped() && !aliased {
+            return internal_ids;
+        }
+        vocabulary.to_external(internal_ids, buffer);
+        if aliased {
+            let aliases = buffer
+                .iter()
+                .flat_map(|x| vocabulary.aliases(x as u32))
+                .map(|x| *x as usize)
+                .collect_vec();
+            buffer.extend(aliases);
+        }
+        buffer
+    }
+
+    /// Accept `token_id` like [`Sampler::all_possible_next_tokens`], and sum `probs`, a probability for every token id,
+    /// over the possible tokens.
+    ///
+    /// Returns 0 when the sampler terminates, and an error when the token is rejected
+    /// or a possible token id has no probability.
+    pub fn admitted_mass(&mut self, token_id: Option<u32>, probs: &[f32]) -> Result<f32, Error> {
+        Ok(self.admitted(token_id, probs)?.mass)
+    }
+
+    /// Like [`Sampler::admitted_mass`], but also compute the entropy of `probs` renormalized over the possible tokens.
+    pub fn admitted(
+        &mut self,
+        token_id: Option<u32>,
+        probs: &[f32],
+    ) -> Result<AdmittedMass, Error> {
+        match self.all_possible_next_tokens(token_id)? {
+            PossibleTokensResult::Continue(token_ids) => AdmittedMass::new(token_ids.iter(), probs),
+            PossibleTokensResult::End => Ok(AdmittedMass::default()),
+            PossibleTokensResult::InputTokenRejected => Err(anyhow!(
+                "Token id {token_id:?} is rejected, so no probability mass is admitted."
+            )),
+        }
+    }
+
+    /// Accept the next tokens as long as the grammar allows exactly one token, up to `max_tokens` tokens,
+    /// and return their ids.
+    ///
+    /// This should be called after [`Sampler::all_possible_next_tokens`] returns [`PossibleTokensResult::Continue`].
+    /// Afterwards, `all_possible_next_tokens(None)` returns the possible tokens, or the end, after the forced tokens.
+    pub fn forced_continuation(&mut self, max_tokens: usize) -> Result<Vec<u32>, Error> {
+        fn unique_token_id(result: PossibleTokensResult) -> Option<u32> {
+            match result {
+                PossibleTokensResult::Continue(token_ids) => {
+                    let mut iter = token_ids.iter();
+                    match (iter.next(), iter.next()) {
+                        (Some(token_id), None) => Some(token_id as u32),
+                        _ => None,
+                    }
+                }
+                _ => None,
+            }
+        }
+        let mut forced = vec![];
+        // The possible tokens of the current state are already recorded by the caller.
+        let steps = self.metrics.steps.len();
+        let mut token_id = unique_token_id(self.all_possible_next_tokens(None)?);
+        self.metrics.steps.truncate(steps);
+        while let Some(id) = token_id {
+            if forced.len() >= max_tokens {
+                break;
+            }
+            forced.push(id);
+            token_id = unique_token_id(self.all_possible_next_tokens(Some(id))?);
+        }
+        Ok(forced)
+    }
+
+    /// Truncate each stack to the fewest top items, up to `depth`, that no token can consume entirely.
+    /// Stacks that cannot be truncated are kept in full.
+    fn truncated_stacks(&mut self, depth: usize) -> Vec<Vec<StackItem>> {
+        let mut result = Vec::with_capacity(self.stacks.len());
+        for stack in self.stacks.iter() {
+            let mut min_len: usize = 0;
+            let mut kept = stack.len();
+            for (i, item) in stack.iter().rev().take(depth).enumerate() {
+                let item_min_len = match item {
+                    StackItem::Terminal(id, start) => self.grammar.terminals.get(*id).len() - start,
+                    StackItem::Terminals(node_id) => *self
+                        .min_terminal_lens
+                        .entry(*node_id)
+                        .or_insert_with(|| self.grammar.terminals_trie.min_terminal_len(*node_id)),
+                    // A nonterminal may derive a short terminal, so nothing below it can be ruled out.
+                    StackItem::Nonterminal(_) => break,
+                };
+                min_len = min_len.saturating_add(item_min_len);
+                if min_len >= self.max_token_len {
+                    kept = i + 1;
+                    break;
+                }
+            }
+            result.push(stack[stack.len() - kept..].to_vec());
+        }
+        result
+    }
+
+    /// Compute the possible tokens into `self.token_ids`, calling `visitor` with each one as it is found.
+    fn update_token_ids(&mut self, visitor: &mut Option<&mut dyn FnMut(u32)>) -> Result<(), Error> {
+        let union_start = self.timing.is_some().then(Instant::now);
+        let mut cached_node_id = FxHashSet::default();
+        for stack in self.stacks.iter() {
+            if let StackItem::Terminals(node_id) =
+                stack.last().expect("The stack should not be empty.")
+            {
+                if cached_node_id.contains(node_id) {
+                    continue;
+                }
+                if let Some((k, _)) = self
+                    .grammar
+                    .terminals_trie
+                    .roots
+                    .iter()
+                    .find(|(_, v)| **v == *node_id)
+                {
+                    if let Some(x) = self.grammar.nonterminal_to_token_ids.get(k) {
+                        match visitor.as_mut() {
+                            Some(visitor) => {
+                                for id in x.iter() {
+                                    if self.token_ids.insert(id) {
+                                        visitor(self.vocabulary.external_id(id as u32));
+                                    }
                                 }
-                                self.stack_arena.clear();
-                                // println!("failed: {:?}",failed_prefixs);
                             }
-                            // println!("stack: {:?}, {:?}", stack, now.elapsed());
-                            // println!("{:?}",accepted_prefixs);
+                            None => self.token_ids.union_with(x),
+                        }
+                        cached_node_id.insert(*node_id);
+                    }
+                }
+            }
+        }
+        Self::record_time(&mut self.timing, union_start, |x, t| x.fast_path_union = t);
+        let scan_start = union_start.map(|_| Instant::now());
+        let allocations = self.stack_arena.allocations;
+        let (mut tokens_visited, mut tokens_checked, mut tokens_accepted) = (0, 0, 0);
+        let (mut tokens_filtered, mut tokens_too_long) = (0, 0);
+        let mut stack_to_bytes_cache = StackToBytesCache::default();
+        let mut filter = match self.config.signature_filter_enabled {
+            true => Some(
+                self.signature_filter
+                    .get_or_insert_with(|| SignatureFilter::new(&self.grammar)),
+            ),
+            false => None,
+        };
+        // The stacks sharing a top item are scanned together, so each token of the top is visited once
+        // and matched against the stacks of the group until one accepts it.
+        let mut groups: Vec<(StackItem, Vec<&Vec<StackItem>>)> = vec![];
+        let mut group_of_top: FxHashMap<StackItem, usize> = FxHashMap::default();
+        for stack in self.stacks.iter() {
+            let top = *stack.last().unwrap();
+            let group = *group_of_top.entry(top).or_insert_with(|| {
+                groups.push((top, vec![]));
+                groups.len() - 1
+            });
+            groups[group].1.push(stack);
+        }
+        // The tokens rejected by every stack of the current group. A token can be yielded more than once for a top,
+        // e.g. once per terminal of a trie starting with its first byte, and the allowed tokens are in `self.token_ids`.
+        let mut rejected = FxHashSet::default();
+        for (top, stacks) in groups {
+            let iter = BufferOrTreeIter::new(
+                &self.tokens_buffer,
+                &self.vocabulary.token_to_id,
+                &self.grammar,
+                top,
+            );
+            let stack_signatures = stacks
+                .iter()
+                .map(|stack| {
+                    filter
+                        .as_mut()
+                        .map(|x| x.stack(&self.grammar, stack, self.max_token_len))
+                        // A stack that can match every bit rejects nothing, so the lookups are skipped.
+                        .filter(|x| *x != u64::MAX)
+                })
+                .collect::<Vec<_>>();
+            let stack_bounds = stacks
+                .iter()
+                .map(|stack| match self.config.length_bound_enabled {
+                    true => max_stack_bytes(&self.grammar, stack, self.max_token_len),
+                    false => usize::MAX,
+                })
+                .collect::<Vec<_>>();
+            rejected.clear();
+            for (token, token_id) in iter {
+                let internal_id = self.vocabulary.internal_id(*token_id) as usize;
+                if self.token_ids.contains(internal_id) || !rejected.insert(internal_id) {
+                    continue;
+                }
+                tokens_visited += 1;
+                for ((stack, stack_signature), stack_bound) in stacks
+                    .iter()
+                    .zip(stack_signatures.iter())
+                    .zip(stack_bounds.iter())
+                {
+                    if token.0.len() > *stack_bound {
+                        tokens_too_long += 1;
+                        continue;
+                    }
+                    if let Some(stack_signature) = stack_signature {
+                        if SignatureFilter::rejects(
+                            *stack_signature,
+                            self.vocabulary.byte_signatures[token_id],
+                        ) {
+                            tokens_filtered += 1;
+                            continue;
+                        }
+                    }
+                    tokens_checked += 1;
+                    let arena = unsafe {
+                        NonNull::new_unchecked(&mut self.stack_arena as *mut BufferArena<StackItem>)
+                    };
+                    let mut temp_stack = self.stack_arena.allocate_a_stack(stack.len())?;
+                    temp_stack.copy_from_slice(stack.as_slice());
+                    let mut cache;
+                    if self.config.stack_to_bytes_cache_enabled() {
+                        cache = Some(&mut stack_to_bytes_cache);
+                    } else {
+                        cache = None;
+                    }
+                    let result = Self::find_stacks_matching_bytes::<
+                        fn(&[Option<StackItem>], Option<StackItem>),
+                    >(
+                        arena,
+                        &mut temp_stack,
+                        &self.grammar,
+                        Some(&token.0[..]),
+                        0,
+                        false,
+                        &mut cache,
+                        &mut None,
+                        &mut None,
+                    )?;
+                    self.stack_arena.clear();
+                    if result {
+                        tokens_accepted += 1;
+                        rejected.remove(&internal_id);
+                        self.token_ids.insert(internal_id);
+                        if let Some(visitor) = visitor.as_mut() {
+                            visitor(*token_id);
                         }
-                        entry.insert(self.token_ids.clone());
-                        Ok(PossibleTokensResult::Continue(&self.token_ids))
+                        break;
                     }
                 }
             }
         }
+        let arena_allocations = self.stack_arena.allocations - allocations;
+        Self::record_time(&mut self.timing, scan_start, |x, t| {
+            x.scan = t;
+            x.tokens_visited = tokens_visited;
+            x.tokens_checked = tokens_checked;
+            x.tokens_accepted = tokens_accepted;
+            x.tokens_filtered = tokens_filtered;
+            x.tokens_too_long = tokens_too_long;
+            x.arena_allocations = arena_allocations;
+            x.stack_to_bytes_cache_hits += stack_to_bytes_cache.hits;
+            x.stack_to_bytes_cache_misses += stack_to_bytes_cache.misses;
+        });
+        Ok(())
     }
+    /// Accept a token, or only expand the stacks to terminals when `token_id` is `None`.
+    ///
+    /// The stacks are left untouched when the token is rejected or an error is returned,
+    /// so the possible tokens stay the same. The token history is cleared on rejection however.
     pub fn accept_a_token(&mut self, token_id: Option<u32>) -> Result<AcceptTokenResult, Error> {
-        let mut find_stacks_matching_bytes = |bytes| {
-            let len = self.stacks.len();
-            let mut accepted = false;
-            for i in 0..len {
-                let arena = unsafe {
-                    NonNull::new_unchecked(&mut self.stack_arena as *mut BufferArena<StackItem>)
-                };
-                let mut stack = self.stack_arena.allocate_a_stack(self.stacks[i].len())?;
-                stack.copy_from_slice(&self.stacks[i]);
-                let stack_to_bytes_cache: &mut FxHashMap<
-                    (FixedBuffer<StackItem>, Box<[u8]>),
-                    bool,
-                > = &mut FxHashMap::default();
-                match stack.last() {
-                    Some(_) => {
-                        let mut cache;
-                        if self.stack_to_bytes_cache_enabled {
-                            cache = Some(stack_to_bytes_cache);
-                        } else {
-                            cache = None;
+        let vocabulary = self.vocabulary.clone();
+        let bytes = match token_id {
+            Some(id) => Some(
+                vocabulary
+                    .token_bytes(id, self.config.aliases_enabled)
+                    .ok_or(anyhow!("Token id {id} is not in the vocabulary."))?,
+            ),
+            None => None,
+        };
+        let result = self.accept_optional_bytes(bytes, &mut None)?;
+        match (result, token_id) {
+            (AcceptTokenResult::Failed, token_id) => {
+                if let (Some(history), Some(id)) = (self.token_history.take(), token_id) {
+                    self.rejected = Some((history, id));
+                }
+            }
+            (_, Some(id)) => {
+                if let Some(history) = self.token_history.as_mut() {
+                    history.push(id);
+                }
+            }
+            (_, None) => {}
+        }
+        Ok(result)
+    }
+
+    /// Accept arbitrary bytes as if they were a token.
+    ///
+    /// The bytes do not need to correspond to any token in the vocabulary, so the token history is no longer recorded.
+    pub fn accept_bytes(&mut self, bytes: &[u8]) -> Result<AcceptTokenResult, Error> {
+        self.token_history = None;
+        self.rejected = None;
+        self.accept_optional_bytes(Some(bytes), &mut None)
+    }
+
+    /// The token ids accepted since the sampler was created or reset,
+    /// or `None` if bytes that are not a whole token were accepted or a token was rejected.
+    pub fn token_history(&self) -> Option<&[u32]> {
+        self.token_history.as_deref()
+    }
+
+    /// A [`TraceBundle`] that reproduces this sampler, with the token history up to and including the first rejected token.
+    ///
+    /// Returns an error if the token history is not recorded because bytes were accepted, see [`Sampler::token_history`].
+    pub fn trace_bundle(&self) -> Result<TraceBundle, Error> {
+        let token_ids = match (&self.rejected, &self.token_history) {
+            (Some((history, id)), _) => history.iter().copied().chain([*id]).collect(),
+            (None, Some(history)) => history.clone(),
+            (None, None) => bail!(
+                "The token history is not recorded because bytes that are not a whole token were accepted."
+            ),
+        };
+        Ok(TraceBundle {
+            grammar: self.grammar.source.clone(),
+            start_nonterminal: self.start_nonterminal.clone(),
+            vocabulary: self.vocabulary.clone(),
+            config: self.config.clone(),
+            token_ids,
+        })
+    }
+
+    /// Save [`Sampler::trace_bundle`] to the directory `path`, see [`TraceBundle::save`].
+    pub fn record_trace_bundle(&self, path: impl AsRef<Path>) -> Result<(), Error> {
+        self.trace_bundle()?.save(path)
+    }
+
+    /// Bring the sampler to the state after `history`, for callers that pass the full token history at each step.
+    ///
+    /// When the recorded token history equals `history[..previous_len]`, only the tokens after `previous_len` are accepted.
+    /// Otherwise the sampler is reset and the whole history is replayed, see [`FastForwardResult::replayed`].
+    /// Accepting stops at the first rejected token.
+    pub fn fast_forward(
+        &mut self,
+        history: &[u32],
+        previous_len: usize,
+    ) -> Result<FastForwardResult, Error> {
+        let replayed = match (&self.token_history, history.get(..previous_len)) {
+            (Some(recorded), Some(prefix)) => recorded.as_slice() != prefix,
+            _ => true,
+        };
+        let suffix = if replayed {
+            self.reset();
+            history
+        } else {
+            &history[previous_len..]
+        };
+        let mut result = if self.stacks.iter().any(|x| x.is_empty()) {
+            AcceptTokenResult::End
+        } else {
+            AcceptTokenResult::Continue
+        };
+        for token_id in suffix {
+            result = self.accept_a_token(Some(*token_id))?;
+            if result == AcceptTokenResult::Failed {
+                break;
+            }
+        }
+        Ok(FastForwardResult { result, replayed })
+    }
+
+    /// Report how many distinct stacks accepting `bytes` produces, and which nonterminals split the stacks.
+    ///
+    /// The stacks are left untouched. The split points are bounded, see [`TraceReport::truncated`].
+    pub fn trace_bytes(&mut self, bytes: &[u8]) -> Result<TraceReport, Error> {
+        let (stacks, step) = (self.stacks.clone(), self.step);
+        let accepted_len = self.accepted_bytes.len();
+        let mut tracer = Some(SplitTracer::new(&self.grammar));
+        let result = self.accept_optional_bytes(Some(bytes), &mut tracer);
+        let stack_count = self.stack_delta.after;
+        self.stacks = stacks;
+        self.step = step;
+        self.accepted_bytes.truncate(accepted_len);
+        self.stacks_memory.set(stacks_bytes(&self.stacks));
+        let result = result?;
+        Ok(tracer.unwrap().into_report(result, stack_count))
+    }
+
+    /// Accept a token, or the longest prefix of its bytes that the grammar allows if the whole token is rejected.
+    ///
+    /// The stacks are left untouched when no non-empty prefix is allowed.
+    /// [`ClosestAcceptResult::dropped_bytes`] reports how many trailing bytes of the token were discarded.
+    pub fn accept_closest(&mut self, token_id: u32) -> Result<ClosestAcceptResult, Error> {
+        let vocabulary = self.vocabulary.clone();
+        let bytes = vocabulary
+            .id_to_token
+            .get(&token_id)
+            .ok_or(anyhow!("Token id {token_id} is not in the vocabulary."))?;
+        let token_history = self.token_history.clone();
+        let (stacks, step, accepted_len) =
+            (self.stacks.clone(), self.step, self.accepted_bytes.len());
+        let (mut len, mut result, mut stack_delta) = (0, AcceptTokenResult::Failed, None);
+        // Every prefix of an allowed prefix is allowed, so the bytes are matched one at a time until one is rejected,
+        // which leaves the stacks of the longest allowed prefix.
+        for byte in bytes.iter() {
+            let accepted = match self.accept_bytes(std::slice::from_ref(byte)) {
+                Err(error) => {
+                    self.stacks = stacks;
+                    self.stacks_memory.set(stacks_bytes(&self.stacks));
+                    self.step = step;
+                    self.accepted_bytes.truncate(accepted_len);
+                    self.token_history = token_history;
+                    return Err(error);
+                }
+                accepted => accepted?,
+            };
+            if accepted == AcceptTokenResult::Failed {
+                break;
+            }
+            (len, result) = (len + 1, accepted);
+            // The stacks before the first byte, and the stacks after the last one.
+            let first = stack_delta.unwrap_or(self.stack_delta);
+            stack_delta = Some(StackDelta {
+                created: self.stack_delta.created,
+                after: self.stack_delta.after,
+                ..first
+            });
+        }
+        if let Some(stack_delta) = stack_delta {
+            // The prefix is accepted as one token.
+            self.step = step + 1;
+            self.stack_delta = stack_delta;
+        }
+        if len == bytes.len() {
+            self.token_history = token_history.map(|mut x| {
+                x.push(token_id);
+                x
+            });
+        } else if len == 0 {
+            self.token_history = token_history;
+        }
+        Ok(ClosestAcceptResult {
+            result,
+            accepted_bytes: len,
+            dropped_bytes: bytes.len() - len,
+            substituted_token_id: None,
+        })
+    }
+
+    /// Accept a token, or the allowed token sharing the longest byte prefix with it if the token is rejected.
+    ///
+    /// Ties are broken by the smallest token id. The stacks are left untouched when no token is allowed.
+    /// [`ClosestAcceptResult::accepted_bytes`] is the length of the shared prefix,
+    /// and [`ClosestAcceptResult::dropped_bytes`] is the number of bytes of the token after it.
+    pub fn accept_nearest_token(&mut self, token_id: u32) -> Result<ClosestAcceptResult, Error> {
+        let vocabulary = self.vocabulary.clone();
+        let bytes = vocabulary
+            .id_to_token
+            .get(&token_id)
+            .ok_or(anyhow!("Token id {token_id} is not in the vocabulary."))?;
+        let stacks = self.stacks.clone();
+        let (token_history, rejected) = (self.token_history.clone(), self.rejected.clone());
+        let result = self.accept_a_token(Some(token_id))?;
+        if result != AcceptTokenResult::Failed {
+            return Ok(ClosestAcceptResult {
+                result,
+                accepted_bytes: bytes.len(),
+                dropped_bytes: 0,
+                substituted_token_id: None,
+            });
+        }
+        // The rejected token is not replayed by the trace bundle, since the stacks are restored.
+        self.token_history = token_history.clone();
+        self.rejected = rejected;
+        self.token_ids.clear();
+        // Expand the stacks first in case no token has been accepted yet.
+        if self.accept_a_token(None)? == AcceptTokenResult::Continue {
+            self.update_token_ids(&mut None)?;
+        }
+        let nearest = self
+            .token_ids
+            .iter()
+            .map(|id| {
+                let id = vocabulary.external_id(id as u32);
+                let common_prefix_len = vocabulary.id_to_token[&id]
+                    .iter()
+                    .zip(bytes.iter())
+                    .take_while(|(a, b)| a == b)
+                    .count();
+                (common_prefix_len, id)
+            })
+            .max_by(|(len_a, id_a), (len_b, id_b)| len_a.cmp(len_b).then(id_b.cmp(id_a)));
+        self.token_ids.clear();
+        match nearest {
+            Some((common_prefix_len, nearest_id)) => Ok(ClosestAcceptResult {
+                result: self.accept_a_token(Some(nearest_id))?,
+                accepted_bytes: common_prefix_len,
+                dropped_bytes: bytes.len() - common_prefix_len,
+                substituted_token_id: Some(nearest_id),
+            }),
+            None => {
+                self.stacks = stacks;
+                self.stacks_memory.set(stacks_bytes(&self.stacks));
+                self.token_history = token_history;
+                Ok(ClosestAcceptResult {
+                    result: AcceptTokenResult::Failed,
+                    accepted_bytes: 0,
+                    dropped_bytes: bytes.len(),
+                    substituted_token_id: None,
+                })
+            }
+        }
+    }
+
+    /// Accept the bytes, or only expand the stacks when `bytes` is `None`.
+    ///
+    /// The new stacks are built apart from the current ones and replace them only if the bytes are accepted,
+    /// so the stacks are left untouched when the result is [`AcceptTokenResult::Failed`] or an error.
+    fn accept_optional_bytes(
+        &mut self,
+        bytes: Option<&[u8]>,
+        tracer: &mut Option<SplitTracer>,
+    ) -> Result<AcceptTokenResult, Error> {
+        let before = self.stacks.len();
+        self.stack_delta = StackDelta {
+            before,
+            created: 0,
+            pruned: 0,
+            after: before,
+        };
+        if bytes.is_none() && self.stacks.iter().any(|x| x.is_empty()) {
+            // The sampler has already terminated.
+            return Ok(AcceptTokenResult::End);
+        }
+        let mut find_stacks_matching_bytes =
+            |stacks: &[Vec<StackItem>], bytes, tracer: &mut Option<SplitTracer>| {
+                let mut new_stacks: Vec<Vec<StackItem>> = vec![];
+                let mut accepted = false;
+                let mut matched_stacks = 0;
+                // A hit skips the stacks found for the key, which were already added to new_stacks in this pass.
+                let mut stack_to_bytes_cache = StackToBytesCache::default();
+                for old_stack in stacks.iter() {
+                    let arena = unsafe {
+                        NonNull::new_unchecked(&mut self.stack_arena as *mut BufferArena<StackItem>)
+                    };
+                    let mut stack = self.stack_arena.allocate_a_stack(old_stack.len())?;
+                    stack.copy_from_slice(old_stack);
+                    match stack.last() {
+                        Some(_) => {
+                            let mut cache;
+                            // Cached results skip the expansions the tracer records.
+                            if self.config.stack_to_bytes_cache_enabled() && tracer.is_none() {
+                                cache = Some(&mut stack_to_bytes_cache);
+                            } else {
+                                cache = None;
+                            }
+                            let matched = Self::find_stacks_matching_bytes(
+                                arena,
+                                &mut stack,
+                                &self.grammar,
+                                bytes,
+                                0,
+                                true,
+                                &mut cache,
+                                &mut Some(
+                                    |temp_stack: &[Option<StackItem>], top: Option<StackItem>| {
+                                        let mut new_vec = Vec::with_capacity(temp_stack.len() + 1);
+                                        new_vec.extend(temp_stack.iter().map(|x| x.unwrap()));
+                                        if let Some(top) = top {
+                                            new_vec.push(top);
+                                        }
+                                        if !new_stacks.contains(&new_vec) {
+                                            new_stacks.push(new_vec);
+                                        }
+                                    },
+                                ),
+                                tracer,
+                            );
+                            // The arena is cleared before an error is returned, so the sampler stays usable.
+                            self.stack_arena.clear();
+                            if matched? {
+                                matched_stacks += 1;
+                                accepted = true;
+                            }
                         }
-                        accepted |= Self::find_stacks_matching_bytes(
-                            arena,
-                            &mut stack,
-                            &self.grammar,
-                            bytes,
-                            0,
-                            true,
-                            &mut cache,
-                            &mut Some(
-                                |temp_stack: &[Option<StackItem>], top: Option<StackItem>| {
-                                    let mut new_vec = Vec::with_capacity(temp_stack.len() + 1);
-                                    new_vec.extend(temp_stack.iter().map(|x| x.unwrap()));
-                                    if let Some(top) = top {
-                                        new_vec.push(top);
-                                    }
-                                    if !self.stacks[len..].iter().any(|x| *x == new_vec) {
-                                        self.stacks.push(new_vec);
-                                    }
-                                },
-                            ),
-                        )?;
-                    }
-                    None => {
-                        continue;
+                        None => {
+                            continue;
+                        }
+                    };
+                }
+                let result = if accepted {
+                    if new_stacks.is_empty() || new_stacks.iter().any(|x| x.is_empty()) {
+                        AcceptTokenResult::End
+                    } else {
+                        AcceptTokenResult::Continue
                     }
+                } else {
+                    AcceptTokenResult::Failed
                 };
-                self.stack_arena.clear();
+                let cache_counts = (stack_to_bytes_cache.hits, stack_to_bytes_cache.misses);
+                Ok((
+                    result,
+                    stacks.len() - matched_stacks,
+                    new_stacks,
+                    cache_counts,
+                ))
+            };
+        let (mut result, pruned, mut new_stacks, mut cache_counts) =
+            find_stacks_matching_bytes(&self.stacks, bytes, tracer)?;
+        if let (Some(bytes), AmbiguityPolicy::Error | AmbiguityPolicy::PreferFirst) =
+            (bytes, self.config.ambiguity_policy)
+        {
+            if compete(&self.grammar, &new_stacks.iter().collect_vec()) {
+                Self::resolve_ambiguity(
+                    &self.grammar,
+                    self.config.ambiguity_policy,
+                    bytes,
+                    &mut new_stacks,
+                )?;
             }
-            for i in (0..len).rev() {
-                self.stacks.swap_remove(i);
+        }
+        let created = new_stacks.len();
+        if bytes.is_some() && result == AcceptTokenResult::Continue {
+            if let (Some(tracer), Some(bytes)) = (tracer.as_mut(), bytes) {
+                tracer.byte_offset_base = bytes.len();
             }
-            if accepted {
-                if self.stacks.is_empty() || self.stacks.iter().any(|x| x.is_empty()) {
-                    return Ok(AcceptTokenResult::End);
-                }
-                Ok(AcceptTokenResult::Continue)
-            } else {
-                Ok(AcceptTokenResult::Failed)
+            let (expanded, expanded_counts);
+            (result, _, expanded, expanded_counts) =
+                find_stacks_matching_bytes(&new_stacks, None, tracer)?;
+            new_stacks = expanded;
+            cache_counts.0 += expanded_counts.0;
+            cache_counts.1 += expanded_counts.1;
+        }
+        self.accept_cache_counts = cache_counts;
+        self.stack_delta = StackDelta {
+            before,
+            created,
+            pruned,
+            after: new_stacks.len(),
+        };
+        if result != AcceptTokenResult::Failed {
+            self.stacks = new_stacks;
+            self.stacks_memory.set(stacks_bytes(&self.stacks));
+            if let Some(bytes) = bytes {
+                self.step += 1;
+                self.accepted_bytes.extend_from_slice(bytes);
+            }
+        }
+        #[cfg(debug_assertions)]
+        if let Err(violations) = self.validate_invariants() {
+            panic!("The sampler is corrupted: {}", violations.iter().join(" "));
+        }
+        Ok(result)
+    }
+
+    /// Check that the sampler is in a consistent state, e.g. in a health check of a long-running server.
+    ///
+    /// Every item of the stacks and of the stacks cached for the possible tokens must refer to a nonterminal,
+    /// a trie node and a terminal of the grammar, the temporary stack arena must be empty,
+    /// and the token history must only hold token ids of the vocabulary.
+    /// Debug builds run the check after every accepted token and panic on a violation.
+    pub fn validate_invariants(&self) -> std::result::Result<(), Vec<InvariantViolation>> {
+        let grammar = &self.grammar;
+        let mut violations = vec![];
+        let mut check_stack = |location: StackLocation, stack: &[StackItem]| {
+            for item in stack {
+                violations.extend(match *item {
+                    StackItem::Nonterminal(id)
+                        if !grammar.nonterminal_id_to_expression.contains_key(&id) =>
+                    {
+                        Some(InvariantViolation::UnknownNonterminal { location, id: id.0 })
+                    }
+                    StackItem::Terminals(id) if id.id >= grammar.terminals_trie.node_count() => {
+                        Some(InvariantViolation::UnknownTrieNode {
+                            location,
+                            id: id.id,
+                        })
+                    }
+                    StackItem::Terminal(id, _) if id.0 >= grammar.terminals.len() => {
+                        Some(InvariantViolation::UnknownTerminal { location, id: id.0 })
+                    }
+                    StackItem::Terminal(id, start) if start >= grammar.terminals.get(id).len() => {
+                        Some(InvariantViolation::TerminalOffsetOutOfRange {
+                            location,
+                            start,
+                            len: grammar.terminals.get(id).len(),
+                        })
+                    }
+                    _ => None,
+                });
             }
         };
-        let result;
-        let bytes = token_id.map(|id| self.vocabulary.id_to_token[&id].as_slice());
-        if bytes.is_some() {
-            result = find_stacks_matching_bytes(bytes)?;
-            if result == AcceptTokenResult::Failed || result == AcceptTokenResult::End {
-                return Ok(result);
+        for (i, stack) in self.stacks.iter().enumerate() {
+            check_stack(StackLocation::Stacks(i), stack);
+        }
+        for stacks in self.stacks_to_token_ids.keys() {
+            for stack in stacks {
+                check_stack(StackLocation::Cache, stack);
             }
         }
-        find_stacks_matching_bytes(None)
+        if self.stack_arena.in_use() > 0 {
+            violations.push(InvariantViolation::ArenaInUse(self.stack_arena.in_use()));
+        }
+        for id in self.token_history.iter().flatten() {
+            if self.vocabulary.token_bytes(*id, true).is_none() {
+                violations.push(InvariantViolation::UnknownHistoryToken(*id));
+            }
+        }
+        match violations.is_empty() {
+            true => std::result::Result::Ok(()),
+            false => Err(violations),
+        }
+    }
+
+    /// Corrupt the internals of the sampler, to test [`Sampler::validate_invariants`].
+    #[cfg(feature = "fixtures")]
+    #[doc(hidden)]
+    pub fn corrupt(&mut self, corruption: Corruption) {
+        let unknown = usize::MAX / 2;
+        let item = match corruption {
+            Corruption::UnknownNonterminal | Corruption::CachedUnknownNonterminal => {
+                StackItem::Nonterminal(NonterminalID(unknown))
+            }
+            Corruption::UnknownTrieNode => StackItem::Terminals(TrieNodeID { id: unknown }),
+            Corruption::UnknownTerminal => StackItem::Terminal(TerminalID(unknown), 1),
+            Corruption::TerminalOffsetOutOfRange => StackItem::Terminal(TerminalID(0), unknown),
+            Corruption::ArenaInUse => {
+                let _ = self.stack_arena.allocate_a_stack(1);
+                return;
+            }
+            Corruption::UnknownHistoryToken => {
+                self.token_history
+                    .get_or_insert_with(Vec::new)
+                    .push(u32::MAX);
+                return;
+            }
+        };
+        let stacks = vec![vec![item]];
+        if corruption == Corruption::CachedUnknownNonterminal {
+            self.stacks_to_token_ids
+                .insert_unique(hash_key(&stacks), stacks, TokenMask::new());
+        } else {
+            self.stacks.extend(stacks);
+        }
     }
+    /// Apply the ambiguity policy to the competing stacks matching `bytes`.
+    fn resolve_ambiguity(
+        grammar: &Grammar,
+        policy: AmbiguityPolicy,
+        bytes: &[u8],
+        stacks: &mut Vec<Vec<StackItem>>,
+    ) -> Result<(), Error> {
+        let format_item = item_formatter(grammar);
+        let mut formatted = stacks
+            .drain(..)
+            .map(|stack| (stack.iter().map(&format_item).join(", "), stack))
+            .collect_vec();
+        formatted.sort_by(|a, b| a.0.cmp(&b.0));
+        if policy == AmbiguityPolicy::Error {
+            let top_items = formatted
+                .iter()
+                .filter(|(_, stack)| !is_undecided(grammar, stack))
+                .map(|(_, stack)| stack.last().map_or("the end".to_string(), &format_item))
+                .collect();
+            return Err(AmbiguityError {
+                token: bytes.to_vec(),
+                top_items,
+            }
+            .into());
+        }
+        for (_, stack) in formatted {
+            let mut kept = stacks.iter().collect_vec();
+            kept.push(&stack);
+            if !compete(grammar, &kept) {
+                stacks.push(stack);
+            }
+        }
+        Ok(())
+    }
+
     fn match_stack_to_bytes(
         stack: &FixedBuffer<StackItem>,
         bytes: Option<&[u8]>,
         remaining_byte_start: usize,
-        trie: &TerminalsTrie,
+        grammar: &Grammar,
         find_all: bool,
     ) -> BytesMatchResults {
         #[allow(clippy::too_many_arguments)]
@@ -393,7 +2132,7 @@ impl Sampler {
             stack: &FixedBuffer<StackItem>,
             bytes: &[u8],
             bytes_index: usize,
-            trie: &TerminalsTrie,
+            grammar: &Grammar,
             stack_offset: usize,
             find_all: bool,
             found: &mut bool,
@@ -412,15 +2151,18 @@ impl Sampler {
                         });
                     }
                 }
-                StackItem::Terminal(terminal) => {
-                    let terminal = unsafe { &*terminal };
+                StackItem::Terminal(terminal_id, start) => {
+                    let terminal = &grammar.terminals.get(terminal_id)[start..];
                     for i in 0..terminal.len() {
                         if bytes.len() == i + bytes_index {
                             *found = true;
                             result.push(BytesMatchResult {
                                 remaining_bytes_start: INVALID_INDEX,
                                 stack_offset: stack_offset as u32,
-                                modified_item_at_offset: Some(StackItem::Terminal(&terminal[i..])),
+                                modified_item_at_offset: Some(StackItem::Terminal(
+                                    terminal_id,
+                                    start + i,
+                                )),
                             });
                             return;
                         }
@@ -442,7 +2184,7 @@ impl Sampler {
                             stack,
                             bytes,
                             terminal.len() + bytes_index,
-                            trie,
+                            grammar,
                             stack_offset - 1,
                             find_all,
                             found,
@@ -451,6 +2193,7 @@ impl Sampler {
                     }
                 }
                 StackItem::Terminals(current_node_id) => {
+                    let trie = &grammar.terminals_trie;
                     let mut nodes = Vec::with_capacity(bytes.len() - bytes_index);
                     let mut flag = true;
                     {
@@ -484,7 +2227,7 @@ impl Sampler {
                                 stack,
                                 bytes,
                                 bytes_index + i + 1,
-                                trie,
+                                grammar,
                                 stack_offset - 1,
                                 find_all,
                                 found,
@@ -498,6 +2241,9 @@ impl Sampler {
                     if let Some(last_node_id) = nodes.last() {
                         if flag {
                             let last_node = trie.get(*last_node_id);
+                            // A node that completes a terminal and also prefixes longer ones gives two different
+                            // stacks, one still inside the longer terminals and one after the completed terminal.
+                            // They are not duplicates, and token set tries, like <any!>, never stop inside a token.
                             if !last_node.children.is_empty() && last_node.can_stop {
                                 *found = true;
                                 result.push(BytesMatchResult {
@@ -532,7 +2278,7 @@ impl Sampler {
                     stack,
                     bytes,
                     remaining_byte_start,
-                    trie,
+                    grammar,
                     stack_offset,
                     find_all,
                     &mut found,
@@ -551,48 +2297,56 @@ impl Sampler {
     #[allow(clippy::too_many_arguments)]
     #[allow(clippy::type_complexity)]
     fn find_stacks_matching_bytes<'b, F1>(
-        mut arena: NonNull<BufferArena<StackItem>>,
+        arena: NonNull<BufferArena<StackItem>>,
         stack: &mut FixedBuffer<StackItem>,
         grammar: &Grammar,
         bytes: Option<&'b [u8]>,
         remaining_byte_start: usize,
         find_all: bool,
-        stack_to_bytes_cache: &mut Option<
-            &mut FxHashMap<(FixedBuffer<StackItem>, Box<[u8]>), bool>,
-        >,
+        stack_to_bytes_cache: &mut Option<&mut StackToBytesCache>,
         after_finding_stack: &mut Option<F1>,
+        tracer: &mut Option<SplitTracer>,
     ) -> Result<bool, Error>
     where
         F1: FnMut(&[Option<StackItem>], Option<StackItem>),
     {
-        let trie = &grammar.terminals_trie;
         let mut _find_stacks_matching_bytes =
             |mut arena: NonNull<BufferArena<StackItem>>,
              top: NonterminalID,
              stack: &[Option<StackItem>],
              bytes: Option<&'b [u8]>,
              remaining_byte_start: usize,
-             stack_to_bytes_cache: &mut Option<
-                &mut FxHashMap<(FixedBuffer<StackItem>, Box<[u8]>), bool>,
-            >,
-             after_finding_stack: &mut Option<F1>| {
+             stack_to_bytes_cache: &mut Option<&mut StackToBytesCache>,
+             after_finding_stack: &mut Option<F1>,
+             tracer: &mut Option<SplitTracer>| {
                 let mut found = false;
-                match &grammar.nonterminal_id_to_expression[&top] {
+                let expressions = grammar.nonterminal_id_to_expression.get(&top).ok_or_else(|| {
+                    anyhow!("A nonterminal is used before it is defined. except!([nonterminal]) cannot depend on itself.")
+                })?;
+                match expressions {
                     SimplifiedExpressions::Expressions(expressions) => {
+                        let mut branches = vec![];
                         for expression in expressions.iter() {
                             let temp_stack = &mut unsafe { arena.as_mut() }
                                 .allocate_a_stack(stack.len() + expression.len())?;
                             temp_stack.copy_from_raw_slice(stack);
                             for term in expression.iter().rev() {
                                 temp_stack.push(match term {
-                                    U8Term::Terminal(value) => {
-                                        StackItem::Terminal(value.as_slice())
-                                    }
+                                    U8Term::Terminal(value) => StackItem::Terminal(*value, 0),
                                     U8Term::Nonterminal(value) => StackItem::Nonterminal(
-                                        grammar.nonterminal_to_terminal_id[value],
+                                        *grammar.nonterminal_to_terminal_id.get(value).ok_or_else(
+                                            || {
+                                                anyhow!(
+                                                    "Nonterminal string <{value}> is not defined."
+                                                )
+                                            },
+                                        )?,
                                     ),
                                 });
                             }
+                            if let Some(tracer) = tracer.as_mut() {
+                                tracer.enter();
+                            }
                             let temp = Self::find_stacks_matching_bytes(
                                 arena,
                                 temp_stack,
@@ -602,12 +2356,28 @@ impl Sampler {
                                 find_all,
                                 stack_to_bytes_cache,
                                 after_finding_stack,
+                                tracer,
                             )?;
+                            if let Some(tracer) = tracer.as_mut() {
+                                let splits = tracer.exit();
+                                if temp {
+                                    branches.push(SplitBranch {
+                                        expansion: format_expression(
+                                            expression,
+                                            &grammar.terminals,
+                                        ),
+                                        splits,
+                                    });
+                                }
+                            }
                             found |= temp;
                             if !find_all && found {
                                 return Ok(found);
                             }
                         }
+                        if let Some(tracer) = tracer.as_mut() {
+                            tracer.record(top, remaining_byte_start, branches);
+                        }
                     }
                     SimplifiedExpressions::Terminals(node_id) => {
                         let temp_stack =
@@ -623,6 +2393,7 @@ impl Sampler {
                             find_all,
                             stack_to_bytes_cache,
                             after_finding_stack,
+                            tracer,
                         )?;
                         if !find_all && found {
                             return Ok(found);
@@ -633,24 +2404,23 @@ impl Sampler {
             };
         match stack.pop() {
             Some(value) => match value {
-                StackItem::Nonterminal(top) => {
-                    return _find_stacks_matching_bytes(
-                        arena,
-                        top,
-                        stack.as_raw_slice(),
-                        bytes,
-                        remaining_byte_start,
-                        stack_to_bytes_cache,
-                        after_finding_stack,
-                    )
-                }
-                StackItem::Terminal(_) | StackItem::Terminals(_) => {
+                StackItem::Nonterminal(top) => _find_stacks_matching_bytes(
+                    arena,
+                    top,
+                    stack.as_raw_slice(),
+                    bytes,
+                    remaining_byte_start,
+                    stack_to_bytes_cache,
+                    after_finding_stack,
+                    tracer,
+                ),
+                StackItem::Terminal(_, _) | StackItem::Terminals(_) => {
                     stack.push(value);
                     match Self::match_stack_to_bytes(
                         stack,
                         bytes,
                         remaining_byte_start,
-                        trie,
+                        grammar,
                         find_all,
                     ) {
                         BytesMatchResults::Failed => Ok(false),
@@ -688,44 +2458,51 @@ impl Sampler {
                                             &stack[..(result.stack_offset + 1) as usize]
                                         ),
                                     };
-                                    let mut temp_stack = unsafe { arena.as_mut() }
-                                        .allocate_a_stack((result.stack_offset + 1) as usize)?;
-                                    temp_stack.copy_from_raw_slice(
-                                        &stack[..result.stack_offset as usize],
-                                    );
-                                    if let Some(value) = result.modified_item_at_offset {
-                                        temp_stack.push(value);
-                                    }
-                                    let k = (
-                                        temp_stack,
-                                        (&bytes.unwrap()[result.remaining_bytes_start as usize..])
-                                            .into(),
-                                    );
+                                    let prefix = &stack[..result.stack_offset as usize];
+                                    let remaining_bytes =
+                                        &bytes.unwrap()[result.remaining_bytes_start as usize..];
                                     let temp;
                                     if let Some(stack_to_bytes_cache) = stack_to_bytes_cache {
-                                        if let Some(value) = stack_to_bytes_cache.get(&k) {
+                                        // The nonterminal to expand is part of the key, or stacks that only differ in it would share a result.
+                                        let top_item = Some(StackItem::Nonterminal(top));
+                                        let hash = hash_key(&(prefix, top_item, remaining_bytes));
+                                        if let Some(value) =
+                                            stack_to_bytes_cache.entries.get(hash, |(k, b)| {
+                                                k.split_last() == Some((&top_item, prefix))
+                                                    && **b == *remaining_bytes
+                                            })
+                                        {
+                                            stack_to_bytes_cache.hits += 1;
                                             temp = *value;
                                         } else {
+                                            stack_to_bytes_cache.misses += 1;
                                             temp = _find_stacks_matching_bytes(
                                                 arena,
                                                 top,
-                                                &stack[..result.stack_offset as usize],
+                                                prefix,
                                                 bytes,
                                                 result.remaining_bytes_start as usize,
                                                 &mut Some(stack_to_bytes_cache),
                                                 after_finding_stack,
+                                                tracer,
                                             )?;
-                                            stack_to_bytes_cache.insert(k, temp);
+                                            let key = prefix.iter().copied().chain([top_item]);
+                                            stack_to_bytes_cache.entries.insert_unique(
+                                                hash,
+                                                (key.collect(), remaining_bytes.into()),
+                                                temp,
+                                            );
                                         }
                                     } else {
                                         temp = _find_stacks_matching_bytes(
                                             arena,
                                             top,
-                                            &stack[..result.stack_offset as usize],
+                                            prefix,
                                             bytes,
                                             result.remaining_bytes_start as usize,
                                             &mut None,
                                             after_finding_stack,
+                                            tracer,
                                         )?;
                                     }
                                     flag |= temp;
@@ -739,7 +2516,17 @@ impl Sampler {
                     }
                 }
             },
-            None => Ok(false),
+            // Every item is matched, like after epsilon alternatives, so the stack ends
+            // unless bytes remain to be matched.
+            None => {
+                let ended = bytes.is_none_or(|bytes| remaining_byte_start == bytes.len());
+                if ended {
+                    if let Some(f) = after_finding_stack.as_mut() {
+                        f(&[], None)
+                    }
+                }
+                Ok(ended)
+            }
         }
     }
 }
@@ -0,0 +1,137 @@
+//! Compose a BNF schema from several pieces, e.g. a static schema and productions generated at runtime
+//! like an enum of the valid tool names, so the grammar is built once from the whole schema.
+//!
+//! The pieces are concatenated, so the grammar behaves exactly like one built from the concatenated schema,
+//! where the alternatives of a nonterminal defined more than once are unioned.
+//! [`SchemaBuilder::duplicates`] decides whether a nonterminal may be defined by several pieces.
+use crate::grammar::{strip_comments, Grammar, GrammarBuildOptions};
+use crate::include::defined_nonterminal;
+use crate::vocabulary::Vocabulary;
+use anyhow::{bail, ensure, Error};
+use std::collections::BTreeMap;
+use std::sync::Arc;
+
+/// What [`SchemaBuilder`] does with a nonterminal defined by more than one piece.
+#[derive(Debug, PartialEq, Clone, Copy, Eq, Default)]
+pub enum DuplicatePolicy {
+    /// Return an error naming the nonterminal and the pieces defining it.
+    #[default]
+    Reject,
+    /// Union the alternatives of all the definitions, like a schema defining the nonterminal twice.
+    Union,
+}
+
+/// Collects schemas and productions into one schema, see the [module documentation](self).
+#[derive(Debug, Clone, Default)]
+pub struct SchemaBuilder {
+    duplicates: DuplicatePolicy,
+    /// The description of each piece for errors, and its text.
+    pieces: Vec<(String, String)>,
+}
+
+impl SchemaBuilder {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Whether a nonterminal may be defined by several pieces. Defaults to [`DuplicatePolicy::Reject`].
+    pub fn duplicates(mut self, policy: DuplicatePolicy) -> Self {
+        self.duplicates = policy;
+        self
+    }
+
+    /// Add a schema in BNF text format.
+    pub fn add_schema(&mut self, schema: &str) -> &mut Self {
+        let description = format!("schema {}", self.pieces.len() + 1);
+        self.pieces.push((description, schema.to_string()));
+        self
+    }
+
+    /// Add a production of `nonterminal`, where each alternative is a list of BNF terms
+    /// like `"'search'"` or `"<name>"`. An empty alternative matches no bytes.
+    ///
+    /// Returns an error if the name of the nonterminal is empty or contains `<` or `>`, or if there is no alternative.
+    pub fn add_production(
+        &mut self,
+        nonterminal: &str,
+        alternatives: &[&[&str]],
+    ) -> Result<&mut Self, Error> {
+        ensure!(
+            !nonterminal.is_empty() && !nonterminal.contains(['<', '>']),
+            "<{nonterminal}> is not a valid name of a nonterminal."
+        );
+        ensure!(
+            !alternatives.is_empty(),
+            "The production of <{nonterminal}> has no alternative."
+        );
+        let alternatives = alternatives
+            .iter()
+            .map(|terms| match terms.is_empty() {
+                true => "''".to_string(),
+                false => terms.concat(),
+            })
+            .collect::<Vec<_>>();
+        self.pieces.push((
+            format!("production of <{nonterminal}>"),
+            format!("<{nonterminal}>::={}", alternatives.join("|")),
+        ));
+        Ok(self)
+    }
+
+    /// The concatenated schema.
+    ///
+    /// Returns an error if a nonterminal is defined by several pieces and duplicates are rejected.
+    pub fn to_schema(&self) -> Result<String, Error> {
+        if self.duplicates == DuplicatePolicy::Reject {
+            let mut definitions = BTreeMap::new();
+            for (i, (description, text)) in self.pieces.iter().enumerate() {
+                for name in strip_comments(text).lines().filter_map(defined_nonterminal) {
+                    match definitions.get(name) {
+                        Some((j, other)) if *j != i => bail!(
+                            "<{name}> is defined by both the {other} and the {description}. \
+                            Rename one of them, or union them with DuplicatePolicy::Union."
+                        ),
+                        Some(_) => {}
+                        None => {
+                            definitions.insert(name.to_string(), (i, description));
+                        }
+                    }
+                }
+            }
+        }
+        let mut schema = String::new();
+        for (_, text) in self.pieces.iter() {
+            schema.push_str(text);
+            if !text.ends_with('\n') {
+                schema.push('\n');
+            }
+        }
+        Ok(schema)
+    }
+
+    /// Build the grammar of the concatenated schema with [`Grammar::with_options`].
+    pub fn build(
+        &self,
+        vocabulary: Arc<Vocabulary>,
+        options: GrammarBuildOptions,
+    ) -> Result<Arc<Grammar>, Error> {
+        Grammar::with_options(&self.to_schema()?, vocabulary, options)
+    }
+}
+
+/// Concatenate `base` and the `overlays` into one schema, rejecting a nonterminal defined by more than one of them.
+pub fn merge(base: &str, overlays: &[&str]) -> Result<String, Error> {
+    let mut builder = SchemaBuilder::new();
+    builder.add_schema(base);
+    for overlay in overlays {
+        builder.add_schema(overlay);
+    }
+    builder.to_schema()
+}
+
+impl Grammar {
+    /// A [`SchemaBuilder`] to compose the schema of a grammar from several pieces.
+    pub fn builder() -> SchemaBuilder {
+        SchemaBuilder::new()
+    }
+}
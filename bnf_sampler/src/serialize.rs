@@ -0,0 +1,189 @@
+//! Save a built grammar with [`Grammar::to_bytes`] and load it with [`Grammar::from_bytes`],
+//! so a grammar over a large vocabulary, whose `<any!>` and `<except!(...)>` insert every token
+//! into the terminals trie, is built once and cached on disk.
+//!
+//! The bytes are JSON holding the [`FORMAT_VERSION`], a fingerprint of the vocabulary and the grammar.
+//! Every id inside a loaded grammar is checked, so corrupted bytes are an error instead of a panic later.
+//!
+//! [`Grammar::new_cached`] keeps the bytes in a directory, named after a hash of the schema and the vocabulary.
+use crate::grammar::{Grammar, SimplifiedExpressions, U8Term};
+use crate::vocabulary::Vocabulary;
+use anyhow::{bail, ensure, Context, Error};
+use itertools::Itertools;
+use rustc_hash::{FxHashSet, FxHasher};
+use serde::{Deserialize, Serialize};
+use std::fs;
+use std::hash::Hasher;
+use std::path::{Path, PathBuf};
+use std::sync::Arc;
+
+/// The version of the bytes of [`Grammar::to_bytes`], bumped whenever the layout of a grammar changes.
+pub const FORMAT_VERSION: u32 = 1;
+
+#[derive(Serialize)]
+struct Saved<'a> {
+    version: u32,
+    vocabulary: u64,
+    grammar: &'a Grammar,
+}
+
+/// The fields checked before the grammar is deserialized, so an old file gets a clear error.
+#[derive(Deserialize)]
+struct Header {
+    version: u32,
+    vocabulary: u64,
+}
+
+#[derive(Deserialize)]
+struct Loaded {
+    grammar: Grammar,
+}
+
+/// A hash of the tokens and their ids, which is the same for the same vocabulary on the same platform.
+fn fingerprint(vocabulary: &Vocabulary) -> u64 {
+    let mut hasher = FxHasher::default();
+    for (id, token) in vocabulary
+        .id_to_token
+        .iter()
+        .sorted_unstable_by_key(|(id, _)| **id)
+    {
+        hasher.write_u32(*id);
+        hasher.write_usize(token.len());
+        hasher.write(token);
+    }
+    hasher.finish()
+}
+
+/// The file in `cache_dir` of the grammar of `schema` and the vocabulary with `fingerprint`.
+fn cache_file(cache_dir: &Path, schema: &str, fingerprint: u64) -> PathBuf {
+    let mut hasher = FxHasher::default();
+    hasher.write_u32(FORMAT_VERSION);
+    hasher.write_u64(fingerprint);
+    hasher.write(schema.as_bytes());
+    cache_dir.join(format!("{:016x}.grammar.json", hasher.finish()))
+}
+
+impl Grammar {
+    /// Like [`Grammar::new`], but the built grammar is saved in `cache_dir` and loaded from it
+    /// the next time the same schema is built with the same vocabulary.
+    ///
+    /// The file is named after a hash of the schema, the tokens of the vocabulary and the [`FORMAT_VERSION`],
+    /// since the token sets of `<any!>` and `<except!(...)>` depend on the tokens. A file that cannot be loaded,
+    /// like a corrupted file or one of an older version, is rebuilt and overwritten.
+    /// Returns an error if the schema is invalid or the file cannot be written.
+    pub fn new_cached(
+        schema: &str,
+        vocabulary: Arc<Vocabulary>,
+        stack_arena_capacity: usize,
+        cache_dir: impl AsRef<Path>,
+    ) -> Result<Arc<Self>, Error> {
+        let cache_dir = cache_dir.as_ref();
+        let fingerprint = fingerprint(&vocabulary);
+        let path = cache_file(cache_dir, schema, fingerprint);
+        if let Ok(bytes) = fs::read(&path) {
+            match Self::from_bytes(&bytes, vocabulary.clone()) {
+                // The hash of another schema may collide.
+                Ok(grammar) if grammar.source == schema => return Ok(grammar),
+                _ => {}
+            }
+        }
+        let grammar = Self::new(schema, vocabulary.clone(), stack_arena_capacity)?;
+        fs::create_dir_all(cache_dir)
+            .with_context(|| format!("cannot create the grammar cache {:?}", cache_dir))?;
+        // Write to another file first, so a concurrent or interrupted build never leaves a partial file.
+        let temporary = path.with_extension(format!("{}.tmp", std::process::id()));
+        fs::write(&temporary, grammar.to_bytes(&vocabulary)?)
+            .and_then(|_| fs::rename(&temporary, &path))
+            .with_context(|| format!("cannot write the grammar cache {:?}", path))?;
+        Ok(grammar)
+    }
+
+    /// Serialize the grammar, see the [module documentation](self).
+    ///
+    /// `vocabulary` must be the vocabulary the grammar is built with.
+    pub fn to_bytes(&self, vocabulary: &Vocabulary) -> Result<Vec<u8>, Error> {
+        Ok(serde_json::to_vec(&Saved {
+            version: FORMAT_VERSION,
+            vocabulary: fingerprint(vocabulary),
+            grammar: self,
+        })?)
+    }
+
+    /// Deserialize a grammar saved by [`Grammar::to_bytes`] with the same vocabulary.
+    ///
+    /// Returns an error if the bytes are of another [`FORMAT_VERSION`] or another vocabulary,
+    /// or if they are corrupted.
+    pub fn from_bytes(bytes: &[u8], vocabulary: Arc<Vocabulary>) -> Result<Arc<Self>, Error> {
+        let header: Header =
+            serde_json::from_slice(bytes).context("The saved grammar is corrupted.")?;
+        ensure!(
+            header.version == FORMAT_VERSION,
+            "The grammar is saved in the format version {}, but the version {FORMAT_VERSION} is expected. \
+            Build the grammar again.",
+            header.version
+        );
+        ensure!(
+            header.vocabulary == fingerprint(&vocabulary),
+            "The grammar is saved with another vocabulary."
+        );
+        let mut grammar = serde_json::from_slice::<Loaded>(bytes)
+            .context("The saved grammar is corrupted.")?
+            .grammar;
+        grammar.terminals.reindex();
+        grammar
+            .check_ids(&vocabulary)
+            .context("The saved grammar is corrupted.")?;
+        grammar.compute_bounds();
+        Ok(Arc::new(grammar))
+    }
+
+    /// Check every id of a deserialized grammar, so using it cannot index out of bounds.
+    fn check_ids(&self, vocabulary: &Vocabulary) -> Result<(), Error> {
+        self.terminals_trie.validate(&self.terminals)?;
+        let ids: FxHashSet<_> = self.nonterminal_to_terminal_id.values().collect();
+        for id in ids.iter() {
+            ensure!(
+                self.nonterminal_id_to_expression.contains_key(id),
+                "The nonterminal {} has no expressions.",
+                id.0
+            );
+        }
+        for (id, expressions) in self.nonterminal_id_to_expression.iter() {
+            ensure!(ids.contains(&id), "The nonterminal {} has no name.", id.0);
+            match expressions {
+                SimplifiedExpressions::Expressions(expressions) => {
+                    for term in expressions.iter().flatten() {
+                        match term {
+                            U8Term::Terminal(terminal) => ensure!(
+                                terminal.0 < self.terminals.len(),
+                                "The terminal {} is not interned.",
+                                terminal.0
+                            ),
+                            U8Term::Nonterminal(nonterminal) => ensure!(
+                                self.nonterminal_to_terminal_id.contains_key(nonterminal),
+                                "Nonterminal string <{nonterminal}> is not defined."
+                            ),
+                        }
+                    }
+                }
+                SimplifiedExpressions::Terminals(root) => ensure!(
+                    self.terminals_trie.roots.get(id) == Some(root),
+                    "The nonterminal {} has an invalid terminals trie.",
+                    id.0
+                ),
+            }
+        }
+        let internal_len = vocabulary.internal_len();
+        for (id, token_ids) in self.nonterminal_to_token_ids.iter() {
+            ensure!(
+                self.nonterminal_id_to_expression.contains_key(id),
+                "The tokens of the nonterminal {} have no expressions.",
+                id.0
+            );
+            if let Some(x) = token_ids.iter().find(|x| *x >= internal_len) {
+                bail!("The token id {x} is not in the vocabulary.");
+            }
+        }
+        Ok(())
+    }
+}
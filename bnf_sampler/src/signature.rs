@@ -0,0 +1,122 @@
+//! Bloom-like 64-bit signatures of byte values, which let the sampler reject a token with a single AND
+//! before matching it against a stack.
+//!
+//! A byte sets one of the 64 bits, so several bytes share a bit. The signature of a stack is a superset of the
+//! bytes any token matched from the stack can contain, hence a token with a bit outside it cannot be matched,
+//! while a token passing the filter may still be rejected by the matching.
+use crate::grammar::{Grammar, SimplifiedExpressions, U8Term};
+use crate::sampler::StackItem;
+use crate::trie::{TerminalsTrie, TrieNodeID};
+use crate::utils::NonterminalID;
+use rustc_hash::FxHashMap;
+
+/// The bit of `byte` in a signature.
+///
+/// Fibonacci hashing spreads runs of bytes like digits and lowercase letters over different bits.
+fn byte_bit(byte: u8) -> u64 {
+    1 << ((byte as u64).wrapping_mul(0x9E37_79B9_7F4A_7C15) >> 58)
+}
+
+/// The signature of the byte values in `bytes`.
+pub(crate) fn byte_signature(bytes: &[u8]) -> u64 {
+    bytes
+        .iter()
+        .fold(0, |signature, byte| signature | byte_bit(*byte))
+}
+
+/// Computes the signature of the bytes a stack can still match, see the module documentation.
+#[derive(Clone, Debug, Default)]
+pub(crate) struct SignatureFilter {
+    nonterminals: FxHashMap<NonterminalID, u64>,
+    nodes: FxHashMap<TrieNodeID, u64>,
+}
+
+impl SignatureFilter {
+    pub fn new(grammar: &Grammar) -> Self {
+        let mut filter = SignatureFilter::default();
+        for (id, expressions) in grammar.nonterminal_id_to_expression.iter() {
+            if let SimplifiedExpressions::Terminals(node_id) = expressions {
+                let signature = filter.node(&grammar.terminals_trie, *node_id);
+                filter.nonterminals.insert(*id, signature);
+            }
+        }
+        // Signatures only gain bits, so they stabilize once a round changes nothing.
+        loop {
+            let mut changed = false;
+            for (id, expressions) in grammar.nonterminal_id_to_expression.iter() {
+                let SimplifiedExpressions::Expressions(expressions) = expressions else {
+                    continue;
+                };
+                let mut signature = filter.nonterminals.get(id).copied().unwrap_or(0);
+                for term in expressions.iter().flatten() {
+                    signature |= match term {
+                        U8Term::Terminal(terminal) => {
+                            byte_signature(grammar.terminals.get(*terminal))
+                        }
+                        U8Term::Nonterminal(nonterminal) => grammar
+                            .nonterminal_to_terminal_id
+                            .get(nonterminal)
+                            .and_then(|x| filter.nonterminals.get(x))
+                            .copied()
+                            .unwrap_or(0),
+                    };
+                }
+                if filter.nonterminals.insert(*id, signature) != Some(signature) {
+                    changed = true;
+                }
+            }
+            if !changed {
+                break filter;
+            }
+        }
+    }
+
+    /// The signature of the bytes below `node_id`.
+    fn node(&mut self, trie: &TerminalsTrie, node_id: TrieNodeID) -> u64 {
+        if let Some(signature) = self.nodes.get(&node_id) {
+            return *signature;
+        }
+        let mut signature = 0;
+        let mut nodes = vec![node_id];
+        while let Some(node_id) = nodes.pop() {
+            for (byte, child) in trie.get(node_id).children.iter() {
+                signature |= byte_bit(*byte);
+                nodes.push(*child);
+            }
+        }
+        self.nodes.insert(node_id, signature);
+        signature
+    }
+
+    /// The signature of the bytes a token of at most `max_token_len` bytes can match from the top of `stack`.
+    ///
+    /// Only the items a token can reach are included, like [`crate::sampler::SamplerConfig::cache_key_depth`].
+    pub fn stack(&mut self, grammar: &Grammar, stack: &[StackItem], max_token_len: usize) -> u64 {
+        let mut signature = 0;
+        let mut min_len: usize = 0;
+        for item in stack.iter().rev() {
+            if min_len >= max_token_len {
+                break;
+            }
+            match item {
+                StackItem::Terminal(id, start) => {
+                    let terminal = &grammar.terminals.get(*id)[*start..];
+                    signature |= byte_signature(terminal);
+                    min_len = min_len.saturating_add(terminal.len());
+                }
+                StackItem::Terminals(node_id) => {
+                    signature |= self.node(&grammar.terminals_trie, *node_id);
+                }
+                StackItem::Nonterminal(id) => {
+                    signature |= self.nonterminals.get(id).copied().unwrap_or(u64::MAX);
+                }
+            }
+        }
+        signature
+    }
+
+    /// Whether a token with `token_signature` definitely cannot be matched from a stack with `stack_signature`.
+    pub fn rejects(stack_signature: u64, token_signature: u64) -> bool {
+        token_signature & !stack_signature != 0
+    }
+}
@@ -0,0 +1,1008 @@
+use crate::grammar::TerminalsInterner;
+use crate::json_schema::bnf_terminal;
+use crate::mask::TokenMask;
+use crate::trie::TerminalsTrie;
+use crate::utils;
+use crate::utils::NonterminalID;
+use crate::vocabulary::{TokenClass, Vocabulary};
+use anyhow::{anyhow, bail, ensure, Error};
+use itertools::Itertools;
+use memchr::memmem;
+use regex_automata::dfa::{dense, Automaton, StartKind};
+use regex_automata::{Anchored, Input, MatchKind};
+use rustc_hash::{FxHashMap, FxHashSet};
+use std::fmt::Write;
+
+/// What a special nonterminal like `<name!(args)>` matches, as returned by [`SpecialForm::parse`].
+#[derive(Debug, PartialEq, Clone, Eq)]
+pub enum ParsedForm {
+    /// The tokens selected by [`SpecialForm::build`].
+    Tokens,
+    /// Any token except the tokens accepted by the nonterminal, like `<except!([nonterminal])>`.
+    ///
+    /// The tokens are only known once the rest of the grammar is built, so [`SpecialForm::build`] is not called.
+    ExceptNonterminal(String),
+    /// What `<start>` matches in the BNF rules, like `<regex!('pattern')>`.
+    ///
+    /// The nonterminals defined in `rules` are local to the special nonterminal, while the other nonterminals
+    /// refer to the rules of the schema. `rules` may use special nonterminals, and [`SpecialForm::build`] is not called.
+    Rules { start: String, rules: String },
+}
+
+/// A kind of special nonterminal written as `<name!>` or `<name!(args)>` in the BNF schema.
+///
+/// `<any!>`, `<except!(...)>`, `<except_ci!(...)>`, `<any_except_bytes!(...)>`, `<byte!(...)>`, `<token_class!(...)>`, `<regex!(...)>`, `<char!(...)>`,
+/// `<number!(...)>`, `<decimal!(...)>`, `<date!>`, `<time!>`, `<datetime!>`, `<int!>`, `<float!>`, `<ws!>` and `<string_char!>`
+/// are always registered.
+/// More forms can be registered with [`crate::grammar::GrammarBuildOptions::register_form`].
+pub trait SpecialForm {
+    /// The name before `!`, like `except` in `<except!('a')>`.
+    fn name(&self) -> &str;
+    /// Check the text between the parentheses, which is empty when there are none.
+    ///
+    /// The error is reported as the reason why the nonterminal is invalid.
+    fn parse(&self, args: &str) -> Result<ParsedForm, Error>;
+    /// Select the tokens matched by a nonterminal whose [`SpecialForm::parse`] returns [`ParsedForm::Tokens`].
+    fn build(&self, ctx: &mut GrammarBuildCtx) -> Result<(), Error>;
+}
+
+/// The nonterminal being built by [`SpecialForm::build`] and the tokens it matches.
+pub struct GrammarBuildCtx<'a> {
+    nonterminal: &'a str,
+    args: &'a str,
+    vocabulary: &'a Vocabulary,
+    nonterminal_id: NonterminalID,
+    terminals_trie: &'a mut TerminalsTrie,
+    terminals: &'a mut TerminalsInterner,
+    token_ids: TokenMask,
+}
+
+impl<'a> GrammarBuildCtx<'a> {
+    pub(crate) fn new(
+        nonterminal: &'a str,
+        args: &'a str,
+        vocabulary: &'a Vocabulary,
+        nonterminal_id: NonterminalID,
+        terminals_trie: &'a mut TerminalsTrie,
+        terminals: &'a mut TerminalsInterner,
+    ) -> Self {
+        GrammarBuildCtx {
+            nonterminal,
+            args,
+            vocabulary,
+            nonterminal_id,
+            terminals_trie,
+            terminals,
+            token_ids: TokenMask::new(),
+        }
+    }
+
+    /// The whole nonterminal, like `except!('a')`.
+    pub fn nonterminal(&self) -> &str {
+        self.nonterminal
+    }
+
+    /// The text between the parentheses, which is empty when there are none.
+    pub fn args(&self) -> &str {
+        self.args
+    }
+
+    pub fn vocabulary(&self) -> &Vocabulary {
+        self.vocabulary
+    }
+
+    /// Match the tokens for which `predicate` returns true. Returns the number of such tokens.
+    pub fn add_tokens(&mut self, predicate: impl Fn(&[u8]) -> bool) -> usize {
+        let mut count = 0;
+        for (key, token_id) in self.vocabulary.token_to_id.iter() {
+            if predicate(&key.0) {
+                count += 1;
+                self.token_ids
+                    .insert(self.vocabulary.internal_id(*token_id) as usize);
+                let terminal = self.terminals.intern(&key.0);
+                self.terminals_trie
+                    .add(terminal, self.terminals, self.nonterminal_id, false);
+            }
+        }
+        count
+    }
+
+    /// Match each of `bytes` as a single-byte terminal, whether or not it is a token of the vocabulary.
+    /// Returns the number of the bytes that are tokens.
+    pub fn add_bytes(&mut self, bytes: impl IntoIterator<Item = u8>) -> usize {
+        let mut count = 0;
+        for byte in bytes {
+            if let Some(token_id) = self.vocabulary.token_to_id.get(&[byte][..]) {
+                count += 1;
+                self.token_ids
+                    .insert(self.vocabulary.internal_id(*token_id) as usize);
+            }
+            let terminal = self.terminals.intern(&[byte]);
+            self.terminals_trie
+                .add(terminal, self.terminals, self.nonterminal_id, false);
+        }
+        count
+    }
+
+    /// Match the tokens that contain none of `literals`, and the part of a token before the first literal,
+    /// like `<except!('literal')>`. Returns the number of whole tokens matched.
+    pub fn add_tokens_except_literals(&mut self, literals: &[&[u8]]) -> usize {
+        let token_ids = add_tokens_except_literals(
+            self.terminals_trie,
+            self.terminals,
+            self.nonterminal_id,
+            self.vocabulary,
+            literals,
+            false,
+        );
+        let count = token_ids.len();
+        self.token_ids.union_with(&token_ids);
+        count
+    }
+
+    /// Like [`GrammarBuildCtx::add_tokens_except_literals`], where the literals match in any ASCII casing,
+    /// like `<except_ci!('literal')>`.
+    pub fn add_tokens_except_literals_ignoring_ascii_case(&mut self, literals: &[&[u8]]) -> usize {
+        let token_ids = add_tokens_except_literals(
+            self.terminals_trie,
+            self.terminals,
+            self.nonterminal_id,
+            self.vocabulary,
+            literals,
+            true,
+        );
+        let count = token_ids.len();
+        self.token_ids.union_with(&token_ids);
+        count
+    }
+
+    pub(crate) fn into_token_ids(self) -> Result<TokenMask, Error> {
+        ensure!(
+            self.terminals_trie.roots.contains_key(&self.nonterminal_id),
+            "<{}> is invalid because it matches no token in the vocabulary.",
+            self.nonterminal
+        );
+        Ok(self.token_ids)
+    }
+}
+
+/// Add every token to the trie and exclude the excepted literals from it, in any ASCII casing with `ignore_ascii_case`.
+/// Returns the internal ids of the tokens that contain none of the literals, see [`Vocabulary::is_remapped`].
+pub(crate) fn add_tokens_except_literals(
+    terminals_trie: &mut TerminalsTrie,
+    terminals: &mut TerminalsInterner,
+    nonterminal_id: NonterminalID,
+    vocabulary: &Vocabulary,
+    literals: &[&[u8]],
+    ignore_ascii_case: bool,
+) -> TokenMask {
+    let mut token_ids = TokenMask::new();
+    let lowercase_literals = literals
+        .iter()
+        .map(|x| x.to_ascii_lowercase())
+        .collect_vec();
+    for (key, token_id) in vocabulary.token_to_id.iter() {
+        let terminal = terminals.intern(&key.0);
+        terminals_trie.add(terminal, terminals, nonterminal_id, false);
+        let contains_literal = if ignore_ascii_case {
+            let token = key.0.to_ascii_lowercase();
+            lowercase_literals
+                .iter()
+                .any(|x| memmem::find(&token, x).is_some())
+        } else {
+            literals.iter().any(|x| memmem::find(&key.0, x).is_some())
+        };
+        if !contains_literal {
+            token_ids.insert(vocabulary.internal_id(*token_id) as usize);
+        }
+    }
+    for literal in literals {
+        terminals_trie.except_literal(literal, nonterminal_id, ignore_ascii_case);
+    }
+    token_ids
+}
+
+/// `<any!>`, and `<any!(<=N)>` or `<any!(==N)>`, which match at most or exactly N bytes of any tokens.
+///
+/// A bounded `<any!>` expands to a rule per remaining byte, so a token crossing the bound is split there
+/// and its remaining bytes are matched by the rest of the grammar. A multibyte character may be cut at the bound.
+/// `>=N` is not supported, since `>` ends the nonterminal.
+pub(crate) struct AnyForm;
+
+impl AnyForm {
+    /// The rules of a bounded `<any!>`, where `<sK>` matches the bytes after the first K bytes.
+    fn bounded_rules(args: &str) -> Result<String, Error> {
+        let (exact, bound) = match (args.strip_prefix("<="), args.strip_prefix("==")) {
+            (Some(bound), _) => (false, bound),
+            (_, Some(bound)) => (true, bound),
+            _ => bail!("({args}) is not a bound like <=32 or ==32."),
+        };
+        let bound: usize = bound
+            .trim()
+            .parse()
+            .map_err(|_| anyhow!("({args}) is not a bound like <=32 or ==32."))?;
+        ensure!(bound > 0, "the bound {bound} is not at least 1.");
+        let mut rules = String::new();
+        for k in 0..bound {
+            let alternatives = match (k + 1 == bound, exact) {
+                (true, _) => "<byte>".to_string(),
+                (false, true) => format!("<byte><s{}>", k + 1),
+                (false, false) => format!("<byte>|<byte><s{}>", k + 1),
+            };
+            writeln!(rules, "<s{k}>::={alternatives}").unwrap();
+        }
+        let bytes = (0..=u8::MAX).map(|x| bnf_terminal(&[x])).join("|");
+        writeln!(rules, "<byte>::={bytes}").unwrap();
+        Ok(rules)
+    }
+}
+
+impl SpecialForm for AnyForm {
+    fn name(&self) -> &str {
+        "any"
+    }
+
+    fn parse(&self, args: &str) -> Result<ParsedForm, Error> {
+        if args.is_empty() {
+            return Ok(ParsedForm::Tokens);
+        }
+        Ok(ParsedForm::Rules {
+            start: "s0".to_string(),
+            rules: Self::bounded_rules(args)?,
+        })
+    }
+
+    fn build(&self, ctx: &mut GrammarBuildCtx) -> Result<(), Error> {
+        ctx.add_tokens(|_| true);
+        Ok(())
+    }
+}
+
+/// `<except!('literal')>`, `<except!('a', x"hex")>` and `<except!([nonterminal])>`
+pub(crate) struct ExceptForm;
+
+impl ExceptForm {
+    fn literal(args: &str) -> Result<Vec<u8>, Error> {
+        if let Some(hex) = args
+            .strip_prefix("x\"")
+            .or_else(|| args.strip_prefix("x'"))
+            .and_then(|x| x.strip_suffix(['"', '\'']))
+        {
+            return utils::parse_hex_literal(hex);
+        }
+        let literal = args
+            .strip_prefix(['"', '\''])
+            .and_then(|x| x.strip_suffix(['"', '\'']))
+            .ok_or_else(|| {
+                anyhow!("({args}) is not a quoted literal, a hex literal or a [nonterminal].")
+            })?;
+        ensure!(!literal.is_empty(), "the brackets contain nothing.");
+        utils::fix_utf8_escape(literal)
+    }
+
+    /// The comma separated literals, where a comma inside quotes is part of a literal.
+    fn literals(args: &str) -> Result<Vec<Vec<u8>>, Error> {
+        let mut literals = vec![];
+        let mut rest = args.trim();
+        loop {
+            let start = rest.strip_prefix('x').unwrap_or(rest);
+            let end = match start.chars().next() {
+                Some(quote @ ('"' | '\'')) => {
+                    let mut escaped = false;
+                    start[1..]
+                        .char_indices()
+                        .find(|(_, x)| {
+                            let closes = !escaped && *x == quote;
+                            escaped = !escaped && *x == '\\';
+                            closes
+                        })
+                        .map(|(i, _)| rest.len() - start.len() + i + 2)
+                }
+                _ => None,
+            }
+            .unwrap_or(rest.find(',').unwrap_or(rest.len()));
+            literals.push(Self::literal(&rest[..end])?);
+            rest = rest[end..].trim_start();
+            if rest.is_empty() {
+                return Ok(literals);
+            }
+            rest = rest
+                .strip_prefix(',')
+                .ok_or_else(|| anyhow!("the literals ({args}) are not separated by commas."))?
+                .trim_start();
+        }
+    }
+}
+
+impl SpecialForm for ExceptForm {
+    fn name(&self) -> &str {
+        "except"
+    }
+
+    fn parse(&self, args: &str) -> Result<ParsedForm, Error> {
+        ensure!(!args.is_empty(), "the brackets contain nothing.");
+        if let Some(nonterminal) = args.strip_prefix('[').and_then(|x| x.strip_suffix(']')) {
+            ensure!(!nonterminal.is_empty(), "the brackets contain nothing.");
+            return Ok(ParsedForm::ExceptNonterminal(nonterminal.to_string()));
+        }
+        Self::literals(args)?;
+        Ok(ParsedForm::Tokens)
+    }
+
+    fn build(&self, ctx: &mut GrammarBuildCtx) -> Result<(), Error> {
+        let literals = Self::literals(ctx.args())?;
+        ctx.add_tokens_except_literals(&literals.iter().map(|x| &x[..]).collect_vec());
+        Ok(())
+    }
+}
+
+/// `<except_ci!('literal')>`, which excepts the literals like `<except!(...)>` in any ASCII casing.
+pub(crate) struct ExceptCiForm;
+
+impl SpecialForm for ExceptCiForm {
+    fn name(&self) -> &str {
+        "except_ci"
+    }
+
+    fn parse(&self, args: &str) -> Result<ParsedForm, Error> {
+        ensure!(!args.is_empty(), "the brackets contain nothing.");
+        ExceptForm::literals(args)?;
+        Ok(ParsedForm::Tokens)
+    }
+
+    fn build(&self, ctx: &mut GrammarBuildCtx) -> Result<(), Error> {
+        let literals = ExceptForm::literals(ctx.args())?;
+        ctx.add_tokens_except_literals_ignoring_ascii_case(
+            &literals.iter().map(|x| &x[..]).collect_vec(),
+        );
+        Ok(())
+    }
+}
+
+/// `<any_except_bytes!(0x0A, 0x22)>`
+pub(crate) struct AnyExceptBytesForm;
+
+impl SpecialForm for AnyExceptBytesForm {
+    fn name(&self) -> &str {
+        "any_except_bytes"
+    }
+
+    fn parse(&self, args: &str) -> Result<ParsedForm, Error> {
+        utils::parse_excepted_bytes(args)?;
+        Ok(ParsedForm::Tokens)
+    }
+
+    fn build(&self, ctx: &mut GrammarBuildCtx) -> Result<(), Error> {
+        let excepted_bytes = utils::parse_excepted_bytes(ctx.args())?;
+        // Only the tokens without the excepted bytes are added to the trie,
+        // so no path through the trie can contain them.
+        let count = ctx.add_tokens(|token| token.iter().all(|x| !excepted_bytes.contains(x)));
+        ensure!(
+            count > 0,
+            "<{}> is invalid because every token in the vocabulary contains an excepted byte.",
+            ctx.nonterminal()
+        );
+        Ok(())
+    }
+}
+
+/// `<byte!(0x00-0x1F)>`, which matches a single byte in any of the comma separated ranges,
+/// like `<byte!(0x30-0x39,0x41-0x46)>`. A range may also be a single byte like `0x0A`.
+pub(crate) struct ByteForm;
+
+impl ByteForm {
+    /// The bytes of the ranges in ascending order, without duplicates.
+    fn bytes(args: &str) -> Result<Vec<u8>, Error> {
+        ensure!(!args.trim().is_empty(), "no byte range is given.");
+        let mut bytes = vec![];
+        for range in args.split(',') {
+            let (low, high) = match range.split_once('-') {
+                Some((low, high)) => (
+                    utils::parse_excepted_bytes(low)?[0],
+                    utils::parse_excepted_bytes(high)?[0],
+                ),
+                None => {
+                    let byte = utils::parse_excepted_bytes(range)?[0];
+                    (byte, byte)
+                }
+            };
+            ensure!(
+                low <= high,
+                "the range {} is reversed, so it would match no byte.",
+                range.trim()
+            );
+            bytes.extend(low..=high);
+        }
+        bytes.sort_unstable();
+        bytes.dedup();
+        Ok(bytes)
+    }
+}
+
+impl SpecialForm for ByteForm {
+    fn name(&self) -> &str {
+        "byte"
+    }
+
+    fn parse(&self, args: &str) -> Result<ParsedForm, Error> {
+        Self::bytes(args)?;
+        Ok(ParsedForm::Tokens)
+    }
+
+    fn build(&self, ctx: &mut GrammarBuildCtx) -> Result<(), Error> {
+        let bytes = Self::bytes(ctx.args())?;
+        ctx.add_bytes(bytes);
+        Ok(())
+    }
+}
+
+/// `<token_class!(alphabetic_only)>`, which matches the tokens of a [`TokenClass`].
+pub(crate) struct TokenClassForm;
+
+impl SpecialForm for TokenClassForm {
+    fn name(&self) -> &str {
+        "token_class"
+    }
+
+    fn parse(&self, args: &str) -> Result<ParsedForm, Error> {
+        args.trim().parse::<TokenClass>()?;
+        Ok(ParsedForm::Tokens)
+    }
+
+    fn build(&self, ctx: &mut GrammarBuildCtx) -> Result<(), Error> {
+        let class: TokenClass = ctx.args().trim().parse()?;
+        ctx.add_tokens(|token| class.contains(token));
+        Ok(())
+    }
+}
+
+/// `<regex!('pattern')>`, which matches the strings matched entirely by the pattern.
+///
+/// The pattern is written as in the `regex` crate, without unescaping, and is compiled into a byte level DFA
+/// whose states become rules. `>` cannot appear in a nonterminal, so it should be written as `\x3E`.
+pub(crate) struct RegexForm;
+
+impl RegexForm {
+    fn rules(pattern: &str) -> Result<String, Error> {
+        let dfa = dense::Builder::new()
+            .configure(
+                dense::Config::new()
+                    .start_kind(StartKind::Anchored)
+                    .match_kind(MatchKind::All)
+                    .minimize(true),
+            )
+            .build(pattern)
+            .map_err(|e| {
+                let mut reason = e.to_string();
+                let mut source = std::error::Error::source(&e);
+                while let Some(e) = source {
+                    write!(reason, ": {e}").unwrap();
+                    source = e.source();
+                }
+                anyhow!("the pattern {pattern} cannot be compiled into a DFA: {reason}")
+            })?;
+        let start = dfa.start_state_forward(&Input::new("").anchored(Anchored::Yes))?;
+        // Number the reachable states with the start state first.
+        let mut states = vec![start];
+        let mut indices = FxHashMap::from_iter([(start, 0)]);
+        let mut transitions: Vec<Vec<(usize, u8)>> = vec![];
+        let mut accepting = vec![];
+        while transitions.len() < states.len() {
+            let state = states[transitions.len()];
+            // A match is reported one transition late, so the input ending here matches if the end of input leads to a match.
+            accepting.push(dfa.is_match_state(dfa.next_eoi_state(state)));
+            let mut edges = vec![];
+            for byte in 0..=u8::MAX {
+                let next = dfa.next_state(state, byte);
+                if dfa.is_dead_state(next) || dfa.is_quit_state(next) {
+                    continue;
+                }
+                let index = *indices.entry(next).or_insert_with(|| {
+                    states.push(next);
+                    states.len() - 1
+                });
+                edges.push((index, byte));
+            }
+            transitions.push(edges);
+        }
+        ensure!(
+            !accepting[0],
+            "the pattern {pattern} matches the empty string."
+        );
+        // Whether a nonempty string leads from the state to a match.
+        let mut productive = vec![false; states.len()];
+        let mut changed = true;
+        while changed {
+            changed = false;
+            for (state, edges) in transitions.iter().enumerate() {
+                if !productive[state]
+                    && edges
+                        .iter()
+                        .any(|(next, _)| accepting[*next] || productive[*next])
+                {
+                    productive[state] = true;
+                    changed = true;
+                }
+            }
+        }
+        ensure!(productive[0], "the pattern {pattern} matches nothing.");
+        // <sN> matches the rest of the string from state N, and <cN> matches one byte of a set,
+        // so a set of bytes leading to the same state is matched by the terminals trie.
+        let mut rules = String::new();
+        let mut classes: Vec<Vec<u8>> = vec![];
+        for (state, edges) in transitions.iter().enumerate() {
+            if !productive[state] {
+                continue;
+            }
+            let mut alternatives = vec![];
+            for (next, edges) in &edges.iter().sorted().group_by(|(next, _)| *next) {
+                if !accepting[next] && !productive[next] {
+                    continue;
+                }
+                let bytes = edges.map(|(_, byte)| *byte).collect_vec();
+                let head = if bytes.len() == 1 {
+                    bnf_terminal(&bytes)
+                } else {
+                    let class = classes.iter().position(|x| *x == bytes).unwrap_or_else(|| {
+                        classes.push(bytes);
+                        classes.len() - 1
+                    });
+                    format!("<c{class}>")
+                };
+                if accepting[next] {
+                    alternatives.push(head.clone());
+                }
+                if productive[next] {
+                    alternatives.push(format!("{head}<s{next}>"));
+                }
+            }
+            writeln!(rules, "<s{state}>::={}", alternatives.join("|")).unwrap();
+        }
+        for (class, bytes) in classes.iter().enumerate() {
+            let alternatives = bytes.iter().map(|x| bnf_terminal(&[*x])).join("|");
+            writeln!(rules, "<c{class}>::={alternatives}").unwrap();
+        }
+        Ok(rules)
+    }
+}
+
+impl SpecialForm for RegexForm {
+    fn name(&self) -> &str {
+        "regex"
+    }
+
+    fn parse(&self, args: &str) -> Result<ParsedForm, Error> {
+        let pattern = args
+            .strip_prefix('\'')
+            .and_then(|x| x.strip_suffix('\''))
+            .or_else(|| args.strip_prefix('"').and_then(|x| x.strip_suffix('"')))
+            .ok_or_else(|| anyhow!("({args}) is not a quoted pattern."))?;
+        Ok(ParsedForm::Rules {
+            start: "s0".to_string(),
+            rules: Self::rules(pattern)?,
+        })
+    }
+
+    fn build(&self, _: &mut GrammarBuildCtx) -> Result<(), Error> {
+        unreachable!("<regex!(...)> expands to rules.")
+    }
+}
+
+/// `<char!(U+4E00-U+9FFF, U+3041)>`, which matches one character in any of the comma separated code point ranges.
+///
+/// The UTF-8 encodings of a range are not one byte range, so the ranges are compiled like `<regex!(...)>`,
+/// whose rules share the terminals trie between characters with the same leading bytes.
+pub(crate) struct CharForm;
+
+impl CharForm {
+    fn code_point(x: &str) -> Result<char, Error> {
+        let hex = x
+            .trim()
+            .strip_prefix("U+")
+            .ok_or_else(|| anyhow!("{x} is not a code point like U+4E00."))?;
+        let code_point = u32::from_str_radix(hex, 16)
+            .map_err(|_| anyhow!("{x} is not a code point like U+4E00."))?;
+        char::from_u32(code_point)
+            .ok_or_else(|| anyhow!("{} is not a Unicode scalar value.", x.trim()))
+    }
+
+    /// The ranges as a class of the `regex` crate, like `[\x{4E00}-\x{9FFF}]`.
+    fn class(args: &str) -> Result<String, Error> {
+        ensure!(!args.trim().is_empty(), "no code point range is given.");
+        let mut class = String::from("[");
+        for range in args.split(',') {
+            let (low, high) = match range.split_once('-') {
+                Some((low, high)) => (Self::code_point(low)?, Self::code_point(high)?),
+                None => (Self::code_point(range)?, Self::code_point(range)?),
+            };
+            ensure!(low <= high, "the range {} is reversed.", range.trim());
+            write!(class, "\\x{{{:X}}}-\\x{{{:X}}}", low as u32, high as u32).unwrap();
+        }
+        class.push(']');
+        Ok(class)
+    }
+}
+
+impl SpecialForm for CharForm {
+    fn name(&self) -> &str {
+        "char"
+    }
+
+    fn parse(&self, args: &str) -> Result<ParsedForm, Error> {
+        Ok(ParsedForm::Rules {
+            start: "s0".to_string(),
+            rules: RegexForm::rules(&Self::class(args)?)?,
+        })
+    }
+
+    fn build(&self, _: &mut GrammarBuildCtx) -> Result<(), Error> {
+        unreachable!("<char!(...)> expands to rules.")
+    }
+}
+
+/// The rules matching numbers digit by digit.
+#[derive(Default)]
+struct DigitRules {
+    rules: Vec<String>,
+    nonterminals: FxHashSet<String>,
+}
+
+impl DigitRules {
+    /// The digits from `first` to `last`, as a terminal or a nonterminal.
+    fn digits(&mut self, first: u8, last: u8) -> String {
+        if first == last {
+            return format!("'{}'", first as char);
+        }
+        let nonterminal = format!("d{}{}", first as char, last as char);
+        if self.nonterminals.insert(nonterminal.clone()) {
+            let alternatives = (first..=last).map(|x| format!("'{}'", x as char)).join("|");
+            self.rules.push(format!("<{nonterminal}>::={alternatives}"));
+        }
+        format!("<{nonterminal}>")
+    }
+
+    /// A nonterminal matching `min` to `max` digits, where `0 < min <= max`. `usize::MAX` means no maximum.
+    fn digit_string(&mut self, min: usize, max: usize) -> String {
+        let nonterminal = format!("n{min}_{max}");
+        if self.nonterminals.insert(nonterminal.clone()) {
+            let digit = self.digits(b'0', b'9');
+            let mut alternatives = vec![];
+            if min <= 1 {
+                alternatives.push(digit.clone());
+            }
+            if max > 1 {
+                let rest_max = if max == usize::MAX { max } else { max - 1 };
+                alternatives
+                    .push(digit + &self.digit_string(min.saturating_sub(1).max(1), rest_max));
+            }
+            self.rules
+                .push(format!("<{nonterminal}>::={}", alternatives.join("|")));
+        }
+        format!("<{nonterminal}>")
+    }
+
+    /// A nonterminal matching the digit strings from `low` to `high` of the same length.
+    fn nonterminal(&mut self, low: &[u8], high: &[u8]) -> String {
+        let nonterminal = format!(
+            "r{}_{}",
+            std::str::from_utf8(low).unwrap(),
+            std::str::from_utf8(high).unwrap()
+        );
+        if self.nonterminals.insert(nonterminal.clone()) {
+            let alternatives = self.alternatives(low, high).join("|");
+            self.rules.push(format!("<{nonterminal}>::={alternatives}"));
+        }
+        format!("<{nonterminal}>")
+    }
+
+    /// The alternatives matching the digit strings from `low` to `high` of the same nonzero length.
+    fn alternatives(&mut self, low: &[u8], high: &[u8]) -> Vec<String> {
+        let (first, last) = (low[0], high[0]);
+        if low.len() == 1 {
+            return vec![self.digits(first, last)];
+        }
+        let (low, high) = (&low[1..], &high[1..]);
+        let zeros = vec![b'0'; low.len()];
+        let nines = vec![b'9'; low.len()];
+        if first == last || (low == zeros && high == nines) {
+            return vec![self.digits(first, last) + &self.nonterminal(low, high)];
+        }
+        let mut alternatives = vec![self.digits(first, first) + &self.nonterminal(low, &nines)];
+        if first + 1 < last {
+            alternatives.push(self.digits(first + 1, last - 1) + &self.nonterminal(&zeros, &nines));
+        }
+        alternatives.push(self.digits(last, last) + &self.nonterminal(&zeros, high));
+        alternatives
+    }
+
+    /// The alternatives matching the natural numbers from `low` to `high` without leading zeros.
+    fn naturals(&mut self, low: u64, high: u64) -> Vec<String> {
+        let (low, high) = (low.to_string(), high.to_string());
+        let mut alternatives = vec![];
+        for len in low.len()..=high.len() {
+            let first = if len == low.len() {
+                low.clone()
+            } else {
+                format!("1{}", "0".repeat(len - 1))
+            };
+            let last = if len == high.len() {
+                high.clone()
+            } else {
+                "9".repeat(len)
+            };
+            alternatives.extend(self.alternatives(first.as_bytes(), last.as_bytes()));
+        }
+        alternatives
+    }
+}
+
+/// `<number!(min, max)>`, which matches the integers from `min` to `max` inclusive, without leading zeros or `-0`.
+pub(crate) struct NumberForm;
+
+impl NumberForm {
+    fn bounds(args: &str) -> Result<(i64, i64), Error> {
+        let (min, max) = args
+            .split_once(',')
+            .ok_or_else(|| anyhow!("({args}) is not two comma separated integers."))?;
+        let (min, max): (i64, i64) = (min.trim().parse()?, max.trim().parse()?);
+        ensure!(
+            min <= max,
+            "the minimum {min} is greater than the maximum {max}."
+        );
+        Ok((min, max))
+    }
+}
+
+impl SpecialForm for NumberForm {
+    fn name(&self) -> &str {
+        "number"
+    }
+
+    fn parse(&self, args: &str) -> Result<ParsedForm, Error> {
+        let (min, max) = Self::bounds(args)?;
+        let mut rules = DigitRules::default();
+        let mut alternatives = vec![];
+        if min < 0 {
+            let low = if max < 0 { max.unsigned_abs() } else { 1 };
+            for alternative in rules.naturals(low, min.unsigned_abs()) {
+                alternatives.push(format!("'-'{alternative}"));
+            }
+        }
+        if max >= 0 {
+            alternatives.extend(rules.naturals(min.max(0) as u64, max as u64));
+        }
+        rules
+            .rules
+            .push(format!("<start>::={}", alternatives.join("|")));
+        Ok(ParsedForm::Rules {
+            start: "start".to_string(),
+            rules: rules.rules.join("\n"),
+        })
+    }
+
+    fn build(&self, _: &mut GrammarBuildCtx) -> Result<(), Error> {
+        unreachable!("<number!(...)> expands to rules.")
+    }
+}
+
+/// `<decimal!(int_digits, frac_digits, flags...)>`, which matches an optional `-`, at most `int_digits` digits
+/// without leading zeros, `.` and at most `frac_digits` digits.
+///
+/// The flags are `exact` for exactly `frac_digits` fraction digits, `optional` to allow omitting the fraction,
+/// and `exponent` to allow an exponent like `e-5` after the number.
+pub(crate) struct DecimalForm;
+
+impl SpecialForm for DecimalForm {
+    fn name(&self) -> &str {
+        "decimal"
+    }
+
+    fn parse(&self, args: &str) -> Result<ParsedForm, Error> {
+        let mut args = args.split(',').map(str::trim);
+        let mut digits = |kind| -> Result<usize, Error> {
+            let digits: usize = args
+                .next()
+                .filter(|x| !x.is_empty())
+                .ok_or_else(|| anyhow!("the maximum number of {kind} digits is missing."))?
+                .parse()?;
+            ensure!(
+                digits > 0,
+                "the maximum number of {kind} digits should be positive."
+            );
+            Ok(digits)
+        };
+        let int_digits = digits("integer")?;
+        let frac_digits = digits("fraction")?;
+        let (mut exact, mut optional, mut exponent) = (false, false, false);
+        for flag in args {
+            match flag {
+                "exact" => exact = true,
+                "optional" => optional = true,
+                "exponent" => exponent = true,
+                _ => bail!("{flag} is not one of exact, optional and exponent."),
+            }
+        }
+        let mut rules = DigitRules::default();
+        let mut integer = vec!["'0'".to_string(), rules.digits(b'1', b'9')];
+        if int_digits > 1 {
+            integer.push(rules.digits(b'1', b'9') + &rules.digit_string(1, int_digits - 1));
+        }
+        rules
+            .rules
+            .push(format!("<integer>::={}", integer.join("|")));
+        let min_frac_digits = if exact { frac_digits } else { 1 };
+        let fraction = rules.digit_string(min_frac_digits, frac_digits);
+        let mut unsigned = vec![format!("<integer>'.'{fraction}")];
+        if optional {
+            unsigned.push("<integer>".to_string());
+        }
+        rules
+            .rules
+            .push(format!("<unsigned>::={}", unsigned.join("|")));
+        let mut alternatives = vec!["<unsigned>".to_string(), "'-'<unsigned>".to_string()];
+        if exponent {
+            let digits = rules.digit_string(1, usize::MAX);
+            rules.rules.push(format!(
+                "<exponent>::=<e>{digits}|<e>'+'{digits}|<e>'-'{digits}\n<e>::='e'|'E'"
+            ));
+            alternatives = alternatives
+                .into_iter()
+                .flat_map(|x| [x.clone(), x + "<exponent>"])
+                .collect();
+        }
+        rules
+            .rules
+            .push(format!("<start>::={}", alternatives.join("|")));
+        Ok(ParsedForm::Rules {
+            start: "start".to_string(),
+            rules: rules.rules.join("\n"),
+        })
+    }
+
+    fn build(&self, _: &mut GrammarBuildCtx) -> Result<(), Error> {
+        unreachable!("<decimal!(...)> expands to rules.")
+    }
+}
+
+/// `<date!>` matching `YYYY-MM-DD`, `<time!>` matching `HH:MM:SS` with optional fractional seconds,
+/// and `<datetime!>` matching a date, `T`, a time and an optional `Z` or `+HH:MM` offset, as in ISO 8601.
+///
+/// The day is bounded by the month, except that February always has 29 days.
+pub(crate) struct DateTimeForm(pub(crate) &'static str);
+
+impl DateTimeForm {
+    /// `<hh>` matching 00 to 23 and `<mm>` matching 00 to 59.
+    fn hour_minute_rules(rules: &mut DigitRules) -> String {
+        let d09 = rules.digits(b'0', b'9');
+        format!(
+            "<hh>::={}{d09}|'2'{}\n<mm>::={}{d09}",
+            rules.digits(b'0', b'1'),
+            rules.digits(b'0', b'3'),
+            rules.digits(b'0', b'5')
+        )
+    }
+}
+
+impl SpecialForm for DateTimeForm {
+    fn name(&self) -> &str {
+        self.0
+    }
+
+    fn parse(&self, args: &str) -> Result<ParsedForm, Error> {
+        ensure!(args.is_empty(), "{}! takes no arguments.", self.0);
+        let mut rules = DigitRules::default();
+        let d09 = rules.digits(b'0', b'9');
+        let start = match self.0 {
+            "date" => format!(
+                "<start>::={d09}{d09}{d09}{d09}'-'<month_day>
+<month_day>::='02-'<day29>|<month31>'-'<day31>|<month30>'-'<day30>
+<month31>::='01'|'03'|'05'|'07'|'08'|'10'|'12'
+<month30>::='04'|'06'|'09'|'11'
+<day29>::='0'{}|'1'{d09}|'2'{d09}
+<day30>::=<day29>|'30'
+<day31>::=<day29>|'30'|'31'",
+                rules.digits(b'1', b'9')
+            ),
+            "time" => format!(
+                "<start>::=<hh>':'<mm>':'<mm>|<hh>':'<mm>':'<mm>'.'{}
+{}",
+                rules.digit_string(1, usize::MAX),
+                Self::hour_minute_rules(&mut rules)
+            ),
+            _ => format!(
+                "<start>::=<date!>'T'<time!>|<date!>'T'<time!><offset>
+<offset>::='Z'|'+'<hh>':'<mm>|'-'<hh>':'<mm>
+{}",
+                Self::hour_minute_rules(&mut rules)
+            ),
+        };
+        rules.rules.push(start);
+        Ok(ParsedForm::Rules {
+            start: "start".to_string(),
+            rules: rules.rules.join("\n"),
+        })
+    }
+
+    fn build(&self, _: &mut GrammarBuildCtx) -> Result<(), Error> {
+        unreachable!("<{}!> expands to rules.", self.0)
+    }
+}
+
+/// The helper nonterminals a rule of the BNF schema may define, in which case the rule replaces the form
+/// and [`crate::grammar::Grammar::validate`] warns about it.
+pub(crate) const HELPER_FORMS: [&str; 4] = ["int", "float", "ws", "string_char"];
+
+/// The helper nonterminals of JSON: `<int!>` matching an integer like `-12` without leading zeros,
+/// `<float!>` matching a number like `-0.5e+10` whose fraction and exponent are optional,
+/// `<ws!>` matching any number of spaces, tabs and line breaks, and `<string_char!>` matching
+/// one character of a string, which is any byte but `"`, `\` and control characters, or an escape like `\n` or `\u00e9`.
+pub(crate) struct HelperForm(pub(crate) &'static str);
+
+impl HelperForm {
+    /// `<int>` matching an optional `-` and digits without leading zeros.
+    fn int_rules(rules: &mut DigitRules) -> String {
+        let d19 = rules.digits(b'1', b'9');
+        let digits = rules.digit_string(1, usize::MAX);
+        format!("<int>::=<unsigned>|'-'<unsigned>\n<unsigned>::='0'|{d19}|{d19}{digits}")
+    }
+}
+
+impl SpecialForm for HelperForm {
+    fn name(&self) -> &str {
+        self.0
+    }
+
+    fn parse(&self, args: &str) -> Result<ParsedForm, Error> {
+        ensure!(args.is_empty(), "{}! takes no arguments.", self.0);
+        let mut rules = DigitRules::default();
+        let start = match self.0 {
+            "int" => format!("<start>::=<int>\n{}", Self::int_rules(&mut rules)),
+            "float" => format!(
+                "<start>::=<int>|<int><fraction>|<int><exponent>|<int><fraction><exponent>
+<fraction>::='.'{digits}
+<exponent>::=<e>{digits}|<e>'+'{digits}|<e>'-'{digits}
+<e>::='e'|'E'
+{}",
+                Self::int_rules(&mut rules),
+                digits = rules.digit_string(1, usize::MAX)
+            ),
+            "ws" => "<start>::=''|<byte!(0x09-0x0A,0x0D,0x20)><start>".to_string(),
+            _ => "<start>::=<byte!(0x20-0x21,0x23-0x5B,0x5D-0xFF)>|<byte!(0x5C)><escape>
+<escape>::=<byte!(0x22,0x2F,0x5C,0x62,0x66,0x6E,0x72,0x74)>|'u'<hex><hex><hex><hex>
+<hex>::=<byte!(0x30-0x39,0x41-0x46,0x61-0x66)>"
+                .to_string(),
+        };
+        rules.rules.push(start);
+        Ok(ParsedForm::Rules {
+            start: "start".to_string(),
+            rules: rules.rules.join("\n"),
+        })
+    }
+
+    fn build(&self, _: &mut GrammarBuildCtx) -> Result<(), Error> {
+        unreachable!("<{}!> expands to rules.", self.0)
+    }
+}
+
+/// The forms that are always registered.
+pub(crate) fn builtin_forms() -> Vec<Box<dyn SpecialForm>> {
+    vec![
+        Box::new(AnyForm),
+        Box::new(ExceptForm),
+        Box::new(ExceptCiForm),
+        Box::new(AnyExceptBytesForm),
+        Box::new(ByteForm),
+        Box::new(TokenClassForm),
+        Box::new(RegexForm),
+        Box::new(CharForm),
+        Box::new(NumberForm),
+        Box::new(DecimalForm),
+        Box::new(DateTimeForm("date")),
+        Box::new(DateTimeForm("time")),
+        Box::new(DateTimeForm("datetime")),
+        Box::new(HelperForm("int")),
+        Box::new(HelperForm("float")),
+        Box::new(HelperForm("ws")),
+        Box::new(HelperForm("string_char")),
+    ]
+}
@@ -1,33 +1,90 @@
-use anyhow::{ensure, Error};
+use crate::memory::{SharedAccountant, Tracked};
+use anyhow::Error;
+use std::fmt;
 use std::ops::{Index, RangeTo};
-#[derive(Clone, Debug)]
+
+/// The error of a [`BufferArena`] without room for a requested stack,
+/// returned inside [`anyhow::Error`] so it can be recovered with [`anyhow::Error::downcast_ref`].
+#[derive(Debug, PartialEq, Clone, Copy, Eq)]
+pub(crate) struct ArenaExhausted {
+    pub capacity: usize,
+    pub requested: usize,
+    pub capacity_estimated: bool,
+}
+
+impl fmt::Display for ArenaExhausted {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(
+            f,
+            "Not enough arena capacity: current capacity {} {} is smaller than requested capacity {}. Increase arena_capacity or grammar_arena_capacity.",
+            self.capacity,
+            if self.capacity_estimated {
+                "estimated by estimate_stack_arena_capacity"
+            } else {
+                "set by the user"
+            },
+            self.requested
+        )
+    }
+}
+
+impl std::error::Error for ArenaExhausted {}
+
+#[derive(Debug)]
 pub(crate) struct BufferArena<T: Clone + Copy> {
     arena: Vec<Option<T>>,
     current_ptr: usize,
+    capacity_estimated: bool,
+    /// The number of stacks allocated since the arena was created.
+    pub allocations: usize,
+    memory: Tracked,
 }
 
 impl<T: Clone + Copy> BufferArena<T> {
-    pub fn with_capacity(capacity: usize) -> Self {
+    /// `capacity_estimated` only changes the error message when the arena runs out of capacity.
+    /// The bytes of the arena are reported to `accountant` until it is dropped.
+    pub fn with_capacity(
+        capacity: usize,
+        capacity_estimated: bool,
+        accountant: Option<SharedAccountant>,
+    ) -> Self {
         let mut area = Vec::with_capacity(capacity);
         area.resize(capacity, None);
+        let memory = Tracked::new(accountant, std::mem::size_of_val(area.as_slice()));
         BufferArena {
             arena: area,
             current_ptr: 0,
+            capacity_estimated,
+            allocations: 0,
+            memory,
         }
     }
 
-    pub fn allocate_a_stack(&mut self, capacity: usize) -> Result<FixedBuffer<T>, Error> {
-        ensure!(
-            self.current_ptr + capacity <= self.arena.len(),
-            "Not enough arena capacity: current capacity {} is smaller than requested capacity {}. Increase arena_capacity or grammar_arena_capacity.",
-            self.arena.len(),
-            self.current_ptr + capacity
-        );
+    /// The bytes of the arena.
+    pub fn memory_bytes(&self) -> usize {
+        self.memory.bytes()
+    }
+
+    pub fn allocate_a_stack(&mut self, capacity: usize) -> Result<FixedBuffer<'_, T>, Error> {
+        if self.current_ptr + capacity > self.arena.len() {
+            return Err(ArenaExhausted {
+                capacity: self.arena.len(),
+                requested: self.current_ptr + capacity,
+                capacity_estimated: self.capacity_estimated,
+            }
+            .into());
+        }
         let buffer = &mut self.arena[self.current_ptr..self.current_ptr + capacity];
         self.current_ptr += capacity;
+        self.allocations += 1;
         Ok(FixedBuffer { buffer, top: 0 })
     }
 
+    /// The number of items allocated since the arena was last cleared.
+    pub fn in_use(&self) -> usize {
+        self.current_ptr
+    }
+
     pub fn clear(&mut self) {
         for i in 0..self.current_ptr {
             self.arena[i] = None;
@@ -35,12 +92,22 @@ impl<T: Clone + Copy> BufferArena<T> {
         self.current_ptr = 0;
     }
 }
+// The arena only holds temporary stacks while bytes are matched, so a clone gets an empty arena of the same capacity
+// instead of a copy of whatever a failed match left behind.
+impl<T: Clone + Copy> Clone for BufferArena<T> {
+    fn clone(&self) -> Self {
+        let mut arena = Self::with_capacity(self.arena.len(), self.capacity_estimated, None);
+        arena.memory = self.memory.clone();
+        arena
+    }
+}
 #[derive(Debug, Hash, PartialEq, Eq)]
 pub(crate) struct FixedBuffer<'a, T: Copy> {
     buffer: &'a mut [Option<T>],
     top: usize,
 }
 
+// Indexing out of the stack is an internal invariant violation, so it panics instead of returning an error.
 impl<'a, T: Copy> Index<usize> for FixedBuffer<'a, T> {
     type Output = T;
 
@@ -0,0 +1,167 @@
+use crate::grammar::Grammar;
+use crate::sampler::AcceptTokenResult;
+use crate::utils::NonterminalID;
+use rustc_hash::FxHashMap;
+use std::fmt;
+
+/// The maximum number of split points recorded in a [`TraceReport`].
+const MAX_SPLITS: usize = 256;
+/// The maximum nesting of split points recorded in a [`TraceReport`].
+const MAX_SPLIT_DEPTH: usize = 16;
+
+/// A nonterminal whose expansion leads to more than one stack.
+#[derive(Debug, PartialEq, Clone, Eq)]
+pub struct SplitNode {
+    pub nonterminal: String,
+    /// The index of the first byte matched by the expansions.
+    pub byte_offset: usize,
+    /// The expansions that match the bytes.
+    pub branches: Vec<SplitBranch>,
+}
+
+/// One of the competing expansions of a [`SplitNode`].
+#[derive(Debug, PartialEq, Clone, Eq)]
+pub struct SplitBranch {
+    /// The expansion in BNF syntax.
+    pub expansion: String,
+    /// The split points nested in this expansion.
+    pub splits: Vec<SplitNode>,
+}
+
+/// The result of [`crate::sampler::Sampler::trace_bytes`].
+#[derive(Debug, PartialEq, Clone, Eq)]
+pub struct TraceReport {
+    pub result: AcceptTokenResult,
+    /// The number of distinct stacks after accepting the bytes.
+    pub stack_count: usize,
+    pub splits: Vec<SplitNode>,
+    /// Whether split points are omitted because the report is too large or too deep.
+    pub truncated: bool,
+}
+
+/// Collects the split points while the sampler matches bytes in find all mode.
+pub(crate) struct SplitTracer {
+    nonterminal_names: FxHashMap<NonterminalID, String>,
+    /// The split points found under each expansion being searched, innermost last.
+    frames: Vec<Vec<SplitNode>>,
+    splits: usize,
+    truncated: bool,
+    /// Added to the byte offsets, since the expansions after the bytes are matched start from 0.
+    pub(crate) byte_offset_base: usize,
+}
+
+fn depth(splits: &[SplitNode]) -> usize {
+    splits
+        .iter()
+        .map(|x| {
+            1 + x
+                .branches
+                .iter()
+                .map(|x| depth(&x.splits))
+                .max()
+                .unwrap_or(0)
+        })
+        .max()
+        .unwrap_or(0)
+}
+
+impl SplitTracer {
+    pub fn new(grammar: &Grammar) -> Self {
+        SplitTracer {
+            nonterminal_names: grammar
+                .nonterminal_to_terminal_id
+                .iter()
+                .map(|(k, v)| (*v, k.clone()))
+                .collect(),
+            frames: vec![vec![]],
+            splits: 0,
+            truncated: false,
+            byte_offset_base: 0,
+        }
+    }
+
+    pub fn enter(&mut self) {
+        self.frames.push(vec![]);
+    }
+
+    pub fn exit(&mut self) -> Vec<SplitNode> {
+        self.frames.pop().unwrap_or_default()
+    }
+
+    /// Record the expansions of a nonterminal that match, sorted for a stable report. Fewer than two matching expansions are not a split,
+    /// so the split points nested in the matching expansion, if any, belong to the enclosing expansion.
+    pub fn record(
+        &mut self,
+        nonterminal: NonterminalID,
+        byte_offset: usize,
+        mut branches: Vec<SplitBranch>,
+    ) {
+        let mut node = match branches.len() {
+            0 => return,
+            1 => {
+                let splits = branches.pop().unwrap().splits;
+                self.frames.last_mut().unwrap().extend(splits);
+                return;
+            }
+            _ => {
+                branches.sort_by(|a, b| a.expansion.cmp(&b.expansion));
+                SplitNode {
+                    nonterminal: self.nonterminal_names[&nonterminal].clone(),
+                    byte_offset: self.byte_offset_base + byte_offset,
+                    branches,
+                }
+            }
+        };
+        if self.splits >= MAX_SPLITS {
+            self.truncated = true;
+            return;
+        }
+        for branch in node.branches.iter_mut() {
+            if depth(&branch.splits) >= MAX_SPLIT_DEPTH {
+                branch.splits.clear();
+                self.truncated = true;
+            }
+        }
+        self.splits += 1;
+        self.frames.last_mut().unwrap().push(node);
+    }
+
+    pub fn into_report(mut self, result: AcceptTokenResult, stack_count: usize) -> TraceReport {
+        TraceReport {
+            result,
+            stack_count,
+            splits: self.frames.swap_remove(0),
+            truncated: self.truncated,
+        }
+    }
+}
+
+impl fmt::Display for TraceReport {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        fn write_splits(
+            f: &mut fmt::Formatter<'_>,
+            splits: &[SplitNode],
+            indent: usize,
+        ) -> fmt::Result {
+            for split in splits {
+                writeln!(
+                    f,
+                    "{:indent$}<{}> at byte {}:",
+                    "", split.nonterminal, split.byte_offset
+                )?;
+                for branch in split.branches.iter() {
+                    writeln!(f, "{:indent$}  {}", "", branch.expansion)?;
+                    write_splits(f, &branch.splits, indent + 4)?;
+                }
+            }
+            Ok(())
+        }
+        writeln!(f, "Result: {:?}", self.result)?;
+        writeln!(f, "Stacks: {}", self.stack_count)?;
+        write_splits(f, &self.splits, 0)?;
+        if self.truncated {
+            writeln!(f, "Some split points are omitted.")?;
+        }
+        Ok(())
+    }
+}
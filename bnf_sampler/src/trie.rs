@@ -1,9 +1,15 @@
 use itertools::Itertools;
 use nohash_hasher::BuildNoHashHasher;
-use std::{collections::HashMap, hash::Hash};
+use std::{
+    collections::{HashMap, VecDeque},
+    hash::Hash,
+};
 
+use crate::grammar::TerminalsInterner;
 use crate::utils::NonterminalID;
+use crate::utils::TerminalID;
 #[derive(Clone, Debug)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
 pub(crate) struct TerminalsTrie {
     pub roots: HashMap<NonterminalID, TrieNodeID, BuildNoHashHasher<NonterminalID>>,
     arena: Vec<TrieNode>,
@@ -13,6 +19,7 @@ pub(crate) struct TerminalsTrieIter<'a> {
     initial_index: usize,
     pub stack: Vec<std::collections::hash_map::Iter<'a, u8, TrieNodeID>>,
     trie: &'a TerminalsTrie,
+    terminals: &'a TerminalsInterner,
 }
 
 impl<'a> Iterator for TerminalsTrieIter<'a> {
@@ -30,8 +37,8 @@ impl<'a> Iterator for TerminalsTrieIter<'a> {
                     }
                     Some((_, v)) => {
                         self.stack.push(self.trie.get(*v).children.iter());
-                        if let Some(value) = &self.trie.get(*v).value {
-                            return Some(&value[self.initial_index..]);
+                        if let Some(value) = self.trie.get(*v).value {
+                            return Some(&self.terminals.get(value)[self.initial_index..]);
                         }
                     }
                 },
@@ -56,6 +63,11 @@ impl TerminalsTrie {
         }
     }
 
+    /// The number of nodes of all the tries.
+    pub fn node_count(&self) -> usize {
+        self.arena.len()
+    }
+
     pub fn get(&self, node_id: TrieNodeID) -> &TrieNode {
         &self.arena[node_id.id]
     }
@@ -64,17 +76,27 @@ impl TerminalsTrie {
         &mut self.arena[node_id.id]
     }
 
-    pub fn add(&mut self, terminal: &[u8], nonterminal_id: NonterminalID, can_stop: bool) {
-        let mut current_node_id = *self.roots.entry(nonterminal_id).or_insert(Self::new_node(
-            &mut self.arena,
-            TrieNode {
-                index: 0,
-                negative_bytes_index: None,
-                value: None,
-                children: HashMap::default(),
-                can_stop,
-            },
-        ));
+    /// Add the interned `terminal` under the root of `nonterminal_id`. The node it ends at refers to it by its id.
+    pub fn add(
+        &mut self,
+        terminal_id: TerminalID,
+        terminals: &TerminalsInterner,
+        nonterminal_id: NonterminalID,
+        can_stop: bool,
+    ) {
+        let terminal = terminals.get(terminal_id);
+        let mut current_node_id = *self.roots.entry(nonterminal_id).or_insert_with(|| {
+            Self::new_node(
+                &mut self.arena,
+                TrieNode {
+                    index: 0,
+                    negative_bytes_index: None,
+                    value: None,
+                    children: HashMap::default(),
+                    can_stop,
+                },
+            )
+        });
         for i in terminal {
             let matched_child_node = self.get(current_node_id).children.get(i);
             match matched_child_node {
@@ -98,21 +120,37 @@ impl TerminalsTrie {
                 }
             }
         }
-        let mut temp = Vec::with_capacity(terminal.len());
-        temp.extend_from_slice(terminal);
-        self.get_mut(current_node_id).value = Some(temp.into_boxed_slice());
+        let node = self.get_mut(current_node_id);
+        if node.value.is_none() {
+            node.value = Some(terminal_id);
+        }
     }
 
-    pub fn except_literal(&mut self, terminal: &[u8], nonterminal_id: NonterminalID) {
+    /// Mark the nodes where `terminal` ends, so matching stops before the literal.
+    ///
+    /// The matched length of the literal follows the failure links of the Knuth-Morris-Pratt algorithm,
+    /// so a literal overlapping itself like `aab` in `aaab` is found. When several literals end at a node,
+    /// the longest one is kept, since matching stops before the earliest start.
+    ///
+    /// With `ignore_ascii_case`, every ASCII casing of the literal is marked.
+    pub fn except_literal(
+        &mut self,
+        terminal: &[u8],
+        nonterminal_id: NonterminalID,
+        ignore_ascii_case: bool,
+    ) {
         fn _except_literal(
             this: &mut TerminalsTrie,
             current_node_id: TrieNodeID,
             terminal: &[u8],
+            failure: &[usize],
             mut index: usize,
+            ignore_ascii_case: bool,
         ) {
             if index == terminal.len() {
-                this.get_mut(current_node_id).negative_bytes_index = Some(index as u16);
-                index = 0;
+                let node = this.get_mut(current_node_id);
+                node.negative_bytes_index = node.negative_bytes_index.max(Some(index as u16));
+                index = failure[index - 1];
             }
             let current_node = this.get(current_node_id);
             for (k, v) in current_node
@@ -121,37 +159,139 @@ impl TerminalsTrie {
                 .map(|(k, v)| (*k, *v))
                 .collect_vec()
             {
-                if terminal[index] == k {
-                    _except_literal(this, v, terminal, index + 1);
-                } else if terminal[0] == k {
-                    _except_literal(this, v, terminal, 1);
+                let k = if ignore_ascii_case {
+                    k.to_ascii_lowercase()
                 } else {
-                    _except_literal(this, v, terminal, 0);
+                    k
+                };
+                let mut next = index;
+                while next > 0 && terminal[next] != k {
+                    next = failure[next - 1];
+                }
+                if terminal[next] == k {
+                    next += 1;
                 }
+                _except_literal(this, v, terminal, failure, next, ignore_ascii_case);
+            }
+        }
+        let lowercase;
+        let terminal = if ignore_ascii_case {
+            lowercase = terminal.to_ascii_lowercase();
+            &lowercase[..]
+        } else {
+            terminal
+        };
+        // The length of the longest proper prefix of terminal[..=i] that is also its suffix.
+        let mut failure = vec![0; terminal.len()];
+        let mut matched = 0;
+        for i in 1..terminal.len() {
+            while matched > 0 && terminal[i] != terminal[matched] {
+                matched = failure[matched - 1];
+            }
+            if terminal[i] == terminal[matched] {
+                matched += 1;
+            }
+            failure[i] = matched;
+        }
+        _except_literal(
+            self,
+            self.roots[&nonterminal_id],
+            terminal,
+            &failure,
+            0,
+            ignore_ascii_case,
+        );
+    }
+
+    /// The fewest bytes needed to complete a terminal from the node, or `usize::MAX` if no terminal can be completed.
+    pub fn min_terminal_len(&self, node_id: TrieNodeID) -> usize {
+        let mut queue = VecDeque::from([(node_id, 0)]);
+        while let Some((node_id, len)) = queue.pop_front() {
+            let node = self.get(node_id);
+            if node.value.is_some() {
+                return len;
+            }
+            queue.extend(node.children.values().map(|child| (*child, len + 1)));
+        }
+        usize::MAX
+    }
+
+    /// The most bytes that can be matched below each node, indexed by the node id.
+    ///
+    /// A child is always added after its parent, so the nodes are visited from the last one.
+    pub fn max_depths(&self) -> Vec<usize> {
+        let mut depths = vec![0; self.arena.len()];
+        for (id, node) in self.arena.iter().enumerate().rev() {
+            depths[id] = node
+                .children
+                .values()
+                .map(|child| depths[child.id] + 1)
+                .max()
+                .unwrap_or(0);
+        }
+        depths
+    }
+
+    /// Check the ids and the byte indices of the nodes after the trie is deserialized.
+    ///
+    /// A child must come after its parent in the arena, which also rules out cycles.
+    #[cfg(feature = "serde")]
+    pub fn validate(&self, terminals: &TerminalsInterner) -> Result<(), anyhow::Error> {
+        use anyhow::ensure;
+        for root in self.roots.values() {
+            ensure!(
+                root.id < self.arena.len() && self.arena[root.id].index == 0,
+                "The root {} of the terminals trie is invalid.",
+                root.id
+            );
+        }
+        for (id, node) in self.arena.iter().enumerate() {
+            for child in node.children.values() {
+                ensure!(
+                    id < child.id
+                        && child.id < self.arena.len()
+                        && self.arena[child.id].index == node.index + 1,
+                    "The child {} of the node {id} of the terminals trie is invalid.",
+                    child.id
+                );
             }
+            ensure!(
+                node.value.is_none_or(|value| value.0 < terminals.len()
+                    && terminals.get(value).len() == node.index as usize)
+                    && node.negative_bytes_index.unwrap_or(0) <= node.index,
+                "The node {id} of the terminals trie is invalid."
+            );
         }
-        _except_literal(self, self.roots[&nonterminal_id], terminal, 0);
+        Ok(())
     }
 
-    pub fn iter(&self, start_node_id: TrieNodeID) -> TerminalsTrieIter {
+    pub fn iter<'a>(
+        &'a self,
+        start_node_id: TrieNodeID,
+        terminals: &'a TerminalsInterner,
+    ) -> TerminalsTrieIter<'a> {
         let stack = vec![self.get(start_node_id).children.iter()];
-        return TerminalsTrieIter {
+        TerminalsTrieIter {
             trie: self,
+            terminals,
             initial_index: self.get(start_node_id).index as usize,
             stack,
-        };
+        }
     }
 }
 #[derive(PartialEq, Clone, Debug, Copy, Eq, Hash)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
 pub struct TrieNodeID {
     pub id: usize,
 }
 #[derive(Clone, Debug)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
 pub(crate) struct TrieNode {
     pub index: u16,
     pub can_stop: bool,
     pub negative_bytes_index: Option<u16>,
-    pub value: Option<Box<[u8]>>,
+    /// The terminal ending at the node, whose bytes are the path from the root.
+    pub value: Option<TerminalID>,
     pub children: HashMap<u8, TrieNodeID, BuildNoHashHasher<u8>>,
 }
 
@@ -0,0 +1,126 @@
+//! A walkthrough of the API in three steps, whose examples run as doctests against the [`crate::fixtures`].
+//!
+//! # Building the vocabulary, the grammar and the sampler
+//!
+//! A [`Vocabulary`](crate::vocabulary::Vocabulary) maps the token ids of a model to their bytes.
+//! It is usually read from a file with [`read_rwkv_world_vocab`](crate::utils::read_rwkv_world_vocab),
+//! while the examples here use the fixture vocabulary. A [`Grammar`](crate::grammar::Grammar) is built
+//! from a BNF schema once and can be shared by many samplers. A [`Sampler`](crate::sampler::Sampler)
+//! tracks one generation from a start nonterminal.
+//!
+//! ```
+//! use bnf_sampler::fixtures;
+//! use bnf_sampler::prelude::*;
+//!
+//! # fn main() -> Result<(), Error> {
+//! let vocabulary = fixtures::vocabulary();
+//! let grammar = Grammar::new(fixtures::ARITHMETIC_GRAMMAR, vocabulary.clone(), 0)?;
+//! let sampler = Sampler::with_config(
+//!     grammar.clone(),
+//!     "start".to_string(),
+//!     vocabulary.clone(),
+//!     SamplerConfig::new(),
+//! )?;
+//! # drop(sampler);
+//! # Ok(())
+//! # }
+//! ```
+//!
+//! # The generation loop
+//!
+//! The first call of [`Sampler::all_possible_next_tokens`](crate::sampler::Sampler::all_possible_next_tokens)
+//! passes `None` and returns the tokens allowed at the start. Every later call passes the token chosen
+//! from the previous mask, which accepts it and returns the tokens allowed after it, until
+//! [`PossibleTokensResult::End`](crate::sampler::PossibleTokensResult::End) tells the sentence is complete.
+//! Passing `None` again does not move the sampler, it only returns the same tokens.
+//!
+//! The mask borrows the sampler, so a token id has to be picked before the next call.
+//! Here the model is replaced by a list of tokens, and a real model would mask its logits with the ids instead.
+//!
+//! ```
+//! # use bnf_sampler::fixtures;
+//! # use bnf_sampler::prelude::*;
+//! # fn main() -> Result<(), Error> {
+//! # let vocabulary = fixtures::vocabulary();
+//! # let grammar = Grammar::new(fixtures::ARITHMETIC_GRAMMAR, vocabulary.clone(), 0)?;
+//! # let mut sampler =
+//! #     Sampler::with_config(grammar, "start".to_string(), vocabulary.clone(), SamplerConfig::new())?;
+//! let mut model = ["12", "+", "3", "="].into_iter();
+//! let mut token_id = None;
+//! let mut output = String::new();
+//! loop {
+//!     match sampler.all_possible_next_tokens(token_id)? {
+//!         PossibleTokensResult::Continue(mask) => {
+//!             let token = model.next().expect("The sentence is not complete.");
+//!             let id = vocabulary.token_to_id[token.as_bytes()];
+//!             assert!(mask.contains(id as usize));
+//!             output.push_str(token);
+//!             token_id = Some(id);
+//!         }
+//!         PossibleTokensResult::End => break,
+//!         PossibleTokensResult::InputTokenRejected => unreachable!("Only allowed tokens are chosen."),
+//!     }
+//! }
+//! assert_eq!(output, "12+3=");
+//! # Ok(())
+//! # }
+//! ```
+//!
+//! When the mask should outlive the step, e.g. to be copied to a GPU while the token is accepted,
+//! [`Sampler::next_mask`](crate::sampler::Sampler::next_mask) returns an owned copy, and
+//! [`Sampler::accept_a_token`](crate::sampler::Sampler::accept_a_token) accepts the token separately.
+//!
+//! ```
+//! # use bnf_sampler::fixtures;
+//! # use bnf_sampler::prelude::*;
+//! # fn main() -> Result<(), Error> {
+//! # let vocabulary = fixtures::vocabulary();
+//! # let grammar = Grammar::new(fixtures::ARITHMETIC_GRAMMAR, vocabulary.clone(), 0)?;
+//! # let mut sampler =
+//! #     Sampler::with_config(grammar, "start".to_string(), vocabulary.clone(), SamplerConfig::new())?;
+//! let mut model = ["(", "2", ")", "="].into_iter();
+//! let mut sizes = vec![];
+//! while let OwnedPossibleTokensResult::Continue(mask) = sampler.next_mask(None)? {
+//!     let id = vocabulary.token_to_id[model.next().unwrap().as_bytes()];
+//!     sampler.accept_a_token(Some(id))?;
+//!     // The mask is still held after the sampler moved on.
+//!     sizes.push(mask.len());
+//! }
+//! assert_eq!(sizes.len(), 4);
+//! # Ok(())
+//! # }
+//! ```
+//!
+//! # Rejected tokens and the end of a sentence
+//!
+//! A token outside the mask is rejected instead of corrupting the sampler: the stacks are left untouched,
+//! so the same tokens stay allowed and another token can be tried. `accept_a_token` returns
+//! [`AcceptTokenResult::Failed`](crate::sampler::AcceptTokenResult::Failed), and
+//! `all_possible_next_tokens` returns `InputTokenRejected`. A token completing the sentence returns
+//! [`AcceptTokenResult::End`](crate::sampler::AcceptTokenResult::End), after which no token is allowed
+//! until the sampler is [reset](crate::sampler::Sampler::reset).
+//! Errors, like a token id missing from the vocabulary, are returned as [`anyhow::Error`].
+//!
+//! ```
+//! # use bnf_sampler::fixtures;
+//! # use bnf_sampler::prelude::*;
+//! # fn main() -> Result<(), Error> {
+//! # let vocabulary = fixtures::vocabulary();
+//! # let grammar = Grammar::new(fixtures::ARITHMETIC_GRAMMAR, vocabulary.clone(), 0)?;
+//! # let mut sampler =
+//! #     Sampler::with_config(grammar, "start".to_string(), vocabulary.clone(), SamplerConfig::new())?;
+//! let id = |token: &str| vocabulary.token_to_id[token.as_bytes()];
+//! sampler.all_possible_next_tokens(None)?;
+//! assert_eq!(sampler.accept_a_token(Some(id("=")))?, AcceptTokenResult::Failed);
+//! assert_eq!(
+//!     sampler.all_possible_next_tokens(Some(id("=")))?,
+//!     PossibleTokensResult::InputTokenRejected
+//! );
+//! assert_eq!(sampler.accept_a_token(Some(id("7")))?, AcceptTokenResult::Continue);
+//! assert_eq!(sampler.accept_a_token(Some(id("=")))?, AcceptTokenResult::End);
+//!
+//! sampler.reset();
+//! assert!(sampler.accept_a_token(Some(u32::MAX)).is_err());
+//! # Ok(())
+//! # }
+//! ```
@@ -1,33 +1,81 @@
-use anyhow::{anyhow, Error};
+use anyhow::{anyhow, ensure, Context, Error};
 use lazy_static::lazy_static;
-use qp_trie::Trie;
 use regex::Regex;
 use rustc_hash::FxHashMap;
-use std::borrow::Borrow;
+use std::borrow::{Borrow, Cow};
+use std::fmt::Write as _;
 use std::fs::File;
 use std::io::{prelude::*, BufReader};
 use std::path::Path;
 use std::sync::Arc;
 
 use crate::vocabulary::Vocabulary;
+use crate::vocabulary::DEFAULT_MAX_TOKEN_BYTES;
 
-pub(crate) static ANY_NONTERMINAL_NAME: &str = "any!";
+pub(crate) static TERMINALS_NONTERMINAL_NAME: &str = "terminals!";
 lazy_static! {
-    pub(crate) static ref EXCEPT_LITERAL_REGEX: Regex =
-        Regex::new("except!\\(['\"](.+?)['\"]\\)").unwrap();
+    /// Matches `<name!>` and `<name!(args)>`, capturing the name and the args.
+    pub(crate) static ref SPECIAL_FORM_REGEX: Regex =
+        Regex::new("<([A-Za-z_][A-Za-z0-9_]*)!(?:\\((.*?)\\))?>").unwrap();
 }
-lazy_static! {
-    pub(crate) static ref EXCEPT_NONTERMINAL_REGEX: Regex =
-        Regex::new("except!\\(\\[(.+?)\\]\\)").unwrap();
+/// The name among `names` closest to the misspelled `name`, when it is at most 2 edits away.
+pub(crate) fn closest_name<'a>(
+    name: &str,
+    names: impl IntoIterator<Item = &'a str>,
+) -> Option<&'a str> {
+    let name = name.chars().collect::<Vec<_>>();
+    names
+        .into_iter()
+        .map(|candidate| {
+            // The Levenshtein distance, keeping one row of the table.
+            let mut row: Vec<usize> = (0..=name.len()).collect();
+            for (i, x) in candidate.chars().enumerate() {
+                let mut diagonal = row[0];
+                row[0] = i + 1;
+                for (j, y) in name.iter().enumerate() {
+                    let next = (diagonal + usize::from(x != *y))
+                        .min(row[j] + 1)
+                        .min(row[j + 1] + 1);
+                    diagonal = row[j + 1];
+                    row[j + 1] = next;
+                }
+            }
+            (row[name.len()], candidate)
+        })
+        .filter(|(distance, _)| *distance <= 2)
+        .min()
+        .map(|(_, candidate)| candidate)
 }
-lazy_static! {
-    pub(crate) static ref EXCEPTS_REGEX: Regex =
-        Regex::new("except!\\(['\"](.+?)['\"]\\)|except!\\(\\[(.+?)\\]\\)").unwrap();
+
+/// Parse the even-length hex string of `<except!(x"00E2809C")>` into raw bytes.
+pub(crate) fn parse_hex_literal(hex: &str) -> Result<Vec<u8>, Error> {
+    let invalid = || anyhow!("x\"{hex}\" is not an even-length hex string.");
+    if !hex.len().is_multiple_of(2) || !hex.bytes().all(|x| x.is_ascii_hexdigit()) {
+        return Err(invalid());
+    }
+    (0..hex.len())
+        .step_by(2)
+        .map(|i| u8::from_str_radix(&hex[i..i + 2], 16).map_err(|_| invalid()))
+        .collect()
 }
-pub(crate) fn extract_excepted<'a>(regex: &Regex, except_nonterminal: &'a str) -> Option<&'a str> {
-    Some(regex.captures(except_nonterminal)?.extract::<1>().1[0])
+/// Parse the comma separated hex bytes of `<any_except_bytes!(0x0A, 0x22)>`.
+pub(crate) fn parse_excepted_bytes(list: &str) -> Result<Vec<u8>, Error> {
+    let mut bytes = vec![];
+    for item in list.split(',').map(str::trim) {
+        let digits = item
+            .strip_prefix("0x")
+            .or_else(|| item.strip_prefix("0X"))
+            .filter(|x| !x.is_empty() && x.len() <= 2)
+            .ok_or_else(|| anyhow!("`{item}` is not a hex byte like 0x0A."))?;
+        bytes.push(
+            u8::from_str_radix(digits, 16)
+                .map_err(|_| anyhow!("`{item}` is not a hex byte like 0x0A."))?,
+        );
+    }
+    Ok(bytes)
 }
 #[derive(PartialEq, Clone, Debug, Copy, Eq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
 pub(crate) struct NonterminalID(pub usize);
 
 impl std::hash::Hash for NonterminalID {
@@ -38,30 +86,23 @@ impl std::hash::Hash for NonterminalID {
 }
 impl nohash_hasher::IsEnabled for NonterminalID {}
 
-#[derive(PartialEq, Clone, Debug, Eq, Hash)]
-pub struct U8ArrayWrapper(pub Box<[u8]>);
+#[derive(PartialEq, Clone, Debug, Copy, Eq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub(crate) struct TerminalID(pub usize);
 
-impl Borrow<[u8]> for U8ArrayWrapper {
+impl std::hash::Hash for TerminalID {
     #[inline]
-    fn borrow(&self) -> &[u8] {
-        &self.0
+    fn hash<H: std::hash::Hasher>(&self, hasher: &mut H) {
+        hasher.write_usize(self.0)
     }
 }
+impl nohash_hasher::IsEnabled for TerminalID {}
 
-impl qp_trie::Break for U8ArrayWrapper {
-    type Split = [u8];
-
-    #[inline]
-    fn empty<'a>() -> &'a [u8] {
-        <&'a [u8]>::default()
-    }
-
-    #[inline]
-    fn find_break(&self, loc: usize) -> &[u8] {
-        &self.0[..loc]
-    }
-}
+/// Moved to [`crate::vocabulary::U8ArrayWrapper`], next to the vocabulary using it.
+#[doc(hidden)]
+pub use crate::vocabulary::U8ArrayWrapper;
 
+#[allow(dead_code)]
 #[derive(PartialEq, Clone, Debug, Eq, Hash)]
 pub(crate) struct SliceU8Wrapper<'a>(pub &'a [u8]);
 
@@ -88,102 +129,185 @@ impl<'a> qp_trie::Break for SliceU8Wrapper<'a> {
 
 /// Read the vocabulary from RWKV-world model series vocabulary file.
 pub fn read_rwkv_world_vocab(path: impl AsRef<Path>) -> Result<Arc<Vocabulary>, Error> {
+    read_rwkv_world_vocab_with_max_token_bytes(path, DEFAULT_MAX_TOKEN_BYTES)
+}
+
+/// Read the vocabulary in RWKV-world model series vocabulary format,
+/// rejecting tokens longer than `max_token_bytes`.
+pub fn read_rwkv_world_vocab_with_max_token_bytes(
+    path: impl AsRef<Path>,
+    max_token_bytes: usize,
+) -> Result<Arc<Vocabulary>, Error> {
     let path = path.as_ref();
-    let file = File::open(path).unwrap();
-    let reader = BufReader::new(file);
-    let mut id_to_token: FxHashMap<u32, Vec<u8>> = FxHashMap::default();
-    let mut id_to_token_string: FxHashMap<u32, String> = FxHashMap::default();
-    let mut token_to_id = Trie::<U8ArrayWrapper, u32>::new();
-    for line in reader.lines() {
-        let line = line.unwrap();
-        let mut start = line.find(' ').ok_or(anyhow!(
-            "invalid format: ensure this is RWKV world model's vocab file {:?}",
-            path
-        ))?;
-        let mut end = line.rfind(' ').ok_or(anyhow!(
+    let file = File::open(path).with_context(|| format!("cannot open vocab file {:?}", path))?;
+    read_rwkv_world_vocab_from_reader(BufReader::new(file), max_token_bytes).with_context(|| {
+        format!(
             "invalid format: ensure this is RWKV world model's vocab file {:?}",
             path
-        ))?;
-        let token_id = line[..start]
-            .parse::<u32>()
-            .unwrap_or_else(|x| panic!("{line} cannot be parsed: {x}"));
+        )
+    })
+}
+
+/// Read the vocabulary in RWKV-world model series vocabulary format from a reader,
+/// rejecting tokens longer than `max_token_bytes`.
+pub fn read_rwkv_world_vocab_from_reader(
+    reader: impl BufRead,
+    max_token_bytes: usize,
+) -> Result<Arc<Vocabulary>, Error> {
+    let mut id_to_token: FxHashMap<u32, Vec<u8>> = FxHashMap::default();
+    let mut id_to_token_string: FxHashMap<u32, String> = FxHashMap::default();
+    for (line_number, line) in reader.lines().enumerate() {
+        let line = line?;
+        let invalid_line = || {
+            anyhow!(
+                "line {}: {:?} is not in the format `<token id> <quoted token> <token length>`",
+                line_number + 1,
+                line
+            )
+        };
+        let mut start = line.find(' ').ok_or_else(invalid_line)?;
+        let end = line.rfind(' ').ok_or_else(invalid_line)?;
+        let token_id = line[..start].parse::<u32>().map_err(|x| {
+            anyhow!(
+                "line {}: token id {:?} cannot be parsed: {x}",
+                line_number + 1,
+                &line[..start]
+            )
+        })?;
         start += 1;
-        end -= 1;
-        if line.chars().nth(start).unwrap() == 'b' {
+        if line.as_bytes().get(start) == Some(&b'b') {
             start += 2;
         } else {
             start += 1;
         }
-        // println!("token: {}",&line[start..end]);
-        let token = fix_utf8_escape(&line[start..end]);
-        id_to_token.insert(token_id, token.clone());
-        token_to_id.insert(U8ArrayWrapper(token.into()), token_id);
-        // println!("{:?}", String::from_utf8(token.clone()));
-        id_to_token_string.insert(token_id, line[start..end].to_string());
+        // The closing quote is right before the last space.
+        let token_string = end
+            .checked_sub(1)
+            .filter(|end| start <= *end)
+            .and_then(|end| line.get(start..end))
+            .ok_or_else(invalid_line)?;
+        let token = fix_utf8_escape(token_string)
+            .with_context(|| format!("line {}: invalid token", line_number + 1))?;
+        ensure!(
+            token.len() <= max_token_bytes,
+            "line {}: token id {token_id} is {} bytes long, which exceeds the maximum of {max_token_bytes} bytes.",
+            line_number + 1,
+            token.len()
+        );
+        id_to_token.insert(token_id, token);
+        id_to_token_string.insert(token_id, token_string.to_string());
     }
-    Ok(Arc::new(Vocabulary {
-        token_to_id,
-        id_to_token_string,
+    Ok(Arc::new(Vocabulary::new(
         id_to_token,
-    }))
+        id_to_token_string,
+        max_token_bytes,
+    )?))
+}
+
+/// The bytes as UTF-8, where each byte of an invalid UTF-8 sequence is written as a `\xHH` escape,
+/// so tokens like byte fallback tokens can be displayed without losing their bytes.
+pub fn escape_invalid_utf8(bytes: &[u8]) -> Cow<'_, str> {
+    if let Ok(string) = std::str::from_utf8(bytes) {
+        return Cow::Borrowed(string);
+    }
+    let mut escaped = String::with_capacity(bytes.len() * 2);
+    for chunk in bytes.utf8_chunks() {
+        escaped.push_str(chunk.valid());
+        for byte in chunk.invalid() {
+            write!(escaped, "\\x{byte:02X}").unwrap();
+        }
+    }
+    Cow::Owned(escaped)
+}
+
+/// Write the vocabulary in RWKV-world model series vocabulary format, ordered by token id,
+/// so [`read_rwkv_world_vocab_from_reader`] reads the same tokens back.
+///
+/// Printable ASCII bytes are written as they are and the other bytes as `\xHH` escapes.
+pub fn write_rwkv_world_vocab(
+    vocabulary: &Vocabulary,
+    mut writer: impl Write,
+) -> Result<(), Error> {
+    let mut ids: Vec<&u32> = vocabulary.id_to_token.keys().collect();
+    ids.sort_unstable();
+    for id in ids {
+        let token = &vocabulary.id_to_token[id];
+        let mut escaped = String::with_capacity(token.len());
+        for byte in token.iter() {
+            match byte {
+                b'\\' => escaped.push_str("\\\\"),
+                0x20..=0x7E => escaped.push(*byte as char),
+                _ => write!(escaped, "\\x{byte:02x}").unwrap(),
+            }
+        }
+        writeln!(writer, "{id} '{escaped}' {}", token.len())?;
+    }
+    Ok(())
 }
 
 /// translated from <https://github.com/npk48/rwkv_cuda/blob/main/tokenizer.hpp#L166>
 ///
 /// sequence need to be unescaped:
 ///
-///     "\\symbol", ["\\", "symbol"]
+/// ```text
+/// "\\symbol", ["\\", "symbol"]
 ///
-///     "\\",       ["\\"]
+/// "\\",       ["\\"]
 ///
-///     "\\t",      ["\\", "t"]
+/// "\\t",      ["\\", "t"]
 ///
-///     "\\n",      ["\\", "n"]
+/// "\\n",      ["\\", "n"]
 ///
-///     "\\r",      ["\\", "r"]
+/// "\\r",      ["\\", "r"]
 ///
-///     "\\x12",    ["\\", "x", "1", "2"]
+/// "\\x12",    ["\\", "x", "1", "2"]
 ///
-///     "\\u1234",  ["\\", "u", "1", "2", "3", "4"]
-pub fn fix_utf8_escape(token: &str) -> Vec<u8> {
-    let mut result: Vec<u8> = Vec::with_capacity(token.as_bytes().len());
-    let mut token = token;
+/// "\\u1234",  ["\\", "u", "1", "2", "3", "4"]
+/// ```
+///
+/// An error is returned when an escape sequence is incomplete or invalid.
+pub fn fix_utf8_escape(token: &str) -> Result<Vec<u8>, Error> {
+    let mut result: Vec<u8> = Vec::with_capacity(token.len());
+    let mut chars = token.chars();
     let convert_to_utf8 = |c: char, buffer: &mut Vec<u8>| {
         let mut temp = [0, 0, 0, 0];
         buffer.extend(c.encode_utf8(&mut temp).as_bytes());
     };
-    while !token.is_empty() {
-        let c = token.chars().next().unwrap();
+    let take_hex_digits = |chars: &mut std::str::Chars, count: usize| {
+        let hex_digits: String = chars.take(count).collect();
+        ensure!(
+            hex_digits.len() == count && hex_digits.chars().all(|c| c.is_ascii_hexdigit()),
+            "{token:?} contains an invalid escape sequence: expected {count} hex digits, found {hex_digits:?}."
+        );
+        Ok(u32::from_str_radix(&hex_digits, 16)?)
+    };
+    while let Some(c) = chars.next() {
         if c == '\\' {
-            let next_c = token.chars().nth(1).unwrap();
+            let next_c = chars
+                .next()
+                .ok_or_else(|| anyhow!("{token:?} ends with an incomplete escape sequence."))?;
             if next_c == 't' {
                 result.push(b'\t');
-                token = &token[2..];
             } else if next_c == 'n' {
                 result.push(b'\n');
-                token = &token[2..];
             } else if next_c == 'r' {
                 result.push(b'\r');
-                token = &token[2..];
             } else if next_c == 'x' {
-                let hex_digits: String = token.chars().skip(2).take(2).collect();
-                result.push(u8::from_str_radix(&hex_digits, 16).unwrap());
-                token = &token[4..];
+                result.push(take_hex_digits(&mut chars, 2)? as u8);
             } else if next_c == 'u' {
-                let hex_digits: String = token.chars().skip(2).take(4).collect();
+                let code_point = take_hex_digits(&mut chars, 4)?;
                 convert_to_utf8(
-                    char::from_u32(u32::from_str_radix(&hex_digits, 16).unwrap()).unwrap(),
+                    char::from_u32(code_point).ok_or_else(|| {
+                        anyhow!("{token:?} contains an invalid unicode code point {code_point:#x}.")
+                    })?,
                     &mut result,
                 );
-                token = &token[6..];
             } else {
-                result.push(next_c as u8);
-                token = &token[2..];
+                convert_to_utf8(next_c, &mut result);
             }
         } else {
             convert_to_utf8(c, &mut result);
-            token = &token[c.len_utf8()..];
         }
     }
-    result
+    Ok(result)
 }
@@ -1,8 +1,54 @@
-use bit_set::BitSet;
+use crate::mask::TokenMask;
+use anyhow::{anyhow, ensure, Error};
+use itertools::Itertools;
 use qp_trie::Trie;
 use rustc_hash::FxHashMap;
 
-use crate::utils::U8ArrayWrapper;
+use crate::signature::byte_signature;
+use crate::utils;
+use std::borrow::{Borrow, Cow};
+use std::fmt;
+use std::str::FromStr;
+use std::sync::OnceLock;
+
+/// The bytes of a token as the key of [`Vocabulary::token_to_id`].
+#[derive(PartialEq, Clone, Debug, Eq, Hash)]
+pub struct U8ArrayWrapper(pub Box<[u8]>);
+
+impl Borrow<[u8]> for U8ArrayWrapper {
+    #[inline]
+    fn borrow(&self) -> &[u8] {
+        &self.0
+    }
+}
+
+impl qp_trie::Break for U8ArrayWrapper {
+    type Split = [u8];
+
+    #[inline]
+    fn empty<'a>() -> &'a [u8] {
+        <&'a [u8]>::default()
+    }
+
+    #[inline]
+    fn find_break(&self, loc: usize) -> &[u8] {
+        &self.0[..loc]
+    }
+}
+
+/// The default maximum length of a token in bytes.
+/// Longer tokens are most likely corrupted vocabulary lines and would bloat the terminals trie.
+pub const DEFAULT_MAX_TOKEN_BYTES: usize = 1024;
+
+/// Dense indices of the token ids of a vocabulary whose ids are sparse,
+/// so the token sets inside the sampler and the grammar are sized by the number of tokens rather than the largest id.
+#[derive(Debug, Clone)]
+struct DenseIndex {
+    dense_index: FxHashMap<u32, u32>,
+    /// The token id of each dense index.
+    token_ids: Vec<u32>,
+}
+
 #[derive(Debug, Clone)]
 /// The struct represents a language model's vocabulary.
 pub struct Vocabulary {
@@ -10,25 +56,480 @@ pub struct Vocabulary {
     /// This field represents a map from token id to the token in bytes.
     pub id_to_token: FxHashMap<u32, Vec<u8>>,
     /// This field represents a map from token id to the token in UTF-8 String representation.
+    ///
+    /// It may miss ids, or hold lossy strings of tokens that are not valid UTF-8,
+    /// so tokens should be displayed with [`Vocabulary::token_display`].
     pub id_to_token_string: FxHashMap<u32, String>,
+    /// The maximum length of a token in bytes this vocabulary was checked against.
+    pub max_token_bytes: usize,
+    /// The byte signature of every token, see [`crate::sampler::SamplerConfig::signature_filter`].
+    pub(crate) byte_signatures: FxHashMap<u32, u64>,
+    dense: Option<DenseIndex>,
+    /// The id in [`Vocabulary::token_to_id`] of each aliased token id, see [`Vocabulary::alias_token`].
+    alias_to_id: FxHashMap<u32, u32>,
+    /// The aliased token ids of each id in [`Vocabulary::token_to_id`].
+    id_to_aliases: FxHashMap<u32, Vec<u32>>,
+    /// The token classes computed by [`Vocabulary::classify`].
+    classes: OnceLock<TokenClasses>,
 }
 
 impl Vocabulary {
+    /// Create a vocabulary from the tokens and their UTF-8 String representations.
+    ///
+    /// When less than half of the ids up to the largest id are used, like in a pruned vocabulary,
+    /// the ids are remapped to dense indices internally, see [`Vocabulary::is_remapped`].
+    ///
+    /// When several ids share the same bytes, the largest id is kept in [`Vocabulary::token_to_id`]
+    /// and the others become its aliases, see [`Vocabulary::alias_token`].
+    ///
+    /// Returns an error if any token is longer than `max_token_bytes`.
+    pub fn new(
+        id_to_token: FxHashMap<u32, Vec<u8>>,
+        id_to_token_string: FxHashMap<u32, String>,
+        max_token_bytes: usize,
+    ) -> Result<Self, Error> {
+        let mut builder = VocabularyBuilder::new(max_token_bytes);
+        for (id, token) in id_to_token
+            .into_iter()
+            .sorted_unstable_by_key(|(id, _)| *id)
+        {
+            builder.insert(id, token)?;
+        }
+        builder.id_to_token_string = id_to_token_string;
+        builder.finish()
+    }
+
+    /// Create a vocabulary where every byte is its own token, whose id is the byte.
+    ///
+    /// It lets a grammar be driven byte by byte without a real tokenizer, with masks of at most 256 tokens.
+    /// The token string of a byte outside printable ASCII is written as `\xHH`.
+    pub fn byte_level() -> Self {
+        let id_to_token: FxHashMap<u32, Vec<u8>> =
+            (0..=u8::MAX).map(|x| (x as u32, vec![x])).collect();
+        let id_to_token_string = (0..=u8::MAX)
+            .map(|x| {
+                let string = if x == b' ' || x.is_ascii_graphic() {
+                    (x as char).to_string()
+                } else {
+                    format!("\\x{x:02X}")
+                };
+                (x as u32, string)
+            })
+            .collect();
+        Self::new(id_to_token, id_to_token_string, 1).unwrap()
+    }
+
+    /// Make `from_id` an alias of the token whose bytes are `to_bytes`, e.g. an added token of a chat model
+    /// whose bytes duplicate ordinary text.
+    ///
+    /// With [`crate::sampler::SamplerConfig::treat_aliased_ids_as_bytes`], the sampler accepts `from_id` as `to_bytes`,
+    /// and the possible tokens include `from_id` whenever they include the id of `to_bytes` in [`Vocabulary::token_to_id`].
+    /// `from_id` does not need to be in [`Vocabulary::id_to_token`].
+    ///
+    /// Returns an error if `to_bytes` is not a token, or `from_id` is the id of some bytes in [`Vocabulary::token_to_id`].
+    pub fn alias_token(&mut self, from_id: u32, to_bytes: &[u8]) -> Result<(), Error> {
+        let Some(id) = self.token_to_id.get(to_bytes).copied() else {
+            return Err(anyhow!(
+                "Token id {from_id} cannot be an alias of {:?} because no token has these bytes.",
+                utils::escape_invalid_utf8(to_bytes)
+            ));
+        };
+        if let Some(token) = self.id_to_token.get(&from_id) {
+            ensure!(
+                self.token_to_id.get(&token[..]) != Some(&from_id),
+                "Token id {from_id} cannot be an alias because it is the id of {:?} in token_to_id.",
+                utils::escape_invalid_utf8(token)
+            );
+        }
+        if let Some(previous) = self.alias_to_id.insert(from_id, id) {
+            self.id_to_aliases
+                .get_mut(&previous)
+                .unwrap()
+                .retain(|x| *x != from_id);
+        }
+        self.id_to_aliases.entry(id).or_default().push(from_id);
+        Ok(())
+    }
+
+    /// The aliased token ids of `token_id`, see [`Vocabulary::alias_token`].
+    pub fn aliases(&self, token_id: u32) -> &[u32] {
+        self.id_to_aliases
+            .get(&token_id)
+            .map_or(&[], |x| x.as_slice())
+    }
+
+    /// Whether any token id is aliased, see [`Vocabulary::alias_token`].
+    pub(crate) fn has_aliases(&self) -> bool {
+        !self.alias_to_id.is_empty()
+    }
+
+    /// The bytes the sampler matches for `token_id`, which are the bytes of the id it is an alias of
+    /// when `aliased` is true.
+    pub(crate) fn token_bytes(&self, token_id: u32, aliased: bool) -> Option<&[u8]> {
+        let id = match aliased {
+            true => self.alias_to_id.get(&token_id).unwrap_or(&token_id),
+            false => &token_id,
+        };
+        self.id_to_token.get(id).map(|x| x.as_slice())
+    }
+
+    /// Whether the token ids are remapped to dense indices inside the sampler and the grammar.
+    ///
+    /// The token ids passed to and returned from the public API are always the ids of the vocabulary.
+    pub fn is_remapped(&self) -> bool {
+        self.dense.is_some()
+    }
+
+    /// The index of `token_id` in the token sets inside the sampler and the grammar.
+    pub(crate) fn internal_id(&self, token_id: u32) -> u32 {
+        match &self.dense {
+            Some(dense) => dense.dense_index[&token_id],
+            None => token_id,
+        }
+    }
+
+    /// The token id of an index in the token sets inside the sampler and the grammar.
+    pub(crate) fn external_id(&self, internal_id: u32) -> u32 {
+        match &self.dense {
+            Some(dense) => dense.token_ids[internal_id as usize],
+            None => internal_id,
+        }
+    }
+
+    /// One more than the largest index in the token sets inside the sampler and the grammar.
+    pub(crate) fn internal_len(&self) -> usize {
+        match &self.dense {
+            Some(dense) => dense.token_ids.len(),
+            None => self
+                .id_to_token
+                .keys()
+                .max()
+                .map_or(0, |id| *id as usize + 1),
+        }
+    }
+
+    /// Translate a token set inside the sampler and the grammar into token ids.
+    pub(crate) fn to_external(&self, internal_ids: &TokenMask, token_ids: &mut TokenMask) {
+        token_ids.clear();
+        token_ids.extend(
+            internal_ids
+                .iter()
+                .map(|x| self.external_id(x as u32) as usize),
+        );
+    }
+
+    /// The tokens of every [`TokenClass`], computed on the first call and kept for the later ones.
+    pub fn classify(&self) -> &TokenClasses {
+        self.classes.get_or_init(|| {
+            let mut classes = TokenClasses {
+                token_ids: TokenClass::ALL.map(|_| TokenMask::new()),
+            };
+            for (id, token) in self.id_to_token.iter() {
+                for (class, token_ids) in TokenClass::ALL.iter().zip(classes.token_ids.iter_mut()) {
+                    if class.contains(token) {
+                        token_ids.insert(*id as usize);
+                    }
+                }
+            }
+            classes
+        })
+    }
+
+    /// The length of the longest token in bytes.
+    pub fn max_token_len(&self) -> usize {
+        self.id_to_token
+            .values()
+            .map(|token| token.len())
+            .max()
+            .unwrap_or(0)
+    }
+
+    /// The token `token_id` for display. Its String representation is used when it has one and its bytes
+    /// are valid UTF-8, otherwise its bytes are used with invalid UTF-8 written as `\xHH` escapes,
+    /// see [`crate::utils::escape_invalid_utf8`]. A token id not in the vocabulary is displayed as `<unknown token id>`.
+    pub fn token_display(&self, token_id: u32) -> Cow<'_, str> {
+        let Some(token) = self.id_to_token.get(&token_id) else {
+            return Cow::Owned(format!("<unknown token {token_id}>"));
+        };
+        match (
+            std::str::from_utf8(token),
+            self.id_to_token_string.get(&token_id),
+        ) {
+            (Ok(_), Some(string)) => Cow::Borrowed(string),
+            _ => utils::escape_invalid_utf8(token),
+        }
+    }
+
+    /// Get the token strings of the token ids. Token ids that are not in the vocabulary or
+    /// in [`Vocabulary::id_to_token_string`] are skipped, see [`Vocabulary::token_display`] to display every token.
     pub fn get_token_strings_from_token_ids<'a>(
         &'a self,
-        token_ids: &'a BitSet,
+        token_ids: &'a TokenMask,
     ) -> impl Iterator<Item = &'a str> {
         token_ids
             .iter()
-            .map(|x| self.id_to_token_string[&(x as u32)].as_str())
+            .filter_map(|x| self.id_to_token_string.get(&(x as u32)).map(|x| x.as_str()))
     }
 
+    /// Get the tokens of the token ids. Token ids that are not in the vocabulary are skipped.
     pub fn get_token_from_token_ids<'a>(
         &'a self,
-        token_ids: &'a BitSet,
+        token_ids: &'a TokenMask,
     ) -> impl Iterator<Item = &'a [u8]> {
         token_ids
             .iter()
-            .map(|x| self.id_to_token[&(x as u32)].as_slice())
+            .filter_map(|x| self.id_to_token.get(&(x as u32)).map(|x| x.as_slice()))
+    }
+}
+
+/// Build a [`Vocabulary`] as the tokens arrive, e.g. streamed from a tokenizer, instead of collecting
+/// the maps of [`Vocabulary::new`] first.
+///
+/// Each token is checked when it is added, and the errors name its token id.
+/// The result equals the vocabulary [`Vocabulary::new`] creates from the same tokens.
+#[derive(Debug, Clone)]
+pub struct VocabularyBuilder {
+    token_to_id: Trie<U8ArrayWrapper, u32>,
+    id_to_token: FxHashMap<u32, Vec<u8>>,
+    id_to_token_string: FxHashMap<u32, String>,
+    max_token_bytes: usize,
+    /// The ids sharing their bytes with a larger id, which become its aliases.
+    duplicates: Vec<u32>,
+}
+
+impl VocabularyBuilder {
+    /// A builder rejecting tokens longer than `max_token_bytes`.
+    pub fn new(max_token_bytes: usize) -> Self {
+        Self {
+            token_to_id: Trie::new(),
+            id_to_token: FxHashMap::default(),
+            id_to_token_string: FxHashMap::default(),
+            max_token_bytes,
+            duplicates: vec![],
+        }
+    }
+
+    fn insert(&mut self, id: u32, token: Vec<u8>) -> Result<(), Error> {
+        ensure!(
+            token.len() <= self.max_token_bytes,
+            "Token id {id} is {} bytes long, which exceeds the maximum of {} bytes.",
+            token.len(),
+            self.max_token_bytes
+        );
+        ensure!(
+            !self.id_to_token.contains_key(&id),
+            "Token id {id} is added more than once."
+        );
+        // Like in [`Vocabulary::new`], the largest id of the same bytes is kept and the others become aliases.
+        let key = U8ArrayWrapper(token.as_slice().into());
+        match self.token_to_id.get(token.as_slice()).copied() {
+            Some(previous) if previous > id => self.duplicates.push(id),
+            Some(previous) => {
+                self.duplicates.push(previous);
+                self.token_to_id.insert(key, id);
+            }
+            None => {
+                self.token_to_id.insert(key, id);
+            }
+        }
+        self.id_to_token.insert(id, token);
+        Ok(())
+    }
+
+    /// Add the token `id` with its bytes, and its UTF-8 String representation if there is one.
+    pub fn add_token(
+        &mut self,
+        id: u32,
+        token: &[u8],
+        token_string: Option<&str>,
+    ) -> Result<&mut Self, Error> {
+        self.insert(id, token.to_vec())?;
+        if let Some(token_string) = token_string {
+            self.id_to_token_string.insert(id, token_string.to_string());
+        }
+        Ok(self)
+    }
+
+    /// Add the tokens `ids[i]` with the bytes `tokens[i]`, without String representations.
+    ///
+    /// The tokens before an invalid one are kept.
+    pub fn add_batch(&mut self, ids: &[u32], tokens: &[&[u8]]) -> Result<&mut Self, Error> {
+        ensure!(
+            ids.len() == tokens.len(),
+            "The batch has {} token ids but {} tokens.",
+            ids.len(),
+            tokens.len()
+        );
+        for (id, token) in ids.iter().zip(tokens) {
+            self.insert(*id, token.to_vec())?;
+        }
+        Ok(self)
+    }
+
+    /// Set the UTF-8 String representation of the added token `id`, e.g. of a token added by
+    /// [`VocabularyBuilder::add_batch`].
+    pub fn add_token_string(&mut self, id: u32, token_string: &str) -> Result<&mut Self, Error> {
+        ensure!(
+            self.id_to_token.contains_key(&id),
+            "Token id {id} has no token."
+        );
+        self.id_to_token_string.insert(id, token_string.to_string());
+        Ok(self)
+    }
+
+    /// The number of tokens added so far.
+    pub fn len(&self) -> usize {
+        self.id_to_token.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.id_to_token.is_empty()
+    }
+
+    /// Create the vocabulary of the added tokens.
+    pub fn finish(self) -> Result<Vocabulary, Error> {
+        let VocabularyBuilder {
+            token_to_id,
+            id_to_token,
+            id_to_token_string,
+            max_token_bytes,
+            duplicates,
+        } = self;
+        let byte_signatures = id_to_token
+            .iter()
+            .map(|(id, token)| (*id, byte_signature(token)))
+            .collect();
+        let max_id = id_to_token.keys().max().map_or(0, |id| *id as usize);
+        let dense = (max_id + 1 > 2 * id_to_token.len()).then(|| {
+            let token_ids: Vec<u32> = id_to_token.keys().copied().sorted_unstable().collect();
+            DenseIndex {
+                dense_index: token_ids
+                    .iter()
+                    .enumerate()
+                    .map(|(index, id)| (*id, index as u32))
+                    .collect(),
+                token_ids,
+            }
+        });
+        let mut vocabulary = Vocabulary {
+            token_to_id,
+            id_to_token,
+            id_to_token_string,
+            max_token_bytes,
+            byte_signatures,
+            dense,
+            alias_to_id: FxHashMap::default(),
+            id_to_aliases: FxHashMap::default(),
+            classes: OnceLock::new(),
+        };
+        for id in duplicates.into_iter().sorted_unstable() {
+            let token = vocabulary.id_to_token[&id].clone();
+            vocabulary.alias_token(id, &token)?;
+        }
+        Ok(vocabulary)
+    }
+}
+
+/// A category of tokens, see [`Vocabulary::classify`].
+///
+/// It is written in snake case in `<token_class!(alphabetic_only)>`, and the empty token belongs to no class.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum TokenClass {
+    /// UTF-8 tokens whose characters are all alphabetic, like `Hello` or `é`.
+    AlphabeticOnly,
+    /// UTF-8 tokens whose characters are all numeric, like `2024`.
+    NumericOnly,
+    /// UTF-8 tokens whose characters are all whitespace, like `\n  `.
+    WhitespaceOnly,
+    /// Tokens containing an ASCII punctuation byte, like `",`.
+    ContainsPunctuation,
+    /// Tokens starting with a space, like ` the`.
+    StartsWithSpace,
+    /// Tokens containing `\n`.
+    ContainsNewline,
+    /// Tokens which are not valid UTF-8 on their own, like a part of a multibyte character.
+    NonUtf8,
+}
+
+impl TokenClass {
+    /// Every token class.
+    pub const ALL: [TokenClass; 7] = [
+        TokenClass::AlphabeticOnly,
+        TokenClass::NumericOnly,
+        TokenClass::WhitespaceOnly,
+        TokenClass::ContainsPunctuation,
+        TokenClass::StartsWithSpace,
+        TokenClass::ContainsNewline,
+        TokenClass::NonUtf8,
+    ];
+
+    /// Whether `token` belongs to the class.
+    pub fn contains(&self, token: &[u8]) -> bool {
+        let all_chars = |predicate: fn(char) -> bool| {
+            std::str::from_utf8(token).is_ok_and(|x| !x.is_empty() && x.chars().all(predicate))
+        };
+        match self {
+            TokenClass::AlphabeticOnly => all_chars(char::is_alphabetic),
+            TokenClass::NumericOnly => all_chars(char::is_numeric),
+            TokenClass::WhitespaceOnly => all_chars(char::is_whitespace),
+            TokenClass::ContainsPunctuation => token.iter().any(|x| x.is_ascii_punctuation()),
+            TokenClass::StartsWithSpace => token.starts_with(b" "),
+            TokenClass::ContainsNewline => token.contains(&b'\n'),
+            TokenClass::NonUtf8 => std::str::from_utf8(token).is_err(),
+        }
+    }
+
+    fn name(&self) -> &'static str {
+        match self {
+            TokenClass::AlphabeticOnly => "alphabetic_only",
+            TokenClass::NumericOnly => "numeric_only",
+            TokenClass::WhitespaceOnly => "whitespace_only",
+            TokenClass::ContainsPunctuation => "contains_punctuation",
+            TokenClass::StartsWithSpace => "starts_with_space",
+            TokenClass::ContainsNewline => "contains_newline",
+            TokenClass::NonUtf8 => "non_utf8",
+        }
+    }
+}
+
+impl fmt::Display for TokenClass {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(self.name())
+    }
+}
+
+impl FromStr for TokenClass {
+    type Err = Error;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        TokenClass::ALL
+            .into_iter()
+            .find(|x| x.name() == s)
+            .ok_or_else(|| {
+                anyhow!(
+                    "{s:?} is not a token class, which is one of {}.",
+                    TokenClass::ALL.iter().join(", ")
+                )
+            })
+    }
+}
+
+/// The token ids of every [`TokenClass`] in a vocabulary, returned by [`Vocabulary::classify`].
+#[derive(Debug, Clone)]
+pub struct TokenClasses {
+    token_ids: [TokenMask; 7],
+}
+
+impl TokenClasses {
+    /// The ids of the tokens in `class`.
+    pub fn get(&self, class: TokenClass) -> &TokenMask {
+        &self.token_ids[TokenClass::ALL.iter().position(|x| *x == class).unwrap()]
+    }
+
+    /// The classes `token_id` belongs to.
+    pub fn classes_of(&self, token_id: u32) -> impl Iterator<Item = TokenClass> + '_ {
+        TokenClass::ALL
+            .into_iter()
+            .filter(move |x| self.get(*x).contains(token_id as usize))
     }
 }
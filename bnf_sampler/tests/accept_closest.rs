@@ -0,0 +1,96 @@
+mod common;
+
+use bnf_sampler::sampler::{AcceptTokenResult, ClosestAcceptResult};
+use bnf_sampler::vocabulary::Vocabulary;
+use common::tiny_sampler;
+
+fn id(vocabulary: &Vocabulary, token: &str) -> u32 {
+    vocabulary.token_to_id[token.as_bytes()]
+}
+
+#[test]
+fn accepted_token_drops_nothing() {
+    let (mut sampler, vocabulary) = tiny_sampler("<start>::='true'");
+    assert_eq!(
+        sampler.accept_closest(id(&vocabulary, "true")).unwrap(),
+        ClosestAcceptResult {
+            result: AcceptTokenResult::End,
+            accepted_bytes: 4,
+            dropped_bytes: 0,
+            substituted_token_id: None,
+        }
+    );
+}
+
+#[test]
+fn longest_legal_prefix_is_accepted() {
+    let (mut sampler, vocabulary) = tiny_sampler("<start>::='nul'<tail>\n<tail>::='x'");
+    assert_eq!(
+        sampler.accept_closest(id(&vocabulary, "null")).unwrap(),
+        ClosestAcceptResult {
+            result: AcceptTokenResult::Continue,
+            accepted_bytes: 3,
+            dropped_bytes: 1,
+            substituted_token_id: None,
+        }
+    );
+    assert_eq!(
+        sampler.accept_a_token(Some(id(&vocabulary, "x"))).unwrap(),
+        AcceptTokenResult::End
+    );
+}
+
+#[test]
+fn no_legal_prefix_leaves_the_sampler_untouched() {
+    let (mut sampler, vocabulary) = tiny_sampler("<start>::='true'");
+    assert_eq!(
+        sampler.accept_closest(id(&vocabulary, "false")).unwrap(),
+        ClosestAcceptResult {
+            result: AcceptTokenResult::Failed,
+            accepted_bytes: 0,
+            dropped_bytes: 5,
+            substituted_token_id: None,
+        }
+    );
+    assert_eq!(
+        sampler
+            .accept_a_token(Some(id(&vocabulary, "true")))
+            .unwrap(),
+        AcceptTokenResult::End
+    );
+}
+
+#[test]
+fn nearest_allowed_token_is_substituted() {
+    let (mut sampler, vocabulary) = tiny_sampler("<start>::='nope'|'true'");
+    assert_eq!(
+        sampler
+            .accept_nearest_token(id(&vocabulary, "null"))
+            .unwrap(),
+        ClosestAcceptResult {
+            result: AcceptTokenResult::Continue,
+            accepted_bytes: 1,
+            dropped_bytes: 3,
+            substituted_token_id: Some(id(&vocabulary, "n")),
+        }
+    );
+    assert_eq!(
+        sampler.accept_a_token(Some(id(&vocabulary, "o"))).unwrap(),
+        AcceptTokenResult::Continue
+    );
+}
+
+#[test]
+fn trace_bundle_replays_the_substituted_token() {
+    let (mut sampler, vocabulary) = tiny_sampler("<start>::='nope'|'true'");
+    let (n, o) = (id(&vocabulary, "n"), id(&vocabulary, "o"));
+    sampler
+        .accept_nearest_token(id(&vocabulary, "null"))
+        .unwrap();
+    sampler.accept_a_token(Some(o)).unwrap();
+    let bundle = sampler.trace_bundle().unwrap();
+    assert_eq!(bundle.token_ids, [n, o]);
+    let replay = bundle.replay().unwrap();
+    assert_eq!(replay.accepted, 2);
+    assert_eq!(replay.sampler.to_string(), sampler.to_string());
+}
@@ -0,0 +1,63 @@
+mod common;
+
+use bnf_sampler::sampler::{PossibleTokensResult, SamplerConfig};
+use common::{new_sampler, tiny_vocabulary};
+
+const GRAMMAR: &str = "<start>::='ok'|'no'";
+
+/// 0.5 for `ok`, 0.25 for `no`, 0.125 for `o` and 0.0625 for `,`, which is never possible.
+fn probs() -> Vec<f32> {
+    let vocabulary = tiny_vocabulary();
+    let mut probs = vec![0.0; *vocabulary.id_to_token.keys().max().unwrap() as usize + 1];
+    for (token, p) in [("ok", 0.5), ("no", 0.25), ("o", 0.125), (",", 0.0625)] {
+        probs[vocabulary.token_to_id[token.as_bytes()] as usize] = p;
+    }
+    probs
+}
+
+#[test]
+fn mass_is_summed_over_the_mask() {
+    let vocabulary = tiny_vocabulary();
+    let mut sampler = new_sampler(GRAMMAR, &vocabulary, SamplerConfig::default());
+    let mask = match sampler.all_possible_next_tokens(None).unwrap() {
+        PossibleTokensResult::Continue(mask) => mask.clone(),
+        x => panic!("{x:?}"),
+    };
+    // `ok`, `no`, `o` and `n`.
+    assert_eq!(mask.len(), 4);
+    let admitted = sampler.admitted(None, &probs()).unwrap();
+    assert_eq!(admitted.mass, 0.875);
+    // -(4/7 log 4/7 + 2/7 log 2/7 + 1/7 log 1/7)
+    assert!(
+        (admitted.entropy_bits - 1.3788).abs() < 1e-3,
+        "{admitted:?}"
+    );
+    let o = vocabulary.token_to_id[&b"o"[..]];
+    assert_eq!(sampler.admitted_mass(Some(o), &probs()).unwrap(), 0.0);
+}
+
+#[test]
+fn end_and_rejection() {
+    let vocabulary = tiny_vocabulary();
+    let mut sampler = new_sampler(GRAMMAR, &vocabulary, SamplerConfig::default());
+    let ok = vocabulary.token_to_id[&b"ok"[..]];
+    let comma = vocabulary.token_to_id[&b","[..]];
+    assert!(sampler
+        .admitted_mass(Some(comma), &probs())
+        .unwrap_err()
+        .to_string()
+        .contains("is rejected"));
+    let admitted = sampler.admitted(Some(ok), &probs()).unwrap();
+    assert_eq!((admitted.mass, admitted.entropy_bits), (0.0, 0.0));
+}
+
+#[test]
+fn probabilities_must_cover_the_mask() {
+    let vocabulary = tiny_vocabulary();
+    let mut sampler = new_sampler(GRAMMAR, &vocabulary, SamplerConfig::default());
+    assert!(sampler
+        .admitted_mass(None, &[0.5; 8])
+        .unwrap_err()
+        .to_string()
+        .contains("only 8 probabilities are given"));
+}
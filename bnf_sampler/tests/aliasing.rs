@@ -0,0 +1,110 @@
+use bnf_sampler::grammar::Grammar;
+use bnf_sampler::sampler::{AcceptTokenResult, PossibleTokensResult, Sampler, SamplerConfig};
+use bnf_sampler::vocabulary::{Vocabulary, DEFAULT_MAX_TOKEN_BYTES};
+use rustc_hash::FxHashMap;
+use std::sync::Arc;
+
+const GRAMMAR: &str = "<start>::='<|assistant|>'<reply>\n<reply>::='hi!'";
+/// The id a chat model emits for `<|assistant|>`, which is missing from the exported vocabulary.
+const ADDED: u32 = 32001;
+
+/// `hi` is both id 1 and id 5, so id 1 is an alias of id 5.
+fn chat_vocabulary() -> Vocabulary {
+    let tokens = ["<|assistant|>", "hi", "!", "h", "i", "hi"];
+    let id_to_token: FxHashMap<u32, Vec<u8>> = tokens
+        .iter()
+        .enumerate()
+        .map(|(i, x)| (i as u32, x.as_bytes().to_vec()))
+        .collect();
+    let id_to_token_string = tokens
+        .iter()
+        .enumerate()
+        .map(|(i, x)| (i as u32, x.to_string()))
+        .collect();
+    Vocabulary::new(id_to_token, id_to_token_string, DEFAULT_MAX_TOKEN_BYTES).unwrap()
+}
+
+fn sampler(vocabulary: Vocabulary, aliased: bool) -> Sampler {
+    let vocabulary = Arc::new(vocabulary);
+    Sampler::with_config(
+        Grammar::new(GRAMMAR, vocabulary.clone(), 0).unwrap(),
+        "start".to_string(),
+        vocabulary,
+        SamplerConfig::default().treat_aliased_ids_as_bytes(aliased),
+    )
+    .unwrap()
+}
+
+fn mask(sampler: &mut Sampler, input: Option<u32>) -> Vec<usize> {
+    match sampler.all_possible_next_tokens(input).unwrap() {
+        PossibleTokensResult::Continue(mask) => mask.iter().collect(),
+        x => panic!("{x:?}"),
+    }
+}
+
+#[test]
+fn aliased_ids_are_accepted_and_listed() {
+    let mut vocabulary = chat_vocabulary();
+    vocabulary.alias_token(ADDED, b"<|assistant|>").unwrap();
+    assert_eq!(vocabulary.aliases(0), [ADDED]);
+    let mut sampler = sampler(vocabulary, true);
+    assert_eq!(mask(&mut sampler, None), [0, ADDED as usize]);
+    assert_eq!(mask(&mut sampler, Some(ADDED)), [1, 3, 5]);
+    assert_eq!(
+        sampler.accept_a_token(Some(1)).unwrap(),
+        AcceptTokenResult::Continue
+    );
+    assert_eq!(
+        sampler.all_possible_next_tokens(Some(2)).unwrap(),
+        PossibleTokensResult::End
+    );
+    assert_eq!(sampler.token_history(), Some(&[ADDED, 1, 2][..]));
+}
+
+#[test]
+fn aliases_are_ignored_by_default() {
+    let mut vocabulary = chat_vocabulary();
+    vocabulary.alias_token(ADDED, b"<|assistant|>").unwrap();
+    let mut sampler = sampler(vocabulary, false);
+    assert_eq!(mask(&mut sampler, None), [0]);
+    assert!(sampler
+        .accept_a_token(Some(ADDED))
+        .unwrap_err()
+        .to_string()
+        .contains("not in the vocabulary"));
+    assert_eq!(mask(&mut sampler, Some(0)), [3, 5]);
+}
+
+#[test]
+fn duplicate_ids_become_aliases() {
+    let vocabulary = chat_vocabulary();
+    assert_eq!(vocabulary.token_to_id[&b"hi"[..]], 5);
+    assert_eq!(vocabulary.aliases(5), [1]);
+    let mut sampler = sampler(vocabulary, true);
+    sampler.accept_a_token(Some(0)).unwrap();
+    let mut visited = vec![];
+    // The second visit reads the possible tokens cache.
+    for _ in 0..2 {
+        visited.clear();
+        sampler
+            .visit_allowed_tokens(None, &mut |x| visited.push(x))
+            .unwrap();
+        visited.sort_unstable();
+        assert_eq!(visited, [1, 3, 5]);
+    }
+}
+
+#[test]
+fn invalid_aliases_are_rejected() {
+    let mut vocabulary = chat_vocabulary();
+    assert!(vocabulary
+        .alias_token(ADDED, b"<|user|>")
+        .unwrap_err()
+        .to_string()
+        .contains("no token has these bytes"));
+    assert!(vocabulary
+        .alias_token(2, b"hi")
+        .unwrap_err()
+        .to_string()
+        .contains("it is the id of \"!\" in token_to_id"));
+}
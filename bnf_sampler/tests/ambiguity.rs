@@ -0,0 +1,88 @@
+mod common;
+
+use bnf_sampler::fixtures;
+use bnf_sampler::sampler::{
+    AcceptTokenResult, AmbiguityError, AmbiguityPolicy, PossibleTokensResult, Sampler,
+    SamplerConfig,
+};
+use common::{new_sampler, tiny_vocabulary};
+
+/// After `q`, both <first> and <second> remain, and they only share `v`.
+const AMBIGUOUS: &str = "<start>::=<first>|<second>
+<first>::='q'<tail1>
+<second>::='q'<tail2>
+<tail1>::='v'|'w'
+<tail2>::='v'|'z'";
+
+fn tokens(sampler: &mut Sampler, token_id: Option<u32>) -> Vec<String> {
+    let vocabulary = tiny_vocabulary();
+    match sampler.all_possible_next_tokens(token_id).unwrap() {
+        PossibleTokensResult::Continue(token_ids) => vocabulary
+            .get_token_strings_from_token_ids(token_ids)
+            .map(str::to_string)
+            .collect(),
+        other => panic!("{other:?}"),
+    }
+}
+
+fn sampler(policy: AmbiguityPolicy) -> Sampler {
+    let config = SamplerConfig::new().ambiguity_policy(policy);
+    let mut sampler = new_sampler(AMBIGUOUS, &tiny_vocabulary(), config);
+    assert_eq!(tokens(&mut sampler, None), vec!["q"]);
+    sampler
+}
+
+#[test]
+fn track_keeps_every_competing_stack() {
+    let mut sampler = sampler(AmbiguityPolicy::Track);
+    let q = tiny_vocabulary().token_to_id[&b"q"[..]];
+    assert_eq!(tokens(&mut sampler, Some(q)), vec!["v", "w", "z"]);
+}
+
+#[test]
+fn error_names_the_token_and_the_competing_items() {
+    let mut sampler = sampler(AmbiguityPolicy::Error);
+    let stacks = sampler.to_string();
+    let q = tiny_vocabulary().token_to_id[&b"q"[..]];
+    let error = sampler.accept_a_token(Some(q)).unwrap_err();
+    let ambiguity = error.downcast_ref::<AmbiguityError>().unwrap();
+    assert_eq!(ambiguity.token, b"q");
+    assert_eq!(ambiguity.top_items, vec!["<tail1>", "<tail2>"]);
+    assert!(error.to_string().contains("2 competing stacks"), "{error}");
+    // The stacks are left untouched.
+    assert_eq!(sampler.to_string(), stacks);
+}
+
+#[test]
+fn prefer_first_keeps_the_first_stack() {
+    let mut sampler = sampler(AmbiguityPolicy::PreferFirst);
+    let vocabulary = tiny_vocabulary();
+    let q = vocabulary.token_to_id[&b"q"[..]];
+    assert_eq!(tokens(&mut sampler, Some(q)), vec!["v", "w"]);
+    let z = vocabulary.token_to_id[&b"z"[..]];
+    assert_eq!(
+        sampler.accept_a_token(Some(z)).unwrap(),
+        AcceptTokenResult::Failed
+    );
+}
+
+#[test]
+fn fixture_grammars_are_not_ambiguous() {
+    let vocabulary = fixtures::vocabulary();
+    for (name, grammar) in fixtures::grammars() {
+        for pick in [1, 7, 13] {
+            let config = SamplerConfig::new().ambiguity_policy(AmbiguityPolicy::Error);
+            let mut sampler = new_sampler(grammar, &vocabulary, config);
+            let mut token_id = None;
+            for step in 0..40 {
+                let result = sampler.all_possible_next_tokens(token_id);
+                let allowed: Vec<usize> = match result {
+                    Ok(PossibleTokensResult::Continue(x)) => x.iter().collect(),
+                    Ok(_) => break,
+                    Err(e) => panic!("{name} at step {step}: {e}"),
+                };
+                token_id = Some(allowed[step * pick % allowed.len()] as u32);
+            }
+        }
+    }
+}
@@ -0,0 +1,76 @@
+mod common;
+
+use bnf_sampler::grammar::Grammar;
+use bnf_sampler::sampler::{AcceptTokenResult, PossibleTokensResult, Sampler};
+use bnf_sampler::vocabulary::Vocabulary;
+use common::{tiny_sampler, tiny_vocabulary};
+
+fn first_tokens(sampler: &mut Sampler, vocabulary: &Vocabulary) -> Vec<Vec<u8>> {
+    match sampler.all_possible_next_tokens(None).unwrap() {
+        PossibleTokensResult::Continue(token_ids) => vocabulary
+            .get_token_from_token_ids(token_ids)
+            .map(|x| x.to_vec())
+            .collect(),
+        result => panic!("{result:?}"),
+    }
+}
+
+#[test]
+fn single_excepted_byte() {
+    let (mut sampler, vocabulary) = tiny_sampler("<start>::=<any_except_bytes!(0x0A)>");
+    let tokens = first_tokens(&mut sampler, &vocabulary);
+    assert!(tokens.iter().all(|x| !x.contains(&b'\n')));
+    assert!(tokens.contains(&b"\"".to_vec()));
+    assert_eq!(tokens.len(), vocabulary.token_to_id.iter().count() - 2);
+}
+
+#[test]
+fn multiple_excepted_bytes() {
+    let (mut sampler, vocabulary) = tiny_sampler("<start>::=<any_except_bytes!(0x0A, 0x22)>");
+    let tokens = first_tokens(&mut sampler, &vocabulary);
+    assert!(tokens
+        .iter()
+        .all(|x| !x.contains(&b'\n') && !x.contains(&b'"')));
+    assert!(tokens.contains(&b"name".to_vec()));
+    assert!(!tokens.contains(&b"{\"".to_vec()));
+}
+
+#[test]
+fn excepted_bytes_cannot_be_smuggled_through_the_following_terminal() {
+    let (mut sampler, vocabulary) = tiny_sampler("<start>::=<any_except_bytes!(0x22)>'\",'");
+    let id = |token: &str| Some(vocabulary.token_to_id[token.as_bytes()]);
+    sampler.all_possible_next_tokens(None).unwrap();
+    assert_eq!(
+        sampler.accept_a_token(id("name")).unwrap(),
+        AcceptTokenResult::Continue
+    );
+    assert_eq!(
+        sampler.accept_a_token(id("\"")).unwrap(),
+        AcceptTokenResult::Continue
+    );
+    // A second quote could only come from the excepted nonterminal.
+    assert_eq!(
+        sampler.clone().accept_a_token(id("\"")).unwrap(),
+        AcceptTokenResult::Failed
+    );
+    assert_eq!(
+        sampler.accept_a_token(id(",")).unwrap(),
+        AcceptTokenResult::End
+    );
+}
+
+#[test]
+fn invalid_excepted_bytes_are_errors() {
+    for grammar in [
+        "<start>::=<any_except_bytes!()>",
+        "<start>::=<any_except_bytes!(0x)>",
+        "<start>::=<any_except_bytes!(0x100)>",
+        "<start>::=<any_except_bytes!(10)>",
+        "<any_except_bytes!(0x0A)>::='a'",
+    ] {
+        assert!(
+            Grammar::new(grammar, tiny_vocabulary(), 0).is_err(),
+            "{grammar}"
+        );
+    }
+}
@@ -0,0 +1,40 @@
+mod common;
+
+use bnf_sampler::fixtures;
+use bnf_sampler::grammar::Grammar;
+use bnf_sampler::sampler::{PossibleTokensResult, Sampler, SamplerConfig};
+use bnf_sampler::utils;
+use common::{longest_tokens, tiny_vocabulary, BENCHMARK_GRAMMARS};
+
+#[test]
+fn estimated_arena_is_large_enough_for_the_benchmark_grammars() {
+    let benchmark_vocabulary =
+        utils::read_rwkv_world_vocab(concat!(env!("CARGO_MANIFEST_DIR"), "/../assets/vocab.txt"))
+            .unwrap();
+    for vocabulary in [
+        tiny_vocabulary(),
+        fixtures::vocabulary(),
+        benchmark_vocabulary,
+    ] {
+        for (grammar, output) in BENCHMARK_GRAMMARS {
+            let grammar = Grammar::new(grammar, vocabulary.clone(), 0).unwrap();
+            // 0 estimates the capacity from the grammar and the vocabulary.
+            let config = SamplerConfig::new().stack_arena_capacity(0);
+            let mut sampler =
+                Sampler::with_config(grammar, "start".to_string(), vocabulary.clone(), config)
+                    .unwrap();
+            let mut input = None;
+            // The sampler ends as soon as a stack is empty, which may be before the last token.
+            let mut tokens = longest_tokens(output.as_bytes(), &vocabulary).into_iter();
+            loop {
+                // Every possible token is matched against the stacks, so the longest tokens fill the arena.
+                match sampler.all_possible_next_tokens(input) {
+                    Ok(PossibleTokensResult::Continue(_)) => {}
+                    Ok(PossibleTokensResult::End) => break,
+                    result => panic!("{output}: {result:?}"),
+                }
+                input = Some(tokens.next().expect(output));
+            }
+        }
+    }
+}
@@ -0,0 +1,63 @@
+mod common;
+
+use bnf_sampler::boundary::BoundaryFix;
+use bnf_sampler::grammar::Grammar;
+use bnf_sampler::sampler::{AcceptTokenResult, SamplerConfig};
+use common::{new_sampler, tiny_vocabulary};
+
+fn rejected(grammar: &str, token: &str) -> bool {
+    let vocabulary = tiny_vocabulary();
+    let mut sampler = new_sampler(grammar, &vocabulary, SamplerConfig::new());
+    sampler
+        .accept_a_token(Some(vocabulary.token_to_id[token.as_bytes()]))
+        .unwrap()
+        == AcceptTokenResult::Failed
+}
+
+#[test]
+fn token_starting_in_a_terminal_is_reported() {
+    let input = "<start>::='\"'<except!('\"')>";
+    let vocabulary = tiny_vocabulary();
+    let grammar = Grammar::new(input, vocabulary.clone(), 0).unwrap();
+    let conflicts = grammar.boundary_conflicts(&vocabulary);
+    assert_eq!(conflicts.len(), 1);
+    let conflict = &conflicts[0];
+    assert_eq!(conflict.nonterminal, "start");
+    assert_eq!(conflict.terminal, b"\"");
+    assert_eq!(conflict.token_set, "except!('\"')");
+    assert_eq!(conflict.fix, BoundaryFix::MergeTerminal);
+    // `:[` is written as `:` and `[`, but `":[` covers the quote too.
+    assert_eq!(
+        conflict.token_ids,
+        vec![vocabulary.token_to_id["\":[".as_bytes()]]
+    );
+    assert!(rejected(input, "\":["));
+    // `":` is accepted since `:` is a token.
+    assert!(!rejected(input, "\":"));
+}
+
+#[test]
+fn token_ending_in_a_terminal_is_reported() {
+    let input = "<start>::=<except!('z')>'z'";
+    let vocabulary = tiny_vocabulary();
+    let grammar = Grammar::new(input, vocabulary.clone(), 0).unwrap();
+    let conflicts = grammar.boundary_conflicts(&vocabulary);
+    assert_eq!(conflicts.len(), 1);
+    assert_eq!(conflicts[0].fix, BoundaryFix::SplitTerminal);
+    assert_eq!(
+        conflicts[0].token_ids,
+        vec![vocabulary.token_to_id["xyz".as_bytes()]]
+    );
+    assert!(rejected(input, "xyz"));
+    assert_eq!(
+        conflicts[0].to_string(),
+        "1 tokens crossing 'z' and <except!('z')> in <start>::=<except!('z')>'z' are rejected. Consider splitting the start of 'z' into <except!('z')>."
+    );
+}
+
+#[test]
+fn grammar_without_token_sets_has_no_conflicts() {
+    let vocabulary = tiny_vocabulary();
+    let grammar = Grammar::new("<start>::='ab'<x>\n<x>::='c'", vocabulary.clone(), 0).unwrap();
+    assert!(grammar.boundary_conflicts(&vocabulary).is_empty());
+}
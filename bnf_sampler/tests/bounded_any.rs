@@ -0,0 +1,71 @@
+mod common;
+
+use bnf_sampler::grammar::Grammar;
+use bnf_sampler::sampler::{PossibleTokensResult, SamplerConfig};
+use common::{new_sampler, tiny_vocabulary, validates};
+
+#[test]
+fn at_most_n_bytes() {
+    let vocabulary = tiny_vocabulary();
+    let grammar = "<start>::='\"'<any!(<=5)>'\"'";
+    assert!(validates(grammar, &vocabulary, b"\"hello\""));
+    assert!(validates(grammar, &vocabulary, b"\"a\""));
+    assert!(!validates(grammar, &vocabulary, b"\"hello!\""));
+    assert!(!validates(grammar, &vocabulary, b"\"\""));
+}
+
+#[test]
+fn exactly_n_bytes() {
+    let vocabulary = tiny_vocabulary();
+    let grammar = "<start>::=<any!(==3)>'.'";
+    assert!(validates(grammar, &vocabulary, b"abc."));
+    assert!(validates(grammar, &vocabulary, b"...."));
+    assert!(!validates(grammar, &vocabulary, b"ab."));
+    assert!(!validates(grammar, &vocabulary, b"abcd."));
+}
+
+#[test]
+fn tokens_past_the_bound_are_masked_out() {
+    let vocabulary = tiny_vocabulary();
+    let mut sampler = new_sampler(
+        "<start>::=<any!(<=2)>'!'",
+        &vocabulary,
+        SamplerConfig::new(),
+    );
+    let PossibleTokensResult::Continue(mask) = sampler.all_possible_next_tokens(None).unwrap()
+    else {
+        panic!("the sampler should continue");
+    };
+    // A token may end with the `!` after the bound.
+    for token_id in mask.iter() {
+        let token = &vocabulary.id_to_token[&(token_id as u32)];
+        assert!(
+            token.len() <= 2 || (token.len() == 3 && token.ends_with(b"!")),
+            "{token:?}"
+        );
+    }
+    for token in ["ab", "a", "!", "yes"] {
+        let allowed = mask.contains(vocabulary.token_to_id[token.as_bytes()] as usize);
+        assert_eq!(allowed, token != "yes", "{token}");
+    }
+}
+
+#[test]
+fn invalid_bounds_are_rejected() {
+    let vocabulary = tiny_vocabulary();
+    for (grammar, message) in [
+        (
+            "<start>::=<any!(<5)>",
+            "<any!(<5)> is invalid because (<5) is not a bound like <=32 or ==32.",
+        ),
+        (
+            "<start>::=<any!(==0)>",
+            "<any!(==0)> is invalid because the bound 0 is not at least 1.",
+        ),
+    ] {
+        let error = Grammar::new(grammar, vocabulary.clone(), 0)
+            .unwrap_err()
+            .to_string();
+        assert_eq!(error, message);
+    }
+}
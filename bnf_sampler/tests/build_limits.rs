@@ -0,0 +1,129 @@
+mod common;
+
+use bnf_sampler::grammar::{BuildLimit, BuildLimits, Grammar, GrammarBuildOptions, GrammarError};
+use common::tiny_vocabulary;
+use std::time::{Duration, Instant};
+
+fn build_error(input: &str, limits: BuildLimits) -> Option<GrammarError> {
+    let error = Grammar::with_options(
+        input,
+        tiny_vocabulary(),
+        GrammarBuildOptions::new().limits(limits),
+    )
+    .unwrap_err();
+    error.downcast_ref::<GrammarError>().copied()
+}
+
+#[test]
+fn too_many_alternatives() {
+    let alternatives = (0..20000).map(|i| format!("'{i}'")).collect::<Vec<_>>();
+    let input = format!("<start>::={}", alternatives.join("|"));
+    let limits = BuildLimits {
+        max_productions: 100,
+        ..BuildLimits::default()
+    };
+    let now = Instant::now();
+    assert_eq!(
+        build_error(&input, limits),
+        Some(GrammarError::BuildLimitExceeded(BuildLimit::Productions))
+    );
+    assert!(
+        now.elapsed() < Duration::from_secs(5),
+        "{:?}",
+        now.elapsed()
+    );
+}
+
+#[test]
+fn repetitions_are_counted_before_they_are_expanded() {
+    let limits = BuildLimits {
+        max_productions: 100,
+        ..BuildLimits::default()
+    };
+    for input in [
+        "<start>::='a'{0,1000}",
+        "<start>::='x'<b>?<b>?<b>?<b>?<b>?<b>?<b>?\n<b>::='b'",
+    ] {
+        assert_eq!(
+            build_error(input, limits),
+            Some(GrammarError::BuildLimitExceeded(BuildLimit::Productions)),
+            "{input}"
+        );
+    }
+    // Repetitions of the same term share their nonterminals, so they are counted once.
+    let input = "<start>::='a'{1,40}'b'|'a'{1,40}'c'|'a'{0,40}'d'";
+    Grammar::with_options(
+        input,
+        tiny_vocabulary(),
+        GrammarBuildOptions::new().limits(limits),
+    )
+    .unwrap();
+}
+
+#[test]
+fn too_many_terminal_bytes() {
+    let input = format!(
+        "<start>::='{}'<a>\n<a>::='{}'",
+        "x".repeat(60),
+        "y".repeat(60)
+    );
+    let limits = BuildLimits {
+        max_terminal_bytes_total: 100,
+        ..BuildLimits::default()
+    };
+    assert_eq!(
+        build_error(&input, limits),
+        Some(GrammarError::BuildLimitExceeded(
+            BuildLimit::TerminalBytesTotal
+        ))
+    );
+}
+
+#[test]
+fn too_many_trie_nodes() {
+    let limits = BuildLimits {
+        max_trie_nodes: 10,
+        ..BuildLimits::default()
+    };
+    for input in ["<start>::=<any!>", "<start>::=<except!([a])>\n<a>::='b'"] {
+        assert_eq!(
+            build_error(input, limits),
+            Some(GrammarError::BuildLimitExceeded(BuildLimit::TrieNodes)),
+            "{input}"
+        );
+    }
+}
+
+#[test]
+fn build_time_is_checked() {
+    let limits = BuildLimits {
+        max_build_time: Duration::ZERO,
+        ..BuildLimits::default()
+    };
+    let error = build_error("<start>::='a'", limits).unwrap();
+    assert_eq!(
+        error,
+        GrammarError::BuildLimitExceeded(BuildLimit::BuildTime)
+    );
+    assert_eq!(
+        error.to_string(),
+        "Building the grammar exceeds the maximum build time."
+    );
+}
+
+#[test]
+fn grammars_within_the_limits_build() {
+    let limits = BuildLimits {
+        max_productions: 4,
+        max_terminal_bytes_total: 4,
+        max_trie_nodes: 1000,
+        max_build_time: Duration::from_secs(60),
+    };
+    let grammar = Grammar::with_options(
+        "<start>::='a'<b>|'c'\n<b>::='d'|'e'",
+        tiny_vocabulary(),
+        GrammarBuildOptions::new().limits(limits),
+    )
+    .unwrap();
+    assert_eq!(grammar.nonterminals(), vec!["b", "start"]);
+}
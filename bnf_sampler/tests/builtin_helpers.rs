@@ -0,0 +1,82 @@
+mod common;
+
+use bnf_sampler::grammar::{DiagnosticKind, Grammar, GrammarBuildOptions, Severity};
+use common::{tiny_vocabulary, validates};
+
+#[test]
+fn int_rejects_leading_zeros() {
+    let vocabulary = tiny_vocabulary();
+    for valid in ["0", "-0", "7", "-12", "1234567890"] {
+        assert!(
+            validates("<start>::=<int!>", &vocabulary, valid.as_bytes()),
+            "{valid}"
+        );
+    }
+    for invalid in ["01", "-01", "00", "-", "+1", "1.5", ""] {
+        assert!(
+            !validates("<start>::=<int!>", &vocabulary, invalid.as_bytes()),
+            "{invalid}"
+        );
+    }
+}
+
+#[test]
+fn float_matches_json_numbers() {
+    let vocabulary = tiny_vocabulary();
+    for valid in ["-0.5e+10", "0", "-12", "3.14", "1e5", "2E-3", "10.25e0"] {
+        assert!(
+            validates("<start>::=<float!>", &vocabulary, valid.as_bytes()),
+            "{valid}"
+        );
+    }
+    for invalid in ["01", "01.5", "1.", ".5", "1e", "1e+", "-", "1.5.2", "0x10"] {
+        assert!(
+            !validates("<start>::=<float!>", &vocabulary, invalid.as_bytes()),
+            "{invalid}"
+        );
+    }
+}
+
+#[test]
+fn ws_and_string_char_match_json() {
+    let vocabulary = tiny_vocabulary();
+    let grammar = "<start>::='['<ws!>'\"'<chars>'\"'<ws!>']'\n<chars>::=''|<string_char!><chars>";
+    assert!(validates(grammar, &vocabulary, b"[\"id\"]"));
+    assert!(validates(grammar, &vocabulary, b"[ \t\r\n\"\"\n]"));
+    assert!(validates(
+        grammar,
+        &vocabulary,
+        br#"["a\"b\\c\/\n\u00E9\uABcd"]"#
+    ));
+    assert!(validates(grammar, &vocabulary, "[\"é\"]".as_bytes()));
+    assert!(!validates(grammar, &vocabulary, br#"["a"b"]"#));
+    assert!(!validates(grammar, &vocabulary, br#"["\q"]"#));
+    assert!(!validates(grammar, &vocabulary, br#"["\u12"]"#));
+    assert!(!validates(grammar, &vocabulary, b"[\"a\nb\"]"));
+    assert!(!validates(grammar, &vocabulary, b"[\x0b\"\"]"));
+}
+
+#[test]
+fn helpers_take_no_arguments() {
+    assert!(Grammar::new("<start>::=<int!(5)>", tiny_vocabulary(), 0).is_err());
+}
+
+#[test]
+fn user_definitions_replace_helpers_with_a_warning() {
+    let vocabulary = tiny_vocabulary();
+    let grammar = "<start>::=<int!>' '<float!>\n<int!>::='one'|'two'";
+    assert!(validates(grammar, &vocabulary, b"two -0.5e+10"));
+    assert!(!validates(grammar, &vocabulary, b"2 -0.5e+10"));
+    let diagnostics = Grammar::check(grammar, vocabulary, GrammarBuildOptions::new(), "start");
+    let warnings = diagnostics
+        .iter()
+        .filter(|x| x.kind == DiagnosticKind::OverriddenHelper)
+        .map(|x| {
+            assert_eq!(x.severity, Severity::Warning);
+            x.nonterminal.as_deref().unwrap()
+        })
+        .collect::<Vec<_>>();
+    assert_eq!(warnings, ["int!"]);
+    // Other special nonterminals still cannot be defined.
+    assert!(Grammar::new("<start>::=<date!>\n<date!>::='today'", tiny_vocabulary(), 0).is_err());
+}
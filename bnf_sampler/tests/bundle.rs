@@ -0,0 +1,77 @@
+mod common;
+
+use bnf_sampler::bundle::TraceBundle;
+use bnf_sampler::sampler::{AcceptTokenResult, CacheMode, Sampler, SamplerConfig};
+use common::{new_sampler, tiny_vocabulary};
+use std::path::PathBuf;
+
+const GRAMMAR: &str =
+    "<start>::='{'<items>'}'\n<items>::=<item>|<item>','<items>\n<item>::='yes'|'no'";
+
+fn bundle_dir(name: &str) -> PathBuf {
+    let path =
+        std::env::temp_dir().join(format!("bnf_sampler_bundle_{name}_{}", std::process::id()));
+    let _ = std::fs::remove_dir_all(&path);
+    path
+}
+
+fn accept(sampler: &mut Sampler, token: &str) -> AcceptTokenResult {
+    let vocabulary = tiny_vocabulary();
+    sampler
+        .accept_a_token(Some(vocabulary.token_to_id[token.as_bytes()]))
+        .unwrap()
+}
+
+#[test]
+fn replay_stops_at_the_rejected_token() {
+    let vocabulary = tiny_vocabulary();
+    let config = SamplerConfig::new()
+        .cache_mode(CacheMode::TrieNodeOnly)
+        .signature_filter(false);
+    let mut sampler = new_sampler(GRAMMAR, &vocabulary, config.clone());
+    for token in ["{", "yes", ",", "no"] {
+        assert_eq!(accept(&mut sampler, token), AcceptTokenResult::Continue);
+    }
+    assert_eq!(accept(&mut sampler, "yes"), AcceptTokenResult::Failed);
+    let path = bundle_dir("rejected");
+    sampler.record_trace_bundle(&path).unwrap();
+
+    let bundle = TraceBundle::load(&path).unwrap();
+    assert_eq!(bundle.grammar, GRAMMAR);
+    assert_eq!(bundle.start_nonterminal, "start");
+    assert_eq!(bundle.config, config);
+    assert_eq!(bundle.vocabulary.id_to_token, vocabulary.id_to_token);
+    assert_eq!(bundle.token_ids.len(), 5);
+    let mut replay = bundle.replay().unwrap();
+    assert_eq!(replay.accepted, 4);
+    assert_eq!(replay.result, AcceptTokenResult::Failed);
+    assert_eq!(accept(&mut replay.sampler, "}"), AcceptTokenResult::End);
+    std::fs::remove_dir_all(&path).unwrap();
+}
+
+#[test]
+fn replay_without_rejection_ends_like_the_generation() {
+    let vocabulary = tiny_vocabulary();
+    let mut sampler = new_sampler(GRAMMAR, &vocabulary, SamplerConfig::new());
+    for token in ["{", "no", "}"] {
+        accept(&mut sampler, token);
+    }
+    let path = bundle_dir("ended");
+    sampler.record_trace_bundle(&path).unwrap();
+    let replay = TraceBundle::load(&path).unwrap().replay().unwrap();
+    assert_eq!(replay.accepted, 3);
+    assert_eq!(replay.result, AcceptTokenResult::End);
+    std::fs::remove_dir_all(&path).unwrap();
+}
+
+#[test]
+fn bundles_need_a_token_history() {
+    let vocabulary = tiny_vocabulary();
+    let mut sampler = new_sampler(GRAMMAR, &vocabulary, SamplerConfig::new());
+    sampler.accept_bytes(b"{ye").unwrap();
+    assert!(sampler
+        .trace_bundle()
+        .unwrap_err()
+        .to_string()
+        .contains("The token history is not recorded"));
+}
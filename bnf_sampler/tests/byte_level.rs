@@ -0,0 +1,90 @@
+use bnf_sampler::grammar::Grammar;
+use bnf_sampler::sampler::{AcceptTokenResult, PossibleTokensResult, Sampler, SamplerConfig};
+use bnf_sampler::vocabulary::Vocabulary;
+use std::sync::Arc;
+
+const GRAMMAR: &str = r#"<start>::='{"'<key>'":'<value>'}'
+<key>::=<except!('"')>|<except!('"')><key>
+<value>::='true'|'false'|<digits>
+<digits>::=<digit>|<digit><digits>
+<digit>::='0'|'1'|'2'|'3'|'4'|'5'|'6'|'7'|'8'|'9'"#;
+
+fn new_sampler(grammar: &str) -> Sampler {
+    let vocabulary = Arc::new(Vocabulary::byte_level());
+    let grammar = Grammar::new(grammar, vocabulary.clone(), 0).unwrap();
+    Sampler::with_config(
+        grammar,
+        "start".to_string(),
+        vocabulary,
+        SamplerConfig::new(),
+    )
+    .unwrap()
+}
+
+fn mask(sampler: &mut Sampler, byte: Option<u8>) -> Vec<u8> {
+    match sampler
+        .all_possible_next_tokens(byte.map(u32::from))
+        .unwrap()
+    {
+        PossibleTokensResult::Continue(token_ids) => token_ids.iter().map(|x| x as u8).collect(),
+        PossibleTokensResult::End => vec![],
+        PossibleTokensResult::InputTokenRejected => panic!("{byte:?} is rejected."),
+    }
+}
+
+#[test]
+fn every_byte_is_a_token() {
+    let vocabulary = Vocabulary::byte_level();
+    assert_eq!(vocabulary.id_to_token.len(), 256);
+    assert_eq!(vocabulary.max_token_len(), 1);
+    for byte in 0..=u8::MAX {
+        assert_eq!(vocabulary.id_to_token[&(byte as u32)], vec![byte]);
+        assert_eq!(
+            vocabulary.token_to_id.get(&[byte][..]),
+            Some(&(byte as u32))
+        );
+    }
+    assert_eq!(vocabulary.id_to_token_string[&(b'a' as u32)], "a");
+    assert_eq!(vocabulary.id_to_token_string[&(b' ' as u32)], " ");
+    assert_eq!(vocabulary.id_to_token_string[&0x0A], "\\x0A");
+    assert_eq!(vocabulary.id_to_token_string[&0xFF], "\\xFF");
+}
+
+#[test]
+fn masks_at_byte_granularity() {
+    let mut sampler = new_sampler(GRAMMAR);
+    assert_eq!(mask(&mut sampler, None), b"{");
+    assert_eq!(mask(&mut sampler, Some(b'{')), b"\"");
+    let key_bytes = mask(&mut sampler, Some(b'"'));
+    assert_eq!(key_bytes.len(), 255);
+    assert!(!key_bytes.contains(&b'"'));
+    let after_key = mask(&mut sampler, Some(b'k'));
+    assert_eq!(after_key.len(), 256);
+    assert_eq!(mask(&mut sampler, Some(b'"')), b":");
+    let mut expected_values = b"0123456789ft".to_vec();
+    expected_values.sort();
+    assert_eq!(mask(&mut sampler, Some(b':')), expected_values);
+    assert_eq!(mask(&mut sampler, Some(b't')), b"r");
+    for byte in *b"rue" {
+        mask(&mut sampler, Some(byte));
+    }
+    assert_eq!(mask(&mut sampler, Some(b'}')), b"");
+}
+
+/// The mask must contain exactly the bytes a clone of the sampler accepts.
+#[test]
+fn masks_equal_the_accepted_bytes() {
+    let mut sampler = new_sampler(GRAMMAR);
+    let mut allowed = mask(&mut sampler, None);
+    for byte in *br#"{"ab":42}"# {
+        let accepted: Vec<u8> = (0..=u8::MAX)
+            .filter(|x| {
+                let mut clone = sampler.clone();
+                clone.accept_a_token(Some(*x as u32)).unwrap() != AcceptTokenResult::Failed
+            })
+            .collect();
+        assert_eq!(allowed, accepted, "before {:?}", byte as char);
+        allowed = mask(&mut sampler, Some(byte));
+    }
+    assert!(allowed.is_empty());
+}
@@ -0,0 +1,95 @@
+mod common;
+
+use bnf_sampler::grammar::{Diagnostic, DiagnosticKind, Grammar};
+use bnf_sampler::sampler::{PossibleTokensResult, SamplerConfig};
+use common::{new_sampler, tiny_vocabulary, validates};
+
+#[test]
+fn control_characters_match_single_bytes() {
+    let vocabulary = tiny_vocabulary();
+    let mut sampler = new_sampler(
+        "<start>::=<byte!(0x00-0x1F)>",
+        &vocabulary,
+        SamplerConfig::new(),
+    );
+    let PossibleTokensResult::Continue(token_ids) = sampler.all_possible_next_tokens(None).unwrap()
+    else {
+        panic!()
+    };
+    let mut tokens = vocabulary
+        .get_token_from_token_ids(token_ids)
+        .map(|x| x.to_vec())
+        .collect::<Vec<_>>();
+    tokens.sort();
+    assert_eq!(tokens, (0..=0x1F).map(|x| vec![x]).collect::<Vec<_>>());
+}
+
+#[test]
+fn multiple_ranges() {
+    let grammar = "<start>::=<hex><hex>\n<hex>::=<byte!(0x30-0x39, 0x41-0x46)>";
+    let vocabulary = tiny_vocabulary();
+    for (output, valid) in [
+        (&b"A9"[..], true),
+        (b"0F", true),
+        (b"a9", false),
+        (b"G0", false),
+        (b"0", false),
+    ] {
+        assert_eq!(validates(grammar, &vocabulary, output), valid, "{output:?}");
+    }
+}
+
+#[test]
+fn single_bytes_and_binary_data() {
+    // A protobuf-like field: the tag 0x08, then a varint of one byte.
+    let grammar = "<start>::=<byte!(0x08)><byte!(0x00-0x7F)>";
+    let vocabulary = tiny_vocabulary();
+    assert!(validates(grammar, &vocabulary, b"\x08\x7F"));
+    assert!(!validates(grammar, &vocabulary, b"\x08\x80"));
+    assert!(!validates(grammar, &vocabulary, b"\x09\x00"));
+}
+
+#[test]
+fn a_token_may_cross_the_byte() {
+    let grammar = "<start>::=<byte!(0x61-0x7A)>'yz'";
+    let vocabulary = tiny_vocabulary();
+    let mut sampler = new_sampler(grammar, &vocabulary, SamplerConfig::new());
+    let PossibleTokensResult::Continue(token_ids) = sampler.all_possible_next_tokens(None).unwrap()
+    else {
+        panic!()
+    };
+    assert!(token_ids.contains(vocabulary.token_to_id[&b"xyz"[..]] as usize));
+}
+
+#[test]
+fn the_terminals_are_the_bytes() {
+    let grammar = Grammar::new("<start>::=<byte!(0x41-0x43,0x42)>", tiny_vocabulary(), 0).unwrap();
+    let mut terminals = grammar
+        .terminals_of("byte!(0x41-0x43,0x42)")
+        .unwrap()
+        .collect::<Vec<_>>();
+    terminals.sort();
+    assert_eq!(terminals, [b"A", b"B", b"C"]);
+}
+
+#[test]
+fn reversed_ranges_are_errors() {
+    let error = Grammar::new("<start>::=<byte!(0x1F-0x00)>", tiny_vocabulary(), 0).unwrap_err();
+    let diagnostic = error.downcast_ref::<Diagnostic>().unwrap();
+    assert_eq!(diagnostic.kind, DiagnosticKind::InvalidSpecialForm);
+    assert!(
+        diagnostic.message.contains("0x1F-0x00 is reversed"),
+        "{error}"
+    );
+    for grammar in [
+        "<start>::=<byte!()>",
+        "<start>::=<byte!(0x00-)>",
+        "<start>::=<byte!(0x00-0x100)>",
+        "<start>::=<byte!(0-9)>",
+    ] {
+        assert!(
+            Grammar::new(grammar, tiny_vocabulary(), 0).is_err(),
+            "{grammar}"
+        );
+    }
+}
@@ -0,0 +1,130 @@
+mod common;
+
+use bnf_sampler::differential::compare_samplers;
+use bnf_sampler::sampler::{PossibleTokensResult, SamplerConfig};
+use common::{new_sampler, tiny_vocabulary};
+
+/// A xorshift generator, so the random grammars and walks are the same on every run.
+struct Rng(u64);
+
+impl Rng {
+    fn below(&mut self, n: usize) -> usize {
+        self.0 ^= self.0 << 13;
+        self.0 ^= self.0 >> 7;
+        self.0 ^= self.0 << 17;
+        (self.0 % n as u64) as usize
+    }
+}
+
+/// Short terminals, and terminals longer than any token of the tiny vocabulary, which allow truncating the stacks.
+const TERMINALS: &[&str] = &[
+    "a",
+    "id",
+    "xyz",
+    "{",
+    "}",
+    ",",
+    "abcdefghijklmnopqrstuvwxyz",
+    "}}}}}}}}}}}}}}}}}}}}",
+];
+
+/// A grammar of a few nonterminals, each with an alternative of terminals only, so every nonterminal is productive.
+/// An alternative only starts with a nonterminal defined after its own, so there is no left recursion.
+fn random_grammar(rng: &mut Rng) -> String {
+    let count = 2 + rng.below(3);
+    let terminal = |rng: &mut Rng| format!("'{}'", TERMINALS[rng.below(TERMINALS.len())]);
+    let mut lines = vec!["<start>::=<n0>".to_string()];
+    for i in 0..count {
+        let mut alternatives = vec![terminal(rng)];
+        for _ in 0..1 + rng.below(3) {
+            let mut terms = vec![match i + 1 < count && rng.below(2) == 0 {
+                true => format!("<n{}>", i + 1 + rng.below(count - i - 1)),
+                false => terminal(rng),
+            }];
+            for _ in 0..rng.below(4) {
+                terms.push(match rng.below(2) {
+                    0 => format!("<n{}>", rng.below(count)),
+                    _ => terminal(rng),
+                });
+            }
+            alternatives.push(terms.concat());
+        }
+        lines.push(format!("<n{i}>::={}", alternatives.join("|")));
+    }
+    lines.join("\n")
+}
+
+/// Pick random possible tokens until the end or `max_steps` tokens.
+fn random_walk(grammar: &str, rng: &mut Rng, max_steps: usize) -> Vec<u32> {
+    let vocabulary = tiny_vocabulary();
+    let mut sampler = new_sampler(grammar, &vocabulary, SamplerConfig::new());
+    let mut script = vec![];
+    let mut input = None;
+    while script.len() < max_steps {
+        let ids: Vec<usize> = match sampler.all_possible_next_tokens(input).unwrap() {
+            PossibleTokensResult::Continue(mask) => mask.iter().collect(),
+            _ => break,
+        };
+        let id = ids[rng.below(ids.len())] as u32;
+        script.push(id);
+        input = Some(id);
+    }
+    script
+}
+
+#[test]
+fn truncated_keys_give_the_same_masks_on_random_grammars() {
+    let vocabulary = tiny_vocabulary();
+    let mut rng = Rng(0x9E37_79B9_7F4A_7C15);
+    for _ in 0..20 {
+        let grammar = random_grammar(&mut rng);
+        let scripts: Vec<Vec<u32>> = (0..4)
+            .map(|_| random_walk(&grammar, &mut rng, 25))
+            .collect();
+        let mut full = new_sampler(&grammar, &vocabulary, SamplerConfig::new());
+        for depth in [1, 3, 16] {
+            let config = SamplerConfig::new().cache_key_depth(Some(depth));
+            let mut truncated = new_sampler(&grammar, &vocabulary, config);
+            // The samplers keep their caches across the walks, so later walks hit entries of earlier ones.
+            for script in scripts.iter() {
+                full.reset();
+                truncated.reset();
+                let divergences = compare_samplers(&mut full, &mut truncated, script);
+                assert!(
+                    divergences.is_empty(),
+                    "{grammar}\ndepth {depth}: {}",
+                    divergences[0]
+                );
+            }
+        }
+    }
+}
+
+#[test]
+fn truncated_keys_cache_fewer_masks_on_a_deep_grammar() {
+    let vocabulary = tiny_vocabulary();
+    let grammar = "<start>::=<v>\n<v>::='x'|'('<v>')abcdefghijklmnopqrstuvwxyz'";
+    let output = format!(
+        "{}x{}",
+        "(".repeat(8),
+        ")abcdefghijklmnopqrstuvwxyz".repeat(8)
+    );
+    let script: Vec<u32> = output
+        .bytes()
+        .map(|x| vocabulary.token_to_id[&[x][..]])
+        .collect();
+    let mut full = new_sampler(grammar, &vocabulary, SamplerConfig::new());
+    let mut truncated = new_sampler(
+        grammar,
+        &vocabulary,
+        SamplerConfig::new().cache_key_depth(Some(4)),
+    );
+    assert_eq!(compare_samplers(&mut full, &mut truncated, &script), []);
+    // Closing each level is a different full key, while the truncated keys only keep the innermost `)abc...`.
+    let (full_bytes, truncated_bytes) = (full.cached_masks_bytes(), truncated.cached_masks_bytes());
+    assert!(
+        truncated_bytes * 3 <= full_bytes,
+        "{truncated_bytes} {full_bytes}"
+    );
+    assert!(truncated.stats().cache_bytes < full.stats().cache_bytes);
+}
@@ -0,0 +1,63 @@
+mod common;
+
+use bnf_sampler::differential::compare_samplers;
+use bnf_sampler::fixtures;
+use bnf_sampler::sampler::{CacheMode, PossibleTokensResult, SamplerConfig};
+use common::{longest_tokens, new_sampler, tiny_vocabulary, BENCHMARK_GRAMMARS};
+
+#[test]
+fn modes_give_the_same_masks_on_the_benchmark_grammars() {
+    let vocabulary = tiny_vocabulary();
+    for (grammar, output) in BENCHMARK_GRAMMARS {
+        // Both the tokens of the tiny vocabulary and one byte per token.
+        let tokens = longest_tokens(output.as_bytes(), &vocabulary);
+        let bytes: Vec<u32> = output
+            .bytes()
+            .map(|x| vocabulary.token_to_id[&[x][..]])
+            .collect();
+        for cache_mode in [CacheMode::TrieNodeOnly, CacheMode::None] {
+            let mut full = new_sampler(grammar, &vocabulary, SamplerConfig::new());
+            let config = SamplerConfig::new().cache_mode(cache_mode);
+            let mut other = new_sampler(grammar, &vocabulary, config);
+            for script in [&tokens, &bytes] {
+                full.reset();
+                other.reset();
+                assert_eq!(
+                    compare_samplers(&mut full, &mut other, script),
+                    [],
+                    "{grammar} {cache_mode:?}"
+                );
+                // The walk reaches the end instead of stopping at a rejected token.
+                assert_eq!(
+                    full.all_possible_next_tokens(None).unwrap(),
+                    PossibleTokensResult::End,
+                    "{output}"
+                );
+            }
+        }
+    }
+}
+
+#[test]
+fn trie_node_only_caches_no_masks() {
+    let vocabulary = tiny_vocabulary();
+    let config = SamplerConfig::new().cache_mode(CacheMode::TrieNodeOnly);
+    let mut sampler = new_sampler(fixtures::JSON_OBJECT_GRAMMAR, &vocabulary, config);
+    let mut input = None;
+    for step in 0..1000 {
+        match sampler.all_possible_next_tokens(input).unwrap() {
+            PossibleTokensResult::Continue(mask) => {
+                // Walk different states by picking a different possible token at every step.
+                let ids: Vec<usize> = mask.iter().collect();
+                input = Some(ids[step * 7919 % ids.len()] as u32);
+            }
+            PossibleTokensResult::End => {
+                sampler.reset();
+                input = None;
+            }
+            result => panic!("{result:?}"),
+        }
+        assert_eq!(sampler.cached_masks_bytes(), 0);
+        assert_eq!(sampler.stats().cache_bytes, 0);
+    }
+}
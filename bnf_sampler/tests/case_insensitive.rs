@@ -0,0 +1,61 @@
+mod common;
+
+use bnf_sampler::grammar::Grammar;
+use bnf_sampler::sampler::{AcceptTokenResult, SamplerConfig};
+use common::{assert_same_masks, new_sampler, tiny_vocabulary, validates};
+
+#[test]
+fn every_casing_of_a_keyword_matches() {
+    let grammar = "<start>::=i\"select\"' '<column>\n<column>::='id'|i'xyz'";
+    let vocabulary = tiny_vocabulary();
+    for output in [
+        &b"select id"[..],
+        b"SELECT xyz",
+        b"Select XyZ",
+        b"sElEcT id",
+    ] {
+        assert!(validates(grammar, &vocabulary, output), "{output:?}");
+    }
+    for output in [&b"selec id"[..], b"SELECT ID", b"selectid", b"select  id"] {
+        assert!(!validates(grammar, &vocabulary, output), "{output:?}");
+    }
+}
+
+#[test]
+fn other_bytes_are_matched_as_written() {
+    let grammar = "<start>::=i'a_1-\\x42'";
+    let vocabulary = tiny_vocabulary();
+    assert!(validates(grammar, &vocabulary, b"A_1-B"));
+    assert!(validates(grammar, &vocabulary, b"a_1-B"));
+    assert!(!validates(grammar, &vocabulary, b"a_1-b"));
+}
+
+#[test]
+fn a_keyword_can_be_split_across_tokens() {
+    let vocabulary = tiny_vocabulary();
+    let mut sampler = new_sampler("<start>::=i'ixyzd'", &vocabulary, SamplerConfig::new());
+    for (token, result) in [
+        ("I", AcceptTokenResult::Continue),
+        ("xyz", AcceptTokenResult::Continue),
+        ("D", AcceptTokenResult::End),
+    ] {
+        let id = vocabulary.token_to_id[token.as_bytes()];
+        assert_eq!(sampler.accept_a_token(Some(id)).unwrap(), result, "{token}");
+    }
+}
+
+#[test]
+fn the_masks_are_those_of_the_classes() {
+    assert_same_masks(
+        "<start>::=i'id'i\"XYZ\"* '}'",
+        "<start>::=[iI][dD]([xX][yY][zZ])* '}'",
+        &["I", "d", "xyz", "X", "Y", "z", "}"],
+    );
+}
+
+#[test]
+fn empty_and_unclosed_terminals() {
+    let vocabulary = tiny_vocabulary();
+    assert!(validates("<start>::=i''", &vocabulary, b""));
+    assert!(Grammar::new("<start>::=i'abc", vocabulary, 0).is_err());
+}
@@ -0,0 +1,88 @@
+mod common;
+
+use bnf_sampler::grammar::Grammar;
+use common::{assert_same_masks, tiny_vocabulary, validates};
+
+#[test]
+fn classes_match_the_manual_expansion() {
+    let grammar = "<start>::=[a-c][0-9x]*'.'";
+    let expanded = "<start>::=<first><rest>'.'|<first>'.'
+<first>::='a'|'b'|'c'
+<rest>::=<char>|<char><rest>
+<char>::='0'|'1'|'2'|'3'|'4'|'5'|'6'|'7'|'8'|'9'|'x'";
+    assert_same_masks(grammar, expanded, &["b", "1", "0", "x", "."]);
+    assert_same_masks(grammar, expanded, &["a", "."]);
+}
+
+#[test]
+fn identifiers_are_matched_across_tokens() {
+    let vocabulary = tiny_vocabulary();
+    let grammar = "<start>::=[a-zA-Z_][a-zA-Z0-9_]*';'";
+    assert!(validates(grammar, &vocabulary, b"hello_world42;"));
+    assert!(validates(grammar, &vocabulary, b"_;"));
+    assert!(!validates(grammar, &vocabulary, b"4ever;"));
+    assert!(!validates(grammar, &vocabulary, b"a-b;"));
+    let grammar = Grammar::new(grammar, vocabulary, 0).unwrap();
+    assert_eq!(grammar.nonterminals(), ["start"]);
+}
+
+#[test]
+fn negated_classes_cover_all_bytes() {
+    let vocabulary = tiny_vocabulary();
+    let grammar = "<start>::='\"'[^\"\\\\]+'\"'";
+    assert!(validates(
+        grammar,
+        &vocabulary,
+        "\"héllo world\"".as_bytes()
+    ));
+    assert!(validates(grammar, &vocabulary, b"\"\xff\x00\""));
+    assert!(!validates(grammar, &vocabulary, b"\"a\\b\""));
+    assert!(!validates(grammar, &vocabulary, b"\"\""));
+}
+
+#[test]
+fn escapes_and_non_ascii_ranges() {
+    let vocabulary = tiny_vocabulary();
+    let grammar = "<start>::=[\\]\\-]+'.'";
+    assert!(validates(grammar, &vocabulary, b"]-]."));
+    assert!(!validates(grammar, &vocabulary, b"\\."));
+    let grammar = "<start>::=[-a]'.'";
+    assert!(validates(grammar, &vocabulary, b"-."));
+    let grammar = "<start>::=[\\x7F-\\xFF\\n]+'.'";
+    assert!(validates(grammar, &vocabulary, "é\n.".as_bytes()));
+    assert!(!validates(grammar, &vocabulary, b"e."));
+    let grammar = "<start>::=[<>]'.'";
+    assert!(validates(grammar, &vocabulary, b">."));
+}
+
+#[test]
+fn brackets_inside_terminals_are_literal() {
+    let vocabulary = tiny_vocabulary();
+    assert!(validates("<start>::='[]'", &vocabulary, b"[]"));
+}
+
+#[test]
+fn invalid_classes_are_rejected() {
+    let vocabulary = tiny_vocabulary();
+    for (grammar, message) in [
+        (
+            "<start>::=[z-a]",
+            "<start> is invalid because [z-a] is invalid: the range 'z'-'a' is reversed.",
+        ),
+        (
+            "<start>::=[^\\x00-\\xFF]",
+            "<start> is invalid because [^\\x00-\\xFF] is invalid: it matches no byte.",
+        ),
+        (
+            "<start>::=[é]",
+            "<start> is invalid because [é] is invalid: é is not a single byte, write bytes above 0x7F like \\xC3 instead.",
+        ),
+        (
+            "<start>::=[a-z\n<b>::='b'",
+            "<start> is invalid because [a-z is not closed.",
+        ),
+    ] {
+        let error = Grammar::new(grammar, vocabulary.clone(), 0).unwrap_err();
+        assert_eq!(error.to_string(), message, "{grammar}");
+    }
+}
@@ -0,0 +1,50 @@
+mod common;
+
+use bnf_sampler::grammar::Grammar;
+use common::{tiny_vocabulary, validates};
+
+const CJK: &str = "<start>::='<'<char!(U+4E00-U+9FFF)>+'>'";
+
+#[test]
+fn characters_in_the_range_are_matched_across_tokens() {
+    let vocabulary = tiny_vocabulary();
+    // The tiny vocabulary has no multi-byte character, so every character spans several byte tokens.
+    assert!(validates(CJK, &vocabulary, "<中文>".as_bytes()));
+    assert!(validates(CJK, &vocabulary, "<一鿿>".as_bytes()));
+    assert!(!validates(CJK, &vocabulary, "<ab>".as_bytes()));
+    assert!(!validates(CJK, &vocabulary, "<かな>".as_bytes()));
+    assert!(!validates(CJK, &vocabulary, b"<\xe4\xb8>"));
+}
+
+#[test]
+fn several_ranges_and_single_code_points() {
+    let vocabulary = tiny_vocabulary();
+    let grammar = "<start>::=<char!(U+0041-U+005A, U+3041-U+3096, U+00E9)>+'.'";
+    assert!(validates(grammar, &vocabulary, "ABかなé.".as_bytes()));
+    assert!(!validates(grammar, &vocabulary, "a.".as_bytes()));
+    assert!(!validates(grammar, &vocabulary, "è.".as_bytes()));
+    // The range crosses the surrogates, which are skipped, and the four byte encodings.
+    let grammar = "<start>::=<char!(U+D7FF-U+10FFFF)>'.'";
+    assert!(validates(grammar, &vocabulary, "\u{E000}.".as_bytes()));
+    assert!(validates(grammar, &vocabulary, "😀.".as_bytes()));
+    assert!(!validates(grammar, &vocabulary, "中.".as_bytes()));
+}
+
+#[test]
+fn invalid_ranges_are_rejected() {
+    let vocabulary = tiny_vocabulary();
+    for (args, reason) in [
+        ("U+9FFF-U+4E00", "the range U+9FFF-U+4E00 is reversed."),
+        ("4E00-9FFF", "4E00 is not a code point like U+4E00."),
+        ("U+D800", "U+D800 is not a Unicode scalar value."),
+        ("", "no code point range is given."),
+    ] {
+        let grammar = format!("<start>::=<char!({args})>");
+        let error = Grammar::new(&grammar, vocabulary.clone(), 0).unwrap_err();
+        assert_eq!(
+            error.to_string(),
+            format!("<char!({args})> is invalid because {reason}"),
+            "{grammar}"
+        );
+    }
+}
@@ -0,0 +1,94 @@
+mod common;
+
+use bnf_sampler::sampler::{CacheMode, PossibleTokensResult, Sampler, SamplerConfig};
+use bnf_sampler::vocabulary::Vocabulary;
+use common::{new_sampler, tiny_vocabulary};
+
+const GRAMMAR: &str = r#"<start>::='{'<pairs>'}'
+<pairs>::=<pair>|<pair>','<pairs>
+<pair>::='"'<key>'"'':'<value>|'"'<key>'"'':'<ws><value>
+<key>::=<except!('"')>|<except!('"')><key>
+<value>::=<number>|'true'|'false'|'null'|'"'<key>'"'|'{'<pairs>'}'
+<number>::=<digit>|<digit><number>
+<digit>::='0'|'1'|'2'|'3'|'4'|'5'|'6'|'7'|'8'|'9'
+<ws>::=' '|' '<ws>"#;
+
+fn mask(sampler: &mut Sampler, token_id: Option<u32>) -> Option<Vec<usize>> {
+    match sampler.all_possible_next_tokens(token_id).unwrap() {
+        PossibleTokensResult::Continue(token_ids) => Some(token_ids.iter().collect()),
+        PossibleTokensResult::End => None,
+        PossibleTokensResult::InputTokenRejected => panic!("{token_id:?} is rejected."),
+    }
+}
+
+fn replay(
+    vocabulary: &std::sync::Arc<Vocabulary>,
+    config: &SamplerConfig,
+    history: &[u32],
+) -> Option<Vec<usize>> {
+    let mut sampler = new_sampler(GRAMMAR, vocabulary, config.clone());
+    let mut result = mask(&mut sampler, None);
+    for token_id in history {
+        result = mask(&mut sampler, Some(*token_id));
+    }
+    result
+}
+
+fn clone_and_diverge(config: SamplerConfig) {
+    let vocabulary = tiny_vocabulary();
+    let mut original = new_sampler(GRAMMAR, &vocabulary, config.clone());
+    let prefix: Vec<u32> = ["{", "\"", "a", "\"", ":"]
+        .iter()
+        .map(|x| vocabulary.token_to_id[x.as_bytes()])
+        .collect();
+    mask(&mut original, None);
+    for token_id in prefix.iter() {
+        mask(&mut original, Some(*token_id));
+    }
+    let mut clone = original.clone();
+    let mut histories = [prefix.clone(), prefix];
+    let mut masks = [mask(&mut original, None), mask(&mut clone, None)];
+    for _ in 0..8 {
+        for (i, sampler) in [&mut original, &mut clone].into_iter().enumerate() {
+            let Some(token_ids) = &masks[i] else {
+                continue;
+            };
+            // The original takes the lowest allowed id and the clone the highest, so they diverge.
+            let token_id = if i == 0 {
+                token_ids[0]
+            } else {
+                *token_ids.last().unwrap()
+            } as u32;
+            histories[i].push(token_id);
+            masks[i] = mask(sampler, Some(token_id));
+            assert_eq!(
+                masks[i],
+                replay(&vocabulary, &config, &histories[i]),
+                "history: {:?}",
+                histories[i]
+            );
+        }
+    }
+    assert_ne!(histories[0], histories[1]);
+}
+
+#[test]
+fn clone_diverges_like_fresh_samplers() {
+    clone_and_diverge(SamplerConfig::new());
+}
+
+#[test]
+fn clone_diverges_like_fresh_samplers_in_every_cache_mode() {
+    for cache_mode in [CacheMode::TrieNodeOnly, CacheMode::None] {
+        clone_and_diverge(SamplerConfig::new().cache_mode(cache_mode));
+    }
+    clone_and_diverge(SamplerConfig::new().stack_to_bytes_cache(false));
+}
+
+#[test]
+fn clone_of_a_fresh_sampler_matches_the_original() {
+    let vocabulary = tiny_vocabulary();
+    let mut original = new_sampler(GRAMMAR, &vocabulary, SamplerConfig::new());
+    let mut clone = original.clone();
+    assert_eq!(mask(&mut clone, None), mask(&mut original, None));
+}
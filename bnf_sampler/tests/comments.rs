@@ -0,0 +1,35 @@
+mod common;
+
+use bnf_sampler::grammar::Grammar;
+use common::{tiny_vocabulary, validates};
+
+const GRAMMAR: &str = r##"# The start of the document.
+// A heading, then the text.
+<start>::=<heading>' '<text> ; a trailing comment
+; <phantom>::=<except!('x')>
+<heading>::="# not a comment"|'// nor this'|'a;b' ; the last one has a semicolon
+<text>::=[a-z;]|[a-z;]<text>
+"##;
+
+#[test]
+fn comments_are_removed_but_not_inside_terminals() {
+    let vocabulary = tiny_vocabulary();
+    assert!(validates(GRAMMAR, &vocabulary, b"# not a comment ab"));
+    assert!(validates(GRAMMAR, &vocabulary, b"// nor this x;y"));
+    assert!(validates(GRAMMAR, &vocabulary, b"a;b z"));
+    assert!(!validates(GRAMMAR, &vocabulary, b"a z"));
+}
+
+#[test]
+fn commented_out_special_forms_create_no_productions() {
+    let grammar = Grammar::new(GRAMMAR, tiny_vocabulary(), 0).unwrap();
+    assert_eq!(grammar.nonterminals(), vec!["heading", "start", "text"]);
+}
+
+#[test]
+fn errors_keep_the_line_numbers() {
+    let error = Grammar::new("# comment\n\n<start>::='a'\n<b>:='c'", tiny_vocabulary(), 0)
+        .unwrap_err()
+        .to_string();
+    assert!(error.contains("at line 4"), "{error}");
+}
@@ -0,0 +1,200 @@
+//! A deterministic stand-in for an LLM that drives a sampler through whole generations.
+#![allow(dead_code)]
+use anyhow::{anyhow, Error};
+use bnf_sampler::grammar::Grammar;
+use bnf_sampler::sampler::{AcceptTokenResult, PossibleTokensResult, Sampler, SamplerConfig};
+use bnf_sampler::utils;
+use bnf_sampler::vocabulary::{Vocabulary, DEFAULT_MAX_TOKEN_BYTES};
+use rustc_hash::FxHashMap;
+use std::sync::Arc;
+
+/// How the model picks a token from the allowed tokens.
+pub enum Model<'a> {
+    /// Pick the allowed token with the lowest id.
+    LowestId,
+    /// Pick the first allowed token in the list, using each token at most once,
+    /// or the allowed token with the lowest id if none of the remaining tokens is allowed.
+    Prefer(&'a [&'a str]),
+}
+
+#[derive(Debug)]
+pub struct Generation {
+    pub token_ids: Vec<u32>,
+    pub output: Vec<u8>,
+    /// Whether the sampler reached the end within the step limit.
+    pub ended: bool,
+}
+
+impl Generation {
+    pub fn output(&self) -> String {
+        String::from_utf8_lossy(&self.output).into_owned()
+    }
+}
+
+pub fn tiny_vocabulary() -> Arc<Vocabulary> {
+    utils::read_rwkv_world_vocab(concat!(
+        env!("CARGO_MANIFEST_DIR"),
+        "/assets/tiny_vocab.txt"
+    ))
+    .unwrap()
+}
+
+/// A vocabulary of `tokens`, where the id of a token is its index.
+pub fn vocabulary_of(tokens: &[&str]) -> Arc<Vocabulary> {
+    let id_to_token = tokens
+        .iter()
+        .enumerate()
+        .map(|(i, x)| (i as u32, x.as_bytes().to_vec()))
+        .collect();
+    let id_to_token_string: FxHashMap<u32, String> = tokens
+        .iter()
+        .enumerate()
+        .map(|(i, x)| (i as u32, x.escape_debug().to_string()))
+        .collect();
+    Arc::new(Vocabulary::new(id_to_token, id_to_token_string, DEFAULT_MAX_TOKEN_BYTES).unwrap())
+}
+
+pub fn new_sampler(grammar: &str, vocabulary: &Arc<Vocabulary>, config: SamplerConfig) -> Sampler {
+    let grammar = Grammar::new(grammar, vocabulary.clone(), 0).unwrap();
+    Sampler::with_config(grammar, "start".to_string(), vocabulary.clone(), config).unwrap()
+}
+
+/// A sampler of `grammar` over the tiny vocabulary, with the vocabulary to look up its tokens.
+pub fn tiny_sampler(grammar: &str) -> (Sampler, Arc<Vocabulary>) {
+    let vocabulary = tiny_vocabulary();
+    let sampler = new_sampler(grammar, &vocabulary, SamplerConfig::new());
+    (sampler, vocabulary)
+}
+
+/// Walk `tokens` with samplers of both grammars and check they allow the same tokens at every step.
+pub fn assert_same_masks(grammar: &str, expanded: &str, tokens: &[&str]) {
+    let vocabulary = tiny_vocabulary();
+    let mut samplers =
+        [grammar, expanded].map(|x| new_sampler(x, &vocabulary, SamplerConfig::default()));
+    let mut input = None;
+    for token in tokens.iter().map(Some).chain([None]) {
+        let [a, b] =
+            samplers
+                .each_mut()
+                .map(|x| match x.all_possible_next_tokens(input).unwrap() {
+                    PossibleTokensResult::Continue(mask) => Some(mask.clone()),
+                    PossibleTokensResult::End => None,
+                    x => panic!("{grammar} rejects {input:?}: {x:?}"),
+                });
+        assert_eq!(a, b, "{grammar} after {input:?}");
+        let Some(token) = token else {
+            assert_eq!(a, None, "{grammar} does not end");
+            break;
+        };
+        input = Some(vocabulary.token_to_id[token.as_bytes()]);
+    }
+}
+
+/// Run the mask, sample and accept loop until the end or `max_steps` tokens are accepted.
+pub fn generate(
+    sampler: &mut Sampler,
+    vocabulary: &Vocabulary,
+    model: &Model,
+    max_steps: usize,
+) -> Result<Generation, Error> {
+    let mut preferred: Vec<&str> = match model {
+        Model::LowestId => vec![],
+        Model::Prefer(tokens) => tokens.to_vec(),
+    };
+    let mut generation = Generation {
+        token_ids: vec![],
+        output: vec![],
+        ended: false,
+    };
+    let mut input_token_id = None;
+    while generation.token_ids.len() <= max_steps {
+        let token_ids = match sampler.all_possible_next_tokens(input_token_id)? {
+            PossibleTokensResult::Continue(token_ids) => token_ids,
+            PossibleTokensResult::End => {
+                generation.ended = true;
+                break;
+            }
+            PossibleTokensResult::InputTokenRejected => {
+                return Err(anyhow!(
+                    "The sampler rejected the allowed token {input_token_id:?}."
+                ))
+            }
+        };
+        if generation.token_ids.len() == max_steps {
+            break;
+        }
+        let preferred_index = preferred.iter().position(|token| {
+            vocabulary
+                .token_to_id
+                .get(token.as_bytes())
+                .is_some_and(|id| token_ids.contains(*id as usize))
+        });
+        let token_id = match preferred_index {
+            Some(i) => vocabulary.token_to_id[preferred.remove(i).as_bytes()],
+            None => token_ids
+                .iter()
+                .next()
+                .ok_or(anyhow!("The sampler allows no token."))? as u32,
+        };
+        generation.token_ids.push(token_id);
+        generation
+            .output
+            .extend_from_slice(&vocabulary.id_to_token[&token_id]);
+        input_token_id = Some(token_id);
+    }
+    Ok(generation)
+}
+
+/// Whether a fresh sampler of `grammar` reaches the end after accepting `output`.
+pub fn validates(grammar: &str, vocabulary: &Arc<Vocabulary>, output: &[u8]) -> bool {
+    let mut sampler = new_sampler(grammar, vocabulary, SamplerConfig::new());
+    sampler.accept_bytes(output).unwrap() == AcceptTokenResult::End
+}
+
+/// The grammars of the `scan` and `cache` benchmarks and the fixture grammars, with an output each.
+pub const BENCHMARK_GRAMMARS: &[(&str, &str)] = &[
+    (
+        r#"<start>::='{'<members>'}'
+<members>::=<member>|<member>', '<members>
+<member>::='"'<chars>'": '<value>
+<value>::='"'<chars>'"'|<integer>|'true'|'false'|'null'
+<chars>::=<char>|<char><chars>
+<char>::=<except!([escaped])>|'\\"'|'\\\\'
+<escaped>::='"'|'\\'|'\n'
+<integer>::=<digit>|<digit><integer>
+<digit>::='0'|'1'|'2'|'3'|'4'|'5'|'6'|'7'|'8'|'9'"#,
+        r#"{"name": "Alice", "age": 42, "admin": true}"#,
+    ),
+    (
+        r#"<start>::=<digit>{4}'-'<digit>{2}'-'<digit>{2}'T'<digit>{2}':'<digit>{2}
+<digit>::='0'|'1'|'2'|'3'|'4'|'5'|'6'|'7'|'8'|'9'"#,
+        "2024-01-15T12:30",
+    ),
+    (
+        r#"<start>::=<string>|<string>', '<start>
+<string>::='"'<chars>'"'
+<chars>::=<char>|<char><chars>
+<char>::=<except!([excepted])>|'\\"'
+<excepted>::='"'|'\\'|'\n'|'\t'"#,
+        r#""Alice and the \"end\"", "Bob likes files""#,
+    ),
+    (
+        bnf_sampler::fixtures::JSON_OBJECT_GRAMMAR,
+        r#"{"name": "x", "n":-12}"#,
+    ),
+    (bnf_sampler::fixtures::ARITHMETIC_GRAMMAR, "(12 + 3) * -2="),
+];
+
+/// Split `output` into the longest tokens of the vocabulary from left to right.
+pub fn longest_tokens(mut output: &[u8], vocabulary: &Vocabulary) -> Vec<u32> {
+    let mut tokens = vec![];
+    while !output.is_empty() {
+        let len = (1..=output.len().min(vocabulary.max_token_len()))
+            .rev()
+            .find(|len| vocabulary.token_to_id.contains_key(&output[..*len]))
+            .unwrap();
+        tokens.push(vocabulary.token_to_id[&output[..len]]);
+        output = &output[len..];
+    }
+    tokens
+}
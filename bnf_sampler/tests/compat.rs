@@ -0,0 +1,46 @@
+#![allow(deprecated)]
+use bnf_sampler::compat::{read_world_vocab, Sampler};
+
+const GRAMMAR: &str = "<start>::='The answer is '<number>'.'\n<number>::=<digit>|<digit><number>\n<digit>::='0'|'1'|'2'|'3'|'4'|'5'|'6'|'7'|'8'|'9'";
+
+#[test]
+fn legacy_generation_loop() {
+    let (tokens_tree, id_to_token_string) = read_world_vocab(concat!(
+        env!("CARGO_MANIFEST_DIR"),
+        "/assets/tiny_vocab.txt"
+    ));
+    let mut sampler = Sampler::new(GRAMMAR, "start", &tokens_tree, 1024 * 1024);
+    let mut output = String::new();
+    let mut input_token_id = None;
+    for preferred in ["The", " answer", " is", " ", "4", "2", "."] {
+        let token_ids = sampler
+            .all_possible_next_tokens(input_token_id)
+            .expect("the previous token is allowed");
+        let token_id = tokens_tree[preferred.as_bytes()];
+        assert!(token_ids.contains(token_id as usize), "{preferred:?}");
+        output.push_str(&id_to_token_string[&token_id]);
+        input_token_id = Some(token_id);
+    }
+    assert!(sampler
+        .all_possible_next_tokens(input_token_id)
+        .unwrap()
+        .is_empty());
+    assert_eq!(output, "The answer is 42.");
+}
+
+#[test]
+fn rejected_tokens_are_none() {
+    let (tokens_tree, _) = read_world_vocab(concat!(
+        env!("CARGO_MANIFEST_DIR"),
+        "/assets/tiny_vocab.txt"
+    ));
+    let mut sampler = Sampler::new(GRAMMAR, "start", &tokens_tree, 0);
+    assert!(sampler.all_possible_next_tokens(None).is_some());
+    let mut clone = sampler.clone();
+    assert!(!clone.accept_a_token(Some(tokens_tree[&b"4"[..]])));
+    assert_eq!(
+        sampler.all_possible_next_tokens(Some(tokens_tree[&b"4"[..]])),
+        None
+    );
+    assert!(sampler.inner().token_history().is_none());
+}
@@ -0,0 +1,87 @@
+mod common;
+
+use bnf_sampler::complete::Completion;
+use bnf_sampler::grammar::Grammar;
+use common::tiny_vocabulary;
+
+const SQL: &str = "<start>::=<select>|<delete>
+<select>::=<select_keyword>' '<columns>' FROM '<table>
+<delete>::='DELETE FROM '<table>
+<select_keyword>::='SELECT'|'SET'
+<columns>::='*'|<column>|<column>', '<columns>
+<column>::='id'|'name'|'email'
+<table>::='users'|'orders'";
+
+fn completions(prefix: &str) -> Vec<(String, String)> {
+    let grammar = Grammar::new(SQL, tiny_vocabulary(), 0).unwrap();
+    grammar
+        .completions("start", prefix.as_bytes(), 10)
+        .unwrap()
+        .into_iter()
+        .map(|Completion { bytes, nonterminal }| (String::from_utf8(bytes).unwrap(), nonterminal))
+        .collect()
+}
+
+fn pairs(expected: &[(&str, &str)]) -> Vec<(String, String)> {
+    expected
+        .iter()
+        .map(|(bytes, nonterminal)| (bytes.to_string(), nonterminal.to_string()))
+        .collect()
+}
+
+#[test]
+fn empty_prefix_suggests_the_keywords() {
+    assert_eq!(
+        completions(""),
+        pairs(&[("DELETE FROM ", "delete"), ("SE", "select_keyword")])
+    );
+}
+
+#[test]
+fn branching_terminals_continue_from_the_typed_bytes() {
+    assert_eq!(
+        completions("SE"),
+        pairs(&[("LECT", "select_keyword"), ("T", "select_keyword")])
+    );
+    assert_eq!(completions("SELECT"), pairs(&[(" ", "select")]));
+    assert_eq!(completions("DEL"), pairs(&[("ETE FROM ", "delete")]));
+}
+
+#[test]
+fn ambiguous_position_gives_every_choice() {
+    assert_eq!(
+        completions("SELECT "),
+        pairs(&[
+            ("*", "columns"),
+            ("email", "column"),
+            ("id", "column"),
+            ("name", "column"),
+        ])
+    );
+    // `name` can end the columns or be followed by more of them.
+    assert_eq!(
+        completions("SELECT name"),
+        pairs(&[(" FROM ", "select"), (", ", "columns")])
+    );
+    assert_eq!(
+        completions("SELECT * FROM "),
+        pairs(&[("orders", "table"), ("users", "table")])
+    );
+}
+
+#[test]
+fn invalid_prefix_and_limit() {
+    assert_eq!(completions("UPDATE"), vec![]);
+    // A complete sentence has nothing left to suggest.
+    assert_eq!(completions("DELETE FROM users"), vec![]);
+    let grammar = Grammar::new(SQL, tiny_vocabulary(), 0).unwrap();
+    assert_eq!(
+        grammar.completions("start", b"SELECT ", 2).unwrap().len(),
+        2
+    );
+    let error = grammar.completions("query", b"", 10).unwrap_err();
+    assert_eq!(
+        error.to_string(),
+        "<query> is not defined in the BNF schema."
+    );
+}
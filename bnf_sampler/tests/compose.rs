@@ -0,0 +1,178 @@
+mod common;
+
+use bnf_sampler::compose::{SamplerSpec, UnionSampler};
+use bnf_sampler::grammar::Grammar;
+use bnf_sampler::mask::TokenMask;
+use bnf_sampler::sampler::{PossibleTokensResult, Sampler, SamplerConfig};
+use bnf_sampler::vocabulary::Vocabulary;
+use common::{new_sampler, tiny_vocabulary};
+use std::sync::Arc;
+
+const PREAMBLE: &str = "<start>::='ok.'";
+const TOOL_CALL: &str = "<start>::='call('<digit>')'\n<digit>::='1'|'2'";
+const TEXT: &str = "<start>::=<word>'.'\n<word>::='a'|'a'<word>";
+
+fn spec(grammar: &str, vocabulary: &Arc<Vocabulary>) -> SamplerSpec {
+    SamplerSpec::new(
+        Grammar::new(grammar, vocabulary.clone(), 0).unwrap(),
+        "start",
+        vocabulary.clone(),
+    )
+}
+
+fn id(vocabulary: &Vocabulary, token: &str) -> u32 {
+    vocabulary.token_to_id[token.as_bytes()]
+}
+
+fn first_mask(grammar: &str, vocabulary: &Arc<Vocabulary>) -> TokenMask {
+    match new_sampler(grammar, vocabulary, SamplerConfig::default())
+        .all_possible_next_tokens(None)
+        .unwrap()
+    {
+        PossibleTokensResult::Continue(mask) => mask.clone(),
+        x => panic!("{x:?}"),
+    }
+}
+
+#[test]
+fn chain_switches_phases_at_the_end_of_each() {
+    let vocabulary = tiny_vocabulary();
+    let mut chain = new_sampler(PREAMBLE, &vocabulary, SamplerConfig::default())
+        .then(spec(TOOL_CALL, &vocabulary))
+        .then(spec(TEXT, &vocabulary));
+    let mut input = None;
+    let mut phases = vec![];
+    for token in ["ok", ".", "call", "(", "2", ")", "a", "a", "."] {
+        let mask = match chain.all_possible_next_tokens(input).unwrap() {
+            PossibleTokensResult::Continue(mask) => mask.clone(),
+            x => panic!("{token}: {x:?}"),
+        };
+        assert!(mask.contains(id(&vocabulary, token) as usize), "{token}");
+        // The first mask of each phase is the mask of a fresh sampler of its grammar.
+        let phase = chain.phase();
+        if phases.last() != Some(&phase) {
+            let grammar = [PREAMBLE, TOOL_CALL, TEXT][phase];
+            assert_eq!(mask, first_mask(grammar, &vocabulary), "{token}");
+        }
+        phases.push(phase);
+        input = Some(id(&vocabulary, token));
+    }
+    assert_eq!(
+        chain.all_possible_next_tokens(input).unwrap(),
+        PossibleTokensResult::End
+    );
+    assert_eq!(phases, [0, 0, 1, 1, 1, 1, 2, 2, 2]);
+}
+
+#[test]
+fn chain_rejects_tokens_of_other_phases() {
+    let vocabulary = tiny_vocabulary();
+    let mut chain = new_sampler(PREAMBLE, &vocabulary, SamplerConfig::default())
+        .then(spec(TOOL_CALL, &vocabulary));
+    for token in ["ok", "."] {
+        chain.accept_a_token(Some(id(&vocabulary, token))).unwrap();
+    }
+    assert_eq!(chain.phase(), 1);
+    assert_eq!(
+        chain
+            .all_possible_next_tokens(Some(id(&vocabulary, "ok")))
+            .unwrap(),
+        PossibleTokensResult::InputTokenRejected
+    );
+    assert_eq!(chain.phase(), 1);
+    assert!(matches!(
+        chain
+            .all_possible_next_tokens(Some(id(&vocabulary, "call")))
+            .unwrap(),
+        PossibleTokensResult::Continue(_)
+    ));
+}
+
+fn union(vocabulary: &Arc<Vocabulary>) -> UnionSampler {
+    UnionSampler::new(vec![
+        new_sampler(TOOL_CALL, vocabulary, SamplerConfig::default()),
+        new_sampler(TEXT, vocabulary, SamplerConfig::default()),
+    ])
+    .unwrap()
+}
+
+#[test]
+fn union_masks_with_all_candidates_until_one_is_left() {
+    let vocabulary = tiny_vocabulary();
+    let mut union = union(&vocabulary);
+    let mut expected = first_mask(TOOL_CALL, &vocabulary);
+    expected.union_with(&first_mask(TEXT, &vocabulary));
+    match union.all_possible_next_tokens(None).unwrap() {
+        PossibleTokensResult::Continue(mask) => assert_eq!(*mask, expected),
+        x => panic!("{x:?}"),
+    }
+    assert_eq!(union.candidates(), [0, 1]);
+    match union
+        .all_possible_next_tokens(Some(id(&vocabulary, "call")))
+        .unwrap()
+    {
+        PossibleTokensResult::Continue(mask) => {
+            assert!(mask.contains(id(&vocabulary, "(") as usize));
+            assert!(!mask.contains(id(&vocabulary, "a") as usize));
+        }
+        x => panic!("{x:?}"),
+    }
+    assert_eq!(union.candidates(), [0]);
+    for token in ["(", "1"] {
+        union.accept_a_token(Some(id(&vocabulary, token))).unwrap();
+    }
+    assert_eq!(union.ended(), None);
+    assert_eq!(
+        union
+            .all_possible_next_tokens(Some(id(&vocabulary, ")")))
+            .unwrap(),
+        PossibleTokensResult::End
+    );
+    assert_eq!(union.ended(), Some(0));
+}
+
+#[test]
+fn union_keeps_the_candidates_when_all_reject() {
+    let vocabulary = tiny_vocabulary();
+    let mut union = union(&vocabulary);
+    assert_eq!(
+        union
+            .all_possible_next_tokens(Some(id(&vocabulary, "ok")))
+            .unwrap(),
+        PossibleTokensResult::InputTokenRejected
+    );
+    assert_eq!(union.candidates(), [0, 1]);
+    for token in ["a", "a"] {
+        union.accept_a_token(Some(id(&vocabulary, token))).unwrap();
+    }
+    assert_eq!(union.candidates(), [1]);
+    assert_eq!(
+        union
+            .all_possible_next_tokens(Some(id(&vocabulary, ".")))
+            .unwrap(),
+        PossibleTokensResult::End
+    );
+    assert_eq!(union.ended(), Some(1));
+}
+
+#[test]
+fn union_needs_one_shared_vocabulary() {
+    let vocabulary = tiny_vocabulary();
+    assert!(UnionSampler::new(vec![]).is_err());
+    let other = tiny_vocabulary();
+    let grammar = Grammar::new(TEXT, other.clone(), 0).unwrap();
+    let samplers = vec![
+        new_sampler(TEXT, &vocabulary, SamplerConfig::default()),
+        Sampler::with_config(
+            grammar,
+            "start".to_string(),
+            other,
+            SamplerConfig::default(),
+        )
+        .unwrap(),
+    ];
+    assert!(UnionSampler::new(samplers)
+        .unwrap_err()
+        .to_string()
+        .contains("same vocabulary"));
+}
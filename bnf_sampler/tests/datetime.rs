@@ -0,0 +1,104 @@
+mod common;
+
+use bnf_sampler::presets;
+use bnf_sampler::sampler::SamplerConfig;
+use common::{generate, tiny_vocabulary, validates, Model};
+use serde_json::json;
+
+fn assert_validates(grammar: &str, accepted: &[&str], rejected: &[&str]) {
+    let vocabulary = tiny_vocabulary();
+    for input in accepted {
+        assert!(
+            validates(grammar, &vocabulary, input.as_bytes()),
+            "{grammar}: {input}"
+        );
+    }
+    for input in rejected {
+        assert!(
+            !validates(grammar, &vocabulary, input.as_bytes()),
+            "{grammar}: {input}"
+        );
+    }
+}
+
+#[test]
+fn dates() {
+    assert_validates(
+        "<start>::=<date!>",
+        &[
+            "2024-01-31",
+            "1999-02-29",
+            "0001-04-30",
+            "2024-12-01",
+            "2024-10-19",
+        ],
+        &[
+            "2024-00-10",
+            "2024-13-01",
+            "2024-01-00",
+            "2024-01-32",
+            "2024-04-31",
+            "2024-02-30",
+            "24-01-01",
+            "2024-1-01",
+            "2024/01/01",
+            "2024-01-1",
+        ],
+    );
+}
+
+#[test]
+fn times() {
+    assert_validates(
+        "<start>::=<time!>",
+        &["00:00:00", "23:59:59", "12:30:45.5", "08:00:00.123456"],
+        &[
+            "24:00:00",
+            "12:60:00",
+            "12:00:60",
+            "12:00",
+            "12:00:00.",
+            "1:00:00",
+        ],
+    );
+}
+
+#[test]
+fn datetimes() {
+    assert_validates(
+        "<start>::=<datetime!>",
+        &[
+            "2024-01-15T10:20:30",
+            "2024-01-15T10:20:30Z",
+            "2024-01-15T10:20:30.25+05:30",
+            "2024-06-30T23:59:59-08:00",
+        ],
+        &[
+            "2024-01-15 10:20:30",
+            "2024-01-15T10:20:30+5:30",
+            "2024-01-15T10:20:30z",
+            "2024-06-31T00:00:00Z",
+            "2024-01-15",
+        ],
+    );
+}
+
+#[test]
+fn json_schema_date_time_format() {
+    let vocabulary = tiny_vocabulary();
+    let schema = json!({
+        "type": "object",
+        "properties": {"at": {"type": "string", "format": "date-time"}},
+        "required": ["at"]
+    });
+    let mut sampler =
+        presets::constrained_json(&schema, vocabulary.clone(), SamplerConfig::new()).unwrap();
+    let preferred = [
+        "{\"", "20", "12", "-", "1", "2", "-", "2", "5", "T", "1", "2", ":", "30", ":", "0", "0",
+        "Z", "\"}",
+    ];
+    let generation = generate(&mut sampler, &vocabulary, &Model::Prefer(&preferred), 64).unwrap();
+    assert!(generation.ended, "{}", generation.output());
+    let document: serde_json::Value = serde_json::from_slice(&generation.output).unwrap();
+    assert_eq!(document, json!({"at": "2012-12-25T12:30:00Z"}));
+}
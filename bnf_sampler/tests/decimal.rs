@@ -0,0 +1,113 @@
+mod common;
+
+use bnf_sampler::grammar::Grammar;
+use bnf_sampler::presets;
+use bnf_sampler::sampler::{AcceptTokenResult, SamplerConfig};
+use common::{tiny_vocabulary, validates};
+use serde_json::json;
+
+fn assert_validates(grammar: &str, accepted: &[&str], rejected: &[&str]) {
+    let vocabulary = tiny_vocabulary();
+    for input in accepted {
+        assert!(
+            validates(grammar, &vocabulary, input.as_bytes()),
+            "{grammar}: {input}"
+        );
+    }
+    for input in rejected {
+        assert!(
+            !validates(grammar, &vocabulary, input.as_bytes()),
+            "{grammar}: {input}"
+        );
+    }
+}
+
+#[test]
+fn at_most_the_fraction_digits() {
+    assert_validates(
+        "<start>::=<decimal!(10, 2)>",
+        &["0.00", "0.5", "-3.14", "1234567890.99", "10.0"],
+        &[
+            ".5",
+            "01.23",
+            "00.1",
+            "1.234",
+            "12345678901.5",
+            "5",
+            "5.",
+            "-",
+            "-.5",
+            "+1.5",
+        ],
+    );
+}
+
+#[test]
+fn exact_and_optional_fractions() {
+    assert_validates(
+        "<start>::=<decimal!(3, 2, exact)>",
+        &["0.00", "999.99", "-1.50"],
+        &["1.5", "1.500", "1000.00", "7"],
+    );
+    assert_validates(
+        "<start>::='$'<decimal!(3, 2, exact, optional)>",
+        &["$7", "$7.25", "$0"],
+        &["$7.2", "$07", "$7."],
+    );
+}
+
+#[test]
+fn exponents() {
+    assert_validates(
+        "<start>::=<decimal!(1, 3, exponent)>",
+        &["1.5e10", "0.001E-3", "-2.0e+7", "9.99"],
+        &["1.5e", "1.5e+", "15.0e1", "1e5"],
+    );
+}
+
+#[test]
+fn invalid_arguments_are_rejected() {
+    let vocabulary = tiny_vocabulary();
+    for (args, reason) in [
+        ("10", "the maximum number of fraction digits is missing."),
+        (
+            "0, 2",
+            "the maximum number of integer digits should be positive.",
+        ),
+        (
+            "3, 2, rounded",
+            "rounded is not one of exact, optional and exponent.",
+        ),
+    ] {
+        let error = Grammar::new(
+            &format!("<start>::=<decimal!({args})>"),
+            vocabulary.clone(),
+            0,
+        )
+        .unwrap_err()
+        .to_string();
+        assert_eq!(
+            error,
+            format!("<decimal!({args})> is invalid because {reason}")
+        );
+    }
+}
+
+#[test]
+fn json_schema_multiple_of() {
+    let vocabulary = tiny_vocabulary();
+    let schema = json!({"type": "number", "multipleOf": 0.01});
+    let mut sampler =
+        presets::constrained_json(&schema, vocabulary.clone(), SamplerConfig::new()).unwrap();
+    for (price, accepted) in [
+        ("19.99", true),
+        ("20", true),
+        ("0.5", true),
+        ("19.999", false),
+        ("1e2", false),
+    ] {
+        sampler.reset();
+        let result = sampler.accept_bytes(price.as_bytes()).unwrap();
+        assert_eq!(result == AcceptTokenResult::End, accepted, "{price}");
+    }
+}
@@ -0,0 +1,163 @@
+mod common;
+
+use bnf_sampler::grammar::{Diagnostic, DiagnosticKind, Grammar, GrammarBuildOptions, Severity};
+use bnf_sampler::vocabulary::{Vocabulary, DEFAULT_MAX_TOKEN_BYTES};
+use common::tiny_vocabulary;
+use rustc_hash::FxHashMap;
+use std::sync::Arc;
+
+fn check(grammar: &str) -> Vec<(Severity, DiagnosticKind, Option<String>)> {
+    Grammar::check(
+        grammar,
+        tiny_vocabulary(),
+        GrammarBuildOptions::new(),
+        "start",
+    )
+    .into_iter()
+    .map(|x| (x.severity, x.kind, x.nonterminal))
+    .collect()
+}
+
+fn at(nonterminal: &str) -> Option<String> {
+    Some(nonterminal.to_string())
+}
+
+#[test]
+fn clean_grammar_has_no_diagnostics() {
+    assert_eq!(check("<start>::='a'<x>|'b'\n<x>::='c'|'d'"), vec![]);
+}
+
+#[test]
+fn build_errors_are_diagnostics() {
+    use DiagnosticKind::*;
+    assert_eq!(
+        check("<start>::=<valu>\n<value>::='1'"),
+        vec![(Severity::Error, UndefinedNonterminal, at("valu"))]
+    );
+    assert_eq!(
+        check("<start>::=<a>|<b>\n<b>::='1'"),
+        vec![(Severity::Error, UndefinedNonterminal, at("a"))]
+    );
+    assert_eq!(
+        check("<start>::=<x>|<y>"),
+        vec![(Severity::Error, UndefinedNonterminal, None)]
+    );
+    assert_eq!(
+        check("<start>::=<b>'x'\n<b>::=<start>'y'|'z'"),
+        vec![(Severity::Error, LeftRecursion, at("b"))]
+    );
+    assert_eq!(
+        check("<start>::='\"'<except!()>'\"'"),
+        vec![(Severity::Error, InvalidSpecialForm, at("except!()"))]
+    );
+    assert_eq!(
+        check("<start>::='a"),
+        vec![(Severity::Error, InvalidSchema, None)]
+    );
+}
+
+#[test]
+fn grammar_new_returns_the_error_diagnostic() {
+    let error = Grammar::new(
+        "<start>::=<b>'x'\n<b>::=<start>'y'|'z'",
+        tiny_vocabulary(),
+        0,
+    )
+    .unwrap_err();
+    let diagnostic = error.downcast_ref::<Diagnostic>().unwrap();
+    assert_eq!(diagnostic.kind, DiagnosticKind::LeftRecursion);
+    assert_eq!(
+        diagnostic.production.as_deref(),
+        Some("<b> -> <start> -> <b>")
+    );
+    assert_eq!(diagnostic.to_string(), error.to_string());
+}
+
+#[test]
+fn start_problems_are_errors() {
+    let grammar =
+        Grammar::new("<start>::='x'<start>\n<other>::='y'", tiny_vocabulary(), 0).unwrap();
+    let diagnostics = grammar.validate("start");
+    assert_eq!(diagnostics[0].severity, Severity::Error);
+    assert_eq!(diagnostics[0].kind, DiagnosticKind::NonProductive);
+    assert_eq!(
+        grammar
+            .validate("begin")
+            .into_iter()
+            .map(|x| (x.severity, x.kind, x.nonterminal))
+            .collect::<Vec<_>>(),
+        vec![(
+            Severity::Error,
+            DiagnosticKind::UndefinedNonterminal,
+            at("begin")
+        )]
+    );
+}
+
+#[test]
+fn warnings_are_sorted_by_nonterminal() {
+    use DiagnosticKind::*;
+    assert_eq!(
+        check("<start>::='a'|'b'<loop>|'c'<except!([all])>\n<all>::=<any!>\n<loop>::='x'<loop>\n<unused>::='u'"),
+        vec![
+            (Severity::Warning, EmptyTokenSet, at("except!([all])")),
+            (Severity::Warning, NonProductive, at("loop")),
+            (Severity::Warning, Unreachable, at("unused")),
+        ]
+    );
+}
+
+/// No token contains `z`, and `q` only appears after `x`.
+fn gap_vocabulary() -> Arc<Vocabulary> {
+    let tokens = ["a", "b", "ab", "xq", "."];
+    let id_to_token: FxHashMap<u32, Vec<u8>> = tokens
+        .iter()
+        .enumerate()
+        .map(|(i, x)| (i as u32, x.as_bytes().to_vec()))
+        .collect();
+    let id_to_token_string = tokens
+        .iter()
+        .enumerate()
+        .map(|(i, x)| (i as u32, x.to_string()))
+        .collect();
+    Arc::new(Vocabulary::new(id_to_token, id_to_token_string, DEFAULT_MAX_TOKEN_BYTES).unwrap())
+}
+
+#[test]
+fn unproducible_terminals_are_warnings_unless_pruned() {
+    let grammar = "<start>::=<word>'.'\n<word>::='a'|'ab'|'aq'|'xq'|'zz'";
+    let diagnostics = Grammar::check(
+        grammar,
+        gap_vocabulary(),
+        GrammarBuildOptions::new(),
+        "start",
+    );
+    assert_eq!(
+        diagnostics
+            .iter()
+            .map(|x| (x.kind, x.nonterminal.as_deref(), x.production.as_deref()))
+            .collect::<Vec<_>>(),
+        vec![
+            (
+                DiagnosticKind::UnproducibleTerminal,
+                Some("word"),
+                Some("'aq'")
+            ),
+            (
+                DiagnosticKind::UnproducibleTerminal,
+                Some("word"),
+                Some("'zz'")
+            ),
+        ]
+    );
+    assert!(diagnostics[1].message.contains("no token contains 'z'"));
+    assert_eq!(
+        Grammar::check(
+            grammar,
+            gap_vocabulary(),
+            GrammarBuildOptions::new().prune_unreachable(true),
+            "start",
+        ),
+        vec![]
+    );
+}
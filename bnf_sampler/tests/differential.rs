@@ -0,0 +1,85 @@
+mod common;
+
+use bnf_sampler::differential::{compare_samplers, StepOutcome};
+use bnf_sampler::mask::TokenMask;
+use bnf_sampler::sampler::{CacheMode, SamplerConfig};
+use common::{new_sampler, tiny_vocabulary};
+use std::sync::Arc;
+
+const GRAMMAR: &str =
+    "<start>::='{'<items>'}'\n<items>::=<item>|<item>','<items>\n<item>::='yes'|'no'";
+
+fn script(tokens: &[&str]) -> Vec<u32> {
+    let vocabulary = tiny_vocabulary();
+    tokens
+        .iter()
+        .map(|x| vocabulary.token_to_id[x.as_bytes()])
+        .collect()
+}
+
+#[test]
+fn configurations_with_the_same_masks_do_not_diverge() {
+    let vocabulary = tiny_vocabulary();
+    let mut a = new_sampler(GRAMMAR, &vocabulary, SamplerConfig::new());
+    let mut b = new_sampler(
+        GRAMMAR,
+        &vocabulary,
+        SamplerConfig::new()
+            .cache_mode(CacheMode::None)
+            .signature_filter(false)
+            .stack_to_bytes_cache(false),
+    );
+    let script = script(&["{", "y", "es", ",", "no", "}"]);
+    assert_eq!(compare_samplers(&mut a, &mut b, &script), []);
+}
+
+#[test]
+fn different_masks_are_reported_with_their_difference() {
+    let vocabulary = tiny_vocabulary();
+    let mut a = new_sampler(GRAMMAR, &vocabulary, SamplerConfig::new());
+    let mut b = new_sampler(
+        "<start>::='{'<items>'}'\n<items>::=<item>|<item>','<items>\n<item>::='yes'",
+        &vocabulary,
+        SamplerConfig::new(),
+    );
+    let divergences = compare_samplers(&mut a, &mut b, &script(&["{", "yes", "}"]));
+    assert_eq!(divergences.len(), 1);
+    let divergence = &divergences[0];
+    assert_eq!(
+        (divergence.step, divergence.token_id),
+        (1, Some(script(&["{"])[0]))
+    );
+    assert_eq!(divergence.only_in_a(), script(&["n", "no"]));
+    assert!(divergence.only_in_b().is_empty());
+    assert_eq!(
+        divergence.to_string(),
+        format!(
+            "Step 1 after token {}: a returns 4 possible tokens and b returns 2 possible tokens, only a allows {:?}",
+            script(&["{"])[0],
+            script(&["n", "no"])
+        )
+    );
+}
+
+#[test]
+fn the_walk_stops_at_different_results() {
+    let vocabulary = tiny_vocabulary();
+    let mut a = new_sampler("<start>::='ab'", &vocabulary, SamplerConfig::new());
+    let mut b = new_sampler("<start>::='ab!'", &vocabulary, SamplerConfig::new());
+    let divergences = compare_samplers(&mut a, &mut b, &script(&["ab", "!"]));
+    assert_eq!(divergences.len(), 1);
+    assert_eq!(divergences[0].step, 1);
+    assert_eq!(divergences[0].a, StepOutcome::End);
+    assert!(matches!(divergences[0].b, StepOutcome::Continue(_)));
+}
+
+#[test]
+fn masks_are_compared_by_their_ids() {
+    let mut a = TokenMask::with_capacity(4096);
+    a.extend([1, 2]);
+    let b: TokenMask = [2, 1].into_iter().collect();
+    assert_eq!(
+        StepOutcome::Continue(Arc::new(a)),
+        StepOutcome::Continue(Arc::new(b))
+    );
+}
@@ -0,0 +1,45 @@
+mod common;
+
+use bnf_sampler::grammar::{Grammar, GrammarError};
+use bnf_sampler::sampler::{Sampler, SamplerConfig};
+use common::tiny_vocabulary;
+
+#[test]
+fn blank_schemas_are_empty_grammars() {
+    let vocabulary = tiny_vocabulary();
+    for input in [
+        "",
+        "  \n\t\n",
+        "# a comment\n\n// another one\n; and another",
+    ] {
+        let error = Grammar::new(input, vocabulary.clone(), 0).unwrap_err();
+        assert_eq!(
+            error.downcast_ref::<GrammarError>(),
+            Some(&GrammarError::EmptyGrammar),
+            "{input:?}"
+        );
+    }
+}
+
+#[test]
+fn unknown_start_lists_the_nonterminals() {
+    let vocabulary = tiny_vocabulary();
+    let grammar = Grammar::new(
+        "<root>::=<value>|<except!('a')>\n<value>::='b'",
+        vocabulary.clone(),
+        0,
+    )
+    .unwrap();
+    assert_eq!(grammar.nonterminals(), vec!["root", "value"]);
+    let error = Sampler::with_config(
+        grammar,
+        "start".to_string(),
+        vocabulary,
+        SamplerConfig::new(),
+    )
+    .unwrap_err();
+    assert_eq!(
+        error.to_string(),
+        "Start_nonterminal start is not defined in the BNF schema. The defined nonterminals are <root>, <value>."
+    );
+}
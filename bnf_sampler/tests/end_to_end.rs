@@ -0,0 +1,74 @@
+// Each example loads the setup in `examples/common` as its own module.
+#![allow(clippy::duplicate_mod)]
+#[path = "../examples/chat_tool_call.rs"]
+#[allow(dead_code)]
+mod chat_tool_call;
+mod common;
+#[path = "../examples/enum_choice.rs"]
+#[allow(dead_code)]
+mod enum_choice;
+#[path = "../examples/json_mode.rs"]
+#[allow(dead_code)]
+mod json_mode;
+
+use bnf_sampler::sampler::{CacheMode, SamplerConfig};
+use common::{generate, new_sampler, tiny_vocabulary, validates, Model};
+
+const MAX_STEPS: usize = 64;
+
+fn assert_generates(grammar: &str, model: Model, expected: &str) {
+    let vocabulary = tiny_vocabulary();
+    for cache_mode in [CacheMode::Full, CacheMode::TrieNodeOnly, CacheMode::None] {
+        let mut sampler = new_sampler(
+            grammar,
+            &vocabulary,
+            SamplerConfig::new().cache_mode(cache_mode),
+        );
+        let generation = generate(&mut sampler, &vocabulary, &model, MAX_STEPS).unwrap();
+        assert!(generation.ended, "{cache_mode:?}: {generation:?}");
+        assert_eq!(generation.output(), expected, "{cache_mode:?}");
+        assert!(validates(grammar, &vocabulary, &generation.output));
+    }
+}
+
+#[test]
+fn enum_choice_with_lowest_ids() {
+    assert_generates(enum_choice::GRAMMAR, Model::LowestId, "blue");
+}
+
+#[test]
+fn json_mode_with_preferred_tokens() {
+    assert_generates(
+        json_mode::GRAMMAR,
+        Model::Prefer(&["{\"", "name", "\":", " ", "42", "}"]),
+        "{\"name\": 42}",
+    );
+}
+
+#[test]
+fn chat_tool_call_with_preferred_tokens() {
+    assert_generates(
+        chat_tool_call::GRAMMAR,
+        Model::Prefer(&[
+            " the", " weather", "?", "\n", "Action", "weather", "(", "Paris", ")",
+        ]),
+        "Thought: the weather?\nAction: weather(Paris)",
+    );
+}
+
+#[test]
+fn unbounded_generation_stops_at_the_limit() {
+    let vocabulary = tiny_vocabulary();
+    let mut sampler = new_sampler(json_mode::GRAMMAR, &vocabulary, SamplerConfig::new());
+    let generation = generate(&mut sampler, &vocabulary, &Model::LowestId, MAX_STEPS).unwrap();
+    // The lowest id continues the string forever.
+    assert!(!generation.ended);
+    assert_eq!(generation.token_ids.len(), MAX_STEPS);
+}
+
+#[test]
+fn token_matched_through_alternatives_with_the_same_remaining_bytes() {
+    // After `a`, both alternatives have `bc` left to match, which must not share a cached result.
+    let grammar = "<start>::=<letter><letter>|'a'<rest>\n<letter>::='a'|'b'|'c'\n<rest>::=<letter><last>\n<last>::=<letter>";
+    assert_generates(grammar, Model::Prefer(&["abc"]), "abc");
+}
@@ -0,0 +1,82 @@
+mod common;
+
+use bnf_sampler::enumerate::{EnumLimit, EnumLimitReached, EnumLimits};
+use bnf_sampler::grammar::Grammar;
+use common::tiny_vocabulary;
+
+#[test]
+fn enum_template_sentences() {
+    let grammar = Grammar::new(
+        "<start>::=<size>' '<color>' '<shape>\n<size>::='small'|'large'\n<color>::='red'|'green'|'blue'\n<shape>::='box'|'ball'",
+        tiny_vocabulary(),
+        0,
+    )
+    .unwrap();
+    let sentences = grammar
+        .enumerate_sentences("start", EnumLimits::default())
+        .unwrap();
+    let mut expected = vec![];
+    for size in ["small", "large"] {
+        for color in ["red", "green", "blue"] {
+            for shape in ["box", "ball"] {
+                expected.push(format!("{size} {color} {shape}").into_bytes());
+            }
+        }
+    }
+    expected.sort();
+    assert_eq!(sentences, expected);
+}
+
+#[test]
+fn optional_parts_and_token_sets() {
+    let grammar = Grammar::new(
+        "<start>::='v'<digit><suffix>\n<digit>::=[1-3]\n<suffix>::=''|'-rc'",
+        tiny_vocabulary(),
+        0,
+    )
+    .unwrap();
+    let sentences = grammar
+        .enumerate_sentences("start", EnumLimits::default())
+        .unwrap();
+    assert_eq!(
+        sentences,
+        ["v1", "v1-rc", "v2", "v2-rc", "v3", "v3-rc"].map(|x| x.as_bytes().to_vec())
+    );
+}
+
+#[test]
+fn recursive_rules_are_infinite() {
+    let grammar = Grammar::new(
+        "<start>::=<digits>\n<digits>::=[0-9]|[0-9]<digits>",
+        tiny_vocabulary(),
+        0,
+    )
+    .unwrap();
+    let error = grammar
+        .enumerate_sentences("start", EnumLimits::default())
+        .unwrap_err();
+    let reached = error.downcast_ref::<EnumLimitReached>().unwrap();
+    assert!(reached.infinite, "{reached:?}");
+}
+
+#[test]
+fn small_limits_on_finite_grammars() {
+    let grammar =
+        Grammar::new("<start>::=<a><a>\n<a>::='x'|'y'|'z'", tiny_vocabulary(), 0).unwrap();
+    let limits = EnumLimits {
+        max_sentences: 4,
+        ..EnumLimits::default()
+    };
+    let error = grammar.enumerate_sentences("start", limits).unwrap_err();
+    assert_eq!(
+        error.downcast_ref::<EnumLimitReached>(),
+        Some(&EnumLimitReached {
+            limit: EnumLimit::Sentences,
+            infinite: false
+        })
+    );
+    assert_eq!(
+        error.to_string(),
+        "The enumeration reaches the maximum number of sentences, but the grammar is finite."
+    );
+}
@@ -0,0 +1,80 @@
+mod common;
+
+use bnf_sampler::sampler::{AcceptTokenResult, PossibleTokensResult, SamplerConfig};
+use common::{new_sampler, tiny_vocabulary, validates};
+
+fn possible_tokens(result: PossibleTokensResult) -> Vec<String> {
+    let vocabulary = tiny_vocabulary();
+    match result {
+        PossibleTokensResult::Continue(token_ids) => vocabulary
+            .get_token_strings_from_token_ids(token_ids)
+            .map(|x| x.to_string())
+            .collect(),
+        result => panic!("{result:?}"),
+    }
+}
+
+#[test]
+fn optional_part_between_terminals() {
+    let vocabulary = tiny_vocabulary();
+    for grammar in [
+        "<start>::='a'<opt>'b'\n<opt>::='x'|''",
+        "<start>::='a'<opt>'b'\n<opt>::='x'|\"\"",
+    ] {
+        assert!(validates(grammar, &vocabulary, b"ab"));
+        assert!(validates(grammar, &vocabulary, b"axb"));
+        assert!(!validates(grammar, &vocabulary, b"a"));
+        assert!(!validates(grammar, &vocabulary, b"axxb"));
+        let mut sampler = new_sampler(grammar, &vocabulary, SamplerConfig::new());
+        // The token `ab` spans the empty alternative.
+        assert_eq!(
+            possible_tokens(sampler.all_possible_next_tokens(None).unwrap()),
+            ["a", "ab"]
+        );
+        let a = vocabulary.token_to_id[&b"a"[..]];
+        assert_eq!(
+            possible_tokens(sampler.all_possible_next_tokens(Some(a)).unwrap()),
+            ["b", "x"]
+        );
+    }
+}
+
+#[test]
+fn consecutive_optional_parts() {
+    let vocabulary = tiny_vocabulary();
+    let grammar = "<start>::='a'<x><y>'b'\n<x>::='x'|''\n<y>::='y'|''";
+    for output in ["ab", "axb", "ayb", "axyb"] {
+        assert!(
+            validates(grammar, &vocabulary, output.as_bytes()),
+            "{output}"
+        );
+    }
+    assert!(!validates(grammar, &vocabulary, b"ayxb"));
+}
+
+#[test]
+fn ends_when_the_rest_of_the_stack_is_nullable() {
+    let vocabulary = tiny_vocabulary();
+    let grammar = "<start>::='a'<opt>\n<opt>::='x'|''";
+    let mut sampler = new_sampler(grammar, &vocabulary, SamplerConfig::new());
+    assert_eq!(
+        sampler
+            .accept_a_token(Some(vocabulary.token_to_id[&b"a"[..]]))
+            .unwrap(),
+        AcceptTokenResult::End
+    );
+    assert!(validates(grammar, &vocabulary, b"ax"));
+}
+
+#[test]
+fn nullable_start_ends_immediately() {
+    let vocabulary = tiny_vocabulary();
+    for grammar in ["<start>::=''", "<start>::=<opt>\n<opt>::='x'|''"] {
+        let mut sampler = new_sampler(grammar, &vocabulary, SamplerConfig::new());
+        assert_eq!(
+            sampler.all_possible_next_tokens(None).unwrap(),
+            PossibleTokensResult::End,
+            "{grammar}"
+        );
+    }
+}
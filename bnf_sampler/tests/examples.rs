@@ -0,0 +1,43 @@
+//! Runs the examples so they can't rot.
+// Each example loads the setup in `examples/common` as its own module.
+#![allow(clippy::duplicate_mod)]
+#[path = "../examples/chat_tool_call.rs"]
+#[allow(dead_code)]
+mod chat_tool_call;
+#[path = "../examples/enum_choice.rs"]
+#[allow(dead_code)]
+mod enum_choice;
+#[path = "../examples/hold_mask.rs"]
+#[allow(dead_code)]
+mod hold_mask;
+#[path = "../examples/json_mode.rs"]
+#[allow(dead_code)]
+mod json_mode;
+
+#[test]
+fn json_mode_generates_the_target() {
+    assert_eq!(json_mode::run().unwrap(), json_mode::TARGET);
+}
+
+#[test]
+fn enum_choice_only_allows_the_choices() {
+    let (output, mut choices) = enum_choice::run().unwrap();
+    assert_eq!(output, enum_choice::TARGET);
+    choices.sort();
+    assert_eq!(choices, vec!["b", "blue", "g", "green", "r", "red", "y"]);
+}
+
+#[test]
+fn chat_tool_call_generates_the_target() {
+    let (output, model_calls) = chat_tool_call::run().unwrap();
+    assert_eq!(output, chat_tool_call::TARGET);
+    // `Thought:` and the `:` after `Action` are forced, one byte token at a time.
+    assert_eq!(model_calls, 16);
+}
+
+#[test]
+fn hold_mask_reads_the_masks_after_accepting() {
+    let (output, mask_sizes) = hold_mask::run().unwrap();
+    assert_eq!(output, "The answer is yes.");
+    assert_eq!(mask_sizes, [2, 4, 1, 1, 2, 2, 2, 2, 1]);
+}
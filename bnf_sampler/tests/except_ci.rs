@@ -0,0 +1,45 @@
+mod common;
+
+use bnf_sampler::sampler::{PossibleTokensResult, SamplerConfig};
+use common::{new_sampler, validates, vocabulary_of};
+
+const TOKENS: &[&str] = &[
+    "e", "n", "d", "E", "N", "D", " ", "end", "END", "eNd", "x", "xEnD", "en", "En", "nd", "ND",
+    ".",
+];
+
+fn allowed_tokens(grammar: &str) -> Vec<&'static str> {
+    let mut sampler = new_sampler(grammar, &vocabulary_of(TOKENS), SamplerConfig::new());
+    match sampler.all_possible_next_tokens(None).unwrap() {
+        PossibleTokensResult::Continue(token_ids) => token_ids.iter().map(|x| TOKENS[x]).collect(),
+        result => panic!("{result:?}"),
+    }
+}
+
+#[test]
+fn tokens_containing_any_casing_are_masked_out() {
+    let expected = [
+        "e", "n", "d", "E", "N", "D", " ", "x", "en", "En", "nd", "ND", ".",
+    ];
+    assert_eq!(allowed_tokens("<start>::=<except_ci!('END')>"), expected);
+    assert_eq!(allowed_tokens("<start>::=<except_ci!('eNd')>"), expected);
+    assert_eq!(
+        allowed_tokens("<start>::=<except!('END')>"),
+        [
+            "e", "n", "d", "E", "N", "D", " ", "end", "eNd", "x", "xEnD", "en", "En", "nd", "ND",
+            "."
+        ]
+    );
+}
+
+#[test]
+fn tokens_stop_before_any_casing() {
+    let vocabulary = vocabulary_of(TOKENS);
+    let grammar = "<start>::=<except_ci!('end', '.')>'.'";
+    assert!(validates(grammar, &vocabulary, b"En."));
+    assert!(!validates(grammar, &vocabulary, b"eNd."));
+    assert!(!validates(grammar, &vocabulary, b"xEnD."));
+    let grammar = "<start>::=<except_ci!('end')>'EnD'";
+    assert!(validates(grammar, &vocabulary, b"xEnD"));
+    assert!(!validates(grammar, &vocabulary, b"EnD"));
+}
@@ -0,0 +1,45 @@
+mod common;
+
+use bnf_sampler::grammar::Grammar;
+use bnf_sampler::sampler::{PossibleTokensResult, SamplerConfig};
+use common::{new_sampler, vocabulary_of};
+
+const TOKENS: &[&str] = &["a", "\0", "b\0c", "\u{201c}", "x\u{201c}", "\0\u{201c}"];
+
+fn allowed_tokens(grammar: &str) -> Vec<&'static str> {
+    let mut sampler = new_sampler(grammar, &vocabulary_of(TOKENS), SamplerConfig::new());
+    match sampler.all_possible_next_tokens(None).unwrap() {
+        PossibleTokensResult::Continue(token_ids) => token_ids.iter().map(|x| TOKENS[x]).collect(),
+        result => panic!("{result:?}"),
+    }
+}
+
+#[test]
+fn tokens_containing_the_hex_literal_are_masked_out() {
+    assert_eq!(
+        allowed_tokens("<start>::=<except!(x\"00\")>"),
+        vec!["a", "\u{201c}", "x\u{201c}"]
+    );
+    assert_eq!(
+        allowed_tokens("<start>::=<except!(x'00E2809C')>"),
+        vec!["a", "\0", "b\0c", "\u{201c}", "x\u{201c}"]
+    );
+    assert_eq!(
+        allowed_tokens("<start>::=<except!(x\"e2809c\")>"),
+        vec!["a", "\0", "b\0c"]
+    );
+}
+
+#[test]
+fn invalid_hex_literals_are_errors() {
+    for (grammar, literal) in [
+        ("<start>::=<except!(x\"0\")>", "x\"0\""),
+        ("<start>::=<except!(x\"0g\")>", "x\"0g\""),
+        ("<start>::=<except!(x\"00 11\")>", "x\"00 11\""),
+    ] {
+        let error = Grammar::new(grammar, vocabulary_of(TOKENS), 0)
+            .unwrap_err()
+            .to_string();
+        assert!(error.contains(literal), "{error}");
+    }
+}
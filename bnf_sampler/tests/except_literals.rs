@@ -0,0 +1,76 @@
+mod common;
+
+use bnf_sampler::grammar::Grammar;
+use bnf_sampler::sampler::{PossibleTokensResult, SamplerConfig};
+use common::{new_sampler, validates, vocabulary_of};
+
+const TOKENS: &[&str] = &[
+    "a", "b", "c", "x", ",", "ab", "ba", "abc", "xab", "aab", "aaab", "a,b", ".",
+];
+
+fn allowed_tokens(grammar: &str) -> Vec<&'static str> {
+    let mut sampler = new_sampler(grammar, &vocabulary_of(TOKENS), SamplerConfig::new());
+    match sampler.all_possible_next_tokens(None).unwrap() {
+        PossibleTokensResult::Continue(token_ids) => token_ids.iter().map(|x| TOKENS[x]).collect(),
+        result => panic!("{result:?}"),
+    }
+}
+
+#[test]
+fn tokens_containing_any_literal_are_masked_out() {
+    assert_eq!(
+        allowed_tokens("<start>::=<except!('ab', 'ba')>"),
+        ["a", "b", "c", "x", ",", "a,b", "."]
+    );
+    assert_eq!(
+        allowed_tokens("<start>::=<except!('ab','abc')>"),
+        ["a", "b", "c", "x", ",", "ba", "a,b", "."]
+    );
+    assert_eq!(
+        allowed_tokens("<start>::=<except!(',', x\"63\")>"),
+        ["a", "b", "x", "ab", "ba", "xab", "aab", "aaab", "."]
+    );
+}
+
+#[test]
+fn literals_overlapping_themselves_are_found() {
+    assert_eq!(
+        allowed_tokens("<start>::=<except!('aab')>"),
+        ["a", "b", "c", "x", ",", "ab", "ba", "abc", "xab", "a,b", "."]
+    );
+    let vocabulary = vocabulary_of(TOKENS);
+    assert!(!validates(
+        "<start>::=<except!('aab')>'.'",
+        &vocabulary,
+        b"aaab."
+    ));
+    assert!(validates(
+        "<start>::=<except!('aab')>'aab.'",
+        &vocabulary,
+        b"aaab."
+    ));
+}
+
+#[test]
+fn text_stops_before_the_first_literal() {
+    let vocabulary = vocabulary_of(TOKENS);
+    let grammar =
+        "<start>::=<text>'.'\n<text>::=<except!('ab','abc','.')>|<except!('ab','abc','.')><text>";
+    assert!(validates(grammar, &vocabulary, b"ba,cba,a."));
+    assert!(!validates(grammar, &vocabulary, b"xab."));
+    let grammar = "<start>::=<except!('ab','abc')>'abc'";
+    assert!(validates(grammar, &vocabulary, b"xabc"));
+    assert!(validates(grammar, &vocabulary, b"aabc"));
+    assert!(!validates(grammar, &vocabulary, b"xab"));
+}
+
+#[test]
+fn literals_must_be_separated_by_commas() {
+    let error = Grammar::new("<start>::=<except!('a' 'b')>", vocabulary_of(TOKENS), 0)
+        .unwrap_err()
+        .to_string();
+    assert!(
+        error.contains("the literals ('a' 'b') are not separated by commas."),
+        "{error}"
+    );
+}
@@ -0,0 +1,107 @@
+mod common;
+
+use bnf_sampler::sampler::{
+    AcceptTokenResult, FastForwardResult, PossibleTokensResult, Sampler, SamplerConfig,
+};
+use bnf_sampler::vocabulary::Vocabulary;
+use common::{new_sampler, tiny_vocabulary};
+use std::sync::Arc;
+
+const GRAMMAR: &str = "<start>::='{\"name\":\"'<name>'\",\"age\":'<age>'}'
+<name>::=<except!('\"')>|<except!('\"')><name>
+<age>::=<digit>|<digit><age>
+<digit>::='0'|'1'|'2'|'3'|'4'|'5'|'6'|'7'|'8'|'9'";
+
+fn token_ids(vocabulary: &Vocabulary, tokens: &[&str]) -> Vec<u32> {
+    tokens
+        .iter()
+        .map(|x| vocabulary.token_to_id[x.as_bytes()])
+        .collect()
+}
+
+fn mask(sampler: &mut Sampler) -> Vec<usize> {
+    match sampler.all_possible_next_tokens(None).unwrap() {
+        PossibleTokensResult::Continue(token_ids) => token_ids.iter().collect(),
+        result => panic!("{result:?}"),
+    }
+}
+
+fn fresh_mask(vocabulary: &Arc<Vocabulary>, history: &[u32]) -> Vec<usize> {
+    let mut sampler = new_sampler(GRAMMAR, vocabulary, SamplerConfig::new());
+    for token_id in history {
+        sampler.accept_a_token(Some(*token_id)).unwrap();
+    }
+    mask(&mut sampler)
+}
+
+#[test]
+fn fast_forward_accepts_only_the_new_tokens() {
+    let vocabulary = tiny_vocabulary();
+    let mut sampler = new_sampler(GRAMMAR, &vocabulary, SamplerConfig::new());
+    let history = token_ids(
+        &vocabulary,
+        &["{\"", "name", "\":", "\"", "Bob", "\",", "\""],
+    );
+    let mut previous_len = 0;
+    for len in [2, 5, history.len()] {
+        let result = sampler.fast_forward(&history[..len], previous_len).unwrap();
+        assert_eq!(
+            result,
+            FastForwardResult {
+                result: AcceptTokenResult::Continue,
+                replayed: false
+            }
+        );
+        assert_eq!(sampler.token_history(), Some(&history[..len]));
+        assert_eq!(mask(&mut sampler), fresh_mask(&vocabulary, &history[..len]));
+        previous_len = len;
+    }
+}
+
+#[test]
+fn fast_forward_replays_a_diverging_history() {
+    let vocabulary = tiny_vocabulary();
+    let mut sampler = new_sampler(GRAMMAR, &vocabulary, SamplerConfig::new());
+    let history = token_ids(&vocabulary, &["{\"", "name", "\":", "\"", "Bob"]);
+    sampler.fast_forward(&history, 0).unwrap();
+    let diverged = token_ids(&vocabulary, &["{\"", "name", "\":", "\"", "Alice", "\","]);
+    let result = sampler.fast_forward(&diverged, history.len()).unwrap();
+    assert!(result.replayed);
+    assert_eq!(result.result, AcceptTokenResult::Continue);
+    assert_eq!(sampler.token_history(), Some(diverged.as_slice()));
+    assert_eq!(mask(&mut sampler), fresh_mask(&vocabulary, &diverged));
+}
+
+#[test]
+fn fast_forward_replays_a_truncated_history() {
+    let vocabulary = tiny_vocabulary();
+    let mut sampler = new_sampler(GRAMMAR, &vocabulary, SamplerConfig::new());
+    let history = token_ids(&vocabulary, &["{\"", "name", "\":", "\"", "Bob"]);
+    sampler.fast_forward(&history, 0).unwrap();
+    // The caller dropped the last token, e.g. to regenerate it.
+    let result = sampler.fast_forward(&history[..4], history.len()).unwrap();
+    assert!(result.replayed);
+    assert_eq!(sampler.token_history(), Some(&history[..4]));
+    assert_eq!(mask(&mut sampler), fresh_mask(&vocabulary, &history[..4]));
+}
+
+#[test]
+fn fast_forward_replays_after_bytes_are_accepted() {
+    let vocabulary = tiny_vocabulary();
+    let mut sampler = new_sampler(GRAMMAR, &vocabulary, SamplerConfig::new());
+    sampler.accept_bytes(b"{\"name").unwrap();
+    assert_eq!(sampler.token_history(), None);
+    let history = token_ids(&vocabulary, &["{\"", "name"]);
+    assert!(sampler.fast_forward(&history, 2).unwrap().replayed);
+    assert_eq!(sampler.token_history(), Some(history.as_slice()));
+}
+
+#[test]
+fn fast_forward_stops_at_a_rejected_token() {
+    let vocabulary = tiny_vocabulary();
+    let mut sampler = new_sampler(GRAMMAR, &vocabulary, SamplerConfig::new());
+    let history = token_ids(&vocabulary, &["{\"", "age", "\":"]);
+    let result = sampler.fast_forward(&history, 0).unwrap();
+    assert_eq!(result.result, AcceptTokenResult::Failed);
+    assert_eq!(sampler.token_history(), None);
+}
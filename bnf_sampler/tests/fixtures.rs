@@ -0,0 +1,53 @@
+mod common;
+
+use bnf_sampler::fixtures;
+use bnf_sampler::sampler::{PossibleTokensResult, SamplerConfig};
+use common::{generate, new_sampler, Model};
+
+#[test]
+fn fixture_vocabulary_covers_bytes_whitespace_and_multi_byte_tokens() {
+    let vocabulary = fixtures::vocabulary();
+    assert_eq!(vocabulary.id_to_token.len(), 500);
+    for byte in 0..=255u8 {
+        assert!(vocabulary.token_to_id.get(&[byte][..]).is_some(), "{byte}");
+    }
+    for token in ["    ", "\r\n", "\n  ", "é", "中文", "😀"] {
+        assert!(
+            vocabulary.token_to_id.get(token.as_bytes()).is_some(),
+            "{token:?}"
+        );
+    }
+}
+
+#[test]
+fn fixture_grammars_generate_valid_output() {
+    let vocabulary = fixtures::vocabulary();
+    let cases = [
+        (
+            fixtures::JSON_OBJECT_GRAMMAR,
+            &[
+                "{\"", "name", "\": ", "\"", "中文", "\"", ", \"", "age", "\":", "42", "}",
+            ][..],
+            "{\"name\": \"中文\", \"age\":42}",
+        ),
+        (
+            fixtures::ARITHMETIC_GRAMMAR,
+            &["(", "12", " +", " ", "3", ")", " *", " ", "-", "2", "="][..],
+            "(12 + 3) * -2=",
+        ),
+    ];
+    for (grammar, tokens, expected) in cases {
+        let mut sampler = new_sampler(grammar, &vocabulary, SamplerConfig::new());
+        let generation = generate(&mut sampler, &vocabulary, &Model::Prefer(tokens), 64).unwrap();
+        assert!(generation.ended, "{}", generation.output());
+        assert_eq!(generation.output(), expected);
+    }
+    for (name, grammar) in fixtures::grammars() {
+        let mut sampler = new_sampler(grammar, &vocabulary, SamplerConfig::new());
+        let result = sampler.all_possible_next_tokens(None).unwrap();
+        assert!(
+            matches!(result, PossibleTokensResult::Continue(x) if !x.is_empty()),
+            "{name}"
+        );
+    }
+}
@@ -0,0 +1,139 @@
+mod common;
+
+use bnf_sampler::differential::compare_samplers;
+use bnf_sampler::fixtures;
+use bnf_sampler::grammar::{Grammar, GrammarBuildOptions};
+use bnf_sampler::introspect::PublicTerm;
+use bnf_sampler::sampler::{Sampler, SamplerConfig};
+use bnf_sampler::vocabulary::Vocabulary;
+use common::{generate, tiny_vocabulary, Model};
+use std::sync::Arc;
+
+fn build(grammar: &str, vocabulary: &Arc<Vocabulary>, fold: bool) -> Arc<Grammar> {
+    Grammar::with_options(
+        grammar,
+        vocabulary.clone(),
+        GrammarBuildOptions::new().fold_terminals(fold),
+    )
+    .unwrap()
+}
+
+fn samplers(grammar: &str, vocabulary: &Arc<Vocabulary>) -> [Sampler; 2] {
+    [false, true].map(|fold| {
+        Sampler::with_config(
+            build(grammar, vocabulary, fold),
+            "start".to_string(),
+            vocabulary.clone(),
+            SamplerConfig::new(),
+        )
+        .unwrap()
+    })
+}
+
+/// Walk each token sequence with samplers of the grammar built with and without folding.
+fn assert_same_behavior(grammar: &str, scripts: &[&[&str]]) {
+    let vocabulary = tiny_vocabulary();
+    for script in scripts {
+        let [mut a, mut b] = samplers(grammar, &vocabulary);
+        let script = script
+            .iter()
+            .map(|x| vocabulary.token_to_id[x.as_bytes()])
+            .collect::<Vec<_>>();
+        assert_eq!(compare_samplers(&mut a, &mut b, &script), [], "{script:?}");
+    }
+}
+
+fn terminal(bytes: &str) -> PublicTerm {
+    PublicTerm::Terminal(bytes.as_bytes().to_vec())
+}
+
+fn nonterminal(name: &str) -> PublicTerm {
+    PublicTerm::Nonterminal(name.to_string())
+}
+
+#[test]
+fn shared_bytes_are_folded_into_the_uses() {
+    let grammar = "<start>::='{'<key>':'<value>'}'\n<key>::='\"'<name>'\"'|'\"id\"'\n<name>::='a'|'b'<name>\n<value>::='1'|'2'";
+    let vocabulary = tiny_vocabulary();
+    let folded = build(grammar, &vocabulary, true);
+    assert_eq!(
+        folded.productions("start").unwrap(),
+        [[
+            terminal("{\""),
+            nonterminal("folded!(key)"),
+            terminal("\":"),
+            nonterminal("value"),
+            terminal("}")
+        ]]
+    );
+    assert_eq!(
+        folded.productions("folded!(key)").unwrap(),
+        [vec![terminal("id")], vec![nonterminal("name")]]
+    );
+    // <key> can still be a start nonterminal.
+    assert_eq!(
+        folded.productions("key").unwrap(),
+        [[terminal("\""), nonterminal("folded!(key)"), terminal("\"")]]
+    );
+    assert_eq!(folded.folded_bytes(), 2);
+    assert_eq!(build(grammar, &vocabulary, false).folded_bytes(), 0);
+    assert_same_behavior(
+        grammar,
+        &[
+            &["{", "\"", "b", "a", "\":", "1", "}"],
+            &["{\"", "id", "\":", "2", "}"],
+            &["{", "\"", "\"", ":"],
+            &["{", "\"", "id", "\"", ":", "1", "}"],
+        ],
+    );
+}
+
+#[test]
+fn recursive_nonterminals_are_folded() {
+    let grammar = "<start>::='('<nested>')'\n<nested>::='x'<nested>'y'|'xy'";
+    assert!(build(grammar, &tiny_vocabulary(), true).folded_bytes() > 0);
+    assert_same_behavior(
+        grammar,
+        &[
+            &["(", "x", "x", "y", "y", ")"],
+            &["(", "x", "y", ")"],
+            &["(", "x", "x", "y", "y", "y"],
+            &["(", "x", "y", "y"],
+        ],
+    );
+}
+
+#[test]
+fn excepted_and_unused_nonterminals_are_not_folded() {
+    let grammar =
+        "<start>::='a'<except!([quoted])>'a'|<quoted>\n<quoted>::='\"'<x>'\"'\n<x>::='b'|'c'";
+    let vocabulary = tiny_vocabulary();
+    let folded = build(grammar, &vocabulary, true);
+    assert_eq!(
+        folded.productions("quoted").unwrap(),
+        [[terminal("\""), nonterminal("x"), terminal("\"")]]
+    );
+    assert_eq!(folded.folded_bytes(), 0);
+    assert_same_behavior(grammar, &[&["\"", "b", "\""], &["a", "hello", "a"]]);
+}
+
+#[test]
+fn fixture_grammars_keep_their_masks() {
+    let vocabulary = fixtures::vocabulary();
+    let tokens = [
+        "{", "\"", "name", "\":", " ", "12", ",", " \"", "ok", "\": ", "true", "}", "+", "(", "3",
+        ")", "=",
+    ];
+    for (name, grammar) in fixtures::grammars() {
+        let [mut a, mut b] = samplers(grammar, &vocabulary);
+        let generation =
+            generate(&mut a.clone(), &vocabulary, &Model::Prefer(&tokens), 40).unwrap();
+        assert!(generation.ended, "{name}: {}", generation.output());
+        assert_eq!(
+            compare_samplers(&mut a, &mut b, &generation.token_ids),
+            [],
+            "{name}"
+        );
+    }
+    assert!(build(fixtures::JSON_OBJECT_GRAMMAR, &vocabulary, true).folded_bytes() > 0);
+}
@@ -0,0 +1,67 @@
+mod common;
+
+use bnf_sampler::sampler::{PossibleTokensResult, Sampler};
+use bnf_sampler::vocabulary::Vocabulary;
+use common::tiny_sampler;
+
+// No token other than `q` and `z` starts with the fixed middle section.
+const GRAMMAR: &str = "<start>::=<digit>'qzqzqzq'<digit>\n<digit>::='1'|'2'";
+
+fn to_string(vocabulary: &Vocabulary, token_ids: &[u32]) -> String {
+    token_ids
+        .iter()
+        .map(|id| String::from_utf8_lossy(&vocabulary.id_to_token[id]).into_owned())
+        .collect()
+}
+
+fn possible_tokens(sampler: &mut Sampler, vocabulary: &Vocabulary) -> Vec<String> {
+    match sampler.all_possible_next_tokens(None).unwrap() {
+        PossibleTokensResult::Continue(token_ids) => {
+            let mut tokens: Vec<String> = vocabulary
+                .get_token_strings_from_token_ids(token_ids)
+                .map(str::to_string)
+                .collect();
+            tokens.sort();
+            tokens
+        }
+        result => panic!("{result:?}"),
+    }
+}
+
+#[test]
+fn fixed_section_is_forced() {
+    let (mut sampler, vocabulary) = tiny_sampler(GRAMMAR);
+    assert_eq!(possible_tokens(&mut sampler, &vocabulary), vec!["1", "2"]);
+    // The mask branches at the start.
+    assert!(sampler.forced_continuation(usize::MAX).unwrap().is_empty());
+    let one = vocabulary.token_to_id["1".as_bytes()];
+    assert!(matches!(
+        sampler.all_possible_next_tokens(Some(one)).unwrap(),
+        PossibleTokensResult::Continue(_)
+    ));
+    let forced = sampler.forced_continuation(usize::MAX).unwrap();
+    assert_eq!(to_string(&vocabulary, &forced), "qzqzqzq");
+    assert_eq!(possible_tokens(&mut sampler, &vocabulary), vec!["1", "2"]);
+}
+
+#[test]
+fn forced_continuation_stops_at_the_limit() {
+    let (mut sampler, vocabulary) = tiny_sampler(GRAMMAR);
+    let one = vocabulary.token_to_id["1".as_bytes()];
+    sampler.all_possible_next_tokens(Some(one)).unwrap();
+    let forced = sampler.forced_continuation(3).unwrap();
+    assert_eq!(to_string(&vocabulary, &forced), "qzq");
+    assert_eq!(possible_tokens(&mut sampler, &vocabulary), vec!["z"]);
+}
+
+#[test]
+fn forced_continuation_stops_at_the_end() {
+    let (mut sampler, vocabulary) = tiny_sampler("<start>::='qzq'");
+    sampler.all_possible_next_tokens(None).unwrap();
+    let forced = sampler.forced_continuation(usize::MAX).unwrap();
+    assert_eq!(to_string(&vocabulary, &forced), "qzq");
+    assert_eq!(
+        sampler.all_possible_next_tokens(None).unwrap(),
+        PossibleTokensResult::End
+    );
+}
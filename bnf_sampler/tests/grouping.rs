@@ -0,0 +1,79 @@
+mod common;
+
+use bnf_sampler::grammar::Grammar;
+use common::{assert_same_masks, tiny_vocabulary};
+
+#[test]
+fn groups_match_the_manual_expansion() {
+    let grammar = "<start>::=('red'|'blue')' '<item>\n<item>::='sum'|'list'";
+    let expanded = "<start>::=<color>' '<item>\n<color>::='red'|'blue'\n<item>::='sum'|'list'";
+    assert_same_masks(grammar, expanded, &["red", " ", "sum"]);
+    assert_same_masks(grammar, expanded, &["blue", " ", "list"]);
+}
+
+#[test]
+fn nested_and_repeated_groups_match_the_manual_expansion() {
+    let grammar = "<start>::='['('a'('b'|'c')|'x')*']'";
+    let expanded = "<start>::='['<items>']'|'[]'
+<items>::=<item>|<item><items>
+<item>::='a'<bc>|'x'
+<bc>::='b'|'c'";
+    assert_same_masks(grammar, expanded, &["[", "a", "b", "x", "a", "c", "]"]);
+    assert_same_masks(grammar, expanded, &["[", "]"]);
+}
+
+#[test]
+fn groups_can_contain_special_nonterminals() {
+    let grammar = "<start>::='\"'(<except!('\"')>|'\\\\\"')+'\"'";
+    let expanded = "<start>::='\"'<chars>'\"'
+<chars>::=<char>|<char><chars>
+<char>::=<except!('\"')>|'\\\\\"'";
+    assert_same_masks(
+        grammar,
+        expanded,
+        &["\"", "hello", " world", "\\", "\"", "ok", "\""],
+    );
+}
+
+#[test]
+fn parentheses_inside_terminals_and_special_nonterminals_are_literal() {
+    let grammar = "<start>::='('<any_except_bytes!(0x29)>')'|'()'";
+    let expanded = "<start>::='('<a>')'|'()'\n<a>::=<any_except_bytes!(0x29)>";
+    assert_same_masks(grammar, expanded, &["(", "ok", ")"]);
+}
+
+#[test]
+fn group_names_are_hidden_and_do_not_clash() {
+    let vocabulary = tiny_vocabulary();
+    let grammar = Grammar::new(
+        "<start>::=('ok'|'no')<start/group1!>\n<start/group1!>::='.'",
+        vocabulary,
+        0,
+    )
+    .unwrap();
+    assert_eq!(grammar.nonterminals(), ["start"]);
+    let debug = format!("{grammar:?}");
+    assert!(debug.contains("start/group2!"), "{debug}");
+}
+
+#[test]
+fn unbalanced_and_empty_groups_are_rejected() {
+    let vocabulary = tiny_vocabulary();
+    for (grammar, message) in [
+        (
+            "<start>::=('a'|'b'",
+            "<start> is invalid because a ( is not closed.",
+        ),
+        (
+            "<start>::='a')",
+            "<start> is invalid because a ) is not opened.",
+        ),
+        (
+            "<start>::='a'()",
+            "<start> is invalid because the group () is empty.",
+        ),
+    ] {
+        let error = Grammar::new(grammar, vocabulary.clone(), 0).unwrap_err();
+        assert_eq!(error.to_string(), message, "{grammar}");
+    }
+}
@@ -0,0 +1,199 @@
+mod common;
+
+use bnf_sampler::grammar::{Grammar, GrammarBuildOptions, ParseError};
+use bnf_sampler::sampler::{AcceptTokenResult, Sampler};
+use common::tiny_vocabulary;
+use std::fs;
+use std::path::PathBuf;
+
+/// A fresh directory with the files, where a name like `lib/date.bnf` creates the subdirectory.
+fn schema_dir(name: &str, files: &[(&str, &str)]) -> PathBuf {
+    let path =
+        std::env::temp_dir().join(format!("bnf_sampler_include_{name}_{}", std::process::id()));
+    let _ = fs::remove_dir_all(&path);
+    for (file, content) in files {
+        let file = path.join(file);
+        fs::create_dir_all(file.parent().unwrap()).unwrap();
+        fs::write(file, content).unwrap();
+    }
+    path
+}
+
+#[test]
+fn includes_are_relative_to_the_including_file() {
+    let path = schema_dir(
+        "relative",
+        &[
+            (
+                "main.bnf",
+                "@include \"lib/date.bnf\"\n<start>::='due '<date>",
+            ),
+            (
+                "lib/date.bnf",
+                "# Shared rules\n@include \"digits.bnf\"\n<date>::=<digit><digit>'-'<digit><digit>",
+            ),
+            ("lib/digits.bnf", "<digit>::=[0-9]"),
+        ],
+    );
+    let vocabulary = tiny_vocabulary();
+    let grammar = Grammar::from_file(
+        path.join("main.bnf"),
+        vocabulary.clone(),
+        GrammarBuildOptions::new(),
+    )
+    .unwrap();
+    let mut sampler = Sampler::new(grammar, "start".to_string(), vocabulary, 0, false).unwrap();
+    assert_eq!(
+        sampler.accept_bytes(b"due 12-31").unwrap(),
+        AcceptTokenResult::End
+    );
+    fs::remove_dir_all(&path).unwrap();
+}
+
+#[test]
+fn from_files_merges_in_order_and_includes_each_file_once() {
+    let path = schema_dir(
+        "files",
+        &[
+            (
+                "start.bnf",
+                "@include \"digits.bnf\"\n<start>::=<number>' '<date>",
+            ),
+            (
+                "number.bnf",
+                "@include \"digits.bnf\"\n<number>::=<digit>|<digit><number>",
+            ),
+            ("date.bnf", "<date>::=<digit><digit>'-'<digit><digit>"),
+            ("digits.bnf", "<digit>::=[0-9]"),
+        ],
+    );
+    let vocabulary = tiny_vocabulary();
+    let files = ["start.bnf", "number.bnf", "date.bnf"].map(|x| path.join(x));
+    let grammar =
+        Grammar::from_files(&files, vocabulary.clone(), GrammarBuildOptions::new()).unwrap();
+    assert_eq!(grammar.nonterminals().len(), 4);
+    let mut sampler = Sampler::new(grammar, "start".to_string(), vocabulary, 0, false).unwrap();
+    assert_eq!(
+        sampler.accept_bytes(b"42 12-31").unwrap(),
+        AcceptTokenResult::End
+    );
+    fs::remove_dir_all(&path).unwrap();
+}
+
+#[test]
+fn cycles_collisions_and_depth_are_errors() {
+    let path = schema_dir(
+        "errors",
+        &[
+            ("a.bnf", "@include \"b.bnf\"\n<start>::='a'"),
+            ("b.bnf", "@include \"a.bnf\"\n<b>::='b'"),
+            (
+                "c.bnf",
+                "@include \"d.bnf\"\n<start>::=<digit>\n<digit>::='1'",
+            ),
+            ("d.bnf", "<digit>::=[0-9]"),
+            ("e.bnf", "@include dates.bnf\n<start>::='e'"),
+            ("deep.bnf", "@include \"deep.bnf\"\n<start>::='x'"),
+        ],
+    );
+    let vocabulary = tiny_vocabulary();
+    let error = |file: &str| {
+        Grammar::from_file(
+            path.join(file),
+            vocabulary.clone(),
+            GrammarBuildOptions::new(),
+        )
+        .unwrap_err()
+    };
+    assert!(error("a.bnf")
+        .to_string()
+        .contains("includes itself through"));
+    assert!(error("deep.bnf")
+        .to_string()
+        .contains("includes itself through"));
+    let collision = error("c.bnf").to_string();
+    assert!(
+        collision.starts_with("<digit> is defined in both") && collision.contains("d.bnf"),
+        "{collision}"
+    );
+    assert_eq!(
+        format!("{:#}", error("e.bnf")),
+        format!(
+            "line 1 of {:?}: @include should be followed by a quoted path, like @include \"dates.bnf\".",
+            path.join("e.bnf")
+        )
+    );
+    let missing = error("missing.bnf").to_string();
+    assert!(
+        missing.starts_with("cannot open the BNF schema"),
+        "{missing}"
+    );
+    fs::remove_dir_all(&path).unwrap();
+}
+
+#[test]
+fn include_depth_is_limited() {
+    let files = (0..20)
+        .map(|i| {
+            (
+                format!("{i}.bnf"),
+                format!("@include \"{}.bnf\"\n<r{i}>::='x'", i + 1),
+            )
+        })
+        .chain([("20.bnf".to_string(), "<start>::='x'".to_string())])
+        .collect::<Vec<_>>();
+    let files = files
+        .iter()
+        .map(|(a, b)| (a.as_str(), b.as_str()))
+        .collect::<Vec<_>>();
+    let path = schema_dir("depth", &files);
+    let error = Grammar::from_file(
+        path.join("0.bnf"),
+        tiny_vocabulary(),
+        GrammarBuildOptions::new(),
+    )
+    .unwrap_err();
+    assert!(
+        error
+            .to_string()
+            .ends_with("is included more than 16 files deep."),
+        "{error}"
+    );
+    fs::remove_dir_all(&path).unwrap();
+}
+
+#[test]
+fn syntax_errors_point_at_the_line_of_their_file() {
+    let path = schema_dir(
+        "syntax",
+        &[
+            ("main.bnf", "@include \"rules.bnf\"\n<start>::=<rule>"),
+            ("rules.bnf", "# The rules\n\n<rule>::='a' 'b"),
+        ],
+    );
+    let error = Grammar::from_file(
+        path.join("main.bnf"),
+        tiny_vocabulary(),
+        GrammarBuildOptions::new(),
+    )
+    .unwrap_err();
+    assert_eq!(
+        error.to_string(),
+        format!("in the BNF schema {:?}", path.join("rules.bnf"))
+    );
+    let error = error.downcast_ref::<ParseError>().unwrap();
+    assert_eq!((error.line, error.column), (3, 14));
+    fs::remove_dir_all(&path).unwrap();
+}
+
+#[test]
+fn include_without_a_file_is_an_error() {
+    let error =
+        Grammar::new("@include \"a.bnf\"\n<start>::='a'", tiny_vocabulary(), 0).unwrap_err();
+    assert!(
+        error
+            .to_string()
+            .starts_with("@include is only resolved by"),
+        "{error}"
+    );
+}
@@ -0,0 +1,95 @@
+mod common;
+
+use bnf_sampler::differential::compare_samplers;
+use bnf_sampler::grammar::{Grammar, GrammarBuildOptions};
+use bnf_sampler::introspect::PublicTerm;
+use bnf_sampler::sampler::{Sampler, SamplerConfig};
+use common::tiny_vocabulary;
+use std::sync::Arc;
+
+fn build(grammar: &str, inline: bool) -> Arc<Grammar> {
+    Grammar::with_options(
+        grammar,
+        tiny_vocabulary(),
+        GrammarBuildOptions::new().inline_trivial_nonterminals(inline),
+    )
+    .unwrap()
+}
+
+/// Walk each token sequence with samplers of the grammar built with and without inlining.
+fn assert_same_behavior(grammar: &str, scripts: &[&[&str]]) {
+    let vocabulary = tiny_vocabulary();
+    for script in scripts {
+        let [mut a, mut b] = [false, true].map(|inline| {
+            Sampler::with_config(
+                build(grammar, inline),
+                "start".to_string(),
+                vocabulary.clone(),
+                SamplerConfig::new(),
+            )
+            .unwrap()
+        });
+        let script = script
+            .iter()
+            .map(|x| vocabulary.token_to_id[x.as_bytes()])
+            .collect::<Vec<_>>();
+        assert_eq!(compare_samplers(&mut a, &mut b, &script), [], "{script:?}");
+    }
+}
+
+#[test]
+fn chains_are_inlined() {
+    let grammar = "<start>::='{'<a>'}'|<a>\n<a>::=<b>\n<b>::=<c>\n<c>::='x'<c>|'y'";
+    assert_eq!(
+        build(grammar, false).nonterminals(),
+        ["a", "b", "c", "start"]
+    );
+    assert_eq!(build(grammar, true).nonterminals(), ["c", "start"]);
+    assert_same_behavior(
+        grammar,
+        &[
+            &["{", "x", "x", "y", "}"],
+            &["x", "y"],
+            &["{", "y", "x"],
+            &["}"],
+        ],
+    );
+}
+
+#[test]
+fn inlined_terminals_are_merged() {
+    let grammar = "<start>::=<key>':'<value><end>\n<key>::='key'\n<value>::='1'|'2'\n<end>::=<close>\n<close>::='}'";
+    let terminal = |x: &str| PublicTerm::Terminal(x.as_bytes().to_vec());
+    assert_eq!(
+        build(grammar, true).productions("start").unwrap(),
+        [[
+            terminal("key:"),
+            PublicTerm::Nonterminal("value".to_string()),
+            terminal("}")
+        ]]
+    );
+    assert_same_behavior(grammar, &[&["key", ":", "1", "}"], &["key", ":", "3"]]);
+}
+
+#[test]
+fn special_nonterminals_are_not_inlined() {
+    let grammar = "<start>::='\"'<text>'\"'|'('<except!([word])>')'\n<text>::=<except!('\"')>\n<word>::=<inner>\n<inner>::='ab'";
+    let inlined = build(grammar, true);
+    assert_eq!(inlined.nonterminals(), ["start", "text", "word"]);
+    assert_same_behavior(
+        grammar,
+        &[
+            &["\"", "hello", " world", "\""],
+            &["(", "a", "b", ")"],
+            &["(", "abc", ")"],
+        ],
+    );
+}
+
+#[test]
+fn unused_nonterminals_are_kept() {
+    // <start> has a single nonterminal alternative, but no rule uses it, so it is still a start nonterminal.
+    let grammar = "<start>::=<value>\n<value>::='yes'|'no'";
+    assert_eq!(build(grammar, true).nonterminals(), ["start", "value"]);
+    assert_same_behavior(grammar, &[&["yes"], &["no"]]);
+}
@@ -0,0 +1,93 @@
+mod common;
+
+use bnf_sampler::grammar::Grammar;
+use bnf_sampler::sampler::{Sampler, SamplerConfig};
+use common::{assert_same_masks, tiny_vocabulary};
+
+/// Rules repeating the same separator between nonterminals, like a grammar generated from a schema.
+fn repeated_separators(rules: usize, separator: &str) -> String {
+    let mut lines = vec![
+        format!(
+            "<start>::={}",
+            (0..rules)
+                .map(|i| format!("<r{i}>"))
+                .collect::<Vec<_>>()
+                .join("|")
+        ),
+        "<v>::='v'|'w'".to_string(),
+    ];
+    for i in 0..rules {
+        lines.push(format!(
+            "<r{i}>::='k{i}'<v>{separator}<v>|'k{i}'<v>{separator}<v>{separator}<v>"
+        ));
+    }
+    lines.join("\n")
+}
+
+#[test]
+fn shared_terminals_match_like_distinct_ones() {
+    let grammar = repeated_separators(12, "', '");
+    let expanded = format!("{}\n<sep>::=', '", repeated_separators(12, "<sep>"));
+    for tokens in [
+        &["k", "1", "v", ",", " ", "w"][..],
+        &["k", "1", "1", "w", ",", " ", "v", ",", " ", "v"],
+        &["k", "3", "v", ",", " ", "w", ",", " ", "v"],
+    ] {
+        assert_same_masks(&grammar, &expanded, tokens);
+    }
+    // Terminals that are prefixes of each other are interned separately.
+    let grammar = "<start>::='ab'<x>|'abc'<x>|'ab'\n<x>::='ab'|'abc'|'!'";
+    let expanded =
+        "<start>::=<ab><x>|<abc><x>|<ab>\n<x>::=<ab>|<abc>|'!'\n<ab>::='ab'\n<abc>::='abc'";
+    for tokens in [&["ab", "c", "!"][..], &["a", "b", "a", "b", "c"], &["ab"]] {
+        assert_same_masks(grammar, expanded, tokens);
+    }
+}
+
+#[test]
+fn repeated_terminals_are_stored_once() {
+    let vocabulary = tiny_vocabulary();
+    let build = |rules: usize, separator: &str| {
+        Grammar::new(
+            &repeated_separators(rules, separator),
+            vocabulary.clone(),
+            0,
+        )
+        .unwrap()
+    };
+    // A longer separator used 600 times only costs its extra bytes once, in the arena and in the index.
+    let long = "', and then, after that, '";
+    let large = build(200, long);
+    assert_eq!(
+        large.terminals_bytes() - build(200, "','").terminals_bytes(),
+        2 * (long.len() - 3)
+    );
+    let sampler = Sampler::with_config(
+        large.clone(),
+        "start".to_string(),
+        vocabulary,
+        SamplerConfig::new(),
+    )
+    .unwrap();
+    let stats = sampler.stats();
+    assert_eq!(stats.grammar_terminals_bytes, large.terminals_bytes());
+    assert_eq!(
+        stats.total(),
+        stats.arena_bytes + stats.stacks_bytes + stats.cache_bytes
+    );
+}
+
+#[test]
+fn tokens_of_token_sets_are_stored_once() {
+    let vocabulary = tiny_vocabulary();
+    let terminals_bytes = |grammar: &str| {
+        Grammar::new(grammar, vocabulary.clone(), 0)
+            .unwrap()
+            .terminals_bytes()
+    };
+    // Both token sets hold nearly every token of the vocabulary in their tries, and refer to the same terminals.
+    let one = terminals_bytes("<start>::=<except!('x')>");
+    let two = terminals_bytes("<start>::=<except!('x')>|'.'<except!('y')>");
+    assert!(one > 0);
+    assert_eq!(two, one);
+}
@@ -0,0 +1,54 @@
+mod common;
+
+use bnf_sampler::grammar::Grammar;
+use bnf_sampler::introspect::PublicTerm;
+use common::tiny_vocabulary;
+
+fn terminal(bytes: &str) -> PublicTerm {
+    PublicTerm::Terminal(bytes.as_bytes().to_vec())
+}
+
+fn nonterminal(name: &str) -> PublicTerm {
+    PublicTerm::Nonterminal(name.to_string())
+}
+
+#[test]
+fn productions_list_the_sorted_alternatives() {
+    let grammar = Grammar::new(
+        "<start>::='a'<b>|''|'c''d'\n<b>::='b'|'b'<b>",
+        tiny_vocabulary(),
+        0,
+    )
+    .unwrap();
+    assert_eq!(grammar.nonterminals(), vec!["b", "start"]);
+    assert_eq!(
+        grammar.productions("start").unwrap(),
+        vec![
+            vec![],
+            vec![terminal("a"), nonterminal("b")],
+            vec![terminal("cd")]
+        ]
+    );
+    assert_eq!(
+        grammar.productions("b").unwrap(),
+        vec![vec![terminal("b")], vec![terminal("b"), nonterminal("b")]]
+    );
+    assert_eq!(grammar.productions("c"), None);
+    assert!(grammar.terminals_of("start").is_none());
+}
+
+#[test]
+fn terminals_of_lists_the_set_of_a_special_nonterminal() {
+    let grammar = Grammar::new("<start>::='\"'<except!('\"')>'\"'", tiny_vocabulary(), 0).unwrap();
+    let productions = grammar.productions("start").unwrap();
+    let PublicTerm::Nonterminal(name) = &productions[0][1] else {
+        panic!("{productions:?}");
+    };
+    assert_eq!(grammar.productions(name), Some(vec![]));
+    let terminals = grammar.terminals_of(name).unwrap().collect::<Vec<_>>();
+    for token in ["abc", "hello", " the"] {
+        assert!(terminals.contains(&token.as_bytes()), "{token}");
+    }
+    assert!(terminals.iter().all(|x| !x.is_empty()));
+    assert!(grammar.terminals_of("missing").is_none());
+}
@@ -0,0 +1,70 @@
+use bnf_sampler::grammar::Grammar;
+use bnf_sampler::sampler::{PossibleTokensResult, Sampler, SamplerConfig};
+use bnf_sampler::utils;
+use bnf_sampler::vocabulary::{VocabularyBuilder, DEFAULT_MAX_TOKEN_BYTES};
+use std::sync::Arc;
+
+/// Byte fallback tokens and a token cut inside a character, without String representations except `a`.
+fn byte_fallback_vocabulary() -> Arc<bnf_sampler::vocabulary::Vocabulary> {
+    let mut builder = VocabularyBuilder::new(DEFAULT_MAX_TOKEN_BYTES);
+    builder.add_token(1, b"a", Some("a")).unwrap();
+    builder.add_token(2, b"\xFF", None).unwrap();
+    builder.add_token(3, b"\xE2\x80", None).unwrap();
+    builder.add_token(4, b"\x9C", None).unwrap();
+    builder.add_token(5, b"b", None).unwrap();
+    builder.add_token(6, "é".as_bytes(), None).unwrap();
+    Arc::new(builder.finish().unwrap())
+}
+
+#[test]
+fn invalid_tokens_are_displayed_with_escapes() {
+    let vocabulary = byte_fallback_vocabulary();
+    let displayed: Vec<_> = (1..=7).map(|x| vocabulary.token_display(x)).collect();
+    assert_eq!(
+        displayed,
+        [
+            "a",
+            "\\xFF",
+            "\\xE2\\x80",
+            "\\x9C",
+            "b",
+            "é",
+            "<unknown token 7>"
+        ]
+    );
+    assert_eq!(utils::escape_invalid_utf8(b"ok\xC3"), "ok\\xC3");
+}
+
+#[test]
+fn invalid_tokens_pass_through_grammar_and_sampling() {
+    let vocabulary = byte_fallback_vocabulary();
+    let grammar = Grammar::new(
+        "<start>::='a'<quote>|'a'<except!([quote])>'b'\n<quote>::='\\xE2\\x80\\x9C'",
+        vocabulary.clone(),
+        0,
+    )
+    .unwrap();
+    let mut sampler = Sampler::with_config(
+        grammar,
+        "start".to_string(),
+        vocabulary.clone(),
+        SamplerConfig::new(),
+    )
+    .unwrap();
+    sampler.all_possible_next_tokens(None).unwrap();
+    let mut masks = vec![];
+    for token_id in [1, 3, 4] {
+        match sampler.all_possible_next_tokens(Some(token_id)).unwrap() {
+            PossibleTokensResult::Continue(mask) => masks.push(
+                mask.iter()
+                    .map(|x| vocabulary.token_display(x as u32).into_owned())
+                    .collect::<Vec<_>>(),
+            ),
+            PossibleTokensResult::End => break,
+            result => panic!("{result:?}"),
+        }
+        assert!(!sampler.to_string().is_empty());
+    }
+    assert!(masks[0].contains(&"\\xE2\\x80".to_string()), "{masks:?}");
+    assert_eq!(masks[1], ["\\x9C"]);
+}
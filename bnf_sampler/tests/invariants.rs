@@ -0,0 +1,97 @@
+mod common;
+
+use bnf_sampler::fixtures;
+use bnf_sampler::sampler::{
+    Corruption, InvariantViolation, PossibleTokensResult, SamplerConfig, StackLocation,
+};
+use common::new_sampler;
+
+#[test]
+fn a_sampler_in_use_is_valid() {
+    let vocabulary = fixtures::vocabulary();
+    let mut sampler = new_sampler(
+        fixtures::JSON_OBJECT_GRAMMAR,
+        &vocabulary,
+        SamplerConfig::new(),
+    );
+    assert_eq!(sampler.validate_invariants(), Ok(()));
+    let mut input = None;
+    for token in ["{\"", "name", "\": ", "42", "}"] {
+        assert!(matches!(
+            sampler.all_possible_next_tokens(input).unwrap(),
+            PossibleTokensResult::Continue(_)
+        ));
+        assert_eq!(sampler.validate_invariants(), Ok(()));
+        input = Some(vocabulary.token_to_id[token.as_bytes()]);
+    }
+    sampler.all_possible_next_tokens(input).unwrap();
+    assert_eq!(sampler.validate_invariants(), Ok(()));
+}
+
+#[test]
+fn corruptions_are_detected() {
+    let vocabulary = fixtures::vocabulary();
+    let mut sampler = new_sampler(
+        fixtures::ARITHMETIC_GRAMMAR,
+        &vocabulary,
+        SamplerConfig::new(),
+    );
+    sampler.all_possible_next_tokens(None).unwrap();
+    let unknown = usize::MAX / 2;
+    for corruption in [
+        Corruption::UnknownNonterminal,
+        Corruption::UnknownTrieNode,
+        Corruption::UnknownTerminal,
+        Corruption::TerminalOffsetOutOfRange,
+        Corruption::CachedUnknownNonterminal,
+        Corruption::ArenaInUse,
+        Corruption::UnknownHistoryToken,
+    ] {
+        let mut corrupted = sampler.clone();
+        corrupted.corrupt(corruption);
+        let violations = corrupted.validate_invariants().unwrap_err();
+        assert_eq!(violations.len(), 1, "{corruption:?}");
+        use InvariantViolation as V;
+        use StackLocation::{Cache, Stacks};
+        assert!(
+            match (corruption, &violations[0]) {
+                (Corruption::UnknownNonterminal, V::UnknownNonterminal { location, id }) =>
+                    matches!(location, Stacks(_)) && *id == unknown,
+                (Corruption::UnknownTrieNode, V::UnknownTrieNode { location, id }) =>
+                    matches!(location, Stacks(_)) && *id == unknown,
+                (Corruption::UnknownTerminal, V::UnknownTerminal { location, id }) =>
+                    matches!(location, Stacks(_)) && *id == unknown,
+                (
+                    Corruption::TerminalOffsetOutOfRange,
+                    V::TerminalOffsetOutOfRange { start, .. },
+                ) => *start == unknown,
+                (Corruption::CachedUnknownNonterminal, V::UnknownNonterminal { location, .. }) =>
+                    *location == Cache,
+                (Corruption::ArenaInUse, V::ArenaInUse(items)) => *items == 1,
+                (Corruption::UnknownHistoryToken, V::UnknownHistoryToken(id)) => *id == u32::MAX,
+                _ => false,
+            },
+            "{corruption:?} gives {violations:?}"
+        );
+        // The original sampler is untouched.
+        assert_eq!(sampler.validate_invariants(), Ok(()));
+    }
+}
+
+#[test]
+#[cfg(debug_assertions)]
+#[should_panic(
+    expected = "The sampler is corrupted: a cached stack refers to the undefined nonterminal"
+)]
+fn debug_builds_check_after_each_accept() {
+    let vocabulary = fixtures::vocabulary();
+    let mut sampler = new_sampler(
+        fixtures::ARITHMETIC_GRAMMAR,
+        &vocabulary,
+        SamplerConfig::new(),
+    );
+    sampler.all_possible_next_tokens(None).unwrap();
+    // A corrupted current stack could panic while the token is matched, before the check.
+    sampler.corrupt(Corruption::CachedUnknownNonterminal);
+    let _ = sampler.accept_a_token(Some(vocabulary.token_to_id[b"1".as_slice()]));
+}
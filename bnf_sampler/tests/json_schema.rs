@@ -0,0 +1,122 @@
+mod common;
+
+use bnf_sampler::grammar::Grammar;
+use bnf_sampler::json_schema::to_bnf;
+use bnf_sampler::sampler::{AcceptTokenResult, Sampler};
+use common::tiny_vocabulary;
+use serde_json::{json, Value};
+
+/// Accept `document` one byte token at a time, and return the result of the last token,
+/// or `Failed` as soon as a token is rejected.
+fn run(schema: &Value, document: &str) -> AcceptTokenResult {
+    let vocabulary = tiny_vocabulary();
+    let grammar = Grammar::new(&to_bnf(schema).unwrap(), vocabulary.clone(), 0).unwrap();
+    let mut sampler =
+        Sampler::new(grammar, "start".to_string(), vocabulary.clone(), 0, false).unwrap();
+    let mut result = AcceptTokenResult::Continue;
+    for byte in document.bytes() {
+        assert_eq!(result, AcceptTokenResult::Continue, "{document}");
+        result = sampler
+            .accept_a_token(Some(vocabulary.token_to_id[&[byte][..]]))
+            .unwrap();
+        if result == AcceptTokenResult::Failed {
+            break;
+        }
+    }
+    result
+}
+
+#[test]
+fn object_with_required_and_optional_properties() {
+    let schema = json!({
+        "type": "object",
+        "properties": {
+            "name": {"type": "string"},
+            "age": {"type": "integer"},
+            "score": {"type": "number"},
+            "admin": {"type": "boolean"},
+            "note": {"type": "null"},
+            "role": {"enum": ["user", "owner"]}
+        },
+        "required": ["name", "role"]
+    });
+    for document in [
+        r#"{"name": "Ann \"A\"", "age": 41, "score": -1.5e3, "admin": true, "note": null, "role": "user"}"#,
+        r#"{"name":"Bo","role":"owner"}"#,
+    ] {
+        assert_eq!(run(&schema, document), AcceptTokenResult::End, "{document}");
+    }
+    for document in [r#"{"role":"user"}"#, r#"{"name":"Bo","role":"admin"}"#] {
+        assert_eq!(
+            run(&schema, document),
+            AcceptTokenResult::Failed,
+            "{document}"
+        );
+    }
+}
+
+#[test]
+fn arrays_with_min_and_max_items() {
+    let schema =
+        json!({"type": "array", "items": {"type": "integer"}, "minItems": 2, "maxItems": 3});
+    assert_eq!(run(&schema, "[1, 2]"), AcceptTokenResult::End);
+    assert_eq!(run(&schema, "[1,2,3]"), AcceptTokenResult::End);
+    assert_eq!(run(&schema, "[1]"), AcceptTokenResult::Failed);
+    assert_eq!(run(&schema, "[1,2,3,4]"), AcceptTokenResult::Failed);
+    let schema = json!({"type": "array", "items": {"type": "boolean"}, "maxItems": 1});
+    assert_eq!(run(&schema, "[]"), AcceptTokenResult::End);
+    assert_eq!(run(&schema, "[true]"), AcceptTokenResult::End);
+    assert_eq!(run(&schema, "[true,false]"), AcceptTokenResult::Failed);
+    let schema = json!({"type": "array", "items": {"type": "null"}, "minItems": 1});
+    assert_eq!(run(&schema, "[]"), AcceptTokenResult::Failed);
+    assert_eq!(run(&schema, "[null, null, null]"), AcceptTokenResult::End);
+    let error = to_bnf(&json!({"type": "array", "minItems": 3, "maxItems": 2})).unwrap_err();
+    assert_eq!(
+        error.to_string(),
+        "minItems should not be larger than maxItems."
+    );
+}
+
+#[test]
+fn refs_within_the_document() {
+    let schema = json!({
+        "$defs": {
+            "point": {
+                "type": "object",
+                "properties": {"x": {"type": "integer"}, "y": {"type": "integer"}},
+                "required": ["x", "y"]
+            },
+            "tree": {
+                "type": "object",
+                "properties": {
+                    "value": {"type": "integer"},
+                    "children": {"type": "array", "items": {"$ref": "#/$defs/tree"}}
+                },
+                "required": ["value"]
+            }
+        },
+        "type": "object",
+        "properties": {
+            "from": {"$ref": "#/$defs/point"},
+            "to": {"$ref": "#/$defs/point"},
+            "tree": {"$ref": "#/$defs/tree"}
+        },
+        "required": ["from", "to", "tree"]
+    });
+    let document = r#"{"from": {"x": 1, "y": 2}, "to": {"x": 3, "y": 4}, "tree": {"value": 1, "children": [{"value": 2, "children": []}, {"value": 3}]}}"#;
+    assert_eq!(run(&schema, document), AcceptTokenResult::End);
+    assert_eq!(
+        run(&schema, r#"{"from": {"x": 1}"#),
+        AcceptTokenResult::Failed
+    );
+    for (reference, message) in [
+        (
+            "other.json#/a",
+            "Only a $ref within the same document, like #/$defs/name, is supported, but other.json#/a is found.",
+        ),
+        ("#/$defs/missing", "$ref #/$defs/missing does not point into the schema."),
+    ] {
+        let error = to_bnf(&json!({"$ref": reference})).unwrap_err();
+        assert_eq!(error.to_string(), message);
+    }
+}
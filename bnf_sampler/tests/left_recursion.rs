@@ -0,0 +1,43 @@
+mod common;
+
+use bnf_sampler::grammar::Grammar;
+use common::{tiny_vocabulary, validates};
+
+fn error(grammar: &str) -> String {
+    Grammar::new(grammar, tiny_vocabulary(), 0)
+        .unwrap_err()
+        .to_string()
+}
+
+#[test]
+fn direct_left_recursion_is_rejected() {
+    assert_eq!(
+        error("<start>::=<a>\n<a>::=<a>'x'|'y'"),
+        "Left recursion is not supported, but <a> can start with itself through <a> -> <a>."
+    );
+}
+
+#[test]
+fn indirect_left_recursion_names_the_cycle() {
+    assert_eq!(
+        error("<start>::=<a>'!'\n<a>::=<b>'x'|'y'\n<b>::=<a>'z'"),
+        "Left recursion is not supported, but <a> can start with itself through <a> -> <b> -> <a>."
+    );
+}
+
+#[test]
+fn left_recursion_through_an_empty_prefix_is_rejected() {
+    assert_eq!(
+        error("<start>::=<b>\n<b>::=<opt><b>'x'|'y'\n<opt>::='o'|''"),
+        "Left recursion is not supported, but <b> can start with itself through <b> -> <b>."
+    );
+}
+
+#[test]
+fn right_recursion_is_accepted() {
+    let vocabulary = tiny_vocabulary();
+    let grammar = "<start>::=<a>\n<a>::=<opt>'x'<a>|'y'\n<opt>::='o'|''";
+    assert!(validates(grammar, &vocabulary, b"xoxy"));
+    let grammar = "<start>::=<items>\n<items>::=<item>|<item>','<items>\n<item>::='yes'|'no'";
+    assert!(validates(grammar, &vocabulary, b"yes,no"));
+}
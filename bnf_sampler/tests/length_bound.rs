@@ -0,0 +1,77 @@
+mod common;
+
+use bnf_sampler::differential::compare_samplers;
+use bnf_sampler::fixtures;
+use bnf_sampler::metrics::StepTiming;
+use bnf_sampler::sampler::{PossibleTokensResult, SamplerConfig};
+use common::{new_sampler, tiny_vocabulary};
+
+fn config(length_bound: bool) -> SamplerConfig {
+    SamplerConfig::new()
+        .collect_timing(true)
+        .signature_filter(false)
+        .length_bound(length_bound)
+}
+
+fn first_step(grammar: &str, length_bound: bool) -> (StepTiming, Vec<usize>) {
+    let vocabulary = tiny_vocabulary();
+    let mut sampler = new_sampler(grammar, &vocabulary, config(length_bound));
+    let mask = match sampler.all_possible_next_tokens(None).unwrap() {
+        PossibleTokensResult::Continue(token_ids) => token_ids.iter().collect(),
+        result => panic!("{result:?}"),
+    };
+    (*sampler.last_step_timing().unwrap(), mask)
+}
+
+#[test]
+fn tokens_longer_than_the_stack_are_skipped() {
+    // `age`, `and`, `array`, `answer` and `abc` are longer than the 2 bytes left.
+    let (bounded, bounded_mask) = first_step("<start>::='a'|'ab'", true);
+    let (unbounded, unbounded_mask) = first_step("<start>::='a'|'ab'", false);
+    assert_eq!(bounded_mask, unbounded_mask);
+    assert_eq!(bounded.tokens_too_long, 5);
+    assert_eq!(unbounded.tokens_too_long, 0);
+    assert_eq!(
+        bounded.tokens_checked + bounded.tokens_too_long,
+        unbounded.tokens_checked
+    );
+    // The bound includes the items below the top.
+    let (timing, _) = first_step("<start>::=<a>'nd'\n<a>::='a'|'b'", true);
+    assert_eq!(timing.tokens_too_long, 3);
+}
+
+#[test]
+fn recursive_stacks_skip_nothing() {
+    // The only stack is `'a'` followed by the recursive <tail>.
+    let (timing, _) = first_step("<start>::='a'<tail>\n<tail>::='b'<tail>|'c'", true);
+    assert_eq!(timing.tokens_too_long, 0);
+}
+
+#[test]
+fn masks_do_not_change() {
+    let vocabulary = fixtures::vocabulary();
+    let scripts: [&[&str]; 3] = [
+        &[
+            "{\"", "name", "\": ", "\"", "中文", "\"", ", \"", "age", "\":", "42", "}",
+        ],
+        &["(", "12", " +", " ", "3", ")", " *", " ", "-", "2", "="],
+        &[
+            "2", "0", "2", "4", "-", "0", "1", "-", "12", "T", "12", ":", "30",
+        ],
+    ];
+    let date = "<start>::=<d><d><d><d>'-'<d><d>'-'<d><d>'T'<d><d>':'<d><d>\n<d>::=[0-9]";
+    let grammars = fixtures::grammars().map(|(_, grammar)| grammar);
+    for (grammar, script) in grammars.into_iter().chain([date]).zip(scripts) {
+        let script: Vec<u32> = script
+            .iter()
+            .map(|x| vocabulary.token_to_id[x.as_bytes()])
+            .collect();
+        let mut bounded = new_sampler(grammar, &vocabulary, config(true));
+        let mut unbounded = new_sampler(grammar, &vocabulary, config(false));
+        assert_eq!(
+            compare_samplers(&mut bounded, &mut unbounded, &script),
+            vec![],
+            "{grammar}"
+        );
+    }
+}
@@ -0,0 +1,128 @@
+mod common;
+
+use bnf_sampler::grammar::{Grammar, GrammarBuildOptions, DEFAULT_MAX_TERMINAL_BYTES};
+use bnf_sampler::lint::LintKind;
+use bnf_sampler::utils;
+use bnf_sampler::vocabulary::{Vocabulary, DEFAULT_MAX_TOKEN_BYTES};
+use common::tiny_vocabulary;
+use rustc_hash::FxHashMap;
+
+#[test]
+fn oversized_vocab_line_is_an_error() {
+    let long_token = "a".repeat(DEFAULT_MAX_TOKEN_BYTES + 1);
+    let input = format!("1 'b' 1\n2 '{long_token}' {}\n", long_token.len());
+    let error = utils::read_rwkv_world_vocab_from_reader(input.as_bytes(), DEFAULT_MAX_TOKEN_BYTES)
+        .unwrap_err()
+        .to_string();
+    assert!(error.contains("line 2"), "{error}");
+    assert!(error.contains("token id 2"), "{error}");
+    assert!(error.contains("1025 bytes"), "{error}");
+    let vocabulary =
+        utils::read_rwkv_world_vocab_from_reader(input.as_bytes(), DEFAULT_MAX_TOKEN_BYTES + 1)
+            .unwrap();
+    assert_eq!(vocabulary.max_token_bytes, DEFAULT_MAX_TOKEN_BYTES + 1);
+}
+
+#[test]
+fn oversized_token_is_an_error() {
+    let id_to_token = FxHashMap::from_iter([(1, b"ab".to_vec()), (7, b"abcd".to_vec())]);
+    let id_to_token_string = FxHashMap::from_iter([(1, "ab".to_string()), (7, "abcd".to_string())]);
+    let error = Vocabulary::new(id_to_token.clone(), id_to_token_string.clone(), 3)
+        .unwrap_err()
+        .to_string();
+    assert!(error.contains("Token id 7 is 4 bytes long"), "{error}");
+    let vocabulary = Vocabulary::new(id_to_token, id_to_token_string, 4).unwrap();
+    assert_eq!(vocabulary.token_to_id["abcd".as_bytes()], 7);
+}
+
+#[test]
+fn oversized_terminal_is_an_error() {
+    let vocabulary = tiny_vocabulary();
+    let grammar = format!("<start>::='{}'", "a".repeat(DEFAULT_MAX_TERMINAL_BYTES + 1));
+    let error = Grammar::new(&grammar, vocabulary.clone(), 0)
+        .unwrap_err()
+        .to_string();
+    assert!(error.contains("<start>"), "{error}");
+    // Consecutive terminals are merged before the check.
+    let error = Grammar::with_max_terminal_bytes(
+        "<start>::='ab''cd'<x>\n<x>::='e'|'f'",
+        vocabulary.clone(),
+        0,
+        3,
+    )
+    .unwrap_err()
+    .to_string();
+    assert!(error.contains("4 bytes"), "{error}");
+    let grammar =
+        Grammar::with_max_terminal_bytes("<start>::='abcd'<x>\n<x>::='e'|'f'", vocabulary, 0, 4)
+            .unwrap();
+    assert_eq!(grammar.max_terminal_bytes(), 4);
+}
+
+#[test]
+fn too_many_except_literals_is_an_error() {
+    // <all> produces every token of the tiny vocabulary, which has about 390 distinct tokens.
+    let grammar = "<start>::='a'<except!([all])>|'b'\n<all>::=<any!>";
+    let options = GrammarBuildOptions::new().max_except_terminals(100);
+    let error = Grammar::with_options(grammar, tiny_vocabulary(), options)
+        .unwrap_err()
+        .to_string();
+    assert!(error.contains("except!([all]) is invalid"), "{error}");
+    assert!(error.contains("more than the maximum of 100"), "{error}");
+    // A sample of the literals is listed.
+    assert!(error.contains("e.g. \""), "{error}");
+    let options = GrammarBuildOptions::new().max_except_terminals(1000);
+    Grammar::with_options(grammar, tiny_vocabulary(), options).unwrap();
+}
+
+#[test]
+fn large_except_literal_sets_are_linted() {
+    let grammar =
+        "<start>::='a'<except!([all])>|'b'<except!([digit])>\n<all>::=<any!>\n<digit>::='1'|'2'";
+    let large_excepts = |max_except_terminals| {
+        let options = GrammarBuildOptions::new().max_except_terminals(max_except_terminals);
+        Grammar::with_options(grammar, tiny_vocabulary(), options)
+            .unwrap()
+            .lint()
+            .into_iter()
+            .filter(|x| x.kind == LintKind::LargeExceptSet)
+            .collect::<Vec<_>>()
+    };
+    let findings = large_excepts(600);
+    assert_eq!(findings.len(), 1, "{findings:?}");
+    assert_eq!(findings[0].nonterminal, "except!([all])");
+    assert!(
+        findings[0].message.contains("maximum of 600"),
+        "{}",
+        findings[0]
+    );
+    assert_eq!(large_excepts(1000), vec![]);
+}
+
+/// An except!([nonterminal]) whose long alternative needs a temporary stack arena of about 400 items.
+fn deep_except_grammar() -> String {
+    format!(
+        "<start>::=<except!([seq])>'.'\n<seq>::={}|<abc><seq>\n<abc>::='a'|'b'|'c'|'ab'|'abc'",
+        "<abc>".repeat(200)
+    )
+}
+
+#[test]
+fn small_except_arena_is_retried_with_doubled_capacity() {
+    // 2 is doubled to 512 within the retries.
+    Grammar::new(&deep_except_grammar(), tiny_vocabulary(), 2).unwrap();
+}
+
+#[test]
+fn except_arena_exceeding_the_retries_is_an_error() {
+    // 1 is doubled to 256 at most.
+    let error = Grammar::new(&deep_except_grammar(), tiny_vocabulary(), 1)
+        .unwrap_err()
+        .to_string();
+    assert!(error.contains("except!([seq]) is invalid"), "{error}");
+    assert!(
+        error.contains("temporary stack arena larger than 256"),
+        "{error}"
+    );
+    assert!(error.contains("doubled 8 times"), "{error}");
+}
@@ -0,0 +1,76 @@
+mod common;
+
+use bnf_sampler::grammar::Grammar;
+use bnf_sampler::lint::LintKind;
+use common::tiny_vocabulary;
+
+fn lint(grammar: &str) -> Vec<(LintKind, String)> {
+    Grammar::new(grammar, tiny_vocabulary(), 0)
+        .unwrap()
+        .lint()
+        .into_iter()
+        .map(|x| (x.kind, x.nonterminal))
+        .collect()
+}
+
+#[test]
+fn clean_grammar_has_no_findings() {
+    assert_eq!(lint("<start>::='a'<x>|'b'<x>\n<x>::='c'|'d'"), vec![]);
+}
+
+#[test]
+fn duplicate_alternatives_are_found() {
+    assert_eq!(
+        lint("<start>::='ab'<y>|'a''b'<y>|'c'<y>\n<y>::='c'|'d'"),
+        vec![(LintKind::DuplicateAlternative, "start".to_string())]
+    );
+}
+
+#[test]
+fn shared_prefixes_are_found() {
+    assert_eq!(
+        lint("<start>::='ab'<y>|'abc'<y>|'b'<y>\n<y>::='c'|'d'"),
+        vec![(LintKind::SharedPrefix, "start".to_string())]
+    );
+}
+
+#[test]
+fn single_alternatives_are_found() {
+    assert_eq!(
+        lint("<start>::='a'<x>|'b'<x>\n<x>::=<y>'c'\n<y>::='c'|'d'"),
+        vec![(LintKind::SingleAlternative, "x".to_string())]
+    );
+}
+
+#[test]
+fn empty_token_sets_are_found() {
+    assert_eq!(
+        lint("<start>::='a'<except!([all])>|'b'\n<all>::=<any!>"),
+        vec![
+            (LintKind::EmptyTokenSet, "except!([all])".to_string()),
+            (LintKind::LargeExceptSet, "except!([all])".to_string())
+        ]
+    );
+}
+
+#[test]
+fn unreachable_nonterminals_are_listed() {
+    let grammar = Grammar::new(
+        "<start>::='a'<used>|<except!([quote])>\n<used>::='b'|'c'<used>\n<quote>::='\"'\n<dead>::='d'<deader>\n<deader>::='e'",
+        tiny_vocabulary(),
+        0,
+    )
+    .unwrap();
+    assert_eq!(
+        grammar.unreachable_nonterminals("start"),
+        vec!["dead", "deader"]
+    );
+    assert_eq!(
+        grammar.unreachable_nonterminals("dead"),
+        vec!["quote", "start", "used"]
+    );
+    assert_eq!(
+        grammar.unreachable_nonterminals("missing").len(),
+        grammar.nonterminals().len()
+    );
+}
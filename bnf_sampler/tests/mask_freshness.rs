@@ -0,0 +1,104 @@
+mod common;
+
+use bnf_sampler::mask::TokenMask;
+use bnf_sampler::sampler::{
+    AcceptTokenResult, OwnedPossibleTokensResult, SamplerConfig, StaleMaskError,
+};
+use common::{new_sampler, tiny_vocabulary};
+use std::sync::Arc;
+
+const GRAMMAR: &str = "<start>::='a'<next>|'b'<next>\n<next>::='a'|'b'|'c'";
+
+fn mask(result: OwnedPossibleTokensResult) -> Arc<TokenMask> {
+    match result {
+        OwnedPossibleTokensResult::Continue(mask) => mask,
+        result => panic!("{result:?}"),
+    }
+}
+
+#[test]
+fn a_mask_of_a_previous_step_is_stale() {
+    let vocabulary = tiny_vocabulary();
+    let id = |token: &str| vocabulary.token_to_id[token.as_bytes()];
+    let mut sampler = new_sampler(GRAMMAR, &vocabulary, SamplerConfig::new());
+    let first = mask(sampler.next_mask(None).unwrap());
+    assert_eq!(first.step(), Some(0));
+    assert_eq!(first.stamp().unwrap().state_hash, sampler.state_hash());
+    sampler.assert_mask_fresh(&first).unwrap();
+    // Computing the mask again without a token does not move the sampler.
+    let again = mask(sampler.next_mask(None).unwrap());
+    assert_eq!(again.stamp(), first.stamp());
+
+    // The race: another path accepts a token before the old mask is applied.
+    assert_eq!(
+        sampler.accept_a_token(Some(id("b"))).unwrap(),
+        AcceptTokenResult::Continue
+    );
+    let error = sampler.assert_mask_fresh(&first).unwrap_err();
+    assert_eq!(
+        error.downcast_ref::<StaleMaskError>(),
+        Some(&StaleMaskError {
+            mask: first.stamp(),
+            current_step: 1
+        })
+    );
+    assert_eq!(
+        error.to_string(),
+        "The mask is computed for step 0, but the sampler is at step 1."
+    );
+    let second = mask(sampler.next_mask(None).unwrap());
+    assert_eq!(second.step(), Some(1));
+    sampler.assert_mask_fresh(&second).unwrap();
+
+    sampler.reset();
+    assert!(sampler.assert_mask_fresh(&second).is_err());
+    let third = mask(sampler.next_mask(None).unwrap());
+    assert_eq!(third.step(), Some(2));
+    // The masks of the same state at different steps are still equal as sets.
+    assert_eq!(first, third);
+}
+
+#[test]
+fn strict_accept_rejects_a_stale_mask() {
+    let vocabulary = tiny_vocabulary();
+    let id = |token: &str| vocabulary.token_to_id[token.as_bytes()];
+    let mut sampler = new_sampler(GRAMMAR, &vocabulary, SamplerConfig::new());
+    let first = mask(sampler.next_mask(None).unwrap());
+    assert_eq!(
+        sampler
+            .accept_a_token_strict(Some(id("a")), &first)
+            .unwrap(),
+        AcceptTokenResult::Continue
+    );
+    let error = sampler
+        .accept_a_token_strict(Some(id("c")), &first)
+        .unwrap_err();
+    assert!(error.downcast_ref::<StaleMaskError>().is_some());
+    // Nothing is accepted with the stale mask.
+    assert_eq!(sampler.token_history(), Some([id("a")].as_slice()));
+
+    let second = mask(sampler.next_mask(None).unwrap());
+    assert_eq!(
+        sampler
+            .accept_a_token_strict(Some(id("c")), &second)
+            .unwrap(),
+        AcceptTokenResult::End
+    );
+    // Masks not returned by next_mask have no stamp.
+    let error = sampler.assert_mask_fresh(&TokenMask::new()).unwrap_err();
+    assert_eq!(error.downcast_ref::<StaleMaskError>().unwrap().mask, None);
+}
+
+#[test]
+fn a_rejected_token_or_a_trace_does_not_move_the_sampler() {
+    let vocabulary = tiny_vocabulary();
+    let id = |token: &str| vocabulary.token_to_id[token.as_bytes()];
+    let mut sampler = new_sampler(GRAMMAR, &vocabulary, SamplerConfig::new());
+    let first = mask(sampler.next_mask(None).unwrap());
+    assert_eq!(
+        sampler.accept_a_token(Some(id("c"))).unwrap(),
+        AcceptTokenResult::Failed
+    );
+    sampler.trace_bytes(b"ab").unwrap();
+    sampler.assert_mask_fresh(&first).unwrap();
+}
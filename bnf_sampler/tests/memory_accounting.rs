@@ -0,0 +1,87 @@
+mod common;
+
+use bnf_sampler::memory::CountingAccountant;
+use bnf_sampler::sampler::{CacheMode, Sampler, SamplerConfig};
+use common::{generate, new_sampler, tiny_vocabulary, Model};
+use std::sync::Arc;
+
+const GRAMMAR: &str =
+    "<start>::='{'<items>'}'\n<items>::=<item>|<item>','<items>\n<item>::='id'|'xyz'";
+
+fn accounted_sampler(accountant: &Arc<CountingAccountant>, config: SamplerConfig) -> Sampler {
+    new_sampler(
+        GRAMMAR,
+        &tiny_vocabulary(),
+        config.memory_accountant(accountant.clone()),
+    )
+}
+
+#[test]
+fn the_arena_is_accounted_until_the_sampler_is_dropped() {
+    let accountant = Arc::new(CountingAccountant::new());
+    let sampler = accounted_sampler(&accountant, SamplerConfig::new().stack_arena_capacity(4096));
+    let stats = sampler.stats();
+    assert!(stats.arena_bytes >= 4096);
+    assert_eq!(accountant.current(), stats.total());
+    let clone = sampler.clone();
+    assert_eq!(accountant.current(), 2 * stats.total());
+    drop(sampler);
+    assert_eq!(accountant.current(), clone.stats().total());
+    drop(clone);
+    assert_eq!(accountant.current(), 0);
+    assert_eq!(accountant.peak(), 2 * stats.total());
+}
+
+#[test]
+fn the_cache_rises_on_insert_and_falls_on_eviction() {
+    let accountant = Arc::new(CountingAccountant::new());
+    let vocabulary = tiny_vocabulary();
+    let mut sampler = accounted_sampler(&accountant, SamplerConfig::new());
+    assert_eq!(sampler.stats().cache_bytes, 0);
+    let before = accountant.current();
+    generate(
+        &mut sampler,
+        &vocabulary,
+        &Model::Prefer(&["{", "id", ",", "xyz", "}"]),
+        8,
+    )
+    .unwrap();
+    let stats = sampler.stats();
+    assert!(stats.cache_bytes > sampler.cached_masks_bytes());
+    assert!(accountant.current() > before);
+    assert_eq!(accountant.current(), stats.total());
+    sampler.clear_cache();
+    assert_eq!(sampler.stats().cache_bytes, 0);
+    assert_eq!(accountant.current(), stats.total() - stats.cache_bytes);
+}
+
+#[test]
+fn the_stacks_are_accounted_as_they_change() {
+    let accountant = Arc::new(CountingAccountant::new());
+    let vocabulary = tiny_vocabulary();
+    let mut sampler = accounted_sampler(
+        &accountant,
+        SamplerConfig::new().cache_mode(CacheMode::None),
+    );
+    let initial = sampler.stats().stacks_bytes;
+    assert!(initial > 0);
+    for token in ["{", "id", ","] {
+        let id = vocabulary.token_to_id[token.as_bytes()];
+        sampler.accept_a_token(Some(id)).unwrap();
+        assert_eq!(sampler.stats().cache_bytes, 0);
+        assert_eq!(accountant.current(), sampler.stats().total());
+    }
+    assert_ne!(sampler.stats().stacks_bytes, initial);
+    sampler.reset();
+    assert_eq!(sampler.stats().stacks_bytes, initial);
+    assert_eq!(accountant.current(), sampler.stats().total());
+}
+
+#[test]
+fn samplers_without_an_accountant_still_count_their_bytes() {
+    let vocabulary = tiny_vocabulary();
+    let mut sampler = new_sampler(GRAMMAR, &vocabulary, SamplerConfig::new());
+    generate(&mut sampler, &vocabulary, &Model::LowestId, 8).unwrap();
+    let stats = sampler.stats();
+    assert!(stats.arena_bytes > 0 && stats.stacks_bytes > 0 && stats.cache_bytes > 0);
+}
@@ -0,0 +1,80 @@
+mod common;
+
+use bnf_sampler::grammar::Grammar;
+use bnf_sampler::metrics::StepMetrics;
+use bnf_sampler::sampler::{CacheMode, PossibleTokensResult, Sampler, SamplerConfig};
+use bnf_sampler::vocabulary::Vocabulary;
+use common::tiny_vocabulary;
+
+const GRAMMAR: &str = "<start>::=<digit>'qz'<digit>\n<digit>::='1'|'2'";
+
+fn generate(sampler: &mut Sampler, vocabulary: &Vocabulary) {
+    let mut input_token_id = None;
+    for token in [None, Some("1"), Some("q"), Some("z"), Some("2")] {
+        if let Some(token) = token {
+            input_token_id = Some(vocabulary.token_to_id[token.as_bytes()]);
+        }
+        match sampler.all_possible_next_tokens(input_token_id).unwrap() {
+            PossibleTokensResult::Continue(_) | PossibleTokensResult::End => {}
+            PossibleTokensResult::InputTokenRejected => panic!("{token:?} is rejected"),
+        }
+    }
+}
+
+fn step(mask_size: usize, end_eligible: bool) -> StepMetrics {
+    StepMetrics {
+        mask_size,
+        forced: mask_size == 1,
+        end_eligible,
+    }
+}
+
+#[test]
+fn forced_steps_are_detected() {
+    let vocabulary = tiny_vocabulary();
+    let grammar = Grammar::new(GRAMMAR, vocabulary.clone(), 0).unwrap();
+    for cache_mode in [CacheMode::Full, CacheMode::None] {
+        let mut sampler = Sampler::with_config(
+            grammar.clone(),
+            "start".to_string(),
+            vocabulary.clone(),
+            SamplerConfig::new().cache_mode(cache_mode).metrics(true),
+        )
+        .unwrap();
+        // The second generation hits the possible tokens cache in the full cache mode.
+        for _ in 0..2 {
+            generate(&mut sampler, &vocabulary);
+            let metrics = sampler.metrics();
+            assert_eq!(
+                metrics.steps,
+                vec![
+                    step(2, false),
+                    step(1, false),
+                    step(1, false),
+                    step(2, false),
+                    step(0, true)
+                ]
+            );
+            assert_eq!(metrics.forced_steps(), 2);
+            assert_eq!(metrics.end_eligible_steps(), 1);
+            assert_eq!(metrics.vocabulary_size, vocabulary.id_to_token.len());
+            sampler.reset();
+            assert!(sampler.metrics().steps.is_empty());
+        }
+    }
+}
+
+#[test]
+fn metrics_are_disabled_by_default() {
+    let vocabulary = tiny_vocabulary();
+    let grammar = Grammar::new(GRAMMAR, vocabulary.clone(), 0).unwrap();
+    let mut sampler = Sampler::with_config(
+        grammar,
+        "start".to_string(),
+        vocabulary.clone(),
+        SamplerConfig::new(),
+    )
+    .unwrap();
+    generate(&mut sampler, &vocabulary);
+    assert!(sampler.metrics().steps.is_empty());
+}
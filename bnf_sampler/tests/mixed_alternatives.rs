@@ -0,0 +1,51 @@
+mod common;
+
+use bnf_sampler::grammar::Grammar;
+use common::{assert_same_masks, tiny_vocabulary, validates};
+
+#[test]
+fn terminal_and_nonterminal_alternatives_can_be_mixed() {
+    // A nonterminal whose alternatives are single terminals and single nonterminals used to panic.
+    let grammar = "<start>::=<a>'!'\n<a>::='x'|<b>|'yz'|<c>\n<b>::='b'\n<c>::='c'|'cc'";
+    let expanded = "<start>::=<a>'!'\n<a>::='x'|'b'|'yz'|'c'|'cc'";
+    for tokens in [
+        &["x", "!"][..],
+        &["b", "!"],
+        &["y", "z", "!"],
+        &["c", "c", "!"],
+    ] {
+        assert_same_masks(grammar, expanded, tokens);
+    }
+    let vocabulary = tiny_vocabulary();
+    assert!(validates("<start>::='x'|<b>\n<b>::='y'", &vocabulary, b"y"));
+    assert!(!validates(
+        "<start>::='x'|<b>\n<b>::='y'",
+        &vocabulary,
+        b"xy"
+    ));
+}
+
+#[test]
+fn special_nonterminals_cannot_be_defined() {
+    for (grammar, special) in [
+        ("<start>::=<any!>\n<any!>::='x'", "any!"),
+        (
+            "<start>::=<except!('a')>\n<except!('a')>::='x'",
+            "except!('a')",
+        ),
+        (
+            "<start>::=<except!([b])>\n<b>::='b'\n<except!([b])>::='x'",
+            "except!([b])",
+        ),
+    ] {
+        let error = Grammar::new(grammar, tiny_vocabulary(), 0)
+            .unwrap_err()
+            .to_string();
+        assert_eq!(
+            error,
+            format!(
+                "<{special}> is a special nonterminal and cannot be defined in the BNF schema."
+            )
+        );
+    }
+}
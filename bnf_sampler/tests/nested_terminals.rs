@@ -0,0 +1,50 @@
+mod common;
+
+use bnf_sampler::sampler::{AcceptTokenResult, SamplerConfig};
+use common::{assert_same_masks, new_sampler, tiny_vocabulary};
+
+const NESTED: &str = "<start>::=<w>'.'\n<w>::='a'|'ab'|'abc'";
+
+/// Accept `tokens` and return the number of stacks after each.
+fn stack_counts(grammar: &str, tokens: &[&str]) -> Vec<usize> {
+    let vocabulary = tiny_vocabulary();
+    let mut sampler = new_sampler(grammar, &vocabulary, SamplerConfig::default());
+    tokens
+        .iter()
+        .map(|token| {
+            let result = sampler
+                .accept_a_token(Some(vocabulary.token_to_id[token.as_bytes()]))
+                .unwrap();
+            assert_ne!(result, AcceptTokenResult::Failed, "{token}");
+            sampler.last_step_stack_delta().after
+        })
+        .collect()
+}
+
+#[test]
+fn nested_terminals_match_the_expansion_without_nesting() {
+    let expanded = "<start>::=<w>'.'\n<w>::='a'|'a'<b>\n<b>::='b'|'bc'";
+    for tokens in [
+        &["a", "b", "c", "."][..],
+        &["ab", "."],
+        &["abc", "."],
+        &["a", "."],
+    ] {
+        assert_same_masks(NESTED, expanded, tokens);
+    }
+}
+
+#[test]
+fn a_terminal_that_prefixes_others_keeps_both_stacks() {
+    // Inside `ab` or `abc`, or after `a`, then inside `abc` or after `ab`, then only after `abc`.
+    assert_eq!(stack_counts(NESTED, &["a", "b", "c"]), [2, 2, 1]);
+}
+
+#[test]
+fn token_sets_do_not_stop_inside_a_token() {
+    // `ab` is one token of the first <any!>, or the tokens `a` and `b` of both, but never `a` and half of `ab`.
+    assert_eq!(
+        stack_counts("<start>::=<any!><any!>'.'", &["ab", "abc", "."]),
+        [2, 1, 1]
+    );
+}
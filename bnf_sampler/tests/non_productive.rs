@@ -0,0 +1,59 @@
+mod common;
+
+use bnf_sampler::grammar::Grammar;
+use bnf_sampler::lint::LintKind;
+use bnf_sampler::sampler::{Sampler, SamplerConfig};
+use common::tiny_vocabulary;
+
+fn sampler_error(grammar: &str) -> String {
+    let vocabulary = tiny_vocabulary();
+    let grammar = Grammar::new(grammar, vocabulary.clone(), 0).unwrap();
+    Sampler::with_config(
+        grammar,
+        "start".to_string(),
+        vocabulary,
+        SamplerConfig::new(),
+    )
+    .unwrap_err()
+    .to_string()
+}
+
+#[test]
+fn recursion_without_a_base_case_is_rejected() {
+    assert_eq!(
+        sampler_error("<start>::='a'<loop>\n<loop>::='x'<loop>"),
+        "Start_nonterminal start can never match a complete sentence, because every alternative of <loop>, <start> uses one of them. Add an alternative that ends, like the base case of a recursion."
+    );
+}
+
+#[test]
+fn mutual_recursion_without_a_base_case_is_rejected() {
+    assert!(
+        sampler_error("<start>::=<a>\n<a>::='x'<b>\n<b>::='y'<a>").contains("<a>, <b>, <start>")
+    );
+}
+
+#[test]
+fn non_productive_alternatives_of_a_productive_start_are_linted() {
+    let vocabulary = tiny_vocabulary();
+    let grammar = Grammar::new(
+        "<start>::='a'|'b'<loop>\n<loop>::='x'<loop>",
+        vocabulary.clone(),
+        0,
+    )
+    .unwrap();
+    let findings = grammar
+        .lint()
+        .into_iter()
+        .filter(|x| x.kind == LintKind::NonProductive)
+        .map(|x| x.nonterminal)
+        .collect::<Vec<_>>();
+    assert_eq!(findings, vec!["loop"]);
+    assert!(Sampler::with_config(
+        grammar,
+        "start".to_string(),
+        vocabulary,
+        SamplerConfig::new()
+    )
+    .is_ok());
+}
@@ -0,0 +1,135 @@
+mod common;
+
+use bnf_sampler::grammar::Grammar;
+use bnf_sampler::presets;
+use bnf_sampler::sampler::{AcceptTokenResult, Sampler, SamplerConfig};
+use common::{generate, tiny_vocabulary, Model};
+use serde_json::json;
+
+/// Whether the grammar matches exactly `bytes`, checking each string on a reset sampler.
+fn validator(grammar: &str) -> impl FnMut(&str) -> bool {
+    let vocabulary = tiny_vocabulary();
+    let grammar = Grammar::new(grammar, vocabulary.clone(), 0).unwrap();
+    let mut sampler = Sampler::with_config(
+        grammar,
+        "start".to_string(),
+        vocabulary,
+        SamplerConfig::new(),
+    )
+    .unwrap();
+    move |bytes| {
+        sampler.reset();
+        sampler.accept_bytes(bytes.as_bytes()).unwrap() == AcceptTokenResult::End
+    }
+}
+
+#[test]
+fn every_value_in_a_small_range() {
+    let mut matches = validator("<start>::=<number!(7, 255)>");
+    for value in 0..=299 {
+        assert_eq!(
+            matches(&value.to_string()),
+            (7..=255).contains(&value),
+            "{value}"
+        );
+    }
+    for rejected in ["007", "07", "0255", "00", "-7", "2550", "", "+8"] {
+        assert!(!matches(rejected), "{rejected}");
+    }
+}
+
+#[test]
+fn ranges_starting_at_zero_and_spanning_lengths() {
+    let mut matches = validator("<start>::=<number!(0, 1000)>");
+    for value in 0..=1100 {
+        assert_eq!(matches(&value.to_string()), value <= 1000, "{value}");
+    }
+    for rejected in ["00", "01", "-0", "0999"] {
+        assert!(!matches(rejected), "{rejected}");
+    }
+}
+
+#[test]
+fn negative_bounds() {
+    let mut matches = validator("<start>::=<number!(-15, 12)>");
+    for value in -30..=30 {
+        assert_eq!(
+            matches(&value.to_string()),
+            (-15..=12).contains(&value),
+            "{value}"
+        );
+    }
+    assert!(!matches("-0"));
+    assert!(!matches("-05"));
+    let mut matches = validator("<start>::=<number!(-120, -8)>");
+    for value in -200..=10 {
+        assert_eq!(
+            matches(&value.to_string()),
+            (-120..=-8).contains(&value),
+            "{value}"
+        );
+    }
+}
+
+#[test]
+fn single_value_and_extreme_bounds() {
+    let mut matches = validator("<start>::='x='<number!(42, 42)>");
+    assert!(matches("x=42"));
+    assert!(!matches("x=41"));
+    assert!(!matches("x=43"));
+    let mut matches = validator(&format!("<start>::=<number!({}, {})>", i64::MIN, i64::MAX));
+    for value in [i64::MIN, -1, 0, 1, i64::MAX] {
+        assert!(matches(&value.to_string()), "{value}");
+    }
+    assert!(!matches("9223372036854775808"));
+    assert!(!matches("-9223372036854775809"));
+}
+
+#[test]
+fn invalid_bounds_are_rejected() {
+    let vocabulary = tiny_vocabulary();
+    for (args, reason) in [
+        ("5, 1", "the minimum 5 is greater than the maximum 1."),
+        ("5", "(5) is not two comma separated integers."),
+    ] {
+        let error = Grammar::new(
+            &format!("<start>::=<number!({args})>"),
+            vocabulary.clone(),
+            0,
+        )
+        .unwrap_err()
+        .to_string();
+        assert_eq!(
+            error,
+            format!("<number!({args})> is invalid because {reason}")
+        );
+    }
+}
+
+#[test]
+fn json_schema_integer_bounds() {
+    let vocabulary = tiny_vocabulary();
+    let schema = json!({
+        "type": "object",
+        "properties": {"age": {"type": "integer", "minimum": 0, "exclusiveMaximum": 150}},
+        "required": ["age"]
+    });
+    let mut sampler =
+        presets::constrained_json(&schema, vocabulary.clone(), SamplerConfig::new()).unwrap();
+    let generation = generate(
+        &mut sampler,
+        &vocabulary,
+        &Model::Prefer(&["{\"", "20", "0", "}"]),
+        64,
+    )
+    .unwrap();
+    assert!(generation.ended, "{}", generation.output());
+    assert_eq!(generation.output(), r#"{"age":20}"#);
+    for (age, accepted) in [("149", true), ("150", false), ("-1", false), ("07", false)] {
+        sampler.reset();
+        let result = sampler
+            .accept_bytes(format!(r#"{{"age":{age}}}"#).as_bytes())
+            .unwrap();
+        assert_eq!(result == AcceptTokenResult::End, accepted, "{age}");
+    }
+}
@@ -0,0 +1,43 @@
+mod common;
+
+use bnf_sampler::grammar::{Grammar, ParseError};
+use common::tiny_vocabulary;
+
+fn parse_error(input: &str) -> ParseError {
+    let error = Grammar::new(input, tiny_vocabulary(), 0).unwrap_err();
+    error
+        .downcast_ref::<ParseError>()
+        .unwrap_or_else(|| panic!("{error}"))
+        .clone()
+}
+
+#[test]
+fn missing_definition_symbol() {
+    let error = parse_error("<start>::='a'<b>\n<b>:='c'");
+    assert_eq!((error.line, error.column), (2, 4));
+    assert_eq!(error.snippet, "<b>:='c'");
+    assert_eq!(
+        error.to_string(),
+        "The BNF schema cannot be parsed at line 2, column 4: ::= is expected after the nonterminal. The line is `<b>:='c'`."
+    );
+}
+
+#[test]
+fn unclosed_terminal() {
+    let error = parse_error("<start>::=<b>\n<b>::='c'|'d\n");
+    assert_eq!((error.line, error.column), (2, 11));
+    assert!(error.message.contains("not closed with '"), "{error}");
+}
+
+#[test]
+fn unexpected_character() {
+    let error = parse_error("<start>::='a'\n  | 'b' %\n");
+    assert_eq!((error.line, error.column), (2, 9));
+}
+
+#[test]
+fn unclosed_nonterminal() {
+    let error = parse_error("<start ::= 'a'");
+    assert_eq!((error.line, error.column), (1, 1));
+    assert_eq!(error.message, "the nonterminal is not closed with >.");
+}
@@ -0,0 +1,100 @@
+mod common;
+
+use bnf_sampler::sampler::{
+    OwnedPossibleTokensResult, PossibleTokensResult, RegionKind, Sampler, SamplerConfig,
+    SamplerContext, VisitOutcome,
+};
+use bnf_sampler::vocabulary::Vocabulary;
+use common::{new_sampler, tiny_vocabulary};
+use std::sync::{Arc, Mutex};
+
+const GRAMMAR: &str = "<start>::=<item>'}'|<item><start>\n<item>::='id'|'xyz'";
+
+/// A sampler whose hook forbids `id` in even steps.
+fn sampler_forbidding_id_in_even_steps(vocabulary: &Arc<Vocabulary>) -> Sampler {
+    let id = vocabulary.token_to_id[&b"id"[..]] as usize;
+    new_sampler(
+        GRAMMAR,
+        vocabulary,
+        SamplerConfig::new().post_mask_hook(Box::new(move |context, mask| {
+            if context.step % 2 == 0 {
+                mask.remove(id);
+            }
+        })),
+    )
+}
+
+fn allows(sampler: &mut Sampler, token_id: Option<u32>, allowed: u32) -> bool {
+    match sampler.all_possible_next_tokens(token_id).unwrap() {
+        PossibleTokensResult::Continue(mask) => mask.contains(allowed as usize),
+        result => panic!("{result:?}"),
+    }
+}
+
+#[test]
+fn the_masks_alternate_with_the_steps() {
+    let vocabulary = tiny_vocabulary();
+    let [id, xyz] = ["id", "xyz"].map(|x| vocabulary.token_to_id[x.as_bytes()]);
+    let mut sampler = sampler_forbidding_id_in_even_steps(&vocabulary);
+    assert!(!allows(&mut sampler, None, id));
+    assert!(allows(&mut sampler, None, xyz));
+    // The same state is revisited through the cache, and the hook still applies.
+    for (token, id_allowed) in [(xyz, true), (id, false), (xyz, true), (xyz, false)] {
+        assert_eq!(allows(&mut sampler, Some(token), id), id_allowed);
+        assert!(allows(&mut sampler, None, xyz));
+    }
+    let mut unhooked = new_sampler(GRAMMAR, &vocabulary, SamplerConfig::new());
+    assert!(allows(&mut unhooked, None, id));
+}
+
+#[test]
+fn the_hook_applies_to_owned_and_visited_masks() {
+    let vocabulary = tiny_vocabulary();
+    let id = vocabulary.token_to_id[&b"id"[..]];
+    let mut sampler = sampler_forbidding_id_in_even_steps(&vocabulary);
+    let OwnedPossibleTokensResult::Continue(mask) = sampler.next_mask(None).unwrap() else {
+        panic!()
+    };
+    assert!(!mask.contains(id as usize));
+    let mut visited = vec![];
+    let outcome = sampler
+        .visit_allowed_tokens(None, &mut |x| visited.push(x))
+        .unwrap();
+    assert_eq!(outcome, VisitOutcome::Continue { cached: false });
+    assert!(!visited.is_empty() && !visited.contains(&id));
+}
+
+#[test]
+fn the_context_describes_the_sampler() {
+    let vocabulary = tiny_vocabulary();
+    let contexts = Arc::new(Mutex::new(vec![]));
+    let recorded = contexts.clone();
+    let mut sampler = new_sampler(
+        "<start>::='id'<any!>",
+        &vocabulary,
+        SamplerConfig::new().post_mask_hook(Box::new(move |context: &SamplerContext, _| {
+            recorded.lock().unwrap().push((
+                context.accepted_bytes.to_vec(),
+                context.step,
+                context.region_kind.clone(),
+            ));
+        })),
+    );
+    sampler.all_possible_next_tokens(None).unwrap();
+    sampler
+        .all_possible_next_tokens(Some(vocabulary.token_to_id[&b"id"[..]]))
+        .unwrap();
+    assert_eq!(
+        *contexts.lock().unwrap(),
+        [
+            (vec![], 0, RegionKind::Structured),
+            (
+                b"id".to_vec(),
+                1,
+                RegionKind::TokenSet {
+                    nonterminal: "any!".to_string()
+                }
+            ),
+        ]
+    );
+}
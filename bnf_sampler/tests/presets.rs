@@ -0,0 +1,93 @@
+mod common;
+
+use bnf_sampler::presets;
+use bnf_sampler::sampler::SamplerConfig;
+use common::{generate, tiny_vocabulary, Model};
+use serde_json::{json, Value};
+
+fn generate_document(schema: &Value, preferred: &[&str]) -> Value {
+    let vocabulary = tiny_vocabulary();
+    let mut sampler =
+        presets::constrained_json(schema, vocabulary.clone(), SamplerConfig::new()).unwrap();
+    let generation = generate(&mut sampler, &vocabulary, &Model::Prefer(preferred), 64).unwrap();
+    assert!(generation.ended, "{}", generation.output());
+    serde_json::from_slice(&generation.output)
+        .unwrap_or_else(|e| panic!("{} is not JSON: {e}", generation.output()))
+}
+
+#[test]
+fn object_with_required_and_optional_properties() {
+    let schema = json!({
+        "type": "object",
+        "properties": {
+            "name": {"type": "string"},
+            "age": {"type": "integer"},
+            "city": {"type": "string"}
+        },
+        "required": ["name", "age"]
+    });
+    let document = generate_document(
+        &schema,
+        &["{\"", "name", "\":", "\"", "Bob", "\",", "42", "}"],
+    );
+    assert_eq!(document, json!({"name": "Bob", "age": 42}));
+    assert!(document["name"].is_string());
+    assert!(document["age"].is_i64());
+    assert!(document.get("city").is_none_or(|x| x.is_string()));
+}
+
+#[test]
+fn enums_booleans_and_arrays() {
+    let schema = json!({
+        "type": "object",
+        "properties": {
+            "color": {"enum": ["red", "green", "blue"]},
+            "ok": {"type": "boolean"},
+            "items": {"type": "array", "items": {"type": "number"}}
+        },
+        "required": ["color", "ok", "items"]
+    });
+    let document = generate_document(
+        &schema,
+        &[
+            "{\"", "\"", "green", "\",", "true", ",", "[", "12", ".", "5", ",", "30", "]", "}",
+        ],
+    );
+    assert!(["red", "green", "blue"].contains(&document["color"].as_str().unwrap()));
+    assert!(document["ok"].is_boolean());
+    let items = document["items"].as_array().unwrap();
+    assert!(items.iter().all(|x| x.is_number()));
+    assert_eq!(
+        document,
+        json!({"color": "green", "ok": true, "items": [12.5, 30]})
+    );
+}
+
+#[test]
+fn nested_objects_and_nullable_values() {
+    let schema = json!({
+        "type": "object",
+        "properties": {
+            "user": {
+                "type": "object",
+                "properties": {"id": {"type": "integer"}, "name": {"type": ["string", "null"]}},
+                "required": ["id", "name"]
+            },
+            "result": {"anyOf": [{"type": "null"}, {"const": "ok"}]}
+        },
+        "required": ["user", "result"]
+    });
+    let document = generate_document(
+        &schema,
+        &["{\"", "{\"", "1", "null", "}", "\"", "ok", "\"", "}"],
+    );
+    assert!(document["user"]["id"].is_i64());
+    assert!(document["user"]["name"].is_null() || document["user"]["name"].is_string());
+    assert!(document["result"].is_null() || document["result"] == "ok");
+}
+
+#[test]
+fn quotes_and_backslashes_in_constants_are_escaped() {
+    let schema = json!({"const": "a'b\"c\\"});
+    assert_eq!(generate_document(&schema, &[]), json!("a'b\"c\\"));
+}
@@ -0,0 +1,120 @@
+use bnf_sampler::grammar::{Grammar, GrammarBuildOptions};
+use bnf_sampler::lint::LintKind;
+use bnf_sampler::sampler::{AcceptTokenResult, PossibleTokensResult, Sampler, SamplerConfig};
+use bnf_sampler::vocabulary::{Vocabulary, DEFAULT_MAX_TOKEN_BYTES};
+use rustc_hash::FxHashMap;
+use std::sync::Arc;
+
+/// No token contains `z`, and `q` only appears after `x`, so `'aq'` and `'zz'` cannot be produced.
+fn gap_vocabulary() -> Arc<Vocabulary> {
+    let tokens = ["a", "b", "ab", "xq", "."];
+    let id_to_token: FxHashMap<u32, Vec<u8>> = tokens
+        .iter()
+        .enumerate()
+        .map(|(i, x)| (i as u32, x.as_bytes().to_vec()))
+        .collect();
+    let id_to_token_string = tokens
+        .iter()
+        .enumerate()
+        .map(|(i, x)| (i as u32, x.to_string()))
+        .collect();
+    Arc::new(Vocabulary::new(id_to_token, id_to_token_string, DEFAULT_MAX_TOKEN_BYTES).unwrap())
+}
+
+const GAP: &str = "<start>::=<word>'.'
+<word>::='a'|'b'|'ab'|'aq'|'xq'|'zz'|<dead>'b'
+<dead>::='aq'|'zz'";
+
+fn build(prune: bool) -> Arc<Grammar> {
+    Grammar::with_options(
+        GAP,
+        gap_vocabulary(),
+        GrammarBuildOptions::new().prune_unreachable(prune),
+    )
+    .unwrap()
+}
+
+#[test]
+fn unreachable_alternatives_are_pruned_and_reported() {
+    let grammar = build(true);
+    let pruned: Vec<(String, String)> = grammar
+        .lint()
+        .into_iter()
+        .filter(|x| x.kind == LintKind::UnreachableAlternative)
+        .map(|x| (x.nonterminal, x.message))
+        .collect();
+    assert_eq!(
+        pruned,
+        vec![
+            (
+                "dead".to_string(),
+                "'aq' was pruned because no token contains 'aq', and no token ending with 'a' can be followed by a token starting with 'q'.".to_string()
+            ),
+            (
+                "dead".to_string(),
+                "'zz' was pruned because no token contains 'z'.".to_string()
+            ),
+            (
+                "word".to_string(),
+                "'aq' was pruned because no token contains 'aq', and no token ending with 'a' can be followed by a token starting with 'q'.".to_string()
+            ),
+            (
+                "word".to_string(),
+                "'zz' was pruned because no token contains 'z'.".to_string()
+            ),
+            (
+                "word".to_string(),
+                "<dead>'b' was pruned because <dead> has no reachable alternative.".to_string()
+            ),
+        ]
+    );
+    // `aq`, `z` and `zz` of <word>, which keeps `a`, and `a`, `aq`, `z` and `zz` of <dead>.
+    assert_eq!(grammar.pruned_trie_nodes(), 7);
+}
+
+#[test]
+fn pruning_is_off_by_default() {
+    let grammar = build(false);
+    assert!(grammar
+        .lint()
+        .iter()
+        .all(|x| x.kind != LintKind::UnreachableAlternative));
+    assert_eq!(grammar.pruned_trie_nodes(), 0);
+}
+
+#[test]
+fn pruning_keeps_the_possible_tokens() {
+    let vocabulary = gap_vocabulary();
+    // The ids of `a`, `b`, `ab`, `xq` and `.`.
+    for input in [&[2, 4][..], &[0, 1, 4], &[3, 4]] {
+        let mut samplers = [build(false), build(true)].map(|grammar| {
+            Sampler::with_config(
+                grammar,
+                "start".to_string(),
+                vocabulary.clone(),
+                SamplerConfig::default(),
+            )
+            .unwrap()
+        });
+        for (i, token) in input.iter().enumerate() {
+            let masks = samplers.each_mut().map(|sampler| {
+                match sampler.all_possible_next_tokens(None).unwrap() {
+                    PossibleTokensResult::Continue(mask) => mask.clone(),
+                    x => panic!("{x:?}"),
+                }
+            });
+            assert_eq!(masks[0], masks[1], "{input:?}");
+            let expected = match i + 1 == input.len() {
+                true => AcceptTokenResult::End,
+                false => AcceptTokenResult::Continue,
+            };
+            for sampler in samplers.iter_mut() {
+                assert_eq!(
+                    sampler.accept_a_token(Some(*token)).unwrap(),
+                    expected,
+                    "{input:?}"
+                );
+            }
+        }
+    }
+}
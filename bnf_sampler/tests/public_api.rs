@@ -0,0 +1,169 @@
+//! Compare the public items of the crate with `tests/public_api.txt`, so changes to the public surface are intentional.
+//!
+//! Run `BLESS=1 cargo test --test public_api` to accept a change.
+use std::collections::BTreeSet;
+use std::fs;
+use std::path::Path;
+
+const ITEM_KEYWORDS: [&str; 9] = [
+    "fn ", "struct ", "enum ", "trait ", "const ", "static ", "type ", "use ", "mod ",
+];
+
+/// The public modules declared in `lib.rs`.
+fn public_modules(src: &Path) -> Vec<String> {
+    fs::read_to_string(src.join("lib.rs"))
+        .unwrap()
+        .lines()
+        .filter_map(|x| x.strip_prefix("pub mod "))
+        .map(|x| x.trim_end_matches(';').to_string())
+        .collect()
+}
+
+/// The name of the type declared or implemented by `line`, like `Sampler` for `impl<'a> Trait for Sampler<'a> {`.
+fn type_name(line: &str) -> String {
+    let line = line.split(" for ").last().unwrap();
+    let line = line
+        .trim_start_matches("pub ")
+        .trim_start_matches("struct ")
+        .trim_start_matches("enum ")
+        .trim_start_matches("trait ")
+        .trim_start_matches("impl");
+    let line = match line.strip_prefix('<') {
+        Some(x) => &x[x.find('>').unwrap() + 1..],
+        None => line,
+    };
+    line.trim()
+        .chars()
+        .take_while(|x| x.is_alphanumeric() || *x == '_')
+        .collect()
+}
+
+/// The signature of the item or field starting at `lines[0]`, without its body and on one line.
+fn signature(lines: &[&str], is_field: bool) -> String {
+    let mut signature = String::new();
+    // The braces of a `use` are part of it.
+    let is_use = lines[0].trim_start().starts_with("pub use ");
+    for line in lines {
+        signature.push(' ');
+        signature.push_str(line.trim());
+        if let Some(i) = signature.find('{').filter(|_| !is_use) {
+            signature.truncate(i);
+            break;
+        }
+        if signature.ends_with(';') || (is_field && signature.ends_with(',')) {
+            signature.pop();
+            break;
+        }
+    }
+    signature
+        .split_whitespace()
+        .collect::<Vec<_>>()
+        .join(" ")
+        .replace("( ", "(")
+        .replace("{ ", "{")
+        .replace(", )", ")")
+        .replace(",)", ")")
+        .replace(", }", "}")
+        .replace(",}", "}")
+}
+
+fn public_api() -> String {
+    let src = Path::new(env!("CARGO_MANIFEST_DIR")).join("src");
+    let modules = public_modules(&src);
+    let sources: Vec<(String, String)> = modules
+        .iter()
+        .map(|x| {
+            (
+                x.clone(),
+                fs::read_to_string(src.join(format!("{x}.rs"))).unwrap(),
+            )
+        })
+        .collect();
+    let public_types: BTreeSet<String> = sources
+        .iter()
+        .flat_map(|(_, source)| source.lines())
+        .filter(|x| {
+            ["pub struct ", "pub enum ", "pub trait "]
+                .iter()
+                .any(|keyword| x.starts_with(keyword))
+        })
+        .map(type_name)
+        .collect();
+    let mut items = vec![];
+    for module in modules.iter() {
+        items.push(format!("pub mod {module}"));
+    }
+    for (module, source) in sources.iter() {
+        let lines: Vec<&str> = source.lines().collect();
+        // The public type whose impl or fields are being read.
+        let mut parent: Option<String> = None;
+        for (i, line) in lines.iter().enumerate() {
+            if *line == "}" {
+                parent = None;
+                continue;
+            }
+            if line.ends_with('{') && (line.starts_with("impl") || line.starts_with("pub struct "))
+            {
+                parent = Some(type_name(line)).filter(|x| public_types.contains(x));
+                if line.starts_with("impl") {
+                    continue;
+                }
+            }
+            let indent = line.len() - line.trim_start().len();
+            let Some(rest) = line.trim_start().strip_prefix("pub ") else {
+                continue;
+            };
+            let is_item = ITEM_KEYWORDS.iter().any(|x| rest.starts_with(x));
+            let is_field = parent.is_some() && indent == 4 && !is_item;
+            let public = match indent {
+                0 => is_item,
+                4 => parent.is_some(),
+                _ => false,
+            };
+            let hidden = lines[..i]
+                .iter()
+                .rev()
+                .take_while(|x| {
+                    x.trim_start().starts_with("#[") || x.trim_start().starts_with("///")
+                })
+                .any(|x| x.trim() == "#[doc(hidden)]");
+            if !public || hidden || !(is_item || is_field) {
+                continue;
+            }
+            let prefix = match (indent, &parent) {
+                (4, Some(parent)) => format!("{parent}::"),
+                _ => String::new(),
+            };
+            items.push(format!(
+                "{module}: {prefix}{}",
+                signature(&lines[i..], is_field).trim()
+            ));
+        }
+    }
+    items.join("\n") + "\n"
+}
+
+#[test]
+fn public_api_matches_the_snapshot() {
+    let path = Path::new(env!("CARGO_MANIFEST_DIR")).join("tests/public_api.txt");
+    let actual = public_api();
+    if std::env::var_os("BLESS").is_some() {
+        fs::write(&path, actual).unwrap();
+        return;
+    }
+    let expected = fs::read_to_string(&path).unwrap_or_default();
+    let added: Vec<&str> = actual
+        .lines()
+        .filter(|x| !expected.lines().any(|y| y == *x))
+        .collect();
+    let removed: Vec<&str> = expected
+        .lines()
+        .filter(|x| !actual.lines().any(|y| y == *x))
+        .collect();
+    assert!(
+        added.is_empty() && removed.is_empty(),
+        "The public API changed. Run `BLESS=1 cargo test --test public_api` if it is intentional.\nadded:\n{}\nremoved:\n{}",
+        added.join("\n"),
+        removed.join("\n")
+    );
+}
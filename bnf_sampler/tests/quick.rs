@@ -0,0 +1,50 @@
+mod common;
+
+use bnf_sampler::quick;
+use common::tiny_vocabulary;
+
+const GRAMMAR: &str = "<start>::='id'<rest>|'xyz'\n<rest>::='!'|'?'";
+
+#[test]
+fn allowed_first_tokens_are_the_prefixes_of_the_first_terminals() {
+    let vocabulary = tiny_vocabulary();
+    let tokens = quick::allowed_first_tokens(GRAMMAR, "start", &vocabulary).unwrap();
+    let mut expected = ["i", "id", "x", "xy", "xyz"]
+        .iter()
+        .filter_map(|x| vocabulary.token_to_id.get(x.as_bytes()))
+        .map(|x| *x as usize)
+        .collect::<Vec<_>>();
+    expected.sort_unstable();
+    assert_eq!(tokens.iter().collect::<Vec<_>>(), expected);
+    assert!(quick::allowed_first_tokens(GRAMMAR, "undefined", &vocabulary).is_err());
+}
+
+#[test]
+fn accepts_only_complete_sentences() {
+    let vocabulary = tiny_vocabulary();
+    let ids = |tokens: &[&str]| {
+        tokens
+            .iter()
+            .map(|x| vocabulary.token_to_id[x.as_bytes()])
+            .collect::<Vec<_>>()
+    };
+    for tokens in [&["id", "!"][..], &["i", "d", "?"], &["xyz"]] {
+        assert!(
+            quick::accepts(GRAMMAR, "start", &vocabulary, &ids(tokens)).unwrap(),
+            "{tokens:?}"
+        );
+    }
+    // An incomplete sentence, a rejected token, and tokens after the end.
+    for tokens in [&["id"][..], &["id", "x"], &["xyz", "!"]] {
+        assert!(
+            !quick::accepts(GRAMMAR, "start", &vocabulary, &ids(tokens)).unwrap(),
+            "{tokens:?}"
+        );
+    }
+}
+
+#[test]
+fn no_tokens_are_not_a_sentence() {
+    let vocabulary = tiny_vocabulary();
+    assert!(!quick::accepts(GRAMMAR, "start", &vocabulary, &[]).unwrap());
+}
@@ -0,0 +1,61 @@
+mod common;
+
+use bnf_sampler::grammar::{DiagnosticKind, Grammar, GrammarBuildOptions, Severity};
+use common::{tiny_vocabulary, validates};
+
+#[test]
+fn raw_terminals_keep_their_backslashes() {
+    let vocabulary = tiny_vocabulary();
+    let grammar = r#"<start>::=r"C:\temp\file""#;
+    assert!(validates(grammar, &vocabulary, br"C:\temp\file"));
+    assert!(!validates(grammar, &vocabulary, b"C:\temp\\file"));
+    // The same terminal without the prefix turns \t into a tab.
+    assert!(validates(r#"<start>::="C:\temp""#, &vocabulary, b"C:\temp"));
+    assert!(validates(r"<start>::=r'\d+\n'", &vocabulary, br"\d+\n"));
+}
+
+#[test]
+fn raw_terminals_with_hashes_contain_quotes() {
+    let vocabulary = tiny_vocabulary();
+    let grammar = r###"<start>::=r#"say "hi" and 'bye'"#<end>
+<end>::=r##"\"#"##"###;
+    assert!(validates(
+        grammar,
+        &vocabulary,
+        br##"say "hi" and 'bye'\"#"##
+    ));
+    assert!(validates(r#"<start>::=r"a'b"|'x'"#, &vocabulary, b"a'b"));
+}
+
+#[test]
+fn raw_terminals_do_not_span_nonterminals_or_other_terminals() {
+    let vocabulary = tiny_vocabulary();
+    let grammar = "<r>::='r'\n<start>::=<r>'r\"'<r>";
+    assert!(validates(grammar, &vocabulary, b"rr\"r"));
+    assert!(Grammar::new("<start>::=r#\"abc\"", vocabulary, 0).is_err());
+}
+
+#[test]
+fn unknown_escapes_are_warned_about() {
+    let grammar = r#"<start>::='\q'<path>|'\t\n\r\x41\u0042\\'
+<path>::="C:\Users"|r"C:\Users"|[\]a]"#;
+    let diagnostics = Grammar::check(
+        grammar,
+        tiny_vocabulary(),
+        GrammarBuildOptions::new(),
+        "start",
+    );
+    let warnings = diagnostics
+        .iter()
+        .filter(|x| x.kind == DiagnosticKind::UnknownEscape)
+        .map(|x| {
+            assert_eq!(x.severity, Severity::Warning);
+            (
+                x.nonterminal.as_deref().unwrap(),
+                x.production.as_deref().unwrap(),
+            )
+        })
+        .collect::<Vec<_>>();
+    assert_eq!(warnings, [("path", r#""C:\Users""#), ("start", r"'\q'")]);
+    assert!(diagnostics[0].message.contains("raw terminal"));
+}
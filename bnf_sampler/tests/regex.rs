@@ -0,0 +1,83 @@
+mod common;
+
+use bnf_sampler::grammar::Grammar;
+use bnf_sampler::sampler::SamplerConfig;
+use common::{assert_same_masks, generate, new_sampler, tiny_vocabulary, Model};
+use regex::Regex;
+
+fn generate_field(grammar: &str, preferred: &[&str]) -> String {
+    let vocabulary = tiny_vocabulary();
+    let mut sampler = new_sampler(grammar, &vocabulary, SamplerConfig::new());
+    let generation = generate(&mut sampler, &vocabulary, &Model::Prefer(preferred), 64).unwrap();
+    assert!(generation.ended, "{}", generation.output());
+    generation.output()
+}
+
+#[test]
+fn date_field() {
+    let output = generate_field(
+        r#"<start>::='{"date":"'<regex!('[0-9]{4}-[0-9]{2}-[0-9]{2}')>'"}'"#,
+        &["20", "12", "-", "1", "2", "-", "2", "5", "\"}"],
+    );
+    assert_eq!(output, r#"{"date":"2012-12-25"}"#);
+    let output = generate_field(
+        r#"<start>::='{"date":"'<regex!('[0-9]{4}-[0-9]{2}-[0-9]{2}')>'"}'"#,
+        &[],
+    );
+    assert!(
+        Regex::new(r#"^\{"date":"[0-9]{4}-[0-9]{2}-[0-9]{2}"\}$"#)
+            .unwrap()
+            .is_match(&output),
+        "{output}"
+    );
+}
+
+#[test]
+fn uuid_field() {
+    let grammar = r#"<start>::='id='<regex!('[0-9a-f]{8}-[0-9a-f]{4}-[0-9a-f]{4}-[0-9a-f]{4}-[0-9a-f]{12}')>"#;
+    let uuid =
+        Regex::new("^id=[0-9a-f]{8}-[0-9a-f]{4}-[0-9a-f]{4}-[0-9a-f]{4}-[0-9a-f]{12}$").unwrap();
+    let output = generate_field(grammar, &["abc", "12", "ab", "c", "d", "e", "f"]);
+    assert!(uuid.is_match(&output), "{output}");
+    assert!(output.starts_with("id=abc12abc-"), "{output}");
+    let output = generate_field(grammar, &[]);
+    assert!(uuid.is_match(&output), "{output}");
+}
+
+#[test]
+fn alternatives_and_repetition() {
+    let output = generate_field("<start>::=<regex!('(yes|no)+!')>", &["no", "yes", "!"]);
+    assert_eq!(output, "noyes!");
+}
+
+#[test]
+fn tokens_split_and_span_matches_like_the_manual_expansion() {
+    let grammar = "<start>::=<regex!('(yes|no)+')>'!'";
+    let expanded = "<start>::=<words>'!'\n<words>::=<word>|<word><words>\n<word>::='yes'|'no'";
+    assert_same_masks(grammar, expanded, &["y", "es", "n", "o", "yes", "!"]);
+    assert_same_masks(grammar, expanded, &["no", "y", "e", "s", "!"]);
+}
+
+#[test]
+fn unsupported_patterns_are_rejected() {
+    let vocabulary = tiny_vocabulary();
+    for (pattern, reason) in [
+        ("(a)\\1", "backreferences are not supported"),
+        ("a(?=b)", "look-around"),
+        ("a*", "matches the empty string"),
+        ("[a", "unclosed character class"),
+    ] {
+        let error = Grammar::new(
+            &format!("<start>::='x'<regex!('{pattern}')>"),
+            vocabulary.clone(),
+            0,
+        )
+        .unwrap_err()
+        .to_string();
+        assert!(
+            error.starts_with(&format!("<regex!('{pattern}')> is invalid because")),
+            "{error}"
+        );
+        assert!(error.contains(reason), "{error}");
+    }
+}
@@ -0,0 +1,60 @@
+mod common;
+
+use bnf_sampler::sampler::{PossibleTokensResult, RegionKind, SamplerConfig};
+use common::{new_sampler, tiny_vocabulary};
+
+const GRAMMAR: &str = "<start>::='Q: '<question>'?'' A: '<answer>'.'
+<question>::=<except!('?')>|<except!('?')><question>
+<answer>::='yes'|'no'";
+
+#[test]
+fn region_kind_follows_the_template() {
+    let vocabulary = tiny_vocabulary();
+    let mut sampler = new_sampler(GRAMMAR, &vocabulary, SamplerConfig::new());
+    assert_eq!(sampler.region_kind(), RegionKind::Structured);
+    let mut regions = vec![];
+    let mut input_token_id = None;
+    for token in [
+        "Q", ":", " ", "what", " ", "is", "?", " ", "A", ":", " ", "no", ".",
+    ] {
+        let result = sampler.all_possible_next_tokens(input_token_id).unwrap();
+        assert!(matches!(result, PossibleTokensResult::Continue(_)));
+        regions.push(sampler.region_kind());
+        input_token_id = Some(vocabulary.token_to_id[token.as_bytes()]);
+    }
+    assert_eq!(
+        sampler.all_possible_next_tokens(input_token_id).unwrap(),
+        PossibleTokensResult::End
+    );
+    regions.push(sampler.region_kind());
+    let question = RegionKind::TokenSet {
+        nonterminal: "except!('?')".to_string(),
+    };
+    let mut expected = vec![RegionKind::Structured; 3];
+    // Only free text can follow `Q: `, then `?` may end the question after any token.
+    expected.push(question);
+    expected.extend(vec![RegionKind::Mixed; 3]);
+    expected.extend(vec![RegionKind::Structured; 7]);
+    assert_eq!(regions, expected);
+}
+
+#[test]
+fn region_kind_names_the_except_nonterminal() {
+    let vocabulary = tiny_vocabulary();
+    let mut sampler = new_sampler(
+        "<start>::='<'<text>'!'\n<text>::=<except!('!')>|<except!('!')><text>",
+        &vocabulary,
+        SamplerConfig::new(),
+    );
+    sampler.all_possible_next_tokens(None).unwrap();
+    assert_eq!(sampler.region_kind(), RegionKind::Structured);
+    sampler
+        .all_possible_next_tokens(Some(vocabulary.token_to_id["<".as_bytes()]))
+        .unwrap();
+    assert_eq!(
+        sampler.region_kind(),
+        RegionKind::TokenSet {
+            nonterminal: "except!('!')".to_string()
+        }
+    );
+}
@@ -0,0 +1,84 @@
+mod common;
+
+use bnf_sampler::fixtures;
+use bnf_sampler::sampler::{AcceptTokenResult, PossibleTokensResult, Sampler, SamplerConfig};
+use common::{new_sampler, tiny_vocabulary};
+
+fn mask(sampler: &mut Sampler, token_id: Option<u32>) -> Option<Vec<usize>> {
+    match sampler.all_possible_next_tokens(token_id).unwrap() {
+        PossibleTokensResult::Continue(token_ids) => Some(token_ids.iter().collect()),
+        _ => None,
+    }
+}
+
+/// Walk the grammar, and at each step check that rejected tokens change neither the stacks nor the possible tokens.
+fn assert_rejections_leave_state_untouched(grammar: &str, config: SamplerConfig) {
+    let vocabulary = fixtures::vocabulary();
+    let mut sampler = new_sampler(grammar, &vocabulary, config);
+    let mut token_id = None;
+    for step in 0..30 {
+        let Some(allowed) = mask(&mut sampler, token_id) else {
+            break;
+        };
+        let stacks = sampler.to_string();
+        let state_hash = sampler.state_hash();
+        let rejected = vocabulary
+            .id_to_token
+            .keys()
+            .filter(|id| {
+                !allowed.contains(&(**id as usize))
+                    && sampler.clone().accept_a_token(Some(**id)).unwrap()
+                        == AcceptTokenResult::Failed
+            })
+            .take(5)
+            .copied()
+            .collect::<Vec<_>>();
+        for id in rejected {
+            assert_eq!(
+                sampler.accept_a_token(Some(id)).unwrap(),
+                AcceptTokenResult::Failed
+            );
+            assert_eq!(sampler.to_string(), stacks, "step {step}, token {id}");
+            assert_eq!(sampler.state_hash(), state_hash);
+            assert_eq!(mask(&mut sampler, Some(id)), None);
+            assert_eq!(sampler.to_string(), stacks, "step {step}, token {id}");
+            assert_eq!(mask(&mut sampler, None).as_ref(), Some(&allowed));
+        }
+        token_id = Some(allowed[step * 7 % allowed.len()] as u32);
+    }
+}
+
+#[test]
+fn rejected_tokens_leave_the_fixture_grammars_untouched() {
+    for (_, grammar) in fixtures::grammars() {
+        assert_rejections_leave_state_untouched(grammar, SamplerConfig::new());
+        assert_rejections_leave_state_untouched(
+            grammar,
+            SamplerConfig::new().stack_to_bytes_cache(false),
+        );
+    }
+}
+
+#[test]
+fn rejected_partial_match_leaves_the_stacks_untouched() {
+    // "abc" matches the first bytes of both alternatives before they disagree.
+    let vocabulary = tiny_vocabulary();
+    let mut sampler = new_sampler(
+        "<start>::=<a>'!'|<b>'?'\n<a>::='ab'|'abd'\n<b>::='a'<c>\n<c>::='bd'|'be'",
+        &vocabulary,
+        SamplerConfig::new(),
+    );
+    let before = mask(&mut sampler, None);
+    let stacks = sampler.to_string();
+    assert_eq!(
+        sampler.accept_bytes(b"abc").unwrap(),
+        AcceptTokenResult::Failed
+    );
+    assert_eq!(sampler.to_string(), stacks);
+    assert_eq!(sampler.last_step_stack_delta().after, 0);
+    assert_eq!(mask(&mut sampler, None), before);
+    assert_eq!(
+        sampler.accept_bytes(b"abd!").unwrap(),
+        AcceptTokenResult::End
+    );
+}
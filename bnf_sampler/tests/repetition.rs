@@ -0,0 +1,205 @@
+mod common;
+
+use bnf_sampler::grammar::{Grammar, ParseError};
+use bnf_sampler::sampler::{AcceptTokenResult, PossibleTokensResult, SamplerConfig};
+use common::{assert_same_masks, tiny_vocabulary};
+
+#[test]
+fn star_matches_the_manual_expansion() {
+    let grammar = "<start>::='a'<b>*'c'\n<b>::='b'|'x'";
+    let expanded = "<start>::='a'<bs>'c'|'ac'\n<bs>::=<b>|<b><bs>\n<b>::='b'|'x'";
+    assert_same_masks(grammar, expanded, &["a", "b", "x", "b", "c"]);
+    assert_same_masks(grammar, expanded, &["a", "c"]);
+}
+
+#[test]
+fn plus_and_optional_match_the_manual_expansion() {
+    let grammar = "<start>::=<digit>+'.'<sign>?'!'\n<digit>::='1'|'2'\n<sign>::='+'|'-'";
+    let expanded = "<start>::=<digits>'.'<sign>'!'|<digits>'.!'
+<digits>::=<digit>|<digit><digits>
+<digit>::='1'|'2'
+<sign>::='+'|'-'";
+    assert_same_masks(grammar, expanded, &["1", "2", "1", ".", "-", "!"]);
+    assert_same_masks(grammar, expanded, &["2", ".", "!"]);
+}
+
+#[test]
+fn terminals_can_be_repeated() {
+    let grammar = "<start>::='('\"ab\"*')'";
+    let expanded = "<start>::='('<abs>')'|'()'\n<abs>::='ab'|'ab'<abs>";
+    assert_same_masks(grammar, expanded, &["(", "ab", "ab", ")"]);
+}
+
+#[test]
+fn operators_inside_terminals_are_literal() {
+    let grammar = "<start>::='a*'<b>?'+?'\n<b>::='b'";
+    let expanded = "<start>::='a*'<b>'+?'|'a*+?'\n<b>::='b'";
+    assert_same_masks(grammar, expanded, &["a", "*", "b", "+", "?"]);
+    assert_same_masks(grammar, expanded, &["a", "*", "+", "?"]);
+}
+
+#[test]
+fn star_and_optional_can_match_no_occurrence() {
+    let vocabulary = tiny_vocabulary();
+    for (grammar, outputs) in [
+        ("<start>::=<o>'.'\n<o>::=<a>?\n<a>::='a'", &[".", "a."][..]),
+        (
+            "<start>::=<o>'.'\n<o>::=<a>*\n<a>::='a'",
+            &[".", "a.", "aaa."],
+        ),
+    ] {
+        for output in outputs {
+            assert!(
+                common::validates(grammar, &vocabulary, output.as_bytes()),
+                "{grammar} {output}"
+            );
+        }
+        // Sampling `.` first takes the empty alternative of `<o>`.
+        let mut sampler = common::new_sampler(grammar, &vocabulary, SamplerConfig::new());
+        match sampler.all_possible_next_tokens(None).unwrap() {
+            PossibleTokensResult::Continue(mask) => {
+                assert!(mask.contains(vocabulary.token_to_id[&b"."[..]] as usize))
+            }
+            result => panic!("{result:?}"),
+        }
+        let dot = vocabulary.token_to_id[&b"."[..]];
+        assert_eq!(
+            sampler.all_possible_next_tokens(Some(dot)).unwrap(),
+            PossibleTokensResult::End,
+            "{grammar}"
+        );
+    }
+    assert!(!common::validates(
+        "<start>::=<o>'.'\n<o>::=<a>?\n<a>::='a'",
+        &vocabulary,
+        b"aa."
+    ));
+}
+
+#[test]
+fn repetition_nonterminals_are_hidden() {
+    let grammar = Grammar::new(
+        "<start>::=<b>+<c>*'.'\n<b>::='b'\n<c>::='c'",
+        tiny_vocabulary(),
+        0,
+    )
+    .unwrap();
+    assert_eq!(grammar.nonterminals(), ["b", "c", "start"]);
+}
+
+#[test]
+fn zero_lower_bound_allows_the_empty_string() {
+    let vocabulary = tiny_vocabulary();
+    let grammar = "<start>::=<o>'.'\n<o>::='a'{0,2}";
+    for output in [".", "a.", "aa."] {
+        assert!(
+            common::validates(grammar, &vocabulary, output.as_bytes()),
+            "{output}"
+        );
+    }
+    assert!(!common::validates(grammar, &vocabulary, b"aaa."));
+    // The whole start nonterminal can be empty too.
+    let mut sampler = common::new_sampler("<start>::='a'{0,2}", &vocabulary, SamplerConfig::new());
+    assert_eq!(
+        sampler.all_possible_next_tokens(None).unwrap(),
+        PossibleTokensResult::End
+    );
+}
+
+#[test]
+fn bounds_match_the_manual_expansion() {
+    let grammar = "<start>::=<hex>{2,4}'.'\n<hex>::='a'|'b'|'1'";
+    let expanded =
+        "<start>::=<hex><hex>'.'|<hex><hex><hex>'.'|<hex><hex><hex><hex>'.'\n<hex>::='a'|'b'|'1'";
+    assert_same_masks(grammar, expanded, &["a", "1", "b", "b", "."]);
+    assert_same_masks(grammar, expanded, &["a", "1", "."]);
+}
+
+#[test]
+fn exact_and_open_bounds_match_the_manual_expansion() {
+    let grammar = "<start>::='x'{3}<d>{2,}'.'\n<d>::='1'|'2'";
+    let expanded = "<start>::='xxx'<d><ds>'.'\n<ds>::=<d>|<d><ds>\n<d>::='1'|'2'";
+    assert_same_masks(grammar, expanded, &["x", "x", "x", "1", "2", "."]);
+    assert_same_masks(grammar, expanded, &["x", "x", "x", "2", "2", "1", "1", "."]);
+}
+
+#[test]
+fn zero_lower_bound_allows_no_copy() {
+    let grammar = "<start>::='a'<b>{0,2}'c'\n<b>::='b'";
+    let expanded = "<start>::='ac'|'a'<b>'c'|'a'<b><b>'c'\n<b>::='b'";
+    for tokens in [&["a", "c"][..], &["a", "b", "c"], &["a", "b", "b", "c"]] {
+        assert_same_masks(grammar, expanded, tokens);
+    }
+}
+
+#[test]
+fn ipv4_addresses() {
+    let grammar = "<start>::=<octet>'.'<octet>'.'<octet>'.'<octet>'$'
+<octet>::=<digit>|<nonzero><digit>|'1'<digit>{2}|'2'<low><digit>|'25'<high>
+<digit>::='0'|<nonzero>
+<nonzero>::='1'|'2'|'3'|'4'|'5'|'6'|'7'|'8'|'9'
+<low>::='0'|'1'|'2'|'3'|'4'
+<high>::='0'|'1'|'2'|'3'|'4'|'5'";
+    let vocabulary = tiny_vocabulary();
+    for address in ["192.168.0.255$", "10.0.0.1$", "249.250.199.9$"] {
+        assert!(
+            common::validates(grammar, &vocabulary, address.as_bytes()),
+            "{address}"
+        );
+    }
+    for address in ["256.1.1.1$", "01.1.1.1$", "1.1.1.1000$", "1.1.1$"] {
+        assert!(
+            !common::validates(grammar, &vocabulary, address.as_bytes()),
+            "{address}"
+        );
+    }
+}
+
+#[test]
+fn invalid_bounds_are_errors() {
+    for (bounds, shown) in [("{3,2}", "{3,2}"), ("{x}", "{x}"), ("{,2}", "{,2}")] {
+        let error = Grammar::new(
+            &format!("<start>::='a'<b>{bounds}\n<b>::='b'"),
+            tiny_vocabulary(),
+            0,
+        )
+        .unwrap_err()
+        .to_string();
+        assert_eq!(
+            error,
+            format!("<start> is invalid because {shown} is not a valid repetition like {{2}}, {{2,5}} or {{2,}}.")
+        );
+    }
+}
+
+#[test]
+fn large_bounds_are_errors() {
+    let vocabulary = tiny_vocabulary();
+    for (input, column) in [
+        ("<a>::='a'\n<start>::=<a>{1,20000}", 14),
+        ("<a>::='a'\n<start>::='[{'<a>'}'  'a'{20000}", 26),
+    ] {
+        let error = Grammar::new(input, vocabulary.clone(), 0).unwrap_err();
+        let error = error.downcast_ref::<ParseError>().unwrap();
+        assert_eq!((error.line, error.column), (2, column), "{error}");
+        assert!(error.message.contains("1024"), "{error}");
+    }
+    // The largest bounds expand without deep recursion.
+    let grammar = "<start>::='<'<a>{0,1024}'>'\n<a>::='a'";
+    for (copies, result) in [
+        (1024, AcceptTokenResult::End),
+        (1025, AcceptTokenResult::Failed),
+    ] {
+        let mut sampler = common::new_sampler(grammar, &vocabulary, SamplerConfig::new());
+        let output = format!("<{}>", "a".repeat(copies));
+        // One byte at a time, since the arena holds the stacks of every byte accepted at once.
+        let mut accepted = AcceptTokenResult::Continue;
+        for byte in output.as_bytes() {
+            accepted = sampler.accept_bytes(&[*byte]).unwrap();
+            if accepted != AcceptTokenResult::Continue {
+                break;
+            }
+        }
+        assert_eq!(accepted, result);
+    }
+}
@@ -0,0 +1,108 @@
+//! Malformed input must produce errors instead of panics.
+mod common;
+
+use bnf_sampler::grammar::Grammar;
+use bnf_sampler::sampler::{Sampler, SamplerConfig};
+use bnf_sampler::vocabulary::DEFAULT_MAX_TOKEN_BYTES;
+use bnf_sampler::{quick, utils};
+use common::tiny_vocabulary;
+use std::panic::{catch_unwind, AssertUnwindSafe};
+
+fn assert_no_panic<T>(input: &str, f: impl FnOnce() -> T) -> T {
+    catch_unwind(AssertUnwindSafe(f)).unwrap_or_else(|_| panic!("{input:?} panics"))
+}
+
+const MALFORMED_GRAMMARS: &[&str] = &[
+    "",
+    "<start>",
+    "<start>::=",
+    "<start>::=<undefined>",
+    "<start>::='a'<undefined>",
+    "<start>::='\\'",
+    "<start>::='\\x'",
+    "<start>::='\\xZZ'",
+    "<start>::='\\u12'",
+    "<start>::='\\uD800'",
+    "<start>::=<except!('\\')>",
+    "<start>::=<except!([undefined])>",
+    "<start>::=<except!([start])>",
+    "<start>::=<except!([a])>\n<a>::='x'<undefined>",
+    "<start>::=<any!>\n<any!>::='x'",
+];
+
+#[test]
+fn malformed_grammars_are_errors() {
+    let vocabulary = tiny_vocabulary();
+    for grammar in MALFORMED_GRAMMARS {
+        let result = assert_no_panic(grammar, || Grammar::new(grammar, vocabulary.clone(), 0));
+        assert!(result.is_err(), "{grammar:?} should be rejected");
+        let result = assert_no_panic(grammar, || {
+            quick::allowed_first_tokens(grammar, "start", &vocabulary)
+        });
+        assert!(result.is_err(), "{grammar:?} should be rejected");
+    }
+}
+
+#[test]
+fn undefined_start_nonterminal_is_an_error() {
+    let vocabulary = tiny_vocabulary();
+    let grammar = Grammar::new("<start>::='a'", vocabulary.clone(), 0).unwrap();
+    let result = assert_no_panic("undefined", || {
+        Sampler::with_config(
+            grammar,
+            "undefined".to_string(),
+            vocabulary,
+            SamplerConfig::new(),
+        )
+    });
+    assert!(result.is_err());
+}
+
+#[test]
+fn out_of_range_token_ids_are_errors() {
+    let vocabulary = tiny_vocabulary();
+    let grammar = Grammar::new("<start>::='a'|'b'", vocabulary.clone(), 0).unwrap();
+    let mut sampler = Sampler::with_config(
+        grammar,
+        "start".to_string(),
+        vocabulary.clone(),
+        SamplerConfig::new(),
+    )
+    .unwrap();
+    for token_id in [0, u32::MAX, 100_000] {
+        let result = assert_no_panic("out of range", || {
+            sampler.all_possible_next_tokens(Some(token_id)).map(|_| ())
+        });
+        assert!(result.is_err(), "{token_id} should be rejected");
+        let result = assert_no_panic("out of range", || sampler.accept_a_token(Some(token_id)));
+        assert!(result.is_err(), "{token_id} should be rejected");
+    }
+    let result = assert_no_panic("out of range", || {
+        quick::accepts("<start>::='a'", "start", &vocabulary, &[u32::MAX])
+    });
+    assert!(result.is_err());
+}
+
+const MALFORMED_VOCAB_LINES: &[&str] = &[
+    "",
+    "1",
+    "1 'a'",
+    "x 'a' 1",
+    "-1 'a' 1",
+    "1 é 1",
+    "1 '\\' 1",
+    "1 '\\x1' 1",
+    "1 '\\uZZZZ' 1",
+];
+
+#[test]
+fn malformed_vocab_lines_are_errors() {
+    for line in MALFORMED_VOCAB_LINES {
+        let input = format!("98 'a' 1\n{line}\n");
+        let result = assert_no_panic(line, || {
+            utils::read_rwkv_world_vocab_from_reader(input.as_bytes(), DEFAULT_MAX_TOKEN_BYTES)
+        });
+        assert!(result.is_err(), "{line:?} should be rejected");
+    }
+    assert!(utils::read_rwkv_world_vocab("does/not/exist.txt").is_err());
+}
@@ -0,0 +1,65 @@
+mod common;
+
+use bnf_sampler::metrics::StepTiming;
+use bnf_sampler::sampler::{PossibleTokensResult, SamplerConfig};
+use common::{new_sampler, tiny_vocabulary};
+
+fn first_step(grammar: &str, signature_filter: bool) -> (StepTiming, Vec<usize>) {
+    let vocabulary = tiny_vocabulary();
+    let mut sampler = new_sampler(
+        grammar,
+        &vocabulary,
+        SamplerConfig::new()
+            .collect_timing(true)
+            .signature_filter(signature_filter)
+            .length_bound(false),
+    );
+    let mask = match sampler.all_possible_next_tokens(None).unwrap() {
+        PossibleTokensResult::Continue(token_ids) => token_ids.iter().collect(),
+        result => panic!("{result:?}"),
+    };
+    (*sampler.last_step_timing().unwrap(), mask)
+}
+
+#[test]
+fn stacks_sharing_a_top_visit_each_token_once() {
+    let vocabulary = tiny_vocabulary();
+    // Every token starting with `a` is yielded once per terminal of <a>.
+    let candidates = vocabulary
+        .token_to_id
+        .iter()
+        .filter(|(token, _)| token.0.first() == Some(&b'a'))
+        .count();
+    let single = "<start>::=<a>'1'\n<a>::='ab'|'ac'|'ad'";
+    let shared = "<start>::=<a>'1'|<a>'2'|<a>'3'\n<a>::='ab'|'ac'|'ad'";
+    for signature_filter in [true, false] {
+        let (single_timing, single_mask) = first_step(single, signature_filter);
+        let (shared_timing, shared_mask) = first_step(shared, signature_filter);
+        assert_eq!(single_timing.tokens_visited, candidates);
+        assert_eq!(shared_timing.tokens_visited, candidates);
+        assert_eq!(
+            single_timing.tokens_checked + single_timing.tokens_filtered,
+            candidates
+        );
+        // A token is matched against the next stack only when the previous ones reject it.
+        let rejected = candidates - shared_timing.tokens_accepted;
+        assert_eq!(
+            shared_timing.tokens_checked + shared_timing.tokens_filtered,
+            shared_timing.tokens_accepted + 3 * rejected,
+            "{shared_timing:?}"
+        );
+        assert_eq!(single_mask, shared_mask);
+    }
+}
+
+#[test]
+fn each_stack_of_a_group_can_allow_a_token() {
+    let vocabulary = tiny_vocabulary();
+    // Both stacks have <a> on top, and `and` is only accepted by the first one while `at` is only accepted by the second.
+    let (_, mask) = first_step("<start>::=<a>'nd'|<a>'t'\n<a>::='a'|'b'", true);
+    let id = |token: &str| vocabulary.token_to_id[token.as_bytes()] as usize;
+    for token in ["a", "an", "and", "at"] {
+        assert!(mask.contains(&id(token)), "{token}");
+    }
+    assert!(!mask.contains(&id("age")));
+}
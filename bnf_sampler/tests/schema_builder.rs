@@ -0,0 +1,95 @@
+mod common;
+
+use bnf_sampler::grammar::{Grammar, GrammarBuildOptions};
+use bnf_sampler::sampler::{AcceptTokenResult, Sampler, SamplerConfig};
+use bnf_sampler::schema::{merge, DuplicatePolicy};
+use common::{assert_same_masks, tiny_vocabulary, validates};
+
+const BASE: &str = "<start>::='call '<tool_name>'('<argument>')'
+<argument>::='a'|'b'";
+
+fn accepts(grammar: std::sync::Arc<Grammar>, output: &str) -> bool {
+    let vocabulary = tiny_vocabulary();
+    let mut sampler = Sampler::with_config(
+        grammar,
+        "start".to_string(),
+        vocabulary,
+        SamplerConfig::new(),
+    )
+    .unwrap();
+    sampler.accept_bytes(output.as_bytes()).unwrap() == AcceptTokenResult::End
+}
+
+#[test]
+fn productions_are_added_to_the_schema() {
+    let mut builder = Grammar::builder();
+    builder
+        .add_schema(BASE)
+        .add_production("tool_name", &[&["'search'"], &["'calc'", "<suffix>"]])
+        .unwrap()
+        .add_production("suffix", &[&[], &["'ulator'"]])
+        .unwrap();
+    let schema = builder.to_schema().unwrap();
+    assert_eq!(
+        schema,
+        format!("{BASE}\n<tool_name>::='search'|'calc'<suffix>\n<suffix>::=''|'ulator'\n")
+    );
+    let grammar = builder
+        .build(tiny_vocabulary(), GrammarBuildOptions::new())
+        .unwrap();
+    for output in ["call search(a)", "call calc(b)", "call calculator(a)"] {
+        assert!(accepts(grammar.clone(), output), "{output}");
+    }
+    assert!(!accepts(grammar, "call browse(a)"));
+}
+
+#[test]
+fn duplicates_are_rejected_by_default() {
+    let error = merge(BASE, &["<tool_name>::='search'", "<argument>::='c'"]).unwrap_err();
+    assert_eq!(
+        error.to_string(),
+        "<argument> is defined by both the schema 1 and the schema 3. \
+        Rename one of them, or union them with DuplicatePolicy::Union."
+    );
+    let mut builder = Grammar::builder();
+    builder
+        .add_schema(BASE)
+        .add_production("argument", &[&["'c'"]])
+        .unwrap();
+    assert!(builder
+        .to_schema()
+        .unwrap_err()
+        .to_string()
+        .contains("the production of <argument>"));
+    // A schema may still define a nonterminal twice itself.
+    merge("<start>::='a'\n<start>::='b'", &[]).unwrap();
+}
+
+#[test]
+fn unioned_duplicates_match_the_concatenated_schema() {
+    let overlay = "<tool_name>::='search'\n<argument>::='c'|'and'";
+    let mut builder = Grammar::builder().duplicates(DuplicatePolicy::Union);
+    builder.add_schema(BASE).add_schema(overlay);
+    let schema = builder.to_schema().unwrap();
+    assert_same_masks(
+        &schema,
+        &format!("{BASE}\n{overlay}"),
+        &["c", "a", "l", "l", " ", "search", "(", "and", ")"],
+    );
+    let vocabulary = tiny_vocabulary();
+    for output in ["call search(a)", "call search(c)", "call search(and)"] {
+        assert!(
+            validates(&schema, &vocabulary, output.as_bytes()),
+            "{output}"
+        );
+    }
+}
+
+#[test]
+fn invalid_productions_are_errors() {
+    let mut builder = Grammar::builder();
+    for (nonterminal, alternatives) in [("", &[&["'a'"][..]][..]), ("a>", &[&["'a'"]]), ("a", &[])]
+    {
+        assert!(builder.add_production(nonterminal, alternatives).is_err());
+    }
+}
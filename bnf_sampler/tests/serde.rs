@@ -0,0 +1,183 @@
+#![cfg(feature = "serde")]
+mod common;
+
+use bnf_sampler::fixtures;
+use bnf_sampler::grammar::Grammar;
+use bnf_sampler::sampler::{PossibleTokensResult, Sampler, SamplerConfig};
+use bnf_sampler::serialize::FORMAT_VERSION;
+use bnf_sampler::vocabulary::Vocabulary;
+use common::tiny_vocabulary;
+use serde_json::Value;
+use std::fs;
+use std::path::PathBuf;
+use std::sync::Arc;
+
+const EXCEPT: &str = r#"<start>::='"'<chars>'"'<digit>
+<digit>::=<any!>
+<chars>::=<except!('"')>|<except!('"')><chars>"#;
+
+fn sampler(grammar: Arc<Grammar>, vocabulary: &Arc<Vocabulary>) -> Sampler {
+    Sampler::with_config(
+        grammar,
+        "start".to_string(),
+        vocabulary.clone(),
+        SamplerConfig::new(),
+    )
+    .unwrap()
+}
+
+/// Walk both samplers with the middle allowed token and check they allow the same tokens at every step.
+fn assert_same_masks(mut a: Sampler, mut b: Sampler) {
+    let mut input = None;
+    for _ in 0..8 {
+        let [a, b] = [&mut a, &mut b].map(|x| match x.all_possible_next_tokens(input).unwrap() {
+            PossibleTokensResult::Continue(mask) => Some(mask.iter().collect::<Vec<_>>()),
+            _ => None,
+        });
+        assert_eq!(a, b);
+        let Some(mask) = a else {
+            return;
+        };
+        input = Some(mask[mask.len() / 2] as u32);
+    }
+}
+
+fn saved(grammar: &str, vocabulary: &Arc<Vocabulary>) -> Value {
+    let grammar = Grammar::new(grammar, vocabulary.clone(), 0).unwrap();
+    serde_json::from_slice(&grammar.to_bytes(vocabulary).unwrap()).unwrap()
+}
+
+fn load(saved: &Value, vocabulary: &Arc<Vocabulary>) -> Result<Arc<Grammar>, anyhow::Error> {
+    Grammar::from_bytes(&serde_json::to_vec(saved).unwrap(), vocabulary.clone())
+}
+
+#[test]
+fn loaded_grammars_allow_the_same_tokens() {
+    let vocabulary = fixtures::vocabulary();
+    for (_, grammar) in fixtures::grammars().into_iter().chain([("except", EXCEPT)]) {
+        let built = Grammar::new(grammar, vocabulary.clone(), 0).unwrap();
+        let loaded =
+            Grammar::from_bytes(&built.to_bytes(&vocabulary).unwrap(), vocabulary.clone()).unwrap();
+        assert_same_masks(sampler(built, &vocabulary), sampler(loaded, &vocabulary));
+    }
+}
+
+#[test]
+fn another_vocabulary_is_rejected() {
+    let saved = saved(EXCEPT, &tiny_vocabulary());
+    let error = load(&saved, &fixtures::vocabulary()).unwrap_err();
+    assert_eq!(
+        error.to_string(),
+        "The grammar is saved with another vocabulary."
+    );
+}
+
+#[test]
+fn another_version_is_rejected() {
+    let vocabulary = tiny_vocabulary();
+    let mut saved = saved(EXCEPT, &vocabulary);
+    saved["version"] = (FORMAT_VERSION + 1).into();
+    let error = load(&saved, &vocabulary).unwrap_err();
+    assert!(
+        error.to_string().contains("Build the grammar again."),
+        "{error}"
+    );
+}
+
+#[test]
+fn corrupted_ids_are_errors() {
+    let vocabulary = tiny_vocabulary();
+    let saved = saved(EXCEPT, &vocabulary);
+    let corruptions: [fn(&mut Value); 4] = [
+        |x| {
+            let children = x["grammar"]["terminals_trie"]["arena"][0]["children"]
+                .as_object_mut()
+                .unwrap();
+            let child = children.values_mut().next().unwrap();
+            child["id"] = 1_000_000.into();
+        },
+        |x| {
+            // A child before its parent could make a cycle.
+            let arena = x["grammar"]["terminals_trie"]["arena"]
+                .as_array_mut()
+                .unwrap();
+            let node = arena
+                .iter_mut()
+                .skip(1)
+                .find(|node| !node["children"].as_object().unwrap().is_empty())
+                .unwrap();
+            let child = node["children"].as_object_mut().unwrap().values_mut();
+            child.into_iter().next().unwrap()["id"] = 0.into();
+        },
+        |x| {
+            let token_ids = x["grammar"]["nonterminal_to_token_ids"]
+                .as_object_mut()
+                .unwrap();
+            let ids = token_ids.values_mut().next().unwrap();
+            ids.as_array_mut().unwrap().push(1_000_000.into());
+        },
+        |x| {
+            x["grammar"]["terminals"]["terminals"] = Value::Array(vec![]);
+        },
+    ];
+    for corrupt in corruptions {
+        let mut saved = saved.clone();
+        corrupt(&mut saved);
+        let error = load(&saved, &vocabulary).unwrap_err();
+        assert_eq!(error.to_string(), "The saved grammar is corrupted.");
+    }
+    let bytes = serde_json::to_vec(&saved).unwrap();
+    assert!(Grammar::from_bytes(&bytes[..bytes.len() / 2], vocabulary).is_err());
+}
+
+fn cache_dir(name: &str) -> PathBuf {
+    let path =
+        std::env::temp_dir().join(format!("bnf_sampler_cache_{name}_{}", std::process::id()));
+    let _ = fs::remove_dir_all(&path);
+    path
+}
+
+fn cache_files(path: &PathBuf) -> Vec<PathBuf> {
+    let mut files = fs::read_dir(path)
+        .unwrap()
+        .map(|x| x.unwrap().path())
+        .collect::<Vec<_>>();
+    files.sort();
+    files
+}
+
+#[test]
+fn cached_grammars_are_loaded() {
+    let vocabulary = tiny_vocabulary();
+    let path = cache_dir("loaded");
+    let built = Grammar::new_cached(EXCEPT, vocabulary.clone(), 0, &path).unwrap();
+    let files = cache_files(&path);
+    assert_eq!(files.len(), 1);
+    let modified = fs::metadata(&files[0]).unwrap().modified().unwrap();
+    let loaded = Grammar::new_cached(EXCEPT, vocabulary.clone(), 0, &path).unwrap();
+    assert_eq!(cache_files(&path), files);
+    assert_eq!(
+        fs::metadata(&files[0]).unwrap().modified().unwrap(),
+        modified
+    );
+    assert_same_masks(sampler(built, &vocabulary), sampler(loaded, &vocabulary));
+    // Another schema or another vocabulary is another file.
+    Grammar::new_cached("<start>::='a'", vocabulary, 0, &path).unwrap();
+    Grammar::new_cached(EXCEPT, fixtures::vocabulary(), 0, &path).unwrap();
+    assert_eq!(cache_files(&path).len(), 3);
+}
+
+#[test]
+fn corrupted_cache_files_are_rebuilt() {
+    let vocabulary = tiny_vocabulary();
+    let path = cache_dir("corrupted");
+    Grammar::new_cached(EXCEPT, vocabulary.clone(), 0, &path).unwrap();
+    let file = cache_files(&path).remove(0);
+    let bytes = fs::read(&file).unwrap();
+    fs::write(&file, &bytes[..bytes.len() / 2]).unwrap();
+    let rebuilt = Grammar::new_cached(EXCEPT, vocabulary.clone(), 0, &path).unwrap();
+    assert_eq!(fs::read(&file).unwrap(), bytes);
+    let built = Grammar::new(EXCEPT, vocabulary.clone(), 0).unwrap();
+    assert_same_masks(sampler(built, &vocabulary), sampler(rebuilt, &vocabulary));
+    assert_eq!(cache_files(&path), [file]);
+}
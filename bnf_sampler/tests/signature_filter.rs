@@ -0,0 +1,91 @@
+mod common;
+
+use bnf_sampler::sampler::{CacheMode, PossibleTokensResult, Sampler, SamplerConfig};
+use bnf_sampler::vocabulary::Vocabulary;
+use common::{new_sampler, tiny_vocabulary};
+use std::sync::Arc;
+
+const GRAMMARS: &[&str] = &[
+    r#"<start>::='{"'<key>'":'<value>'}'
+<key>::=<except!('"')>|<except!('"')><key>
+<value>::='true'|'false'|'null'|<digits>
+<digits>::=<digit>|<digit><digits>
+<digit>::='0'|'1'|'2'|'3'|'4'|'5'|'6'|'7'|'8'|'9'"#,
+    r#"<start>::=<string>
+<string>::='"'<content>'"'
+<content>::=<except!([escaped_literals])>|<except!([escaped_literals])><content>|'\\"'<content>|'\\"'
+<escaped_literals>::='\t'|'\n'|'\r'|'"'"#,
+    "<start>::=<sequence>\n<sequence>::=<base>|<base><sequence>\n<base>::=\"A\"|\"C\"|\"G\"|\"T\"",
+    "<start>::='The answer: '<any!>' done.'",
+    "<start>::=<v><v>'.'\n<v>::='a'|'ab'|'abc'|<w>\n<w>::='b'|'bc'",
+    "<start>::='[' <v> ']'\n<v>::='true'|'false'|'[' <v> ']'|'true, '<v>",
+    "<start>::=<text>'.'\n<text>::=<any_except_bytes!(0x2E, 0x0A)>|<any_except_bytes!(0x2E, 0x0A)><text>",
+    "<start>::=<regex!('[a-f0-9]{8}-(yes|no)')>",
+    "<start>::='x='<number!(-40, 1234)>' y='<decimal!(3, 2)>';'",
+];
+
+fn masks(sampler: &mut Sampler, token_id: Option<u32>) -> Option<Vec<usize>> {
+    match sampler.all_possible_next_tokens(token_id).unwrap() {
+        PossibleTokensResult::Continue(token_ids) => Some(token_ids.iter().collect()),
+        _ => None,
+    }
+}
+
+/// Drive a sampler with the filter and one without it through the same tokens,
+/// picking a different allowed token at each step, and compare the possible tokens.
+fn assert_same_masks(grammar: &str, vocabulary: &Arc<Vocabulary>, cache_mode: CacheMode) {
+    let config = SamplerConfig::new().cache_mode(cache_mode);
+    let mut filtered = new_sampler(grammar, vocabulary, config.clone().signature_filter(true));
+    let mut unfiltered = new_sampler(grammar, vocabulary, config.signature_filter(false));
+    let mut token_id = None;
+    for step in 0..40 {
+        let mask = masks(&mut filtered, token_id);
+        assert_eq!(
+            mask,
+            masks(&mut unfiltered, token_id),
+            "{grammar} at step {step}"
+        );
+        let Some(mask) = mask else {
+            break;
+        };
+        token_id = Some(mask[step * 7 % mask.len()] as u32);
+    }
+}
+
+#[test]
+fn filter_never_changes_the_possible_tokens() {
+    let vocabularies = [tiny_vocabulary(), Arc::new(Vocabulary::byte_level())];
+    for grammar in GRAMMARS {
+        for vocabulary in vocabularies.iter() {
+            for cache_mode in [CacheMode::Full, CacheMode::None] {
+                assert_same_masks(grammar, vocabulary, cache_mode);
+            }
+        }
+    }
+}
+
+#[test]
+fn filter_skips_tokens_with_bytes_the_grammar_cannot_match() {
+    let vocabulary = tiny_vocabulary();
+    let grammar = "<start>::='true'|'tree'|<digits>\n<digits>::='1'|'2'|'1'<digits>|'2'<digits>";
+    let mut timings = vec![];
+    for enabled in [true, false] {
+        let mut sampler = new_sampler(
+            grammar,
+            &vocabulary,
+            SamplerConfig::new()
+                .collect_timing(true)
+                .signature_filter(enabled),
+        );
+        sampler.all_possible_next_tokens(None).unwrap();
+        timings.push(*sampler.last_step_timing().unwrap());
+    }
+    let (filtered, unfiltered) = (timings[0], timings[1]);
+    assert!(filtered.tokens_filtered > 0, "{filtered:?}");
+    assert_eq!(unfiltered.tokens_filtered, 0);
+    assert_eq!(
+        filtered.tokens_checked + filtered.tokens_filtered,
+        unfiltered.tokens_checked
+    );
+    assert_eq!(filtered.tokens_accepted, unfiltered.tokens_accepted);
+}
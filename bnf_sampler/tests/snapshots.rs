@@ -0,0 +1,122 @@
+//! Compare the sorted allowed token ids at each step of a scripted generation with `tests/snapshots/<grammar>.txt`,
+//! for each fixture grammar and the fixture vocabulary, so changes to the masks are intentional.
+//!
+//! Run `BLESS=1 cargo test --test snapshots` to regenerate the snapshots after an intentional change.
+mod common;
+
+use bnf_sampler::fixtures;
+use bnf_sampler::sampler::{CacheMode, PossibleTokensResult, SamplerConfig};
+use bnf_sampler::vocabulary::Vocabulary;
+use common::new_sampler;
+use std::fmt::Write as _;
+use std::fs;
+use std::path::Path;
+use std::sync::Arc;
+
+/// The tokens accepted by each fixture grammar, in the order of [`fixtures::grammars`].
+const SCRIPTS: [&[&str]; 2] = [
+    &[
+        "{\"", "name", "\": ", "\"", "中文", "\"", ", \"", "age", "\":", "42", "}",
+    ],
+    &["(", "12", " +", " ", "3", ")", " *", " ", "-", "2", "="],
+];
+
+/// The ids as ranges, like `3-7 9 12-13`.
+fn ranges(ids: impl IntoIterator<Item = usize>) -> String {
+    let mut ranges: Vec<(usize, usize)> = vec![];
+    for id in ids {
+        match ranges.last_mut() {
+            Some((_, end)) if *end + 1 == id => *end = id,
+            _ => ranges.push((id, id)),
+        }
+    }
+    ranges
+        .iter()
+        .map(|(start, end)| match start == end {
+            true => start.to_string(),
+            false => format!("{start}-{end}"),
+        })
+        .collect::<Vec<_>>()
+        .join(" ")
+}
+
+/// One line per step, with the accepted token and the allowed token ids.
+fn snapshot(
+    grammar: &str,
+    script: &[&str],
+    vocabulary: &Arc<Vocabulary>,
+    config: SamplerConfig,
+) -> String {
+    let mut sampler = new_sampler(grammar, vocabulary, config);
+    let mut lines = String::new();
+    let mut input_token_id = None;
+    for step in 0..=script.len() {
+        match input_token_id {
+            Some(id) => write!(lines, "{step} after {:?}:", vocabulary.token_display(id)),
+            None => write!(lines, "{step} start:"),
+        }
+        .unwrap();
+        match sampler.all_possible_next_tokens(input_token_id).unwrap() {
+            PossibleTokensResult::Continue(ids) => {
+                let mut ids = ids.iter().collect::<Vec<_>>();
+                ids.sort_unstable();
+                writeln!(lines, " {} ids: {}", ids.len(), ranges(ids)).unwrap();
+            }
+            result => writeln!(lines, " {result:?}").unwrap(),
+        }
+        input_token_id = script
+            .get(step)
+            .map(|x| vocabulary.token_to_id[x.as_bytes()]);
+    }
+    lines
+}
+
+/// The lines that differ, prefixed with `-` for the snapshot and `+` for the actual masks.
+fn diff(expected: &str, actual: &str) -> String {
+    let (expected, actual) = (
+        expected.lines().collect::<Vec<_>>(),
+        actual.lines().collect::<Vec<_>>(),
+    );
+    let mut diff = String::new();
+    for i in 0..expected.len().max(actual.len()) {
+        let (a, b) = (expected.get(i), actual.get(i));
+        if a != b {
+            if let Some(a) = a {
+                writeln!(diff, "-{a}").unwrap();
+            }
+            if let Some(b) = b {
+                writeln!(diff, "+{b}").unwrap();
+            }
+        }
+    }
+    diff
+}
+
+#[test]
+fn fixture_masks_match_the_snapshots() {
+    let vocabulary = fixtures::vocabulary();
+    let directory = Path::new(env!("CARGO_MANIFEST_DIR")).join("tests/snapshots");
+    for ((name, grammar), script) in fixtures::grammars().into_iter().zip(SCRIPTS) {
+        let path = directory.join(format!("{name}.txt"));
+        let actual = snapshot(grammar, script, &vocabulary, SamplerConfig::new());
+        // The cache must not change the masks.
+        let uncached = snapshot(
+            grammar,
+            script,
+            &vocabulary,
+            SamplerConfig::new().cache_mode(CacheMode::None),
+        );
+        assert_eq!(diff(&actual, &uncached), "", "{name} without the cache");
+        if std::env::var_os("BLESS").is_some() {
+            fs::create_dir_all(&directory).unwrap();
+            fs::write(&path, actual).unwrap();
+            continue;
+        }
+        let expected = fs::read_to_string(&path).unwrap_or_default();
+        assert!(
+            expected == actual,
+            "The masks of {name} changed. Run `BLESS=1 cargo test --test snapshots` if it is intentional.\n{}",
+            diff(&expected, &actual)
+        );
+    }
+}
@@ -0,0 +1,127 @@
+mod common;
+
+use bnf_sampler::grammar::Grammar;
+use bnf_sampler::sampler::{PossibleTokensResult, Sampler, SamplerConfig};
+use bnf_sampler::vocabulary::{Vocabulary, DEFAULT_MAX_TOKEN_BYTES};
+use common::{new_sampler, tiny_vocabulary};
+use rustc_hash::FxHashMap;
+use std::sync::Arc;
+
+const GRAMMARS: &[&str] = &[
+    r#"<start>::='{"'<key>'":'<value>'}'
+<key>::=<except!('"')>|<except!('"')><key>
+<value>::='true'|'false'|'null'|<digits>
+<digits>::=<digit>|<digit><digits>
+<digit>::='0'|'1'|'2'|'3'|'4'|'5'|'6'|'7'|'8'|'9'"#,
+    r#"<start>::=<string>
+<string>::='"'<content>'"'
+<content>::=<except!([escaped_literals])>|<except!([escaped_literals])><content>|'\\"'<content>|'\\"'
+<escaped_literals>::='\t'|'\n'|'\r'|'"'"#,
+    "<start>::='The answer: '<any!>' done.'",
+    "<start>::=<text>'.'\n<text>::=<any_except_bytes!(0x2E, 0x0A)>|<any_except_bytes!(0x2E, 0x0A)><text>",
+];
+
+/// The sparse id of a token id of the tiny vocabulary.
+fn sparse_id(id: u32) -> u32 {
+    id * 97 + 1000
+}
+
+fn remap<T: Clone>(map: &FxHashMap<u32, T>) -> FxHashMap<u32, T> {
+    map.iter()
+        .map(|(id, value)| (sparse_id(*id), value.clone()))
+        .collect()
+}
+
+/// The tiny vocabulary with ids spread over a range 97 times larger.
+fn sparse_vocabulary(vocabulary: &Vocabulary) -> Arc<Vocabulary> {
+    Arc::new(
+        Vocabulary::new(
+            remap(&vocabulary.id_to_token),
+            remap(&vocabulary.id_to_token_string),
+            DEFAULT_MAX_TOKEN_BYTES,
+        )
+        .unwrap(),
+    )
+}
+
+fn mask(sampler: &mut Sampler, token_id: Option<u32>) -> Option<Vec<u32>> {
+    match sampler.all_possible_next_tokens(token_id).unwrap() {
+        PossibleTokensResult::Continue(token_ids) => {
+            Some(token_ids.iter().map(|x| x as u32).collect())
+        }
+        _ => None,
+    }
+}
+
+#[test]
+fn sparse_ids_are_remapped_automatically() {
+    let vocabulary = tiny_vocabulary();
+    assert!(!vocabulary.is_remapped());
+    assert!(!Vocabulary::byte_level().is_remapped());
+    assert!(sparse_vocabulary(&vocabulary).is_remapped());
+}
+
+#[test]
+fn sparse_vocabulary_behaves_like_the_dense_one() {
+    let dense = tiny_vocabulary();
+    let sparse = sparse_vocabulary(&dense);
+    for grammar in GRAMMARS {
+        let mut dense_sampler = new_sampler(grammar, &dense, SamplerConfig::new());
+        let mut sparse_sampler = new_sampler(grammar, &sparse, SamplerConfig::new());
+        let mut token_id = None;
+        for step in 0..30 {
+            let expected = mask(&mut dense_sampler, token_id);
+            let actual = mask(&mut sparse_sampler, token_id.map(sparse_id));
+            assert_eq!(
+                actual,
+                expected
+                    .as_ref()
+                    .map(|x| x.iter().map(|id| sparse_id(*id)).collect()),
+                "{grammar} at step {step}"
+            );
+            let Some(expected) = expected else {
+                break;
+            };
+            token_id = Some(expected[step * 5 % expected.len()]);
+        }
+        let max_id = *sparse.id_to_token.keys().max().unwrap() as usize;
+        assert!(sparse_sampler.mask_capacity() < max_id / 10, "{grammar}");
+        // A roaring bitmap is only as large as its largest id.
+        if cfg!(not(feature = "roaring")) {
+            assert!(dense_sampler.mask_capacity() >= dense.id_to_token.len());
+        }
+    }
+}
+
+#[test]
+fn nearest_token_and_boundary_conflicts_use_token_ids() {
+    let dense = tiny_vocabulary();
+    let sparse = sparse_vocabulary(&dense);
+    let grammar = "<start>::='\"'<except!('\"')>'\"'";
+    let mut dense_sampler = new_sampler(grammar, &dense, SamplerConfig::new());
+    let mut sparse_sampler = new_sampler(grammar, &sparse, SamplerConfig::new());
+    let token_id = dense.token_to_id["abc".as_bytes()];
+    let expected = dense_sampler.accept_nearest_token(token_id).unwrap();
+    let actual = sparse_sampler
+        .accept_nearest_token(sparse_id(token_id))
+        .unwrap();
+    assert_eq!(
+        actual.substituted_token_id,
+        expected.substituted_token_id.map(sparse_id)
+    );
+    assert_eq!(actual.accepted_bytes, expected.accepted_bytes);
+
+    let expected = Grammar::new(grammar, dense.clone(), 0)
+        .unwrap()
+        .boundary_conflicts(&dense);
+    let actual = Grammar::new(grammar, sparse.clone(), 0)
+        .unwrap()
+        .boundary_conflicts(&sparse);
+    assert!(!expected.is_empty());
+    assert_eq!(expected.len(), actual.len());
+    for (expected, actual) in expected.iter().zip(actual.iter()) {
+        let mut token_ids: Vec<u32> = expected.token_ids.iter().map(|x| sparse_id(*x)).collect();
+        token_ids.sort_unstable();
+        assert_eq!(actual.token_ids, token_ids);
+    }
+}
@@ -0,0 +1,125 @@
+mod common;
+
+use anyhow::{ensure, Error};
+use bnf_sampler::grammar::{Grammar, GrammarBuildOptions};
+use bnf_sampler::quick;
+use bnf_sampler::sampler::{PossibleTokensResult, Sampler, SamplerConfig};
+use bnf_sampler::special::{GrammarBuildCtx, ParsedForm, SpecialForm};
+use common::tiny_vocabulary;
+
+/// `<digits!(n)>` matches the tokens made of at most n ASCII digits.
+struct Digits;
+
+impl SpecialForm for Digits {
+    fn name(&self) -> &str {
+        "digits"
+    }
+
+    fn parse(&self, args: &str) -> Result<ParsedForm, Error> {
+        let n: usize = args.parse()?;
+        ensure!(n > 0, "at least one digit is required.");
+        Ok(ParsedForm::Tokens)
+    }
+
+    fn build(&self, ctx: &mut GrammarBuildCtx) -> Result<(), Error> {
+        let n: usize = ctx.args().parse()?;
+        ctx.add_tokens(|token| token.len() <= n && token.iter().all(u8::is_ascii_digit));
+        Ok(())
+    }
+}
+
+fn first_tokens(input: &str, options: GrammarBuildOptions) -> Result<Vec<String>, Error> {
+    let vocabulary = tiny_vocabulary();
+    let grammar = Grammar::with_options(input, vocabulary.clone(), options)?;
+    let mut sampler = Sampler::with_config(
+        grammar,
+        "start".to_string(),
+        vocabulary.clone(),
+        SamplerConfig::new(),
+    )?;
+    let mut tokens = match sampler.all_possible_next_tokens(None)? {
+        PossibleTokensResult::Continue(token_ids) => vocabulary
+            .get_token_strings_from_token_ids(token_ids)
+            .map(|x| x.to_string())
+            .collect::<Vec<_>>(),
+        result => panic!("{result:?}"),
+    };
+    tokens.sort();
+    Ok(tokens)
+}
+
+#[test]
+fn custom_form_selects_tokens() {
+    let options = GrammarBuildOptions::new().register_form(Box::new(Digits));
+    let tokens = first_tokens("<start>::=<digits!(1)>|'x'<digits!(2)>", options).unwrap();
+    assert_eq!(
+        tokens,
+        vec!["0", "1", "2", "3", "4", "5", "6", "7", "8", "9", "x"]
+    );
+    let options = GrammarBuildOptions::new().register_form(Box::new(Digits));
+    let tokens = first_tokens("<start>::=<digits!(2)>", options).unwrap();
+    assert!(tokens.contains(&"42".to_string()));
+    assert!(tokens.contains(&"7".to_string()));
+}
+
+#[test]
+fn custom_form_errors_name_the_nonterminal() {
+    let options = GrammarBuildOptions::new().register_form(Box::new(Digits));
+    let error = first_tokens("<start>::=<digits!(0)>", options)
+        .unwrap_err()
+        .to_string();
+    assert_eq!(
+        error,
+        "<digits!(0)> is invalid because at least one digit is required."
+    );
+}
+
+#[test]
+fn unregistered_forms_are_errors() {
+    let error = first_tokens("<start>::=<digits!(1)>", GrammarBuildOptions::new())
+        .unwrap_err()
+        .to_string();
+    assert!(error.contains("unknown special form digits!"), "{error}");
+}
+
+#[test]
+fn forms_cannot_be_registered_twice() {
+    let options = GrammarBuildOptions::new()
+        .register_form(Box::new(Digits))
+        .register_form(Box::new(Digits));
+    assert!(first_tokens("<start>::='a'", options).is_err());
+}
+
+#[test]
+fn custom_forms_cannot_be_defined() {
+    let options = GrammarBuildOptions::new().register_form(Box::new(Digits));
+    assert!(first_tokens("<start>::=<digits!(1)>\n<digits!(1)>::='a'", options).is_err());
+}
+
+#[test]
+fn builtin_forms_are_registered() {
+    let vocabulary = tiny_vocabulary();
+    for input in [
+        "<start>::=<any!>",
+        "<start>::=<except!('a')>",
+        "<start>::=<except!(\"a\")>",
+        "<start>::=<except!(x\"61\")>",
+        "<start>::=<except!([a])>\n<a>::='a'",
+        "<start>::=<any_except_bytes!(0x61)>",
+    ] {
+        let tokens = quick::allowed_first_tokens(input, "start", &vocabulary).unwrap();
+        assert!(!tokens.is_empty(), "{input}");
+    }
+    for input in [
+        "<start>::=<any!(a)>",
+        "<start>::=<except!()>",
+        "<start>::=<except!([])>",
+        "<start>::=<except!(a)>",
+        "<start>::=<any_except_bytes!(a)>",
+    ] {
+        assert!(
+            Grammar::new(input, vocabulary.clone(), 0).is_err(),
+            "{input}"
+        );
+    }
+}
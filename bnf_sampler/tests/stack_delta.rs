@@ -0,0 +1,57 @@
+use bnf_sampler::grammar::Grammar;
+use bnf_sampler::sampler::{AcceptTokenResult, Sampler, SamplerConfig, StackDelta};
+use bnf_sampler::utils;
+
+const GRAMMAR: &str = "<start>::=<a>'!'|<b>'?'|'q'
+<a>::='xy'
+<b>::='x'<c>|'x'<d>
+<c>::='y'|'yz'
+<d>::=<c>'w'";
+
+fn delta(before: usize, created: usize, pruned: usize, after: usize) -> StackDelta {
+    StackDelta {
+        before,
+        created,
+        pruned,
+        after,
+    }
+}
+
+#[test]
+fn stack_delta_matches_manual_counting() {
+    let vocabulary = utils::read_rwkv_world_vocab(concat!(
+        env!("CARGO_MANIFEST_DIR"),
+        "/assets/tiny_vocab.txt"
+    ))
+    .unwrap();
+    let grammar = Grammar::new(GRAMMAR, vocabulary.clone(), 0).unwrap();
+    let mut sampler = Sampler::with_config(
+        grammar,
+        "start".to_string(),
+        vocabulary.clone(),
+        SamplerConfig::new(),
+    )
+    .unwrap();
+    sampler.all_possible_next_tokens(None).unwrap();
+    // <a>'!', 'x'<c>'?', 'x'<d>'?' and 'q'.
+    assert_eq!(sampler.last_step_stack_delta(), delta(1, 4, 0, 4));
+    let mut accept = |token: &str| {
+        let result = sampler
+            .accept_a_token(Some(vocabulary.token_to_id[token.as_bytes()]))
+            .unwrap();
+        assert_eq!(result, AcceptTokenResult::Continue);
+        sampler.last_step_stack_delta()
+    };
+    // 'q' is pruned, and the three others remain.
+    assert_eq!(accept("x"), delta(4, 3, 1, 3));
+    // <c> either ends after 'y' or continues with 'z', under both expansions of <b>, besides <a>'!'.
+    assert_eq!(accept("y"), delta(3, 5, 0, 5));
+    // Only the two stacks continuing with 'z' remain.
+    assert_eq!(accept("z"), delta(5, 2, 3, 2));
+    assert_eq!(accept("w"), delta(2, 1, 1, 1));
+    let result = sampler
+        .accept_a_token(Some(vocabulary.token_to_id["!".as_bytes()]))
+        .unwrap();
+    assert_eq!(result, AcceptTokenResult::Failed);
+    assert_eq!(sampler.last_step_stack_delta(), delta(1, 0, 1, 0));
+}
@@ -0,0 +1,85 @@
+mod common;
+
+use bnf_sampler::grammar::{Diagnostic, DiagnosticKind, Grammar, ParseError};
+use bnf_sampler::sampler::{AcceptTokenResult, Sampler, SamplerConfig};
+use common::tiny_vocabulary;
+
+#[test]
+fn the_declared_start_is_the_default_of_samplers() {
+    let vocabulary = tiny_vocabulary();
+    let grammar = Grammar::new(
+        "# A list of ids.\n@start <list>\n<list>::='id'|'id,'<list>\n<start>::='xyz'",
+        vocabulary.clone(),
+        0,
+    )
+    .unwrap();
+    assert_eq!(grammar.start_nonterminal(), "list");
+    let samplers = [
+        Sampler::with_default_start(grammar.clone(), vocabulary.clone(), SamplerConfig::new()),
+        Sampler::new(grammar.clone(), String::new(), vocabulary.clone(), 0, true),
+    ];
+    for sampler in samplers {
+        let mut sampler = sampler.unwrap();
+        assert_eq!(
+            sampler.accept_bytes(b"id,id").unwrap(),
+            AcceptTokenResult::End
+        );
+    }
+    // An explicit start nonterminal still wins.
+    let mut sampler = Sampler::new(grammar, "start".to_string(), vocabulary, 0, true).unwrap();
+    assert_eq!(
+        sampler.accept_bytes(b"xyz").unwrap(),
+        AcceptTokenResult::End
+    );
+}
+
+#[test]
+fn the_start_defaults_to_start() {
+    let vocabulary = tiny_vocabulary();
+    let grammar = Grammar::new("<start>::='id'", vocabulary.clone(), 0).unwrap();
+    assert_eq!(grammar.start_nonterminal(), "start");
+    let mut sampler =
+        Sampler::with_default_start(grammar, vocabulary.clone(), SamplerConfig::new()).unwrap();
+    assert_eq!(sampler.accept_bytes(b"id").unwrap(), AcceptTokenResult::End);
+    // Without @start, a grammar without <start> is still valid for other start nonterminals.
+    assert!(Grammar::new("<json>::='{}'", vocabulary, 0).is_ok());
+}
+
+#[test]
+fn the_angle_brackets_are_optional() {
+    let grammar = Grammar::new("@start json\n<json>::='{}'", tiny_vocabulary(), 0).unwrap();
+    assert_eq!(grammar.start_nonterminal(), "json");
+}
+
+#[test]
+fn the_declared_start_is_validated_when_the_grammar_is_built() {
+    let error = Grammar::new("@start <lsit>\n<list>::='id'", tiny_vocabulary(), 0).unwrap_err();
+    let diagnostic = error.downcast_ref::<Diagnostic>().unwrap();
+    assert_eq!(diagnostic.kind, DiagnosticKind::UndefinedNonterminal);
+    assert_eq!(diagnostic.nonterminal.as_deref(), Some("lsit"));
+    let error = Grammar::new(
+        "@start <loop>\n<loop>::='x'<loop>\n<start>::='x'",
+        tiny_vocabulary(),
+        0,
+    )
+    .unwrap_err();
+    let diagnostic = error.downcast_ref::<Diagnostic>().unwrap();
+    assert_eq!(diagnostic.kind, DiagnosticKind::NonProductive);
+}
+
+#[test]
+fn invalid_directives_are_errors() {
+    for grammar in [
+        "@start\n<start>::='x'",
+        "@start <a> <b>\n<a>::='x'",
+        "@start <a>\n@start <b>\n<a>::='x'\n<b>::='x'",
+    ] {
+        assert!(
+            Grammar::new(grammar, tiny_vocabulary(), 0).is_err(),
+            "{grammar}"
+        );
+    }
+    // The blanked directive keeps the line numbers of syntax errors.
+    let error = Grammar::new("@start <a>\n<a>::='x'\n<b>:='c'", tiny_vocabulary(), 0).unwrap_err();
+    assert_eq!(error.downcast_ref::<ParseError>().unwrap().line, 3);
+}
@@ -0,0 +1,99 @@
+mod common;
+
+use bnf_sampler::sampler::{PossibleTokensResult, Sampler, SamplerConfig};
+use bnf_sampler::vocabulary::Vocabulary;
+use common::{new_sampler, tiny_vocabulary};
+use rustc_hash::FxHashMap;
+
+const GRAMMARS: &[&str] = &[
+    r#"<start>::='{"'<key>'":'<value>'}'
+<key>::=<except!('"')>|<except!('"')><key>
+<value>::='true'|'false'|<digits>
+<digits>::=<digit>|<digit><digits>
+<digit>::='0'|'1'|'2'|'3'|'4'|'5'|'6'|'7'|'8'|'9'"#,
+    "<start>::=<sequence>\n<sequence>::=<base>|<base><sequence>\n<base>::=\"A\"|\"C\"|\"G\"|\"T\"",
+    "<start>::=<v><v>'.'\n<v>::='a'|'ab'|'abc'|<w>\n<w>::='b'|'bc'",
+    "<start>::='[' <v> ']'\n<v>::='true'|'false'|'[' <v> ']'|'true, '<v>",
+    "<start>::='abc'<number!(0, 250)>'.'",
+];
+
+fn accept_tokens(sampler: &mut Sampler, vocabulary: &Vocabulary, tokens: &[&str]) {
+    for token in tokens {
+        let token_id = vocabulary.token_to_id[token.as_bytes()];
+        assert!(matches!(
+            sampler.all_possible_next_tokens(Some(token_id)).unwrap(),
+            PossibleTokensResult::Continue(_)
+        ));
+    }
+}
+
+#[test]
+fn segmentations_of_the_same_bytes_have_the_same_hash() {
+    let vocabulary = tiny_vocabulary();
+    for (grammar, segmentations) in [
+        (
+            GRAMMARS[0],
+            [
+                &["{\"", "ab", "c"][..],
+                &["{", "\"", "a", "b", "c"],
+                &["{\"", "abc"],
+            ],
+        ),
+        (
+            GRAMMARS[4],
+            [
+                &["ab", "c", "12"][..],
+                &["abc", "1", "2"],
+                &["a", "b", "c", "12"],
+            ],
+        ),
+    ] {
+        let hashes: Vec<u64> = segmentations
+            .iter()
+            .map(|tokens| {
+                let mut sampler = new_sampler(grammar, &vocabulary, SamplerConfig::new());
+                accept_tokens(&mut sampler, &vocabulary, tokens);
+                sampler.state_hash()
+            })
+            .collect();
+        assert!(hashes.iter().all(|x| *x == hashes[0]), "{grammar}");
+    }
+}
+
+#[test]
+fn reset_restores_the_initial_hash() {
+    let vocabulary = tiny_vocabulary();
+    let mut sampler = new_sampler(GRAMMARS[0], &vocabulary, SamplerConfig::new());
+    sampler.all_possible_next_tokens(None).unwrap();
+    let initial = sampler.state_hash();
+    accept_tokens(&mut sampler, &vocabulary, &["{\"", "a"]);
+    assert_ne!(sampler.state_hash(), initial);
+    sampler.reset();
+    sampler.all_possible_next_tokens(None).unwrap();
+    assert_eq!(sampler.state_hash(), initial);
+}
+
+/// Walk every grammar through different tokens and check that states with different masks never share a hash.
+#[test]
+fn states_with_different_masks_have_different_hashes() {
+    let vocabulary = tiny_vocabulary();
+    let mut states = 0;
+    for grammar in GRAMMARS {
+        let mut masks: FxHashMap<u64, Vec<usize>> = FxHashMap::default();
+        for choice in 0..5 {
+            let mut sampler = new_sampler(grammar, &vocabulary, SamplerConfig::new());
+            let mut token_id = None;
+            for step in 0..20 {
+                let mask: Vec<usize> = match sampler.all_possible_next_tokens(token_id).unwrap() {
+                    PossibleTokensResult::Continue(token_ids) => token_ids.iter().collect(),
+                    _ => break,
+                };
+                let expected = masks.entry(sampler.state_hash()).or_insert(mask.clone());
+                assert_eq!(*expected, mask, "{grammar}");
+                token_id = Some(mask[(step + choice) * 3 % mask.len()] as u32);
+            }
+        }
+        states += masks.len();
+    }
+    assert!(states > 20, "{states}");
+}
@@ -0,0 +1,39 @@
+mod common;
+
+use bnf_sampler::sampler::SamplerConfig;
+use common::{assert_same_masks, new_sampler, tiny_vocabulary};
+
+const VALUE: &str = "<start>::='['<value>']'
+<value>::='null'|'true'|'false'|<number>
+<number>::=<digit>|<digit><number>
+<digit>::='0'|'1'|'2'";
+
+#[test]
+fn split_terminals_match_the_manual_split() {
+    let expanded = "<start>::='['<value>']'
+<value>::=<literal>|<number>
+<literal>::='null'|'true'|'false'
+<number>::=<digit>|<digit><number>
+<digit>::='0'|'1'|'2'";
+    for tokens in [
+        &["[", "null", "]"][..],
+        &["[", "t", "r", "u", "e", "]"],
+        &["[", "f", "a", "l", "s", "e", "]"],
+        &["[", "1", "20", "]"],
+    ] {
+        assert_same_masks(VALUE, expanded, tokens);
+    }
+}
+
+#[test]
+fn stacks_show_the_split_terminals_by_name() {
+    let vocabulary = tiny_vocabulary();
+    let mut sampler = new_sampler(VALUE, &vocabulary, SamplerConfig::new());
+    sampler
+        .all_possible_next_tokens(Some(vocabulary.token_to_id["[".as_bytes()]))
+        .unwrap();
+    // The single terminal alternatives of <value> are matched by a nonterminal named after it.
+    let stacks = sampler.to_string();
+    assert!(stacks.contains("[']', <terminals!(value)>]"), "{stacks}");
+    assert!(stacks.contains("[']', <digit>]"), "{stacks}");
+}
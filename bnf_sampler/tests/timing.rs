@@ -0,0 +1,112 @@
+mod common;
+
+use bnf_sampler::sampler::{CacheMode, PossibleTokensResult, SamplerConfig};
+use common::{new_sampler, tiny_vocabulary};
+use std::time::Duration;
+
+const GRAMMAR: &str = r#"<start>::='{"'<key>'":'<value>'}'
+<key>::=<except!('"')>|<except!('"')><key>
+<value>::='true'|'false'|<digits>
+<digits>::=<digit>|<digit><digits>
+<digit>::='0'|'1'|'2'|'3'|'4'|'5'|'6'|'7'|'8'|'9'"#;
+
+#[test]
+fn phases_sum_to_about_the_total() {
+    let vocabulary = tiny_vocabulary();
+    for cache_mode in [CacheMode::Full, CacheMode::None] {
+        let mut sampler = new_sampler(
+            GRAMMAR,
+            &vocabulary,
+            SamplerConfig::new()
+                .cache_mode(cache_mode)
+                .collect_timing(true),
+        );
+        let mut input_token_id = None;
+        for token in ["{\"", "a", "\":", "12"] {
+            let mask_size = match sampler.all_possible_next_tokens(input_token_id).unwrap() {
+                PossibleTokensResult::Continue(token_ids) => token_ids.len(),
+                result => panic!("{result:?}"),
+            };
+            let timing = *sampler.last_step_timing().unwrap();
+            assert!(timing.phases() <= timing.total, "{timing:?}");
+            assert!(
+                timing.total - timing.phases() <= timing.total / 2 + Duration::from_millis(1),
+                "{timing:?}"
+            );
+            assert!(
+                timing.tokens_accepted <= timing.tokens_checked,
+                "{timing:?}"
+            );
+            assert!(timing.tokens_accepted <= mask_size, "{timing:?}");
+            if timing.tokens_checked > 0 {
+                assert!(
+                    timing.arena_allocations >= timing.tokens_checked,
+                    "{timing:?}"
+                );
+            }
+            input_token_id = Some(vocabulary.token_to_id[token.as_bytes()]);
+        }
+    }
+}
+
+#[test]
+fn cache_hits_skip_the_scan() {
+    let vocabulary = tiny_vocabulary();
+    let mut sampler = new_sampler(
+        GRAMMAR,
+        &vocabulary,
+        SamplerConfig::new().collect_timing(true),
+    );
+    sampler.all_possible_next_tokens(None).unwrap();
+    assert!(sampler.last_step_timing().unwrap().tokens_checked > 0);
+    sampler.reset();
+    assert_eq!(sampler.last_step_timing(), None);
+    sampler.all_possible_next_tokens(None).unwrap();
+    let timing = sampler.last_step_timing().unwrap();
+    assert_eq!(timing.tokens_checked, 0);
+    assert_eq!(timing.scan, Duration::ZERO);
+}
+
+#[test]
+fn disabled_timing_is_none() {
+    let vocabulary = tiny_vocabulary();
+    let mut sampler = new_sampler(GRAMMAR, &vocabulary, SamplerConfig::new());
+    sampler.all_possible_next_tokens(None).unwrap();
+    assert_eq!(sampler.last_step_timing(), None);
+}
+
+#[test]
+fn stack_to_bytes_cache_counts() {
+    let vocabulary = tiny_vocabulary();
+    // `10`, `20`, `30` and `00` all leave `0` to match after the first digit.
+    let grammar = "<start>::=<digits>'!'\n<digits>::=<digit>|<digit><digits>\n<digit>::=[0-9]";
+    let mut masks = vec![];
+    for enabled in [true, false] {
+        let mut sampler = new_sampler(
+            grammar,
+            &vocabulary,
+            SamplerConfig::new()
+                .cache_mode(CacheMode::TrieNodeOnly)
+                .stack_to_bytes_cache(enabled)
+                .collect_timing(true),
+        );
+        let mut input_token_id = None;
+        let (mut hits, mut misses) = (0, 0);
+        for token in ["12", "42", "!"] {
+            match sampler.all_possible_next_tokens(input_token_id).unwrap() {
+                PossibleTokensResult::Continue(token_ids) => masks.push(token_ids.clone()),
+                result => panic!("{result:?}"),
+            }
+            let timing = sampler.last_step_timing().unwrap();
+            hits += timing.stack_to_bytes_cache_hits;
+            misses += timing.stack_to_bytes_cache_misses;
+            input_token_id = Some(vocabulary.token_to_id[token.as_bytes()]);
+        }
+        match enabled {
+            true => assert!(hits > 0 && misses > 0, "{hits} hits, {misses} misses"),
+            false => assert_eq!((hits, misses), (0, 0)),
+        }
+    }
+    let (on, off) = masks.split_at(masks.len() / 2);
+    assert_eq!(on, off);
+}
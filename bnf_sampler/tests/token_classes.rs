@@ -0,0 +1,127 @@
+mod common;
+
+use bnf_sampler::grammar::{Grammar, GrammarBuildOptions};
+use bnf_sampler::sampler::{AcceptTokenResult, Sampler, SamplerConfig};
+use bnf_sampler::vocabulary::{TokenClass, Vocabulary};
+use common::{tiny_vocabulary, validates};
+use std::sync::Arc;
+
+fn members(vocabulary: &Vocabulary, class: TokenClass) -> Vec<&[u8]> {
+    vocabulary
+        .classify()
+        .get(class)
+        .iter()
+        .map(|x| &vocabulary.id_to_token[&(x as u32)][..])
+        .collect()
+}
+
+fn id(vocabulary: &Vocabulary, token: &str) -> u32 {
+    vocabulary.token_to_id[token.as_bytes()]
+}
+
+#[test]
+fn classes_of_the_fixture_vocabulary() {
+    let vocabulary = tiny_vocabulary();
+    let numeric = members(&vocabulary, TokenClass::NumericOnly);
+    assert_eq!(numeric.len(), 16);
+    assert!(numeric.contains(&&b"42"[..]) && numeric.contains(&&b"7"[..]));
+    let alphabetic = members(&vocabulary, TokenClass::AlphabeticOnly);
+    for token in ["Hello", "abc", "END", "x"] {
+        assert!(alphabetic.contains(&token.as_bytes()), "{token}");
+    }
+    for token in [" the", "{\"", "10", "\u{7f}"] {
+        assert!(!alphabetic.contains(&token.as_bytes()), "{token}");
+    }
+    let whitespace = members(&vocabulary, TokenClass::WhitespaceOnly);
+    for token in [" ", "\t", "\n\n"] {
+        assert!(whitespace.contains(&token.as_bytes()), "{token:?}");
+    }
+    assert!(!whitespace.contains(&&b" the"[..]));
+    let newline = members(&vocabulary, TokenClass::ContainsNewline);
+    assert_eq!(newline, [&b"\n"[..], &b"\n\n"[..]]);
+    let punctuation = members(&vocabulary, TokenClass::ContainsPunctuation);
+    assert!(punctuation.contains(&&b"\":["[..]) && punctuation.contains(&&b" ="[..]));
+    assert!(!punctuation.contains(&&b"abc"[..]));
+    let space = members(&vocabulary, TokenClass::StartsWithSpace);
+    assert!(space.iter().all(|x| x[0] == b' '));
+    assert!(space.contains(&&b" London"[..]));
+    let non_utf8 = members(&vocabulary, TokenClass::NonUtf8);
+    assert_eq!(non_utf8.len(), 128);
+    assert!(non_utf8.iter().all(|x| x[0] >= 0x80));
+}
+
+#[test]
+fn classes_of_a_token() {
+    let vocabulary = tiny_vocabulary();
+    let classes = vocabulary.classify();
+    assert_eq!(
+        classes.classes_of(id(&vocabulary, " ")).collect::<Vec<_>>(),
+        [TokenClass::WhitespaceOnly, TokenClass::StartsWithSpace]
+    );
+    assert_eq!(
+        classes
+            .classes_of(id(&vocabulary, " {"))
+            .collect::<Vec<_>>(),
+        [TokenClass::ContainsPunctuation, TokenClass::StartsWithSpace]
+    );
+    assert!(std::ptr::eq(classes, vocabulary.classify()));
+}
+
+#[test]
+fn token_class_nonterminals() {
+    let vocabulary = tiny_vocabulary();
+    let grammar = "<start>::=<token_class!(numeric_only)>'!'";
+    assert!(validates(grammar, &vocabulary, b"42!"));
+    assert!(!validates(grammar, &vocabulary, b"a!"));
+    let grammar = Grammar::with_options(
+        "<start>::=<word>'!'",
+        vocabulary.clone(),
+        GrammarBuildOptions::new().add_token_class("word", TokenClass::AlphabeticOnly),
+    )
+    .unwrap();
+    let mut sampler = Sampler::with_config(
+        grammar,
+        "start".to_string(),
+        vocabulary.clone(),
+        SamplerConfig::new(),
+    )
+    .unwrap();
+    assert_eq!(
+        sampler.accept_bytes(b"Hello!").unwrap(),
+        AcceptTokenResult::End
+    );
+    // The token classes are kept in the schema of trace bundles.
+    sampler.reset();
+    for token in ["Hello", "!"] {
+        sampler
+            .accept_a_token(Some(id(&vocabulary, token)))
+            .unwrap();
+    }
+    let replay = sampler.trace_bundle().unwrap().replay().unwrap();
+    assert_eq!(replay.result, AcceptTokenResult::End);
+}
+
+#[test]
+fn invalid_token_classes_are_rejected() {
+    let vocabulary: Arc<Vocabulary> = tiny_vocabulary();
+    let error = Grammar::new("<start>::=<token_class!(letters)>", vocabulary.clone(), 0)
+        .unwrap_err()
+        .to_string();
+    assert!(
+        error.starts_with(
+            "<token_class!(letters)> is invalid because \"letters\" is not a token class"
+        ),
+        "{error}"
+    );
+    let error = Grammar::with_options(
+        "<start>::=<word>\n<word>::='a'",
+        vocabulary,
+        GrammarBuildOptions::new().add_token_class("word", TokenClass::AlphabeticOnly),
+    )
+    .unwrap_err()
+    .to_string();
+    assert_eq!(
+        error,
+        "<word> is a token class and cannot be defined in the BNF schema."
+    );
+}
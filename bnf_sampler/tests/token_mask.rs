@@ -0,0 +1,31 @@
+//! Run with and without the `roaring` feature, so both implementations of `TokenMask` behave like a set.
+use bnf_sampler::mask::TokenMask;
+use std::collections::BTreeSet;
+
+#[test]
+fn token_mask_behaves_like_a_set() {
+    let mut mask = TokenMask::with_capacity(100);
+    let mut expected = BTreeSet::new();
+    assert!(mask.is_empty());
+    for id in [5, 3, 70_000, 5, 64, 0, 1 << 20] {
+        assert_eq!(mask.insert(id), expected.insert(id), "{id}");
+    }
+    assert_eq!(mask.len(), expected.len());
+    assert!(mask.iter().eq(expected.iter().copied()));
+    assert!(mask.contains(70_000) && !mask.contains(6));
+    assert!(mask.capacity() > 1 << 20);
+
+    let other: TokenMask = [6, 5, 200_000].into_iter().collect();
+    mask.union_with(&other);
+    expected.extend([6, 5, 200_000]);
+    assert!(mask.iter().eq(expected.iter().copied()));
+    assert!(mask.to_bit_set().iter().eq(expected.iter().copied()));
+    assert!(mask.memory_bytes() > 0);
+
+    let mut extended = TokenMask::new();
+    extended.extend(expected.iter().copied());
+    assert_eq!(extended, mask);
+    mask.clear();
+    assert!(mask.is_empty() && mask.iter().next().is_none());
+    assert_ne!(extended, mask);
+}
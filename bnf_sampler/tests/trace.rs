@@ -0,0 +1,65 @@
+mod common;
+
+use bnf_sampler::sampler::AcceptTokenResult;
+use bnf_sampler::trace::{SplitBranch, SplitNode};
+use common::tiny_sampler;
+
+const GRAMMAR: &str = "<start>::=<a>'!'|<b>'?'|'q'
+<a>::='xy'
+<b>::='x'<c>|'x'<d>
+<c>::='y'|'yz'
+<d>::=<c>'w'";
+
+fn branch(expansion: &str, splits: Vec<SplitNode>) -> SplitBranch {
+    SplitBranch {
+        expansion: expansion.to_string(),
+        splits,
+    }
+}
+
+#[test]
+fn split_points_are_reported() {
+    let (mut sampler, _) = tiny_sampler(GRAMMAR);
+    let report = sampler.trace_bytes(b"xy").unwrap();
+    assert_eq!(report.result, AcceptTokenResult::Continue);
+    // <a>'!', and <c> either ending or continuing with 'z' under both expansions of <b>.
+    assert_eq!(report.stack_count, 5);
+    assert!(!report.truncated);
+    assert_eq!(
+        report.splits,
+        vec![SplitNode {
+            nonterminal: "start".to_string(),
+            byte_offset: 0,
+            branches: vec![
+                branch("<a>'!'", vec![]),
+                branch(
+                    "<b>'?'",
+                    vec![SplitNode {
+                        nonterminal: "b".to_string(),
+                        byte_offset: 0,
+                        branches: vec![branch("'x'<c>", vec![]), branch("'x'<d>", vec![])],
+                    }]
+                ),
+            ],
+        }]
+    );
+}
+
+#[test]
+fn unambiguous_and_rejected_bytes_have_no_split_points() {
+    let (mut sampler, _) = tiny_sampler(GRAMMAR);
+    let report = sampler.trace_bytes(b"q").unwrap();
+    assert_eq!(report.result, AcceptTokenResult::End);
+    assert!(report.splits.is_empty());
+    let report = sampler.trace_bytes(b"k").unwrap();
+    assert_eq!(report.result, AcceptTokenResult::Failed);
+    assert_eq!(report.stack_count, 0);
+    assert!(report.splits.is_empty());
+}
+
+#[test]
+fn tracing_leaves_the_stacks_untouched() {
+    let (mut sampler, _) = tiny_sampler(GRAMMAR);
+    sampler.trace_bytes(b"xy").unwrap();
+    assert_eq!(sampler.accept_bytes(b"q").unwrap(), AcceptTokenResult::End);
+}
@@ -0,0 +1,43 @@
+mod common;
+
+use bnf_sampler::grammar::Grammar;
+use bnf_sampler::sampler::{Sampler, SamplerConfig};
+use common::tiny_vocabulary;
+
+fn error(grammar: &str) -> String {
+    Grammar::new(grammar, tiny_vocabulary(), 0)
+        .unwrap_err()
+        .to_string()
+}
+
+#[test]
+fn undefined_nonterminals_are_listed_with_their_users() {
+    assert_eq!(
+        error("<start>::=<valu>|<pair>\n<pair>::=<key>':'<valu>\n<value>::='1'"),
+        "The BNF schema uses undefined nonterminals: <key> in <pair>; <valu> in <pair>, <start> (did you mean <value>?)."
+    );
+}
+
+#[test]
+fn special_nonterminals_are_defined() {
+    let vocabulary = tiny_vocabulary();
+    let grammar = "<start>::=<any!>|<except!('a')>|<except!([value])>\n<value>::='b'";
+    assert!(Grammar::new(grammar, vocabulary, 0).is_ok());
+}
+
+#[test]
+fn misspelled_start_is_suggested() {
+    let vocabulary = tiny_vocabulary();
+    let grammar = Grammar::new("<start>::=<value>\n<value>::='b'", vocabulary.clone(), 0).unwrap();
+    let error = Sampler::with_config(
+        grammar,
+        "strat".to_string(),
+        vocabulary,
+        SamplerConfig::new(),
+    )
+    .unwrap_err();
+    assert_eq!(
+        error.to_string(),
+        "Start_nonterminal strat is not defined in the BNF schema. The defined nonterminals are <start>, <value>. Did you mean <start>?"
+    );
+}
@@ -0,0 +1,92 @@
+mod common;
+
+use bnf_sampler::sampler::{CacheMode, PossibleTokensResult, Sampler, SamplerConfig, VisitOutcome};
+use common::{new_sampler, tiny_vocabulary};
+
+const GRAMMAR: &str = r#"<start>::='{"'<key>'":'<value>'}'
+<key>::=<except!('"')>|<except!('"')><key>
+<value>::='true'|'false'|<digits>
+<digits>::=<digit>|<digit><digits>
+<digit>::='0'|'1'|'2'|'3'|'4'|'5'|'6'|'7'|'8'|'9'"#;
+
+fn visit(sampler: &mut Sampler, token_id: Option<u32>) -> (VisitOutcome, Vec<u32>) {
+    let mut visited = vec![];
+    let outcome = sampler
+        .visit_allowed_tokens(token_id, &mut |id| visited.push(id))
+        .unwrap();
+    (outcome, visited)
+}
+
+fn mask(sampler: &mut Sampler, token_id: Option<u32>) -> Option<Vec<u32>> {
+    match sampler.all_possible_next_tokens(token_id).unwrap() {
+        PossibleTokensResult::Continue(token_ids) => {
+            Some(token_ids.iter().map(|x| x as u32).collect())
+        }
+        _ => None,
+    }
+}
+
+/// Visit the possible tokens along `tokens` twice, so the second pass hits the cache in [`CacheMode::Full`].
+fn assert_visits_match(cache_mode: CacheMode) {
+    let vocabulary = tiny_vocabulary();
+    let config = SamplerConfig::new().cache_mode(cache_mode);
+    let mut visiting = new_sampler(GRAMMAR, &vocabulary, config.clone());
+    let mut reference = new_sampler(GRAMMAR, &vocabulary, config);
+    let tokens = ["{\"", "ab", "c", "\":", "12", "}"];
+    for pass in 0..2 {
+        visiting.reset();
+        reference.reset();
+        let mut token_id = None;
+        for token in tokens.iter().map(Some).chain([None]) {
+            let (outcome, mut visited) = visit(&mut visiting, token_id);
+            let expected = mask(&mut reference, token_id);
+            match expected {
+                Some(expected) => {
+                    // The first pass may revisit a state too, like after `ab` and `c` inside the key.
+                    match (cache_mode, pass) {
+                        (CacheMode::Full, 0) => {
+                            assert!(matches!(outcome, VisitOutcome::Continue { .. }))
+                        }
+                        _ => assert_eq!(
+                            outcome,
+                            VisitOutcome::Continue {
+                                cached: cache_mode == CacheMode::Full
+                            },
+                            "{token:?}"
+                        ),
+                    }
+                    let len = visited.len();
+                    visited.sort_unstable();
+                    visited.dedup();
+                    assert_eq!(visited.len(), len, "an id is visited twice");
+                    assert_eq!(visited, expected, "{token:?}");
+                }
+                None => {
+                    assert_eq!(outcome, VisitOutcome::End);
+                    assert!(visited.is_empty());
+                }
+            }
+            token_id = token.map(|x| vocabulary.token_to_id[x.as_bytes()]);
+        }
+    }
+}
+
+#[test]
+fn visited_tokens_match_the_possible_tokens() {
+    for cache_mode in [CacheMode::Full, CacheMode::TrieNodeOnly, CacheMode::None] {
+        assert_visits_match(cache_mode);
+    }
+}
+
+#[test]
+fn rejected_token_visits_nothing() {
+    let vocabulary = tiny_vocabulary();
+    let mut sampler = new_sampler(GRAMMAR, &vocabulary, SamplerConfig::new());
+    visit(&mut sampler, None);
+    let (outcome, visited) = visit(
+        &mut sampler,
+        Some(vocabulary.token_to_id[b"yes".as_slice()]),
+    );
+    assert_eq!(outcome, VisitOutcome::InputTokenRejected);
+    assert!(visited.is_empty());
+}
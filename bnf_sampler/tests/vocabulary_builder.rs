@@ -0,0 +1,93 @@
+mod common;
+
+use bnf_sampler::vocabulary::{Vocabulary, VocabularyBuilder, DEFAULT_MAX_TOKEN_BYTES};
+use common::tiny_vocabulary;
+use itertools::Itertools;
+
+fn assert_same(a: &Vocabulary, b: &Vocabulary) {
+    assert_eq!(a.id_to_token, b.id_to_token);
+    assert_eq!(a.id_to_token_string, b.id_to_token_string);
+    assert_eq!(
+        a.token_to_id.iter().collect_vec(),
+        b.token_to_id.iter().collect_vec()
+    );
+    assert_eq!(a.is_remapped(), b.is_remapped());
+    for id in a.id_to_token.keys() {
+        assert_eq!(a.aliases(*id), b.aliases(*id), "{id}");
+    }
+}
+
+#[test]
+fn streamed_tokens_equal_the_maps() {
+    let expected = tiny_vocabulary();
+    let mut builder = VocabularyBuilder::new(DEFAULT_MAX_TOKEN_BYTES);
+    let ids = expected.id_to_token.keys().copied().sorted().collect_vec();
+    let (single, batch) = ids.split_at(ids.len() / 2);
+    // The order tokens arrive in does not matter.
+    for id in single.iter().rev() {
+        builder
+            .add_token(
+                *id,
+                &expected.id_to_token[id],
+                Some(&expected.id_to_token_string[id]),
+            )
+            .unwrap();
+    }
+    let tokens = batch
+        .iter()
+        .map(|id| expected.id_to_token[id].as_slice())
+        .collect_vec();
+    builder.add_batch(batch, &tokens).unwrap();
+    for id in batch {
+        builder
+            .add_token_string(*id, &expected.id_to_token_string[id])
+            .unwrap();
+    }
+    assert_eq!(builder.len(), ids.len());
+    assert_same(&builder.finish().unwrap(), &expected);
+}
+
+#[test]
+fn duplicate_bytes_become_aliases_like_the_maps() {
+    let tokens: [(u32, &[u8]); 4] = [(7, b"hi"), (2, b"hi"), (9, b"hi"), (3, b"!")];
+    let mut builder = VocabularyBuilder::new(DEFAULT_MAX_TOKEN_BYTES);
+    for (id, token) in tokens {
+        builder.add_token(id, token, None).unwrap();
+    }
+    let streamed = builder.finish().unwrap();
+    let expected = Vocabulary::new(
+        tokens.iter().map(|(id, x)| (*id, x.to_vec())).collect(),
+        Default::default(),
+        DEFAULT_MAX_TOKEN_BYTES,
+    )
+    .unwrap();
+    assert_eq!(streamed.token_to_id.get(&b"hi"[..]), Some(&9));
+    assert_eq!(streamed.aliases(9), &[2, 7]);
+    assert_same(&streamed, &expected);
+}
+
+#[test]
+fn invalid_tokens_name_their_id() {
+    let mut builder = VocabularyBuilder::new(4);
+    builder.add_token(1, b"a", None).unwrap();
+    assert_eq!(
+        builder.add_token(1, b"b", None).unwrap_err().to_string(),
+        "Token id 1 is added more than once."
+    );
+    assert_eq!(
+        builder
+            .add_batch(&[2, 3], &[b"ok", b"too long"])
+            .unwrap_err()
+            .to_string(),
+        "Token id 3 is 8 bytes long, which exceeds the maximum of 4 bytes."
+    );
+    assert_eq!(
+        builder.add_batch(&[4], &[]).unwrap_err().to_string(),
+        "The batch has 1 token ids but 0 tokens."
+    );
+    assert_eq!(
+        builder.add_token_string(5, "e").unwrap_err().to_string(),
+        "Token id 5 has no token."
+    );
+    assert_eq!(builder.len(), 2);
+}
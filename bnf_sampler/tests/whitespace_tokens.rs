@@ -0,0 +1,188 @@
+mod common;
+
+use bnf_sampler::grammar::{Grammar, GrammarBuildOptions};
+use bnf_sampler::sampler::{AcceptTokenResult, PossibleTokensResult, Sampler, SamplerConfig};
+use bnf_sampler::vocabulary::{Vocabulary, DEFAULT_MAX_TOKEN_BYTES};
+use rustc_hash::FxHashMap;
+use std::sync::Arc;
+
+/// Single printable bytes, words and the whitespace tokens of real tokenizers,
+/// like a 16 space indent and tokens ending one line and indenting the next.
+fn whitespace_vocabulary() -> Arc<Vocabulary> {
+    let mut tokens: Vec<String> = (b' '..=b'~').map(|x| (x as char).to_string()).collect();
+    tokens.extend(
+        [
+            "\n",
+            "\t",
+            "\r\n",
+            "\n\n",
+            "\n\n\n\n",
+            "\t ",
+            "  ",
+            "    ",
+            "                ",
+            "\n  ",
+            "\n    ",
+            ":\n",
+            ": ",
+            ":\n  ",
+            " -",
+            "- ",
+            "name",
+            "items",
+            "ab",
+            "x\n",
+        ]
+        .map(String::from),
+    );
+    tokens.dedup();
+    let id_to_token: FxHashMap<u32, Vec<u8>> = tokens
+        .iter()
+        .enumerate()
+        .map(|(i, x)| (i as u32, x.as_bytes().to_vec()))
+        .collect();
+    let id_to_token_string = tokens
+        .into_iter()
+        .enumerate()
+        .map(|(i, x)| (i as u32, x))
+        .collect();
+    Arc::new(Vocabulary::new(id_to_token, id_to_token_string, DEFAULT_MAX_TOKEN_BYTES).unwrap())
+}
+
+/// A YAML like document ending with `...`, where each nesting level is indented by two more spaces.
+const INDENTED: &str = r#"<start>::=<entries>'...'
+<entries>::=<entry>|<entry><entries>
+<entry>::=<key>':'<block>|<key>': '<word>'\n'
+<block>::='\n  - '<word>'\n'|'\n  - '<word><items>'\n'|'\n    '<key>': '<word>'\n'
+<items>::='\n  - '<word>|'\n  - '<word><items>
+<key>::='name'|'items'
+<word>::='a'|'b'|'ab'|'x'"#;
+
+fn mask(sampler: &mut Sampler, token_id: Option<u32>) -> Option<Vec<u32>> {
+    match sampler.all_possible_next_tokens(token_id).unwrap() {
+        PossibleTokensResult::Continue(token_ids) => {
+            Some(token_ids.iter().map(|x| x as u32).collect())
+        }
+        _ => None,
+    }
+}
+
+/// The tokens a clone of the sampler accepts, one by one.
+fn brute_force_mask(sampler: &Sampler, vocabulary: &Vocabulary) -> Vec<u32> {
+    let mut token_ids: Vec<u32> = vocabulary
+        .id_to_token
+        .keys()
+        .copied()
+        .filter(|id| {
+            let mut clone = sampler.clone();
+            clone.accept_a_token(Some(*id)).unwrap() != AcceptTokenResult::Failed
+        })
+        .collect();
+    token_ids.sort_unstable();
+    token_ids
+}
+
+/// Follow `text` token by token, always taking the longest allowed token that is a prefix of the rest,
+/// and check every mask against accepting each token.
+fn walk(grammar: Arc<Grammar>, vocabulary: &Arc<Vocabulary>, text: &str) -> Vec<String> {
+    let mut sampler = Sampler::with_config(
+        grammar,
+        "start".to_string(),
+        vocabulary.clone(),
+        SamplerConfig::new(),
+    )
+    .unwrap();
+    let mut rest = text.as_bytes();
+    let mut token_id = None;
+    let mut tokens = vec![];
+    while let Some(mask) = mask(&mut sampler, token_id) {
+        assert_eq!(
+            mask,
+            brute_force_mask(&sampler, vocabulary),
+            "after {tokens:?}"
+        );
+        let Some(id) = mask
+            .iter()
+            .copied()
+            .filter(|id| rest.starts_with(&vocabulary.id_to_token[id]))
+            .max_by_key(|id| vocabulary.id_to_token[id].len())
+        else {
+            break;
+        };
+        rest = &rest[vocabulary.id_to_token[&id].len()..];
+        tokens.push(vocabulary.id_to_token_string[&id].clone());
+        token_id = Some(id);
+    }
+    assert!(
+        rest.is_empty(),
+        "{:?} is left after {tokens:?}",
+        String::from_utf8_lossy(rest)
+    );
+    tokens
+}
+
+#[test]
+fn whitespace_tokens_span_several_grammar_positions() {
+    let vocabulary = whitespace_vocabulary();
+    let grammar = Grammar::new(INDENTED, vocabulary.clone(), 0).unwrap();
+    let text = "name:\n    items: ab\nitems:\n  - a\n  - b\nname: x\n...";
+    let tokens = walk(grammar, &vocabulary, text);
+    // `:\n  ` ends a key and indents the next line, and `x\n` ends a value and the line.
+    assert!(tokens.contains(&":\n  ".to_string()), "{tokens:?}");
+    assert!(tokens.contains(&"\n  ".to_string()), "{tokens:?}");
+    assert!(tokens.contains(&"x\n".to_string()), "{tokens:?}");
+}
+
+#[test]
+fn runs_of_whitespace_tokens() {
+    let vocabulary = whitespace_vocabulary();
+    let grammar = Grammar::new(
+        "<start>::='a'<gap>'b'\n<gap>::='\n\n\n\n\n'|'                 '|'\t  \t'",
+        vocabulary.clone(),
+        0,
+    )
+    .unwrap();
+    for text in ["a\n\n\n\n\nb", "a                 b", "a\t  \tb"] {
+        walk(grammar.clone(), &vocabulary, text);
+    }
+}
+
+#[test]
+fn collapsed_whitespace_runs_accept_any_width() {
+    let vocabulary = whitespace_vocabulary();
+    let options = GrammarBuildOptions::new().collapse_whitespace_runs(true);
+    let grammar = Grammar::with_options(INDENTED, vocabulary.clone(), options).unwrap();
+    let text = "name:\n\n\n\n\n                items:\t ab\r\nitems: x\n\n...";
+    let tokens = walk(grammar.clone(), &vocabulary, text);
+    assert!(
+        tokens.contains(&"                ".to_string()),
+        "{tokens:?}"
+    );
+    assert!(tokens.contains(&"\n\n\n\n".to_string()), "{tokens:?}");
+    assert!(tokens.contains(&"\t ".to_string()), "{tokens:?}");
+    // Runs are one or more whitespace bytes, so the whitespace cannot be dropped.
+    let mut sampler = Sampler::with_config(
+        grammar,
+        "start".to_string(),
+        vocabulary.clone(),
+        SamplerConfig::new(),
+    )
+    .unwrap();
+    assert_eq!(
+        sampler.accept_bytes(b"name:ab").unwrap(),
+        AcceptTokenResult::Failed
+    );
+    // Without the option, the width is exact.
+    let grammar = Grammar::new(INDENTED, vocabulary.clone(), 0).unwrap();
+    let mut sampler = Sampler::with_config(
+        grammar,
+        "start".to_string(),
+        vocabulary,
+        SamplerConfig::new(),
+    )
+    .unwrap();
+    assert_eq!(
+        sampler.accept_bytes(b"name:  ab").unwrap(),
+        AcceptTokenResult::Failed
+    );
+}
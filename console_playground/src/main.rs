@@ -1,7 +1,14 @@
-use bnf_sampler::sampler::{PossibleTokensResult, Sampler};
-use bnf_sampler::utils::U8ArrayWrapper;
-use bnf_sampler::{grammar, utils};
-use clap::Parser;
+use bnf_sampler::bundle::TraceBundle;
+use bnf_sampler::differential::compare_samplers;
+use bnf_sampler::grammar::Severity;
+use bnf_sampler::prelude::*;
+use bnf_sampler::vocabulary::DEFAULT_MAX_TOKEN_BYTES;
+use bnf_sampler::{fixtures, utils};
+use clap::error::ErrorKind;
+use clap::{CommandFactory, Parser, ValueEnum};
+use std::io::{self, Read};
+use std::path::Path;
+use std::sync::Arc;
 use std::time::Instant;
 use std::{fs, vec};
 /// Command line arguments
@@ -17,44 +24,286 @@ struct Args {
     /// to display input in bytes.
     #[arg(short, long, default_value_t = false,action = clap::ArgAction::Set)]
     input_display: bool,
-    /// set the arena capacity.
+    /// set the arena capacity. 0 means the capacity is estimated from the grammar and the vocabulary.
     #[arg(short, long, default_value_t = 1024*1024)]
     arena_capacity: usize,
-    /// set the temp arena capacity used to expand each except!(excepted_literals).
+    /// set the temp arena capacity used to expand each except!(excepted_literals). 0 means the capacity is estimated.
     #[arg(short, long, default_value_t = 1024)]
     grammar_arena_capacity: usize,
     /// enable stack to bytes cache. When a nonterminal directly expands to a lot of nonterminals and terminals, it may be slow.
     #[arg(short, long, default_value_t = true, action = clap::ArgAction::Set)]
     bytes_cache: bool,
-    /// set the initial nonterminal.
-    #[arg(short = 'n', long, default_value = "start")]
+    /// set the initial nonterminal. By default it is the one declared by `@start <name>` in the BNF schema, or start.
+    #[arg(short = 'n', long, default_value = "")]
     start_nonterminal: String,
+    /// set the cache mode. trie-node-only and none trade speed for bounded memory.
+    #[arg(short, long, value_enum, default_value_t = CacheModeArg::Full)]
+    cache_mode: CacheModeArg,
+    /// to collect mask size metrics and print a summary at the end of the session.
+    #[arg(short, long, default_value_t = false, action = clap::ArgAction::Set)]
+    metrics: bool,
+    /// to print the lint findings of the grammar and the nonterminals not reachable from the initial nonterminal.
+    #[arg(short, long, default_value_t = false, action = clap::ArgAction::Set)]
+    lint: bool,
+    /// to print the boundaries between terminals and token sets that some tokens cannot cross.
+    #[arg(long, default_value_t = false, action = clap::ArgAction::Set)]
+    check_boundaries: bool,
+    /// to use a vocabulary where every byte is a token instead of ./assets/vocab.txt.
+    #[arg(long, default_value_t = false, action = clap::ArgAction::Set, num_args = 0..=1, default_missing_value = "true")]
+    byte_level: bool,
+    /// the BNF schema file used instead of ./assets/grammar.bnf, or - to read it from stdin.
+    #[arg(long, conflicts_with = "grammar_text")]
+    grammar: Option<String>,
+    /// the BNF schema itself, e.g. "<start>::='yes'|'no'".
+    #[arg(long)]
+    grammar_text: Option<String>,
+    /// the vocabulary file used instead of ./assets/vocab.txt, or - to read it from stdin.
+    #[arg(long)]
+    vocab: Option<String>,
+    /// to print the diagnostics of the grammar from the initial nonterminal and exit, failing on errors.
+    #[arg(long, default_value_t = false, action = clap::ArgAction::Set, num_args = 0..=1, default_missing_value = "true")]
+    check: bool,
+    /// to print where the time of each step went.
+    #[arg(long, default_value_t = false, action = clap::ArgAction::Set)]
+    timing: bool,
+    /// to replay a trace bundle directory, then continue before the rejected token with the stacks displayed.
+    #[arg(long)]
+    replay: Option<String>,
+    /// to record a trace bundle in this directory when a token is rejected.
+    #[arg(long)]
+    record: Option<String>,
+    /// to compare the samplers of two SamplerConfig JSON files on the --script tokens and print where they diverge.
+    #[arg(long, num_args = 2, value_names = ["OLD", "NEW"], requires = "script")]
+    compare_config: Option<Vec<String>>,
+    /// the file of whitespace separated token ids used by --compare-config.
+    #[arg(long, requires = "compare_config")]
+    script: Option<String>,
 }
 
-fn main() {
-    let args = Args::parse();
-    println!("{:?}", args);
-    let input =
-        fs::read_to_string("./assets/grammar.bnf").expect("./assets/grammar.bnf should exist.");
-    let vocabulary = utils::read_rwkv_world_vocab("./assets/vocab.txt").unwrap();
-    let grammar =
-        grammar::Grammar::new(&input, vocabulary.clone(), args.grammar_arena_capacity).unwrap();
-    let mut machine = Sampler::new(
+#[derive(ValueEnum, Clone, Copy, Debug)]
+enum CacheModeArg {
+    Full,
+    TrieNodeOnly,
+    None,
+}
+
+impl From<CacheModeArg> for CacheMode {
+    fn from(value: CacheModeArg) -> Self {
+        match value {
+            CacheModeArg::Full => CacheMode::Full,
+            CacheModeArg::TrieNodeOnly => CacheMode::TrieNodeOnly,
+            CacheModeArg::None => CacheMode::None,
+        }
+    }
+}
+
+/// The content of the file at `path`, or of stdin when `path` is `-`.
+fn read_text(path: &str) -> String {
+    if path != "-" {
+        return fs::read_to_string(path).unwrap();
+    }
+    let mut text = String::new();
+    io::stdin().read_to_string(&mut text).unwrap();
+    text
+}
+
+/// The BNF schema and the vocabulary of the arguments, or of ./assets by default,
+/// or of the bundled fixtures when the assets are missing.
+fn read_inputs(args: &Args) -> (String, Arc<Vocabulary>) {
+    let input = match (&args.grammar_text, &args.grammar) {
+        (Some(text), _) => text.clone(),
+        (None, Some(path)) => read_text(path),
+        (None, None) => fs::read_to_string("./assets/grammar.bnf").unwrap_or_else(|_| {
+            println!(
+                "./assets/grammar.bnf is not found, so the bundled JSON object grammar is used."
+            );
+            fixtures::JSON_OBJECT_GRAMMAR.to_string()
+        }),
+    };
+    let vocabulary = if args.byte_level {
+        Arc::new(Vocabulary::byte_level())
+    } else if let Some(path) = args.vocab.as_deref() {
+        match path {
+            "-" => read_rwkv_world_vocab_from_reader(io::stdin().lock(), DEFAULT_MAX_TOKEN_BYTES)
+                .unwrap(),
+            path => read_rwkv_world_vocab(path).unwrap(),
+        }
+    } else if Path::new("./assets/vocab.txt").exists() {
+        read_rwkv_world_vocab("./assets/vocab.txt").unwrap()
+    } else {
+        println!("./assets/vocab.txt is not found, so the bundled fixture vocabulary is used.");
+        fixtures::vocabulary()
+    };
+    (input, vocabulary)
+}
+
+/// The vocabulary and the grammar of the arguments, see [`read_inputs`].
+fn new_grammar(args: &Args) -> (Arc<Vocabulary>, Arc<Grammar>) {
+    let (input, vocabulary) = read_inputs(args);
+    let grammar = Grammar::new(&input, vocabulary.clone(), args.grammar_arena_capacity).unwrap();
+    if args.lint {
+        for finding in grammar.lint() {
+            println!("{}", finding);
+        }
+        let start = match args.start_nonterminal.is_empty() {
+            true => grammar.start_nonterminal(),
+            false => &args.start_nonterminal,
+        };
+        for nonterminal in grammar.unreachable_nonterminals(start) {
+            println!("Warning: <{nonterminal}> is not reachable from <{start}>.");
+        }
+    }
+    if args.check_boundaries {
+        for conflict in grammar.boundary_conflicts(&vocabulary) {
+            println!("{}", conflict);
+        }
+    }
+    (vocabulary, grammar)
+}
+
+/// The vocabulary and the sampler of ./assets, see [`new_grammar`].
+fn new_sampler(args: &Args) -> (Arc<Vocabulary>, Sampler) {
+    let (vocabulary, grammar) = new_grammar(args);
+    let machine = Sampler::with_config(
         grammar,
         args.start_nonterminal.clone(),
         vocabulary.clone(),
-        args.arena_capacity,
-        args.bytes_cache,
+        SamplerConfig::new()
+            .stack_arena_capacity(args.arena_capacity)
+            .stack_to_bytes_cache(args.bytes_cache)
+            .cache_mode(args.cache_mode.into())
+            .metrics(args.metrics)
+            .collect_timing(args.timing),
     )
     .unwrap();
+    (vocabulary, machine)
+}
+
+/// The vocabulary and the sampler after the tokens of the bundle, which is described on the way.
+fn replay(path: &str) -> (Arc<Vocabulary>, Sampler) {
+    let bundle = TraceBundle::load(path).unwrap();
+    let replay = bundle.replay().unwrap();
+    println!(
+        "Replayed {} of the {} tokens in {path}.",
+        replay.accepted,
+        bundle.token_ids.len()
+    );
+    match replay.result {
+        AcceptTokenResult::Failed => {
+            let token_id = bundle.token_ids[replay.accepted];
+            println!(
+                "Token {token_id} {:?} at step {} is rejected.",
+                bundle.vocabulary.token_display(token_id),
+                replay.accepted + 1
+            );
+        }
+        AcceptTokenResult::End => println!("One termination path is reached."),
+        AcceptTokenResult::Continue => {}
+    }
+    (bundle.vocabulary, replay.sampler)
+}
+
+/// Walk the samplers of the two config files through the token ids in `script` and print where they diverge.
+fn compare(args: &Args, configs: &[String], script: &str) {
+    let (vocabulary, grammar) = new_grammar(args);
+    let script: Vec<u32> = fs::read_to_string(script)
+        .unwrap()
+        .split_whitespace()
+        .map(|x| {
+            x.parse()
+                .expect("The script should only contain token ids.")
+        })
+        .collect();
+    let [mut a, mut b] = [&configs[0], &configs[1]].map(|path| {
+        let config: serde_json::Value =
+            serde_json::from_str(&fs::read_to_string(path).unwrap()).unwrap();
+        Sampler::with_config(
+            grammar.clone(),
+            args.start_nonterminal.clone(),
+            vocabulary.clone(),
+            SamplerConfig::from_json(&config).unwrap(),
+        )
+        .unwrap()
+    });
+    let divergences = compare_samplers(&mut a, &mut b, &script);
+    if divergences.is_empty() {
+        println!("No divergence between {} and {}.", configs[0], configs[1]);
+    }
+    for divergence in divergences {
+        println!("{}", divergence);
+    }
+}
+
+/// Print the diagnostics of the grammar, and exit with an error code if one of them is an error.
+fn check(args: &Args) {
+    let (input, vocabulary) = read_inputs(args);
+    let diagnostics = Grammar::check(
+        &input,
+        vocabulary,
+        GrammarBuildOptions::new().stack_arena_capacity(args.grammar_arena_capacity),
+        &args.start_nonterminal,
+    );
+    for diagnostic in diagnostics.iter() {
+        println!(
+            "{:?} {:?}{}: {}",
+            diagnostic.severity,
+            diagnostic.kind,
+            match &diagnostic.nonterminal {
+                Some(nonterminal) => format!(" in <{nonterminal}>"),
+                None => String::new(),
+            },
+            diagnostic
+        );
+    }
+    println!("{} diagnostics.", diagnostics.len());
+    if diagnostics.iter().any(|x| x.severity == Severity::Error) {
+        std::process::exit(1);
+    }
+}
+
+fn main() {
+    let mut args = Args::parse();
+    println!("{:?}", args);
+    if args.grammar.as_deref() == Some("-") && args.vocab.as_deref() == Some("-") {
+        Args::command()
+            .error(
+                ErrorKind::ArgumentConflict,
+                "only one of --grammar and --vocab can read from stdin",
+            )
+            .exit();
+    }
+    if args.byte_level && args.vocab.is_some() {
+        Args::command()
+            .error(
+                ErrorKind::ArgumentConflict,
+                "--vocab cannot be used with --byte-level true",
+            )
+            .exit();
+    }
+    if let (Some(configs), Some(script)) = (&args.compare_config, &args.script) {
+        compare(&args, configs, script);
+        return;
+    }
+    if args.check {
+        check(&args);
+        return;
+    }
+    let (vocabulary, mut machine) = match args.replay.clone() {
+        Some(path) => {
+            args.stacks_display = true;
+            replay(&path)
+        }
+        None => new_sampler(&args),
+    };
     if args.stacks_display {
         println!("Stacks: {}", machine);
     }
 
     if let PossibleTokensResult::Continue(result) = machine.all_possible_next_tokens(None).unwrap()
     {
-        let result: Vec<&str> = vocabulary
-            .get_token_strings_from_token_ids(result)
+        let result: Vec<_> = result
+            .iter()
+            .map(|x| vocabulary.token_display(x as u32))
             .collect();
         if args.possible_tokens_display {
             println!("Possible tokens: {:?}", result);
@@ -67,10 +316,32 @@ fn main() {
     loop {
         println!("Input a token: ");
         let mut input = String::new();
-        std::io::stdin()
+        if io::stdin()
             .read_line(&mut input)
-            .expect("Input should exist");
-        let input = utils::fix_utf8_escape(input.trim_end());
+            .expect("Input should exist")
+            == 0
+        {
+            // Stdin is closed, e.g. after a piped grammar or script, so no more tokens can come.
+            println!("The input ends.");
+            break;
+        }
+        if let Some(text) = input.trim_end().strip_prefix(":trace ") {
+            match utils::fix_utf8_escape(text) {
+                Ok(bytes) => match machine.trace_bytes(&bytes) {
+                    Ok(report) => println!("{}", report),
+                    Err(e) => println!("Trace failed: {e}"),
+                },
+                Err(e) => println!("Invalid input: {e}"),
+            }
+            continue;
+        }
+        let input = match utils::fix_utf8_escape(input.trim_end()) {
+            Ok(input) => input,
+            Err(e) => {
+                println!("Invalid input: {e}");
+                continue;
+            }
+        };
         if args.input_display {
             println!("Input: {:?}", input);
         }
@@ -91,12 +362,19 @@ fn main() {
             let end = now.elapsed();
             times.push(end.as_secs_f64());
             println!("Time used: {:?}", end);
-            let result: Vec<&str> = match result.unwrap() {
-                PossibleTokensResult::Continue(result) => vocabulary
-                    .get_token_strings_from_token_ids(result)
+            let result: Vec<_> = match result.unwrap() {
+                PossibleTokensResult::Continue(result) => result
+                    .iter()
+                    .map(|x| vocabulary.token_display(x as u32))
                     .collect(),
                 PossibleTokensResult::InputTokenRejected => {
                     println!("Invalid input.");
+                    if let Some(path) = &args.record {
+                        match machine.record_trace_bundle(path) {
+                            Ok(()) => println!("The trace bundle is recorded in {path}."),
+                            Err(e) => println!("Recording the trace bundle failed: {e}"),
+                        }
+                    }
                     break;
                 }
                 PossibleTokensResult::End => {
@@ -107,13 +385,23 @@ fn main() {
             if args.possible_tokens_display {
                 println!("Possible tokens: {:?}", result);
             }
+            if let Some(timing) = machine.last_step_timing() {
+                println!("{}", timing);
+            }
             if args.stacks_display {
                 println!("{}", machine);
+                println!("Region: {:?}", machine.region_kind());
+                println!("State hash: {:016x}", machine.state_hash());
             }
         }
     }
-    println!(
-        "Average time taken for each token: {}",
-        times.iter().sum::<f64>() / times.len() as f64
-    );
+    if !times.is_empty() {
+        println!(
+            "Average time taken for each token: {}",
+            times.iter().sum::<f64>() / times.len() as f64
+        );
+    }
+    if args.metrics {
+        println!("{}", machine.metrics());
+    }
 }
@@ -0,0 +1,98 @@
+//! Drive the playground binary with piped input.
+use std::io::Write;
+use std::process::{Command, Output, Stdio};
+
+fn run(args: &[&str], stdin: &str) -> Output {
+    let mut child = Command::new(env!("CARGO_BIN_EXE_console_playground"))
+        .args(args)
+        .stdin(Stdio::piped())
+        .stdout(Stdio::piped())
+        .stderr(Stdio::piped())
+        .spawn()
+        .unwrap();
+    child
+        .stdin
+        .take()
+        .unwrap()
+        .write_all(stdin.as_bytes())
+        .unwrap();
+    child.wait_with_output().unwrap()
+}
+
+#[test]
+fn grammar_is_read_from_stdin() {
+    let output = run(&["--grammar", "-", "--byte-level"], "<start>::='yes'|'no'");
+    let stdout = String::from_utf8_lossy(&output.stdout);
+    assert!(output.status.success(), "{stdout}");
+    assert!(
+        stdout.contains(r#"Possible tokens: ["n", "y"]"#),
+        "{stdout}"
+    );
+    assert!(stdout.contains("The input ends."), "{stdout}");
+}
+
+#[test]
+fn grammar_text_takes_tokens_from_stdin() {
+    let output = run(
+        &["--grammar-text", "<start>::='yes'|'no'", "--byte-level"],
+        "y\ne\ns\n",
+    );
+    let stdout = String::from_utf8_lossy(&output.stdout);
+    assert!(output.status.success(), "{stdout}");
+    assert!(
+        stdout.contains("One termination path is reached."),
+        "{stdout}"
+    );
+}
+
+#[test]
+fn vocabulary_is_read_from_stdin() {
+    let output = run(
+        &["--grammar-text", "<start>::='yes'|'no'", "--vocab", "-"],
+        "1 'y' 1\n2 'es' 2\n3 'no' 2\n",
+    );
+    let stdout = String::from_utf8_lossy(&output.stdout);
+    assert!(output.status.success(), "{stdout}");
+    assert!(
+        stdout.contains(r#"Possible tokens: ["y", "no"]"#),
+        "{stdout}"
+    );
+}
+
+#[test]
+fn only_one_argument_reads_stdin() {
+    let output = run(&["--grammar", "-", "--vocab", "-"], "");
+    assert!(!output.status.success());
+    assert!(String::from_utf8_lossy(&output.stderr)
+        .contains("only one of --grammar and --vocab can read from stdin"));
+}
+
+#[test]
+fn check_prints_the_diagnostics() {
+    let output = run(
+        &[
+            "--check",
+            "--grammar-text",
+            "<start>::='a'\n<unused>::='b'",
+            "--byte-level",
+        ],
+        "",
+    );
+    let stdout = String::from_utf8_lossy(&output.stdout);
+    assert!(output.status.success(), "{stdout}");
+    assert!(
+        stdout.contains("Warning Unreachable in <unused>"),
+        "{stdout}"
+    );
+
+    let output = run(
+        &["--check", "--grammar-text", "<start>::=<x>", "--byte-level"],
+        "",
+    );
+    let stdout = String::from_utf8_lossy(&output.stdout);
+    assert!(!output.status.success());
+    assert!(
+        stdout.contains("Error UndefinedNonterminal in <x>"),
+        "{stdout}"
+    );
+}